@@ -0,0 +1,145 @@
+use std::{fmt::Display, str::FromStr};
+
+use windows::{
+    core::Result,
+    Win32::{
+        Foundation::LUID,
+        Graphics::Dxgi::{
+            CreateDXGIFactory1, IDXGIAdapter1, IDXGIFactory1, DXGI_ADAPTER_DESC1,
+            DXGI_ADAPTER_FLAG_SOFTWARE, DXGI_ERROR_NOT_FOUND,
+        },
+    },
+};
+
+use crate::audio::device::find_device_index;
+
+/// Which GPU to capture and encode with, e.g. on systems with both an
+/// integrated and a discrete GPU.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AdapterSelection {
+    Index(usize),
+    /// Matched the same way as microphones, see AudioCaptureDevice::find.
+    Name(String),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseAdapterSelectionError(&'static str);
+
+pub struct GraphicsAdapter {
+    adapter: IDXGIAdapter1,
+    display_name: String,
+    luid: LUID,
+}
+
+impl GraphicsAdapter {
+    /// Lists the hardware adapters, in the order DXGI reports them (the
+    /// first is the default adapter).
+    pub fn enumerate() -> Result<Vec<GraphicsAdapter>> {
+        let factory: IDXGIFactory1 = unsafe { CreateDXGIFactory1()? };
+        let mut adapters = Vec::new();
+        let mut index = 0;
+        loop {
+            let adapter = match unsafe { factory.EnumAdapters1(index) } {
+                Ok(adapter) => adapter,
+                Err(error) if error.code() == DXGI_ERROR_NOT_FOUND => break,
+                Err(error) => return Err(error),
+            };
+            index += 1;
+            let desc = unsafe {
+                let mut desc = DXGI_ADAPTER_DESC1::default();
+                adapter.GetDesc1(&mut desc)?;
+                desc
+            };
+            // Skip the Microsoft Basic Render Driver
+            if desc.Flags & DXGI_ADAPTER_FLAG_SOFTWARE.0 != 0 {
+                continue;
+            }
+            let length = desc
+                .Description
+                .iter()
+                .position(|c| *c == 0)
+                .unwrap_or(desc.Description.len());
+            adapters.push(GraphicsAdapter {
+                adapter,
+                display_name: String::from_utf16_lossy(&desc.Description[..length]),
+                luid: desc.AdapterLuid,
+            });
+        }
+        Ok(adapters)
+    }
+
+    pub fn find(selection: &AdapterSelection) -> Result<Option<GraphicsAdapter>> {
+        let adapters = Self::enumerate()?;
+        let index = match selection {
+            AdapterSelection::Index(index) => Some(*index),
+            AdapterSelection::Name(name) => {
+                find_device_index(adapters.iter().map(|adapter| adapter.display_name()), name)
+            }
+        };
+        Ok(index.and_then(|index| adapters.into_iter().nth(index)))
+    }
+
+    pub fn display_name(&self) -> &str {
+        &self.display_name
+    }
+
+    pub fn adapter(&self) -> &IDXGIAdapter1 {
+        &self.adapter
+    }
+
+    pub fn luid(&self) -> LUID {
+        self.luid
+    }
+}
+
+impl FromStr for AdapterSelection {
+    type Err = ParseAdapterSelectionError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(ParseAdapterSelectionError(
+                "Invalid adapter! Expecting: an adapter index or name.",
+            ));
+        }
+        Ok(s.parse()
+            .map(AdapterSelection::Index)
+            .unwrap_or_else(|_| AdapterSelection::Name(s.to_owned())))
+    }
+}
+
+impl Display for AdapterSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdapterSelection::Index(index) => write!(f, "{}", index),
+            AdapterSelection::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl Display for ParseAdapterSelectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseAdapterSelectionError {}
+
+#[cfg(test)]
+mod tests {
+    use super::AdapterSelection;
+
+    #[test]
+    fn adapter_selection_parsing_test() {
+        assert_eq!("1".parse(), Ok(AdapterSelection::Index(1)));
+        assert_eq!(
+            "NVIDIA".parse(),
+            Ok(AdapterSelection::Name("NVIDIA".to_owned()))
+        );
+        assert_eq!(
+            " Intel(R) UHD ".parse(),
+            Ok(AdapterSelection::Name("Intel(R) UHD".to_owned()))
+        );
+        assert!("".parse::<AdapterSelection>().is_err());
+        assert_eq!(AdapterSelection::Index(0).to_string(), "0");
+    }
+}
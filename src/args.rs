@@ -1,34 +1,380 @@
+use std::time::Duration;
+
 use clap::{Parser, Subcommand};
+use tracing_subscriber::filter::LevelFilter;
 
-use crate::resolution::Resolution;
+use crate::{hotkey::HotKeyBinding, schedule::ClockTime};
+use displayrecorder::{
+    parse_duration, parse_size, AdapterSelection, AudioGain, AudioTrackLayout, BitDepth,
+    CanvasLayout, ColorRange, Container, DisplaySelection, FitMode, Flip, FramePacing,
+    FrameRateMode, KeyCombination, MinimizeAction, OverlayPosition, QueuePolicy, RateControlMode,
+    RedactionStyle, Region, Resolution, Rotation, ScaleFilter, SegmentLimit, StreamUrl, VideoCodec,
+    ZoomFactor,
+};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 pub struct Args {
-    /// The index of the display you'd like to record.
-    #[clap(short, long, default_value_t = 0)]
-    pub display: usize,
+    /// A TOML file with defaults for the other options (e.g. bit-rate = 25 or system-audio = true), for sharing recording profiles. Options on the command line win.
+    #[clap(long)]
+    pub config: Option<String>,
+
+    /// The index of the display you'd like to record, or all. Can be repeated to record multiple displays, each to its own file.
+    #[clap(short, long, default_values_t = [DisplaySelection::Index(0)])]
+    pub display: Vec<DisplaySelection>,
+
+    /// Composites all of the selected displays into a single recording that matches the layout of the desktop.
+    #[clap(long)]
+    pub composite: bool,
+
+    /// How --composite or --process places the displays or windows: desktop (where they are), grid, horizontal (side by side), or vertical (stacked).
+    #[clap(long, default_value_t = CanvasLayout::Desktop)]
+    pub layout: CanvasLayout,
+
+    /// The title or handle (HWND) of a window you'd like to record instead of a display.
+    #[clap(short, long)]
+    pub window: Option<String>,
+
+    /// The id or executable name (e.g. notepad) of a process whose windows you'd like to record together, laid out as they are on the desktop.
+    #[clap(long, conflicts_with = "window")]
+    pub process: Option<String>,
+
+    /// Records an animated test pattern (at the --resolution, or 1080p) instead of a display or window, e.g. to try out encoders and outputs on a machine with nothing to capture.
+    #[clap(long, conflicts_with_all = ["window", "process", "composite"])]
+    pub test_pattern: bool,
+
+    /// A region of the display (or window) to record instead of the whole thing: x,y,width,height.
+    #[clap(long)]
+    pub region: Option<Region>,
+
+    /// Excludes the mouse cursor from the recording.
+    #[clap(long)]
+    pub no_cursor: bool,
 
     /// The bit rate you would like to encode at (in Mbps).
     #[clap(short, long, default_value_t = 18)]
     pub bit_rate: u32,
 
-    /// The frame rate you would like to encode at.
-    #[clap(short, long, default_value_t = 60)]
-    pub frame_rate: u32,
+    /// The frame rate you would like to encode at. Defaults to the refresh rate of the display (e.g. 144), or 60.
+    #[clap(short, long)]
+    pub frame_rate: Option<u32>,
+
+    /// The frame rate mode: cfr (constant) or vfr (variable, the frame rate becomes the maximum).
+    #[clap(long, default_value_t = FrameRateMode::Constant)]
+    pub frame_rate_mode: FrameRateMode,
+
+    /// Drops frames that arrive faster than this (e.g. from 144 Hz displays).
+    #[clap(long)]
+    pub max_fps: Option<u32>,
+
+    /// Drops to this frame rate (e.g. 5) while the screen doesn't change, to keep long recordings small.
+    #[clap(long)]
+    pub idle_fps: Option<u32>,
+
+    /// How long the screen has to stay the same before dropping to the --idle-fps.
+    #[clap(long, value_parser = parse_duration, default_value = "10s")]
+    pub idle_after: Duration,
+
+    /// What to do when frames arrive late because the encoder can't keep up: log (record them anyway), drop (so the encoder can catch up), or duplicate (also repeat the previous frame to fill gaps, requires cfr).
+    #[clap(long, default_value_t = FramePacing::Log)]
+    pub frame_pacing: FramePacing,
+
+    /// How many frames can wait for the encoder before --queue-policy applies.
+    #[clap(long, default_value_t = 3)]
+    pub queue_size: usize,
+
+    /// What to do with new frames when the encoder falls behind and the queue is full: block (wait for the encoder, so frames back up in the capture) or drop-oldest (drop the oldest waiting frame, so the recording stays current).
+    #[clap(long, default_value_t = QueuePolicy::Block)]
+    pub queue_policy: QueuePolicy,
+
+    /// The rate control mode: cqp (constant quality), vbr (variable bit rate), or cbr (constant bit rate). Uses the encoder's default if not provided.
+    #[clap(long)]
+    pub rate_control: Option<RateControlMode>,
+
+    /// The quality you would like to encode at (0-100), requires --rate-control cqp.
+    #[clap(long)]
+    pub quality: Option<u32>,
+
+    /// The number of frames between keyframes. Smaller values make recordings easier to scrub through.
+    #[clap(long)]
+    pub gop: Option<u32>,
+
+    /// Adds a keyframe wherever the screen changes completely (e.g. slide changes in a presentation), so that seeking lands right on them. Not supported with --hdr.
+    #[clap(long)]
+    pub scene_keyframes: bool,
+
+    /// The number of B-frames between reference frames (0 disables them).
+    #[clap(long)]
+    pub bframes: Option<u32>,
+
+    /// Records in HDR (HDR10) so highlights on HDR displays aren't clipped. Requires --codec hevc.
+    #[clap(long)]
+    pub hdr: bool,
+
+    /// The bit depth you would like to encode at: 8 or 10. 10-bit reduces banding in gradients and requires --codec hevc.
+    #[clap(long, default_value_t = BitDepth::Eight)]
+    pub bit_depth: BitDepth,
+
+    /// The color range you would like to encode with: limited (what most players expect) or full.
+    #[clap(long, default_value_t = ColorRange::Limited)]
+    pub color_range: ColorRange,
 
     /// The resolution you would like to encode at: native, 720p, 1080p, 2160p, or 4320p.
     #[clap(short, long, default_value_t = Resolution::Native)]
     pub resolution: Resolution,
 
-    /// The index of the encoder you'd like to use to record (use enum-encoders command for a list of encoders and their indices).
-    #[clap(short, long, default_value_t = 0)]
-    pub encoder: usize,
+    /// If the encoder can't take the resolution (or the size of what's recorded), records at the largest size it supports instead of failing.
+    #[clap(long)]
+    pub auto_downscale: bool,
+
+    /// Rotates the recording clockwise: 0, 90, 180, or 270, e.g. for portrait monitors. With the native resolution, 90 and 270 swap the width and height.
+    #[clap(long, default_value_t = Rotation::None)]
+    pub rotate: Rotation,
+
+    /// Mirrors the recording before it's rotated: h (horizontally) or v (vertically).
+    #[clap(long)]
+    pub flip: Option<Flip>,
+
+    /// Scales the recording with a sharper filter than the default: point, linear, or lanczos. Lanczos keeps text crisp when downscaling, e.g. 4K to 1080p.
+    #[clap(long)]
+    pub scale_filter: Option<ScaleFilter>,
+
+    /// How the recording is fit into a --resolution with a different aspect ratio: letterbox (black bars), stretch, or crop.
+    #[clap(long, default_value_t = FitMode::Letterbox)]
+    pub fit: FitMode,
+
+    /// Zooms in on the mouse cursor by a factor (e.g. 2x), following it smoothly. Handy for tutorials.
+    #[clap(long)]
+    pub zoom_follow: Option<ZoomFactor>,
+
+    /// The codec you would like to encode with: h264, hevc, av1, or vp9.
+    #[clap(short, long, default_value_t = VideoCodec::H264)]
+    pub codec: VideoCodec,
+
+    /// The index of the encoder you'd like to use to record (use enum-encoders command for a list of encoders and their indices). By default the best encoder is picked, falling back to the next one if it can't be set up.
+    #[clap(short, long)]
+    pub encoder: Option<usize>,
+
+    /// The index or name of the GPU to capture and encode with (use enum-adapters command for a list of adapters). Encoder indices are then relative to that adapter's encoders.
+    #[clap(long)]
+    pub adapter: Option<AdapterSelection>,
+
+    /// Also allows software encoders that run on the CPU (e.g. the Microsoft H.264 encoder), which are used if no hardware encoder can be set up. They're slower, but work on machines without a hardware encoder (e.g. some VMs). Their indices follow the hardware encoders' indices.
+    #[clap(long)]
+    pub allow_software_encoder: bool,
+
+    /// Records the audio playing on the default audio device alongside the video.
+    #[clap(long)]
+    pub system_audio: bool,
+
+    /// Records only the audio played by the process with this id (and the processes it started) alongside the video, e.g. a game but not a voice chat. Use instead of --system-audio. Requires Windows 10 Version 2004 or later.
+    #[clap(long, conflicts_with = "system_audio")]
+    pub audio_process: Option<u32>,
+
+    /// The name of a microphone to record alongside the video (use enum-audio-devices command for a list of devices).
+    #[clap(long)]
+    pub mic: Option<String>,
+
+    /// Changes the volume of the microphone, in decibels (e.g. 6dB or -3dB).
+    #[clap(long, allow_hyphen_values = true)]
+    pub mic_gain: Option<AudioGain>,
+
+    /// Turns down the background noise (e.g. fans or typing) of the microphone between speech.
+    #[clap(long)]
+    pub mic_noise_suppression: bool,
+
+    /// How to write multiple audio sources: mixed (one track) or separate (one track per source).
+    #[clap(long, default_value_t = AudioTrackLayout::Mixed)]
+    pub audio_tracks: AudioTrackLayout,
+
+    /// How far the audio may drift from the video (e.g. 10ms) before it's gradually resampled back in sync, since the audio device's clock can run slightly faster or slower than the system clock over long recordings.
+    #[clap(long, value_parser = parse_duration, default_value = "10ms")]
+    pub audio_sync_tolerance: Duration,
+
+    /// Records just the system (or app) audio and/or microphone, without capturing the screen. The output file has to be .m4a (AAC) or .flac (lossless).
+    #[clap(long)]
+    pub no_video: bool,
+
+    /// Streams to an RTMP (rtmp://host[:port]/app/stream-key) or SRT (srt://host:port[?streamid=...]) server instead of writing the output file.
+    #[clap(long)]
+    pub stream: Option<StreamUrl>,
+
+    /// Writes HLS (fragmented MP4 segments and a playlist.m3u8) into the directory instead of the output file, so the recording can be watched in a browser while it's ongoing.
+    #[clap(long)]
+    pub hls: Option<String>,
+
+    /// Sends the frames to NDI as a source with the given name, for video mixers on the local network.
+    #[clap(long)]
+    pub ndi: Option<String>,
+
+    /// Only sends the frames to NDI, without writing the output file.
+    #[clap(long, requires = "ndi")]
+    pub ndi_only: bool,
+
+    /// A title to write into the recording's metadata (MP4, MKV, and WebM).
+    #[clap(long)]
+    pub title: Option<String>,
+
+    /// An author to write into the recording's metadata (MP4, MKV, and WebM).
+    #[clap(long)]
+    pub author: Option<String>,
+
+    /// A comment to write into the recording's metadata (MP4, MKV, and WebM).
+    #[clap(long)]
+    pub comment: Option<String>,
+
+    /// Writes the MP4 as fragments every couple of seconds, so that a recording that's interrupted (e.g. by a crash or a power cut) can be recovered with the repair command. Requires an .mp4 output file and H.264.
+    #[clap(long)]
+    pub fragmented: bool,
+
+    /// Only keeps the last part of the recording (e.g. 30s), which is saved when the recording is stopped.
+    #[clap(long, value_parser = parse_duration)]
+    pub replay: Option<Duration>,
+
+    /// Stops the recording automatically after it has run for this long (e.g. 90s), not counting time spent paused.
+    #[clap(long, value_parser = parse_duration)]
+    pub duration: Option<Duration>,
+
+    /// Stops the recording (and finishes the file) once the free space on the output drive falls below this size (e.g. 500MB).
+    #[clap(long, value_parser = parse_size, default_value = "500MB")]
+    pub min_free_space: u64,
+
+    /// Stops the recording automatically once no audio has been heard for this long (e.g. 5min), such as when a meeting ends. Requires --system-audio, --audio-process, or --mic.
+    #[clap(long, value_parser = parse_duration)]
+    pub stop_on_silence: Option<Duration>,
+
+    /// Starts recording automatically at a local time (e.g. 14:30), instead of waiting for the hotkey.
+    #[clap(long)]
+    pub start_at: Option<ClockTime>,
+
+    /// Starts recording automatically after a delay (e.g. 5s), instead of waiting for the hotkey.
+    #[clap(long, value_parser = parse_duration)]
+    pub delay: Option<Duration>,
+
+    /// Splits the recording into numbered files once each one reaches a duration (e.g. 10min) or a size (e.g. 2GB).
+    #[clap(long)]
+    pub segment: Option<SegmentLimit>,
+
+    /// The format of the recording: mp4, mkv, webm, gif, h264 (raw, without audio), m4a, or flac (audio only, see --no-video). Defaults to the extension of the output file.
+    #[clap(long)]
+    pub format: Option<Container>,
+
+    /// The frame rate of GIF recordings. Frames that arrive faster than this are dropped.
+    #[clap(long, default_value_t = 10)]
+    pub gif_frame_rate: u32,
+
+    /// The maximum width of GIF recordings, larger recordings are scaled down.
+    #[clap(long, default_value_t = 640)]
+    pub gif_max_width: u32,
+
+    /// Shows what is being recorded in a window while recording.
+    #[clap(long)]
+    pub preview: bool,
+
+    /// Saves a small JPEG thumbnail next to the recording (e.g. recording.jpg) once it is stopped.
+    #[clap(long)]
+    pub thumbnail: bool,
+
+    /// Burns the current wall-clock time into the top left corner of the video.
+    #[clap(long)]
+    pub clock_overlay: bool,
+
+    /// Highlights mouse clicks with a circle, e.g. for tutorials.
+    #[clap(long)]
+    pub show_clicks: bool,
+
+    /// Shows the last key combination pressed (e.g. ctrl+shift+p). Only shortcuts are shown, so that typed text such as passwords isn't recorded.
+    #[clap(long)]
+    pub show_keys: bool,
+
+    /// A region of the recording to pixelate, e.g. one that shows sensitive data: x,y,width,height (relative to the top left corner of what's recorded). Can be repeated to blur multiple regions.
+    #[clap(long)]
+    pub blur_region: Vec<Region>,
+
+    /// The title or handle (HWND) of a window to cover in the recording, e.g. a chat window. Can be repeated to exclude multiple windows. The preview window is always left out.
+    #[clap(long)]
+    pub exclude_window: Vec<String>,
+
+    /// The executable name of an app (e.g. slack or 1password.exe) whose windows to cover in the recording, including ones that open while recording, e.g. notifications. Can be repeated to redact multiple apps.
+    #[clap(long)]
+    pub redact_app: Vec<String>,
+
+    /// How excluded windows and the windows of redacted apps are covered: blank or pixelate.
+    #[clap(long, default_value_t = RedactionStyle::Blank)]
+    pub redact_style: RedactionStyle,
+
+    /// What to record while the --window is minimized: pause (the recording), hold (the last frame), or placeholder (a "Window minimized" card).
+    #[clap(long)]
+    pub on_minimize: Option<MinimizeAction>,
+
+    /// What to record while the secure desktop (a UAC prompt or the lock screen) is shown: pause (the recording), hold (the last frame), or placeholder (a "Secure desktop shown" card). By default the video has a gap. The recording resumes once it's gone.
+    #[clap(long)]
+    pub on_secure_desktop: Option<MinimizeAction>,
+
+    /// Only shows this key combination with --show-keys. Can be repeated to allow multiple combinations.
+    #[clap(long)]
+    pub show_keys_allow: Vec<KeyCombination>,
+
+    /// Text to draw on top of the video, e.g. a name or copyright notice.
+    #[clap(long)]
+    pub watermark: Option<String>,
+
+    /// An image (e.g. a PNG logo) to draw on top of the video.
+    #[clap(long)]
+    pub watermark_image: Option<String>,
+
+    /// Where to place the watermark: top-left, top-right, bottom-left, bottom-right, or center.
+    #[clap(long, default_value_t = OverlayPosition::BottomRight)]
+    pub watermark_position: OverlayPosition,
+
+    /// The opacity of the watermark, from 0.0 (invisible) to 1.0 (opaque).
+    #[clap(long, default_value_t = 0.5)]
+    pub watermark_opacity: f32,
+
+    /// The name of a webcam to show on top of the video (picture-in-picture), see enum-webcams.
+    #[clap(long)]
+    pub webcam: Option<String>,
+
+    /// Where to place the webcam: top-left, top-right, bottom-left, bottom-right, or center.
+    #[clap(long, default_value_t = OverlayPosition::BottomRight)]
+    pub webcam_position: OverlayPosition,
+
+    /// The width of the webcam as a percentage of the width of the video.
+    #[clap(long, default_value_t = 25)]
+    pub webcam_size: u32,
+
+    /// The index of a second display to show on top of the video (picture-in-picture).
+    #[clap(long)]
+    pub pip_display: Option<usize>,
+
+    /// The title or handle (HWND) of a window to show on top of the video (picture-in-picture).
+    #[clap(long, conflicts_with = "pip_display")]
+    pub pip_window: Option<String>,
+
+    /// Where to place the picture-in-picture: top-left, top-right, bottom-left, bottom-right, or center.
+    #[clap(long, default_value_t = OverlayPosition::BottomRight)]
+    pub pip_position: OverlayPosition,
+
+    /// The width of the picture-in-picture as a percentage of the width of the video.
+    #[clap(long, default_value_t = 25)]
+    pub pip_size: u32,
+
+    /// Prints the statistics of the recording (frames, encode latency, bit rate, and file size) as JSON once it's stopped.
+    #[clap(long)]
+    pub stats_json: bool,
 
     /// Enables verbose (debug) output.
     #[clap(short, long)]
     pub verbose: bool,
 
+    /// How much is logged: off, error, warn, info, debug, or trace.
+    #[clap(long, default_value_t = LevelFilter::INFO)]
+    pub log_level: LevelFilter,
+
+    /// Also writes the log (with timestamps) to the file, e.g. to attach to a bug report. The console still stops at info.
+    #[clap(long)]
+    pub log_file: Option<String>,
+
     /// The program will wait for a debugger to attach before starting.
     #[clap(long)]
     pub wait_for_debugger: bool,
@@ -37,7 +383,63 @@ pub struct Args {
     #[clap(long)]
     pub console_mode: bool,
 
-    /// The output file that will contain the recording.
+    /// Accepts JSON-RPC commands (start, stop, pause, resume, marker, split, keyframe, status, and exit), one per line, on a named pipe (e.g. \\.\pipe\displayrecorder) instead of hotkeys. Files are numbered (e.g. recording_001.mp4) so that they can be split.
+    #[clap(long, conflicts_with = "console_mode")]
+    pub control_pipe: Option<String>,
+
+    /// Accepts the same commands over HTTP on an address (e.g. 127.0.0.1:8080), e.g. POST /start, POST /stop, or GET /status (elapsed time, dropped frames, and bit rate). Requests need the token that's printed at startup (Authorization: Bearer <token>), and POSTs need a Content-Type of application/json.
+    #[clap(long, conflicts_with = "console_mode")]
+    pub http_control: Option<String>,
+
+    /// The global hotkey that starts and stops the recording, e.g. ctrl+shift+r.
+    #[clap(long, default_value = "ctrl+shift+r")]
+    pub toggle_hotkey: HotKeyBinding,
+
+    /// The global hotkey that pauses and resumes the recording, e.g. ctrl+shift+p.
+    #[clap(long, default_value = "ctrl+shift+p")]
+    pub pause_hotkey: HotKeyBinding,
+
+    /// The global hotkey that forces the next frame to be a keyframe, e.g. ctrl+shift+k.
+    #[clap(long, default_value = "ctrl+shift+k")]
+    pub keyframe_hotkey: HotKeyBinding,
+
+    /// Saves chapters next to the recording (e.g. recording.chapters.vtt) from the markers added with --chapter-hotkey or the marker command.
+    #[clap(long)]
+    pub chapters: bool,
+
+    /// The global hotkey that adds a chapter at the current time when saving chapters, e.g. ctrl+shift+m.
+    #[clap(long, default_value = "ctrl+shift+m")]
+    pub chapter_hotkey: HotKeyBinding,
+
+    /// Reads the recording back once it's saved, and warns if it's shorter than what was recorded (e.g. because the file was truncated). Only for MP4, MKV, and WebM files.
+    #[clap(long)]
+    pub verify: bool,
+
+    /// Only writes to the file while the screen is changing, plus the --pre-roll and --post-roll around the changes (e.g. for monitoring kiosks and dashboards).
+    #[clap(long)]
+    pub record_on_change: bool,
+
+    /// How much of what came before a change is kept with --record-on-change.
+    #[clap(long, value_parser = parse_duration, default_value = "3s")]
+    pub pre_roll: Duration,
+
+    /// How long to keep recording after the last change with --record-on-change.
+    #[clap(long, value_parser = parse_duration, default_value = "5s")]
+    pub post_roll: Duration,
+
+    /// Writes raw frames to a scratch file while recording, and encodes them once the recording stops, for when the encoder can't keep up (e.g. 4K at high frame rates). Needs a fast disk, and can't be used with audio.
+    #[clap(long)]
+    pub encode_later: bool,
+
+    /// The folder for the scratch file of --encode-later, ideally on a fast disk. Defaults to the temp folder.
+    #[clap(long)]
+    pub scratch_dir: Option<String>,
+
+    /// Adds a "GPU reset" marker (and chapter, with --chapters) where the video has a gap because the graphics driver was reset. The recording carries on after a reset either way.
+    #[clap(long)]
+    pub mark_device_loss: bool,
+
+    /// The output file that will contain the recording. The container is picked based on the extension (mp4, mkv, webm, gif, h264, m4a, or flac). It can be a template, e.g. captures/{date}_{time}_{display}.mp4, with {date}, {time}, {display} (the monitor name), {window} (the window title), and {index} (the first number that does not overwrite a recording). Missing folders are created. Use - for stdout or \\.\pipe\name for a named pipe, which are written as fragmented MP4 unless --format h264 is used.
     #[clap(default_value = "recording.mp4")]
     pub output_file: String,
 
@@ -49,6 +451,145 @@ pub struct Args {
 #[derive(Subcommand, Debug)]
 #[clap(args_conflicts_with_subcommands = true)]
 pub enum Commands {
-    /// Lists the available hardware H264 encoders.
-    EnumEncoders,
+    /// Lists the available hardware (and optionally software) encoders.
+    EnumEncoders {
+        /// The codec to list encoders for: h264, hevc, av1, or vp9.
+        #[clap(short, long, default_value_t = VideoCodec::H264)]
+        codec: VideoCodec,
+
+        /// Only lists the encoders on this GPU (an index or name).
+        #[clap(long)]
+        adapter: Option<AdapterSelection>,
+
+        /// Also lists the software encoders, after the hardware encoders.
+        #[clap(long)]
+        allow_software_encoder: bool,
+    },
+    /// Lists what each encoder supports (codecs, maximum resolution, rate control modes, 10-bit, and B-frames).
+    Probe {
+        /// Also probes the software encoders.
+        #[clap(long)]
+        allow_software_encoder: bool,
+
+        /// Prints the capabilities as JSON instead.
+        #[clap(long)]
+        json: bool,
+    },
+    /// Encodes an animated test pattern with each encoder, comparing how fast they encode (frames per second) and how long each frame takes (latency).
+    Bench {
+        /// The codec to benchmark: h264, hevc, av1, or vp9.
+        #[clap(short, long, default_value_t = VideoCodec::H264)]
+        codec: VideoCodec,
+
+        /// The resolution of the test pattern: 720p, 1080p, 2160p, or 4320p.
+        #[clap(short, long, default_value_t = Resolution::_1080p)]
+        resolution: Resolution,
+
+        /// The frame rate to encode at, which each encoder's throughput is compared with.
+        #[clap(short, long, default_value_t = 60)]
+        frame_rate: u32,
+
+        /// The bit rate to encode at (in Mbps).
+        #[clap(short, long, default_value_t = 18)]
+        bit_rate: u32,
+
+        /// How much video each encoder encodes (e.g. 10s).
+        #[clap(short, long, value_parser = parse_duration, default_value = "10s")]
+        duration: Duration,
+
+        /// Also benchmarks the software encoders.
+        #[clap(long)]
+        allow_software_encoder: bool,
+
+        /// Prints the results as JSON instead.
+        #[clap(long)]
+        json: bool,
+    },
+    /// Records a few seconds of a test pattern and reads the recording back, checking its codec, resolution, frame count, and duration. A quick way to check that recording works on this machine.
+    Selftest {
+        /// The codec to record with: h264, hevc, av1, or vp9.
+        #[clap(short, long, default_value_t = VideoCodec::H264)]
+        codec: VideoCodec,
+
+        /// The resolution of the test pattern: 720p, 1080p, 2160p, or 4320p.
+        #[clap(short, long, default_value_t = Resolution::_1080p)]
+        resolution: Resolution,
+
+        /// The frame rate to record at.
+        #[clap(short, long, default_value_t = 30)]
+        frame_rate: u32,
+
+        /// How long to record for (e.g. 3s).
+        #[clap(short, long, value_parser = parse_duration, default_value = "3s")]
+        duration: Duration,
+
+        /// Falls back to a software encoder when there's no hardware encoder.
+        #[clap(long)]
+        allow_software_encoder: bool,
+
+        /// Keeps the recording at this path (an mp4) instead of deleting it afterwards.
+        #[clap(short, long)]
+        output_file: Option<String>,
+    },
+    /// Lists the displays with their indices (for --display), names, bounds, refresh rates, and whether HDR is on.
+    ListDisplays,
+    /// Lists the windows that can be recorded with their handles and titles (for --window).
+    ListWindows,
+    /// Lists the available GPUs (adapters).
+    EnumAdapters,
+    /// Lists the available audio capture devices (e.g. microphones).
+    EnumAudioDevices,
+    /// Lists the available webcams.
+    EnumWebcams,
+    /// Saves a PNG of a display or window without recording it.
+    Screenshot {
+        /// The output file, which must be a .png.
+        #[clap(short, long, default_value_t = String::from("screenshot.png"))]
+        output_file: String,
+
+        /// The index of the display you'd like to capture.
+        #[clap(short, long, default_value_t = 0)]
+        display: usize,
+
+        /// The title or handle (HWND) of a window you'd like to capture instead of a display.
+        #[clap(short, long)]
+        window: Option<String>,
+
+        /// A region of the display (or window) to capture instead of the whole thing: x,y,width,height.
+        #[clap(long)]
+        region: Option<Region>,
+
+        /// Excludes the mouse cursor from the screenshot.
+        #[clap(long)]
+        no_cursor: bool,
+    },
+    /// Recovers an interrupted recording that was made with --fragmented, by writing its complete fragments to a new MP4.
+    Repair {
+        /// The interrupted recording.
+        input_file: String,
+
+        /// The recovered recording. Defaults to the input file's name with ".repaired.mp4".
+        #[clap(short, long)]
+        output_file: Option<String>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, Parser};
+
+    use super::Args;
+
+    #[test]
+    fn args_test() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn mic_gain_test() {
+        let args = Args::try_parse_from(["displayrecorder", "--mic-gain", "-3dB"]).unwrap();
+        assert_eq!(args.mic_gain.unwrap().decibels(), -3.0);
+        let args = Args::try_parse_from(["displayrecorder", "--mic-gain=-3dB"]).unwrap();
+        assert_eq!(args.mic_gain.unwrap().decibels(), -3.0);
+    }
 }
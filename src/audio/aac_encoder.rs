@@ -0,0 +1,202 @@
+use windows::{
+    core::{Error, Result},
+    Win32::{
+        Media::MediaFoundation::{
+            AACMFTEncoder, IMFMediaType, IMFSample, IMFTransform, MFCreateMediaType,
+            MFCreateMemoryBuffer, MFCreateSample, MFT_MESSAGE_COMMAND_DRAIN,
+            MFT_MESSAGE_NOTIFY_BEGIN_STREAMING, MFT_MESSAGE_NOTIFY_START_OF_STREAM,
+            MFT_OUTPUT_DATA_BUFFER, MF_E_INVALIDMEDIATYPE, MF_E_TRANSFORM_NEED_MORE_INPUT,
+            MF_MT_AAC_PAYLOAD_TYPE, MF_MT_AUDIO_NUM_CHANNELS, MF_MT_AUDIO_SAMPLES_PER_SECOND,
+        },
+        System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER},
+    },
+};
+
+use crate::media::get_sample_data;
+
+const HUNDRED_NANOSECONDS_PER_SECOND: u64 = 10_000_000;
+// Each AAC-LC frame holds this many samples per channel
+const FRAMES_PER_AAC_FRAME: u64 = 1024;
+const AAC_LC_OBJECT_TYPE: u16 = 2;
+const SAMPLING_FREQUENCIES: [u32; 12] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000,
+];
+
+/// Encodes PCM audio to raw AAC frames using the AAC encoder that ships with
+/// Windows, for outputs that don't go through the sink writer.
+pub struct AacEncoder {
+    transform: IMFTransform,
+    output_buffer_size: u32,
+    sample_rate: u32,
+    channels: u32,
+    start_time: Option<i64>,
+    frames_encoded: u64,
+}
+
+/// An encoded frame, timestamped in 100ns units.
+pub struct AacFrame {
+    pub time: i64,
+    pub data: Vec<u8>,
+}
+
+impl AacEncoder {
+    /// The output type describes the AAC stream (see AudioEncodingSession),
+    /// the input type the PCM samples that will be encoded.
+    pub fn new(output_type: &IMFMediaType, input_type: &IMFMediaType) -> Result<Self> {
+        unsafe {
+            let sample_rate = output_type.GetUINT32(&MF_MT_AUDIO_SAMPLES_PER_SECOND)?;
+            let channels = output_type.GetUINT32(&MF_MT_AUDIO_NUM_CHANNELS)?;
+            if !SAMPLING_FREQUENCIES.contains(&sample_rate) {
+                return Err(Error::from(MF_E_INVALIDMEDIATYPE));
+            }
+
+            // Raw AAC frames, without ADTS headers
+            let raw_output_type = MFCreateMediaType()?;
+            output_type.CopyAllItems(&raw_output_type)?;
+            raw_output_type.SetUINT32(&MF_MT_AAC_PAYLOAD_TYPE, 0)?;
+
+            // The encoder needs to know its output type before its input type
+            let transform: IMFTransform =
+                CoCreateInstance(&AACMFTEncoder, None, CLSCTX_INPROC_SERVER)?;
+            transform.SetOutputType(0, &raw_output_type, 0)?;
+            transform.SetInputType(0, input_type, 0)?;
+            let output_buffer_size = transform.GetOutputStreamInfo(0)?.cbSize;
+            transform.ProcessMessage(MFT_MESSAGE_NOTIFY_BEGIN_STREAMING, 0)?;
+            transform.ProcessMessage(MFT_MESSAGE_NOTIFY_START_OF_STREAM, 0)?;
+
+            Ok(Self {
+                transform,
+                output_buffer_size,
+                sample_rate,
+                channels,
+                start_time: None,
+                frames_encoded: 0,
+            })
+        }
+    }
+
+    /// The AudioSpecificConfig that decoders need before the first frame.
+    pub fn audio_specific_config(&self) -> [u8; 2] {
+        get_audio_specific_config(self.sample_rate, self.channels)
+    }
+
+    /// The ADTS header to put in front of a raw frame, for containers like
+    /// MPEG-TS that don't carry the AudioSpecificConfig separately.
+    pub fn adts_header(&self, frame_length: usize) -> [u8; 7] {
+        get_adts_header(self.sample_rate, self.channels, frame_length)
+    }
+
+    /// Returns the frames that are ready, the encoder holds on to any
+    /// samples that don't fill a whole frame yet.
+    pub fn encode(&mut self, sample: &IMFSample) -> Result<Vec<AacFrame>> {
+        if self.start_time.is_none() {
+            self.start_time = Some(unsafe { sample.GetSampleTime()? });
+        }
+        unsafe { self.transform.ProcessInput(0, sample, 0)? };
+        self.read_frames()
+    }
+
+    /// Returns whatever the encoder was still holding on to.
+    pub fn flush(&mut self) -> Result<Vec<AacFrame>> {
+        unsafe {
+            self.transform
+                .ProcessMessage(MFT_MESSAGE_COMMAND_DRAIN, 0)?
+        };
+        self.read_frames()
+    }
+
+    fn read_frames(&mut self) -> Result<Vec<AacFrame>> {
+        let mut frames = Vec::new();
+        loop {
+            // The encoder expects us to provide the output samples
+            let sample = unsafe {
+                let buffer = MFCreateMemoryBuffer(self.output_buffer_size)?;
+                let sample = MFCreateSample()?;
+                sample.AddBuffer(&buffer)?;
+                sample
+            };
+            let mut output_buffers = [MFT_OUTPUT_DATA_BUFFER {
+                dwStreamID: 0,
+                pSample: std::mem::ManuallyDrop::new(Some(sample.clone())),
+                dwStatus: 0,
+                pEvents: std::mem::ManuallyDrop::new(None),
+            }];
+            let mut status = 0;
+            let result = unsafe {
+                self.transform
+                    .ProcessOutput(0, &mut output_buffers, &mut status)
+            };
+            unsafe {
+                std::mem::ManuallyDrop::drop(&mut output_buffers[0].pSample);
+                std::mem::ManuallyDrop::drop(&mut output_buffers[0].pEvents);
+            }
+            match result {
+                Ok(()) => {}
+                Err(error) if error.code() == MF_E_TRANSFORM_NEED_MORE_INPUT => return Ok(frames),
+                Err(error) => return Err(error),
+            }
+
+            let data = get_sample_data(&sample)?;
+            if data.is_empty() {
+                continue;
+            }
+            // Frame times are derived from the number of frames so that they
+            // line up exactly with the PCM timeline
+            let time = self.start_time.unwrap_or_default()
+                + (self.frames_encoded * FRAMES_PER_AAC_FRAME * HUNDRED_NANOSECONDS_PER_SECOND
+                    / self.sample_rate as u64) as i64;
+            self.frames_encoded += 1;
+            frames.push(AacFrame { time, data });
+        }
+    }
+}
+
+// See ISO/IEC 14496-3 1.6.2.1, we only ever produce AAC-LC
+fn get_audio_specific_config(sample_rate: u32, channels: u32) -> [u8; 2] {
+    let frequency_index = SAMPLING_FREQUENCIES
+        .iter()
+        .position(|frequency| *frequency == sample_rate)
+        .unwrap_or(15) as u16;
+    let value = (AAC_LC_OBJECT_TYPE << 11) | (frequency_index << 7) | ((channels as u16) << 3);
+    value.to_be_bytes()
+}
+
+// See ISO/IEC 13818-7 6.2, without a CRC and with a variable bitrate
+fn get_adts_header(sample_rate: u32, channels: u32, frame_length: usize) -> [u8; 7] {
+    let frequency_index = SAMPLING_FREQUENCIES
+        .iter()
+        .position(|frequency| *frequency == sample_rate)
+        .unwrap_or(15) as u8;
+    let profile = (AAC_LC_OBJECT_TYPE - 1) as u8;
+    // The length includes the header itself
+    let length = frame_length + 7;
+    let buffer_fullness = 0x7FF;
+    [
+        0xFF,
+        0xF1,
+        (profile << 6) | (frequency_index << 2) | ((channels as u8 >> 2) & 0x1),
+        ((channels as u8 & 0x3) << 6) | ((length >> 11) as u8 & 0x3),
+        (length >> 3) as u8,
+        ((length as u8 & 0x7) << 5) | (buffer_fullness >> 6) as u8,
+        ((buffer_fullness as u8 & 0x3F) << 2),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_adts_header, get_audio_specific_config};
+
+    #[test]
+    fn audio_specific_config_test() {
+        assert_eq!(get_audio_specific_config(48000, 2), [0x11, 0x90]);
+        assert_eq!(get_audio_specific_config(44100, 1), [0x12, 0x08]);
+    }
+
+    #[test]
+    fn adts_header_test() {
+        assert_eq!(
+            get_adts_header(48000, 2, 100),
+            [0xFF, 0xF1, 0x4C, 0x80, 0x0D, 0x7F, 0xFC]
+        );
+    }
+}
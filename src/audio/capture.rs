@@ -0,0 +1,361 @@
+use std::{
+    mem::ManuallyDrop,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        mpsc::{sync_channel, SyncSender},
+    },
+};
+
+use windows::{
+    core::{ComInterface, IUnknown, IUnknown_Vtbl, Interface, Result, GUID, HRESULT},
+    Win32::{
+        Foundation::{E_NOINTERFACE, S_OK},
+        Media::Audio::{
+            eConsole, eRender, ActivateAudioInterfaceAsync,
+            IActivateAudioInterfaceCompletionHandler,
+            IActivateAudioInterfaceCompletionHandler_Vtbl, IAudioCaptureClient, IAudioClient,
+            IMMDevice, IMMDeviceEnumerator, MMDeviceEnumerator, AUDCLNT_BUFFERFLAGS_SILENT,
+            AUDCLNT_SHAREMODE_SHARED, AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM,
+            AUDCLNT_STREAMFLAGS_LOOPBACK, AUDCLNT_STREAMFLAGS_SRC_DEFAULT_QUALITY,
+            AUDIOCLIENT_ACTIVATION_PARAMS, AUDIOCLIENT_ACTIVATION_PARAMS_0,
+            AUDIOCLIENT_ACTIVATION_TYPE_PROCESS_LOOPBACK, AUDIOCLIENT_PROCESS_LOOPBACK_PARAMS,
+            PROCESS_LOOPBACK_MODE_INCLUDE_TARGET_PROCESS_TREE,
+            VIRTUAL_AUDIO_DEVICE_PROCESS_LOOPBACK, WAVEFORMATEX, WAVE_FORMAT_PCM,
+        },
+        System::{
+            Com::{
+                CoCreateInstance, IAgileObject,
+                StructuredStorage::{
+                    PROPVARIANT, PROPVARIANT_0, PROPVARIANT_0_0, PROPVARIANT_0_0_0,
+                },
+                BLOB, CLSCTX_ALL,
+            },
+            Variant::VT_BLOB,
+        },
+    },
+};
+
+use super::{device::AudioCaptureDevice, effects::AudioEffects};
+
+// Buffer duration requested from the audio engine (in 100ns units)
+const BUFFER_DURATION: i64 = 10_000_000;
+
+/// The PCM format we ask the audio engine to convert captured audio to.
+/// The AAC encoder only accepts 16-bit PCM at 44.1 or 48 kHz.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AudioFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bits_per_sample: u16,
+}
+
+impl AudioFormat {
+    pub const STEREO_48KHZ_16BIT: AudioFormat = AudioFormat {
+        sample_rate: 48000,
+        channels: 2,
+        bits_per_sample: 16,
+    };
+
+    pub fn block_alignment(&self) -> u32 {
+        (self.channels as u32 * self.bits_per_sample as u32) / 8
+    }
+
+    pub fn bytes_per_second(&self) -> u32 {
+        self.block_alignment() * self.sample_rate
+    }
+
+    /// Converts a number of frames to a duration in 100ns units.
+    pub fn frames_to_duration(&self, frames: u64) -> i64 {
+        ((frames as i128 * 10_000_000) / self.sample_rate as i128) as i64
+    }
+
+    /// Converts a duration in 100ns units to a number of frames.
+    pub fn duration_to_frames(&self, duration: i64) -> u64 {
+        ((duration.max(0) as i128 * self.sample_rate as i128) / 10_000_000) as u64
+    }
+
+    fn to_wave_format(self) -> WAVEFORMATEX {
+        WAVEFORMATEX {
+            wFormatTag: WAVE_FORMAT_PCM as u16,
+            nChannels: self.channels,
+            nSamplesPerSec: self.sample_rate,
+            nAvgBytesPerSec: self.bytes_per_second(),
+            nBlockAlign: self.block_alignment() as u16,
+            wBitsPerSample: self.bits_per_sample,
+            cbSize: 0,
+        }
+    }
+}
+
+pub struct AudioPacket<'a> {
+    /// The PCM data for this packet, or None if the packet is silent.
+    pub data: Option<&'a [u8]>,
+    pub frames: u32,
+    /// QPC based timestamp (in 100ns units) of the first frame.
+    pub timestamp: i64,
+}
+
+pub struct AudioCapture {
+    audio_client: IAudioClient,
+    capture_client: IAudioCaptureClient,
+    format: AudioFormat,
+    effects: AudioEffects,
+}
+
+unsafe impl Send for AudioCapture {}
+impl AudioCapture {
+    /// Captures the audio being played on the default render endpoint.
+    pub fn new_loopback() -> Result<Self> {
+        let device = unsafe {
+            let enumerator: IMMDeviceEnumerator =
+                CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
+            enumerator.GetDefaultAudioEndpoint(eRender, eConsole)?
+        };
+        Self::new(&device, AUDCLNT_STREAMFLAGS_LOOPBACK)
+    }
+
+    /// Captures audio from a capture endpoint (e.g. a microphone).
+    pub fn new_for_device(device: &AudioCaptureDevice) -> Result<Self> {
+        Self::new(device.device(), 0)
+    }
+
+    /// Captures only the audio being played by a process (and the processes
+    /// it started), e.g. a game but not a voice chat. Requires Windows 10
+    /// Version 2004 or later.
+    pub fn new_for_process(process_id: u32) -> Result<Self> {
+        let audio_client = activate_process_loopback_client(process_id)?;
+        Self::from_client(audio_client, AUDCLNT_STREAMFLAGS_LOOPBACK)
+    }
+
+    fn new(device: &IMMDevice, stream_flags: u32) -> Result<Self> {
+        let audio_client: IAudioClient = unsafe { device.Activate(CLSCTX_ALL, None)? };
+        Self::from_client(audio_client, stream_flags)
+    }
+
+    fn from_client(audio_client: IAudioClient, stream_flags: u32) -> Result<Self> {
+        let format = AudioFormat::STEREO_48KHZ_16BIT;
+        let wave_format = format.to_wave_format();
+        unsafe {
+            // Let the audio engine convert from the mix format for us
+            audio_client.Initialize(
+                AUDCLNT_SHAREMODE_SHARED,
+                stream_flags
+                    | AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM
+                    | AUDCLNT_STREAMFLAGS_SRC_DEFAULT_QUALITY,
+                BUFFER_DURATION,
+                0,
+                &wave_format,
+                None,
+            )?;
+        }
+        let capture_client: IAudioCaptureClient = unsafe { audio_client.GetService()? };
+
+        Ok(Self {
+            audio_client,
+            capture_client,
+            format,
+            effects: AudioEffects::default(),
+        })
+    }
+
+    /// Applies the effects to the captured audio before it's encoded.
+    pub fn with_effects(mut self, effects: AudioEffects) -> Self {
+        self.effects = effects;
+        self
+    }
+
+    pub fn format(&self) -> &AudioFormat {
+        &self.format
+    }
+
+    pub fn effects(&self) -> &AudioEffects {
+        &self.effects
+    }
+
+    pub fn start(&self) -> Result<()> {
+        unsafe { self.audio_client.Start() }
+    }
+
+    pub fn stop(&self) -> Result<()> {
+        unsafe { self.audio_client.Stop() }
+    }
+
+    /// Drains all of the packets currently available from the audio engine.
+    pub fn read_packets<F: FnMut(AudioPacket) -> Result<()>>(
+        &mut self,
+        mut callback: F,
+    ) -> Result<()> {
+        let block_alignment = self.format.block_alignment() as usize;
+        unsafe {
+            while self.capture_client.GetNextPacketSize()? > 0 {
+                let mut data = std::ptr::null_mut();
+                let mut frames = 0;
+                let mut flags = 0;
+                let mut qpc_position = 0;
+                self.capture_client.GetBuffer(
+                    &mut data,
+                    &mut frames,
+                    &mut flags,
+                    None,
+                    Some(&mut qpc_position),
+                )?;
+
+                let data = if (flags & AUDCLNT_BUFFERFLAGS_SILENT.0 as u32) != 0 {
+                    None
+                } else {
+                    Some(std::slice::from_raw_parts(
+                        data,
+                        frames as usize * block_alignment,
+                    ))
+                };
+                let result = callback(AudioPacket {
+                    data,
+                    frames,
+                    timestamp: qpc_position as i64,
+                });
+
+                self.capture_client.ReleaseBuffer(frames)?;
+                result?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// The process loopback device can only be activated asynchronously, so this
+// waits for the activation to complete
+fn activate_process_loopback_client(process_id: u32) -> Result<IAudioClient> {
+    let mut loopback_params = AUDIOCLIENT_ACTIVATION_PARAMS {
+        ActivationType: AUDIOCLIENT_ACTIVATION_TYPE_PROCESS_LOOPBACK,
+        Anonymous: AUDIOCLIENT_ACTIVATION_PARAMS_0 {
+            ProcessLoopbackParams: AUDIOCLIENT_PROCESS_LOOPBACK_PARAMS {
+                TargetProcessId: process_id,
+                ProcessLoopbackMode: PROCESS_LOOPBACK_MODE_INCLUDE_TARGET_PROCESS_TREE,
+            },
+        },
+    };
+    // The params are only read during the call, so they aren't cleared
+    let params = ManuallyDrop::new(PROPVARIANT {
+        Anonymous: PROPVARIANT_0 {
+            Anonymous: ManuallyDrop::new(PROPVARIANT_0_0 {
+                vt: VT_BLOB,
+                Anonymous: PROPVARIANT_0_0_0 {
+                    blob: BLOB {
+                        cbSize: std::mem::size_of::<AUDIOCLIENT_ACTIVATION_PARAMS>() as u32,
+                        pBlobData: &mut loopback_params as *mut _ as *mut u8,
+                    },
+                },
+                ..Default::default()
+            }),
+        },
+    });
+
+    let (sender, receiver) = sync_channel(1);
+    let handler = CompletionHandler::create(sender);
+    let operation = unsafe {
+        ActivateAudioInterfaceAsync(
+            VIRTUAL_AUDIO_DEVICE_PROCESS_LOOPBACK,
+            &IAudioClient::IID,
+            Some(&*params as *const _),
+            &handler,
+        )?
+    };
+    // Once the system lets go of the handler, it has either been called or
+    // never will be
+    drop(handler);
+    let _ = receiver.recv();
+
+    let mut result = S_OK;
+    let mut audio_client = None;
+    unsafe { operation.GetActivateResult(&mut result, &mut audio_client)? };
+    result.ok()?;
+    audio_client.unwrap().cast()
+}
+
+// Signals a channel once the activation has completed. Implemented by hand,
+// as it's the only COM object we implement. It's agile, since it can be
+// called on any thread.
+#[repr(C)]
+struct CompletionHandler {
+    vtable: *const IActivateAudioInterfaceCompletionHandler_Vtbl,
+    ref_count: AtomicU32,
+    sender: SyncSender<()>,
+}
+
+const COMPLETION_HANDLER_VTABLE: IActivateAudioInterfaceCompletionHandler_Vtbl =
+    IActivateAudioInterfaceCompletionHandler_Vtbl {
+        base__: IUnknown_Vtbl {
+            QueryInterface: CompletionHandler::query_interface,
+            AddRef: CompletionHandler::add_ref,
+            Release: CompletionHandler::release,
+        },
+        ActivateCompleted: CompletionHandler::activate_completed,
+    };
+
+impl CompletionHandler {
+    fn create(sender: SyncSender<()>) -> IActivateAudioInterfaceCompletionHandler {
+        let handler = Box::new(Self {
+            vtable: &COMPLETION_HANDLER_VTABLE,
+            ref_count: AtomicU32::new(1),
+            sender,
+        });
+        unsafe { IActivateAudioInterfaceCompletionHandler::from_raw(Box::into_raw(handler) as _) }
+    }
+
+    unsafe extern "system" fn query_interface(
+        this: *mut std::ffi::c_void,
+        iid: &GUID,
+        interface: *mut *const std::ffi::c_void,
+    ) -> HRESULT {
+        if *iid == IUnknown::IID
+            || *iid == IActivateAudioInterfaceCompletionHandler::IID
+            || *iid == IAgileObject::IID
+        {
+            Self::add_ref(this);
+            *interface = this;
+            S_OK
+        } else {
+            *interface = std::ptr::null();
+            E_NOINTERFACE
+        }
+    }
+
+    unsafe extern "system" fn add_ref(this: *mut std::ffi::c_void) -> u32 {
+        let handler = &*(this as *const Self);
+        handler.ref_count.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    unsafe extern "system" fn release(this: *mut std::ffi::c_void) -> u32 {
+        let handler = &*(this as *const Self);
+        let ref_count = handler.ref_count.fetch_sub(1, Ordering::SeqCst) - 1;
+        if ref_count == 0 {
+            drop(Box::from_raw(this as *mut Self));
+        }
+        ref_count
+    }
+
+    unsafe extern "system" fn activate_completed(
+        this: *mut std::ffi::c_void,
+        _operation: *mut std::ffi::c_void,
+    ) -> HRESULT {
+        let handler = &*(this as *const Self);
+        let _ = handler.sender.try_send(());
+        S_OK
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AudioFormat;
+
+    #[test]
+    fn audio_format_test() {
+        let format = AudioFormat::STEREO_48KHZ_16BIT;
+        assert_eq!(format.block_alignment(), 4);
+        assert_eq!(format.bytes_per_second(), 192000);
+        assert_eq!(format.frames_to_duration(48000), 10_000_000);
+        assert_eq!(format.frames_to_duration(480), 100_000);
+        assert_eq!(format.duration_to_frames(10_000_000), 48000);
+        assert_eq!(format.duration_to_frames(-1), 0);
+    }
+}
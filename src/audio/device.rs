@@ -0,0 +1,102 @@
+use windows::{
+    core::Result,
+    Win32::{
+        Devices::FunctionDiscovery::PKEY_Device_FriendlyName,
+        Media::Audio::{
+            eCapture, IMMDevice, IMMDeviceEnumerator, MMDeviceEnumerator, DEVICE_STATE_ACTIVE,
+        },
+        System::Com::{
+            CoCreateInstance,
+            StructuredStorage::{PropVariantClear, PropVariantToBSTR},
+            CLSCTX_ALL, STGM_READ,
+        },
+    },
+};
+
+pub struct AudioCaptureDevice {
+    device: IMMDevice,
+    display_name: String,
+}
+
+impl AudioCaptureDevice {
+    pub fn enumerate() -> Result<Vec<AudioCaptureDevice>> {
+        let devices = unsafe {
+            let enumerator: IMMDeviceEnumerator =
+                CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
+            enumerator.EnumAudioEndpoints(eCapture, DEVICE_STATE_ACTIVE)?
+        };
+        let count = unsafe { devices.GetCount()? };
+        let mut capture_devices = Vec::new();
+        for i in 0..count {
+            let device = unsafe { devices.Item(i)? };
+            let display_name = if let Some(display_name) = get_friendly_name(&device)? {
+                display_name
+            } else {
+                "Unknown".to_owned()
+            };
+            capture_devices.push(AudioCaptureDevice {
+                device,
+                display_name,
+            });
+        }
+        Ok(capture_devices)
+    }
+
+    /// Finds a capture device by name. Exact (case-insensitive) matches are
+    /// preferred, otherwise the first device whose name contains the query is used.
+    pub fn find(name: &str) -> Result<Option<AudioCaptureDevice>> {
+        let devices = Self::enumerate()?;
+        let index = find_device_index(devices.iter().map(|device| device.display_name()), name);
+        Ok(index.and_then(|index| devices.into_iter().nth(index)))
+    }
+
+    pub fn display_name(&self) -> &str {
+        &self.display_name
+    }
+
+    pub fn device(&self) -> &IMMDevice {
+        &self.device
+    }
+}
+
+fn get_friendly_name(device: &IMMDevice) -> Result<Option<String>> {
+    unsafe {
+        let property_store = device.OpenPropertyStore(STGM_READ)?;
+        let mut value = property_store.GetValue(&PKEY_Device_FriendlyName)?;
+        let name = PropVariantToBSTR(&value).ok().map(|name| name.to_string());
+        PropVariantClear(&mut value)?;
+        Ok(name.filter(|name| !name.is_empty()))
+    }
+}
+
+pub fn find_device_index<'a, I: Iterator<Item = &'a str> + Clone>(
+    mut names: I,
+    query: &str,
+) -> Option<usize> {
+    let query = query.to_lowercase();
+    names
+        .clone()
+        .position(|name| name.to_lowercase() == query)
+        .or_else(|| names.position(|name| name.to_lowercase().contains(&query)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_device_index;
+
+    #[test]
+    fn device_name_matching_test() {
+        let names = [
+            "Microphone (USB Audio)",
+            "Microphone",
+            "Headset Microphone (Wireless)",
+        ];
+        assert_eq!(
+            find_device_index(names.iter().copied(), "microphone"),
+            Some(1)
+        );
+        assert_eq!(find_device_index(names.iter().copied(), "USB"), Some(0));
+        assert_eq!(find_device_index(names.iter().copied(), "headset"), Some(2));
+        assert_eq!(find_device_index(names.iter().copied(), "Line In"), None);
+    }
+}
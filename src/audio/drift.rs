@@ -0,0 +1,156 @@
+use std::time::Duration;
+
+use super::capture::AudioFormat;
+
+// How much each measurement moves the drift, since packet timestamps jitter
+// by a few milliseconds
+const SMOOTHING: f64 = 0.05;
+// The most a packet is stretched or squeezed, which is too little to be heard
+const MAX_CORRECTION_RATIO: f64 = 0.005;
+
+/// Keeps the audio in sync with the video over long recordings. The clock of
+/// the audio device can run slightly faster or slower than the system clock
+/// (QPC) that frames are timestamped with, so sample times that are derived
+/// from the number of frames slowly drift away from the packet timestamps.
+/// Once the drift is beyond the tolerance, packets are resampled until it's
+/// well within it again.
+pub struct DriftCorrector {
+    // In 100ns units
+    tolerance: f64,
+    // How far the audio is ahead of the clock (in 100ns units), smoothed
+    drift: Option<f64>,
+    correcting: bool,
+}
+
+impl DriftCorrector {
+    pub fn new(tolerance: Duration) -> Self {
+        Self {
+            tolerance: (tolerance.as_nanos() / 100) as f64,
+            drift: None,
+            correcting: false,
+        }
+    }
+
+    /// Forgets the measured drift, e.g. once a gap is filled with silence.
+    pub fn reset(&mut self) {
+        self.drift = None;
+        self.correcting = false;
+    }
+
+    /// The number of frames a packet should be resampled to, given how far
+    /// the audio is ahead of the packet's timestamp (in 100ns units, negative
+    /// when it's behind).
+    pub fn corrected_frames(&mut self, drift: i64, frames: u32, format: &AudioFormat) -> u32 {
+        let drift = match self.drift {
+            Some(smoothed) => smoothed + (drift as f64 - smoothed) * SMOOTHING,
+            None => drift as f64,
+        };
+        if drift.abs() > self.tolerance {
+            self.correcting = true;
+        } else if drift.abs() < self.tolerance / 2.0 {
+            self.correcting = false;
+        }
+        if !self.correcting || frames == 0 {
+            self.drift = Some(drift);
+            return frames;
+        }
+
+        let max_correction = ((frames as f64 * MAX_CORRECTION_RATIO).ceil() as u32).max(1);
+        let correction = (format.duration_to_frames(drift.abs() as i64) as u32)
+            .clamp(1, max_correction)
+            .min(frames - 1);
+        // The correction itself is known, so it doesn't have to be measured
+        let corrected_time = format.frames_to_duration(correction as u64) as f64;
+        if drift > 0.0 {
+            self.drift = Some(drift - corrected_time);
+            frames - correction
+        } else {
+            self.drift = Some(drift + corrected_time);
+            frames + correction
+        }
+    }
+}
+
+/// Stretches or squeezes interleaved samples to a number of frames, by
+/// interpolating between neighbouring frames.
+pub fn resample(samples: &[i16], channels: u16, frames: usize) -> Vec<i16> {
+    let channels = channels as usize;
+    let input_frames = samples.len() / channels;
+    if input_frames == frames {
+        return samples.to_vec();
+    }
+    if input_frames == 0 {
+        return vec![0; frames * channels];
+    }
+
+    let step = if frames > 1 {
+        (input_frames - 1) as f64 / (frames - 1) as f64
+    } else {
+        0.0
+    };
+    let mut output = Vec::with_capacity(frames * channels);
+    for frame in 0..frames {
+        let position = frame as f64 * step;
+        let index = (position as usize).min(input_frames - 1);
+        let next = (index + 1).min(input_frames - 1);
+        let fraction = position - index as f64;
+        for channel in 0..channels {
+            let current = samples[index * channels + channel] as f64;
+            let next = samples[next * channels + channel] as f64;
+            output.push((current + (next - current) * fraction).round() as i16);
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::audio::capture::AudioFormat;
+
+    use super::{resample, DriftCorrector};
+
+    #[test]
+    fn drift_correction_test() {
+        let format = AudioFormat::STEREO_48KHZ_16BIT;
+        let mut corrector = DriftCorrector::new(Duration::from_millis(10));
+
+        // Drift within the tolerance is left alone
+        assert_eq!(corrector.corrected_frames(50_000, 480, &format), 480);
+
+        // Audio that's ahead is squeezed, and audio that's behind is
+        // stretched, by at most half a percent
+        corrector.reset();
+        assert_eq!(corrector.corrected_frames(500_000, 480, &format), 477);
+        corrector.reset();
+        assert_eq!(corrector.corrected_frames(-500_000, 480, &format), 483);
+
+        // Corrections continue until the drift is well within the tolerance
+        corrector.reset();
+        let mut drift = 150_000;
+        let mut corrections = 0;
+        for _ in 0..1000 {
+            let frames = corrector.corrected_frames(drift, 480, &format);
+            if frames == 480 {
+                break;
+            }
+            drift -= format.frames_to_duration((480 - frames) as u64);
+            corrections += 1;
+        }
+        assert!(corrections > 0);
+        assert!(drift < 50_000);
+    }
+
+    #[test]
+    fn resample_test() {
+        let samples = [0, 100, 10, 110, 20, 120];
+        assert_eq!(resample(&samples, 2, 3), samples.to_vec());
+        assert_eq!(
+            resample(&samples, 2, 5),
+            vec![0, 100, 5, 105, 10, 110, 15, 115, 20, 120]
+        );
+        assert_eq!(resample(&samples, 2, 2), vec![0, 100, 20, 120]);
+        assert_eq!(resample(&[], 2, 2), vec![0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,203 @@
+use std::{fmt::Display, str::FromStr};
+
+use super::capture::AudioFormat;
+
+const MAX_GAIN: f32 = 30.0;
+// Anything quieter than this (about -45 dBFS) is treated as background noise
+const GATE_THRESHOLD: f32 = 0.0056;
+// Background noise is turned down (by about 20 dB) rather than muted, so
+// that the gate closing isn't noticeable
+const GATE_FLOOR: f32 = 0.1;
+// How quickly the gate opens and closes (in seconds)
+const GATE_ATTACK: f32 = 0.005;
+const GATE_RELEASE: f32 = 0.15;
+
+/// A change in volume, in decibels (e.g. 6dB).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AudioGain(f32);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseAudioGainError(&'static str);
+
+impl AudioGain {
+    pub fn decibels(&self) -> f32 {
+        self.0
+    }
+
+    fn factor(&self) -> f32 {
+        10f32.powf(self.0 / 20.0)
+    }
+}
+
+impl FromStr for AudioGain {
+    type Err = ParseAudioGainError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let value = s
+            .strip_suffix("dB")
+            .or_else(|| s.strip_suffix("db"))
+            .unwrap_or(s);
+        match value.trim().parse::<f32>() {
+            Ok(value) if (-MAX_GAIN..=MAX_GAIN).contains(&value) => Ok(AudioGain(value)),
+            _ => Err(ParseAudioGainError(
+                "Invalid gain value! Expecting decibels from -30dB to 30dB, e.g. 6dB.",
+            )),
+        }
+    }
+}
+
+impl Display for AudioGain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}dB", self.0)
+    }
+}
+
+impl Display for ParseAudioGainError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseAudioGainError {}
+
+/// The effects applied to an audio source before it's encoded.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct AudioEffects {
+    pub gain: Option<AudioGain>,
+    /// Turns down the background noise between speech (a noise gate).
+    pub noise_suppression: bool,
+}
+
+impl AudioEffects {
+    pub fn is_empty(&self) -> bool {
+        self.gain.is_none() && !self.noise_suppression
+    }
+}
+
+/// Applies the effects to the samples of a source, keeping track of the
+/// state that carries over from one packet to the next.
+pub struct AudioEffectsProcessor {
+    channels: usize,
+    gain: f32,
+    gate: Option<NoiseGate>,
+}
+
+struct NoiseGate {
+    attack: f32,
+    release: f32,
+    // How loud the source is, following its peaks
+    envelope: f32,
+    gain: f32,
+}
+
+impl AudioEffectsProcessor {
+    pub fn new(effects: &AudioEffects, format: &AudioFormat) -> Self {
+        let gate = if effects.noise_suppression {
+            Some(NoiseGate {
+                attack: get_smoothing(GATE_ATTACK, format.sample_rate),
+                release: get_smoothing(GATE_RELEASE, format.sample_rate),
+                envelope: 0.0,
+                gain: GATE_FLOOR,
+            })
+        } else {
+            None
+        };
+        Self {
+            channels: format.channels as usize,
+            gain: effects.gain.map(|gain| gain.factor()).unwrap_or(1.0),
+            gate,
+        }
+    }
+
+    pub fn process(&mut self, samples: &mut [i16]) {
+        for frame in samples.chunks_exact_mut(self.channels) {
+            let mut gain = self.gain;
+            if let Some(gate) = self.gate.as_mut() {
+                let level = frame
+                    .iter()
+                    .map(|&sample| (sample as f32 / i16::MAX as f32).abs())
+                    .fold(0.0, f32::max);
+                gain *= gate.next_gain(level);
+            }
+            for sample in frame {
+                *sample = (*sample as f32 * gain)
+                    .round()
+                    .clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            }
+        }
+    }
+}
+
+impl NoiseGate {
+    fn next_gain(&mut self, level: f32) -> f32 {
+        let smoothing = if level > self.envelope {
+            self.attack
+        } else {
+            self.release
+        };
+        self.envelope += (level - self.envelope) * smoothing;
+        let (target, smoothing) = if self.envelope > GATE_THRESHOLD {
+            (1.0, self.attack)
+        } else {
+            (GATE_FLOOR, self.release)
+        };
+        self.gain += (target - self.gain) * smoothing;
+        self.gain
+    }
+}
+
+// How far to move towards a target with each frame, so that it's mostly
+// reached after the time
+fn get_smoothing(seconds: f32, sample_rate: u32) -> f32 {
+    1.0 - (-1.0 / (seconds * sample_rate as f32)).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audio::capture::AudioFormat;
+
+    use super::{AudioEffects, AudioEffectsProcessor, AudioGain};
+
+    #[test]
+    fn audio_gain_parsing_test() {
+        assert_eq!(
+            "6dB".parse::<AudioGain>().map(|gain| gain.decibels()),
+            Ok(6.0)
+        );
+        assert_eq!(
+            "-3".parse::<AudioGain>().map(|gain| gain.decibels()),
+            Ok(-3.0)
+        );
+        assert!("40dB".parse::<AudioGain>().is_err());
+        assert!("loud".parse::<AudioGain>().is_err());
+        assert_eq!("6dB".parse::<AudioGain>().unwrap().to_string(), "6dB");
+    }
+
+    #[test]
+    fn audio_effects_test() {
+        let format = AudioFormat::STEREO_48KHZ_16BIT;
+
+        // 6dB about doubles the volume, and clips rather than wrapping
+        let effects = AudioEffects {
+            gain: Some("6dB".parse().unwrap()),
+            noise_suppression: false,
+        };
+        let mut processor = AudioEffectsProcessor::new(&effects, &format);
+        let mut samples = [1000, -1000, 30000, -30000];
+        processor.process(&mut samples);
+        assert_eq!(samples, [1995, -1995, i16::MAX, i16::MIN]);
+
+        // Quiet noise is turned down, while speech gets through
+        let effects = AudioEffects {
+            gain: None,
+            noise_suppression: true,
+        };
+        let mut processor = AudioEffectsProcessor::new(&effects, &format);
+        let mut noise = vec![50; 9600];
+        processor.process(&mut noise);
+        assert!(noise[9598] <= 5);
+        let mut speech = vec![10000; 9600];
+        processor.process(&mut speech);
+        assert!(speech[9598] > 9900);
+    }
+}
@@ -0,0 +1,412 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use tracing::error;
+use windows::{
+    core::Result,
+    Win32::Media::MediaFoundation::{
+        IMFMediaType, IMFSample, MFAudioFormat_AAC, MFAudioFormat_FLAC, MFAudioFormat_PCM,
+        MFCreateMediaType, MFCreateMemoryBuffer, MFCreateSample, MFMediaType_Audio, MFStartup,
+        MFSTARTUP_FULL, MF_MT_AUDIO_AVG_BYTES_PER_SECOND, MF_MT_AUDIO_BITS_PER_SAMPLE,
+        MF_MT_AUDIO_BLOCK_ALIGNMENT, MF_MT_AUDIO_NUM_CHANNELS, MF_MT_AUDIO_SAMPLES_PER_SECOND,
+        MF_MT_MAJOR_TYPE, MF_MT_SUBTYPE,
+    },
+};
+
+use crate::{
+    container::Container,
+    media::MF_VERSION,
+    sample_writer::SampleWriter,
+    timeline::{get_system_relative_time, Timeline},
+};
+
+use super::{
+    capture::{AudioCapture, AudioFormat, AudioPacket},
+    drift::{resample, DriftCorrector},
+    effects::AudioEffectsProcessor,
+    level::AudioLevelMeter,
+    mixer::mix,
+    track_layout::AudioTrackLayout,
+};
+
+// 192 Kbps, the AAC encoder supports 96, 128, 160, and 192 Kbps
+const AAC_BYTES_PER_SECOND: u32 = 24000;
+// How often we poll the audio engine for new packets
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+// Gaps in the captured audio larger than this are filled with silence (100ns units)
+const GAP_TOLERANCE: i64 = 200_000;
+// The audio engine doesn't deliver loopback packets while nothing is playing,
+// so once we fall this far behind the clock we write silence (100ns units)
+const SILENCE_THRESHOLD: i64 = 1_000_000;
+
+pub struct AudioEncodingSession {
+    sample_generator: Option<AudioSampleGenerator>,
+    level_meter: AudioLevelMeter,
+    should_stop: Arc<AtomicBool>,
+    thread_handle: Option<JoinHandle<Result<()>>>,
+}
+
+struct AudioSampleGenerator {
+    sources: Vec<AudioSource>,
+    streams: Vec<AudioStreamWriter>,
+    layout: AudioTrackLayout,
+    level_meter: AudioLevelMeter,
+}
+
+struct AudioSource {
+    capture: AudioCapture,
+    buffer: AudioSourceBuffer,
+    effects: Option<AudioEffectsProcessor>,
+}
+
+// Turns the packets from a capture into a continuous stream of PCM
+// samples that is aligned to the timeline.
+struct AudioSourceBuffer {
+    timeline: Timeline,
+    format: AudioFormat,
+    samples: Vec<i16>,
+    drift: DriftCorrector,
+
+    // Number of frames produced so far (including inserted silence)
+    frames_produced: u64,
+}
+
+struct AudioStreamWriter {
+    sample_writer: Arc<SampleWriter>,
+    stream_index: u32,
+    format: AudioFormat,
+
+    // Number of frames written so far, used to derive sample times
+    frames_written: u64,
+}
+
+impl AudioEncodingSession {
+    /// The audio is kept within the sync tolerance of the video, see
+    /// DriftCorrector.
+    pub fn new(
+        captures: Vec<AudioCapture>,
+        layout: AudioTrackLayout,
+        sync_tolerance: Duration,
+        sample_writer: Arc<SampleWriter>,
+    ) -> Result<Self> {
+        assert!(!captures.is_empty());
+        let format = *captures[0].format();
+        let input_type = create_pcm_media_type(&format)?;
+        let output_type = if sample_writer.container() == Some(Container::Flac) {
+            create_flac_media_type(&format)?
+        } else {
+            create_aac_media_type(&format)?
+        };
+
+        let stream_count = match layout {
+            AudioTrackLayout::Mixed => 1,
+            AudioTrackLayout::Separate => captures.len(),
+        };
+        let mut streams = Vec::new();
+        for _ in 0..stream_count {
+            // The sink writer will load an AAC (or FLAC) encoder for us
+            let stream_index = sample_writer.add_stream(&output_type, &input_type)?;
+            streams.push(AudioStreamWriter {
+                sample_writer: sample_writer.clone(),
+                stream_index,
+                format,
+                frames_written: 0,
+            });
+        }
+
+        let level_meter = AudioLevelMeter::new(captures.len());
+        let sources = captures
+            .into_iter()
+            .map(|capture| {
+                assert_eq!(*capture.format(), format);
+                let effects = if capture.effects().is_empty() {
+                    None
+                } else {
+                    Some(AudioEffectsProcessor::new(capture.effects(), &format))
+                };
+                AudioSource {
+                    effects,
+                    capture,
+                    buffer: AudioSourceBuffer {
+                        timeline: sample_writer.timeline().clone(),
+                        format,
+                        samples: Vec::new(),
+                        drift: DriftCorrector::new(sync_tolerance),
+                        frames_produced: 0,
+                    },
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            sample_generator: Some(AudioSampleGenerator {
+                sources,
+                streams,
+                layout,
+                level_meter: level_meter.clone(),
+            }),
+            level_meter,
+            should_stop: Arc::new(AtomicBool::new(false)),
+            thread_handle: None,
+        })
+    }
+
+    pub fn start(&mut self) -> Result<()> {
+        let mut sample_generator = self.sample_generator.take().unwrap();
+        let should_stop = self.should_stop.clone();
+        for source in &sample_generator.sources {
+            source.capture.start()?;
+        }
+        self.thread_handle = Some(std::thread::spawn(move || -> Result<()> {
+            unsafe { MFStartup(MF_VERSION, MFSTARTUP_FULL)? }
+            let result = sample_generator.run(&should_stop);
+            if let Err(error) = &result {
+                error!(
+                    "Audio recording failed: {:?} - {}",
+                    error.code(),
+                    error.message()
+                );
+                println!("Audio recording stopped unexpectedly!");
+            }
+            for source in &sample_generator.sources {
+                source.capture.stop()?;
+            }
+            result
+        }));
+        Ok(())
+    }
+
+    /// Measures the audio as it's recorded, one level per capture.
+    pub fn level_meter(&self) -> &AudioLevelMeter {
+        &self.level_meter
+    }
+
+    pub fn stop(&mut self) -> Result<()> {
+        if let Some(handle) = self.thread_handle.take() {
+            self.should_stop.store(true, Ordering::SeqCst);
+            handle.join().unwrap()?;
+        }
+        Ok(())
+    }
+}
+
+impl AudioSampleGenerator {
+    fn run(&mut self, should_stop: &AtomicBool) -> Result<()> {
+        while !should_stop.load(Ordering::SeqCst) {
+            self.generate()?;
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        self.generate()
+    }
+
+    fn generate(&mut self) -> Result<()> {
+        for source in &mut self.sources {
+            let buffer = &mut source.buffer;
+            source
+                .capture
+                .read_packets(|packet| buffer.push_packet(&packet))?;
+            buffer.fill_if_behind();
+        }
+
+        match self.layout {
+            AudioTrackLayout::Mixed => {
+                // We can only mix as far as the source that is furthest behind
+                let frames = self
+                    .sources
+                    .iter()
+                    .map(|source| source.buffer.buffered_frames())
+                    .min()
+                    .unwrap_or(0);
+                let samples: Vec<_> = self
+                    .sources
+                    .iter_mut()
+                    .enumerate()
+                    .map(|(index, source)| source.take(index, frames, &self.level_meter))
+                    .collect();
+                self.streams[0].write(&mix(&samples))?;
+            }
+            AudioTrackLayout::Separate => {
+                for (index, (source, stream)) in
+                    self.sources.iter_mut().zip(&mut self.streams).enumerate()
+                {
+                    let frames = source.buffer.buffered_frames();
+                    stream.write(&source.take(index, frames, &self.level_meter))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AudioSource {
+    // Takes the frames to be written, applying the effects and measuring
+    // them along the way
+    fn take(&mut self, index: usize, frames: u64, level_meter: &AudioLevelMeter) -> Vec<i16> {
+        let mut samples = self.buffer.take(frames);
+        if let Some(effects) = self.effects.as_mut() {
+            effects.process(&mut samples);
+        }
+        let end_time = self
+            .buffer
+            .format
+            .frames_to_duration(self.buffer.frames_produced - self.buffer.buffered_frames());
+        level_meter.analyze(index, &samples, end_time);
+        samples
+    }
+}
+
+impl AudioSourceBuffer {
+    fn current_time(&self) -> i64 {
+        self.format.frames_to_duration(self.frames_produced)
+    }
+
+    fn buffered_frames(&self) -> u64 {
+        (self.samples.len() / self.format.channels as usize) as u64
+    }
+
+    fn take(&mut self, frames: u64) -> Vec<i16> {
+        let length = frames as usize * self.format.channels as usize;
+        self.samples.drain(..length).collect()
+    }
+
+    fn push_packet(&mut self, packet: &AudioPacket) -> Result<()> {
+        // Packets that arrive while the recording is paused are dropped
+        if self.timeline.is_paused() {
+            return Ok(());
+        }
+
+        // Sample times are derived from the number of frames we've produced so that
+        // the audio stream stays continuous. Large gaps are filled with silence,
+        // and smaller drift is resampled away.
+        let mut frames = packet.frames;
+        if let Some(packet_time) = self.timeline.relative_time(packet.timestamp) {
+            if packet_time - self.current_time() > GAP_TOLERANCE {
+                self.push_silence_until(packet_time);
+            }
+            let drift = self.current_time() - packet_time;
+            frames = self
+                .drift
+                .corrected_frames(drift, packet.frames, &self.format);
+        }
+
+        let length = frames as usize * self.format.channels as usize;
+        if let Some(data) = packet.data {
+            let samples: Vec<_> = data
+                .chunks_exact(2)
+                .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+                .collect();
+            self.samples
+                .extend(resample(&samples, self.format.channels, frames as usize));
+        } else {
+            self.samples.resize(self.samples.len() + length, 0);
+        }
+        self.frames_produced += frames as u64;
+        Ok(())
+    }
+
+    fn fill_if_behind(&mut self) {
+        let now = if let Some(now) = self.timeline.relative_time(get_system_relative_time()) {
+            now
+        } else {
+            return;
+        };
+        if now - self.current_time() > SILENCE_THRESHOLD {
+            self.push_silence_until(now);
+        }
+    }
+
+    fn push_silence_until(&mut self, time: i64) {
+        let frames = self
+            .format
+            .duration_to_frames(time)
+            .saturating_sub(self.frames_produced);
+        let length = frames as usize * self.format.channels as usize;
+        self.samples.resize(self.samples.len() + length, 0);
+        self.frames_produced += frames;
+        self.drift.reset();
+    }
+}
+
+impl AudioStreamWriter {
+    fn write(&mut self, samples: &[i16]) -> Result<()> {
+        let frames = (samples.len() / self.format.channels as usize) as u64;
+        if frames == 0 {
+            return Ok(());
+        }
+
+        let sample = create_sample(samples)?;
+        let sample_time = self.format.frames_to_duration(self.frames_written);
+        let sample_duration =
+            self.format.frames_to_duration(self.frames_written + frames) - sample_time;
+        unsafe {
+            sample.SetSampleTime(sample_time)?;
+            sample.SetSampleDuration(sample_duration)?;
+        }
+        self.frames_written += frames;
+        self.sample_writer.write(self.stream_index, &sample)
+    }
+}
+
+fn create_sample(samples: &[i16]) -> Result<IMFSample> {
+    let length = std::mem::size_of_val(samples) as u32;
+    unsafe {
+        let buffer = MFCreateMemoryBuffer(length)?;
+        let mut data = std::ptr::null_mut();
+        buffer.Lock(&mut data, None, None)?;
+        let bytes = std::slice::from_raw_parts_mut(data, length as usize);
+        for (bytes, sample) in bytes.chunks_exact_mut(2).zip(samples) {
+            bytes.copy_from_slice(&sample.to_le_bytes());
+        }
+        buffer.Unlock()?;
+        buffer.SetCurrentLength(length)?;
+
+        let sample = MFCreateSample()?;
+        sample.AddBuffer(&buffer)?;
+        Ok(sample)
+    }
+}
+
+fn create_pcm_media_type(format: &AudioFormat) -> Result<IMFMediaType> {
+    unsafe {
+        let media_type = MFCreateMediaType()?;
+        media_type.SetGUID(&MF_MT_MAJOR_TYPE, &MFMediaType_Audio)?;
+        media_type.SetGUID(&MF_MT_SUBTYPE, &MFAudioFormat_PCM)?;
+        media_type.SetUINT32(&MF_MT_AUDIO_NUM_CHANNELS, format.channels as u32)?;
+        media_type.SetUINT32(&MF_MT_AUDIO_SAMPLES_PER_SECOND, format.sample_rate)?;
+        media_type.SetUINT32(&MF_MT_AUDIO_BITS_PER_SAMPLE, format.bits_per_sample as u32)?;
+        media_type.SetUINT32(&MF_MT_AUDIO_BLOCK_ALIGNMENT, format.block_alignment())?;
+        media_type.SetUINT32(&MF_MT_AUDIO_AVG_BYTES_PER_SECOND, format.bytes_per_second())?;
+        Ok(media_type)
+    }
+}
+
+fn create_aac_media_type(format: &AudioFormat) -> Result<IMFMediaType> {
+    unsafe {
+        let media_type = MFCreateMediaType()?;
+        media_type.SetGUID(&MF_MT_MAJOR_TYPE, &MFMediaType_Audio)?;
+        media_type.SetGUID(&MF_MT_SUBTYPE, &MFAudioFormat_AAC)?;
+        media_type.SetUINT32(&MF_MT_AUDIO_NUM_CHANNELS, format.channels as u32)?;
+        media_type.SetUINT32(&MF_MT_AUDIO_SAMPLES_PER_SECOND, format.sample_rate)?;
+        media_type.SetUINT32(&MF_MT_AUDIO_BITS_PER_SAMPLE, format.bits_per_sample as u32)?;
+        media_type.SetUINT32(&MF_MT_AUDIO_AVG_BYTES_PER_SECOND, AAC_BYTES_PER_SECOND)?;
+        Ok(media_type)
+    }
+}
+
+fn create_flac_media_type(format: &AudioFormat) -> Result<IMFMediaType> {
+    unsafe {
+        let media_type = MFCreateMediaType()?;
+        media_type.SetGUID(&MF_MT_MAJOR_TYPE, &MFMediaType_Audio)?;
+        media_type.SetGUID(&MF_MT_SUBTYPE, &MFAudioFormat_FLAC)?;
+        media_type.SetUINT32(&MF_MT_AUDIO_NUM_CHANNELS, format.channels as u32)?;
+        media_type.SetUINT32(&MF_MT_AUDIO_SAMPLES_PER_SECOND, format.sample_rate)?;
+        media_type.SetUINT32(&MF_MT_AUDIO_BITS_PER_SAMPLE, format.bits_per_sample as u32)?;
+        Ok(media_type)
+    }
+}
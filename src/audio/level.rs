@@ -0,0 +1,98 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+// Anything quieter than this (about -50 dBFS) counts as silence
+const SILENCE_THRESHOLD: f32 = 0.003;
+// Quieter levels are reported as this
+const MIN_LEVEL: f32 = -60.0;
+
+/// Measures how loud each audio source is, and when any of them was last
+/// heard. Clones share the same measurements, so that they can be read from
+/// any thread.
+#[derive(Clone)]
+pub struct AudioLevelMeter {
+    levels: Arc<Mutex<Levels>>,
+}
+
+struct Levels {
+    // The peak of each source since the levels were last taken, from 0 to 1
+    peaks: Vec<f32>,
+    // When a source was last louder than silence (in 100ns units, relative
+    // to the timeline)
+    last_sound: i64,
+}
+
+impl AudioLevelMeter {
+    pub fn new(source_count: usize) -> Self {
+        Self {
+            levels: Arc::new(Mutex::new(Levels {
+                peaks: vec![0.0; source_count],
+                last_sound: 0,
+            })),
+        }
+    }
+
+    /// Measures the samples of a source, which end at the time (in 100ns
+    /// units, relative to the timeline).
+    pub fn analyze(&self, source_index: usize, samples: &[i16], end_time: i64) {
+        let peak = samples
+            .iter()
+            .map(|&sample| (sample as f32 / i16::MAX as f32).abs())
+            .fold(0.0, f32::max);
+        let levels = &mut *self.levels.lock().unwrap();
+        levels.peaks[source_index] = levels.peaks[source_index].max(peak);
+        if peak > SILENCE_THRESHOLD {
+            levels.last_sound = levels.last_sound.max(end_time);
+        }
+    }
+
+    /// The peak level of each source (in dBFS) since the levels were last
+    /// taken.
+    pub fn take_levels(&self) -> Vec<f32> {
+        let mut levels = self.levels.lock().unwrap();
+        let source_count = levels.peaks.len();
+        let peaks = std::mem::replace(&mut levels.peaks, vec![0.0; source_count]);
+        peaks.into_iter().map(to_decibels).collect()
+    }
+
+    /// How long nothing has been heard from any source, as of the time (in
+    /// 100ns units, relative to the timeline).
+    pub fn silence_duration(&self, time: i64) -> Duration {
+        let last_sound = self.levels.lock().unwrap().last_sound;
+        Duration::from_nanos((time - last_sound).max(0) as u64 * 100)
+    }
+}
+
+fn to_decibels(peak: f32) -> f32 {
+    if peak > 0.0 {
+        (20.0 * peak.log10()).max(MIN_LEVEL)
+    } else {
+        MIN_LEVEL
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::AudioLevelMeter;
+
+    #[test]
+    fn audio_level_test() {
+        let meter = AudioLevelMeter::new(2);
+        meter.analyze(0, &[0, 16384, -8192], 10_000_000);
+        meter.analyze(1, &[0, 1, -1], 10_000_000);
+        let levels = meter.take_levels();
+        assert!((levels[0] + 6.0).abs() < 0.1);
+        assert_eq!(levels[1], -60.0);
+        // The levels start over once they're taken
+        assert_eq!(meter.take_levels(), vec![-60.0, -60.0]);
+
+        // Only the first source was heard
+        assert_eq!(meter.silence_duration(60_000_000), Duration::from_secs(5));
+        meter.analyze(1, &[0, 0], 80_000_000);
+        assert_eq!(meter.silence_duration(90_000_000), Duration::from_secs(8));
+    }
+}
@@ -0,0 +1,33 @@
+/// Mixes the source samples into the destination, saturating instead of
+/// wrapping when the combined signal clips.
+pub fn mix_into(destination: &mut [i16], source: &[i16]) {
+    for (destination, source) in destination.iter_mut().zip(source) {
+        *destination = destination.saturating_add(*source);
+    }
+}
+
+/// Mixes equal length buffers of interleaved 16-bit PCM samples into one.
+pub fn mix(sources: &[Vec<i16>]) -> Vec<i16> {
+    let length = sources.iter().map(|source| source.len()).min().unwrap_or(0);
+    let mut result = vec![0i16; length];
+    for source in sources {
+        mix_into(&mut result, &source[..length]);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mix, mix_into};
+
+    #[test]
+    fn mix_test() {
+        let mut destination = vec![100, -100, 30000, -30000];
+        mix_into(&mut destination, &[1, -1, 10000, -10000]);
+        assert_eq!(destination, vec![101, -101, i16::MAX, i16::MIN]);
+
+        let mixed = mix(&[vec![1, 2, 3], vec![10, 20]]);
+        assert_eq!(mixed, vec![11, 22]);
+        assert!(mix(&[]).is_empty());
+    }
+}
@@ -0,0 +1,9 @@
+pub mod aac_encoder;
+pub mod capture;
+pub mod device;
+mod drift;
+pub mod effects;
+pub mod encoding_session;
+pub mod level;
+mod mixer;
+pub mod track_layout;
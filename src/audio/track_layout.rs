@@ -0,0 +1,44 @@
+use std::{fmt::Display, str::FromStr};
+
+/// How multiple audio sources are written to the container.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AudioTrackLayout {
+    /// All sources are mixed down to a single track.
+    Mixed,
+    /// Each source is written to its own track.
+    Separate,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseAudioTrackLayoutError(&'static str);
+
+impl FromStr for AudioTrackLayout {
+    type Err = ParseAudioTrackLayoutError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mixed" => Ok(AudioTrackLayout::Mixed),
+            "separate" => Ok(AudioTrackLayout::Separate),
+            _ => Err(ParseAudioTrackLayoutError(
+                "Invalid audio track layout! Expecting: mixed or separate.",
+            )),
+        }
+    }
+}
+
+impl Display for AudioTrackLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = match self {
+            AudioTrackLayout::Mixed => "mixed",
+            AudioTrackLayout::Separate => "separate",
+        };
+        write!(f, "{}", string)
+    }
+}
+
+impl Display for ParseAudioTrackLayoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseAudioTrackLayoutError {}
@@ -0,0 +1,341 @@
+use std::time::{Duration, Instant};
+
+use windows::{
+    core::{ComInterface, Result},
+    Foundation::TimeSpan,
+    Graphics::SizeInt32,
+    Win32::{
+        Graphics::Direct3D11::ID3D11Device,
+        Media::MediaFoundation::{MFStartup, MFSTARTUP_FULL},
+    },
+};
+
+use crate::{
+    adapter::GraphicsAdapter,
+    capture::{CaptureFrameWait, FrameSource},
+    d3d::{create_d3d_device, create_d3d_device_on_adapter},
+    error::RecorderError,
+    media::MF_VERSION,
+    recorder::configuration_error,
+    resolution::Resolution,
+    stats::{LatencyPercentiles, RecordingStats, StatsCounter},
+    test_pattern::TestPatternSource,
+    video::{
+        codec::VideoCodec,
+        color_format::ColorFormat,
+        color_range::ColorRange,
+        encoder::{VideoEncoder, VideoEncoderInputSample, VideoEncoderSettings},
+        encoder_device::VideoEncoderDevice,
+        fit_mode::FitMode,
+        frame_rate_mode::FrameRateMode,
+        gpu_sync::GpuSync,
+        orientation::Orientation,
+        processor::VideoProcessor,
+    },
+};
+
+/// Measures how fast each encoder encodes an animated test pattern, without
+/// capturing anything. The calling thread must be initialized for WinRT,
+/// see RecorderBuilder.
+pub struct BenchmarkBuilder {
+    codec: VideoCodec,
+    resolution: Resolution,
+    frame_rate: u32,
+    bit_rate: u32,
+    duration: Duration,
+    allow_software_encoder: bool,
+}
+
+/// How an encoder did, see BenchmarkBuilder::run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BenchmarkResult {
+    pub encoder: String,
+    /// The GPU the encoder is on, software encoders don't have one.
+    pub adapter: Option<String>,
+    /// Why the encoder couldn't be benchmarked, if it couldn't.
+    pub error: Option<String>,
+    /// The number of frames that were encoded.
+    pub frames: u64,
+    /// How long the encoder took for all of the frames.
+    pub elapsed: Duration,
+    /// The size of the encoded frames.
+    pub bytes: u64,
+    /// How long the encoder took to turn each frame into a sample.
+    pub latency: LatencyPercentiles,
+}
+
+impl BenchmarkResult {
+    pub fn frames_per_second(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+        self.frames as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Whether the encoder could record at the frame rate in real time.
+    pub fn keeps_up(&self, frame_rate: u32) -> bool {
+        self.error.is_none() && self.frames_per_second() >= frame_rate as f64
+    }
+
+    fn failed(
+        encoder_device: &VideoEncoderDevice,
+        adapter: Option<String>,
+        message: String,
+    ) -> Self {
+        Self {
+            encoder: encoder_device.display_name().to_owned(),
+            adapter,
+            error: Some(message),
+            frames: 0,
+            elapsed: Duration::ZERO,
+            bytes: 0,
+            latency: LatencyPercentiles::default(),
+        }
+    }
+}
+
+impl BenchmarkBuilder {
+    pub fn new() -> Self {
+        Self {
+            codec: VideoCodec::H264,
+            resolution: Resolution::_1080p,
+            frame_rate: 60,
+            bit_rate: 18,
+            duration: Duration::from_secs(10),
+            allow_software_encoder: false,
+        }
+    }
+
+    pub fn codec(mut self, codec: VideoCodec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// The size of the frames, which can't be native since nothing is
+    /// captured.
+    pub fn resolution(mut self, resolution: Resolution) -> Self {
+        self.resolution = resolution;
+        self
+    }
+
+    /// The frame rate the frames are timestamped at. The encoders are given
+    /// frames as fast as they take them, so that their throughput can be
+    /// compared with it.
+    pub fn frame_rate(mut self, frame_rate: u32) -> Self {
+        self.frame_rate = frame_rate;
+        self
+    }
+
+    /// The bit rate to encode at (in Mbps).
+    pub fn bit_rate(mut self, bit_rate: u32) -> Self {
+        self.bit_rate = bit_rate;
+        self
+    }
+
+    /// How much video each encoder encodes, at the frame rate.
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Also benchmarks the software encoders, after the hardware encoders.
+    pub fn allow_software_encoder(mut self, allow_software_encoder: bool) -> Self {
+        self.allow_software_encoder = allow_software_encoder;
+        self
+    }
+
+    /// Runs the encoders one after another. An encoder that can't be set up
+    /// is reported in its result rather than failing the benchmark.
+    pub fn run(self) -> std::result::Result<Vec<BenchmarkResult>, RecorderError> {
+        let size = if let Some(size) = self.resolution.get_size() {
+            size
+        } else {
+            return Err(configuration_error(
+                "Benchmarks need a --resolution other than native, since nothing is captured!",
+            )
+            .into());
+        };
+        if self.frame_rate == 0 {
+            return Err(configuration_error("The --frame-rate must be at least 1!").into());
+        }
+        if self.bit_rate == 0 {
+            return Err(configuration_error("The --bit-rate must be at least 1!").into());
+        }
+        let frame_count = (self.duration.as_secs_f64() * self.frame_rate as f64).round() as u64;
+        if frame_count == 0 {
+            return Err(configuration_error(
+                "The --duration is too short for a single frame at the --frame-rate!",
+            )
+            .into());
+        }
+        unsafe { MFStartup(MF_VERSION, MFSTARTUP_FULL)? }
+
+        // The hardware encoders have to be on the same adapter as the frames
+        let mut results = Vec::new();
+        for adapter in GraphicsAdapter::enumerate()? {
+            let encoder_devices = VideoEncoderDevice::enumerate_for_adapter(self.codec, &adapter)?;
+            if encoder_devices.is_empty() {
+                continue;
+            }
+            let d3d_device = create_d3d_device_on_adapter(&adapter.adapter().cast()?)?;
+            for encoder_device in &encoder_devices {
+                results.push(self.run_encoder(
+                    &d3d_device,
+                    encoder_device,
+                    Some(adapter.display_name().to_owned()),
+                    size,
+                    frame_count,
+                ));
+            }
+        }
+        if self.allow_software_encoder {
+            let d3d_device = create_d3d_device()?;
+            for encoder_device in &VideoEncoderDevice::enumerate_software(self.codec)? {
+                results.push(self.run_encoder(
+                    &d3d_device,
+                    encoder_device,
+                    None,
+                    size,
+                    frame_count,
+                ));
+            }
+        }
+        Ok(results)
+    }
+
+    fn run_encoder(
+        &self,
+        d3d_device: &ID3D11Device,
+        encoder_device: &VideoEncoderDevice,
+        adapter: Option<String>,
+        size: SizeInt32,
+        frame_count: u64,
+    ) -> BenchmarkResult {
+        match self.encode_pattern(d3d_device, encoder_device, size, frame_count) {
+            Ok((stats, elapsed)) => BenchmarkResult {
+                encoder: encoder_device.display_name().to_owned(),
+                adapter,
+                error: None,
+                frames: stats.frames,
+                elapsed,
+                bytes: stats.bytes,
+                latency: stats.encode_latency,
+            },
+            Err(error) => {
+                BenchmarkResult::failed(encoder_device, adapter, error.message().to_string())
+            }
+        }
+    }
+
+    fn encode_pattern(
+        &self,
+        d3d_device: &ID3D11Device,
+        encoder_device: &VideoEncoderDevice,
+        size: SizeInt32,
+        frame_count: u64,
+    ) -> Result<(RecordingStats, Duration)> {
+        let settings = VideoEncoderSettings {
+            bit_rate: self.bit_rate * 1_000_000,
+            frame_rate: self.frame_rate,
+            frame_rate_mode: FrameRateMode::Constant,
+            rate_control: None,
+            quality: None,
+            gop_size: None,
+            b_frames: None,
+            color_format: ColorFormat::Sdr,
+            color_range: ColorRange::Limited,
+            orientation: Orientation::default(),
+            scale_filter: None,
+            fit_mode: FitMode::Letterbox,
+        };
+        let mut video_encoder =
+            VideoEncoder::new(encoder_device, d3d_device.clone(), size, size, settings)?;
+        let stats = StatsCounter::default();
+
+        let mut pattern = TestPattern::new(d3d_device, size, frame_count, &settings)?;
+        video_encoder.set_sample_requested_callback({
+            let stats = stats.clone();
+            move || -> Result<Option<VideoEncoderInputSample>> {
+                let sample = pattern.next_sample()?;
+                if let Some(sample) = &sample {
+                    stats.start_encode(sample.timestamp().Duration);
+                }
+                Ok(sample)
+            }
+        });
+        video_encoder.set_sample_rendered_callback({
+            let stats = stats.clone();
+            move |sample| -> Result<()> {
+                let sample = sample.sample();
+                stats.finish_encode(unsafe { sample.GetSampleTime()? });
+                stats.add_frame(unsafe { sample.GetTotalLength()? } as u64);
+                Ok(())
+            }
+        });
+
+        let start = Instant::now();
+        video_encoder.try_start()?;
+        video_encoder.finish()?;
+        Ok((RecordingStats::combine([&stats]), start.elapsed()))
+    }
+}
+
+impl Default for BenchmarkBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Turns the frames of the test pattern into samples for the encoder
+struct TestPattern {
+    source: TestPatternSource,
+    video_processor: VideoProcessor,
+    gpu_sync: GpuSync,
+    frame_duration: i64,
+    frame_index: i64,
+}
+
+unsafe impl Send for TestPattern {}
+impl TestPattern {
+    fn new(
+        d3d_device: &ID3D11Device,
+        size: SizeInt32,
+        frame_count: u64,
+        settings: &VideoEncoderSettings,
+    ) -> Result<Self> {
+        Ok(Self {
+            source: TestPatternSource::new(
+                d3d_device,
+                size,
+                settings.color_format.texture_format(),
+                settings.frame_rate,
+                Some(frame_count),
+            )?,
+            video_processor: VideoProcessor::new(d3d_device.clone(), size, size, settings)?,
+            gpu_sync: GpuSync::new(d3d_device)?,
+            frame_duration: 10_000_000 / settings.frame_rate as i64,
+            frame_index: 0,
+        })
+    }
+
+    // Runs out after the frame count
+    fn next_sample(&mut self) -> Result<Option<VideoEncoderInputSample>> {
+        let frame = match self.source.wait_for_frame(Duration::ZERO)? {
+            CaptureFrameWait::Frame(_, frame) => frame,
+            _ => return Ok(None),
+        };
+        let texture = self.video_processor.process_texture(frame.texture())?;
+        let mut sample = VideoEncoderInputSample::new(
+            TimeSpan {
+                Duration: self.frame_index * self.frame_duration,
+            },
+            TimeSpan {
+                Duration: self.frame_duration,
+            },
+            texture,
+        );
+        sample.set_ready(self.gpu_sync.signal()?);
+        self.frame_index += 1;
+        Ok(Some(sample))
+    }
+}
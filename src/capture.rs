@@ -1,62 +1,222 @@
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::{
+    sync::{
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
 use windows::{
-    core::{IInspectable, Result},
-    Foundation::TypedEventHandler,
+    core::{AgileReference, IInspectable, Result},
+    Foundation::{EventRegistrationToken, TimeSpan, TypedEventHandler},
     Graphics::{
         Capture::{
             Direct3D11CaptureFrame, Direct3D11CaptureFramePool, GraphicsCaptureItem,
             GraphicsCaptureSession,
         },
-        DirectX::DirectXPixelFormat,
-        SizeInt32,
+        DirectX::{Direct3D11::IDirect3DDevice, DirectXPixelFormat},
+        RectInt32, SizeInt32,
     },
     Win32::{
-        Graphics::{Direct3D11::ID3D11Device, Gdi::HMONITOR},
+        Foundation::HWND,
+        Graphics::{
+            Direct3D11::{
+                ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D, D3D11_CPU_ACCESS_READ,
+                D3D11_MAPPED_SUBRESOURCE, D3D11_MAP_READ, D3D11_TEXTURE2D_DESC,
+                D3D11_USAGE_STAGING,
+            },
+            Dxgi::Common::DXGI_SAMPLE_DESC,
+            Gdi::HMONITOR,
+        },
         System::WinRT::Graphics::Capture::IGraphicsCaptureItemInterop,
     },
 };
 
-use crate::d3d::create_direct3d_device;
+use crate::d3d::{create_direct3d_device, get_d3d_interface_from_object};
 
 pub fn create_capture_item_for_monitor(monitor_handle: HMONITOR) -> Result<GraphicsCaptureItem> {
     let interop = windows::core::factory::<GraphicsCaptureItem, IGraphicsCaptureItemInterop>()?;
     unsafe { interop.CreateForMonitor(monitor_handle) }
 }
 
+pub fn create_capture_item_for_window(window_handle: HWND) -> Result<GraphicsCaptureItem> {
+    let interop = windows::core::factory::<GraphicsCaptureItem, IGraphicsCaptureItemInterop>()?;
+    unsafe { interop.CreateForWindow(window_handle) }
+}
+
+pub const DEFAULT_PIXEL_FORMAT: DirectXPixelFormat = DirectXPixelFormat::B8G8R8A8UIntNormalized;
+
+/// The outcome of waiting for the next frame.
+pub enum CaptureFrameWait<F = Direct3D11CaptureFrame> {
+    Frame(usize, F),
+    /// No frame arrived in time, e.g. because the window is minimized.
+    Timeout,
+    /// The item went away (e.g. its display was disconnected), see
+    /// CaptureFrameGenerator::replace_item.
+    Closed(usize),
+    Stopped,
+}
+
+/// Where the frames of a recording come from. Outside of tests that's the
+/// capture, which a TestPatternSource can stand in for.
+pub trait FrameSource: Send {
+    /// Waits for the next frame, see CaptureFrameGenerator::wait_for_frame.
+    fn wait_for_frame(&mut self, timeout: Duration) -> Result<CaptureFrameWait<CapturedFrame>>;
+    fn stop_handle(&self) -> CaptureStopHandle;
+    fn stop_capture(&mut self) -> Result<()>;
+    /// Moves the source over to a new device, see CaptureFrameGenerator::reset_device.
+    fn reset_device(&mut self, d3d_device: ID3D11Device) -> Result<()>;
+    /// Replaces an item that was closed, see CaptureFrameGenerator::replace_item.
+    fn replace_item(&mut self, index: usize, item: GraphicsCaptureItem) -> Result<()>;
+    /// The capture sessions to start, if the source captures anything.
+    fn sessions(&self) -> Vec<GraphicsCaptureSession>;
+}
+
+/// A frame from a FrameSource. Frames from the capture go back to its frame
+/// pool once they're closed.
+pub struct CapturedFrame {
+    texture: ID3D11Texture2D,
+    content_size: SizeInt32,
+    // When the frame was captured, in the same units as the capture's
+    // SystemRelativeTime
+    time: TimeSpan,
+    frame: Option<Direct3D11CaptureFrame>,
+}
+
+impl CapturedFrame {
+    pub fn new(texture: ID3D11Texture2D, content_size: SizeInt32, time: TimeSpan) -> Self {
+        Self {
+            texture,
+            content_size,
+            time,
+            frame: None,
+        }
+    }
+
+    pub fn from_capture(frame: Direct3D11CaptureFrame) -> Result<Self> {
+        Ok(Self {
+            texture: get_d3d_interface_from_object(&frame.Surface()?)?,
+            content_size: frame.ContentSize()?,
+            time: frame.SystemRelativeTime()?,
+            frame: Some(frame),
+        })
+    }
+
+    pub fn texture(&self) -> &ID3D11Texture2D {
+        &self.texture
+    }
+
+    /// The part of the texture that has content, which is smaller than the
+    /// texture while the capture catches up with a resized item.
+    pub fn content_size(&self) -> SizeInt32 {
+        self.content_size
+    }
+
+    pub fn time(&self) -> TimeSpan {
+        self.time
+    }
+
+    pub fn set_time(&mut self, time: TimeSpan) {
+        self.time = time;
+    }
+
+    pub fn close(&self) -> Result<()> {
+        if let Some(frame) = &self.frame {
+            frame.Close()?;
+        }
+        Ok(())
+    }
+}
+
 pub struct CaptureFrameGenerator {
     _d3d_device: ID3D11Device,
-    _item: GraphicsCaptureItem,
+    // Shared with the frame pools, which are recreated on it when resized
+    device: Arc<Mutex<AgileReference<IDirect3DDevice>>>,
+    pixel_format: DirectXPixelFormat,
+    capture_cursor: bool,
+    sources: Vec<CaptureSource>,
+    sender: Sender<CaptureMessage>,
+    receiver: Receiver<CaptureMessage>,
+}
+
+struct CaptureSource {
+    item: GraphicsCaptureItem,
+    closed_token: EventRegistrationToken,
     frame_pool: Direct3D11CaptureFramePool,
     session: GraphicsCaptureSession,
-    sender: Sender<Option<Direct3D11CaptureFrame>>,
-    receiver: Receiver<Option<Direct3D11CaptureFrame>>,
+}
+
+enum CaptureMessage {
+    Frame(usize, Direct3D11CaptureFrame),
+    Closed(usize),
+    Stop,
 }
 
 impl CaptureFrameGenerator {
+    /// Captures multiple items at once. Frames from all of the items are
+    /// delivered in the order they arrive, along with the index of their item.
     pub fn new(
         d3d_device: ID3D11Device,
-        item: GraphicsCaptureItem,
-        size: SizeInt32,
+        items: Vec<(GraphicsCaptureItem, SizeInt32)>,
+        pixel_format: DirectXPixelFormat,
+        capture_cursor: bool,
     ) -> Result<Self> {
         let device = create_direct3d_device(&d3d_device)?;
-        let frame_pool = Direct3D11CaptureFramePool::CreateFreeThreaded(
-            &device,
-            DirectXPixelFormat::B8G8R8A8UIntNormalized,
-            2,
-            size,
-        )?;
+        let device = Arc::new(Mutex::new(AgileReference::new(&device)?));
+        let (sender, receiver) = channel();
+        let mut generator = Self {
+            _d3d_device: d3d_device,
+            device,
+            pixel_format,
+            capture_cursor,
+            sources: Vec::new(),
+            sender,
+            receiver,
+        };
+        for (index, (item, size)) in items.into_iter().enumerate() {
+            let source = generator.create_source(index, item, size)?;
+            generator.sources.push(source);
+        }
+        Ok(generator)
+    }
+
+    fn create_source(
+        &self,
+        index: usize,
+        item: GraphicsCaptureItem,
+        size: SizeInt32,
+    ) -> Result<CaptureSource> {
+        let device = self.device.lock().unwrap().resolve()?;
+        let pixel_format = self.pixel_format;
+        let frame_pool =
+            Direct3D11CaptureFramePool::CreateFreeThreaded(&device, pixel_format, 2, size)?;
         let session = frame_pool.CreateCaptureSession(&item)?;
+        if !self.capture_cursor {
+            session.SetIsCursorCaptureEnabled(false)?;
+        }
 
-        let (sender, receiver) = channel();
         frame_pool.FrameArrived(
             &TypedEventHandler::<Direct3D11CaptureFramePool, IInspectable>::new({
+                let device = self.device.clone();
                 let session = session.clone();
-                let sender = sender.clone();
+                let sender = self.sender.clone();
+                let mut last_size = size;
                 move |frame_pool, _| {
                     let frame_pool = frame_pool.as_ref().unwrap();
                     let frame = frame_pool.TryGetNextFrame()?;
-                    if sender.send(Some(frame)).is_err() {
+
+                    // If the item changed size (e.g. a window was resized), recreate
+                    // the frame pool so that future frames match the new size. The
+                    // current frame is still delivered and will be clamped by the
+                    // consumer.
+                    let content_size = frame.ContentSize()?;
+                    if content_size != last_size {
+                        let device = device.lock().unwrap().resolve()?;
+                        frame_pool.Recreate(&device, pixel_format, 2, content_size)?;
+                        last_size = content_size;
+                    }
+
+                    if sender.send(CaptureMessage::Frame(index, frame)).is_err() {
                         frame_pool.Close()?;
                         session.Close()?;
                     }
@@ -64,38 +224,290 @@ impl CaptureFrameGenerator {
                 }
             }),
         )?;
+        let closed_token = item.Closed(
+            &TypedEventHandler::<GraphicsCaptureItem, IInspectable>::new({
+                let sender = self.sender.clone();
+                move |_, _| {
+                    let _ = sender.send(CaptureMessage::Closed(index));
+                    Ok(())
+                }
+            }),
+        )?;
 
-        Ok(Self {
-            _d3d_device: d3d_device,
-            _item: item,
+        Ok(CaptureSource {
+            item,
+            closed_token,
             frame_pool,
             session,
-            sender,
-            receiver,
         })
     }
 
-    pub fn session(&self) -> &GraphicsCaptureSession {
-        &self.session
+    /// Captures a new item in place of one that was closed (e.g. for a
+    /// display that was disconnected and came back, or after the system
+    /// resumed from sleep). The new item is captured right away.
+    pub fn replace_item(&mut self, index: usize, item: GraphicsCaptureItem) -> Result<()> {
+        let size = item.Size()?;
+        let source = self.create_source(index, item, size)?;
+        source.session.StartCapture()?;
+        // Stops the old capture
+        self.sources[index] = source;
+        Ok(())
     }
 
-    pub fn try_get_next_frame(&mut self) -> Result<Option<Direct3D11CaptureFrame>> {
-        if let Some(frame) = self.receiver.recv().unwrap() {
-            Ok(Some(frame))
-        } else {
-            Ok(None)
+    pub fn sessions(&self) -> Vec<GraphicsCaptureSession> {
+        self.sources
+            .iter()
+            .map(|source| source.session.clone())
+            .collect()
+    }
+
+    pub fn stop_handle(&self) -> CaptureStopHandle {
+        let sender = self.sender.clone();
+        CaptureStopHandle::new(move || {
+            // The capture may have already stopped on its own
+            let _ = sender.send(CaptureMessage::Stop);
+        })
+    }
+
+    /// Waits for the next frame from any item. Items that are closed simply
+    /// stop producing frames.
+    pub fn try_get_next_frame(&mut self) -> Result<Option<(usize, Direct3D11CaptureFrame)>> {
+        loop {
+            match self.receiver.recv().unwrap() {
+                CaptureMessage::Frame(index, frame) => return Ok(Some((index, frame))),
+                CaptureMessage::Closed(_) => {}
+                CaptureMessage::Stop => return Ok(None),
+            }
+        }
+    }
+
+    /// Like try_get_next_frame, but gives up after the timeout and reports
+    /// items that are closed.
+    pub fn wait_for_frame(&mut self, timeout: Duration) -> Result<CaptureFrameWait> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(CaptureMessage::Frame(index, frame)) => Ok(CaptureFrameWait::Frame(index, frame)),
+            Ok(CaptureMessage::Closed(index)) => Ok(CaptureFrameWait::Closed(index)),
+            Err(RecvTimeoutError::Timeout) => Ok(CaptureFrameWait::Timeout),
+            Ok(CaptureMessage::Stop) | Err(RecvTimeoutError::Disconnected) => {
+                Ok(CaptureFrameWait::Stopped)
+            }
         }
     }
 
     pub fn stop_capture(&mut self) -> Result<()> {
-        self.sender.send(None).unwrap();
+        self.sender.send(CaptureMessage::Stop).unwrap();
         Ok(())
     }
+
+    /// Moves the capture over to a new device, e.g. once the old one was
+    /// lost. The capture carries on, with new frames on the new device.
+    pub fn reset_device(&mut self, d3d_device: ID3D11Device) -> Result<()> {
+        let device = create_direct3d_device(&d3d_device)?;
+        *self.device.lock().unwrap() = AgileReference::new(&device)?;
+        for source in &self.sources {
+            source
+                .frame_pool
+                .Recreate(&device, self.pixel_format, 2, source.item.Size()?)?;
+        }
+        self._d3d_device = d3d_device;
+
+        // Frames that already arrived are still on the old device
+        loop {
+            match self.receiver.try_recv() {
+                Ok(CaptureMessage::Frame(_, frame)) => frame.Close()?,
+                Ok(message) => {
+                    // Don't lose a request to stop, or a closed item
+                    self.sender.send(message).unwrap();
+                    break;
+                }
+                Err(_) => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Stops a capture (or another FrameSource) from another thread, even while
+/// it is waiting for a frame.
+pub struct CaptureStopHandle(Box<dyn Fn() + Send + Sync>);
+
+impl CaptureStopHandle {
+    pub fn new<F: 'static + Fn() + Send + Sync>(stop: F) -> Self {
+        Self(Box::new(stop))
+    }
+
+    pub fn stop(&self) {
+        (self.0)()
+    }
 }
 
-impl Drop for CaptureFrameGenerator {
+impl FrameSource for CaptureFrameGenerator {
+    fn wait_for_frame(&mut self, timeout: Duration) -> Result<CaptureFrameWait<CapturedFrame>> {
+        Ok(
+            match CaptureFrameGenerator::wait_for_frame(self, timeout)? {
+                CaptureFrameWait::Frame(index, frame) => {
+                    CaptureFrameWait::Frame(index, CapturedFrame::from_capture(frame)?)
+                }
+                CaptureFrameWait::Timeout => CaptureFrameWait::Timeout,
+                CaptureFrameWait::Closed(index) => CaptureFrameWait::Closed(index),
+                CaptureFrameWait::Stopped => CaptureFrameWait::Stopped,
+            },
+        )
+    }
+
+    fn stop_handle(&self) -> CaptureStopHandle {
+        CaptureFrameGenerator::stop_handle(self)
+    }
+
+    fn stop_capture(&mut self) -> Result<()> {
+        CaptureFrameGenerator::stop_capture(self)
+    }
+
+    fn reset_device(&mut self, d3d_device: ID3D11Device) -> Result<()> {
+        CaptureFrameGenerator::reset_device(self, d3d_device)
+    }
+
+    fn replace_item(&mut self, index: usize, item: GraphicsCaptureItem) -> Result<()> {
+        CaptureFrameGenerator::replace_item(self, index, item)
+    }
+
+    fn sessions(&self) -> Vec<GraphicsCaptureSession> {
+        CaptureFrameGenerator::sessions(self)
+    }
+}
+
+impl Drop for CaptureSource {
     fn drop(&mut self) {
+        let _ = self.item.RemoveClosed(self.closed_token);
         self.session.Close().unwrap();
         self.frame_pool.Close().unwrap();
     }
 }
+
+/// Copies frames back to the CPU, e.g. for GIFs or screenshots.
+pub struct FrameReader {
+    d3d_device: ID3D11Device,
+    d3d_context: ID3D11DeviceContext,
+    staging_texture: Option<ID3D11Texture2D>,
+}
+
+impl FrameReader {
+    pub fn new(d3d_device: ID3D11Device) -> Result<Self> {
+        let d3d_context = unsafe { d3d_device.GetImmediateContext()? };
+        Ok(Self {
+            d3d_device,
+            d3d_context,
+            staging_texture: None,
+        })
+    }
+
+    /// Reads textures of a new device from now on, e.g. once the old one was lost.
+    pub fn reset_device(&mut self, d3d_device: ID3D11Device) -> Result<()> {
+        *self = Self::new(d3d_device)?;
+        Ok(())
+    }
+
+    /// Reads the frame (or the region of it) as tightly packed BGRA pixels.
+    pub fn read(
+        &mut self,
+        frame: &Direct3D11CaptureFrame,
+        region: Option<RectInt32>,
+    ) -> Result<(Vec<u8>, SizeInt32)> {
+        let frame_texture: ID3D11Texture2D = get_d3d_interface_from_object(&frame.Surface()?)?;
+        self.read_texture(&frame_texture, frame.ContentSize()?, region)
+    }
+
+    /// Reads the part of a BGRA texture that has content (or the region of it)
+    /// as tightly packed BGRA pixels.
+    pub fn read_texture(
+        &mut self,
+        texture: &ID3D11Texture2D,
+        content_size: SizeInt32,
+        region: Option<RectInt32>,
+    ) -> Result<(Vec<u8>, SizeInt32)> {
+        let desc = unsafe {
+            let mut desc = D3D11_TEXTURE2D_DESC::default();
+            texture.GetDesc(&mut desc);
+            desc
+        };
+
+        // The frame pool is recreated when the item changes size, so our
+        // staging texture needs to follow suit.
+        let staging_texture = self.get_staging_texture(&desc)?;
+
+        let max_width = content_size.Width.clamp(0, desc.Width as i32);
+        let max_height = content_size.Height.clamp(0, desc.Height as i32);
+        let (left, top, right, bottom) = if let Some(region) = region {
+            (
+                region.X,
+                region.Y,
+                region.X + region.Width,
+                region.Y + region.Height,
+            )
+        } else {
+            (0, 0, max_width, max_height)
+        };
+        let right = right.clamp(0, max_width);
+        let bottom = bottom.clamp(0, max_height);
+        let left = left.clamp(0, right);
+        let top = top.clamp(0, bottom);
+        let size = SizeInt32 {
+            Width: right - left,
+            Height: bottom - top,
+        };
+
+        unsafe {
+            self.d3d_context.CopyResource(&staging_texture, texture);
+            let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+            self.d3d_context
+                .Map(&staging_texture, 0, D3D11_MAP_READ, 0, Some(&mut mapped))?;
+            let data = std::slice::from_raw_parts(
+                mapped.pData as *const u8,
+                (mapped.RowPitch * desc.Height) as usize,
+            );
+            let mut pixels = Vec::with_capacity((size.Width * size.Height * 4) as usize);
+            for y in top..bottom {
+                let start = (y as u32 * mapped.RowPitch) as usize + left as usize * 4;
+                pixels.extend_from_slice(&data[start..start + size.Width as usize * 4]);
+            }
+            self.d3d_context.Unmap(&staging_texture, 0);
+            Ok((pixels, size))
+        }
+    }
+
+    fn get_staging_texture(&mut self, desc: &D3D11_TEXTURE2D_DESC) -> Result<ID3D11Texture2D> {
+        if let Some(texture) = &self.staging_texture {
+            let current_desc = unsafe {
+                let mut current_desc = D3D11_TEXTURE2D_DESC::default();
+                texture.GetDesc(&mut current_desc);
+                current_desc
+            };
+            if current_desc.Width == desc.Width && current_desc.Height == desc.Height {
+                return Ok(texture.clone());
+            }
+        }
+
+        let texture_desc = D3D11_TEXTURE2D_DESC {
+            Width: desc.Width,
+            Height: desc.Height,
+            ArraySize: 1,
+            MipLevels: 1,
+            Format: desc.Format,
+            SampleDesc: DXGI_SAMPLE_DESC {
+                Count: 1,
+                ..Default::default()
+            },
+            Usage: D3D11_USAGE_STAGING,
+            CPUAccessFlags: D3D11_CPU_ACCESS_READ.0 as u32,
+            ..Default::default()
+        };
+        let texture = unsafe {
+            let mut texture = None;
+            self.d3d_device
+                .CreateTexture2D(&texture_desc, None, Some(&mut texture))?;
+            texture.unwrap()
+        };
+        self.staging_texture = Some(texture.clone());
+        Ok(texture)
+    }
+}
@@ -0,0 +1,155 @@
+use std::time::Duration;
+
+use windows::{core::Result, Win32::Media::MediaFoundation::IMFSample};
+
+use crate::replay_buffer::ReplayBuffer;
+
+/// Only lets samples through while the screen is changing, along with the
+/// ones from a little before (the pre-roll) and after (the post-roll) each
+/// change. The gaps in between are left out of the recording.
+pub struct ChangeGate {
+    pre_roll: ReplayBuffer,
+    window: ChangeWindow,
+    writing: bool,
+    // Subtracted from the times of the samples that are let through, so
+    // that they carry on from the last ones
+    offset: i64,
+    // Where the samples that were let through end (in 100ns units)
+    written_until: Option<i64>,
+}
+
+// When the screen started and last changed, and how long the samples after
+// a change are kept for. Samples from before the changes started (e.g.
+// repeats that fill the gap before them) only make it in as the pre-roll.
+struct ChangeWindow {
+    post_roll: i64,
+    first_change: i64,
+    last_change: Option<i64>,
+}
+
+impl ChangeGate {
+    pub fn new(pre_roll: Duration, post_roll: Duration) -> Self {
+        Self {
+            pre_roll: ReplayBuffer::new(pre_roll),
+            window: ChangeWindow::new(post_roll),
+            writing: false,
+            offset: 0,
+            written_until: None,
+        }
+    }
+
+    /// Notes that the frame at the time (in 100ns units, relative to the
+    /// timeline) changed what's recorded.
+    pub fn changed(&mut self, time: i64) {
+        self.window.changed(time);
+    }
+
+    /// Returns the samples to write, with their times moved to close the
+    /// gaps (the first ones start at 0). Samples are held back until there's
+    /// a change, and the pre-roll goes in ahead of it.
+    pub fn push(&mut self, stream_index: u32, sample: &IMFSample) -> Result<Vec<(u32, IMFSample)>> {
+        let time = unsafe { sample.GetSampleTime()? };
+        if !self.window.is_open(time) {
+            self.writing = false;
+            self.pre_roll.push(stream_index, sample)?;
+            return Ok(Vec::new());
+        }
+        let samples = if self.writing {
+            vec![(stream_index, sample.clone())]
+        } else {
+            // Playback starts from the key frame the pre-roll was kept from
+            self.pre_roll.push(stream_index, sample)?;
+            let samples = self.pre_roll.take();
+            if let Some((_, first_sample)) = samples.first() {
+                let start_time = unsafe { first_sample.GetSampleTime()? };
+                self.offset = start_time - self.written_until.unwrap_or(0);
+            }
+            self.writing = true;
+            samples
+        };
+        self.rebase(samples)
+    }
+
+    /// Returns what's left once the recording stops. If nothing changed at
+    /// all, that's the pre-roll, so that the recording isn't empty.
+    pub fn finish(&mut self) -> Result<Vec<(u32, IMFSample)>> {
+        if self.written_until.is_some() {
+            return Ok(Vec::new());
+        }
+        let samples = self.pre_roll.take();
+        if let Some((_, first_sample)) = samples.first() {
+            self.offset = unsafe { first_sample.GetSampleTime()? };
+        }
+        self.rebase(samples)
+    }
+
+    fn rebase(&mut self, samples: Vec<(u32, IMFSample)>) -> Result<Vec<(u32, IMFSample)>> {
+        for (_, sample) in &samples {
+            let time = unsafe { sample.GetSampleTime()? } - self.offset;
+            let duration = unsafe { sample.GetSampleDuration() }.unwrap_or_default();
+            unsafe { sample.SetSampleTime(time)? };
+            self.written_until = Some(self.written_until.unwrap_or(0).max(time + duration));
+        }
+        Ok(samples)
+    }
+}
+
+impl ChangeWindow {
+    fn new(post_roll: Duration) -> Self {
+        Self {
+            post_roll: (post_roll.as_nanos() / 100) as i64,
+            first_change: 0,
+            last_change: None,
+        }
+    }
+
+    fn changed(&mut self, time: i64) {
+        match self.last_change {
+            // Frames that arrive late don't close the window early
+            Some(last_change) if time <= last_change => {}
+            Some(last_change) if time <= last_change + self.post_roll => {
+                self.last_change = Some(time);
+            }
+            _ => {
+                self.first_change = time;
+                self.last_change = Some(time);
+            }
+        }
+    }
+
+    // Whether samples at the time are written
+    fn is_open(&self, time: i64) -> bool {
+        self.last_change.is_some_and(|last_change| {
+            time >= self.first_change && time <= last_change + self.post_roll
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::ChangeWindow;
+
+    #[test]
+    fn change_window_test() {
+        let mut window = ChangeWindow::new(Duration::from_secs(1));
+        // Nothing is written before the first change, other than the pre-roll
+        assert!(!window.is_open(0));
+        window.changed(20_000_000);
+        assert!(!window.is_open(19_999_999));
+        assert!(window.is_open(20_000_000));
+        assert!(window.is_open(30_000_000));
+        assert!(!window.is_open(30_000_001));
+
+        // More changes keep it open, frames that arrive late don't close it early
+        window.changed(25_000_000);
+        window.changed(22_000_000);
+        assert!(window.is_open(35_000_000));
+
+        // A change after the post-roll opens it again from there
+        window.changed(50_000_000);
+        assert!(!window.is_open(40_000_000));
+        assert!(window.is_open(50_000_000));
+    }
+}
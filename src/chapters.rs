@@ -0,0 +1,88 @@
+use std::{fmt::Display, time::Duration};
+
+/// The markers of a recording as WebVTT chapters, which players (e.g.
+/// browsers with a <track kind="chapters">) show as points to jump to.
+/// Each chapter lasts until the next one, or the end of the recording.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Chapters {
+    chapters: Vec<(Duration, String)>,
+    duration: Duration,
+}
+
+impl Chapters {
+    /// Adds a chapter at the time, named "Chapter N" if the title is empty.
+    pub fn add(&mut self, time: Duration, title: &str) {
+        let title = title.trim();
+        let title = if title.is_empty() {
+            format!("Chapter {}", self.chapters.len() + 1)
+        } else {
+            title.to_owned()
+        };
+        self.chapters.push((time, title));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chapters.is_empty()
+    }
+
+    /// The length of the recording, where the last chapter ends.
+    pub fn set_duration(&mut self, duration: Duration) {
+        self.duration = duration;
+    }
+}
+
+impl Display for Chapters {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "WEBVTT")?;
+        let ends = self
+            .chapters
+            .iter()
+            .skip(1)
+            .map(|(time, _)| *time)
+            .chain([self.duration]);
+        for ((start, title), end) in self.chapters.iter().zip(ends) {
+            // Cues have to end after they start
+            if end <= *start {
+                continue;
+            }
+            writeln!(f)?;
+            writeln!(f, "{} --> {}", format_time(*start), format_time(end))?;
+            // A line with just the arrow would end the cue's text
+            writeln!(f, "{}", title.replace("-->", "->").replace('\n', " "))?;
+        }
+        Ok(())
+    }
+}
+
+fn format_time(time: Duration) -> String {
+    let millis = time.as_millis();
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Chapters;
+
+    #[test]
+    fn chapters_test() {
+        let mut chapters = Chapters::default();
+        chapters.add(Duration::from_millis(1500), "Intro");
+        chapters.add(Duration::from_secs(3725), "");
+        // Too late to have a length
+        chapters.add(Duration::from_secs(4000), "Outro");
+        chapters.set_duration(Duration::from_secs(4000));
+        assert_eq!(
+            chapters.to_string(),
+            "WEBVTT\n\n00:00:01.500 --> 01:02:05.000\nIntro\n\n01:02:05.000 --> 01:06:40.000\nChapter 2\n"
+        );
+        assert_eq!(Chapters::default().to_string(), "WEBVTT\n");
+    }
+}
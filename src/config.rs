@@ -0,0 +1,310 @@
+use std::fmt::Display;
+
+use clap::{parser::ValueSource, ArgMatches, Command};
+
+/// The settings of a config file (a small subset of TOML), which provide
+/// defaults for the command line options. Keys are the names of the long
+/// options, e.g. bit-rate = 25 or system-audio = true.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    entries: Vec<(String, ConfigValue)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigValue {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<ConfigValue>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseConfigError {
+    line: usize,
+    message: &'static str,
+}
+
+impl Config {
+    /// Turns the settings into command line arguments. Settings for options
+    /// that were provided on the command line are left out, so that the
+    /// command line wins.
+    pub fn to_args(&self, command: &Command, matches: &ArgMatches) -> Result<Vec<String>, String> {
+        let mut args = Vec::new();
+        for (key, value) in &self.entries {
+            let arg = command
+                .get_arguments()
+                .find(|arg| {
+                    if arg.is_positional() {
+                        arg.get_id().as_str().replace('_', "-") == *key
+                    } else {
+                        arg.get_long() == Some(key.as_str())
+                    }
+                })
+                .filter(|arg| !matches!(arg.get_id().as_str(), "config" | "help" | "version"))
+                .ok_or_else(|| format!("Unknown setting \"{}\"!", key))?;
+            if matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine) {
+                continue;
+            }
+
+            let values = match value {
+                ConfigValue::Array(values) => values.iter().collect(),
+                value => vec![value],
+            };
+            for value in values {
+                let value = match value {
+                    ConfigValue::String(value) => value.clone(),
+                    ConfigValue::Integer(value) => value.to_string(),
+                    ConfigValue::Float(value) => value.to_string(),
+                    ConfigValue::Boolean(value) => value.to_string(),
+                    ConfigValue::Array(_) => {
+                        return Err(format!("The \"{}\" setting can't be nested!", key))
+                    }
+                };
+                if arg.is_positional() {
+                    args.push(value);
+                } else if arg.get_action().takes_values() {
+                    // Joined, so that values like "-3dB" aren't taken for arguments
+                    args.push(format!("--{}={}", key, value));
+                } else {
+                    // Flags can only be turned on
+                    let enabled = value
+                        .parse::<bool>()
+                        .map_err(|_| format!("The \"{}\" setting must be true or false!", key))?;
+                    if enabled {
+                        args.push(format!("--{}", key));
+                    }
+                }
+            }
+        }
+        Ok(args)
+    }
+}
+
+impl std::str::FromStr for Config {
+    type Err = ParseConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut entries = Vec::new();
+        for (index, line) in s.lines().enumerate() {
+            let error = |message| ParseConfigError {
+                line: index + 1,
+                message,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') {
+                return Err(error("Tables aren't supported!"));
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("Expecting a setting: key = value"))?;
+            let key = key.trim();
+            if key.is_empty()
+                || !key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(error(
+                    "Invalid key! Keys are the names of options, e.g. bit-rate.",
+                ));
+            }
+            if entries.iter().any(|(existing, _)| existing == key) {
+                return Err(error("Duplicate key!"));
+            }
+            let mut chars = value.trim().chars().peekable();
+            let value = parse_value(&mut chars).ok_or_else(|| error("Invalid value!"))?;
+            skip_whitespace(&mut chars);
+            match chars.next() {
+                None | Some('#') => {}
+                Some(_) => return Err(error("Unexpected characters after the value!")),
+            }
+            entries.push((key.to_owned(), value));
+        }
+        Ok(Self { entries })
+    }
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+fn skip_whitespace(chars: &mut Chars) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+fn parse_value(chars: &mut Chars) -> Option<ConfigValue> {
+    skip_whitespace(chars);
+    match chars.peek()? {
+        '"' => {
+            chars.next();
+            let mut value = String::new();
+            loop {
+                match chars.next()? {
+                    '"' => return Some(ConfigValue::String(value)),
+                    '\\' => match chars.next()? {
+                        '"' => value.push('"'),
+                        '\\' => value.push('\\'),
+                        'n' => value.push('\n'),
+                        't' => value.push('\t'),
+                        _ => return None,
+                    },
+                    c => value.push(c),
+                }
+            }
+        }
+        // Literal strings don't have escapes, which is handy for Windows paths
+        '\'' => {
+            chars.next();
+            let mut value = String::new();
+            loop {
+                match chars.next()? {
+                    '\'' => return Some(ConfigValue::String(value)),
+                    c => value.push(c),
+                }
+            }
+        }
+        '[' => {
+            chars.next();
+            let mut values = Vec::new();
+            loop {
+                skip_whitespace(chars);
+                if chars.next_if_eq(&']').is_some() {
+                    return Some(ConfigValue::Array(values));
+                }
+                values.push(parse_value(chars)?);
+                skip_whitespace(chars);
+                match chars.next()? {
+                    ',' => continue,
+                    ']' => return Some(ConfigValue::Array(values)),
+                    _ => return None,
+                }
+            }
+        }
+        _ => {
+            let mut word = String::new();
+            while let Some(c) =
+                chars.next_if(|c| !c.is_whitespace() && !matches!(c, ',' | ']' | '#'))
+            {
+                word.push(c);
+            }
+            match word.as_str() {
+                "true" => Some(ConfigValue::Boolean(true)),
+                "false" => Some(ConfigValue::Boolean(false)),
+                // TOML allows underscores between digits, e.g. 1_000
+                word => {
+                    let number = word.replace('_', "");
+                    if let Ok(value) = number.parse() {
+                        Some(ConfigValue::Integer(value))
+                    } else {
+                        number.parse().ok().map(ConfigValue::Float)
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Display for ParseConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.message)
+    }
+}
+impl std::error::Error for ParseConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, FromArgMatches};
+
+    use crate::args::Args;
+
+    use super::{Config, ConfigValue};
+
+    #[test]
+    fn config_parsing_test() {
+        let config: Config = r#"
+            # A shared profile
+            bit-rate = 25
+            codec = "hevc" # Comments can follow values
+            mic = 'C:\Devices\Mic'
+            watermark-opacity = 0.75
+            system-audio = true
+            show-keys-allow = ["ctrl+c", "ctrl+v"]
+        "#
+        .parse()
+        .unwrap();
+        assert_eq!(
+            config.entries,
+            vec![
+                ("bit-rate".to_owned(), ConfigValue::Integer(25)),
+                ("codec".to_owned(), ConfigValue::String("hevc".to_owned())),
+                (
+                    "mic".to_owned(),
+                    ConfigValue::String(r"C:\Devices\Mic".to_owned())
+                ),
+                ("watermark-opacity".to_owned(), ConfigValue::Float(0.75)),
+                ("system-audio".to_owned(), ConfigValue::Boolean(true)),
+                (
+                    "show-keys-allow".to_owned(),
+                    ConfigValue::Array(vec![
+                        ConfigValue::String("ctrl+c".to_owned()),
+                        ConfigValue::String("ctrl+v".to_owned())
+                    ])
+                ),
+            ]
+        );
+
+        assert!("[profile]".parse::<Config>().is_err());
+        assert!("bit-rate".parse::<Config>().is_err());
+        assert!("bit-rate = 25 30".parse::<Config>().is_err());
+        assert!("codec = \"hevc".parse::<Config>().is_err());
+        let error = "a = 1\na = 2".parse::<Config>().unwrap_err();
+        assert_eq!(error.to_string(), "Line 2: Duplicate key!");
+    }
+
+    #[test]
+    fn config_args_test() {
+        let config: Config = r#"
+            bit-rate = 25
+            frame-rate = 30
+            system-audio = true
+            preview = false
+            display = [0, 1]
+            output-file = "recording-{date}.mp4"
+            mic-gain = "-3dB"
+        "#
+        .parse()
+        .unwrap();
+        let command = Args::command();
+        let matches =
+            command
+                .clone()
+                .get_matches_from(["displayrecorder.exe", "--frame-rate", "60"]);
+        assert_eq!(
+            config.to_args(&command, &matches).unwrap(),
+            vec![
+                "--bit-rate=25",
+                "--system-audio",
+                "--display=0",
+                "--display=1",
+                "recording-{date}.mp4",
+                "--mic-gain=-3dB"
+            ]
+        );
+        let args = config.to_args(&command, &matches).unwrap();
+        let matches = command
+            .clone()
+            .try_get_matches_from(std::iter::once("displayrecorder.exe".to_string()).chain(args))
+            .unwrap();
+        let args = Args::from_arg_matches(&matches).unwrap();
+        assert_eq!(args.bit_rate, 25);
+        assert_eq!(args.display.len(), 2);
+        assert_eq!(args.mic_gain.unwrap().decibels(), -3.0);
+
+        let matches = command.clone().get_matches_from(["displayrecorder.exe"]);
+        let config: Config = "bitrate = 25".parse().unwrap();
+        assert!(config.to_args(&command, &matches).is_err());
+        let config: Config = "preview = 1".parse().unwrap();
+        assert!(config.to_args(&command, &matches).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use std::io::{self, Write};
+
+use windows::{
+    core::{Error, Result},
+    Win32::Media::MediaFoundation::{
+        IMFByteStream, IMFMediaType, IMFSample, MFMediaType_Video, MFVideoFormat_H264,
+        MF_E_INVALIDMEDIATYPE, MF_MT_MAJOR_TYPE, MF_MT_SUBTYPE,
+    },
+};
+
+use crate::media::{get_sample_data, is_key_frame};
+
+use super::{to_error, ContainerWriter};
+
+/// Writes the H.264 video as a raw Annex B elementary stream, which is what
+/// the encoder produces already. There's no room for audio or timestamps,
+/// readers are expected to know the frame rate (e.g. ffmpeg -framerate).
+pub struct AnnexBWriter {
+    output: Box<dyn Write + Send>,
+    has_video: bool,
+    // Decoders can only start at a key frame, which carries the parameter sets
+    started: bool,
+}
+
+struct ByteStreamWriter(IMFByteStream);
+
+unsafe impl Send for ByteStreamWriter {}
+
+impl AnnexBWriter {
+    pub fn new(output: Box<dyn Write + Send>) -> Self {
+        Self {
+            output,
+            has_video: false,
+            started: false,
+        }
+    }
+
+    pub fn from_byte_stream(byte_stream: IMFByteStream) -> Self {
+        Self::new(Box::new(ByteStreamWriter(byte_stream)))
+    }
+}
+
+impl ContainerWriter for AnnexBWriter {
+    fn add_stream(
+        &mut self,
+        _output_type: &IMFMediaType,
+        input_type: &IMFMediaType,
+    ) -> Result<u32> {
+        let (major_type, subtype) = unsafe {
+            (
+                input_type.GetGUID(&MF_MT_MAJOR_TYPE)?,
+                input_type.GetGUID(&MF_MT_SUBTYPE)?,
+            )
+        };
+        if major_type != MFMediaType_Video || subtype != MFVideoFormat_H264 || self.has_video {
+            return Err(Error::from(MF_E_INVALIDMEDIATYPE));
+        }
+        self.has_video = true;
+        Ok(0)
+    }
+
+    fn start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_sample(&mut self, _stream_index: u32, sample: &IMFSample) -> Result<()> {
+        if !self.started && !is_key_frame(sample) {
+            return Ok(());
+        }
+        self.started = true;
+        let data = get_sample_data(sample)?;
+        self.output.write_all(&data).map_err(to_error)
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.output.flush().map_err(to_error)
+    }
+}
+
+impl Write for ByteStreamWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = unsafe { self.0.Write(buf) }.map_err(io::Error::other)?;
+        Ok(written as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        unsafe { self.0.Flush() }.map_err(io::Error::other)
+    }
+}
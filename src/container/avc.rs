@@ -0,0 +1,121 @@
+// H.264 encoders hand us Annex B streams (NAL units separated by start codes),
+// while Matroska expects each NAL unit to be prefixed by its length and the
+// parameter sets to be provided up front in an AVCDecoderConfigurationRecord.
+
+const NAL_UNIT_TYPE_SPS: u8 = 7;
+const NAL_UNIT_TYPE_PPS: u8 = 8;
+const NAL_LENGTH_SIZE: usize = 4;
+
+pub fn split_nal_units(data: &[u8]) -> Vec<&[u8]> {
+    let mut units = Vec::new();
+    let mut start = None;
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            if let Some(start) = start {
+                units.push(trim_trailing_zeros(&data[start..i]));
+            }
+            i += 3;
+            start = Some(i);
+        } else {
+            i += 1;
+        }
+    }
+    if let Some(start) = start {
+        units.push(&data[start..]);
+    }
+    units.retain(|unit| !unit.is_empty());
+    units
+}
+
+/// Converts an Annex B sample to length prefixed NAL units.
+pub fn to_length_prefixed(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    for unit in split_nal_units(data) {
+        result.extend_from_slice(&(unit.len() as u32).to_be_bytes());
+        result.extend_from_slice(unit);
+    }
+    result
+}
+
+/// Builds the codec private data from the parameter sets found in a key frame.
+pub fn create_decoder_configuration(data: &[u8]) -> Option<Vec<u8>> {
+    let units = split_nal_units(data);
+    let sps = units
+        .iter()
+        .find(|unit| unit[0] & 0x1F == NAL_UNIT_TYPE_SPS)?;
+    let pps = units
+        .iter()
+        .find(|unit| unit[0] & 0x1F == NAL_UNIT_TYPE_PPS)?;
+    if sps.len() < 4 {
+        return None;
+    }
+
+    let mut result = vec![
+        1,      // configurationVersion
+        sps[1], // AVCProfileIndication
+        sps[2], // profile_compatibility
+        sps[3], // AVCLevelIndication
+        0xFC | (NAL_LENGTH_SIZE as u8 - 1),
+        0xE0 | 1, // numOfSequenceParameterSets
+    ];
+    result.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+    result.extend_from_slice(sps);
+    result.push(1); // numOfPictureParameterSets
+    result.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+    result.extend_from_slice(pps);
+    Some(result)
+}
+
+fn trim_trailing_zeros(unit: &[u8]) -> &[u8] {
+    // Four byte start codes leave a zero at the end of the previous unit
+    let length = unit
+        .iter()
+        .rposition(|byte| *byte != 0)
+        .map_or(0, |i| i + 1);
+    &unit[..length]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{create_decoder_configuration, split_nal_units, to_length_prefixed};
+
+    const SAMPLE: [u8; 19] = [
+        0, 0, 0, 1, 0x67, 0x64, 0x00, 0x28, // SPS
+        0, 0, 0, 1, 0x68, 0xEE, // PPS
+        0, 0, 1, 0x65, 0x88, // IDR slice
+    ];
+
+    #[test]
+    fn split_test() {
+        let units = split_nal_units(&SAMPLE);
+        assert_eq!(
+            units,
+            vec![
+                &[0x67, 0x64, 0x00, 0x28][..],
+                &[0x68, 0xEE][..],
+                &[0x65, 0x88][..]
+            ]
+        );
+        assert!(split_nal_units(&[0x65, 0x88]).is_empty());
+    }
+
+    #[test]
+    fn length_prefixed_test() {
+        assert_eq!(
+            to_length_prefixed(&SAMPLE[14..]),
+            vec![0, 0, 0, 2, 0x65, 0x88]
+        );
+    }
+
+    #[test]
+    fn decoder_configuration_test() {
+        assert_eq!(
+            create_decoder_configuration(&SAMPLE).unwrap(),
+            vec![
+                1, 0x64, 0x00, 0x28, 0xFF, 0xE1, 0, 4, 0x67, 0x64, 0x00, 0x28, 1, 0, 2, 0x68, 0xEE
+            ]
+        );
+        assert_eq!(create_decoder_configuration(&SAMPLE[14..]), None);
+    }
+}
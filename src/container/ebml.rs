@@ -0,0 +1,115 @@
+// Helpers for writing EBML, the binary format Matroska is built on. Each
+// element is an ID, followed by the size of its data, followed by the data.
+
+// Reserving 8 bytes for a size lets us patch it once the real size is known
+pub const RESERVED_SIZE_LENGTH: usize = 8;
+
+pub fn write_id(buffer: &mut Vec<u8>, id: u32) {
+    // IDs already include their length marker
+    let bytes = id.to_be_bytes();
+    let leading_zeros = (id.leading_zeros() / 8) as usize;
+    buffer.extend_from_slice(&bytes[leading_zeros.min(3)..]);
+}
+
+pub fn write_size(buffer: &mut Vec<u8>, size: u64) {
+    let length = get_size_length(size);
+    write_size_with_length(buffer, size, length);
+}
+
+pub fn write_size_with_length(buffer: &mut Vec<u8>, size: u64, length: usize) {
+    assert!((1..=8).contains(&length));
+    let marked = size | (1 << (7 * length));
+    buffer.extend_from_slice(&marked.to_be_bytes()[8 - length..]);
+}
+
+pub fn write_uint(buffer: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let leading_zeros = ((value.leading_zeros() / 8) as usize).min(7);
+    write_binary(buffer, id, &bytes[leading_zeros..]);
+}
+
+pub fn write_float(buffer: &mut Vec<u8>, id: u32, value: f64) {
+    write_binary(buffer, id, &value.to_be_bytes());
+}
+
+pub fn write_string(buffer: &mut Vec<u8>, id: u32, value: &str) {
+    write_binary(buffer, id, value.as_bytes());
+}
+
+pub fn write_binary(buffer: &mut Vec<u8>, id: u32, data: &[u8]) {
+    write_id(buffer, id);
+    write_size(buffer, data.len() as u64);
+    buffer.extend_from_slice(data);
+}
+
+/// Writes an element whose data is built by the provided closure.
+pub fn write_master<F: FnOnce(&mut Vec<u8>)>(buffer: &mut Vec<u8>, id: u32, f: F) {
+    let mut data = Vec::new();
+    f(&mut data);
+    write_binary(buffer, id, &data);
+}
+
+fn get_size_length(size: u64) -> usize {
+    // A size with all of its bits set is reserved to mean "unknown"
+    let mut length = 1;
+    while size >= (1 << (7 * length)) - 1 {
+        length += 1;
+    }
+    length
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_float, write_id, write_size, write_size_with_length, write_uint};
+
+    #[test]
+    fn id_test() {
+        let mut buffer = Vec::new();
+        write_id(&mut buffer, 0x1A45DFA3);
+        write_id(&mut buffer, 0x4286);
+        write_id(&mut buffer, 0xA3);
+        assert_eq!(buffer, [0x1A, 0x45, 0xDF, 0xA3, 0x42, 0x86, 0xA3]);
+    }
+
+    #[test]
+    fn size_test() {
+        let mut buffer = Vec::new();
+        write_size(&mut buffer, 0);
+        assert_eq!(buffer, [0x80]);
+
+        buffer.clear();
+        write_size(&mut buffer, 126);
+        assert_eq!(buffer, [0xFE]);
+
+        // 127 by itself would mean "unknown"
+        buffer.clear();
+        write_size(&mut buffer, 127);
+        assert_eq!(buffer, [0x40, 0x7F]);
+
+        buffer.clear();
+        write_size_with_length(&mut buffer, 5, 8);
+        assert_eq!(buffer, [0x01, 0, 0, 0, 0, 0, 0, 0x05]);
+    }
+
+    #[test]
+    fn uint_test() {
+        let mut buffer = Vec::new();
+        write_uint(&mut buffer, 0xD7, 1);
+        assert_eq!(buffer, [0xD7, 0x81, 0x01]);
+
+        buffer.clear();
+        write_uint(&mut buffer, 0x2AD7B1, 1_000_000);
+        assert_eq!(buffer, [0x2A, 0xD7, 0xB1, 0x83, 0x0F, 0x42, 0x40]);
+
+        buffer.clear();
+        write_uint(&mut buffer, 0xE7, 0);
+        assert_eq!(buffer, [0xE7, 0x81, 0x00]);
+    }
+
+    #[test]
+    fn float_test() {
+        let mut buffer = Vec::new();
+        write_float(&mut buffer, 0x4489, 1.0);
+        assert_eq!(buffer, [0x44, 0x89, 0x88, 0x3F, 0xF0, 0, 0, 0, 0, 0, 0]);
+    }
+}
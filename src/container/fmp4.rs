@@ -0,0 +1,405 @@
+// A minimal fragmented MP4 muxer (see ISO/IEC 14496-12), which writes an
+// initialization segment describing the tracks followed by any number of
+// media segments, as used by HLS. Video is H.264, audio is AAC-LC.
+
+pub const VIDEO_TIMESCALE: u32 = 90_000;
+const MOVIE_TIMESCALE: u32 = 1000;
+const MATRIX: [u32; 9] = [0x00010000, 0, 0, 0, 0x00010000, 0, 0, 0, 0x40000000];
+// See ISO/IEC 14496-12 8.8.3.1
+const SAMPLE_FLAGS_SYNC: u32 = 0x02000000;
+const SAMPLE_FLAGS_NON_SYNC: u32 = 0x01010000;
+pub const TFHD_DEFAULT_BASE_IS_MOOF: u32 = 0x020000;
+pub const TRUN_DATA_OFFSET: u32 = 0x1;
+pub const TRUN_SAMPLE_DURATION: u32 = 0x100;
+pub const TRUN_SAMPLE_SIZE: u32 = 0x200;
+pub const TRUN_SAMPLE_FLAGS: u32 = 0x400;
+pub const TRUN_SAMPLE_COMPOSITION_TIME_OFFSET: u32 = 0x800;
+
+pub enum TrackInfo {
+    Video {
+        width: u32,
+        height: u32,
+        /// The AVCDecoderConfigurationRecord, see create_decoder_configuration.
+        decoder_configuration: Vec<u8>,
+    },
+    Audio {
+        sample_rate: u32,
+        channels: u32,
+        audio_specific_config: [u8; 2],
+    },
+}
+
+/// A sample of a fragment, its times are in the timescale of its track.
+pub struct Fmp4Sample {
+    pub duration: u32,
+    pub composition_offset: i32,
+    pub key_frame: bool,
+    pub data: Vec<u8>,
+}
+
+/// The samples of one track in a media segment.
+pub struct Fragment<'a> {
+    /// The index of the track in the initialization segment.
+    pub track_index: usize,
+    pub base_decode_time: u64,
+    pub samples: &'a [Fmp4Sample],
+}
+
+impl TrackInfo {
+    /// The units that the times of the track's samples are in.
+    pub fn timescale(&self) -> u32 {
+        match self {
+            TrackInfo::Video { .. } => VIDEO_TIMESCALE,
+            TrackInfo::Audio { sample_rate, .. } => *sample_rate,
+        }
+    }
+}
+
+pub fn create_init_segment(tracks: &[TrackInfo]) -> Vec<u8> {
+    let mut output = Vec::new();
+    write_box(&mut output, b"ftyp", |output| {
+        output.extend_from_slice(b"iso6");
+        output.extend_from_slice(&0u32.to_be_bytes());
+        for brand in [b"iso6", b"mp41", b"avc1"] {
+            output.extend_from_slice(brand);
+        }
+    });
+    write_box(&mut output, b"moov", |output| {
+        write_full_box(output, b"mvhd", 0, 0, |output| {
+            // Creation and modification times, then the timescale and duration
+            write_u32s(output, &[0, 0, MOVIE_TIMESCALE, 0]);
+            output.extend_from_slice(&0x00010000u32.to_be_bytes());
+            output.extend_from_slice(&0x0100u16.to_be_bytes());
+            output.extend_from_slice(&[0; 10]);
+            write_u32s(output, &MATRIX);
+            output.extend_from_slice(&[0; 24]);
+            output.extend_from_slice(&(tracks.len() as u32 + 1).to_be_bytes());
+        });
+        for (index, track) in tracks.iter().enumerate() {
+            write_track(output, index as u32 + 1, track);
+        }
+        write_box(output, b"mvex", |output| {
+            for index in 0..tracks.len() as u32 {
+                write_full_box(output, b"trex", 0, 0, |output| {
+                    // The sample description, duration, size, and flags defaults
+                    write_u32s(output, &[index + 1, 1, 0, 0, 0]);
+                });
+            }
+        });
+    });
+    output
+}
+
+pub fn create_media_segment(sequence_number: u32, fragments: &[Fragment]) -> Vec<u8> {
+    let mut output = Vec::new();
+    // The data offsets point into the mdat, they're filled in once we know
+    // how big the moof is
+    let mut data_offset_positions = Vec::new();
+    write_box(&mut output, b"moof", |output| {
+        write_full_box(output, b"mfhd", 0, 0, |output| {
+            output.extend_from_slice(&sequence_number.to_be_bytes());
+        });
+        for fragment in fragments {
+            write_box(output, b"traf", |output| {
+                write_full_box(output, b"tfhd", 0, TFHD_DEFAULT_BASE_IS_MOOF, |output| {
+                    output.extend_from_slice(&(fragment.track_index as u32 + 1).to_be_bytes());
+                });
+                write_full_box(output, b"tfdt", 1, 0, |output| {
+                    output.extend_from_slice(&fragment.base_decode_time.to_be_bytes());
+                });
+                // Version 1 has signed composition offsets
+                let flags = TRUN_DATA_OFFSET
+                    | TRUN_SAMPLE_DURATION
+                    | TRUN_SAMPLE_SIZE
+                    | TRUN_SAMPLE_FLAGS
+                    | TRUN_SAMPLE_COMPOSITION_TIME_OFFSET;
+                write_full_box(output, b"trun", 1, flags, |output| {
+                    output.extend_from_slice(&(fragment.samples.len() as u32).to_be_bytes());
+                    data_offset_positions.push(output.len());
+                    output.extend_from_slice(&0u32.to_be_bytes());
+                    for sample in fragment.samples {
+                        let sample_flags = if sample.key_frame {
+                            SAMPLE_FLAGS_SYNC
+                        } else {
+                            SAMPLE_FLAGS_NON_SYNC
+                        };
+                        write_u32s(
+                            output,
+                            &[sample.duration, sample.data.len() as u32, sample_flags],
+                        );
+                        output.extend_from_slice(&sample.composition_offset.to_be_bytes());
+                    }
+                });
+            });
+        }
+    });
+
+    // The mdat header comes right after the moof
+    let mut data_offset = output.len() + 8;
+    for (position, fragment) in data_offset_positions.into_iter().zip(fragments) {
+        output[position..position + 4].copy_from_slice(&(data_offset as u32).to_be_bytes());
+        data_offset += fragment
+            .samples
+            .iter()
+            .map(|sample| sample.data.len())
+            .sum::<usize>();
+    }
+    write_box(&mut output, b"mdat", |output| {
+        for fragment in fragments {
+            for sample in fragment.samples {
+                output.extend_from_slice(&sample.data);
+            }
+        }
+    });
+    output
+}
+
+fn write_track(output: &mut Vec<u8>, track_id: u32, track: &TrackInfo) {
+    let (handler, width, height) = match track {
+        TrackInfo::Video { width, height, .. } => (b"vide", *width, *height),
+        TrackInfo::Audio { .. } => (b"soun", 0, 0),
+    };
+    write_box(output, b"trak", |output| {
+        // Enabled and in the movie
+        write_full_box(output, b"tkhd", 0, 0x3, |output| {
+            write_u32s(output, &[0, 0, track_id, 0, 0]);
+            output.extend_from_slice(&[0; 8]);
+            // Layer and alternate group
+            output.extend_from_slice(&[0; 4]);
+            let volume: u16 = if handler == b"soun" { 0x0100 } else { 0 };
+            output.extend_from_slice(&volume.to_be_bytes());
+            output.extend_from_slice(&[0; 2]);
+            write_u32s(output, &MATRIX);
+            write_u32s(output, &[width << 16, height << 16]);
+        });
+        write_box(output, b"mdia", |output| {
+            write_full_box(output, b"mdhd", 0, 0, |output| {
+                write_u32s(output, &[0, 0, track.timescale(), 0]);
+                // The packed language code for "und"
+                output.extend_from_slice(&0x55C4u16.to_be_bytes());
+                output.extend_from_slice(&[0; 2]);
+            });
+            write_full_box(output, b"hdlr", 0, 0, |output| {
+                output.extend_from_slice(&[0; 4]);
+                output.extend_from_slice(handler);
+                output.extend_from_slice(&[0; 12]);
+                output.extend_from_slice(b"displayrecorder\0");
+            });
+            write_box(output, b"minf", |output| {
+                match track {
+                    TrackInfo::Video { .. } => write_full_box(output, b"vmhd", 0, 1, |output| {
+                        output.extend_from_slice(&[0; 8]);
+                    }),
+                    TrackInfo::Audio { .. } => write_full_box(output, b"smhd", 0, 0, |output| {
+                        output.extend_from_slice(&[0; 4]);
+                    }),
+                }
+                write_box(output, b"dinf", |output| {
+                    write_full_box(output, b"dref", 0, 0, |output| {
+                        output.extend_from_slice(&1u32.to_be_bytes());
+                        // The data is in the same file
+                        write_full_box(output, b"url ", 0, 1, |_| {});
+                    });
+                });
+                write_box(output, b"stbl", |output| {
+                    write_full_box(output, b"stsd", 0, 0, |output| {
+                        output.extend_from_slice(&1u32.to_be_bytes());
+                        write_sample_entry(output, track);
+                    });
+                    // The samples are all in the fragments
+                    write_full_box(output, b"stts", 0, 0, |output| write_u32s(output, &[0]));
+                    write_full_box(output, b"stsc", 0, 0, |output| write_u32s(output, &[0]));
+                    write_full_box(output, b"stsz", 0, 0, |output| write_u32s(output, &[0, 0]));
+                    write_full_box(output, b"stco", 0, 0, |output| write_u32s(output, &[0]));
+                });
+            });
+        });
+    });
+}
+
+fn write_sample_entry(output: &mut Vec<u8>, track: &TrackInfo) {
+    match track {
+        TrackInfo::Video {
+            width,
+            height,
+            decoder_configuration,
+        } => write_box(output, b"avc1", |output| {
+            // Reserved, then the data reference index
+            output.extend_from_slice(&[0; 6]);
+            output.extend_from_slice(&1u16.to_be_bytes());
+            output.extend_from_slice(&[0; 16]);
+            output.extend_from_slice(&(*width as u16).to_be_bytes());
+            output.extend_from_slice(&(*height as u16).to_be_bytes());
+            // 72 dpi
+            write_u32s(output, &[0x00480000, 0x00480000, 0]);
+            output.extend_from_slice(&1u16.to_be_bytes());
+            output.extend_from_slice(&[0; 32]);
+            output.extend_from_slice(&0x0018u16.to_be_bytes());
+            output.extend_from_slice(&(-1i16).to_be_bytes());
+            write_box(output, b"avcC", |output| {
+                output.extend_from_slice(decoder_configuration);
+            });
+        }),
+        TrackInfo::Audio {
+            sample_rate,
+            channels,
+            audio_specific_config,
+        } => write_box(output, b"mp4a", |output| {
+            output.extend_from_slice(&[0; 6]);
+            output.extend_from_slice(&1u16.to_be_bytes());
+            output.extend_from_slice(&[0; 8]);
+            output.extend_from_slice(&(*channels as u16).to_be_bytes());
+            // 16 bits per sample
+            output.extend_from_slice(&16u16.to_be_bytes());
+            output.extend_from_slice(&[0; 4]);
+            output.extend_from_slice(&(sample_rate << 16).to_be_bytes());
+            write_full_box(output, b"esds", 0, 0, |output| {
+                write_esds(output, audio_specific_config);
+            });
+        }),
+    }
+}
+
+// See ISO/IEC 14496-1 7.2.6, every descriptor here is small enough for a
+// single byte length
+fn write_esds(output: &mut Vec<u8>, audio_specific_config: &[u8]) {
+    let decoder_specific_info = [
+        &[0x05, audio_specific_config.len() as u8],
+        audio_specific_config,
+    ]
+    .concat();
+    let mut decoder_config = vec![
+        0x04,
+        (13 + decoder_specific_info.len()) as u8,
+        // MPEG-4 audio
+        0x40,
+        // An audio stream
+        0x15,
+    ];
+    // The buffer size, then the maximum and average bitrates (unknown)
+    decoder_config.extend_from_slice(&[0; 3]);
+    decoder_config.extend_from_slice(&[0; 8]);
+    decoder_config.extend_from_slice(&decoder_specific_info);
+    let sl_config = [0x06, 0x01, 0x02];
+
+    output.push(0x03);
+    output.push((3 + decoder_config.len() + sl_config.len()) as u8);
+    // The ES_ID and flags
+    output.extend_from_slice(&[0, 0, 0]);
+    output.extend_from_slice(&decoder_config);
+    output.extend_from_slice(&sl_config);
+}
+
+pub fn write_box(
+    output: &mut Vec<u8>,
+    box_type: &[u8; 4],
+    write_content: impl FnOnce(&mut Vec<u8>),
+) {
+    let start = output.len();
+    output.extend_from_slice(&[0; 4]);
+    output.extend_from_slice(box_type);
+    write_content(output);
+    let size = (output.len() - start) as u32;
+    output[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+pub fn write_full_box(
+    output: &mut Vec<u8>,
+    box_type: &[u8; 4],
+    version: u8,
+    flags: u32,
+    write_content: impl FnOnce(&mut Vec<u8>),
+) {
+    write_box(output, box_type, |output| {
+        output.extend_from_slice(&(((version as u32) << 24) | flags).to_be_bytes());
+        write_content(output);
+    });
+}
+
+pub fn write_u32s(output: &mut Vec<u8>, values: &[u32]) {
+    for value in values {
+        output.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{create_init_segment, create_media_segment, Fmp4Sample, Fragment, TrackInfo};
+
+    // Returns the type and the content of each top level box
+    fn read_boxes(data: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut boxes = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            let size = u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+            boxes.push((
+                &data[offset + 4..offset + 8],
+                &data[offset + 8..offset + size],
+            ));
+            offset += size;
+        }
+        boxes
+    }
+
+    #[test]
+    fn init_segment_test() {
+        let segment = create_init_segment(&[
+            TrackInfo::Video {
+                width: 1920,
+                height: 1080,
+                decoder_configuration: vec![1, 0x64, 0, 0x28],
+            },
+            TrackInfo::Audio {
+                sample_rate: 48000,
+                channels: 2,
+                audio_specific_config: [0x11, 0x90],
+            },
+        ]);
+        let boxes = read_boxes(&segment);
+        assert_eq!(boxes.len(), 2);
+        assert_eq!(boxes[0].0, b"ftyp");
+        assert_eq!(boxes[1].0, b"moov");
+        let moov: Vec<_> = read_boxes(boxes[1].1)
+            .iter()
+            .map(|(t, _)| t.to_vec())
+            .collect();
+        assert_eq!(moov, [b"mvhd", b"trak", b"trak", b"mvex"]);
+    }
+
+    #[test]
+    fn media_segment_test() {
+        let samples = [
+            Fmp4Sample {
+                duration: 1500,
+                composition_offset: 0,
+                key_frame: true,
+                data: vec![1, 2, 3],
+            },
+            Fmp4Sample {
+                duration: 1500,
+                composition_offset: -1500,
+                key_frame: false,
+                data: vec![4, 5],
+            },
+        ];
+        let segment = create_media_segment(
+            7,
+            &[Fragment {
+                track_index: 0,
+                base_decode_time: 90_000,
+                samples: &samples,
+            }],
+        );
+        let boxes = read_boxes(&segment);
+        assert_eq!(boxes[0].0, b"moof");
+        assert_eq!(boxes[1].0, b"mdat");
+        assert_eq!(boxes[1].1, [1, 2, 3, 4, 5]);
+
+        // The data offset points at the first byte of the mdat's content
+        let moof = boxes[0].1;
+        let traf = read_boxes(moof)[1].1;
+        let trun = read_boxes(traf)[2].1;
+        let data_offset = u32::from_be_bytes(trun[8..12].try_into().unwrap()) as usize;
+        assert_eq!(segment[data_offset], 1);
+    }
+}
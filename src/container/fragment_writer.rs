@@ -0,0 +1,456 @@
+use std::{io::Write, time::Duration};
+
+use windows::{
+    core::{Error, Result},
+    Win32::Media::MediaFoundation::{
+        IMFMediaType, IMFSample, MFAudioFormat_PCM, MFMediaType_Audio, MFMediaType_Video,
+        MFSampleExtension_DecodeTimestamp, MFVideoFormat_H264, MF_E_INVALIDMEDIATYPE,
+        MF_MT_AUDIO_NUM_CHANNELS, MF_MT_AUDIO_SAMPLES_PER_SECOND, MF_MT_FRAME_SIZE,
+        MF_MT_MAJOR_TYPE, MF_MT_SUBTYPE,
+    },
+};
+
+use crate::{
+    audio::aac_encoder::AacEncoder,
+    media::{get_sample_data, is_key_frame},
+};
+
+use super::{
+    avc::{create_decoder_configuration, to_length_prefixed},
+    fmp4::{
+        create_init_segment, create_media_segment, Fmp4Sample, Fragment, TrackInfo, VIDEO_TIMESCALE,
+    },
+    to_error, ContainerWriter,
+};
+
+const HUNDRED_NANOSECONDS_PER_SECOND: i64 = 10_000_000;
+const NANOSECONDS_PER_HUNDRED_NANOSECONDS: i64 = 100;
+const AAC_SAMPLES_PER_FRAME: i64 = 1024;
+
+/// Where the segments written by a FragmentWriter go.
+pub trait SegmentOutput: Send {
+    /// Called once, before any of the media segments.
+    fn write_init_segment(&mut self, data: &[u8]) -> Result<()>;
+    fn write_media_segment(&mut self, data: &[u8], duration: Duration) -> Result<()>;
+    fn finish(&mut self) -> Result<()>;
+}
+
+/// Writes H.264 video and AAC audio as fragmented MP4, an initialization
+/// segment followed by media segments that can be played as they arrive.
+/// With a segment duration, segments start at the first key frame after
+/// it has passed, or at an earlier one if waiting for the next key frame
+/// would have them round to longer than it (in whole seconds), which HLS
+/// playlists don't allow. Otherwise every video frame gets its own fragment, which
+/// keeps the latency down when piping into another program.
+pub struct FragmentWriter {
+    output: Box<dyn SegmentOutput>,
+    segment_duration: Option<Duration>,
+    tracks: Vec<Track>,
+    // The samples of the segment that's being collected, by track
+    samples: Vec<Vec<PendingSample>>,
+    // Nothing is written until the first key frame, which starts the first segment
+    segment_start: Option<i64>,
+    segment_count: u32,
+    // When the last key frame was, and how long before it the one before was
+    last_key_frame: Option<i64>,
+    key_frame_interval: Option<i64>,
+    // B-frames can make the first decode times negative, which fragments can't store
+    time_offset: i64,
+}
+
+/// Writes all of the segments to a single stream, e.g. stdout.
+pub struct StreamOutput<W: Write + Send> {
+    stream: W,
+}
+
+enum Track {
+    Video {
+        width: u32,
+        height: u32,
+    },
+    Audio {
+        encoder: AacEncoder,
+        sample_rate: u32,
+        channels: u32,
+    },
+}
+
+struct PendingSample {
+    time: i64,
+    decode_time: i64,
+    duration: i64,
+    key_frame: bool,
+    data: Vec<u8>,
+}
+
+unsafe impl Send for FragmentWriter {}
+impl FragmentWriter {
+    pub fn new(output: Box<dyn SegmentOutput>, segment_duration: Option<Duration>) -> Self {
+        Self {
+            output,
+            segment_duration,
+            tracks: Vec::new(),
+            samples: Vec::new(),
+            segment_start: None,
+            segment_count: 0,
+            last_key_frame: None,
+            key_frame_interval: None,
+            time_offset: 0,
+        }
+    }
+
+    fn create_init_segment(&self, decoder_configuration: Vec<u8>) -> Vec<u8> {
+        let mut decoder_configuration = Some(decoder_configuration);
+        let tracks: Vec<_> = self
+            .tracks
+            .iter()
+            .map(|track| match track {
+                Track::Video { width, height } => TrackInfo::Video {
+                    width: *width,
+                    height: *height,
+                    decoder_configuration: decoder_configuration.take().unwrap_or_default(),
+                },
+                Track::Audio {
+                    encoder,
+                    sample_rate,
+                    channels,
+                } => TrackInfo::Audio {
+                    sample_rate: *sample_rate,
+                    channels: *channels,
+                    audio_specific_config: encoder.audio_specific_config(),
+                },
+            })
+            .collect();
+        create_init_segment(&tracks)
+    }
+
+    /// Writes the samples before the end time as a segment.
+    fn write_segment(&mut self, end_time: i64) -> Result<()> {
+        let segment_start = self.segment_start.unwrap();
+        let mut fragments = Vec::new();
+        for (track_index, samples) in self.samples.iter_mut().enumerate() {
+            let count = samples
+                .iter()
+                .take_while(|sample| sample.decode_time < end_time)
+                .count();
+            let samples: Vec<_> = samples.drain(..count).collect();
+            if samples.is_empty() {
+                continue;
+            }
+            let timescale = match &self.tracks[track_index] {
+                Track::Video { .. } => VIDEO_TIMESCALE,
+                Track::Audio { sample_rate, .. } => *sample_rate,
+            };
+            let (base_decode_time, samples) =
+                to_fragment_samples(&samples, timescale, self.time_offset);
+            fragments.push((track_index, base_decode_time, samples));
+        }
+        let fragments: Vec<_> = fragments
+            .iter()
+            .map(|(track_index, base_decode_time, samples)| Fragment {
+                track_index: *track_index,
+                base_decode_time: *base_decode_time,
+                samples,
+            })
+            .collect();
+
+        self.segment_count += 1;
+        let segment = create_media_segment(self.segment_count, &fragments);
+        let duration = (end_time - segment_start).max(0) * NANOSECONDS_PER_HUNDRED_NANOSECONDS;
+        self.segment_start = Some(end_time);
+        self.output
+            .write_media_segment(&segment, Duration::from_nanos(duration as u64))
+    }
+
+    fn write_video(&mut self, stream_index: u32, sample: &IMFSample) -> Result<()> {
+        let key_frame = is_key_frame(sample);
+        let (time, decode_time, duration) = unsafe {
+            let time = sample.GetSampleTime()?;
+            let decode_time = sample
+                .GetUINT64(&MFSampleExtension_DecodeTimestamp)
+                .map(|time| time as i64)
+                .unwrap_or(time);
+            (time, decode_time, sample.GetSampleDuration()?)
+        };
+        let data = get_sample_data(sample)?;
+        let key_frame_interval = self.key_frame_interval;
+        if key_frame {
+            if let Some(last_key_frame) = self.last_key_frame {
+                self.key_frame_interval = Some(decode_time - last_key_frame);
+            }
+            self.last_key_frame = Some(decode_time);
+        }
+
+        match self.segment_start {
+            None => {
+                // The initialization segment needs the parameter sets from a key frame
+                let decoder_configuration = if key_frame {
+                    create_decoder_configuration(&data)
+                } else {
+                    None
+                };
+                let decoder_configuration = match decoder_configuration {
+                    Some(decoder_configuration) => decoder_configuration,
+                    None => return Ok(()),
+                };
+                self.time_offset = (-decode_time).max(0);
+                let init_segment = self.create_init_segment(decoder_configuration);
+                self.output.write_init_segment(&init_segment)?;
+                self.segment_start = Some(decode_time);
+            }
+            Some(segment_start) => {
+                let cut = match self.segment_duration {
+                    Some(segment_duration) => {
+                        key_frame
+                            && should_cut_segment(
+                                decode_time - segment_start,
+                                key_frame_interval,
+                                segment_duration,
+                            )
+                    }
+                    None => true,
+                };
+                if cut {
+                    self.write_segment(decode_time)?;
+                }
+            }
+        }
+
+        self.samples[stream_index as usize].push(PendingSample {
+            time,
+            decode_time,
+            duration,
+            key_frame,
+            data: to_length_prefixed(&data),
+        });
+        Ok(())
+    }
+
+    fn write_audio(&mut self, stream_index: u32, sample: Option<&IMFSample>) -> Result<()> {
+        let (frames, sample_rate) = if let Track::Audio {
+            encoder,
+            sample_rate,
+            ..
+        } = &mut self.tracks[stream_index as usize]
+        {
+            let frames = if let Some(sample) = sample {
+                encoder.encode(sample)?
+            } else {
+                encoder.flush()?
+            };
+            (frames, *sample_rate as i64)
+        } else {
+            return Ok(());
+        };
+        if self.segment_start.is_none() {
+            return Ok(());
+        }
+
+        let duration = AAC_SAMPLES_PER_FRAME * HUNDRED_NANOSECONDS_PER_SECOND / sample_rate;
+        self.samples[stream_index as usize].extend(frames.into_iter().map(|frame| PendingSample {
+            time: frame.time,
+            decode_time: frame.time,
+            duration,
+            key_frame: true,
+            data: frame.data,
+        }));
+        Ok(())
+    }
+}
+
+impl ContainerWriter for FragmentWriter {
+    fn add_stream(&mut self, output_type: &IMFMediaType, input_type: &IMFMediaType) -> Result<u32> {
+        let track = unsafe {
+            let major_type = input_type.GetGUID(&MF_MT_MAJOR_TYPE)?;
+            let subtype = input_type.GetGUID(&MF_MT_SUBTYPE)?;
+            let has_audio = self
+                .tracks
+                .iter()
+                .any(|track| matches!(track, Track::Audio { .. }));
+            if major_type == MFMediaType_Video && subtype == MFVideoFormat_H264 {
+                let frame_size = input_type.GetUINT64(&MF_MT_FRAME_SIZE)?;
+                Track::Video {
+                    width: (frame_size >> 32) as u32,
+                    height: frame_size as u32,
+                }
+            } else if major_type == MFMediaType_Audio && subtype == MFAudioFormat_PCM && !has_audio
+            {
+                // Players only pick up a single audio track from the segments
+                Track::Audio {
+                    encoder: AacEncoder::new(output_type, input_type)?,
+                    sample_rate: output_type.GetUINT32(&MF_MT_AUDIO_SAMPLES_PER_SECOND)?,
+                    channels: output_type.GetUINT32(&MF_MT_AUDIO_NUM_CHANNELS)?,
+                }
+            } else {
+                return Err(Error::from(MF_E_INVALIDMEDIATYPE));
+            }
+        };
+        self.tracks.push(track);
+        self.samples.push(Vec::new());
+        Ok(self.tracks.len() as u32 - 1)
+    }
+
+    fn start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_sample(&mut self, stream_index: u32, sample: &IMFSample) -> Result<()> {
+        match self.tracks[stream_index as usize] {
+            Track::Video { .. } => self.write_video(stream_index, sample),
+            Track::Audio { .. } => self.write_audio(stream_index, Some(sample)),
+        }
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        for stream_index in 0..self.tracks.len() as u32 {
+            self.write_audio(stream_index, None)?;
+        }
+        if self.segment_start.is_none() {
+            return Ok(());
+        }
+        // The last segment ends with the last video frame
+        let end_time = self
+            .tracks
+            .iter()
+            .zip(&self.samples)
+            .filter(|(track, _)| matches!(track, Track::Video { .. }))
+            .filter_map(|(_, samples)| samples.last())
+            .map(|sample| sample.decode_time + sample.duration)
+            .max();
+        if let Some(end_time) = end_time {
+            self.write_segment(end_time)?;
+        }
+        self.output.finish()
+    }
+}
+
+// The durations come from the differences between the decode times, so
+// that rounding to the timescale doesn't add up over time
+fn to_fragment_samples(
+    samples: &[PendingSample],
+    timescale: u32,
+    time_offset: i64,
+) -> (u64, Vec<Fmp4Sample>) {
+    let to_timescale =
+        |time: i64| (time + time_offset).max(0) * timescale as i64 / HUNDRED_NANOSECONDS_PER_SECOND;
+    let fragment_samples = samples
+        .iter()
+        .enumerate()
+        .map(|(index, sample)| {
+            let next_decode_time = samples
+                .get(index + 1)
+                .map(|next| next.decode_time)
+                .unwrap_or(sample.decode_time + sample.duration);
+            Fmp4Sample {
+                duration: (to_timescale(next_decode_time) - to_timescale(sample.decode_time))
+                    as u32,
+                composition_offset: (to_timescale(sample.time) - to_timescale(sample.decode_time))
+                    as i32,
+                key_frame: sample.key_frame,
+                data: sample.data.clone(),
+            }
+        })
+        .collect();
+    (
+        to_timescale(samples[0].decode_time) as u64,
+        fragment_samples,
+    )
+}
+
+impl<W: Write + Send> StreamOutput<W> {
+    pub fn new(stream: W) -> Self {
+        Self { stream }
+    }
+}
+
+impl<W: Write + Send> SegmentOutput for StreamOutput<W> {
+    fn write_init_segment(&mut self, data: &[u8]) -> Result<()> {
+        self.stream.write_all(data).map_err(to_error)
+    }
+
+    fn write_media_segment(&mut self, data: &[u8], _duration: Duration) -> Result<()> {
+        // Readers should get each fragment as soon as it's complete
+        self.stream.write_all(data).map_err(to_error)?;
+        self.stream.flush().map_err(to_error)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.stream.flush().map_err(to_error)
+    }
+}
+
+// Whether a segment should end at a key frame that's the duration (in 100ns
+// units) into it. The interval is between the last two key frames, and is
+// how long the next one is expected to take.
+fn should_cut_segment(
+    duration: i64,
+    key_frame_interval: Option<i64>,
+    segment_duration: Duration,
+) -> bool {
+    let segment_duration = segment_duration.as_nanos() as i64 / NANOSECONDS_PER_HUNDRED_NANOSECONDS;
+    // Anything shorter than half a second past the whole seconds rounds down
+    let max_duration = (segment_duration + HUNDRED_NANOSECONDS_PER_SECOND - 1)
+        / HUNDRED_NANOSECONDS_PER_SECOND
+        * HUNDRED_NANOSECONDS_PER_SECOND
+        + HUNDRED_NANOSECONDS_PER_SECOND / 2;
+    duration >= segment_duration
+        || key_frame_interval.is_some_and(|interval| duration + interval >= max_duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{should_cut_segment, to_fragment_samples, PendingSample};
+
+    #[test]
+    fn cut_segment_test() {
+        let segment_duration = Duration::from_secs(4);
+        // Key frames that come on time end the segment at its duration
+        assert!(!should_cut_segment(
+            20_000_000,
+            Some(20_000_000),
+            segment_duration
+        ));
+        assert!(should_cut_segment(
+            40_000_000,
+            Some(20_000_000),
+            segment_duration
+        ));
+        assert!(should_cut_segment(40_000_000, None, segment_duration));
+        // But if the next one would be too late, the segment ends early
+        assert!(should_cut_segment(
+            30_000_000,
+            Some(30_000_000),
+            segment_duration
+        ));
+        assert!(!should_cut_segment(
+            30_000_000,
+            Some(10_000_000),
+            segment_duration
+        ));
+        assert!(!should_cut_segment(30_000_000, None, segment_duration));
+    }
+
+    #[test]
+    fn fragment_samples_test() {
+        // 30 fps with a B-frame, starting before zero
+        let samples: Vec<_> = [(-333_333, -666_667), (666_667, -333_333), (0, 0)]
+            .into_iter()
+            .map(|(time, decode_time)| PendingSample {
+                time,
+                decode_time,
+                duration: 333_333,
+                key_frame: decode_time < -400_000,
+                data: Vec::new(),
+            })
+            .collect();
+        let (base_decode_time, samples) = to_fragment_samples(&samples, 90_000, 666_667);
+        assert_eq!(base_decode_time, 0);
+        let durations: Vec<_> = samples.iter().map(|sample| sample.duration).collect();
+        assert_eq!(durations, [3000, 3000, 3000]);
+        assert_eq!(samples[0].composition_offset, 3000);
+        assert_eq!(samples[1].composition_offset, 9000);
+        assert!(samples[0].key_frame && !samples[1].key_frame);
+    }
+}
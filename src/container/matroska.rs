@@ -0,0 +1,443 @@
+use windows::{
+    core::{Error, Result},
+    Win32::Media::MediaFoundation::{
+        IMFByteStream, IMFMediaType, IMFSample, MFAudioFormat_PCM, MFMediaType_Audio,
+        MFMediaType_Video, MFVideoFormat_H264, MFVideoFormat_VP90, MF_E_INVALIDMEDIATYPE,
+        MF_MT_AUDIO_BITS_PER_SAMPLE, MF_MT_AUDIO_NUM_CHANNELS, MF_MT_AUDIO_SAMPLES_PER_SECOND,
+        MF_MT_FRAME_SIZE, MF_MT_MAJOR_TYPE, MF_MT_SUBTYPE,
+    },
+};
+
+use super::{
+    avc::{create_decoder_configuration, to_length_prefixed},
+    ebml::{
+        write_binary, write_float, write_id, write_master, write_size, write_size_with_length,
+        write_string, write_uint, RESERVED_SIZE_LENGTH,
+    },
+    Container, ContainerWriter, Metadata,
+};
+use crate::media::{get_sample_data, is_key_frame};
+
+const EBML_ID: u32 = 0x1A45DFA3;
+const EBML_VERSION_ID: u32 = 0x4286;
+const EBML_READ_VERSION_ID: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH_ID: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH_ID: u32 = 0x42F3;
+const DOC_TYPE_ID: u32 = 0x4282;
+const DOC_TYPE_VERSION_ID: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION_ID: u32 = 0x4285;
+const SEGMENT_ID: u32 = 0x18538067;
+const INFO_ID: u32 = 0x1549A966;
+const TIMECODE_SCALE_ID: u32 = 0x2AD7B1;
+const MUXING_APP_ID: u32 = 0x4D80;
+const WRITING_APP_ID: u32 = 0x5741;
+const DURATION_ID: u32 = 0x4489;
+const TITLE_ID: u32 = 0x7BA9;
+const TAGS_ID: u32 = 0x1254C367;
+const TAG_ID: u32 = 0x7373;
+// Empty targets apply the tags to the whole recording
+const TARGETS_ID: u32 = 0x63C0;
+const SIMPLE_TAG_ID: u32 = 0x67C8;
+const TAG_NAME_ID: u32 = 0x45A3;
+const TAG_STRING_ID: u32 = 0x4487;
+const TRACKS_ID: u32 = 0x1654AE6B;
+const TRACK_ENTRY_ID: u32 = 0xAE;
+const TRACK_NUMBER_ID: u32 = 0xD7;
+const TRACK_UID_ID: u32 = 0x73C5;
+const TRACK_TYPE_ID: u32 = 0x83;
+const FLAG_LACING_ID: u32 = 0x9C;
+const CODEC_ID_ID: u32 = 0x86;
+const CODEC_PRIVATE_ID: u32 = 0x63A2;
+const VIDEO_ID: u32 = 0xE0;
+const PIXEL_WIDTH_ID: u32 = 0xB0;
+const PIXEL_HEIGHT_ID: u32 = 0xBA;
+const AUDIO_ID: u32 = 0xE1;
+const SAMPLING_FREQUENCY_ID: u32 = 0xB5;
+const CHANNELS_ID: u32 = 0x9F;
+const BIT_DEPTH_ID: u32 = 0x6264;
+const CLUSTER_ID: u32 = 0x1F43B675;
+const TIMECODE_ID: u32 = 0xE7;
+const SIMPLE_BLOCK_ID: u32 = 0xA3;
+
+const TRACK_TYPE_VIDEO: u64 = 1;
+const TRACK_TYPE_AUDIO: u64 = 2;
+
+// Timecodes are written in milliseconds
+const TIMECODE_SCALE: u64 = 1_000_000;
+const HUNDRED_NANOSECONDS_PER_TIMECODE: i64 = 10_000;
+// Block timecodes are relative to their cluster and must fit in an i16
+const MAX_CLUSTER_DURATION: i64 = 30_000;
+
+/// A minimal Matroska muxer. Media Foundation doesn't ship a Matroska sink,
+/// so samples are written as-is: H.264 or VP9 video and PCM audio. WebM is
+/// a subset of Matroska, which only allows VP9 video (and no PCM audio).
+pub struct MatroskaWriter {
+    byte_stream: IMFByteStream,
+    webm: bool,
+    metadata: Metadata,
+    tracks: Vec<Track>,
+
+    segment_position: u64,
+    duration_position: u64,
+    tracks_written: bool,
+    pending_blocks: Vec<Block>,
+    cluster: Option<Cluster>,
+    // The end of the latest sample written (in 100ns units)
+    end_time: i64,
+}
+
+enum Track {
+    Video {
+        format: VideoFormat,
+        width: u32,
+        height: u32,
+        codec_private: Option<Vec<u8>>,
+    },
+    Audio {
+        sample_rate: u32,
+        channels: u32,
+        bits_per_sample: u32,
+    },
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum VideoFormat {
+    H264,
+    Vp9,
+}
+
+struct Block {
+    stream_index: u32,
+    time: i64,
+    key_frame: bool,
+    data: Vec<u8>,
+}
+
+struct Cluster {
+    timecode: i64,
+    data: Vec<u8>,
+}
+
+unsafe impl Send for MatroskaWriter {}
+impl MatroskaWriter {
+    pub fn new(byte_stream: IMFByteStream, container: Container, metadata: &Metadata) -> Self {
+        Self {
+            byte_stream,
+            webm: container == Container::Webm,
+            metadata: metadata.clone(),
+            tracks: Vec::new(),
+
+            segment_position: 0,
+            duration_position: 0,
+            tracks_written: false,
+            pending_blocks: Vec::new(),
+            cluster: None,
+            end_time: 0,
+        }
+    }
+
+    fn write_bytes(&self, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            let written = unsafe { self.byte_stream.Write(data)? };
+            data = &data[written as usize..];
+        }
+        Ok(())
+    }
+
+    fn position(&self) -> Result<u64> {
+        unsafe { self.byte_stream.GetCurrentPosition() }
+    }
+
+    fn write_tracks(&mut self) -> Result<()> {
+        let mut buffer = Vec::new();
+        write_master(&mut buffer, TRACKS_ID, |buffer| {
+            for (index, track) in self.tracks.iter().enumerate() {
+                write_master(buffer, TRACK_ENTRY_ID, |buffer| {
+                    let track_number = index as u64 + 1;
+                    write_uint(buffer, TRACK_NUMBER_ID, track_number);
+                    write_uint(buffer, TRACK_UID_ID, track_number);
+                    write_uint(buffer, FLAG_LACING_ID, 0);
+                    match track {
+                        Track::Video {
+                            format,
+                            width,
+                            height,
+                            codec_private,
+                        } => {
+                            write_uint(buffer, TRACK_TYPE_ID, TRACK_TYPE_VIDEO);
+                            let codec_id = match format {
+                                VideoFormat::H264 => "V_MPEG4/ISO/AVC",
+                                VideoFormat::Vp9 => "V_VP9",
+                            };
+                            write_string(buffer, CODEC_ID_ID, codec_id);
+                            if let Some(codec_private) = codec_private {
+                                write_binary(buffer, CODEC_PRIVATE_ID, codec_private);
+                            }
+                            write_master(buffer, VIDEO_ID, |buffer| {
+                                write_uint(buffer, PIXEL_WIDTH_ID, *width as u64);
+                                write_uint(buffer, PIXEL_HEIGHT_ID, *height as u64);
+                            });
+                        }
+                        Track::Audio {
+                            sample_rate,
+                            channels,
+                            bits_per_sample,
+                        } => {
+                            write_uint(buffer, TRACK_TYPE_ID, TRACK_TYPE_AUDIO);
+                            write_string(buffer, CODEC_ID_ID, "A_PCM/INT/LIT");
+                            write_master(buffer, AUDIO_ID, |buffer| {
+                                write_float(buffer, SAMPLING_FREQUENCY_ID, *sample_rate as f64);
+                                write_uint(buffer, CHANNELS_ID, *channels as u64);
+                                write_uint(buffer, BIT_DEPTH_ID, *bits_per_sample as u64);
+                            });
+                        }
+                    }
+                });
+            }
+        });
+        self.write_bytes(&buffer)?;
+        self.tracks_written = true;
+
+        // Now that the tracks are known, write out anything we were holding on to
+        let mut pending_blocks = std::mem::take(&mut self.pending_blocks);
+        pending_blocks.sort_by_key(|block| block.time);
+        for block in pending_blocks {
+            self.write_block(block)?;
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self, block: Block) -> Result<()> {
+        let timecode = block.time / HUNDRED_NANOSECONDS_PER_TIMECODE;
+
+        // Start a new cluster on each video key frame so that players can seek
+        let is_video = matches!(
+            self.tracks[block.stream_index as usize],
+            Track::Video { .. }
+        );
+        let needs_cluster = match &self.cluster {
+            Some(cluster) => {
+                (is_video && block.key_frame && timecode > cluster.timecode)
+                    || timecode - cluster.timecode > MAX_CLUSTER_DURATION
+            }
+            None => true,
+        };
+        if needs_cluster {
+            self.close_cluster()?;
+            let mut data = Vec::new();
+            write_uint(&mut data, TIMECODE_ID, timecode.max(0) as u64);
+            self.cluster = Some(Cluster {
+                timecode: timecode.max(0),
+                data,
+            });
+        }
+
+        let cluster = self.cluster.as_mut().unwrap();
+        let relative_timecode =
+            (timecode - cluster.timecode).clamp(i16::MIN as i64, i16::MAX as i64) as i16;
+        let mut header = Vec::new();
+        write_size(&mut header, block.stream_index as u64 + 1);
+        header.extend_from_slice(&relative_timecode.to_be_bytes());
+        header.push(if block.key_frame { 0x80 } else { 0 });
+
+        write_id(&mut cluster.data, SIMPLE_BLOCK_ID);
+        write_size(&mut cluster.data, (header.len() + block.data.len()) as u64);
+        cluster.data.extend_from_slice(&header);
+        cluster.data.extend_from_slice(&block.data);
+        Ok(())
+    }
+
+    fn close_cluster(&mut self) -> Result<()> {
+        if let Some(cluster) = self.cluster.take() {
+            let mut buffer = Vec::new();
+            write_binary(&mut buffer, CLUSTER_ID, &cluster.data);
+            self.write_bytes(&buffer)?;
+        }
+        Ok(())
+    }
+}
+
+impl ContainerWriter for MatroskaWriter {
+    fn add_stream(
+        &mut self,
+        _output_type: &IMFMediaType,
+        input_type: &IMFMediaType,
+    ) -> Result<u32> {
+        // We don't do any encoding, so the input is written as-is
+        let track = unsafe {
+            let major_type = input_type.GetGUID(&MF_MT_MAJOR_TYPE)?;
+            let subtype = input_type.GetGUID(&MF_MT_SUBTYPE)?;
+            let video_format = if subtype == MFVideoFormat_VP90 {
+                Some(VideoFormat::Vp9)
+            } else if subtype == MFVideoFormat_H264 && !self.webm {
+                Some(VideoFormat::H264)
+            } else {
+                None
+            };
+            if let Some(format) = video_format.filter(|_| major_type == MFMediaType_Video) {
+                let frame_size = input_type.GetUINT64(&MF_MT_FRAME_SIZE)?;
+                Track::Video {
+                    format,
+                    width: (frame_size >> 32) as u32,
+                    height: frame_size as u32,
+                    codec_private: None,
+                }
+            } else if major_type == MFMediaType_Audio && subtype == MFAudioFormat_PCM && !self.webm
+            {
+                Track::Audio {
+                    sample_rate: input_type.GetUINT32(&MF_MT_AUDIO_SAMPLES_PER_SECOND)?,
+                    channels: input_type.GetUINT32(&MF_MT_AUDIO_NUM_CHANNELS)?,
+                    bits_per_sample: input_type.GetUINT32(&MF_MT_AUDIO_BITS_PER_SAMPLE)?,
+                }
+            } else {
+                return Err(Error::from(MF_E_INVALIDMEDIATYPE));
+            }
+        };
+        self.tracks.push(track);
+        Ok(self.tracks.len() as u32 - 1)
+    }
+
+    fn start(&mut self) -> Result<()> {
+        let mut buffer = Vec::new();
+        write_master(&mut buffer, EBML_ID, |buffer| {
+            write_uint(buffer, EBML_VERSION_ID, 1);
+            write_uint(buffer, EBML_READ_VERSION_ID, 1);
+            write_uint(buffer, EBML_MAX_ID_LENGTH_ID, 4);
+            write_uint(buffer, EBML_MAX_SIZE_LENGTH_ID, 8);
+            write_string(
+                buffer,
+                DOC_TYPE_ID,
+                if self.webm { "webm" } else { "matroska" },
+            );
+            write_uint(buffer, DOC_TYPE_VERSION_ID, 4);
+            write_uint(buffer, DOC_TYPE_READ_VERSION_ID, 2);
+        });
+
+        // The size of the segment and the duration are patched when we finalize
+        write_id(&mut buffer, SEGMENT_ID);
+        let segment_position = self.position()? + buffer.len() as u64;
+        write_size_with_length(&mut buffer, 0, RESERVED_SIZE_LENGTH);
+
+        let mut info = Vec::new();
+        write_uint(&mut info, TIMECODE_SCALE_ID, TIMECODE_SCALE);
+        write_string(&mut info, MUXING_APP_ID, env!("CARGO_PKG_NAME"));
+        write_string(&mut info, WRITING_APP_ID, env!("CARGO_PKG_NAME"));
+        if let Some(title) = &self.metadata.title {
+            write_string(&mut info, TITLE_ID, title);
+        }
+        let duration_offset = info.len();
+        write_float(&mut info, DURATION_ID, 0.0);
+        write_id(&mut buffer, INFO_ID);
+        write_size(&mut buffer, info.len() as u64);
+        let duration_position = self.position()? + (buffer.len() + duration_offset) as u64;
+        buffer.extend_from_slice(&info);
+        let tags: Vec<_> = [
+            ("ARTIST", &self.metadata.author),
+            ("COMMENT", &self.metadata.comment),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.as_ref().map(|value| (name, value)))
+        .collect();
+        if !tags.is_empty() {
+            write_master(&mut buffer, TAGS_ID, |buffer| {
+                write_master(buffer, TAG_ID, |buffer| {
+                    write_master(buffer, TARGETS_ID, |_| {});
+                    for (name, value) in tags {
+                        write_master(buffer, SIMPLE_TAG_ID, |buffer| {
+                            write_string(buffer, TAG_NAME_ID, name);
+                            write_string(buffer, TAG_STRING_ID, value);
+                        });
+                    }
+                });
+            });
+        }
+        self.write_bytes(&buffer)?;
+
+        self.segment_position = segment_position;
+        self.duration_position = duration_position;
+        Ok(())
+    }
+
+    fn write_sample(&mut self, stream_index: u32, sample: &IMFSample) -> Result<()> {
+        let (time, duration) = unsafe {
+            let time = sample.GetSampleTime()?;
+            let duration = sample.GetSampleDuration().unwrap_or_default();
+            (time, duration)
+        };
+        let key_frame = is_key_frame(sample);
+        let data = get_sample_data(sample)?;
+        self.end_time = self.end_time.max(time + duration);
+
+        let (key_frame, data) = match &mut self.tracks[stream_index as usize] {
+            Track::Video {
+                format: VideoFormat::H264,
+                codec_private,
+                ..
+            } => {
+                if codec_private.is_none() && key_frame {
+                    *codec_private = create_decoder_configuration(&data);
+                }
+                (key_frame, to_length_prefixed(&data))
+            }
+            Track::Video {
+                format: VideoFormat::Vp9,
+                ..
+            } => (key_frame, data),
+            // Every audio sample can be decoded on its own
+            Track::Audio { .. } => (true, data),
+        };
+        let block = Block {
+            stream_index,
+            time,
+            key_frame,
+            data,
+        };
+
+        if self.tracks_written {
+            self.write_block(block)
+        } else {
+            self.pending_blocks.push(block);
+            // Only H.264 needs codec private data, which we get from the first key frame
+            let ready = self.tracks.iter().all(|track| match track {
+                Track::Video {
+                    format: VideoFormat::H264,
+                    codec_private,
+                    ..
+                } => codec_private.is_some(),
+                _ => true,
+            });
+            if ready {
+                self.write_tracks()?;
+            }
+            Ok(())
+        }
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        if !self.tracks_written {
+            self.write_tracks()?;
+        }
+        self.close_cluster()?;
+
+        let end_position = self.position()?;
+        let segment_size = end_position - self.segment_position - RESERVED_SIZE_LENGTH as u64;
+        let mut buffer = Vec::new();
+        write_size_with_length(&mut buffer, segment_size, RESERVED_SIZE_LENGTH);
+        unsafe { self.byte_stream.SetCurrentPosition(self.segment_position)? };
+        self.write_bytes(&buffer)?;
+
+        let duration = self.end_time as f64 / HUNDRED_NANOSECONDS_PER_TIMECODE as f64;
+        let mut buffer = Vec::new();
+        write_float(&mut buffer, DURATION_ID, duration);
+        unsafe {
+            self.byte_stream
+                .SetCurrentPosition(self.duration_position)?
+        };
+        self.write_bytes(&buffer)?;
+
+        unsafe {
+            self.byte_stream.SetCurrentPosition(end_position)?;
+            self.byte_stream.Flush()
+        }
+    }
+}
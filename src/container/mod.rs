@@ -0,0 +1,170 @@
+mod annex_b;
+pub mod avc;
+mod ebml;
+pub mod fmp4;
+pub mod fragment_writer;
+mod matroska;
+pub mod mp4_repair;
+pub mod mpeg_ts;
+pub mod null;
+mod sink_writer;
+
+use std::{fmt::Display, io, path::Path, str::FromStr};
+
+use windows::{
+    core::{Error, Result},
+    Storage::Streams::IRandomAccessStream,
+    Win32::{
+        Foundation::{E_FAIL, WIN32_ERROR},
+        Media::MediaFoundation::{IMFMediaType, IMFSample, MFCreateMFByteStreamOnStreamEx},
+    },
+};
+
+pub use self::annex_b::AnnexBWriter;
+use self::{matroska::MatroskaWriter, sink_writer::SinkWriter};
+
+/// The file format of a recording, selected by the extension of the output file.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Container {
+    Mp4,
+    Mkv,
+    Webm,
+    Gif,
+    /// A raw Annex B H.264 stream, without audio.
+    H264,
+    /// AAC audio in an MP4 file, without video.
+    M4a,
+    /// Lossless audio, without video.
+    Flac,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseContainerError(&'static str);
+
+impl Container {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        path.as_ref().extension()?.to_str()?.parse().ok()
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Container::Mp4 => "mp4",
+            Container::Mkv => "mkv",
+            Container::Webm => "webm",
+            Container::Gif => "gif",
+            Container::H264 => "h264",
+            Container::M4a => "m4a",
+            Container::Flac => "flac",
+        }
+    }
+
+    /// Whether the container only holds audio.
+    pub fn is_audio_only(&self) -> bool {
+        matches!(self, Container::M4a | Container::Flac)
+    }
+}
+
+impl FromStr for Container {
+    type Err = ParseContainerError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mp4" => Ok(Container::Mp4),
+            "mkv" => Ok(Container::Mkv),
+            "webm" => Ok(Container::Webm),
+            "gif" => Ok(Container::Gif),
+            "h264" => Ok(Container::H264),
+            "m4a" => Ok(Container::M4a),
+            "flac" => Ok(Container::Flac),
+            _ => Err(ParseContainerError(
+                "Invalid format value! Expecting: mp4, mkv, webm, gif, h264, m4a, or flac.",
+            )),
+        }
+    }
+}
+
+impl Display for Container {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+impl Display for ParseContainerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseContainerError {}
+
+/// Describes a recording in media libraries and players. Only MP4, M4A,
+/// MKV, and WebM files have metadata.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub comment: Option<String>,
+}
+
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none() && self.author.is_none() && self.comment.is_none()
+    }
+}
+
+/// Writes encoded samples into a container.
+pub trait ContainerWriter: Send {
+    /// Adds a stream to the container. If the input type differs from the
+    /// output type, the writer may encode the samples for the stream.
+    fn add_stream(&mut self, output_type: &IMFMediaType, input_type: &IMFMediaType) -> Result<u32>;
+    fn start(&mut self) -> Result<()>;
+    fn write_sample(&mut self, stream_index: u32, sample: &IMFSample) -> Result<()>;
+    fn finalize(&mut self) -> Result<()>;
+}
+
+pub fn create_container_writer(
+    container: Container,
+    stream: &IRandomAccessStream,
+    metadata: &Metadata,
+) -> Result<Box<dyn ContainerWriter>> {
+    let byte_stream = unsafe { MFCreateMFByteStreamOnStreamEx(stream)? };
+    Ok(match container {
+        Container::Mp4 | Container::M4a | Container::Flac => {
+            Box::new(SinkWriter::new(&byte_stream, container, metadata)?)
+        }
+        Container::Mkv | Container::Webm => {
+            Box::new(MatroskaWriter::new(byte_stream, container, metadata))
+        }
+        Container::H264 => Box::new(AnnexBWriter::from_byte_stream(byte_stream)),
+        // GIFs don't go through Media Foundation at all
+        Container::Gif => panic!("GIF recordings are written by the GIF encoding session!"),
+    })
+}
+
+pub fn to_error(error: io::Error) -> Error {
+    let code = error
+        .raw_os_error()
+        .map(|code| WIN32_ERROR(code as u32).to_hresult())
+        .unwrap_or(E_FAIL);
+    Error::new(code, error.to_string().as_str().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Container;
+
+    #[test]
+    fn container_from_path_test() {
+        assert_eq!(Container::from_path("recording.mp4"), Some(Container::Mp4));
+        assert_eq!(
+            Container::from_path("somedir/recording.MKV"),
+            Some(Container::Mkv)
+        );
+        assert_eq!(Container::from_path("clip.webm"), Some(Container::Webm));
+        assert_eq!(Container::from_path("clip.gif"), Some(Container::Gif));
+        assert_eq!(Container::from_path("clip.h264"), Some(Container::H264));
+        assert_eq!(Container::from_path("meeting.m4a"), Some(Container::M4a));
+        assert_eq!(Container::from_path("meeting.flac"), Some(Container::Flac));
+        assert_eq!(Container::from_path("recording.avi"), None);
+        assert_eq!(Container::from_path("recording"), None);
+    }
+}
@@ -0,0 +1,794 @@
+// Recovers fragmented MP4 recordings (see RecorderBuilder::fragmented) that
+// were interrupted before they were finalized, by rewriting every complete
+// fragment into a regular MP4 with a moov box that describes all of the
+// samples. Regular MP4 recordings can't be recovered this way, their moov
+// box is only written once the recording is finalized.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::Path,
+    time::Duration,
+};
+
+use windows::{
+    core::{Error, Result},
+    Win32::Media::MediaFoundation::MF_E_INVALID_FILE_FORMAT,
+};
+
+use super::{
+    fmp4::{
+        write_box, write_full_box, write_u32s, TFHD_DEFAULT_BASE_IS_MOOF, TRUN_DATA_OFFSET,
+        TRUN_SAMPLE_COMPOSITION_TIME_OFFSET, TRUN_SAMPLE_DURATION, TRUN_SAMPLE_FLAGS,
+        TRUN_SAMPLE_SIZE,
+    },
+    to_error,
+};
+
+// See ISO/IEC 14496-12 8.8.7.1 and 8.8.8.1
+const TFHD_BASE_DATA_OFFSET: u32 = 0x1;
+const TFHD_SAMPLE_DESCRIPTION_INDEX: u32 = 0x2;
+const TFHD_DEFAULT_SAMPLE_DURATION: u32 = 0x8;
+const TFHD_DEFAULT_SAMPLE_SIZE: u32 = 0x10;
+const TFHD_DEFAULT_SAMPLE_FLAGS: u32 = 0x20;
+const TRUN_FIRST_SAMPLE_FLAGS: u32 = 0x4;
+const SAMPLE_IS_NON_SYNC: u32 = 0x00010000;
+
+/// What repair_mp4 recovered.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RepairSummary {
+    pub video_frames: u64,
+    /// The length of the longest track.
+    pub duration: Duration,
+    /// The size of the incomplete data at the end of the file.
+    pub discarded_bytes: u64,
+}
+
+struct Track {
+    track_id: u32,
+    timescale: u32,
+    is_video: bool,
+    // The defaults from the trex box
+    default_duration: u32,
+    default_size: u32,
+    default_flags: u32,
+    samples: Vec<Sample>,
+    // Where the next fragment continues if it doesn't say
+    next_decode_time: u64,
+}
+
+struct Sample {
+    duration: u32,
+    size: u32,
+    sync: bool,
+    composition_offset: i32,
+    // Where the data is in the damaged file
+    offset: u64,
+}
+
+/// Writes the complete fragments of a fragmented MP4 file to a new,
+/// regular MP4 file.
+pub fn repair_mp4<P: AsRef<Path>, Q: AsRef<Path>>(
+    input_path: P,
+    output_path: Q,
+) -> Result<RepairSummary> {
+    let mut input = BufReader::new(File::open(input_path).map_err(to_error)?);
+    let mut output = BufWriter::new(File::create(output_path).map_err(to_error)?);
+    let summary = repair(&mut input, &mut output).map_err(|error| {
+        if error.kind() == ErrorKind::InvalidData {
+            Error::new(MF_E_INVALID_FILE_FORMAT, error.to_string().as_str().into())
+        } else {
+            to_error(error)
+        }
+    })?;
+    output.flush().map_err(to_error)?;
+    Ok(summary)
+}
+
+fn repair<R: Read + Seek, W: Write>(input: &mut R, output: &mut W) -> io::Result<RepairSummary> {
+    let file_size = input.seek(SeekFrom::End(0))?;
+    let mut ftyp = None;
+    let mut moov = None;
+    let mut moofs = Vec::new();
+    // The end of the last box that's all there
+    let mut complete_end = 0;
+    let mut position = 0;
+    while let Some((box_type, header_size, size)) = read_box_header(input, position, file_size)? {
+        let end = if size == 0 {
+            file_size
+        } else {
+            position + size
+        };
+        if end > file_size || size < header_size && size != 0 {
+            // The rest of the file was never written
+            break;
+        }
+        if matches!(&box_type, b"ftyp" | b"moov" | b"moof") {
+            let mut content = vec![0; (end - position - header_size) as usize];
+            input.read_exact(&mut content)?;
+            match &box_type {
+                b"ftyp" => ftyp = Some(content),
+                b"moov" => moov = Some(content),
+                _ => moofs.push((position, content)),
+            }
+        }
+        complete_end = end;
+        position = end;
+    }
+
+    let moov = moov.ok_or_else(|| {
+        invalid_file("The recording has no moov box! Only fragmented recordings (see --fragmented) can be repaired.")
+    })?;
+    if !parse_boxes(&moov)
+        .iter()
+        .any(|(box_type, _)| box_type == b"mvex")
+    {
+        return Err(invalid_file(
+            "The recording isn't fragmented, so it doesn't need to be repaired!",
+        ));
+    }
+    let mut tracks = parse_tracks(&moov)?;
+    for (moof_offset, moof) in &moofs {
+        parse_moof(*moof_offset, moof, &mut tracks)?;
+    }
+    // Samples that weren't written completely are left out
+    let mut data_end = complete_end;
+    for track in &mut tracks {
+        track
+            .samples
+            .retain(|sample| sample.offset + sample.size as u64 <= file_size);
+        for sample in &track.samples {
+            data_end = data_end.max(sample.offset + sample.size as u64);
+        }
+    }
+    if tracks.iter().all(|track| track.samples.is_empty()) {
+        return Err(invalid_file(
+            "The recording doesn't have any complete samples!",
+        ));
+    }
+
+    // Every sample is its own chunk, in the order they were in the file
+    let mut samples: Vec<(usize, usize)> = tracks
+        .iter()
+        .enumerate()
+        .flat_map(|(track_index, track)| {
+            (0..track.samples.len()).map(move |sample_index| (track_index, sample_index))
+        })
+        .collect();
+    samples.sort_by_key(|(track_index, sample_index)| {
+        tracks[*track_index].samples[*sample_index].offset
+    });
+    let data_size: u64 = samples
+        .iter()
+        .map(|(track_index, sample_index)| tracks[*track_index].samples[*sample_index].size as u64)
+        .sum();
+
+    let mut ftyp_box = Vec::new();
+    if let Some(ftyp) = &ftyp {
+        write_box(&mut ftyp_box, b"ftyp", |output| {
+            output.extend_from_slice(ftyp)
+        });
+    }
+    // Big recordings need a 64-bit mdat size, and 64-bit chunk offsets
+    let mdat_header_size = if data_size + 8 > u32::MAX as u64 {
+        16
+    } else {
+        8
+    };
+    let build_moov = |data_start: u64, large_offsets: bool| {
+        let mut offsets = vec![Vec::new(); tracks.len()];
+        let mut offset = data_start;
+        for (track_index, sample_index) in &samples {
+            offsets[*track_index].push(offset);
+            offset += tracks[*track_index].samples[*sample_index].size as u64;
+        }
+        write_moov(&moov, &tracks, &offsets, large_offsets)
+    };
+    let large_offsets =
+        ftyp_box.len() as u64 + build_moov(0, true).len() as u64 + mdat_header_size + data_size
+            > u32::MAX as u64;
+    let data_start =
+        ftyp_box.len() as u64 + build_moov(0, large_offsets).len() as u64 + mdat_header_size;
+    let moov_box = build_moov(data_start, large_offsets);
+
+    output.write_all(&ftyp_box)?;
+    output.write_all(&moov_box)?;
+    if mdat_header_size == 16 {
+        output.write_all(&1u32.to_be_bytes())?;
+        output.write_all(b"mdat")?;
+        output.write_all(&(data_size + 16).to_be_bytes())?;
+    } else {
+        output.write_all(&(data_size as u32 + 8).to_be_bytes())?;
+        output.write_all(b"mdat")?;
+    }
+    // Samples are usually next to each other, so they're copied in runs
+    let mut run: Option<(u64, u64)> = None;
+    for (track_index, sample_index) in &samples {
+        let sample = &tracks[*track_index].samples[*sample_index];
+        run = match run {
+            Some((start, end)) if end == sample.offset => Some((start, end + sample.size as u64)),
+            Some((start, end)) => {
+                copy_range(input, output, start, end)?;
+                Some((sample.offset, sample.offset + sample.size as u64))
+            }
+            None => Some((sample.offset, sample.offset + sample.size as u64)),
+        };
+    }
+    if let Some((start, end)) = run {
+        copy_range(input, output, start, end)?;
+    }
+
+    let duration = tracks
+        .iter()
+        .filter(|track| track.timescale > 0)
+        .map(|track| {
+            Duration::from_secs_f64(get_track_duration(track) as f64 / track.timescale as f64)
+        })
+        .max()
+        .unwrap_or_default();
+    Ok(RepairSummary {
+        video_frames: tracks
+            .iter()
+            .filter(|track| track.is_video)
+            .map(|track| track.samples.len() as u64)
+            .sum(),
+        duration,
+        discarded_bytes: file_size - data_end,
+    })
+}
+
+// Returns the type, the header size, and the size of the box at the position
+fn read_box_header<R: Read + Seek>(
+    input: &mut R,
+    position: u64,
+    file_size: u64,
+) -> io::Result<Option<([u8; 4], u64, u64)>> {
+    if file_size - position < 8 {
+        return Ok(None);
+    }
+    input.seek(SeekFrom::Start(position))?;
+    let mut header = [0u8; 8];
+    input.read_exact(&mut header)?;
+    let size = u32::from_be_bytes(header[..4].try_into().unwrap()) as u64;
+    let box_type = header[4..].try_into().unwrap();
+    if size != 1 {
+        return Ok(Some((box_type, 8, size)));
+    }
+    if file_size - position < 16 {
+        return Ok(None);
+    }
+    let mut large_size = [0u8; 8];
+    input.read_exact(&mut large_size)?;
+    Ok(Some((box_type, 16, u64::from_be_bytes(large_size))))
+}
+
+fn copy_range<R: Read + Seek, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    start: u64,
+    end: u64,
+) -> io::Result<()> {
+    input.seek(SeekFrom::Start(start))?;
+    let copied = std::io::copy(&mut input.take(end - start), output)?;
+    if copied != end - start {
+        return Err(invalid_file("The recording ended unexpectedly!"));
+    }
+    Ok(())
+}
+
+// Returns the type and the content of each box, up to the first incomplete one
+fn parse_boxes(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut boxes = Vec::new();
+    let mut reader = Reader::new(data);
+    while let (Some(size), Some(box_type)) = (reader.u32(), reader.bytes(4)) {
+        let box_type: [u8; 4] = box_type.try_into().unwrap();
+        let content_size = match size {
+            0 => reader.remaining(),
+            1 => match reader.u64() {
+                Some(size) if size >= 16 => size as usize - 16,
+                _ => break,
+            },
+            size if size >= 8 => size as usize - 8,
+            _ => break,
+        };
+        match reader.bytes(content_size) {
+            Some(content) => boxes.push((box_type, content)),
+            None => break,
+        }
+    }
+    boxes
+}
+
+fn find_box<'a>(data: &'a [u8], box_type: &[u8; 4]) -> Option<&'a [u8]> {
+    parse_boxes(data)
+        .into_iter()
+        .find(|(other, _)| other == box_type)
+        .map(|(_, content)| content)
+}
+
+fn parse_tracks(moov: &[u8]) -> io::Result<Vec<Track>> {
+    let damaged = || invalid_file("The recording's moov box is damaged!");
+    let mut tracks = Vec::new();
+    for (box_type, trak) in parse_boxes(moov) {
+        if &box_type != b"trak" {
+            continue;
+        }
+        let tkhd = find_box(trak, b"tkhd").ok_or_else(damaged)?;
+        let mdia = find_box(trak, b"mdia").ok_or_else(damaged)?;
+        let mdhd = find_box(mdia, b"mdhd").ok_or_else(damaged)?;
+        let hdlr = find_box(mdia, b"hdlr").ok_or_else(damaged)?;
+        let track_id_offset = if tkhd.first() == Some(&1) { 20 } else { 12 };
+        let timescale_offset = if mdhd.first() == Some(&1) { 20 } else { 12 };
+        tracks.push(Track {
+            track_id: Reader::at(tkhd, track_id_offset)
+                .u32()
+                .ok_or_else(damaged)?,
+            timescale: Reader::at(mdhd, timescale_offset)
+                .u32()
+                .ok_or_else(damaged)?,
+            is_video: Reader::at(hdlr, 8).bytes(4) == Some(b"vide"),
+            default_duration: 0,
+            default_size: 0,
+            default_flags: 0,
+            samples: Vec::new(),
+            next_decode_time: 0,
+        });
+    }
+    if let Some(mvex) = find_box(moov, b"mvex") {
+        for (box_type, trex) in parse_boxes(mvex) {
+            if &box_type != b"trex" {
+                continue;
+            }
+            let mut reader = Reader::at(trex, 4);
+            let track_id = reader.u32().ok_or_else(damaged)?;
+            let _description_index = reader.u32();
+            if let Some(track) = tracks.iter_mut().find(|track| track.track_id == track_id) {
+                track.default_duration = reader.u32().ok_or_else(damaged)?;
+                track.default_size = reader.u32().ok_or_else(damaged)?;
+                track.default_flags = reader.u32().ok_or_else(damaged)?;
+            }
+        }
+    }
+    Ok(tracks)
+}
+
+// Adds the samples of each of the moof's track fragments to their tracks
+fn parse_moof(moof_offset: u64, moof: &[u8], tracks: &mut [Track]) -> io::Result<()> {
+    let damaged = || invalid_file("A moof box of the recording is damaged!");
+    // Without an explicit base, each track fragment's data follows the previous one's
+    let mut data_end = moof_offset;
+    for (box_type, traf) in parse_boxes(moof) {
+        if &box_type != b"traf" {
+            continue;
+        }
+        let tfhd = find_box(traf, b"tfhd").ok_or_else(damaged)?;
+        let mut reader = Reader::new(tfhd);
+        let flags = reader.u32().ok_or_else(damaged)? & 0xFFFFFF;
+        let track_id = reader.u32().ok_or_else(damaged)?;
+        let track = match tracks.iter_mut().find(|track| track.track_id == track_id) {
+            Some(track) => track,
+            None => continue,
+        };
+        let mut base_offset = if flags & TFHD_DEFAULT_BASE_IS_MOOF != 0 {
+            moof_offset
+        } else {
+            data_end
+        };
+        if flags & TFHD_BASE_DATA_OFFSET != 0 {
+            base_offset = reader.u64().ok_or_else(damaged)?;
+        }
+        if flags & TFHD_SAMPLE_DESCRIPTION_INDEX != 0 {
+            reader.u32().ok_or_else(damaged)?;
+        }
+        let mut optional = |flag: u32, default: u32| -> io::Result<u32> {
+            if flags & flag != 0 {
+                reader.u32().ok_or_else(damaged)
+            } else {
+                Ok(default)
+            }
+        };
+        let default_duration = optional(TFHD_DEFAULT_SAMPLE_DURATION, track.default_duration)?;
+        let default_size = optional(TFHD_DEFAULT_SAMPLE_SIZE, track.default_size)?;
+        let default_flags = optional(TFHD_DEFAULT_SAMPLE_FLAGS, track.default_flags)?;
+
+        if let Some(tfdt) = find_box(traf, b"tfdt") {
+            let mut reader = Reader::new(tfdt);
+            let version = reader.u32().ok_or_else(damaged)? >> 24;
+            track.next_decode_time = if version == 1 {
+                reader.u64()
+            } else {
+                reader.u32().map(u64::from)
+            }
+            .ok_or_else(damaged)?;
+        }
+        let mut offset = base_offset;
+        for (box_type, trun) in parse_boxes(traf) {
+            if &box_type != b"trun" {
+                continue;
+            }
+            let mut reader = Reader::new(trun);
+            let flags = reader.u32().ok_or_else(damaged)? & 0xFFFFFF;
+            let sample_count = reader.u32().ok_or_else(damaged)?;
+            if flags & TRUN_DATA_OFFSET != 0 {
+                let data_offset = reader.u32().ok_or_else(damaged)? as i32;
+                offset = base_offset
+                    .checked_add_signed(data_offset as i64)
+                    .ok_or_else(damaged)?;
+            }
+            let first_sample_flags = if flags & TRUN_FIRST_SAMPLE_FLAGS != 0 {
+                Some(reader.u32().ok_or_else(damaged)?)
+            } else {
+                None
+            };
+            for index in 0..sample_count {
+                let mut optional = |flag: u32, default: u32| -> io::Result<u32> {
+                    if flags & flag != 0 {
+                        reader.u32().ok_or_else(damaged)
+                    } else {
+                        Ok(default)
+                    }
+                };
+                let duration = optional(TRUN_SAMPLE_DURATION, default_duration)?;
+                let size = optional(TRUN_SAMPLE_SIZE, default_size)?;
+                let mut sample_flags = optional(TRUN_SAMPLE_FLAGS, default_flags)?;
+                if index == 0 {
+                    sample_flags = first_sample_flags.unwrap_or(sample_flags);
+                }
+                // Version 0 offsets are unsigned, but never get that big
+                let composition_offset = optional(TRUN_SAMPLE_COMPOSITION_TIME_OFFSET, 0)? as i32;
+                track.samples.push(Sample {
+                    duration,
+                    size,
+                    sync: sample_flags & SAMPLE_IS_NON_SYNC == 0,
+                    composition_offset,
+                    offset,
+                });
+                track.next_decode_time += duration as u64;
+                offset += size as u64;
+            }
+        }
+        data_end = offset;
+    }
+    Ok(())
+}
+
+fn get_track_duration(track: &Track) -> u64 {
+    track
+        .samples
+        .iter()
+        .map(|sample| sample.duration as u64)
+        .sum()
+}
+
+// Copies the moov box, filling in the durations and the sample tables of
+// the tracks, and leaving out the mvex box since there are no fragments
+fn write_moov(moov: &[u8], tracks: &[Track], offsets: &[Vec<u64>], large_offsets: bool) -> Vec<u8> {
+    let movie_timescale = find_box(moov, b"mvhd")
+        .and_then(|mvhd| Reader::at(mvhd, if mvhd.first() == Some(&1) { 20 } else { 12 }).u32())
+        .unwrap_or(1000)
+        .max(1);
+    let to_movie_time = |track: &Track| {
+        get_track_duration(track) * movie_timescale as u64 / track.timescale.max(1) as u64
+    };
+    let movie_duration = tracks.iter().map(to_movie_time).max().unwrap_or(0);
+
+    let mut output = Vec::new();
+    write_box(&mut output, b"moov", |output| {
+        let mut track_index = 0;
+        for (box_type, content) in parse_boxes(moov) {
+            match &box_type {
+                b"mvhd" => write_with_duration(output, b"mvhd", content, 16, 24, movie_duration),
+                b"mvex" => {}
+                b"trak" if track_index < tracks.len() => {
+                    let track = &tracks[track_index];
+                    write_trak(
+                        output,
+                        content,
+                        track,
+                        to_movie_time(track),
+                        &offsets[track_index],
+                        large_offsets,
+                    );
+                    track_index += 1;
+                }
+                _ => write_box(output, &box_type, |output| {
+                    output.extend_from_slice(content)
+                }),
+            }
+        }
+    });
+    output
+}
+
+fn write_trak(
+    output: &mut Vec<u8>,
+    trak: &[u8],
+    track: &Track,
+    movie_duration: u64,
+    offsets: &[u64],
+    large_offsets: bool,
+) {
+    write_box(output, b"trak", |output| {
+        for (box_type, content) in parse_boxes(trak) {
+            match &box_type {
+                b"tkhd" => write_with_duration(output, b"tkhd", content, 20, 28, movie_duration),
+                b"mdia" => write_box(output, b"mdia", |output| {
+                    for (box_type, content) in parse_boxes(content) {
+                        match &box_type {
+                            b"mdhd" => write_with_duration(
+                                output,
+                                b"mdhd",
+                                content,
+                                16,
+                                24,
+                                get_track_duration(track),
+                            ),
+                            b"minf" => write_box(output, b"minf", |output| {
+                                for (box_type, content) in parse_boxes(content) {
+                                    if &box_type == b"stbl" {
+                                        write_stbl(output, content, track, offsets, large_offsets);
+                                    } else {
+                                        write_box(output, &box_type, |output| {
+                                            output.extend_from_slice(content)
+                                        });
+                                    }
+                                }
+                            }),
+                            _ => write_box(output, &box_type, |output| {
+                                output.extend_from_slice(content)
+                            }),
+                        }
+                    }
+                }),
+                _ => write_box(output, &box_type, |output| {
+                    output.extend_from_slice(content)
+                }),
+            }
+        }
+    });
+}
+
+// Copies a full box (mvhd, tkhd, or mdhd) with a new duration, which is at
+// a different offset in version 0 (32-bit times) and version 1 (64-bit times)
+fn write_with_duration(
+    output: &mut Vec<u8>,
+    box_type: &[u8; 4],
+    content: &[u8],
+    version_0_offset: usize,
+    version_1_offset: usize,
+    duration: u64,
+) {
+    let mut content = content.to_vec();
+    if content.first() == Some(&1) {
+        if let Some(field) = content.get_mut(version_1_offset..version_1_offset + 8) {
+            field.copy_from_slice(&duration.to_be_bytes());
+        }
+    } else if let Some(field) = content.get_mut(version_0_offset..version_0_offset + 4) {
+        field.copy_from_slice(&(duration.min(u32::MAX as u64) as u32).to_be_bytes());
+    }
+    write_box(output, box_type, |output| {
+        output.extend_from_slice(&content)
+    });
+}
+
+fn write_stbl(
+    output: &mut Vec<u8>,
+    stbl: &[u8],
+    track: &Track,
+    offsets: &[u64],
+    large_offsets: bool,
+) {
+    let samples = &track.samples;
+    write_box(output, b"stbl", |output| {
+        // Only the sample description is kept, the rest described the fragments
+        if let Some(stsd) = find_box(stbl, b"stsd") {
+            write_box(output, b"stsd", |output| output.extend_from_slice(stsd));
+        }
+        let durations = get_runs(samples.iter().map(|sample| sample.duration));
+        write_full_box(output, b"stts", 0, 0, |output| {
+            output.extend_from_slice(&(durations.len() as u32).to_be_bytes());
+            for (count, duration) in durations {
+                write_u32s(output, &[count, duration]);
+            }
+        });
+        if samples.iter().any(|sample| sample.composition_offset != 0) {
+            let offsets = get_runs(samples.iter().map(|sample| sample.composition_offset));
+            // Version 1 has signed offsets
+            write_full_box(output, b"ctts", 1, 0, |output| {
+                output.extend_from_slice(&(offsets.len() as u32).to_be_bytes());
+                for (count, offset) in offsets {
+                    write_u32s(output, &[count, offset as u32]);
+                }
+            });
+        }
+        if samples.iter().any(|sample| !sample.sync) {
+            let sync_samples: Vec<u32> = (1..)
+                .zip(samples)
+                .filter(|(_, sample)| sample.sync)
+                .map(|(number, _)| number)
+                .collect();
+            write_full_box(output, b"stss", 0, 0, |output| {
+                output.extend_from_slice(&(sync_samples.len() as u32).to_be_bytes());
+                write_u32s(output, &sync_samples);
+            });
+        }
+        write_full_box(output, b"stsz", 0, 0, |output| {
+            write_u32s(output, &[0, samples.len() as u32]);
+            for sample in samples {
+                output.extend_from_slice(&sample.size.to_be_bytes());
+            }
+        });
+        // Every chunk has a single sample
+        write_full_box(output, b"stsc", 0, 0, |output| {
+            if samples.is_empty() {
+                write_u32s(output, &[0]);
+            } else {
+                write_u32s(output, &[1, 1, 1, 1]);
+            }
+        });
+        if large_offsets {
+            write_full_box(output, b"co64", 0, 0, |output| {
+                output.extend_from_slice(&(offsets.len() as u32).to_be_bytes());
+                for offset in offsets {
+                    output.extend_from_slice(&offset.to_be_bytes());
+                }
+            });
+        } else {
+            write_full_box(output, b"stco", 0, 0, |output| {
+                output.extend_from_slice(&(offsets.len() as u32).to_be_bytes());
+                for offset in offsets {
+                    output.extend_from_slice(&(*offset as u32).to_be_bytes());
+                }
+            });
+        }
+    });
+}
+
+// Counts the repeats of each value, for the run-length encoded tables
+fn get_runs<T: PartialEq, I: Iterator<Item = T>>(values: I) -> Vec<(u32, T)> {
+    let mut runs: Vec<(u32, T)> = Vec::new();
+    for value in values {
+        match runs.last_mut() {
+            Some((count, last)) if *last == value => *count += 1,
+            _ => runs.push((1, value)),
+        }
+    }
+    runs
+}
+
+fn invalid_file(message: &'static str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+// Reads big-endian values, returning None past the end of the data
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self::at(data, 0)
+    }
+
+    fn at(data: &'a [u8], position: usize) -> Self {
+        Self { data, position }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.position)
+    }
+
+    fn bytes(&mut self, count: usize) -> Option<&'a [u8]> {
+        let end = self.position.checked_add(count)?;
+        let bytes = self.data.get(self.position..end)?;
+        self.position = end;
+        Some(bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4)
+            .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.bytes(8)
+            .map(|bytes| u64::from_be_bytes(bytes.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{find_box, get_runs, parse_boxes, repair, Reader};
+    use crate::container::fmp4::{
+        create_init_segment, create_media_segment, Fmp4Sample, Fragment, TrackInfo,
+    };
+
+    fn samples(count: usize, first_size: usize) -> Vec<Fmp4Sample> {
+        (0..count)
+            .map(|index| Fmp4Sample {
+                duration: 3000,
+                composition_offset: 0,
+                key_frame: index == 0,
+                data: vec![index as u8; first_size + index],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn repair_test() {
+        let mut file = create_init_segment(&[TrackInfo::Video {
+            width: 1920,
+            height: 1080,
+            decoder_configuration: vec![1, 0x64, 0, 0x28],
+        }]);
+        let first = samples(3, 10);
+        let second = samples(2, 20);
+        for (sequence_number, (base_decode_time, samples)) in
+            [(0, &first), (9000, &second)].into_iter().enumerate()
+        {
+            file.extend_from_slice(&create_media_segment(
+                sequence_number as u32 + 1,
+                &[Fragment {
+                    track_index: 0,
+                    base_decode_time,
+                    samples,
+                }],
+            ));
+        }
+        // The last sample was only partly written
+        file.truncate(file.len() - 5);
+
+        let mut output = Vec::new();
+        let summary = repair(&mut Cursor::new(&file), &mut output).unwrap();
+        assert_eq!(summary.video_frames, 4);
+        assert_eq!(summary.discarded_bytes, 16);
+        assert_eq!(summary.duration.as_millis(), 133);
+
+        let boxes: Vec<_> = parse_boxes(&output)
+            .into_iter()
+            .map(|(box_type, _)| box_type)
+            .collect();
+        assert_eq!(boxes, [*b"ftyp", *b"moov", *b"mdat"]);
+        let moov = find_box(&output, b"moov").unwrap();
+        assert!(find_box(moov, b"mvex").is_none());
+        let stbl = [b"trak", b"mdia", b"minf", b"stbl"]
+            .iter()
+            .fold(moov, |content, box_type| {
+                find_box(content, box_type).unwrap()
+            });
+        let stsz = find_box(stbl, b"stsz").unwrap();
+        assert_eq!(Reader::at(stsz, 8).u32(), Some(4));
+        // The second fragment's key frame is a sync sample
+        let stss = find_box(stbl, b"stss").unwrap();
+        assert_eq!(&stss[4..], [0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 4]);
+
+        // The chunk offsets point at the samples in the new mdat
+        let stco = find_box(stbl, b"stco").unwrap();
+        let offset = Reader::at(stco, 8 + 3 * 4).u32().unwrap() as usize;
+        assert_eq!(output[offset..offset + 20], [0; 20]);
+        let mdat = find_box(&output, b"mdat").unwrap();
+        assert_eq!(mdat.len(), 10 + 11 + 12 + 20);
+    }
+
+    #[test]
+    fn repair_unfragmented_test() {
+        // A regular MP4 that was never finalized only has its media data
+        let mut file = Vec::new();
+        file.extend_from_slice(&16u32.to_be_bytes());
+        file.extend_from_slice(b"mdat");
+        file.extend_from_slice(&[0; 8]);
+        assert!(repair(&mut Cursor::new(&file), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn runs_test() {
+        assert_eq!(
+            get_runs([3000, 3000, 1500, 3000].into_iter()),
+            vec![(2, 3000), (1, 1500), (1, 3000)]
+        );
+        assert!(get_runs(std::iter::empty::<u32>()).is_empty());
+    }
+}
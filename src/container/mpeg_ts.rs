@@ -0,0 +1,365 @@
+// A minimal MPEG-TS muxer (see ISO/IEC 13818-1) for a single program with an
+// H.264 video stream and an optional AAC audio stream, which is what SRT
+// receivers expect. It only produces bytes, it's up to the caller to send them.
+
+use super::avc::split_nal_units;
+
+pub const TS_PACKET_SIZE: usize = 188;
+const SYNC_BYTE: u8 = 0x47;
+const PAT_PID: u16 = 0;
+const PMT_PID: u16 = 0x1000;
+const VIDEO_PID: u16 = 0x100;
+const AUDIO_PID: u16 = 0x101;
+const PROGRAM_NUMBER: u16 = 1;
+const STREAM_TYPE_H264: u8 = 0x1B;
+const STREAM_TYPE_AAC: u8 = 0x0F;
+const VIDEO_STREAM_ID: u8 = 0xE0;
+const AUDIO_STREAM_ID: u8 = 0xC0;
+const NAL_UNIT_TYPE_AUD: u8 = 9;
+// Every access unit has to start with an access unit delimiter
+const ACCESS_UNIT_DELIMITER: [u8; 6] = [0, 0, 0, 1, NAL_UNIT_TYPE_AUD, 0xF0];
+// Timestamps are offset by a second so that decode timestamps, which can be
+// earlier than the presentation timestamps, never go negative
+const TIMESTAMP_OFFSET: u64 = 90_000;
+
+pub struct TsMuxer {
+    has_audio: bool,
+    // Indexed by PidIndex
+    continuity_counters: [u8; 4],
+}
+
+#[derive(Copy, Clone)]
+enum PidIndex {
+    Pat,
+    Pmt,
+    Video,
+    Audio,
+}
+
+impl PidIndex {
+    fn pid(&self) -> u16 {
+        match self {
+            PidIndex::Pat => PAT_PID,
+            PidIndex::Pmt => PMT_PID,
+            PidIndex::Video => VIDEO_PID,
+            PidIndex::Audio => AUDIO_PID,
+        }
+    }
+}
+
+impl TsMuxer {
+    pub fn new(has_audio: bool) -> Self {
+        Self {
+            has_audio,
+            continuity_counters: [0; 4],
+        }
+    }
+
+    /// Packetizes an Annex B sample, timestamped in 100ns units. The program
+    /// tables are repeated before every key frame, so that receivers can
+    /// join the stream at any key frame.
+    pub fn write_video(
+        &mut self,
+        time: i64,
+        decode_time: i64,
+        key_frame: bool,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut output = Vec::new();
+        if key_frame {
+            self.write_tables(&mut output);
+        }
+
+        let presentation_time = to_90khz(time);
+        let decode_time = to_90khz(decode_time);
+        let has_delimiter = split_nal_units(data)
+            .first()
+            .map(|unit| unit[0] & 0x1F == NAL_UNIT_TYPE_AUD)
+            .unwrap_or(false);
+        let mut payload = Vec::with_capacity(data.len() + ACCESS_UNIT_DELIMITER.len());
+        if !has_delimiter {
+            payload.extend_from_slice(&ACCESS_UNIT_DELIMITER);
+        }
+        payload.extend_from_slice(data);
+
+        let pes = create_pes_packet(
+            VIDEO_STREAM_ID,
+            presentation_time,
+            Some(decode_time),
+            &payload,
+        );
+        self.write_packets(
+            &mut output,
+            PidIndex::Video,
+            &pes,
+            Some(decode_time),
+            key_frame,
+        );
+        output
+    }
+
+    /// Packetizes one or more ADTS frames, timestamped in 100ns units.
+    pub fn write_audio(&mut self, time: i64, data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        if self.has_audio {
+            let pes = create_pes_packet(AUDIO_STREAM_ID, to_90khz(time), None, data);
+            self.write_packets(&mut output, PidIndex::Audio, &pes, None, false);
+        }
+        output
+    }
+
+    fn write_tables(&mut self, output: &mut Vec<u8>) {
+        let pat = create_pat();
+        self.write_section(output, PidIndex::Pat, &pat);
+        let pmt = create_pmt(self.has_audio);
+        self.write_section(output, PidIndex::Pmt, &pmt);
+    }
+
+    fn write_section(&mut self, output: &mut Vec<u8>, pid: PidIndex, section: &[u8]) {
+        let start = output.len();
+        self.write_header(output, pid, true, false);
+        // The pointer field, the section starts right after it
+        output.push(0);
+        output.extend_from_slice(section);
+        output.resize(start + TS_PACKET_SIZE, 0xFF);
+    }
+
+    fn write_packets(
+        &mut self,
+        output: &mut Vec<u8>,
+        pid: PidIndex,
+        pes: &[u8],
+        pcr: Option<u64>,
+        random_access: bool,
+    ) {
+        let mut offset = 0;
+        while offset < pes.len() {
+            let first = offset == 0;
+
+            // The first packet may carry the clock reference and the random
+            // access flag, the last one is padded with stuffing bytes
+            let mut adaptation_field = None;
+            if first && (pcr.is_some() || random_access) {
+                let mut field = vec![0u8];
+                if random_access {
+                    field[0] |= 0x40;
+                }
+                if let Some(pcr) = pcr {
+                    field[0] |= 0x10;
+                    field.extend_from_slice(&encode_pcr(pcr));
+                }
+                adaptation_field = Some(field);
+            }
+            let overhead =
+                |field: &Option<Vec<u8>>| field.as_ref().map_or(0, |field| field.len() + 1);
+            let available = TS_PACKET_SIZE - 4 - overhead(&adaptation_field);
+            let remaining = pes.len() - offset;
+            if remaining < available {
+                let mut stuffing = available - remaining;
+                if adaptation_field.is_none() {
+                    // The length byte is the first byte of stuffing
+                    adaptation_field = Some(Vec::new());
+                    stuffing -= 1;
+                }
+                let field = adaptation_field.as_mut().unwrap();
+                if field.is_empty() && stuffing > 0 {
+                    // The flags byte
+                    field.push(0);
+                    stuffing -= 1;
+                }
+                field.resize(field.len() + stuffing, 0xFF);
+            }
+            let payload_size = TS_PACKET_SIZE - 4 - overhead(&adaptation_field);
+
+            self.write_header(output, pid, first, adaptation_field.is_some());
+            if let Some(field) = adaptation_field {
+                output.push(field.len() as u8);
+                output.extend_from_slice(&field);
+            }
+            output.extend_from_slice(&pes[offset..offset + payload_size]);
+            offset += payload_size;
+        }
+    }
+
+    fn write_header(
+        &mut self,
+        output: &mut Vec<u8>,
+        pid: PidIndex,
+        payload_unit_start: bool,
+        has_adaptation_field: bool,
+    ) {
+        let counter = &mut self.continuity_counters[pid as usize];
+        let continuity_counter = *counter;
+        *counter = (*counter + 1) & 0xF;
+        let pid = pid.pid();
+        let adaptation_field_control = if has_adaptation_field { 0x30 } else { 0x10 };
+        output.extend_from_slice(&[
+            SYNC_BYTE,
+            ((payload_unit_start as u8) << 6) | (pid >> 8) as u8 & 0x1F,
+            pid as u8,
+            adaptation_field_control | continuity_counter,
+        ]);
+    }
+}
+
+fn create_pat() -> Vec<u8> {
+    let mut pat = Vec::new();
+    pat.extend_from_slice(&PROGRAM_NUMBER.to_be_bytes());
+    pat.extend_from_slice(&(0xE000 | PMT_PID).to_be_bytes());
+    create_section(0x00, 1, &pat)
+}
+
+fn create_pmt(has_audio: bool) -> Vec<u8> {
+    let mut pmt = Vec::new();
+    // The video stream carries the clock reference
+    pmt.extend_from_slice(&(0xE000 | VIDEO_PID).to_be_bytes());
+    // No program descriptors
+    pmt.extend_from_slice(&0xF000u16.to_be_bytes());
+    let mut streams = vec![(STREAM_TYPE_H264, VIDEO_PID)];
+    if has_audio {
+        streams.push((STREAM_TYPE_AAC, AUDIO_PID));
+    }
+    for (stream_type, pid) in streams {
+        pmt.push(stream_type);
+        pmt.extend_from_slice(&(0xE000 | pid).to_be_bytes());
+        pmt.extend_from_slice(&0xF000u16.to_be_bytes());
+    }
+    create_section(0x02, PROGRAM_NUMBER, &pmt)
+}
+
+fn create_section(table_id: u8, table_id_extension: u16, data: &[u8]) -> Vec<u8> {
+    // The length counts everything after it, including the CRC
+    let length = 5 + data.len() + 4;
+    let mut section = vec![table_id];
+    section.extend_from_slice(&(0xB000 | length as u16).to_be_bytes());
+    section.extend_from_slice(&table_id_extension.to_be_bytes());
+    // Version 0, current, section 0 of 0
+    section.extend_from_slice(&[0xC1, 0, 0]);
+    section.extend_from_slice(data);
+    let crc = crc32(&section);
+    section.extend_from_slice(&crc.to_be_bytes());
+    section
+}
+
+fn create_pes_packet(
+    stream_id: u8,
+    presentation_time: u64,
+    decode_time: Option<u64>,
+    data: &[u8],
+) -> Vec<u8> {
+    let decode_time = decode_time.filter(|decode_time| *decode_time != presentation_time);
+    let mut header = Vec::new();
+    match decode_time {
+        Some(decode_time) => {
+            header.extend_from_slice(&encode_timestamp(0x3, presentation_time));
+            header.extend_from_slice(&encode_timestamp(0x1, decode_time));
+        }
+        None => header.extend_from_slice(&encode_timestamp(0x2, presentation_time)),
+    }
+
+    // Video packets are often too big for the length field, zero means unbounded
+    let length = 3 + header.len() + data.len();
+    let length = if stream_id == VIDEO_STREAM_ID || length > u16::MAX as usize {
+        0
+    } else {
+        length as u16
+    };
+    let mut pes = vec![0, 0, 1, stream_id];
+    pes.extend_from_slice(&length.to_be_bytes());
+    pes.push(0x80);
+    pes.push(if decode_time.is_some() { 0xC0 } else { 0x80 });
+    pes.push(header.len() as u8);
+    pes.extend_from_slice(&header);
+    pes.extend_from_slice(data);
+    pes
+}
+
+fn encode_timestamp(prefix: u8, timestamp: u64) -> [u8; 5] {
+    [
+        (prefix << 4) | ((timestamp >> 29) as u8 & 0x0E) | 1,
+        (timestamp >> 22) as u8,
+        ((timestamp >> 14) as u8 & 0xFE) | 1,
+        (timestamp >> 7) as u8,
+        ((timestamp << 1) as u8 & 0xFE) | 1,
+    ]
+}
+
+// The 27MHz extension is always zero, the base is in 90kHz units
+fn encode_pcr(base: u64) -> [u8; 6] {
+    [
+        (base >> 25) as u8,
+        (base >> 17) as u8,
+        (base >> 9) as u8,
+        (base >> 1) as u8,
+        ((base as u8 & 0x1) << 7) | 0x7E,
+        0,
+    ]
+}
+
+// Converts from 100ns units, timestamps wrap around after 33 bits
+fn to_90khz(time: i64) -> u64 {
+    ((time.max(0) as u64 * 9 / 1000) + TIMESTAMP_OFFSET) & 0x1_FFFF_FFFF
+}
+
+// The CRC used by MPEG-2 sections, which isn't reflected and has no final XOR
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for byte in data {
+        crc ^= (*byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x80000000 != 0 {
+                (crc << 1) ^ 0x04C11DB7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{create_pat, to_90khz, TsMuxer, TS_PACKET_SIZE};
+
+    #[test]
+    fn pat_test() {
+        assert_eq!(
+            create_pat(),
+            [
+                0x00, 0xB0, 0x0D, 0x00, 0x01, 0xC1, 0x00, 0x00, 0x00, 0x01, 0xF0, 0x00, 0x2A, 0xB1,
+                0x04, 0xB2
+            ]
+        );
+    }
+
+    #[test]
+    fn packetizing_test() {
+        let mut muxer = TsMuxer::new(true);
+
+        // A key frame big enough to be split across packets, after the PAT and PMT
+        let frame = [&[0, 0, 0, 1, 0x65][..], &[0xAB; 400]].concat();
+        let output = muxer.write_video(0, 0, true, &frame);
+        assert_eq!(output.len() % TS_PACKET_SIZE, 0);
+        let packets: Vec<_> = output.chunks(TS_PACKET_SIZE).collect();
+        assert_eq!(packets.len(), 5);
+        assert!(packets.iter().all(|packet| packet[0] == 0x47));
+        // Video PID with the payload unit start flag and an adaptation field
+        assert_eq!(&packets[2][1..4], &[0x41, 0x00, 0x30]);
+        // Random access and PCR flags
+        assert_eq!(packets[2][5], 0x50);
+        // The continuity counter goes up for each packet of the stream
+        assert_eq!(packets[3][3] & 0xF, 1);
+        assert_eq!(packets[3][1] & 0x40, 0);
+
+        let output = muxer.write_audio(0, &[0x11; 20]);
+        assert_eq!(output.len(), TS_PACKET_SIZE);
+        assert_eq!(&output[..3], &[0x47, 0x41, 0x01]);
+        assert!(output.ends_with(&[0x11; 20]));
+    }
+
+    #[test]
+    fn timestamp_test() {
+        assert_eq!(to_90khz(0), 90_000);
+        assert_eq!(to_90khz(10_000_000), 180_000);
+    }
+}
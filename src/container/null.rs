@@ -0,0 +1,37 @@
+use windows::{
+    core::Result,
+    Win32::Media::MediaFoundation::{IMFMediaType, IMFSample},
+};
+
+use super::ContainerWriter;
+
+/// Discards every sample, for recordings that only go somewhere other than
+/// the sample writer (e.g. NDI).
+pub struct NullWriter {
+    stream_count: u32,
+}
+
+impl NullWriter {
+    pub fn new() -> Self {
+        Self { stream_count: 0 }
+    }
+}
+
+impl ContainerWriter for NullWriter {
+    fn add_stream(&mut self, _: &IMFMediaType, _: &IMFMediaType) -> Result<u32> {
+        self.stream_count += 1;
+        Ok(self.stream_count - 1)
+    }
+
+    fn start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_sample(&mut self, _: u32, _: &IMFSample) -> Result<()> {
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use std::mem::ManuallyDrop;
+
+use windows::{
+    core::{ComInterface, Interface, Result, HSTRING, PCWSTR, PWSTR},
+    Win32::{
+        Media::MediaFoundation::{
+            IMFAttributes, IMFByteStream, IMFMediaType, IMFSample, IMFSinkWriter,
+            MFCreateAttributes, MFCreateSinkWriterFromURL, MF_PROPERTY_HANDLER_SERVICE,
+            MF_SINK_WRITER_MEDIASINK,
+        },
+        Storage::EnhancedStorage::{PKEY_Author, PKEY_Comment, PKEY_Title},
+        System::{
+            Com::StructuredStorage::{
+                InitPropVariantFromStringVector, PropVariantClear, PROPVARIANT, PROPVARIANT_0,
+                PROPVARIANT_0_0, PROPVARIANT_0_0_0,
+            },
+            Variant::VT_LPWSTR,
+        },
+        UI::Shell::PropertiesSystem::{IPropertyStore, PROPERTYKEY},
+    },
+};
+
+use super::{Container, ContainerWriter, Metadata};
+
+/// Uses the Media Foundation sink writer, which picks the container based
+/// on the extension and loads any encoders that are needed.
+pub struct SinkWriter {
+    sink_writer: IMFSinkWriter,
+    empty_attributes: IMFAttributes,
+}
+
+unsafe impl Send for SinkWriter {}
+impl SinkWriter {
+    pub fn new(
+        byte_stream: &IMFByteStream,
+        container: Container,
+        metadata: &Metadata,
+    ) -> Result<Self> {
+        let empty_attributes = unsafe {
+            let mut attributes = None;
+            MFCreateAttributes(&mut attributes, 0)?;
+            attributes.unwrap()
+        };
+        let url = HSTRING::from(format!(".{}", container.extension()));
+        let sink_writer =
+            unsafe { MFCreateSinkWriterFromURL(&url, byte_stream, &empty_attributes)? };
+        if !metadata.is_empty() {
+            set_metadata(&sink_writer, metadata)?;
+        }
+        Ok(Self {
+            sink_writer,
+            empty_attributes,
+        })
+    }
+}
+
+impl ContainerWriter for SinkWriter {
+    fn add_stream(&mut self, output_type: &IMFMediaType, input_type: &IMFMediaType) -> Result<u32> {
+        unsafe {
+            let stream_index = self.sink_writer.AddStream(output_type)?;
+            self.sink_writer
+                .SetInputMediaType(stream_index, input_type, &self.empty_attributes)?;
+            Ok(stream_index)
+        }
+    }
+
+    fn start(&mut self) -> Result<()> {
+        unsafe { self.sink_writer.BeginWriting() }
+    }
+
+    fn write_sample(&mut self, stream_index: u32, sample: &IMFSample) -> Result<()> {
+        unsafe { self.sink_writer.WriteSample(stream_index, sample) }
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        unsafe { self.sink_writer.Finalize() }
+    }
+}
+
+// The media sink keeps the properties and writes them when it's finalized
+fn set_metadata(sink_writer: &IMFSinkWriter, metadata: &Metadata) -> Result<()> {
+    let property_store: IPropertyStore = unsafe {
+        let mut property_store = std::ptr::null_mut();
+        sink_writer.GetServiceForStream(
+            MF_SINK_WRITER_MEDIASINK.0,
+            &MF_PROPERTY_HANDLER_SERVICE,
+            &IPropertyStore::IID,
+            &mut property_store,
+        )?;
+        IPropertyStore::from_raw(property_store)
+    };
+    if let Some(title) = &metadata.title {
+        set_string(&property_store, &PKEY_Title, title)?;
+    }
+    if let Some(comment) = &metadata.comment {
+        set_string(&property_store, &PKEY_Comment, comment)?;
+    }
+    if let Some(author) = &metadata.author {
+        // Authors are a list of strings
+        let author = HSTRING::from(author.as_str());
+        unsafe {
+            let mut value = InitPropVariantFromStringVector(Some(&[PCWSTR(author.as_ptr())]))?;
+            let result = property_store.SetValue(&PKEY_Author, &value);
+            PropVariantClear(&mut value)?;
+            result?;
+        }
+    }
+    unsafe { property_store.Commit() }
+}
+
+fn set_string(property_store: &IPropertyStore, key: &PROPERTYKEY, value: &str) -> Result<()> {
+    let mut string: Vec<u16> = value.encode_utf16().chain([0]).collect();
+    // The property store copies the string
+    let value = PROPVARIANT {
+        Anonymous: PROPVARIANT_0 {
+            Anonymous: ManuallyDrop::new(PROPVARIANT_0_0 {
+                vt: VT_LPWSTR,
+                Anonymous: PROPVARIANT_0_0_0 {
+                    pwszVal: PWSTR(string.as_mut_ptr()),
+                },
+                ..Default::default()
+            }),
+        },
+    };
+    unsafe { property_store.SetValue(key, &value) }
+}
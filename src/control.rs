@@ -0,0 +1,306 @@
+use std::{
+    sync::mpsc::{channel, Receiver, Sender},
+    time::Duration,
+};
+
+use displayrecorder::RecorderError;
+use windows::{
+    core::{Error, Result, HSTRING},
+    Win32::{
+        Foundation::{CloseHandle, ERROR_PIPE_CONNECTED, E_INVALIDARG, HANDLE},
+        Storage::FileSystem::{
+            ReadFile, WriteFile, FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX,
+        },
+        System::Pipes::{
+            ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PIPE_READMODE_BYTE,
+            PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_WAIT,
+        },
+    },
+};
+
+use crate::{
+    http::{self, MAX_BODY_SIZE},
+    json::JsonValue,
+};
+
+const NAMED_PIPE_PREFIX: &str = r"\\.\pipe\";
+const BUFFER_SIZE: u32 = 4096;
+
+// See the JSON-RPC 2.0 specification
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+// Reserved for implementation-defined server errors
+pub const SERVER_ERROR: i64 = -32000;
+
+/// A method call received by the control server. The client waits until
+/// it's responded to.
+pub struct ControlRequest {
+    pub method: String,
+    pub params: JsonValue,
+    responder: Sender<std::result::Result<JsonValue, ControlError>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ControlError {
+    code: i64,
+    message: String,
+}
+
+/// Collects the requests of other programs (e.g. test automation or a
+/// dashboard) that drive the recording, from any number of listeners. Each
+/// listener serves its clients on dedicated threads, which live for as long
+/// as the process.
+pub struct ControlServer {
+    sender: Sender<ControlRequest>,
+    receiver: Receiver<ControlRequest>,
+}
+
+impl ControlRequest {
+    pub fn respond(self, result: std::result::Result<JsonValue, ControlError>) {
+        // The client may have disconnected in the meantime
+        let _ = self.responder.send(result);
+    }
+
+    /// A string parameter, which is optional.
+    pub fn string_param(&self, name: &str) -> std::result::Result<Option<String>, ControlError> {
+        match self.params.get(name) {
+            None | Some(JsonValue::Null) => Ok(None),
+            Some(JsonValue::String(value)) => Ok(Some(value.clone())),
+            Some(_) => Err(ControlError::invalid_params(format!(
+                "The {} parameter must be a string!",
+                name
+            ))),
+        }
+    }
+}
+
+impl ControlError {
+    fn new<S: Into<String>>(code: i64, message: S) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn code(&self) -> i64 {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn invalid_params<S: Into<String>>(message: S) -> Self {
+        Self::new(INVALID_PARAMS, message)
+    }
+
+    pub fn method_not_found(method: &str) -> Self {
+        Self::new(METHOD_NOT_FOUND, format!("Unknown method \"{}\"!", method))
+    }
+
+    /// The command couldn't be carried out, e.g. stopping a recording that
+    /// hasn't started.
+    pub fn failed<S: Into<String>>(message: S) -> Self {
+        Self::new(SERVER_ERROR, message)
+    }
+}
+
+impl From<Error> for ControlError {
+    fn from(error: Error) -> Self {
+        Self::failed(error.message().to_string())
+    }
+}
+
+impl From<RecorderError> for ControlError {
+    fn from(error: RecorderError) -> Self {
+        Self::failed(error.to_string())
+    }
+}
+
+impl ControlServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accepts JSON-RPC 2.0 requests on a named pipe, one per line. Clients
+    /// are served one at a time.
+    pub fn listen_on_pipe(&self, name: &str) -> Result<()> {
+        if !name.to_lowercase().starts_with(NAMED_PIPE_PREFIX) {
+            return Err(Error::new(
+                E_INVALIDARG,
+                r"The control pipe must be named like \\.\pipe\name!".into(),
+            ));
+        }
+        // Created up front so that a name that's already taken is reported
+        // right away. Only local clients can connect, not ones over SMB.
+        let handle = unsafe {
+            CreateNamedPipeW(
+                &HSTRING::from(name),
+                PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                1,
+                BUFFER_SIZE,
+                BUFFER_SIZE,
+                0,
+                None,
+            )
+        };
+        if handle.is_invalid() {
+            return Err(Error::from_win32());
+        }
+
+        let sender = self.sender.clone();
+        let pipe = Pipe(handle);
+        std::thread::spawn(move || {
+            let pipe = pipe;
+            loop {
+                let connected = unsafe { ConnectNamedPipe(pipe.0, None) };
+                match connected {
+                    Err(error) if error.code() != ERROR_PIPE_CONNECTED.to_hresult() => return,
+                    _ => {}
+                }
+                serve_client(pipe.0, &sender);
+                unsafe {
+                    let _ = DisconnectNamedPipe(pipe.0);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Accepts HTTP requests on a TCP address (e.g. 127.0.0.1:8080), where
+    /// each path names a method, e.g. POST /start or GET /status. Returns the
+    /// token that requests have to send (as Authorization: Bearer <token>),
+    /// POSTs also need a JSON Content-Type.
+    pub fn listen_on_http(&self, address: &str) -> Result<String> {
+        http::listen(address, self.sender.clone())
+    }
+
+    /// Blocks until a request arrives, or gives up after the timeout.
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<ControlRequest> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+}
+
+impl Default for ControlServer {
+    fn default() -> Self {
+        let (sender, receiver) = channel();
+        Self { sender, receiver }
+    }
+}
+
+struct Pipe(HANDLE);
+
+unsafe impl Send for Pipe {}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = CloseHandle(self.0);
+        }
+    }
+}
+
+// Handles requests until the client disconnects
+fn serve_client(handle: HANDLE, sender: &Sender<ControlRequest>) {
+    let mut pending = Vec::new();
+    let mut buffer = [0u8; BUFFER_SIZE as usize];
+    loop {
+        let mut read = 0;
+        let result = unsafe { ReadFile(handle, Some(&mut buffer), Some(&mut read), None) };
+        if result.is_err() || read == 0 {
+            return;
+        }
+        pending.extend_from_slice(&buffer[..read as usize]);
+        while let Some(end) = pending.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(response) = handle_message(line.trim(), sender) {
+                let response = format!("{}\n", response);
+                if unsafe { WriteFile(handle, Some(response.as_bytes()), None, None) }.is_err() {
+                    return;
+                }
+            }
+        }
+        // A line that never ends is dropped along with the client
+        if pending.len() > MAX_BODY_SIZE {
+            return;
+        }
+    }
+}
+
+// Returns the response to send, notifications (requests without an id) don't get one
+fn handle_message(message: &str, sender: &Sender<ControlRequest>) -> Option<JsonValue> {
+    let request = match JsonValue::parse(message) {
+        Some(request) => request,
+        None => {
+            return Some(create_response(
+                JsonValue::Null,
+                Err(ControlError::new(PARSE_ERROR, "Parse error")),
+            ))
+        }
+    };
+    let id = request.get("id").cloned();
+    let method = match request.get("method").and_then(|method| method.as_str()) {
+        Some(method) => method.to_owned(),
+        None => {
+            return Some(create_response(
+                id.unwrap_or(JsonValue::Null),
+                Err(ControlError::new(INVALID_REQUEST, "Invalid request")),
+            ))
+        }
+    };
+
+    let params = request.get("params").cloned().unwrap_or(JsonValue::Null);
+    let result = call(sender, method, params);
+    id.map(|id| create_response(id, result))
+}
+
+/// Hands a request to the recorder and waits for its response.
+pub fn call(
+    sender: &Sender<ControlRequest>,
+    method: String,
+    params: JsonValue,
+) -> std::result::Result<JsonValue, ControlError> {
+    let (responder, response) = channel();
+    let request = ControlRequest {
+        method,
+        params,
+        responder,
+    };
+    if sender.send(request).is_err() {
+        return Err(ControlError::failed("The recorder exited!"));
+    }
+    response
+        .recv()
+        .unwrap_or_else(|_| Err(ControlError::failed("The recorder exited!")))
+}
+
+fn create_response(
+    id: JsonValue,
+    result: std::result::Result<JsonValue, ControlError>,
+) -> JsonValue {
+    match result {
+        Ok(result) => JsonValue::object([
+            ("jsonrpc", JsonValue::string("2.0")),
+            ("id", id),
+            ("result", result),
+        ]),
+        Err(error) => JsonValue::object([
+            ("jsonrpc", JsonValue::string("2.0")),
+            ("id", id),
+            (
+                "error",
+                JsonValue::object([
+                    ("code", JsonValue::Number(error.code as f64)),
+                    ("message", JsonValue::String(error.message)),
+                ]),
+            ),
+        ]),
+    }
+}
@@ -2,25 +2,31 @@ use windows::core::{ComInterface, Interface, Result};
 use windows::Graphics::DirectX::Direct3D11::IDirect3DDevice;
 use windows::Win32::Graphics::Direct3D11::D3D11_CREATE_DEVICE_DEBUG;
 use windows::Win32::Graphics::{
-    Direct3D::{D3D_DRIVER_TYPE, D3D_DRIVER_TYPE_HARDWARE, D3D_DRIVER_TYPE_WARP},
+    Direct3D::{
+        D3D_DRIVER_TYPE, D3D_DRIVER_TYPE_HARDWARE, D3D_DRIVER_TYPE_UNKNOWN, D3D_DRIVER_TYPE_WARP,
+    },
     Direct3D11::{
         D3D11CreateDevice, ID3D11Device, D3D11_CREATE_DEVICE_BGRA_SUPPORT,
         D3D11_CREATE_DEVICE_FLAG, D3D11_SDK_VERSION,
     },
-    Dxgi::{IDXGIDevice, DXGI_ERROR_UNSUPPORTED},
+    Dxgi::{
+        CreateDXGIFactory1, IDXGIAdapter, IDXGIDevice, IDXGIFactory4, DXGI_ADAPTER_DESC,
+        DXGI_ERROR_UNSUPPORTED,
+    },
 };
 use windows::Win32::System::WinRT::Direct3D11::{
     CreateDirect3D11DeviceFromDXGIDevice, IDirect3DDxgiInterfaceAccess,
 };
 
 fn create_d3d_device_with_type(
+    adapter: Option<&IDXGIAdapter>,
     driver_type: D3D_DRIVER_TYPE,
     flags: D3D11_CREATE_DEVICE_FLAG,
     device: *mut Option<ID3D11Device>,
 ) -> Result<()> {
     unsafe {
         D3D11CreateDevice(
-            None,
+            adapter,
             driver_type,
             None,
             flags,
@@ -33,25 +39,59 @@ fn create_d3d_device_with_type(
     }
 }
 
+fn get_device_flags() -> D3D11_CREATE_DEVICE_FLAG {
+    let mut flags = D3D11_CREATE_DEVICE_BGRA_SUPPORT;
+    if cfg!(feature = "d3ddebug") {
+        flags |= D3D11_CREATE_DEVICE_DEBUG;
+    }
+    flags
+}
+
 pub fn create_d3d_device() -> Result<ID3D11Device> {
     let mut device = None;
-    let flags = {
-        let mut flags = D3D11_CREATE_DEVICE_BGRA_SUPPORT;
-        if cfg!(feature = "d3ddebug") {
-            flags |= D3D11_CREATE_DEVICE_DEBUG;
-        }
-        flags
-    };
-    let mut result = create_d3d_device_with_type(D3D_DRIVER_TYPE_HARDWARE, flags, &mut device);
+    let flags = get_device_flags();
+    let mut result =
+        create_d3d_device_with_type(None, D3D_DRIVER_TYPE_HARDWARE, flags, &mut device);
     if let Err(error) = &result {
         if error.code() == DXGI_ERROR_UNSUPPORTED {
-            result = create_d3d_device_with_type(D3D_DRIVER_TYPE_WARP, flags, &mut device);
+            result = create_d3d_device_with_type(None, D3D_DRIVER_TYPE_WARP, flags, &mut device);
         }
     }
     result?;
     Ok(device.unwrap())
 }
 
+/// Creates the device on a specific GPU, without falling back to WARP.
+pub fn create_d3d_device_on_adapter(adapter: &IDXGIAdapter) -> Result<ID3D11Device> {
+    let mut device = None;
+    // The driver type has to be unknown when an adapter is provided
+    create_d3d_device_with_type(
+        Some(adapter),
+        D3D_DRIVER_TYPE_UNKNOWN,
+        get_device_flags(),
+        &mut device,
+    )?;
+    Ok(device.unwrap())
+}
+
+/// Creates a device to replace one that was lost (e.g. to a driver reset),
+/// on the same GPU if it's still there.
+pub fn recreate_d3d_device(d3d_device: &ID3D11Device) -> Result<ID3D11Device> {
+    let adapter = (|| -> Result<IDXGIAdapter> {
+        unsafe {
+            let dxgi_device: IDXGIDevice = d3d_device.cast()?;
+            let mut desc = DXGI_ADAPTER_DESC::default();
+            dxgi_device.GetAdapter()?.GetDesc(&mut desc)?;
+            let factory: IDXGIFactory4 = CreateDXGIFactory1()?;
+            factory.EnumAdapterByLuid(desc.AdapterLuid)
+        }
+    })();
+    match adapter {
+        Ok(adapter) => create_d3d_device_on_adapter(&adapter),
+        Err(_) => create_d3d_device(),
+    }
+}
+
 pub fn create_direct3d_device(d3d_device: &ID3D11Device) -> Result<IDirect3DDevice> {
     let dxgi_device: IDXGIDevice = d3d_device.cast()?;
     let inspectable = unsafe { CreateDirect3D11DeviceFromDXGIDevice(Some(&dxgi_device))? };
@@ -0,0 +1,55 @@
+use windows::Win32::{
+    Foundation::HANDLE,
+    System::StationsAndDesktops::{
+        CloseDesktop, GetUserObjectInformationW, OpenInputDesktop, DESKTOP_CONTROL_FLAGS,
+        DESKTOP_READOBJECTS, UOI_NAME,
+    },
+};
+
+// The desktop that apps (and the capture) run on
+const DEFAULT_DESKTOP_NAME: &str = "Default";
+
+/// Whether the input desktop is a secure one (e.g. for a UAC prompt or the
+/// lock screen), which can't be captured. Secure desktops can't be opened
+/// from the user's session, so failing to open it counts as secure too.
+pub fn is_secure_desktop_shown() -> bool {
+    unsafe {
+        let desktop = match OpenInputDesktop(DESKTOP_CONTROL_FLAGS(0), false, DESKTOP_READOBJECTS) {
+            Ok(desktop) => desktop,
+            Err(_) => return true,
+        };
+        let mut name = [0u16; 64];
+        let result = GetUserObjectInformationW(
+            HANDLE(desktop.0),
+            UOI_NAME,
+            Some(name.as_mut_ptr() as *mut _),
+            std::mem::size_of_val(&name) as u32,
+            None,
+        );
+        let _ = CloseDesktop(desktop);
+        match result {
+            Ok(()) => {
+                let length = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+                is_secure_desktop_name(&String::from_utf16_lossy(&name[..length]))
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+fn is_secure_desktop_name(name: &str) -> bool {
+    !name.eq_ignore_ascii_case(DEFAULT_DESKTOP_NAME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_secure_desktop_name;
+
+    #[test]
+    fn secure_desktop_name_test() {
+        assert!(!is_secure_desktop_name("Default"));
+        assert!(!is_secure_desktop_name("default"));
+        assert!(is_secure_desktop_name("Winlogon"));
+        assert!(is_secure_desktop_name("Screen-saver"));
+    }
+}
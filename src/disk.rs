@@ -0,0 +1,17 @@
+use std::path::Path;
+
+use windows::{core::HSTRING, Win32::Storage::FileSystem::GetDiskFreeSpaceExW};
+
+/// The space left on the volume that the file is (or will be) on, which is
+/// what's available to the current user. None if it can't be determined,
+/// e.g. for pipes.
+pub fn get_free_space<P: AsRef<Path>>(path: P) -> Option<u64> {
+    let folder = match path.as_ref().parent() {
+        Some(folder) if !folder.as_os_str().is_empty() => folder,
+        _ => Path::new("."),
+    };
+    let folder = HSTRING::from(folder.to_str()?);
+    let mut free_bytes = 0;
+    unsafe { GetDiskFreeSpaceExW(&folder, Some(&mut free_bytes), None, None).ok()? };
+    Some(free_bytes)
+}
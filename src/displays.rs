@@ -1,13 +1,261 @@
-use windows::Win32::{
-    Foundation::{BOOL, LPARAM, RECT},
-    Graphics::Gdi::{EnumDisplayMonitors, HDC, HMONITOR},
+use std::{fmt::Display, str::FromStr};
+
+use windows::{
+    core::{ComInterface, HSTRING},
+    Graphics::RectInt32,
+    Win32::{
+        Devices::Display::{
+            DisplayConfigGetDeviceInfo, GetDisplayConfigBufferSizes, QueryDisplayConfig,
+            DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME, DISPLAYCONFIG_DEVICE_INFO_GET_TARGET_NAME,
+            DISPLAYCONFIG_DEVICE_INFO_HEADER, DISPLAYCONFIG_PATH_INFO,
+            DISPLAYCONFIG_SOURCE_DEVICE_NAME, DISPLAYCONFIG_TARGET_DEVICE_NAME,
+            QDC_ONLY_ACTIVE_PATHS,
+        },
+        Foundation::{BOOL, LPARAM, RECT},
+        Graphics::{
+            Dxgi::{
+                Common::DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020, CreateDXGIFactory1,
+                IDXGIFactory1, IDXGIOutput6, DXGI_OUTPUT_DESC1,
+            },
+            Gdi::{
+                EnumDisplayMonitors, EnumDisplaySettingsW, GetMonitorInfoW, DEVMODEW,
+                ENUM_CURRENT_SETTINGS, HDC, HMONITOR, MONITORINFO, MONITORINFOEXW,
+            },
+        },
+    },
 };
 
+/// A display that can be recorded, see DisplayInfo::enumerate.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DisplayInfo {
+    pub name: String,
+    /// Within the virtual desktop.
+    pub bounds: RectInt32,
+    /// In Hz, if Windows knows it.
+    pub refresh_rate: Option<u32>,
+    /// Whether HDR is turned on for the display.
+    pub hdr: bool,
+}
+
+impl DisplayInfo {
+    /// Lists the displays in the order of their indices (e.g. for --display).
+    pub fn enumerate() -> Vec<DisplayInfo> {
+        let hdr_displays = get_hdr_displays();
+        enumerate_displays()
+            .into_iter()
+            .filter_map(|display_handle| {
+                Some(DisplayInfo {
+                    name: get_display_name(display_handle)?,
+                    bounds: get_display_bounds(display_handle)?,
+                    refresh_rate: get_display_refresh_rate(display_handle),
+                    hdr: hdr_displays.contains(&display_handle),
+                })
+            })
+            .collect()
+    }
+}
+
 pub fn get_display_handle_from_index(index: usize) -> Option<HMONITOR> {
     let displays = enumerate_displays();
     displays.get(index).copied()
 }
 
+pub fn get_display_count() -> usize {
+    enumerate_displays().len()
+}
+
+/// Gets the bounds of the display within the virtual desktop.
+pub fn get_display_bounds(display_handle: HMONITOR) -> Option<RectInt32> {
+    let mut info = MONITORINFO {
+        cbSize: std::mem::size_of::<MONITORINFO>() as u32,
+        ..Default::default()
+    };
+    if unsafe { GetMonitorInfoW(display_handle, &mut info).as_bool() } {
+        let rect = info.rcMonitor;
+        Some(RectInt32 {
+            X: rect.left,
+            Y: rect.top,
+            Width: rect.right - rect.left,
+            Height: rect.bottom - rect.top,
+        })
+    } else {
+        None
+    }
+}
+
+/// Gets the name of the monitor (e.g. DELL U2720Q), or the name Windows
+/// gives the display (e.g. DISPLAY1) if the monitor doesn't report one.
+pub fn get_display_name(display_handle: HMONITOR) -> Option<String> {
+    let device_name = get_display_device_name(display_handle)?;
+    if let Some(name) = get_monitor_friendly_name(&device_name) {
+        return Some(name);
+    }
+    Some(device_name.trim_start_matches(r"\\.\").to_owned())
+}
+
+/// Gets the name Windows gives the display, e.g. \\.\DISPLAY1. Unlike the
+/// handle, the name stays the same when the display is reconnected.
+pub fn get_display_device_name(display_handle: HMONITOR) -> Option<String> {
+    let mut info = MONITORINFOEXW {
+        monitorInfo: MONITORINFO {
+            cbSize: std::mem::size_of::<MONITORINFOEXW>() as u32,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    if !unsafe { GetMonitorInfoW(display_handle, &mut info as *mut _ as *mut MONITORINFO) }
+        .as_bool()
+    {
+        return None;
+    }
+    Some(from_wide(&info.szDevice))
+}
+
+/// Finds the display with the name Windows gives it, see
+/// get_display_device_name.
+pub fn find_display_by_device_name(device_name: &str) -> Option<HMONITOR> {
+    enumerate_displays().into_iter().find(|&display_handle| {
+        get_display_device_name(display_handle).as_deref() == Some(device_name)
+    })
+}
+
+/// Gets the refresh rate of the display (in Hz), rounded to the nearest
+/// whole rate (e.g. 60 for 59.94 Hz).
+pub fn get_display_refresh_rate(display_handle: HMONITOR) -> Option<u32> {
+    let device_name = get_display_device_name(display_handle)?;
+    // The display path has the exact rate, the display mode rounds it down
+    if let Some(path) = find_display_path(&device_name) {
+        let rate = path.targetInfo.refreshRate;
+        if let Some(refresh_rate) = round_refresh_rate(rate.Numerator, rate.Denominator) {
+            return Some(refresh_rate);
+        }
+    }
+    let mut mode = DEVMODEW {
+        dmSize: std::mem::size_of::<DEVMODEW>() as u16,
+        ..Default::default()
+    };
+    let found = unsafe {
+        EnumDisplaySettingsW(
+            &HSTRING::from(device_name),
+            ENUM_CURRENT_SETTINGS,
+            &mut mode,
+        )
+    };
+    // 0 and 1 mean the hardware's default rate
+    if found.as_bool() && mode.dmDisplayFrequency > 1 {
+        Some(mode.dmDisplayFrequency)
+    } else {
+        None
+    }
+}
+
+// The displays that use the HDR10 color space, i.e. that have HDR turned on
+fn get_hdr_displays() -> Vec<HMONITOR> {
+    let mut displays = Vec::new();
+    let factory: IDXGIFactory1 = match unsafe { CreateDXGIFactory1() } {
+        Ok(factory) => factory,
+        Err(_) => return displays,
+    };
+    let mut adapter_index = 0;
+    while let Ok(adapter) = unsafe { factory.EnumAdapters1(adapter_index) } {
+        adapter_index += 1;
+        let mut output_index = 0;
+        while let Ok(output) = unsafe { adapter.EnumOutputs(output_index) } {
+            output_index += 1;
+            let desc = output.cast::<IDXGIOutput6>().and_then(|output| unsafe {
+                let mut desc = DXGI_OUTPUT_DESC1::default();
+                output.GetDesc1(&mut desc)?;
+                Ok(desc)
+            });
+            if let Ok(desc) = desc {
+                if desc.ColorSpace == DXGI_COLOR_SPACE_RGB_FULL_G2084_NONE_P2020 {
+                    displays.push(desc.Monitor);
+                }
+            }
+        }
+    }
+    displays
+}
+
+// Rounds a refresh rate given as a fraction, 0 and 1 mean the hardware's
+// default rate
+fn round_refresh_rate(numerator: u32, denominator: u32) -> Option<u32> {
+    if denominator == 0 {
+        return None;
+    }
+    let refresh_rate = (numerator as u64 + denominator as u64 / 2) / denominator as u64;
+    if refresh_rate > 1 {
+        Some(refresh_rate as u32)
+    } else {
+        None
+    }
+}
+
+// Looks up the monitor connected to the display (e.g. \\.\DISPLAY1)
+fn get_monitor_friendly_name(device_name: &str) -> Option<String> {
+    let path = find_display_path(device_name)?;
+    let mut target_name = DISPLAYCONFIG_TARGET_DEVICE_NAME {
+        header: DISPLAYCONFIG_DEVICE_INFO_HEADER {
+            r#type: DISPLAYCONFIG_DEVICE_INFO_GET_TARGET_NAME,
+            size: std::mem::size_of::<DISPLAYCONFIG_TARGET_DEVICE_NAME>() as u32,
+            adapterId: path.targetInfo.adapterId,
+            id: path.targetInfo.id,
+        },
+        ..Default::default()
+    };
+    if unsafe { DisplayConfigGetDeviceInfo(&mut target_name.header) } != 0 {
+        return None;
+    }
+    let name = from_wide(&target_name.monitorFriendlyDeviceName);
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+// Finds the active path from the display (e.g. \\.\DISPLAY1) to its monitor
+fn find_display_path(device_name: &str) -> Option<DISPLAYCONFIG_PATH_INFO> {
+    let (mut path_count, mut mode_count) = (0, 0);
+    unsafe {
+        GetDisplayConfigBufferSizes(QDC_ONLY_ACTIVE_PATHS, &mut path_count, &mut mode_count)
+            .ok()?;
+    }
+    let mut paths = vec![Default::default(); path_count as usize];
+    let mut modes = vec![Default::default(); mode_count as usize];
+    unsafe {
+        QueryDisplayConfig(
+            QDC_ONLY_ACTIVE_PATHS,
+            &mut path_count,
+            paths.as_mut_ptr(),
+            &mut mode_count,
+            modes.as_mut_ptr(),
+            None,
+        )
+        .ok()?;
+    }
+    paths.truncate(path_count as usize);
+
+    paths.into_iter().find(|path| {
+        let mut source_name = DISPLAYCONFIG_SOURCE_DEVICE_NAME {
+            header: DISPLAYCONFIG_DEVICE_INFO_HEADER {
+                r#type: DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME,
+                size: std::mem::size_of::<DISPLAYCONFIG_SOURCE_DEVICE_NAME>() as u32,
+                adapterId: path.sourceInfo.adapterId,
+                id: path.sourceInfo.id,
+            },
+            ..Default::default()
+        };
+        let found = unsafe { DisplayConfigGetDeviceInfo(&mut source_name.header) };
+        found == 0 && from_wide(&source_name.viewGdiDeviceName) == device_name
+    })
+}
+
+fn from_wide(chars: &[u16]) -> String {
+    let length = chars.iter().position(|&c| c == 0).unwrap_or(chars.len());
+    String::from_utf16_lossy(&chars[..length])
+}
+
 fn enumerate_displays() -> Vec<HMONITOR> {
     unsafe {
         let displays = Box::into_raw(Box::default());
@@ -23,3 +271,98 @@ extern "system" fn enum_monitor(monitor: HMONITOR, _: HDC, _: *mut RECT, state:
     }
     true.into()
 }
+
+/// Which displays to record.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DisplaySelection {
+    All,
+    Index(usize),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseDisplaySelectionError(&'static str);
+
+impl FromStr for DisplaySelection {
+    type Err = ParseDisplaySelectionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "all" => Ok(DisplaySelection::All),
+            value => value.parse().map(DisplaySelection::Index).map_err(|_| {
+                ParseDisplaySelectionError(
+                    "Invalid display value! Expecting: a display index or all.",
+                )
+            }),
+        }
+    }
+}
+
+impl Display for DisplaySelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisplaySelection::All => write!(f, "all"),
+            DisplaySelection::Index(index) => write!(f, "{}", index),
+        }
+    }
+}
+
+impl Display for ParseDisplaySelectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseDisplaySelectionError {}
+
+/// Resolves a list of selections to a sorted list of unique display indices.
+pub fn resolve_display_indices(
+    selections: &[DisplaySelection],
+    display_count: usize,
+) -> Vec<usize> {
+    let mut indices: Vec<usize> = selections
+        .iter()
+        .flat_map(|selection| match selection {
+            DisplaySelection::All => (0..display_count).collect(),
+            DisplaySelection::Index(index) => vec![*index],
+        })
+        .collect();
+    indices.sort_unstable();
+    indices.dedup();
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve_display_indices, round_refresh_rate, DisplaySelection};
+
+    #[test]
+    fn display_selection_parsing_test() {
+        assert_eq!("all".parse(), Ok(DisplaySelection::All));
+        assert_eq!("ALL".parse(), Ok(DisplaySelection::All));
+        assert_eq!("2".parse(), Ok(DisplaySelection::Index(2)));
+        assert!("-1".parse::<DisplaySelection>().is_err());
+        assert!("primary".parse::<DisplaySelection>().is_err());
+    }
+
+    #[test]
+    fn display_index_resolution_test() {
+        use DisplaySelection::*;
+        assert_eq!(resolve_display_indices(&[Index(0)], 3), vec![0]);
+        assert_eq!(
+            resolve_display_indices(&[Index(2), Index(0)], 3),
+            vec![0, 2]
+        );
+        assert_eq!(resolve_display_indices(&[All], 3), vec![0, 1, 2]);
+        assert_eq!(resolve_display_indices(&[Index(1), All], 2), vec![0, 1]);
+    }
+
+    #[test]
+    fn refresh_rate_rounding_test() {
+        assert_eq!(round_refresh_rate(144, 1), Some(144));
+        assert_eq!(round_refresh_rate(59940, 1000), Some(60));
+        assert_eq!(round_refresh_rate(119_880, 1000), Some(120));
+        assert_eq!(round_refresh_rate(164_999, 1000), Some(165));
+        // The hardware's default rate
+        assert_eq!(round_refresh_rate(1, 1), None);
+        assert_eq!(round_refresh_rate(0, 0), None);
+    }
+}
@@ -0,0 +1,61 @@
+use std::{fmt::Display, time::Duration};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseDurationError(&'static str);
+
+/// Parses durations such as "500ms", "30s", "10min", or "2h". Values
+/// without a unit are treated as seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, ParseDurationError> {
+    const ERROR: ParseDurationError = ParseDurationError(
+        "Invalid duration value! Expecting a number followed by a unit: ms, s, min, or h (e.g. 30s).",
+    );
+    let value = value.trim().to_lowercase();
+    let unit_start = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(unit_start);
+    let number: f64 = number.parse().map_err(|_| ERROR)?;
+    let seconds_per_unit = match unit.trim() {
+        "ms" => 0.001,
+        "" | "s" | "sec" | "secs" => 1.0,
+        "m" | "min" | "mins" => 60.0,
+        "h" | "hr" | "hrs" => 3600.0,
+        _ => return Err(ERROR),
+    };
+    let duration = Duration::from_secs_f64(number * seconds_per_unit);
+    if duration.is_zero() {
+        Err(ERROR)
+    } else {
+        Ok(duration)
+    }
+}
+
+impl Display for ParseDurationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseDurationError {}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::parse_duration;
+
+    #[test]
+    fn duration_parsing_test() {
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("10min"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("1.5 s"), Ok(Duration::from_millis(1500)));
+
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("s").is_err());
+        assert!(parse_duration("10 parsecs").is_err());
+        assert!(parse_duration("-5s").is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use std::fmt::Display;
+
+use windows::{
+    core::{Error, HRESULT},
+    Win32::Foundation::E_INVALIDARG,
+};
+
+use crate::video::codec::VideoCodec;
+
+/// The part of a recording that failed, see RecorderError.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RecordingStage {
+    Setup,
+    Capture,
+    Encode,
+    Write,
+    Audio,
+}
+
+/// Why a recording couldn't be set up, or stopped early.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RecorderError {
+    /// The settings can't be recorded with (e.g. options that conflict, or
+    /// a display that doesn't exist). The message names the options.
+    InvalidSettings(String),
+    /// None of the encoders took the settings. The message explains why,
+    /// where that's known (e.g. a resolution that's too large).
+    Encoder {
+        codec: VideoCodec,
+        encoder: String,
+        message: String,
+    },
+    /// A call into Windows failed.
+    Windows {
+        stage: RecordingStage,
+        code: HRESULT,
+        message: String,
+    },
+}
+
+impl RecorderError {
+    /// Problems with the settings are reported before the recording starts,
+    /// so anything else that fails later is put down to the stage.
+    pub fn from_windows(stage: RecordingStage, error: Error) -> Self {
+        if stage == RecordingStage::Setup && error.code() == E_INVALIDARG {
+            return RecorderError::InvalidSettings(error.message().to_string());
+        }
+        RecorderError::Windows {
+            stage,
+            code: error.code(),
+            message: error.message().to_string(),
+        }
+    }
+
+    /// The HRESULT behind the error, if Windows reported one.
+    pub fn code(&self) -> Option<HRESULT> {
+        match self {
+            RecorderError::Windows { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+}
+
+/// Puts a failure down to the stage, e.g. when mapping the result of a thread.
+pub fn in_stage(stage: RecordingStage) -> impl Fn(Error) -> RecorderError {
+    move |error| RecorderError::from_windows(stage, error)
+}
+
+impl From<Error> for RecorderError {
+    fn from(error: Error) -> Self {
+        RecorderError::from_windows(RecordingStage::Setup, error)
+    }
+}
+
+impl Display for RecordingStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = match self {
+            RecordingStage::Setup => "setting up the recording",
+            RecordingStage::Capture => "capturing",
+            RecordingStage::Encode => "encoding",
+            RecordingStage::Write => "writing the recording",
+            RecordingStage::Audio => "recording audio",
+        };
+        write!(f, "{}", string)
+    }
+}
+
+impl Display for RecorderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecorderError::InvalidSettings(message) => write!(f, "{}", message),
+            RecorderError::Encoder { message, .. } => write!(f, "{}", message),
+            RecorderError::Windows {
+                stage,
+                code,
+                message,
+            } => write!(
+                f,
+                "Failed while {}: {} (0x{:08X})",
+                stage,
+                message.trim_end(),
+                code.0
+            ),
+        }
+    }
+}
+impl std::error::Error for RecorderError {}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::Graphics::Dxgi::DXGI_ERROR_DEVICE_REMOVED;
+
+    use super::{RecorderError, RecordingStage};
+
+    #[test]
+    fn recorder_error_test() {
+        // Messages about the settings are shown as they are
+        let error = RecorderError::InvalidSettings("Invalid region!".to_owned());
+        assert_eq!(error.to_string(), "Invalid region!");
+        assert_eq!(error.code(), None);
+
+        let error = RecorderError::Windows {
+            stage: RecordingStage::Encode,
+            code: DXGI_ERROR_DEVICE_REMOVED,
+            message: "The GPU was removed.\r\n".to_owned(),
+        };
+        assert_eq!(error.code(), Some(DXGI_ERROR_DEVICE_REMOVED));
+        assert_eq!(
+            error.to_string(),
+            "Failed while encoding: The GPU was removed. (0x887A0005)"
+        );
+    }
+}
@@ -0,0 +1,63 @@
+use std::sync::Once;
+
+use tracelogging as tlg;
+
+// Performance tools (e.g. WPR and WPA) find the provider by its name, or by
+// the GUID that's derived from it: 199de1a0-f2c6-5f09-10c7-eef954bb3572
+tlg::define_provider!(PROVIDER, "DisplayRecorder");
+
+static REGISTER: Once = Once::new();
+
+/// Registers the TraceLogging provider, so that the events below show up
+/// in traces. Nothing is written while no trace is listening.
+pub fn register_provider() {
+    REGISTER.call_once(|| {
+        // The provider stays registered for as long as the process, which
+        // is fine as long as this isn't used by a DLL that's unloaded
+        unsafe {
+            PROVIDER.register();
+        }
+    });
+}
+
+/// A frame arrived from the capture item, captured at the frame time (in
+/// 100ns units, QPC based).
+pub fn frame_captured(item_index: usize, frame_time: i64) {
+    let item_index = item_index as u32;
+    tlg::write_event!(
+        PROVIDER,
+        "FrameCaptured",
+        level(Verbose),
+        u32("ItemIndex", &item_index),
+        i64("FrameTime", &frame_time),
+    );
+}
+
+/// The encoder produced a sample for the frame at the timestamp (in 100ns
+/// units, relative to the timeline).
+pub fn frame_encoded(timestamp: i64, size: u32) {
+    tlg::write_event!(
+        PROVIDER,
+        "FrameEncoded",
+        level(Verbose),
+        i64("Timestamp", &timestamp),
+        u32("Size", &size),
+    );
+}
+
+/// A sample of the stream was handed to the file, stream, or replay buffer.
+pub fn sample_written(stream_index: u32, size: u64) {
+    tlg::write_event!(
+        PROVIDER,
+        "SampleWritten",
+        level(Verbose),
+        u32("StreamIndex", &stream_index),
+        u64("Size", &size),
+    );
+}
+
+/// How many samples wait for the encoder, after one was queued.
+pub fn queue_depth(depth: usize) {
+    let depth = depth as u32;
+    tlg::write_event!(PROVIDER, "QueueDepth", level(Verbose), u32("Depth", &depth),);
+}
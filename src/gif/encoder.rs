@@ -0,0 +1,233 @@
+use std::{collections::HashMap, io::Write};
+
+// Every frame gets its own 256 color palette, so the minimum code size is always 8 bits
+const MIN_CODE_SIZE: u8 = 8;
+const CLEAR_CODE: u16 = 1 << MIN_CODE_SIZE;
+const END_CODE: u16 = CLEAR_CODE + 1;
+const MAX_CODE: u16 = 4096;
+const MAX_SUB_BLOCK_LENGTH: usize = 255;
+
+/// Writes an animated GIF that loops forever.
+pub struct GifEncoder<W: Write> {
+    writer: W,
+    width: u16,
+    height: u16,
+}
+
+impl<W: Write> GifEncoder<W> {
+    pub fn new(mut writer: W, width: u16, height: u16) -> std::io::Result<Self> {
+        writer.write_all(b"GIF89a")?;
+
+        // Logical screen descriptor, without a global color table
+        writer.write_all(&width.to_le_bytes())?;
+        writer.write_all(&height.to_le_bytes())?;
+        writer.write_all(&[0, 0, 0])?;
+
+        // Netscape application extension, loop forever
+        writer.write_all(&[0x21, 0xFF, 11])?;
+        writer.write_all(b"NETSCAPE2.0")?;
+        writer.write_all(&[3, 1, 0, 0, 0])?;
+
+        Ok(Self {
+            writer,
+            width,
+            height,
+        })
+    }
+
+    /// Writes a frame of palette indices, which is shown for the provided
+    /// delay (in hundredths of a second).
+    pub fn write_frame(
+        &mut self,
+        palette: &[[u8; 3]],
+        indices: &[u8],
+        delay: u16,
+    ) -> std::io::Result<()> {
+        assert!(palette.len() <= 1 << MIN_CODE_SIZE);
+        assert_eq!(indices.len(), self.width as usize * self.height as usize);
+
+        // Graphic control extension
+        self.writer.write_all(&[0x21, 0xF9, 4, 0])?;
+        self.writer.write_all(&delay.to_le_bytes())?;
+        self.writer.write_all(&[0, 0])?;
+
+        // Image descriptor, with a local color table of 256 entries
+        self.writer.write_all(&[0x2C, 0, 0, 0, 0])?;
+        self.writer.write_all(&self.width.to_le_bytes())?;
+        self.writer.write_all(&self.height.to_le_bytes())?;
+        self.writer.write_all(&[0x80 | (MIN_CODE_SIZE - 1)])?;
+        for index in 0..(1 << MIN_CODE_SIZE) {
+            let color = palette.get(index).copied().unwrap_or_default();
+            self.writer.write_all(&color)?;
+        }
+
+        // Image data, split into sub-blocks
+        self.writer.write_all(&[MIN_CODE_SIZE])?;
+        let data = lzw_encode(indices);
+        for block in data.chunks(MAX_SUB_BLOCK_LENGTH) {
+            self.writer.write_all(&[block.len() as u8])?;
+            self.writer.write_all(block)?;
+        }
+        self.writer.write_all(&[0])
+    }
+
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.writer.write_all(&[0x3B])?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+struct BitWriter {
+    data: Vec<u8>,
+    buffer: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, code_size: u8) {
+        self.buffer |= (code as u32) << self.bits;
+        self.bits += code_size;
+        while self.bits >= 8 {
+            self.data.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.data.push(self.buffer as u8);
+        }
+        self.data
+    }
+}
+
+fn lzw_encode(indices: &[u8]) -> Vec<u8> {
+    let mut output = BitWriter {
+        data: Vec::new(),
+        buffer: 0,
+        bits: 0,
+    };
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next_code = END_CODE + 1;
+    let mut code_size = MIN_CODE_SIZE + 1;
+    output.write(CLEAR_CODE, code_size);
+
+    let mut indices = indices.iter();
+    let mut current = if let Some(index) = indices.next() {
+        *index as u16
+    } else {
+        output.write(END_CODE, code_size);
+        return output.finish();
+    };
+    for &index in indices {
+        if let Some(code) = table.get(&(current, index)) {
+            current = *code;
+            continue;
+        }
+        output.write(current, code_size);
+        if next_code == MAX_CODE {
+            // The table is full, start over
+            output.write(CLEAR_CODE, code_size);
+            table.clear();
+            next_code = END_CODE + 1;
+            code_size = MIN_CODE_SIZE + 1;
+        } else {
+            table.insert((current, index), next_code);
+            next_code += 1;
+            // The decoder adds its entries one code behind us
+            if next_code > (1 << code_size) && code_size < 12 {
+                code_size += 1;
+            }
+        }
+        current = index as u16;
+    }
+    output.write(current, code_size);
+    output.write(END_CODE, code_size);
+    output.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lzw_encode, GifEncoder, CLEAR_CODE, END_CODE, MIN_CODE_SIZE};
+
+    fn lzw_decode(data: &[u8]) -> Vec<u8> {
+        let mut reader = data
+            .iter()
+            .flat_map(|byte| (0..8).map(move |bit| (byte >> bit) & 1));
+        let mut read = |code_size: u8| -> Option<u16> {
+            let mut code = 0;
+            for bit in 0..code_size {
+                code |= (reader.next()? as u16) << bit;
+            }
+            Some(code)
+        };
+
+        let mut output = Vec::new();
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut code_size = MIN_CODE_SIZE + 1;
+        let mut previous: Option<Vec<u8>> = None;
+        while let Some(code) = read(code_size) {
+            if code == CLEAR_CODE {
+                table = (0..CLEAR_CODE).map(|index| vec![index as u8]).collect();
+                table.push(Vec::new());
+                table.push(Vec::new());
+                code_size = MIN_CODE_SIZE + 1;
+                previous = None;
+                continue;
+            }
+            if code == END_CODE {
+                break;
+            }
+            let entry = if (code as usize) < table.len() {
+                table[code as usize].clone()
+            } else {
+                let mut entry = previous.clone().unwrap();
+                entry.push(entry[0]);
+                entry
+            };
+            output.extend_from_slice(&entry);
+            if let Some(mut previous) = previous {
+                if table.len() < 4096 {
+                    previous.push(entry[0]);
+                    table.push(previous);
+                    if table.len() == (1 << code_size) && code_size < 12 {
+                        code_size += 1;
+                    }
+                }
+            }
+            previous = Some(entry);
+        }
+        output
+    }
+
+    #[test]
+    fn lzw_round_trip_test() {
+        let inputs: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![7],
+            vec![1, 1, 1, 1, 1, 1, 1, 1],
+            (0..=255).collect(),
+            // Enough unique sequences to fill the table and force a reset
+            (0..20000u32)
+                .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+                .collect(),
+        ];
+        for input in inputs {
+            assert_eq!(lzw_decode(&lzw_encode(&input)), input);
+        }
+    }
+
+    #[test]
+    fn gif_structure_test() {
+        let mut encoder = GifEncoder::new(Vec::new(), 2, 1).unwrap();
+        encoder
+            .write_frame(&[[0, 0, 0], [255, 255, 255]], &[0, 1], 10)
+            .unwrap();
+        let data = encoder.finish().unwrap();
+        assert_eq!(&data[..6], b"GIF89a");
+        assert_eq!(&data[6..10], &[2, 0, 1, 0]);
+        assert_eq!(*data.last().unwrap(), 0x3B);
+    }
+}
@@ -0,0 +1,262 @@
+use std::{fs::File, io::BufWriter, path::Path, thread::JoinHandle};
+
+use tracing::error;
+use windows::{
+    core::Result,
+    Graphics::{
+        Capture::{GraphicsCaptureItem, GraphicsCaptureSession},
+        RectInt32, SizeInt32,
+    },
+    Win32::Graphics::Direct3D11::ID3D11Device,
+};
+
+use crate::{
+    capture::{CaptureFrameGenerator, CaptureStopHandle, FrameReader, DEFAULT_PIXEL_FORMAT},
+    container::to_error,
+    timeline::Timeline,
+};
+
+use super::{
+    encoder::GifEncoder,
+    quantizer::{quantize, QuantizedFrame},
+};
+
+// GIF delays are in hundredths of a second, and most viewers treat
+// anything shorter than 2 as much slower than intended.
+const HUNDRED_NANOSECONDS_PER_DELAY: i64 = 100_000;
+const MIN_DELAY: i64 = 2;
+
+#[derive(Copy, Clone, Debug)]
+pub struct GifSettings {
+    pub frame_rate: u32,
+    // Larger recordings are scaled down to this width
+    pub max_width: u32,
+}
+
+/// Records an item to an animated GIF, bypassing Media Foundation.
+pub struct GifEncodingSession {
+    capture_session: GraphicsCaptureSession,
+    stop_handle: CaptureStopHandle,
+    frame_generator: Option<GifFrameGenerator>,
+    thread_handle: Option<JoinHandle<Result<()>>>,
+}
+
+struct GifFrameGenerator {
+    frame_reader: FrameReader,
+
+    frame_generator: CaptureFrameGenerator,
+    region: Option<RectInt32>,
+    output_size: SizeInt32,
+    // The minimum amount of time between frames (in 100ns units)
+    frame_interval: i64,
+
+    timeline: Timeline,
+    last_frame_time: Option<i64>,
+    pending_frame: Option<(i64, QuantizedFrame)>,
+    encoder: GifEncoder<BufWriter<File>>,
+}
+
+impl GifEncodingSession {
+    pub fn new<P: AsRef<Path>>(
+        d3d_device: ID3D11Device,
+        item: GraphicsCaptureItem,
+        region: Option<RectInt32>,
+        capture_cursor: bool,
+        settings: GifSettings,
+        timeline: Timeline,
+        output_path: P,
+    ) -> Result<Self> {
+        let item_size = item.Size()?;
+        let source_size = if let Some(region) = region {
+            SizeInt32 {
+                Width: region.Width,
+                Height: region.Height,
+            }
+        } else {
+            item_size
+        };
+        let output_size = compute_output_size(source_size, settings.max_width);
+
+        let file = File::create(output_path).map_err(to_error)?;
+        let encoder = GifEncoder::new(
+            BufWriter::new(file),
+            output_size.Width as u16,
+            output_size.Height as u16,
+        )
+        .map_err(to_error)?;
+
+        let frame_generator = CaptureFrameGenerator::new(
+            d3d_device.clone(),
+            vec![(item, item_size)],
+            DEFAULT_PIXEL_FORMAT,
+            capture_cursor,
+        )?;
+        let capture_session = frame_generator.sessions().remove(0);
+        let stop_handle = frame_generator.stop_handle();
+
+        Ok(Self {
+            capture_session,
+            stop_handle,
+            frame_generator: Some(GifFrameGenerator {
+                frame_reader: FrameReader::new(d3d_device)?,
+
+                frame_generator,
+                region,
+                output_size,
+                frame_interval: 10_000_000 / settings.frame_rate.max(1) as i64,
+
+                timeline,
+                last_frame_time: None,
+                pending_frame: None,
+                encoder,
+            }),
+            thread_handle: None,
+        })
+    }
+
+    pub fn start(&mut self) -> Result<()> {
+        let frame_generator = self.frame_generator.take().unwrap();
+        self.capture_session.StartCapture()?;
+        self.thread_handle = Some(std::thread::spawn(move || -> Result<()> {
+            let result = frame_generator.run();
+            if let Err(error) = &result {
+                error!(
+                    "GIF recording failed: {:?} - {}",
+                    error.code(),
+                    error.message()
+                );
+                println!("GIF recording stopped unexpectedly!");
+            }
+            result
+        }));
+        Ok(())
+    }
+
+    pub fn stop(&mut self) -> Result<()> {
+        if let Some(handle) = self.thread_handle.take() {
+            self.stop_handle.stop();
+            handle.join().unwrap()?;
+        }
+        Ok(())
+    }
+}
+
+unsafe impl Send for GifFrameGenerator {}
+impl GifFrameGenerator {
+    fn run(mut self) -> Result<()> {
+        while let Some((_, frame)) = self.frame_generator.try_get_next_frame()? {
+            // Frames that arrive while the recording is paused are dropped
+            if self.timeline.is_paused() {
+                frame.Close()?;
+                continue;
+            }
+            let frame_time = frame.SystemRelativeTime()?;
+            let time = self
+                .timeline
+                .relative_time(frame_time.Duration)
+                .unwrap_or_default();
+
+            // Drop frames that arrive faster than our frame rate
+            if let Some(last_frame_time) = self.last_frame_time {
+                if time - last_frame_time < self.frame_interval {
+                    frame.Close()?;
+                    continue;
+                }
+            }
+            self.last_frame_time = Some(time);
+
+            let (pixels, size) = self.frame_reader.read(&frame, self.region)?;
+            frame.Close()?;
+            let pixels = scale_frame(&pixels, size, self.output_size);
+            self.write_pending_frame(time)?;
+            self.pending_frame = Some((time, quantize(&pixels)));
+        }
+
+        // The last frame is shown for a single frame interval
+        let end_time = self.last_frame_time.unwrap_or_default() + self.frame_interval;
+        self.write_pending_frame(end_time)?;
+        self.encoder.finish().map_err(to_error)?;
+        Ok(())
+    }
+
+    fn write_pending_frame(&mut self, next_frame_time: i64) -> Result<()> {
+        if let Some((time, frame)) = self.pending_frame.take() {
+            let delay = ((next_frame_time - time) / HUNDRED_NANOSECONDS_PER_DELAY)
+                .clamp(MIN_DELAY, u16::MAX as i64) as u16;
+            self.encoder
+                .write_frame(&frame.palette, &frame.indices, delay)
+                .map_err(to_error)?;
+        }
+        Ok(())
+    }
+}
+
+fn compute_output_size(source_size: SizeInt32, max_width: u32) -> SizeInt32 {
+    let max_width = max_width.max(1) as i32;
+    if source_size.Width <= max_width {
+        source_size
+    } else {
+        SizeInt32 {
+            Width: max_width,
+            Height: ((source_size.Height as i64 * max_width as i64) / source_size.Width as i64)
+                .max(1) as i32,
+        }
+    }
+}
+
+/// Scales BGRA pixels using the nearest neighbor.
+fn scale_frame(pixels: &[u8], input_size: SizeInt32, output_size: SizeInt32) -> Vec<u8> {
+    let output_length = (output_size.Width * output_size.Height * 4) as usize;
+    if input_size.Width <= 0 || input_size.Height <= 0 {
+        return vec![0; output_length];
+    }
+    if input_size == output_size {
+        return pixels.to_vec();
+    }
+
+    let mut result = Vec::with_capacity(output_length);
+    for y in 0..output_size.Height {
+        let source_y = (y as i64 * input_size.Height as i64 / output_size.Height as i64) as usize;
+        for x in 0..output_size.Width {
+            let source_x = (x as i64 * input_size.Width as i64 / output_size.Width as i64) as usize;
+            let start = (source_y * input_size.Width as usize + source_x) * 4;
+            result.extend_from_slice(&pixels[start..start + 4]);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Graphics::SizeInt32;
+
+    use super::{compute_output_size, scale_frame};
+
+    fn size(width: i32, height: i32) -> SizeInt32 {
+        SizeInt32 {
+            Width: width,
+            Height: height,
+        }
+    }
+
+    #[test]
+    fn output_size_test() {
+        assert_eq!(compute_output_size(size(1920, 1080), 640), size(640, 360));
+        assert_eq!(compute_output_size(size(320, 200), 640), size(320, 200));
+        assert_eq!(compute_output_size(size(4000, 1), 640), size(640, 1));
+    }
+
+    #[test]
+    fn scale_test() {
+        // 2x2: each pixel gets its own value in the blue channel
+        let pixels = [1, 0, 0, 255, 2, 0, 0, 255, 3, 0, 0, 255, 4, 0, 0, 255];
+        assert_eq!(
+            scale_frame(&pixels, size(2, 2), size(1, 1)),
+            vec![1, 0, 0, 255]
+        );
+        let scaled = scale_frame(&pixels, size(2, 2), size(4, 2));
+        let blues: Vec<_> = scaled.chunks_exact(4).map(|pixel| pixel[0]).collect();
+        assert_eq!(blues, vec![1, 1, 2, 2, 3, 3, 4, 4]);
+        assert_eq!(scale_frame(&pixels, size(0, 0), size(1, 1)), vec![0; 4]);
+    }
+}
@@ -0,0 +1,3 @@
+mod encoder;
+pub mod encoding_session;
+mod quantizer;
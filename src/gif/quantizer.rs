@@ -0,0 +1,109 @@
+// Reduces BGRA frames to 256 colors. Colors are bucketed into 15 bits
+// (5 bits per channel), and the most popular buckets form the palette.
+
+const BUCKET_BITS: u32 = 5;
+const BUCKET_COUNT: usize = 1 << (BUCKET_BITS * 3);
+const PALETTE_SIZE: usize = 256;
+
+pub struct QuantizedFrame {
+    pub palette: Vec<[u8; 3]>,
+    pub indices: Vec<u8>,
+}
+
+pub fn quantize(bgra: &[u8]) -> QuantizedFrame {
+    let mut counts = vec![0u32; BUCKET_COUNT];
+    for pixel in bgra.chunks_exact(4) {
+        counts[get_bucket(pixel)] += 1;
+    }
+
+    let mut buckets: Vec<usize> = (0..BUCKET_COUNT)
+        .filter(|bucket| counts[*bucket] > 0)
+        .collect();
+    buckets.sort_by(|a, b| counts[*b].cmp(&counts[*a]).then(a.cmp(b)));
+    buckets.truncate(PALETTE_SIZE);
+    let palette: Vec<[u8; 3]> = buckets.iter().map(|bucket| get_color(*bucket)).collect();
+
+    // Every bucket maps to its closest palette entry, which we only compute
+    // for the buckets that are actually used.
+    let mut lookup = vec![None; BUCKET_COUNT];
+    let indices = bgra
+        .chunks_exact(4)
+        .map(|pixel| {
+            let bucket = get_bucket(pixel);
+            *lookup[bucket].get_or_insert_with(|| find_closest(&palette, get_color(bucket)))
+        })
+        .collect();
+
+    QuantizedFrame { palette, indices }
+}
+
+fn get_bucket(bgra: &[u8]) -> usize {
+    let shift = 8 - BUCKET_BITS;
+    let r = (bgra[2] >> shift) as usize;
+    let g = (bgra[1] >> shift) as usize;
+    let b = (bgra[0] >> shift) as usize;
+    (r << (BUCKET_BITS * 2)) | (g << BUCKET_BITS) | b
+}
+
+fn get_color(bucket: usize) -> [u8; 3] {
+    let mask = (1 << BUCKET_BITS) - 1;
+    let expand = |value: usize| -> u8 {
+        // Spread the value across the full 8 bits
+        let value = value as u8;
+        (value << (8 - BUCKET_BITS)) | (value >> (2 * BUCKET_BITS - 8))
+    };
+    [
+        expand((bucket >> (BUCKET_BITS * 2)) & mask),
+        expand((bucket >> BUCKET_BITS) & mask),
+        expand(bucket & mask),
+    ]
+}
+
+fn find_closest(palette: &[[u8; 3]], color: [u8; 3]) -> u8 {
+    let distance = |entry: &[u8; 3]| -> i32 {
+        entry
+            .iter()
+            .zip(color)
+            .map(|(a, b)| {
+                let difference = *a as i32 - b as i32;
+                difference * difference
+            })
+            .sum()
+    };
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, entry)| distance(entry))
+        .map(|(index, _)| index as u8)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::quantize;
+
+    #[test]
+    fn few_colors_test() {
+        // BGRA: red, green, red, white
+        let pixels = [
+            0, 0, 255, 255, 0, 255, 0, 255, 0, 0, 255, 255, 255, 255, 255, 255,
+        ];
+        let frame = quantize(&pixels);
+        assert_eq!(frame.palette.len(), 3);
+        // The most popular color comes first
+        assert_eq!(frame.palette[0], [255, 0, 0]);
+        assert_eq!(frame.indices[0], frame.indices[2]);
+        assert_eq!(frame.palette[frame.indices[1] as usize], [0, 255, 0]);
+        assert_eq!(frame.palette[frame.indices[3] as usize], [255, 255, 255]);
+    }
+
+    #[test]
+    fn many_colors_test() {
+        let pixels: Vec<u8> = (0..4096u32)
+            .flat_map(|i| [(i * 7) as u8, (i * 13) as u8, (i * 29) as u8, 255])
+            .collect();
+        let frame = quantize(&pixels);
+        assert_eq!(frame.palette.len(), 256);
+        assert_eq!(frame.indices.len(), 4096);
+    }
+}
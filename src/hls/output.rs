@@ -0,0 +1,69 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use windows::core::Result;
+
+use crate::container::{fragment_writer::SegmentOutput, to_error};
+
+use super::playlist::Playlist;
+
+/// How long each segment should be. Segments start at key frames, so the
+/// encoder should produce one at least this often.
+pub const SEGMENT_DURATION: Duration = Duration::from_secs(4);
+pub const PLAYLIST_NAME: &str = "playlist.m3u8";
+const INIT_SEGMENT_NAME: &str = "init.mp4";
+
+/// Writes the segments into a directory along with a playlist, which is
+/// updated after every segment so that the recording can be watched while
+/// it's still going.
+pub struct HlsOutput {
+    directory: PathBuf,
+    playlist: Playlist,
+    segment_count: u32,
+}
+
+impl HlsOutput {
+    /// Creates the directory if it doesn't exist yet.
+    pub fn new<P: AsRef<Path>>(directory: P) -> Result<Self> {
+        let directory = directory.as_ref().to_owned();
+        fs::create_dir_all(&directory).map_err(to_error)?;
+        Ok(Self {
+            directory,
+            playlist: Playlist::new(INIT_SEGMENT_NAME, SEGMENT_DURATION),
+            segment_count: 0,
+        })
+    }
+
+    fn write_file(&self, name: &str, data: &[u8]) -> Result<()> {
+        fs::write(self.directory.join(name), data).map_err(to_error)
+    }
+
+    fn write_playlist(&self) -> Result<()> {
+        // Players may load the playlist at any time, so it's replaced in one go
+        let temp_path = self.directory.join(format!("{}.tmp", PLAYLIST_NAME));
+        fs::write(&temp_path, self.playlist.to_string()).map_err(to_error)?;
+        fs::rename(&temp_path, self.directory.join(PLAYLIST_NAME)).map_err(to_error)
+    }
+}
+
+impl SegmentOutput for HlsOutput {
+    fn write_init_segment(&mut self, data: &[u8]) -> Result<()> {
+        self.write_file(INIT_SEGMENT_NAME, data)
+    }
+
+    fn write_media_segment(&mut self, data: &[u8], duration: Duration) -> Result<()> {
+        let name = format!("segment{:05}.m4s", self.segment_count);
+        self.segment_count += 1;
+        self.write_file(&name, data)?;
+        self.playlist.add_segment(name, duration);
+        self.write_playlist()
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.playlist.end();
+        self.write_playlist()
+    }
+}
@@ -0,0 +1,74 @@
+use std::{fmt::Display, time::Duration};
+
+/// An HLS media playlist (see RFC 8216) that grows as segments are written.
+/// Players keep reloading it to follow an ongoing recording, until the end
+/// is marked.
+pub struct Playlist {
+    init_segment_name: String,
+    // In whole seconds, which can't change once players have loaded it
+    target_duration: u64,
+    segments: Vec<(String, Duration)>,
+    ended: bool,
+}
+
+impl Playlist {
+    pub fn new<S: Into<String>>(init_segment_name: S, target_duration: Duration) -> Self {
+        Self {
+            init_segment_name: init_segment_name.into(),
+            target_duration: target_duration.as_nanos().div_ceil(1_000_000_000) as u64,
+            segments: Vec::new(),
+            ended: false,
+        }
+    }
+
+    pub fn add_segment<S: Into<String>>(&mut self, name: S, duration: Duration) {
+        self.segments.push((name.into(), duration));
+    }
+
+    pub fn end(&mut self) {
+        self.ended = true;
+    }
+}
+
+impl Display for Playlist {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "#EXTM3U")?;
+        writeln!(f, "#EXT-X-VERSION:7")?;
+        writeln!(f, "#EXT-X-TARGETDURATION:{}", self.target_duration)?;
+        writeln!(f, "#EXT-X-MEDIA-SEQUENCE:0")?;
+        writeln!(f, "#EXT-X-PLAYLIST-TYPE:EVENT")?;
+        writeln!(f, "#EXT-X-INDEPENDENT-SEGMENTS")?;
+        writeln!(f, "#EXT-X-MAP:URI=\"{}\"", self.init_segment_name)?;
+        for (name, duration) in &self.segments {
+            writeln!(f, "#EXTINF:{:.3},", duration.as_secs_f64())?;
+            writeln!(f, "{}", name)?;
+        }
+        if self.ended {
+            writeln!(f, "#EXT-X-ENDLIST")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Playlist;
+
+    #[test]
+    fn playlist_test() {
+        // The target duration is rounded up, and doesn't depend on the segments
+        let mut playlist = Playlist::new("init.mp4", Duration::from_millis(3500));
+        playlist.add_segment("segment00000.m4s", Duration::from_millis(4000));
+        playlist.add_segment("segment00001.m4s", Duration::from_millis(3600));
+        assert_eq!(
+            playlist.to_string(),
+            "#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-TARGETDURATION:4\n#EXT-X-MEDIA-SEQUENCE:0\n\
+             #EXT-X-PLAYLIST-TYPE:EVENT\n#EXT-X-INDEPENDENT-SEGMENTS\n#EXT-X-MAP:URI=\"init.mp4\"\n\
+             #EXTINF:4.000,\nsegment00000.m4s\n#EXTINF:3.600,\nsegment00001.m4s\n"
+        );
+        playlist.end();
+        assert!(playlist.to_string().ends_with("#EXT-X-ENDLIST\n"));
+    }
+}
@@ -1,13 +1,43 @@
-use std::sync::atomic::{AtomicI32, Ordering};
+use std::{
+    fmt::Display,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        mpsc::{channel, Receiver},
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
 use windows::{
     core::Result,
     Win32::{
-        Foundation::HWND,
-        UI::Input::KeyboardAndMouse::{RegisterHotKey, UnregisterHotKey, HOT_KEY_MODIFIERS},
+        Foundation::{HWND, LPARAM, WPARAM},
+        System::Threading::GetCurrentThreadId,
+        UI::{
+            Input::KeyboardAndMouse::{
+                RegisterHotKey, UnregisterHotKey, HOT_KEY_MODIFIERS, MOD_ALT, MOD_CONTROL,
+                MOD_NOREPEAT, MOD_SHIFT, MOD_WIN,
+            },
+            WindowsAndMessaging::{
+                GetMessageW, PeekMessageW, PostThreadMessageW, MSG, PM_NOREMOVE, WM_HOTKEY, WM_QUIT,
+            },
+        },
     },
 };
 
-static mut HOT_KEY_ID: AtomicI32 = AtomicI32::new(0);
+static HOT_KEY_ID: AtomicI32 = AtomicI32::new(0);
+
+// Virtual key codes for the keys we accept in a binding
+const VK_F1: u32 = 0x70;
+const MAX_FUNCTION_KEY: u32 = 24;
+
+const MODIFIER_NAMES: [(HOT_KEY_MODIFIERS, &str); 4] = [
+    (MOD_CONTROL, "ctrl"),
+    (MOD_ALT, "alt"),
+    (MOD_SHIFT, "shift"),
+    (MOD_WIN, "win"),
+];
 
 pub struct HotKey {
     id: i32,
@@ -15,12 +45,16 @@ pub struct HotKey {
 
 impl HotKey {
     pub fn new(modifiers: HOT_KEY_MODIFIERS, key: u32) -> Result<Self> {
-        let id = unsafe { HOT_KEY_ID.fetch_add(1, Ordering::SeqCst) + 1 };
+        let id = HOT_KEY_ID.fetch_add(1, Ordering::SeqCst) + 1;
         unsafe {
             RegisterHotKey(HWND(0), id, modifiers, key)?;
         }
         Ok(Self { id })
     }
+
+    pub fn id(&self) -> i32 {
+        self.id
+    }
 }
 
 impl Drop for HotKey {
@@ -28,3 +62,209 @@ impl Drop for HotKey {
         unsafe { UnregisterHotKey(HWND(0), self.id).ok().unwrap() }
     }
 }
+
+/// A key combination, e.g. ctrl+shift+r.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HotKeyBinding {
+    pub modifiers: HOT_KEY_MODIFIERS,
+    pub key: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseHotKeyBindingError(&'static str);
+
+impl FromStr for HotKeyBinding {
+    type Err = ParseHotKeyBindingError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut modifiers = HOT_KEY_MODIFIERS(0);
+        let mut key = None;
+        for part in s.to_lowercase().split('+').map(|part| part.trim()) {
+            let modifier = match part {
+                "ctrl" | "control" => Some(MOD_CONTROL),
+                "alt" => Some(MOD_ALT),
+                "shift" => Some(MOD_SHIFT),
+                "win" => Some(MOD_WIN),
+                _ => None,
+            };
+            if let Some(modifier) = modifier {
+                modifiers |= modifier;
+            } else if key.is_some() {
+                return Err(ParseHotKeyBindingError(
+                    "Invalid hotkey value! Expecting a single key.",
+                ));
+            } else {
+                key = Some(parse_key(part).ok_or(ParseHotKeyBindingError(
+                    "Invalid hotkey value! Expecting a letter, digit, or function key (e.g. ctrl+shift+r).",
+                ))?);
+            }
+        }
+        if let Some(key) = key {
+            if modifiers.0 == 0 {
+                return Err(ParseHotKeyBindingError(
+                    "Invalid hotkey value! Expecting at least one of: ctrl, alt, shift, or win.",
+                ));
+            }
+            Ok(Self { modifiers, key })
+        } else {
+            Err(ParseHotKeyBindingError(
+                "Invalid hotkey value! Expecting a key (e.g. ctrl+shift+r).",
+            ))
+        }
+    }
+}
+
+impl Display for HotKeyBinding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (modifier, name) in MODIFIER_NAMES {
+            if self.modifiers.0 & modifier.0 != 0 {
+                write!(f, "{}+", name)?;
+            }
+        }
+        if (VK_F1..VK_F1 + MAX_FUNCTION_KEY).contains(&self.key) {
+            write!(f, "f{}", self.key - VK_F1 + 1)
+        } else {
+            write!(f, "{}", (self.key as u8 as char).to_ascii_lowercase())
+        }
+    }
+}
+
+impl Display for ParseHotKeyBindingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseHotKeyBindingError {}
+
+fn parse_key(value: &str) -> Option<u32> {
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_alphanumeric() => Some(c.to_ascii_uppercase() as u32),
+        (Some('f'), Some(_)) => {
+            let number: u32 = value[1..].parse().ok()?;
+            if (1..=MAX_FUNCTION_KEY).contains(&number) {
+                Some(VK_F1 + number - 1)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Listens for global hotkeys on a dedicated thread, so that they work
+/// while another app has focus. Each hotkey is reported by its index in
+/// the list of bindings.
+pub struct HotKeyListener {
+    thread_id: u32,
+    receiver: Receiver<usize>,
+    thread_handle: Option<JoinHandle<()>>,
+}
+
+impl HotKeyListener {
+    pub fn new(bindings: &[HotKeyBinding]) -> Result<Self> {
+        let bindings = bindings.to_vec();
+        let (sender, receiver) = channel();
+        let (setup_sender, setup_receiver) = channel();
+        let thread_handle = std::thread::spawn(move || {
+            // Hotkeys without a window are posted to the thread that registered them
+            let hot_keys: Result<Vec<_>> = bindings
+                .iter()
+                .map(|binding| HotKey::new(binding.modifiers | MOD_NOREPEAT, binding.key))
+                .collect();
+            let hot_keys = match hot_keys {
+                Ok(hot_keys) => hot_keys,
+                Err(error) => {
+                    setup_sender.send(Err(error)).unwrap();
+                    return;
+                }
+            };
+            unsafe {
+                // Make sure our message queue exists before anyone posts to it
+                let mut message = MSG::default();
+                PeekMessageW(&mut message, HWND(0), 0, 0, PM_NOREMOVE);
+                setup_sender.send(Ok(GetCurrentThreadId())).unwrap();
+
+                while GetMessageW(&mut message, HWND(0), 0, 0).into() {
+                    if message.message == WM_HOTKEY {
+                        let id = message.wParam.0 as i32;
+                        if let Some(index) = hot_keys.iter().position(|hot_key| hot_key.id() == id)
+                        {
+                            if sender.send(index).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+        });
+        let thread_id = setup_receiver.recv().unwrap()?;
+        Ok(Self {
+            thread_id,
+            receiver,
+            thread_handle: Some(thread_handle),
+        })
+    }
+
+    /// Blocks until one of the hotkeys is pressed, or gives up after the timeout.
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<usize> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+}
+
+impl Drop for HotKeyListener {
+    fn drop(&mut self) {
+        if let Some(handle) = self.thread_handle.take() {
+            unsafe {
+                PostThreadMessageW(self.thread_id, WM_QUIT, WPARAM(0), LPARAM(0))
+                    .ok()
+                    .unwrap();
+            }
+            handle.join().unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::UI::Input::KeyboardAndMouse::{MOD_ALT, MOD_CONTROL, MOD_SHIFT};
+
+    use super::HotKeyBinding;
+
+    #[test]
+    fn binding_parsing_test() {
+        assert_eq!(
+            "ctrl+shift+r".parse(),
+            Ok(HotKeyBinding {
+                modifiers: MOD_CONTROL | MOD_SHIFT,
+                key: 0x52,
+            })
+        );
+        assert_eq!(
+            "Alt + F9".parse(),
+            Ok(HotKeyBinding {
+                modifiers: MOD_ALT,
+                key: 0x78,
+            })
+        );
+        assert_eq!(
+            "control+1"
+                .parse::<HotKeyBinding>()
+                .map(|binding| binding.key),
+            Ok(0x31)
+        );
+
+        assert!("r".parse::<HotKeyBinding>().is_err());
+        assert!("ctrl+shift".parse::<HotKeyBinding>().is_err());
+        assert!("ctrl+r+t".parse::<HotKeyBinding>().is_err());
+        assert!("ctrl+f25".parse::<HotKeyBinding>().is_err());
+        assert!("ctrl+space".parse::<HotKeyBinding>().is_err());
+    }
+
+    #[test]
+    fn binding_display_test() {
+        for value in ["ctrl+shift+r", "alt+f12", "ctrl+alt+shift+win+0"] {
+            assert_eq!(value.parse::<HotKeyBinding>().unwrap().to_string(), value);
+        }
+    }
+}
@@ -0,0 +1,404 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc::Sender, Arc},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use windows::{
+    core::{Error, Result},
+    Win32::Foundation::E_INVALIDARG,
+};
+
+use crate::{
+    control::{call, ControlError, ControlRequest, INVALID_PARAMS, METHOD_NOT_FOUND, PARSE_ERROR},
+    json::JsonValue,
+};
+
+// Requests are small JSON objects, anything bigger is rejected
+pub const MAX_BODY_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, PartialEq)]
+struct HttpRequest {
+    method: String,
+    path: String,
+    // With lowercase names
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+// Who gets to make requests, so that web pages the user visits can't
+struct Access {
+    address: String,
+    token: String,
+}
+
+impl HttpRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Serves the control methods over HTTP, see ControlServer::listen_on_http.
+/// Each connection gets its own thread and is closed after one request.
+/// Returns the token that requests have to send.
+pub fn listen(address: &str, sender: Sender<ControlRequest>) -> Result<String> {
+    let listener = TcpListener::bind(address).map_err(|error| {
+        Error::new(
+            E_INVALIDARG,
+            format!("Could not listen on {}: {}", address, error).into(),
+        )
+    })?;
+    let access = Arc::new(Access {
+        address: address.to_owned(),
+        token: create_token(),
+    });
+    let token = access.token.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let sender = sender.clone();
+            let access = access.clone();
+            std::thread::spawn(move || serve_client(stream, &access, &sender));
+        }
+    });
+    Ok(token)
+}
+
+fn serve_client(stream: TcpStream, access: &Access, sender: &Sender<ControlRequest>) {
+    let (status, body) = match read_request(&mut BufReader::new(&stream)) {
+        Some(request) => match check_access(&request, access) {
+            Ok(()) => handle_request(&request, sender),
+            Err((status, message)) => (status, create_error_body(message)),
+        },
+        None => (400, create_error_body("Bad request")),
+    };
+    // The client may have disconnected in the meantime
+    let _ = write_response(&mut &stream, status, &body);
+}
+
+// Returns None if the request is malformed
+fn read_request<R: BufRead>(reader: &mut R) -> Option<HttpRequest> {
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_owned();
+    let target = parts.next()?;
+    // The query string isn't used
+    let path = target.split('?').next()?.to_owned();
+
+    let mut headers = Vec::new();
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':')?;
+        let (name, value) = (name.trim().to_ascii_lowercase(), value.trim());
+        if name == "content-length" {
+            content_length = value.parse().ok()?;
+        }
+        headers.push((name, value.to_owned()));
+    }
+    if content_length > MAX_BODY_SIZE {
+        return None;
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).ok()?;
+
+    Some(HttpRequest {
+        method,
+        path,
+        headers,
+        body: String::from_utf8(body).ok()?,
+    })
+}
+
+// Returns the status code and the body of the response
+fn handle_request(request: &HttpRequest, sender: &Sender<ControlRequest>) -> (u16, JsonValue) {
+    let method = request.path.trim_start_matches('/');
+    if method.is_empty() || method.contains('/') {
+        return (404, create_error_body("Not found"));
+    }
+    // Everything other than the status changes the recording
+    let allowed = request.method == "POST" || (request.method == "GET" && method == "status");
+    if !allowed {
+        return (
+            405,
+            create_error_body(&format!("Use POST /{} instead!", method)),
+        );
+    }
+    let params = if request.body.trim().is_empty() {
+        JsonValue::Null
+    } else if let Some(params) = JsonValue::parse(&request.body) {
+        params
+    } else {
+        return (400, create_error_body("The body must be a JSON object!"));
+    };
+
+    match call(sender, method.to_owned(), params) {
+        Ok(result) => (200, result),
+        Err(error) => (get_status_code(&error), create_error_body(error.message())),
+    }
+}
+
+// Browsers let any page send requests to the server, but not with a token
+// it doesn't know, a JSON Content-Type (without asking first), or a Host
+// (or Origin) of the server when it's on the loopback address
+fn check_access(
+    request: &HttpRequest,
+    access: &Access,
+) -> std::result::Result<(), (u16, &'static str)> {
+    let host = request.header("host").unwrap_or_default();
+    let origin_host = request
+        .header("origin")
+        .map(|origin| origin.split_once("://").map_or(origin, |(_, host)| host));
+    if !is_allowed_host(host, &access.address)
+        || origin_host.is_some_and(|origin_host| !is_allowed_host(origin_host, &access.address))
+    {
+        return Err((403, "Requests from other hosts aren't allowed!"));
+    }
+    let token = request
+        .header("authorization")
+        .and_then(|authorization| authorization.strip_prefix("Bearer "));
+    if token != Some(access.token.as_str()) {
+        return Err((401, "Requests need the token that was printed at startup!"));
+    }
+    let content_type = request
+        .header("content-type")
+        .and_then(|content_type| content_type.split(';').next())
+        .map(str::trim);
+    if request.method == "POST"
+        && !content_type
+            .is_some_and(|content_type| content_type.eq_ignore_ascii_case("application/json"))
+    {
+        return Err((415, "The Content-Type must be application/json!"));
+    }
+    Ok(())
+}
+
+// Whether a Host header (e.g. localhost:8080) names the server at the
+// address it listens on. Any host can if it listens on all addresses.
+fn is_allowed_host(host: &str, address: &str) -> bool {
+    let (host, address) = (get_host_name(host), get_host_name(address));
+    matches!(address, "0.0.0.0" | "[::]")
+        || host.eq_ignore_ascii_case(address)
+        || (is_loopback(address) && is_loopback(host))
+}
+
+// Without the port
+fn get_host_name(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port)) if !name.is_empty() && !port.contains(']') => name,
+        _ => host,
+    }
+}
+
+fn is_loopback(host: &str) -> bool {
+    host == "[::1]" || host.starts_with("127.") || host.eq_ignore_ascii_case("localhost")
+}
+
+// std doesn't have a random number generator, but it does seed its hashers
+// randomly
+fn create_token() -> String {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_nanos())
+        .unwrap_or_default();
+    (0..2)
+        .map(|_| {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u128(time);
+            format!("{:016x}", hasher.finish())
+        })
+        .collect()
+}
+
+fn get_status_code(error: &ControlError) -> u16 {
+    match error.code() {
+        METHOD_NOT_FOUND => 404,
+        PARSE_ERROR | INVALID_PARAMS => 400,
+        _ => 500,
+    }
+}
+
+fn get_reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        415 => "Unsupported Media Type",
+        _ => "Internal Server Error",
+    }
+}
+
+fn create_error_body(message: &str) -> JsonValue {
+    JsonValue::object([("error", JsonValue::string(message))])
+}
+
+fn write_response<W: Write>(writer: &mut W, status: u16, body: &JsonValue) -> io::Result<()> {
+    let body = body.to_string();
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        get_reason_phrase(status),
+        body.len(),
+        body
+    )?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, sync::mpsc::channel};
+
+    use crate::{
+        control::{ControlError, ControlRequest},
+        json::JsonValue,
+    };
+
+    use super::{
+        check_access, handle_request, is_allowed_host, read_request, write_response, Access,
+        HttpRequest,
+    };
+
+    #[test]
+    fn request_parsing_test() {
+        let mut request = Cursor::new(
+            "POST /marker?x=1 HTTP/1.1\r\nHost: localhost\r\ncontent-length: 17\r\n\r\n{\"label\":\"intro\"}",
+        );
+        assert_eq!(
+            read_request(&mut request),
+            Some(HttpRequest {
+                method: "POST".to_owned(),
+                path: "/marker".to_owned(),
+                headers: vec![
+                    ("host".to_owned(), "localhost".to_owned()),
+                    ("content-length".to_owned(), "17".to_owned()),
+                ],
+                body: "{\"label\":\"intro\"}".to_owned(),
+            })
+        );
+        let mut request = Cursor::new("GET /status HTTP/1.1\r\n\r\n");
+        assert_eq!(read_request(&mut request).unwrap().body, "");
+        // The headers never end
+        assert_eq!(
+            read_request(&mut Cursor::new("GET /status HTTP/1.1\r\n")),
+            None
+        );
+        assert_eq!(read_request(&mut Cursor::new("")), None);
+
+        let mut response = Vec::new();
+        write_response(&mut response, 200, &JsonValue::Null).unwrap();
+        assert_eq!(
+            String::from_utf8(response).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 4\r\nConnection: close\r\n\r\nnull"
+        );
+    }
+
+    #[test]
+    fn request_handling_test() {
+        let (sender, receiver) = channel::<ControlRequest>();
+        std::thread::spawn(move || {
+            for request in receiver.iter() {
+                let result = match request.method.as_str() {
+                    "status" => Ok(JsonValue::object([("recording", JsonValue::Bool(true))])),
+                    method => Err(ControlError::method_not_found(method)),
+                };
+                request.respond(result);
+            }
+        });
+        let request = |method: &str, path: &str, body: &str| {
+            let request = HttpRequest {
+                method: method.to_owned(),
+                path: path.to_owned(),
+                headers: Vec::new(),
+                body: body.to_owned(),
+            };
+            let (status, body) = handle_request(&request, &sender);
+            (status, body.to_string())
+        };
+
+        assert_eq!(
+            request("GET", "/status", ""),
+            (200, r#"{"recording":true}"#.to_owned())
+        );
+        assert_eq!(request("POST", "/status", "{}").0, 200);
+        assert_eq!(request("GET", "/stop", "").0, 405);
+        assert_eq!(request("POST", "/start", "{").0, 400);
+        assert_eq!(
+            request("POST", "/rewind", ""),
+            (404, r#"{"error":"Unknown method \"rewind\"!"}"#.to_owned())
+        );
+        assert_eq!(request("GET", "/", "").0, 404);
+    }
+
+    #[test]
+    fn access_test() {
+        let access = Access {
+            address: "127.0.0.1:8080".to_owned(),
+            token: "secret".to_owned(),
+        };
+        let check = |method: &str, headers: &[(&str, &str)]| {
+            let request = HttpRequest {
+                method: method.to_owned(),
+                path: "/stop".to_owned(),
+                headers: headers
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect(),
+                body: String::new(),
+            };
+            check_access(&request, &access).map_err(|(status, _)| status)
+        };
+        let host = ("host", "localhost:8080");
+        let token = ("authorization", "Bearer secret");
+        let json = ("content-type", "application/json; charset=utf-8");
+
+        assert_eq!(check("POST", &[host, token, json]), Ok(()));
+        assert_eq!(check("GET", &[host, token]), Ok(()));
+        assert_eq!(check("POST", &[host, json]), Err(401));
+        assert_eq!(
+            check("POST", &[host, ("authorization", "Bearer guess"), json]),
+            Err(401)
+        );
+        // Forms can be posted from any page, but not as JSON
+        assert_eq!(check("POST", &[host, token]), Err(415));
+        assert_eq!(
+            check("POST", &[host, token, ("content-type", "text/plain")]),
+            Err(415)
+        );
+        // Pages of other sites, even ones that resolve to the loopback address
+        assert_eq!(
+            check(
+                "POST",
+                &[host, token, json, ("origin", "https://example.com")]
+            ),
+            Err(403)
+        );
+        assert_eq!(
+            check("POST", &[("host", "rebound.example.com:8080"), token, json]),
+            Err(403)
+        );
+        assert_eq!(check("GET", &[token]), Err(403));
+
+        assert!(is_allowed_host("127.0.0.1:8080", "localhost:8080"));
+        assert!(is_allowed_host("[::1]:8080", "127.0.0.1:8080"));
+        assert!(is_allowed_host("192.168.1.5:8080", "192.168.1.5:8080"));
+        assert!(!is_allowed_host("localhost:8080", "192.168.1.5:8080"));
+        assert!(is_allowed_host("recorder.local", "0.0.0.0:8080"));
+    }
+}
@@ -0,0 +1,129 @@
+use windows::{
+    core::{ComInterface, Result, GUID, HSTRING},
+    Graphics::SizeInt32,
+    Win32::{
+        Foundation::GENERIC_WRITE,
+        Graphics::Imaging::{
+            CLSID_WICImagingFactory, GUID_ContainerFormatJpeg, GUID_ContainerFormatPng,
+            GUID_WICPixelFormat24bppBGR, GUID_WICPixelFormat32bppBGRA, IWICBitmapSource,
+            IWICImagingFactory, WICBitmapEncoderNoCache, WICBitmapInterpolationModeFant,
+        },
+        System::Com::{CoCreateInstance, StructuredStorage::IPropertyBag2, CLSCTX_INPROC_SERVER},
+    },
+};
+
+const BYTES_PER_PIXEL: u32 = 4;
+
+pub fn create_imaging_factory() -> Result<IWICImagingFactory> {
+    unsafe { CoCreateInstance(&CLSID_WICImagingFactory, None, CLSCTX_INPROC_SERVER) }
+}
+
+/// Saves tightly packed BGRA pixels as a PNG file.
+pub fn save_png(path: &str, pixels: &[u8], size: SizeInt32) -> Result<()> {
+    // Captured frames don't use alpha, but it isn't always opaque
+    let pixels: Vec<u8> = pixels
+        .chunks_exact(BYTES_PER_PIXEL as usize)
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255])
+        .collect();
+    save_image(
+        path,
+        &GUID_ContainerFormatPng,
+        GUID_WICPixelFormat32bppBGRA,
+        &pixels,
+        size,
+        size,
+    )
+}
+
+/// Saves tightly packed BGRA pixels as a JPEG file, scaled down to fit
+/// within the maximum width.
+pub fn save_jpeg(path: &str, pixels: &[u8], size: SizeInt32, max_width: i32) -> Result<()> {
+    save_image(
+        path,
+        &GUID_ContainerFormatJpeg,
+        GUID_WICPixelFormat24bppBGR,
+        pixels,
+        size,
+        get_scaled_size(size, max_width),
+    )
+}
+
+fn save_image(
+    path: &str,
+    container_format: &GUID,
+    pixel_format: GUID,
+    pixels: &[u8],
+    size: SizeInt32,
+    output_size: SizeInt32,
+) -> Result<()> {
+    unsafe {
+        let factory = create_imaging_factory()?;
+        let bitmap = factory.CreateBitmapFromMemory(
+            size.Width as u32,
+            size.Height as u32,
+            &GUID_WICPixelFormat32bppBGRA,
+            size.Width as u32 * BYTES_PER_PIXEL,
+            pixels,
+        )?;
+        let source: IWICBitmapSource = if output_size != size {
+            let scaler = factory.CreateBitmapScaler()?;
+            scaler.Initialize(
+                &bitmap,
+                output_size.Width as u32,
+                output_size.Height as u32,
+                WICBitmapInterpolationModeFant,
+            )?;
+            scaler.cast()?
+        } else {
+            bitmap.cast()?
+        };
+
+        let stream = factory.CreateStream()?;
+        stream.InitializeFromFilename(&HSTRING::from(path), GENERIC_WRITE.0)?;
+        let encoder = factory.CreateEncoder(container_format, std::ptr::null())?;
+        encoder.Initialize(&stream, WICBitmapEncoderNoCache)?;
+
+        let mut frame = None;
+        let mut options = None;
+        encoder.CreateNewFrame(&mut frame, &mut options)?;
+        let frame = frame.unwrap();
+        frame.Initialize(None::<&IPropertyBag2>)?;
+        frame.SetSize(output_size.Width as u32, output_size.Height as u32)?;
+        // The encoder may pick a different format, WriteSource converts to it
+        let mut pixel_format = pixel_format;
+        frame.SetPixelFormat(&mut pixel_format)?;
+        frame.WriteSource(&source, std::ptr::null())?;
+        frame.Commit()?;
+        encoder.Commit()
+    }
+}
+
+// Keeps the aspect ratio, images are never scaled up
+fn get_scaled_size(size: SizeInt32, max_width: i32) -> SizeInt32 {
+    if size.Width <= max_width || size.Width <= 0 {
+        return size;
+    }
+    let height = (size.Height as i64 * max_width as i64 / size.Width as i64) as i32;
+    SizeInt32 {
+        Width: max_width,
+        Height: height.max(1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Graphics::SizeInt32;
+
+    use super::get_scaled_size;
+
+    #[test]
+    fn scaled_size_test() {
+        let size = |width, height| SizeInt32 {
+            Width: width,
+            Height: height,
+        };
+        assert_eq!(get_scaled_size(size(1920, 1080), 320), size(320, 180));
+        assert_eq!(get_scaled_size(size(200, 100), 320), size(200, 100));
+        assert_eq!(get_scaled_size(size(3000, 1), 320), size(320, 1));
+    }
+}
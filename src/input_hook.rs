@@ -0,0 +1,279 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    sync::{mpsc::channel, Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use windows::{
+    core::Result,
+    Graphics::PointInt32,
+    Win32::{
+        Foundation::{HWND, LPARAM, LRESULT, WPARAM},
+        System::{LibraryLoader::GetModuleHandleW, Threading::GetCurrentThreadId},
+        UI::Input::KeyboardAndMouse::{
+            GetAsyncKeyState, VIRTUAL_KEY, VK_CONTROL, VK_LCONTROL, VK_LMENU, VK_LSHIFT, VK_LWIN,
+            VK_MENU, VK_RCONTROL, VK_RMENU, VK_RSHIFT, VK_RWIN, VK_SHIFT,
+        },
+        UI::WindowsAndMessaging::{
+            CallNextHookEx, GetMessageW, PeekMessageW, PostThreadMessageW, SetWindowsHookExW,
+            UnhookWindowsHookEx, HC_ACTION, HHOOK, HOOKPROC, KBDLLHOOKSTRUCT, MSG, MSLLHOOKSTRUCT,
+            PM_NOREMOVE, WH_KEYBOARD_LL, WH_MOUSE_LL, WINDOWS_HOOK_ID, WM_KEYDOWN, WM_LBUTTONDOWN,
+            WM_MBUTTONDOWN, WM_QUIT, WM_RBUTTONDOWN, WM_SYSKEYDOWN,
+        },
+    },
+};
+
+use crate::key_combination::KeyCombination;
+
+// Clicks older than this are forgotten
+const CLICK_HISTORY: Duration = Duration::from_secs(2);
+
+// Modifiers are reported as part of the next key instead of on their own
+const MODIFIER_KEYS: [VIRTUAL_KEY; 11] = [
+    VK_SHIFT,
+    VK_LSHIFT,
+    VK_RSHIFT,
+    VK_CONTROL,
+    VK_LCONTROL,
+    VK_RCONTROL,
+    VK_MENU,
+    VK_LMENU,
+    VK_RMENU,
+    VK_LWIN,
+    VK_RWIN,
+];
+
+/// A mouse button press, in screen coordinates.
+#[derive(Copy, Clone, Debug)]
+pub struct Click {
+    pub point: PointInt32,
+    pub time: Instant,
+}
+
+/// A key press that passed the filter of a KeyboardHook.
+#[derive(Copy, Clone, Debug)]
+pub struct KeyPress {
+    pub combination: KeyCombination,
+    pub time: Instant,
+}
+
+type ClickHistory = Arc<Mutex<VecDeque<Click>>>;
+type LastKeyPress = Arc<Mutex<Option<KeyPress>>>;
+
+thread_local! {
+    // Hook procedures don't get any context, so each hook thread keeps its own
+    static CLICKS: RefCell<Option<ClickHistory>> = const { RefCell::new(None) };
+    static KEY_PRESSES: RefCell<Option<(LastKeyPress, Option<Vec<KeyCombination>>)>> =
+        const { RefCell::new(None) };
+}
+
+/// Records mouse clicks anywhere on the desktop using a low-level mouse hook.
+pub struct MouseHook {
+    clicks: ClickHistory,
+    _thread: HookThread,
+}
+
+impl MouseHook {
+    pub fn new() -> Result<Self> {
+        let clicks: ClickHistory = Arc::new(Mutex::new(VecDeque::new()));
+        let thread = {
+            let clicks = clicks.clone();
+            HookThread::new(WH_MOUSE_LL, Some(mouse_hook_proc), move || {
+                CLICKS.with(|history| *history.borrow_mut() = Some(clicks));
+            })?
+        };
+        Ok(Self {
+            clicks,
+            _thread: thread,
+        })
+    }
+
+    /// The clicks that happened within the duration, oldest first.
+    pub fn recent_clicks(&self, within: Duration) -> Vec<Click> {
+        let now = Instant::now();
+        self.clicks
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|click| now.duration_since(click.time) <= within)
+            .copied()
+            .collect()
+    }
+}
+
+/// Records the last key combination pressed using a low-level keyboard hook.
+pub struct KeyboardHook {
+    last_key_press: LastKeyPress,
+    _thread: HookThread,
+}
+
+impl KeyboardHook {
+    /// Only shortcuts are recorded, unless an allowlist is provided, in which
+    /// case only the combinations in it are. Anything else is never stored.
+    pub fn new(allowlist: Option<Vec<KeyCombination>>) -> Result<Self> {
+        let last_key_press: LastKeyPress = Arc::new(Mutex::new(None));
+        let thread = {
+            let last_key_press = last_key_press.clone();
+            HookThread::new(WH_KEYBOARD_LL, Some(keyboard_hook_proc), move || {
+                KEY_PRESSES.with(|state| *state.borrow_mut() = Some((last_key_press, allowlist)));
+            })?
+        };
+        Ok(Self {
+            last_key_press,
+            _thread: thread,
+        })
+    }
+
+    pub fn last_key_press(&self) -> Option<KeyPress> {
+        *self.last_key_press.lock().unwrap()
+    }
+}
+
+fn is_allowed(combination: &KeyCombination, allowlist: Option<&[KeyCombination]>) -> bool {
+    if let Some(allowlist) = allowlist {
+        allowlist.contains(combination)
+    } else {
+        combination.is_shortcut() && combination.is_named()
+    }
+}
+
+unsafe extern "system" fn keyboard_hook_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    let is_key_down = matches!(wparam.0 as u32, WM_KEYDOWN | WM_SYSKEYDOWN);
+    if code == HC_ACTION as i32 && is_key_down {
+        let info = &*(lparam.0 as *const KBDLLHOOKSTRUCT);
+        let key = info.vkCode;
+        let is_modifier = MODIFIER_KEYS
+            .iter()
+            .any(|modifier| modifier.0 as u32 == key);
+        if !is_modifier {
+            // The async state already includes modifiers pressed before this key
+            let is_down = |key: VIRTUAL_KEY| GetAsyncKeyState(key.0 as i32) < 0;
+            let combination = KeyCombination {
+                ctrl: is_down(VK_CONTROL),
+                alt: is_down(VK_MENU),
+                shift: is_down(VK_SHIFT),
+                win: is_down(VK_LWIN) || is_down(VK_RWIN),
+                key,
+            };
+            KEY_PRESSES.with(|state| {
+                if let Some((last_key_press, allowlist)) = state.borrow().as_ref() {
+                    if is_allowed(&combination, allowlist.as_deref()) {
+                        *last_key_press.lock().unwrap() = Some(KeyPress {
+                            combination,
+                            time: Instant::now(),
+                        });
+                    }
+                }
+            });
+        }
+    }
+    CallNextHookEx(HHOOK(0), code, wparam, lparam)
+}
+
+unsafe extern "system" fn mouse_hook_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    let is_button_down = matches!(
+        wparam.0 as u32,
+        WM_LBUTTONDOWN | WM_RBUTTONDOWN | WM_MBUTTONDOWN
+    );
+    if code == HC_ACTION as i32 && is_button_down {
+        let info = &*(lparam.0 as *const MSLLHOOKSTRUCT);
+        let click = Click {
+            point: PointInt32 {
+                X: info.pt.x,
+                Y: info.pt.y,
+            },
+            time: Instant::now(),
+        };
+        CLICKS.with(|history| {
+            if let Some(clicks) = history.borrow().as_ref() {
+                let mut clicks = clicks.lock().unwrap();
+                while clicks
+                    .front()
+                    .filter(|old| click.time.duration_since(old.time) > CLICK_HISTORY)
+                    .is_some()
+                {
+                    clicks.pop_front();
+                }
+                clicks.push_back(click);
+            }
+        });
+    }
+    CallNextHookEx(HHOOK(0), code, wparam, lparam)
+}
+
+/// Low-level hooks are called on the thread that installed them, which has
+/// to keep pumping messages for as long as the hook is installed.
+struct HookThread {
+    thread_id: u32,
+    thread_handle: Option<JoinHandle<()>>,
+}
+
+impl HookThread {
+    fn new<F: FnOnce() + Send + 'static>(
+        hook_id: WINDOWS_HOOK_ID,
+        hook_proc: HOOKPROC,
+        init: F,
+    ) -> Result<Self> {
+        let (setup_sender, setup_receiver) = channel();
+        let thread_handle = std::thread::spawn(move || {
+            init();
+            let hook = unsafe {
+                GetModuleHandleW(None)
+                    .and_then(|module| SetWindowsHookExW(hook_id, hook_proc, module, 0))
+            };
+            let hook = match hook {
+                Ok(hook) => hook,
+                Err(error) => {
+                    setup_sender.send(Err(error)).unwrap();
+                    return;
+                }
+            };
+            unsafe {
+                // Make sure our message queue exists before anyone posts to it
+                let mut message = MSG::default();
+                PeekMessageW(&mut message, HWND(0), 0, 0, PM_NOREMOVE);
+                setup_sender.send(Ok(GetCurrentThreadId())).unwrap();
+
+                while GetMessageW(&mut message, HWND(0), 0, 0).into() {}
+                UnhookWindowsHookEx(hook).unwrap();
+            }
+        });
+        let thread_id = setup_receiver.recv().unwrap()?;
+        Ok(Self {
+            thread_id,
+            thread_handle: Some(thread_handle),
+        })
+    }
+}
+
+impl Drop for HookThread {
+    fn drop(&mut self) {
+        if let Some(handle) = self.thread_handle.take() {
+            unsafe {
+                PostThreadMessageW(self.thread_id, WM_QUIT, WPARAM(0), LPARAM(0))
+                    .ok()
+                    .unwrap();
+            }
+            handle.join().unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::key_combination::KeyCombination;
+
+    use super::is_allowed;
+
+    #[test]
+    fn key_filter_test() {
+        let combination = |s: &str| s.parse::<KeyCombination>().unwrap();
+        assert!(is_allowed(&combination("ctrl+s"), None));
+        assert!(!is_allowed(&combination("p"), None));
+        let allowlist = [combination("ctrl+c"), combination("ctrl+v")];
+        assert!(is_allowed(&combination("ctrl+c"), Some(&allowlist)));
+        assert!(!is_allowed(&combination("ctrl+s"), Some(&allowlist)));
+    }
+}
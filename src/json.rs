@@ -0,0 +1,312 @@
+use std::fmt::Display;
+
+// Arrays and objects are parsed recursively, so deeper ones could overflow
+// the stack
+const MAX_DEPTH: usize = 64;
+
+/// Just enough JSON for the control channel's messages.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    // Keeps the order of the members, which makes responses easier to read
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    pub fn object<const N: usize>(members: [(&str, JsonValue); N]) -> Self {
+        JsonValue::Object(
+            members
+                .into_iter()
+                .map(|(name, value)| (name.to_owned(), value))
+                .collect(),
+        )
+    }
+
+    pub fn string<S: Into<String>>(value: S) -> Self {
+        JsonValue::String(value.into())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(members) => members
+                .iter()
+                .find(|(member, _)| member == name)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns None if there's anything other than whitespace after the value.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parser = Parser {
+            chars: s.chars().collect(),
+            position: 0,
+            depth: 0,
+        };
+        let value = parser.parse_value()?;
+        parser.skip_whitespace();
+        if parser.position == parser.chars.len() {
+            Some(value)
+        } else {
+            None
+        }
+    }
+}
+
+impl Display for JsonValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonValue::Null => write!(f, "null"),
+            JsonValue::Bool(value) => write!(f, "{}", value),
+            JsonValue::Number(value) if value.is_finite() => write!(f, "{}", value),
+            JsonValue::Number(_) => write!(f, "null"),
+            JsonValue::String(value) => write_string(f, value),
+            JsonValue::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            JsonValue::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (name, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, name)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut std::fmt::Formatter<'_>, value: &str) -> std::fmt::Result {
+    write!(f, "\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+    // How many values are being parsed, one inside the other
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += 1;
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, word: &str) -> Option<()> {
+        for expected in word.chars() {
+            if self.next()? != expected {
+                return None;
+            }
+        }
+        Some(())
+    }
+
+    fn parse_value(&mut self) -> Option<JsonValue> {
+        if self.depth == MAX_DEPTH {
+            return None;
+        }
+        self.depth += 1;
+        let value = self.parse_nested_value();
+        self.depth -= 1;
+        value
+    }
+
+    fn parse_nested_value(&mut self) -> Option<JsonValue> {
+        self.skip_whitespace();
+        match self.peek()? {
+            'n' => self.expect("null").map(|_| JsonValue::Null),
+            't' => self.expect("true").map(|_| JsonValue::Bool(true)),
+            'f' => self.expect("false").map(|_| JsonValue::Bool(false)),
+            '"' => self.parse_string().map(JsonValue::String),
+            '[' => {
+                self.position += 1;
+                let mut values = Vec::new();
+                self.skip_whitespace();
+                if self.peek()? == ']' {
+                    self.position += 1;
+                    return Some(JsonValue::Array(values));
+                }
+                loop {
+                    values.push(self.parse_value()?);
+                    self.skip_whitespace();
+                    match self.next()? {
+                        ',' => continue,
+                        ']' => return Some(JsonValue::Array(values)),
+                        _ => return None,
+                    }
+                }
+            }
+            '{' => {
+                self.position += 1;
+                let mut members = Vec::new();
+                self.skip_whitespace();
+                if self.peek()? == '}' {
+                    self.position += 1;
+                    return Some(JsonValue::Object(members));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let name = self.parse_string()?;
+                    self.skip_whitespace();
+                    if self.next()? != ':' {
+                        return None;
+                    }
+                    members.push((name, self.parse_value()?));
+                    self.skip_whitespace();
+                    match self.next()? {
+                        ',' => continue,
+                        '}' => return Some(JsonValue::Object(members)),
+                        _ => return None,
+                    }
+                }
+            }
+            _ => self.parse_number(),
+        }
+    }
+
+    fn parse_string(&mut self) -> Option<String> {
+        if self.next()? != '"' {
+            return None;
+        }
+        let mut result = String::new();
+        loop {
+            match self.next()? {
+                '"' => return Some(result),
+                '\\' => match self.next()? {
+                    '"' => result.push('"'),
+                    '\\' => result.push('\\'),
+                    '/' => result.push('/'),
+                    'b' => result.push('\u{8}'),
+                    'f' => result.push('\u{c}'),
+                    'n' => result.push('\n'),
+                    'r' => result.push('\r'),
+                    't' => result.push('\t'),
+                    'u' => {
+                        let mut code = self.parse_hex()?;
+                        // Characters outside the BMP come as a surrogate pair
+                        if (0xD800..0xDC00).contains(&code) {
+                            self.expect("\\u")?;
+                            let low = self.parse_hex()?;
+                            code = 0x10000 + ((code - 0xD800) << 10) + (low.checked_sub(0xDC00)?);
+                        }
+                        result.push(char::from_u32(code)?);
+                    }
+                    _ => return None,
+                },
+                c => result.push(c),
+            }
+        }
+    }
+
+    fn parse_hex(&mut self) -> Option<u32> {
+        let digits: String = (0..4).map(|_| self.next()).collect::<Option<_>>()?;
+        u32::from_str_radix(&digits, 16).ok()
+    }
+
+    fn parse_number(&mut self) -> Option<JsonValue> {
+        let start = self.position;
+        while matches!(self.peek(), Some('-' | '+' | '.' | 'e' | 'E' | '0'..='9')) {
+            self.position += 1;
+        }
+        let number: String = self.chars[start..self.position].iter().collect();
+        number.parse().ok().map(JsonValue::Number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JsonValue;
+
+    #[test]
+    fn json_parsing_test() {
+        let value = JsonValue::parse(
+            r#" {"jsonrpc": "2.0", "id": 3, "method": "marker", "params": {"label": "a \"b\"\né"}, "list": [true, null, -1.5e2]} "#,
+        )
+        .unwrap();
+        assert_eq!(value.get("method").and_then(|m| m.as_str()), Some("marker"));
+        assert_eq!(value.get("id"), Some(&JsonValue::Number(3.0)));
+        assert_eq!(
+            value.get("params").and_then(|p| p.get("label")),
+            Some(&JsonValue::string("a \"b\"\né"))
+        );
+        assert_eq!(
+            value.get("list"),
+            Some(&JsonValue::Array(vec![
+                JsonValue::Bool(true),
+                JsonValue::Null,
+                JsonValue::Number(-150.0)
+            ]))
+        );
+        assert_eq!(JsonValue::parse("{\"a\": 1} x"), None);
+        assert_eq!(JsonValue::parse("{\"a\" 1}"), None);
+        assert_eq!(JsonValue::parse("[1,"), None);
+        // Too deep to parse without running out of stack
+        assert_eq!(JsonValue::parse(&"[".repeat(65_536)), None);
+        let nested = format!("{}{}", "[".repeat(65), "]".repeat(65));
+        assert_eq!(JsonValue::parse(&nested), None);
+        let nested = format!("{}{}", "[".repeat(64), "]".repeat(64));
+        assert!(JsonValue::parse(&nested).is_some());
+    }
+
+    #[test]
+    fn json_writing_test() {
+        let value = JsonValue::object([
+            ("id", JsonValue::Number(1.0)),
+            (
+                "result",
+                JsonValue::Array(vec![JsonValue::string("a\\b\"")]),
+            ),
+            ("elapsed", JsonValue::Number(2.5)),
+        ]);
+        assert_eq!(
+            value.to_string(),
+            r#"{"id":1,"result":["a\\b\""],"elapsed":2.5}"#
+        );
+        assert_eq!(JsonValue::parse(&value.to_string()), Some(value));
+    }
+}
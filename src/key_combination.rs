@@ -0,0 +1,197 @@
+use std::{fmt::Display, str::FromStr};
+
+// Virtual key codes for the keys we can name
+const VK_BACK: u32 = 0x08;
+const VK_TAB: u32 = 0x09;
+const VK_RETURN: u32 = 0x0D;
+const VK_ESCAPE: u32 = 0x1B;
+const VK_SPACE: u32 = 0x20;
+const VK_F1: u32 = 0x70;
+const MAX_FUNCTION_KEY: u32 = 24;
+
+const NAMED_KEYS: [(u32, &str); 16] = [
+    (VK_BACK, "backspace"),
+    (VK_TAB, "tab"),
+    (VK_RETURN, "enter"),
+    (VK_ESCAPE, "esc"),
+    (VK_SPACE, "space"),
+    (0x21, "pageup"),
+    (0x22, "pagedown"),
+    (0x23, "end"),
+    (0x24, "home"),
+    (0x25, "left"),
+    (0x26, "up"),
+    (0x27, "right"),
+    (0x28, "down"),
+    (0x2C, "printscreen"),
+    (0x2D, "insert"),
+    (0x2E, "delete"),
+];
+
+/// A key and the modifiers held down with it, e.g. ctrl+shift+p.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct KeyCombination {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub win: bool,
+    /// The virtual key code.
+    pub key: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseKeyCombinationError(&'static str);
+
+impl KeyCombination {
+    /// Whether this is a shortcut rather than typing. Only shortcuts are shown
+    /// by default, so that text (e.g. passwords) doesn't end up in recordings.
+    pub fn is_shortcut(&self) -> bool {
+        let is_text = self.key == VK_SPACE || get_character(self.key).is_some();
+        // AltGr is reported as ctrl+alt, and is used to type characters on many layouts
+        if is_text && self.ctrl && self.alt && !self.win {
+            return false;
+        }
+        let is_function_key = (VK_F1..VK_F1 + MAX_FUNCTION_KEY).contains(&self.key);
+        self.ctrl || self.alt || self.win || is_function_key || self.key == VK_ESCAPE
+    }
+
+    /// Whether the key is known, so that the combination can be shown.
+    pub fn is_named(&self) -> bool {
+        get_key_name(self.key).is_some()
+    }
+}
+
+impl FromStr for KeyCombination {
+    type Err = ParseKeyCombinationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut combination = KeyCombination::default();
+        let mut key = None;
+        for part in s.to_lowercase().split('+').map(|part| part.trim()) {
+            match part {
+                "ctrl" | "control" => combination.ctrl = true,
+                "alt" => combination.alt = true,
+                "shift" => combination.shift = true,
+                "win" => combination.win = true,
+                _ if key.is_some() => {
+                    return Err(ParseKeyCombinationError(
+                        "Invalid key value! Expecting a single key.",
+                    ))
+                }
+                _ => {
+                    key = Some(parse_key(part).ok_or(ParseKeyCombinationError(
+                        "Invalid key value! Expecting a letter, digit, function key, or key name (e.g. ctrl+shift+p or esc).",
+                    ))?)
+                }
+            }
+        }
+        combination.key = key.ok_or(ParseKeyCombinationError(
+            "Invalid key value! Expecting a key (e.g. ctrl+shift+p).",
+        ))?;
+        Ok(combination)
+    }
+}
+
+/// Formats the combination the way Windows shows shortcuts, e.g. Ctrl+Shift+P.
+impl Display for KeyCombination {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let modifiers = [
+            (self.ctrl, "Ctrl"),
+            (self.alt, "Alt"),
+            (self.shift, "Shift"),
+            (self.win, "Win"),
+        ];
+        for (_, name) in modifiers.iter().filter(|(held, _)| *held) {
+            write!(f, "{}+", name)?;
+        }
+        let name = get_key_name(self.key).unwrap_or_else(|| "?".to_owned());
+        let mut chars = name.chars();
+        if let Some(first) = chars.next() {
+            write!(f, "{}{}", first.to_ascii_uppercase(), chars.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for ParseKeyCombinationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseKeyCombinationError {}
+
+fn get_character(key: u32) -> Option<char> {
+    let c = char::from_u32(key)?;
+    if c.is_ascii_uppercase() || c.is_ascii_digit() {
+        Some(c)
+    } else {
+        None
+    }
+}
+
+fn get_key_name(key: u32) -> Option<String> {
+    if let Some(c) = get_character(key) {
+        Some(c.to_string())
+    } else if (VK_F1..VK_F1 + MAX_FUNCTION_KEY).contains(&key) {
+        Some(format!("f{}", key - VK_F1 + 1))
+    } else {
+        NAMED_KEYS
+            .iter()
+            .find(|(code, _)| *code == key)
+            .map(|(_, name)| name.to_string())
+    }
+}
+
+fn parse_key(value: &str) -> Option<u32> {
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_alphanumeric() => Some(c.to_ascii_uppercase() as u32),
+        (Some('f'), Some(c)) if c.is_ascii_digit() => {
+            let number: u32 = value[1..].parse().ok()?;
+            if (1..=MAX_FUNCTION_KEY).contains(&number) {
+                Some(VK_F1 + number - 1)
+            } else {
+                None
+            }
+        }
+        _ => NAMED_KEYS
+            .iter()
+            .find(|(_, name)| *name == value)
+            .map(|(code, _)| *code),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeyCombination;
+
+    #[test]
+    fn key_combination_parsing_test() {
+        let combination: KeyCombination = "ctrl+shift+p".parse().unwrap();
+        assert!(combination.ctrl && combination.shift && !combination.alt);
+        assert_eq!(combination.key, 0x50);
+        assert_eq!(combination.to_string(), "Ctrl+Shift+P");
+        assert_eq!(
+            "alt+f4".parse::<KeyCombination>().unwrap().to_string(),
+            "Alt+F4"
+        );
+        assert_eq!("Esc".parse::<KeyCombination>().unwrap().to_string(), "Esc");
+        assert!("ctrl+a+b".parse::<KeyCombination>().is_err());
+        assert!("ctrl".parse::<KeyCombination>().is_err());
+        assert!("ctrl+f99".parse::<KeyCombination>().is_err());
+    }
+
+    #[test]
+    fn shortcut_test() {
+        let shortcut = |s: &str| s.parse::<KeyCombination>().unwrap().is_shortcut();
+        assert!(shortcut("ctrl+c"));
+        assert!(shortcut("win+left"));
+        assert!(shortcut("f5"));
+        assert!(shortcut("esc"));
+        assert!(!shortcut("a"));
+        assert!(!shortcut("shift+a"));
+        assert!(!shortcut("space"));
+        assert!(!shortcut("enter"));
+        assert!(!shortcut("ctrl+alt+q"));
+    }
+}
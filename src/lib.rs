@@ -0,0 +1,96 @@
+//! Records displays and windows using Windows.Graphics.Capture and the
+//! hardware encoders available through Media Foundation.
+
+mod adapter;
+mod audio;
+mod bench;
+mod capture;
+mod change_gate;
+mod chapters;
+mod container;
+mod d3d;
+mod desktop;
+mod disk;
+mod displays;
+mod duration;
+mod error;
+mod etw;
+mod gif;
+mod hls;
+mod image;
+mod input_hook;
+mod key_combination;
+mod media;
+mod media_info;
+mod output_template;
+mod pipe;
+mod power;
+mod recorder;
+mod region;
+mod replay_buffer;
+mod resolution;
+mod rtmp;
+mod sample_writer;
+mod screenshot;
+mod segment;
+mod selftest;
+mod size;
+mod srt;
+mod stats;
+mod stream_url;
+mod test_pattern;
+mod timeline;
+mod video;
+mod webcam;
+mod window;
+
+pub use adapter::{AdapterSelection, GraphicsAdapter};
+pub use audio::{
+    device::AudioCaptureDevice,
+    effects::{AudioGain, ParseAudioGainError},
+    track_layout::AudioTrackLayout,
+};
+pub use bench::{BenchmarkBuilder, BenchmarkResult};
+pub use container::mp4_repair::{repair_mp4, RepairSummary};
+pub use container::{Container, Metadata};
+pub use displays::{DisplayInfo, DisplaySelection};
+pub use duration::parse_duration;
+pub use error::{RecorderError, RecordingStage};
+pub use gif::encoding_session::GifSettings;
+pub use key_combination::KeyCombination;
+pub use media_info::{read_media_info, MediaInfo};
+pub use pipe::is_pipe_path;
+pub use recorder::{RecorderBuilder, RecordingEvent, RecordingSession};
+pub use region::Region;
+pub use resolution::Resolution;
+pub use rtmp::url::RtmpUrl;
+pub use screenshot::ScreenshotBuilder;
+pub use segment::SegmentLimit;
+pub use selftest::{SelfTestBuilder, SelfTestCheck, SelfTestReport};
+pub use size::parse_size;
+pub use srt::url::SrtUrl;
+pub use stats::{LatencyPercentiles, RecordingStats};
+pub use stream_url::StreamUrl;
+pub use video::{
+    bit_depth::BitDepth,
+    canvas_layout::{CanvasLayout, ParseCanvasLayoutError},
+    codec::VideoCodec,
+    color_range::ColorRange,
+    encoder_device::{get_no_encoders_message, EncoderCapabilities, VideoEncoderDevice},
+    fit_mode::{FitMode, ParseFitModeError},
+    frame_pacing::FramePacing,
+    frame_rate_mode::FrameRateMode,
+    minimize_action::{MinimizeAction, ParseMinimizeActionError},
+    orientation::{Flip, ParseFlipError, ParseRotationError, Rotation},
+    overlay::{
+        position::OverlayPosition, ParseRedactionStyleError, PictureInPictureSettings,
+        PictureInPictureSource, RedactionStyle, WatermarkContent, WatermarkSettings,
+        WebcamSettings,
+    },
+    rate_control::RateControlMode,
+    sample_queue::{ParseQueuePolicyError, QueuePolicy},
+    scale_filter::{ParseScaleFilterError, ScaleFilter},
+    zoom::{ParseZoomFactorError, ZoomFactor},
+};
+pub use webcam::WebcamDevice;
+pub use window::{enumerate_windows, find_process_windows, find_window, WindowInfo};
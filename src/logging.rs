@@ -0,0 +1,60 @@
+use std::{fs::File, io, sync::Mutex};
+
+use tracing_subscriber::{filter::LevelFilter, fmt, prelude::*};
+
+/// Sends what's logged to the console and, if there is one, to the log file.
+/// The log file gets everything down to the level (e.g. debug for a bug
+/// report), while the console stops at info so that it stays readable.
+pub fn init_logging(level: LevelFilter, log_file: Option<&str>) -> io::Result<()> {
+    let file_layer = match log_file {
+        Some(path) => Some(
+            fmt::layer()
+                .with_writer(Mutex::new(File::create(path)?))
+                .with_ansi(false)
+                .with_filter(level),
+        ),
+        None => None,
+    };
+    let console_layer = fmt::layer()
+        .with_writer(io::stderr)
+        .with_ansi(false)
+        .without_time()
+        .with_target(false)
+        .with_filter(get_console_level(level, log_file.is_some()));
+    tracing_subscriber::registry()
+        .with(console_layer)
+        .with(file_layer)
+        .init();
+    Ok(())
+}
+
+fn get_console_level(level: LevelFilter, has_log_file: bool) -> LevelFilter {
+    if has_log_file {
+        level.min(LevelFilter::INFO)
+    } else {
+        level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::filter::LevelFilter;
+
+    use super::get_console_level;
+
+    #[test]
+    fn console_level_test() {
+        assert_eq!(
+            get_console_level(LevelFilter::DEBUG, false),
+            LevelFilter::DEBUG
+        );
+        assert_eq!(
+            get_console_level(LevelFilter::TRACE, true),
+            LevelFilter::INFO
+        );
+        assert_eq!(
+            get_console_level(LevelFilter::WARN, true),
+            LevelFilter::WARN
+        );
+    }
+}
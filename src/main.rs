@@ -1,70 +1,55 @@
 mod args;
-mod capture;
-mod d3d;
-mod displays;
+mod config;
+mod control;
 mod hotkey;
-mod media;
-mod resolution;
-mod video;
+mod http;
+mod json;
+mod logging;
+mod schedule;
+mod shutdown;
+mod status;
 
-use std::{path::Path, time::Duration};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::mpsc::channel,
+    time::{Duration, Instant},
+};
 
 use args::Args;
-use clap::Parser;
-use hotkey::HotKey;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use config::Config;
+use control::{ControlError, ControlRequest, ControlServer};
+use displayrecorder::{
+    enumerate_windows, find_process_windows, find_window, get_no_encoders_message, is_pipe_path,
+    repair_mp4, AdapterSelection, AudioCaptureDevice, BenchmarkBuilder, BenchmarkResult, Container,
+    DisplayInfo, EncoderCapabilities, GifSettings, GraphicsAdapter, Metadata,
+    PictureInPictureSettings, PictureInPictureSource, RecorderBuilder, RecorderError,
+    RecordingSession, Region, ScreenshotBuilder, SelfTestBuilder, VideoCodec, VideoEncoderDevice,
+    WatermarkContent, WatermarkSettings, WebcamDevice, WebcamSettings,
+};
+use hotkey::{HotKeyBinding, HotKeyListener};
+use json::JsonValue;
+use logging::init_logging;
+use schedule::ClockTime;
+use shutdown::{is_stop_requested, report_progress, ShutdownGuard};
+use status::StatusLine;
+use tracing::warn;
 use windows::{
-    core::{Result, RuntimeName, HSTRING},
-    Foundation::Metadata::ApiInformation,
-    Graphics::{
-        Capture::{GraphicsCaptureItem, GraphicsCaptureSession},
-        SizeInt32,
-    },
-    Storage::{
-        CreationCollisionOption, FileAccessMode, StorageFolder, Streams::IRandomAccessStream,
+    core::Result,
+    Win32::System::{
+        Diagnostics::Debug::{DebugBreak, IsDebuggerPresent},
+        Threading::GetCurrentProcessId,
+        WinRT::{RoInitialize, RO_INIT_MULTITHREADED},
     },
-    Win32::{
-        Foundation::{HWND, MAX_PATH},
-        Graphics::Direct3D11::ID3D11Device,
-        Media::MediaFoundation::{MFStartup, MFSTARTUP_FULL},
-        Storage::FileSystem::GetFullPathNameW,
-        System::{
-            Diagnostics::Debug::{DebugBreak, IsDebuggerPresent},
-            Threading::GetCurrentProcessId,
-            WinRT::{RoInitialize, RO_INIT_MULTITHREADED},
-        },
-        UI::{
-            Input::KeyboardAndMouse::{MOD_CONTROL, MOD_SHIFT},
-            WindowsAndMessaging::{DispatchMessageW, GetMessageW, MSG, WM_HOTKEY},
-        },
-    },
-};
-
-use crate::{
-    capture::create_capture_item_for_monitor,
-    d3d::create_d3d_device,
-    displays::get_display_handle_from_index,
-    media::MF_VERSION,
-    resolution::Resolution,
-    video::{encoder_device::VideoEncoderDevice, encoding_session::VideoEncodingSession},
 };
 
-fn run(
-    display_index: usize,
-    output_path: &str,
-    bit_rate: u32,
-    frame_rate: u32,
-    resolution: Resolution,
-    encoder_index: usize,
-    verbose: bool,
-    wait_for_debugger: bool,
-    console_mode: bool,
-) -> Result<()> {
+fn run(args: &Args) -> std::result::Result<(), RecorderError> {
     unsafe {
         RoInitialize(RO_INIT_MULTITHREADED)?;
     }
-    unsafe { MFStartup(MF_VERSION, MFSTARTUP_FULL)? }
 
-    if wait_for_debugger {
+    if args.wait_for_debugger {
         let pid = unsafe { GetCurrentProcessId() };
         println!("Waiting for a debugger to attach (PID: {})...", pid);
         loop {
@@ -78,104 +63,361 @@ fn run(
         }
     }
 
-    // Check to make sure Windows.Graphics.Capture is available
-    if !required_capture_features_supported()? {
-        exit_with_error("The required screen capture features are not supported on this device for this release of Windows!\nPlease update your operating system (minimum: Windows 10 Version 1903, Build 18362).");
+    let console_mode = args.console_mode;
+    // Dropped after the session, so that it's finalized before exiting
+    let _shutdown = ShutdownGuard::new()?;
+    // Everything is set up before waiting so that the first frames aren't missed
+    let mut session = create_recording_session(args, &args.output_file)?;
+    let start_delay = get_start_delay(args);
+    if let Some(start_delay) = start_delay {
+        session.start_capture()?;
+        wait_for_start(start_delay);
+        if is_stop_requested() {
+            // Nothing was recorded yet
+            return Ok(());
+        }
+    }
+    if is_remote_controlled(args) {
+        if start_delay.is_some() {
+            println!("Starting recording...");
+            session.start()?;
+        }
+        // Recordings are stopped through commands
+        return handle_control_commands(args, &mut session, start_delay.is_some());
+    }
+    if let Some(replay) = args.replay {
+        println!(
+            "Recording, the last {} seconds will be saved when the recording is stopped...",
+            replay.as_secs_f64()
+        );
+        session.start()?;
+        if !console_mode {
+            handle_hot_keys(args, &mut session, true)?;
+        } else {
+            pause(args, &session);
+        }
+        println!("Saving the replay...");
+    } else if !console_mode {
+        if start_delay.is_some() {
+            println!("Starting recording...");
+            session.start()?;
+        }
+        handle_hot_keys(args, &mut session, start_delay.is_some())?;
+        println!("Stopping recording...");
+    } else {
+        session.start()?;
+        pause(args, &session);
+    }
+    stop_recording(args, &mut session)
+}
+
+fn stop_recording(
+    args: &Args,
+    session: &mut RecordingSession,
+) -> std::result::Result<(), RecorderError> {
+    session.stop()?;
+    let stats = get_stats_json(session);
+    if args.stats_json {
+        println!("{}", stats);
+    } else {
+        print_stats(session);
     }
+    Ok(())
+}
 
-    if verbose {
+fn print_stats(session: &RecordingSession) {
+    let elapsed = session.elapsed();
+    let stats = session.stats();
+    println!("Recording statistics:");
+    println!("  Duration:        {:.1}s", elapsed.as_secs_f64());
+    // GIF recordings aren't counted
+    if stats.frames > 0 {
         println!(
-            "Using index \"{}\" and path \"{}\".",
-            display_index, output_path
+            "  Frames:          {} encoded, {} captured, {} dropped",
+            stats.frames, stats.captured_frames, stats.dropped_frames
+        );
+        if stats.late_frames > 0 || stats.duplicated_frames > 0 {
+            println!(
+                "  Pacing:          {} late, {} duplicated",
+                stats.late_frames, stats.duplicated_frames
+            );
+        }
+        let latency = stats.encode_latency;
+        println!(
+            "  Encode latency:  {:.1}ms (p50), {:.1}ms (p95), {:.1}ms (p99)",
+            latency.p50.as_secs_f64() * 1000.0,
+            latency.p95.as_secs_f64() * 1000.0,
+            latency.p99.as_secs_f64() * 1000.0
+        );
+        println!(
+            "  Average bitrate: {:.1} Mbps",
+            stats.bit_rate(elapsed) as f64 / 1_000_000.0
         );
     }
+    println!(
+        "  File size:       {:.1} MB",
+        get_file_size(session) as f64 / 1_000_000.0
+    );
+}
 
-    // Get the display handle using the provided index
-    let display_handle = get_display_handle_from_index(display_index)
-        .expect("The provided display index was out of bounds!");
-    let item = create_capture_item_for_monitor(display_handle)?;
+// Streams and pipes don't leave files behind, so they don't count
+fn get_file_size(session: &RecordingSession) -> u64 {
+    session
+        .output_paths()
+        .iter()
+        .filter_map(|path| std::fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
 
-    // Resolve encoding settings
-    let resolution = if let Some(resolution) = resolution.get_size() {
-        resolution
-    } else {
-        item.Size()?
-    };
-    let bit_rate = bit_rate * 1000000;
-    let encoder_devices = VideoEncoderDevice::enumerate()?;
-    if encoder_devices.is_empty() {
-        exit_with_error("No hardware H264 encoders found!");
+fn get_stats_json(session: &RecordingSession) -> JsonValue {
+    let elapsed = session.elapsed();
+    let stats = session.stats();
+    let milliseconds = |duration: Duration| JsonValue::Number(duration.as_secs_f64() * 1000.0);
+    JsonValue::object([
+        ("elapsed", JsonValue::Number(elapsed.as_secs_f64())),
+        (
+            "captured_frames",
+            JsonValue::Number(stats.captured_frames as f64),
+        ),
+        ("frames", JsonValue::Number(stats.frames as f64)),
+        (
+            "dropped_frames",
+            JsonValue::Number(stats.dropped_frames as f64),
+        ),
+        ("late_frames", JsonValue::Number(stats.late_frames as f64)),
+        (
+            "duplicated_frames",
+            JsonValue::Number(stats.duplicated_frames as f64),
+        ),
+        (
+            "encode_latency_ms",
+            JsonValue::object([
+                ("p50", milliseconds(stats.encode_latency.p50)),
+                ("p95", milliseconds(stats.encode_latency.p95)),
+                ("p99", milliseconds(stats.encode_latency.p99)),
+            ]),
+        ),
+        (
+            "bit_rate",
+            JsonValue::Number(stats.bit_rate(elapsed) as f64),
+        ),
+        (
+            "file_size",
+            JsonValue::Number(get_file_size(session) as f64),
+        ),
+        ("output_paths", get_output_paths(session)),
+    ])
+}
+
+fn create_recording_session(
+    args: &Args,
+    output_file: &str,
+) -> std::result::Result<RecordingSession, RecorderError> {
+    let mut builder = RecorderBuilder::new(output_file)
+        .displays(&args.display)
+        .composite(args.composite)
+        .layout(args.layout)
+        .capture_cursor(!args.no_cursor)
+        .bit_rate(args.bit_rate)
+        .frame_rate_mode(args.frame_rate_mode)
+        .frame_pacing(args.frame_pacing)
+        .queue_size(args.queue_size)
+        .queue_policy(args.queue_policy)
+        .hdr(args.hdr)
+        .scene_keyframes(args.scene_keyframes)
+        .bit_depth(args.bit_depth)
+        .color_range(args.color_range)
+        .resolution(args.resolution)
+        .auto_downscale(args.auto_downscale)
+        .test_pattern(args.test_pattern)
+        .rotation(args.rotate)
+        .fit_mode(args.fit)
+        .codec(args.codec)
+        .system_audio(args.system_audio)
+        .mic_noise_suppression(args.mic_noise_suppression)
+        .audio_tracks(args.audio_tracks)
+        .audio_sync_tolerance(args.audio_sync_tolerance)
+        .no_video(args.no_video)
+        .gif_settings(GifSettings {
+            frame_rate: args.gif_frame_rate,
+            max_width: args.gif_max_width,
+        })
+        .preview(args.preview)
+        .thumbnail(args.thumbnail)
+        .clock_overlay(args.clock_overlay)
+        .show_clicks(args.show_clicks)
+        .show_keys(args.show_keys)
+        .splittable(is_remote_controlled(args))
+        .allow_software_encoder(args.allow_software_encoder)
+        .fragmented(args.fragmented)
+        .chapters(args.chapters)
+        .mark_device_loss(args.mark_device_loss)
+        .verify(args.verify)
+        .record_on_change(args.record_on_change)
+        .pre_roll(args.pre_roll)
+        .post_roll(args.post_roll)
+        .encode_later(args.encode_later)
+        .on_encode_progress(report_progress)
+        .metadata(Metadata {
+            title: args.title.clone(),
+            author: args.author.clone(),
+            comment: args.comment.clone(),
+        })
+        .verbose(args.verbose | args.wait_for_debugger);
+    if let Some(format) = args.format {
+        builder = builder.format(format);
+    }
+    if let Some(encoder) = args.encoder {
+        builder = builder.encoder(encoder);
+    }
+    if let Some(adapter) = &args.adapter {
+        builder = builder.adapter(adapter.clone());
     }
-    if verbose {
-        println!("Encoders ({}):", encoder_devices.len());
-        for encoder_device in &encoder_devices {
-            println!("  {}", encoder_device.display_name());
+    if let Some(window) = &args.window {
+        // Find the window using the provided title or handle
+        let window_handle = if let Some(window_handle) = find_window(window) {
+            window_handle
+        } else {
+            exit_with_error("Could not find a window matching the provided title or handle!");
+        };
+        builder = builder.window(window_handle);
+    }
+    if let Some(process) = &args.process {
+        let windows = find_process_windows(process);
+        if windows.is_empty() {
+            exit_with_error("Could not find any windows for the provided process id or name!");
         }
+        builder = builder.process_windows(windows);
+    }
+    if !args.blur_region.is_empty() {
+        builder = builder.blur_regions(args.blur_region.clone());
+    }
+    if !args.exclude_window.is_empty() {
+        let mut excluded_windows = Vec::new();
+        for window in &args.exclude_window {
+            match find_window(window) {
+                Some(window_handle) => excluded_windows.push(window_handle),
+                None => exit_with_error(&format!(
+                    "Could not find a window to exclude matching \"{}\"!",
+                    window
+                )),
+            }
+        }
+        builder = builder.exclude_windows(excluded_windows);
+    }
+    if !args.redact_app.is_empty() {
+        builder = builder.redact_apps(args.redact_app.clone());
+    }
+    builder = builder.redaction_style(args.redact_style);
+    if let Some(flip) = args.flip {
+        builder = builder.flip(flip);
+    }
+    if let Some(scale_filter) = args.scale_filter {
+        builder = builder.scale_filter(scale_filter);
+    }
+    if let Some(zoom_follow) = args.zoom_follow {
+        builder = builder.zoom_follow(zoom_follow);
+    }
+    if let Some(on_minimize) = args.on_minimize {
+        builder = builder.on_minimize(on_minimize);
+    }
+    if let Some(on_secure_desktop) = args.on_secure_desktop {
+        builder = builder.on_secure_desktop(on_secure_desktop);
+    }
+    if let Some(frame_rate) = args.frame_rate {
+        builder = builder.frame_rate(frame_rate);
+    }
+    if let Some(max_fps) = args.max_fps {
+        builder = builder.max_frame_rate(max_fps);
     }
-    let encoder_device = if let Some(encoder_device) = encoder_devices.get(encoder_index) {
-        encoder_device
+    if let Some(idle_fps) = args.idle_fps {
+        builder = builder
+            .idle_frame_rate(idle_fps)
+            .idle_after(args.idle_after);
+    }
+    if let Some(rate_control) = args.rate_control {
+        builder = builder.rate_control(rate_control);
+    }
+    if let Some(quality) = args.quality {
+        builder = builder.quality(quality);
+    }
+    if let Some(gop) = args.gop {
+        builder = builder.gop_size(gop);
+    }
+    if let Some(bframes) = args.bframes {
+        builder = builder.b_frames(bframes);
+    }
+    if let Some(region) = args.region {
+        builder = builder.region(region);
+    }
+    if let Some(audio_process) = args.audio_process {
+        builder = builder.audio_process(audio_process);
+    }
+    if let Some(scratch_dir) = &args.scratch_dir {
+        builder = builder.scratch_dir(scratch_dir.as_str());
+    }
+    if let Some(mic) = &args.mic {
+        builder = builder.mic(mic.as_str());
+    }
+    if let Some(mic_gain) = args.mic_gain {
+        builder = builder.mic_gain(mic_gain);
+    }
+    if let Some(segment) = args.segment {
+        builder = builder.segment(segment);
+    }
+    if let Some(replay) = args.replay {
+        builder = builder.replay(replay);
+    }
+    if let Some(url) = &args.stream {
+        builder = builder.stream(url.clone());
+    }
+    if let Some(directory) = &args.hls {
+        builder = builder.hls(directory.as_str());
+    }
+    if let Some(name) = &args.ndi {
+        builder = builder.ndi(name.as_str()).ndi_only(args.ndi_only);
+    }
+    let watermark_content = if let Some(text) = &args.watermark {
+        Some(WatermarkContent::Text(text.clone()))
     } else {
-        exit_with_error("Encoder index is out of bounds!");
+        args.watermark_image.clone().map(WatermarkContent::Image)
     };
-    if verbose {
-        println!("Using: {}", encoder_device.display_name());
+    if let Some(content) = watermark_content {
+        builder = builder.watermark(WatermarkSettings {
+            content,
+            position: args.watermark_position,
+            opacity: args.watermark_opacity,
+        });
     }
-
-    // Create our file
-    let path = unsafe {
-        let mut new_path = vec![0u16; MAX_PATH as usize];
-        let length = GetFullPathNameW(&HSTRING::from(output_path), Some(&mut new_path), None);
-        new_path.resize(length as usize, 0);
-        String::from_utf16(&new_path).unwrap()
-    };
-    let path = Path::new(&path);
-    let parent_folder_path = path.parent().unwrap();
-    let parent_folder = StorageFolder::GetFolderFromPathAsync(&HSTRING::from(
-        parent_folder_path.as_os_str().to_str().unwrap(),
-    ))?
-    .get()?;
-    let file_name = path.file_name().unwrap();
-    let file = parent_folder
-        .CreateFileAsync(
-            &HSTRING::from(file_name.to_str().unwrap()),
-            CreationCollisionOption::ReplaceExisting,
-        )?
-        .get()?;
-
-    // Start the recording
-    {
-        let stream = file.OpenAsync(FileAccessMode::ReadWrite)?.get()?;
-        let d3d_device = create_d3d_device()?;
-        let mut session = create_encoding_session(
-            d3d_device,
-            item,
-            encoder_device,
-            resolution,
-            bit_rate,
-            frame_rate,
-            stream,
-        )?;
-        if !console_mode {
-            let mut is_recording = false;
-            pump_messages(|| -> Result<bool> {
-                Ok(if !is_recording {
-                    is_recording = true;
-                    println!("Starting recording...");
-                    session.start()?;
-                    false
-                } else {
-                    true
-                })
-            })?;
-            println!("Stopping recording...");
-        } else {
-            session.start()?;
-            pause();
+    if !args.show_keys_allow.is_empty() {
+        builder = builder.key_allowlist(args.show_keys_allow.clone());
+    }
+    if let Some(webcam) = &args.webcam {
+        builder = builder.webcam(WebcamSettings {
+            device: webcam.clone(),
+            position: args.webcam_position,
+            size: args.webcam_size,
+        });
+    }
+    let pip_source = if let Some(window) = &args.pip_window {
+        match find_window(window) {
+            Some(window_handle) => Some(PictureInPictureSource::Window(window_handle)),
+            None => exit_with_error(
+                "Could not find a window for picture-in-picture matching the provided title or handle!",
+            ),
         }
-        session.stop()?;
+    } else {
+        args.pip_display.map(PictureInPictureSource::Display)
+    };
+    if let Some(source) = pip_source {
+        builder = builder.picture_in_picture(PictureInPictureSettings {
+            source,
+            position: args.pip_position,
+            size: args.pip_size,
+        });
     }
-
-    Ok(())
+    builder.build()
 }
 
 fn main() {
@@ -186,100 +428,708 @@ fn main() {
         std::process::exit(0);
     }
 
-    let args = Args::parse();
+    let args = parse_args();
+    if init_logging(args.log_level, args.log_file.as_deref()).is_err() {
+        exit_with_error("Could not create the log file!");
+    }
 
-    if let Some(command) = args.command {
+    if let Some(command) = &args.command {
         match command {
-            args::Commands::EnumEncoders => enum_encoders().unwrap(),
+            args::Commands::EnumEncoders {
+                codec,
+                adapter,
+                allow_software_encoder,
+            } => enum_encoders(*codec, adapter.as_ref(), *allow_software_encoder).unwrap(),
+            args::Commands::Probe {
+                allow_software_encoder,
+                json,
+            } => probe_encoders(*allow_software_encoder, *json).unwrap(),
+            args::Commands::Bench {
+                codec,
+                resolution,
+                frame_rate,
+                bit_rate,
+                duration,
+                allow_software_encoder,
+                json,
+            } => {
+                let benchmark = BenchmarkBuilder::new()
+                    .codec(*codec)
+                    .resolution(*resolution)
+                    .frame_rate(*frame_rate)
+                    .bit_rate(*bit_rate)
+                    .duration(*duration)
+                    .allow_software_encoder(*allow_software_encoder);
+                if let Err(error) = run_benchmark(benchmark, *frame_rate, *json) {
+                    exit_with_error(&error.to_string());
+                }
+            }
+            args::Commands::Selftest {
+                codec,
+                resolution,
+                frame_rate,
+                duration,
+                allow_software_encoder,
+                output_file,
+            } => {
+                // The recording is only kept if it was asked for
+                let output_path = output_file.clone().unwrap_or_else(|| {
+                    std::env::temp_dir()
+                        .join("displayrecorder-selftest.mp4")
+                        .to_string_lossy()
+                        .into_owned()
+                });
+                let self_test = SelfTestBuilder::new(output_path.as_str())
+                    .codec(*codec)
+                    .resolution(*resolution)
+                    .frame_rate(*frame_rate)
+                    .duration(*duration)
+                    .allow_software_encoder(*allow_software_encoder);
+                let result = run_self_test(self_test);
+                if output_file.is_none() {
+                    let _ = std::fs::remove_file(&output_path);
+                }
+                match result {
+                    Ok(true) => println!("Self-test passed!"),
+                    Ok(false) => exit_with_error("Self-test failed!"),
+                    Err(error) => exit_with_error(&error.to_string()),
+                }
+            }
+            args::Commands::ListDisplays => list_displays(),
+            args::Commands::ListWindows => list_windows(),
+            args::Commands::EnumAdapters => enum_adapters().unwrap(),
+            args::Commands::EnumAudioDevices => enum_audio_devices().unwrap(),
+            args::Commands::EnumWebcams => enum_webcams().unwrap(),
+            args::Commands::Screenshot {
+                output_file,
+                display,
+                window,
+                region,
+                no_cursor,
+            } => take_screenshot(
+                output_file,
+                *display,
+                window.as_deref(),
+                *region,
+                *no_cursor,
+            )
+            .unwrap(),
+            args::Commands::Repair {
+                input_file,
+                output_file,
+            } => repair(input_file, output_file.as_deref()),
         }
         return;
     }
 
-    let monitor_index: usize = args.display;
-    let output_path = args.output_file.as_str();
-    let verbose = args.verbose;
-    let wait_for_debugger = args.wait_for_debugger;
-    let console_mode = args.console_mode;
-    let bit_rate: u32 = args.bit_rate;
-    let frame_rate: u32 = args.frame_rate;
-    let resolution: Resolution = args.resolution;
-    let encoder_index: usize = args.encoder;
-
-    // Validate some of the params
-    if !validate_path(output_path) {
+    // An explicit format wins over the extension of the output file
+    let output_path = if let Some(format) = args.format {
+        Path::new(&args.output_file).with_extension(format.extension())
+    } else {
+        Path::new(&args.output_file).to_owned()
+    };
+    if !is_pipe_path(&args.output_file) && !validate_path(output_path) {
         exit_with_error("Invalid path specified!");
     }
+    let hot_keys = get_hot_keys(&args);
+    if (1..hot_keys.len()).any(|i| hot_keys[..i].contains(&hot_keys[i])) {
+        exit_with_error("The toggle, pause, keyframe, and chapter hotkeys must be different!");
+    }
+    if args.start_at.is_some() && args.delay.is_some() {
+        exit_with_error("Only one of --start-at and --delay can be used!");
+    }
+    if args.watermark.is_some() && args.watermark_image.is_some() {
+        exit_with_error("Only one of --watermark and --watermark-image can be used!");
+    }
+    if args.stop_on_silence.is_some()
+        && !args.system_audio
+        && args.audio_process.is_none()
+        && args.mic.is_none()
+    {
+        exit_with_error(
+            "--stop-on-silence needs audio to listen to! Use --system-audio, --audio-process, or --mic.",
+        );
+    }
 
-    let result = run(
-        monitor_index,
-        output_path,
-        bit_rate,
-        frame_rate,
-        resolution,
-        encoder_index,
-        verbose | wait_for_debugger,
-        wait_for_debugger,
-        console_mode,
-    );
-
-    // We do this for nicer HRESULT printing when errors occur.
-    if let Err(error) = result {
-        error.code().unwrap();
+    // The error says what failed, and where Windows was involved, with which HRESULT
+    if let Err(error) = run(&args) {
+        exit_with_error(&error.to_string());
     }
 }
 
-fn pause() {
+// The settings of the config file are inserted before the command line
+// arguments, leaving out the options that the command line provides.
+fn parse_args() -> Args {
+    let cli_args: Vec<String> = std::env::args().collect();
+    let command = Args::command();
+    let matches = command.clone().get_matches_from(&cli_args);
+    let config_path = match matches.get_one::<String>("config") {
+        Some(path) if matches.subcommand().is_none() => path.clone(),
+        _ => return Args::from_arg_matches(&matches).unwrap_or_else(|error| error.exit()),
+    };
+    let config_args = std::fs::read_to_string(&config_path)
+        .map_err(|error| error.to_string())
+        .and_then(|contents| {
+            contents
+                .parse::<Config>()
+                .map_err(|error| error.to_string())
+        })
+        .and_then(|config| config.to_args(&command, &matches))
+        .unwrap_or_else(|message| {
+            exit_with_error(&format!(
+                "Invalid config file \"{}\"! {}",
+                config_path, message
+            ))
+        });
+    let mut args = vec![cli_args[0].clone()];
+    args.extend(config_args);
+    args.extend(cli_args.into_iter().skip(1));
+    Args::parse_from(args)
+}
+
+fn pause(args: &Args, session: &RecordingSession) {
     println!("Press ENTER to stop recording...");
-    std::io::Read::read(&mut std::io::stdin(), &mut [0]).unwrap();
+    let (sender, receiver) = channel();
+    std::thread::spawn(move || {
+        std::io::Read::read(&mut std::io::stdin(), &mut [0]).unwrap();
+        let _ = sender.send(());
+    });
+    let mut status = StatusLine::new();
+    while let Some(wait_time) = update_status(args, session, &mut status) {
+        if receiver.recv_timeout(wait_time).is_ok() || is_stop_requested() {
+            status.clear();
+            return;
+        }
+    }
+}
+
+fn get_start_delay(args: &Args) -> Option<Duration> {
+    if let Some(start_at) = args.start_at {
+        Some(start_at.duration_from(ClockTime::now()))
+    } else {
+        args.delay
+    }
+}
+
+fn wait_for_start(delay: Duration) {
+    let start_time = Instant::now() + delay;
+    loop {
+        let remaining = start_time.saturating_duration_since(Instant::now());
+        if remaining.is_zero() || is_stop_requested() {
+            println!();
+            return;
+        }
+        print!(
+            "\rStarting in {}s...          ",
+            get_countdown_seconds(remaining)
+        );
+        let _ = std::io::stdout().flush();
+        std::thread::sleep(remaining.min(COUNTDOWN_INTERVAL));
+    }
+}
+
+// Round up so that the countdown ends at 1s rather than 0s
+pub fn get_countdown_seconds(remaining: Duration) -> u64 {
+    remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0)
 }
 
-fn enum_encoders() -> Result<()> {
-    let encoder_devices = VideoEncoderDevice::enumerate()?;
+// How often the countdowns and the status line are updated
+const COUNTDOWN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Updates the status line while recording. Returns how long to wait for
+/// input before the next update, or None once the maximum duration (if
+/// any) has been reached, the disk is almost full, or the audio has been
+/// silent for too long.
+fn update_status(
+    args: &Args,
+    session: &RecordingSession,
+    status: &mut StatusLine,
+) -> Option<Duration> {
+    let remaining = args
+        .duration
+        .map(|duration| duration.saturating_sub(session.elapsed()));
+    if remaining == Some(Duration::ZERO) {
+        status.clear();
+        println!("Reached the maximum duration.");
+        return None;
+    }
+    // Stopping while there's space left lets the file be finalized
+    if let Some(free_space) = session.free_space() {
+        if free_space < args.min_free_space {
+            status.clear();
+            println!(
+                "The disk is almost full ({} MB left), stopping the recording.",
+                free_space >> 20
+            );
+            return None;
+        }
+    }
+    if let (Some(limit), Some(silence)) = (args.stop_on_silence, session.silence_duration()) {
+        if silence >= limit {
+            status.clear();
+            println!("No audio was heard for {}s.", silence.as_secs());
+            return None;
+        }
+    }
+    status.update(session, remaining);
+    Some(remaining.map_or(COUNTDOWN_INTERVAL, |remaining| {
+        remaining.min(COUNTDOWN_INTERVAL)
+    }))
+}
+
+fn enum_encoders(
+    codec: VideoCodec,
+    adapter: Option<&AdapterSelection>,
+    allow_software_encoder: bool,
+) -> Result<()> {
+    let mut encoder_devices = if let Some(adapter) = adapter {
+        let adapter = if let Some(adapter) = GraphicsAdapter::find(adapter)? {
+            adapter
+        } else {
+            exit_with_error("Could not find an adapter matching the provided index or name!");
+        };
+        VideoEncoderDevice::enumerate_for_adapter(codec, &adapter)?
+    } else {
+        VideoEncoderDevice::enumerate(codec)?
+    };
+    if allow_software_encoder {
+        encoder_devices.extend(VideoEncoderDevice::enumerate_software(codec)?);
+    }
     if encoder_devices.is_empty() {
-        exit_with_error("No hardware H264 encoders found!");
+        exit_with_error(&get_no_encoders_message(codec)?);
     }
     println!("Encoders ({}):", encoder_devices.len());
     for (i, encoder_device) in encoder_devices.iter().enumerate() {
-        println!("  {} - {}", i, encoder_device.display_name());
+        if encoder_device.is_hardware() {
+            println!("  {} - {}", i, encoder_device.display_name());
+        } else {
+            println!("  {} - {} (software)", i, encoder_device.display_name());
+        }
+    }
+    Ok(())
+}
+
+fn probe_encoders(allow_software_encoder: bool, json: bool) -> Result<()> {
+    // An encoder that supports several codecs is listed once
+    let mut encoder_devices: Vec<VideoEncoderDevice> = Vec::new();
+    for codec in VideoCodec::ALL {
+        let mut codec_encoder_devices = VideoEncoderDevice::enumerate(codec)?;
+        if allow_software_encoder {
+            codec_encoder_devices.extend(VideoEncoderDevice::enumerate_software(codec)?);
+        }
+        for encoder_device in codec_encoder_devices {
+            if !encoder_devices
+                .iter()
+                .any(|other| other.display_name() == encoder_device.display_name())
+            {
+                encoder_devices.push(encoder_device);
+            }
+        }
+    }
+    if encoder_devices.is_empty() && !json {
+        exit_with_error("No encoders found!");
+    }
+
+    let mut probed = Vec::new();
+    for encoder_device in &encoder_devices {
+        match encoder_device.probe() {
+            Ok(capabilities) => probed.push((encoder_device, capabilities)),
+            Err(error) => warn!(
+                "Could not probe {} ({}).",
+                encoder_device.display_name(),
+                error.message()
+            ),
+        }
+    }
+    if json {
+        let encoders = probed
+            .iter()
+            .map(|(encoder_device, capabilities)| {
+                get_capabilities_json(
+                    encoder_device.display_name(),
+                    encoder_device.is_hardware(),
+                    capabilities,
+                )
+            })
+            .collect();
+        println!("{}", JsonValue::Array(encoders));
+    } else {
+        println!("Encoders ({}):", probed.len());
+        for (encoder_device, capabilities) in &probed {
+            print_capabilities(
+                encoder_device.display_name(),
+                encoder_device.is_hardware(),
+                capabilities,
+            );
+        }
     }
     Ok(())
 }
 
-fn create_encoding_session(
-    d3d_device: ID3D11Device,
-    item: GraphicsCaptureItem,
-    encoder_device: &VideoEncoderDevice,
-    resolution: SizeInt32,
-    bit_rate: u32,
+fn print_capabilities(display_name: &str, hardware: bool, capabilities: &EncoderCapabilities) {
+    let yes_no = |value: bool| if value { "yes" } else { "no" };
+    let join = |values: Vec<String>| {
+        if values.is_empty() {
+            "none".to_owned()
+        } else {
+            values.join(", ")
+        }
+    };
+    if hardware {
+        println!("  {}", display_name);
+    } else {
+        println!("  {} (software)", display_name);
+    }
+    println!(
+        "    Codecs: {}",
+        join(
+            capabilities
+                .codecs
+                .iter()
+                .map(|codec| codec.to_string())
+                .collect()
+        )
+    );
+    if let Some(resolution) = capabilities.max_resolution {
+        println!(
+            "    Max resolution: {}x{}",
+            resolution.Width, resolution.Height
+        );
+    } else {
+        println!("    Max resolution: unknown");
+    }
+    println!(
+        "    Rate control: {}",
+        join(
+            capabilities
+                .rate_control_modes
+                .iter()
+                .map(|mode| mode.to_string())
+                .collect()
+        )
+    );
+    println!("    10-bit: {}", yes_no(capabilities.ten_bit));
+    println!("    B-frames: {}", yes_no(capabilities.b_frames));
+}
+
+fn get_capabilities_json(
+    display_name: &str,
+    hardware: bool,
+    capabilities: &EncoderCapabilities,
+) -> JsonValue {
+    let strings =
+        |values: Vec<String>| JsonValue::Array(values.into_iter().map(JsonValue::String).collect());
+    JsonValue::object([
+        ("name", JsonValue::string(display_name)),
+        ("hardware", JsonValue::Bool(hardware)),
+        (
+            "codecs",
+            strings(
+                capabilities
+                    .codecs
+                    .iter()
+                    .map(|codec| codec.to_string())
+                    .collect(),
+            ),
+        ),
+        (
+            "max_resolution",
+            capabilities
+                .max_resolution
+                .map_or(JsonValue::Null, |resolution| {
+                    JsonValue::object([
+                        ("width", JsonValue::Number(resolution.Width as f64)),
+                        ("height", JsonValue::Number(resolution.Height as f64)),
+                    ])
+                }),
+        ),
+        (
+            "rate_control",
+            strings(
+                capabilities
+                    .rate_control_modes
+                    .iter()
+                    .map(|mode| mode.to_string())
+                    .collect(),
+            ),
+        ),
+        ("ten_bit", JsonValue::Bool(capabilities.ten_bit)),
+        ("b_frames", JsonValue::Bool(capabilities.b_frames)),
+    ])
+}
+
+fn run_benchmark(
+    benchmark: BenchmarkBuilder,
     frame_rate: u32,
-    stream: IRandomAccessStream,
-) -> Result<VideoEncodingSession> {
-    let result = VideoEncodingSession::new(
-        d3d_device,
-        item,
-        encoder_device,
-        resolution,
-        bit_rate,
-        frame_rate,
-        stream,
+    json: bool,
+) -> std::result::Result<(), RecorderError> {
+    unsafe {
+        RoInitialize(RO_INIT_MULTITHREADED)?;
+    }
+    if !json {
+        println!("Benchmarking the encoders...");
+    }
+    let results = benchmark.run()?;
+    if results.is_empty() && !json {
+        exit_with_error("No encoders found!");
+    }
+
+    if json {
+        let results = results
+            .iter()
+            .map(|result| get_benchmark_json(result, frame_rate))
+            .collect();
+        println!("{}", JsonValue::Array(results));
+        return Ok(());
+    }
+    let name_width = results
+        .iter()
+        .map(|result| result.encoder.len())
+        .chain(["Encoder".len()])
+        .max()
+        .unwrap();
+    println!(
+        "{:<width$}  {:>8}  {:>9}  {:>9}  {:>9}  {:>9}",
+        "Encoder",
+        "FPS",
+        "p50",
+        "p95",
+        "p99",
+        "Real time",
+        width = name_width
+    );
+    let milliseconds = |duration: Duration| format!("{:.1}ms", duration.as_secs_f64() * 1000.0);
+    for result in &results {
+        if let Some(error) = &result.error {
+            println!(
+                "{:<width$}  failed: {}",
+                result.encoder,
+                error,
+                width = name_width
+            );
+            continue;
+        }
+        println!(
+            "{:<width$}  {:>8.1}  {:>9}  {:>9}  {:>9}  {:>9}",
+            result.encoder,
+            result.frames_per_second(),
+            milliseconds(result.latency.p50),
+            milliseconds(result.latency.p95),
+            milliseconds(result.latency.p99),
+            if result.keeps_up(frame_rate) {
+                "yes"
+            } else {
+                "no"
+            },
+            width = name_width
+        );
+    }
+    Ok(())
+}
+
+fn get_benchmark_json(result: &BenchmarkResult, frame_rate: u32) -> JsonValue {
+    let milliseconds = |duration: Duration| JsonValue::Number(duration.as_secs_f64() * 1000.0);
+    JsonValue::object([
+        ("name", JsonValue::string(result.encoder.as_str())),
+        (
+            "adapter",
+            result.adapter.as_ref().map_or(JsonValue::Null, |adapter| {
+                JsonValue::string(adapter.as_str())
+            }),
+        ),
+        ("hardware", JsonValue::Bool(result.adapter.is_some())),
+        (
+            "error",
+            result
+                .error
+                .as_ref()
+                .map_or(JsonValue::Null, |error| JsonValue::string(error.as_str())),
+        ),
+        ("frames", JsonValue::Number(result.frames as f64)),
+        ("elapsed", JsonValue::Number(result.elapsed.as_secs_f64())),
+        ("fps", JsonValue::Number(result.frames_per_second())),
+        ("bytes", JsonValue::Number(result.bytes as f64)),
+        (
+            "encode_latency_ms",
+            JsonValue::object([
+                ("p50", milliseconds(result.latency.p50)),
+                ("p95", milliseconds(result.latency.p95)),
+                ("p99", milliseconds(result.latency.p99)),
+            ]),
+        ),
+        ("real_time", JsonValue::Bool(result.keeps_up(frame_rate))),
+    ])
+}
+
+// Returns whether every check passed
+fn run_self_test(self_test: SelfTestBuilder) -> std::result::Result<bool, RecorderError> {
+    unsafe {
+        RoInitialize(RO_INIT_MULTITHREADED)?;
+    }
+    println!("Recording a test pattern...");
+    let report = self_test.run()?;
+    println!(
+        "{:<10}  {:>10}  {:>10}  {:>6}",
+        "Check", "Expected", "Actual", "Result"
     );
-    if result.is_err() {
-        println!("Error during encoder setup, try another set of encoding settings.");
+    for check in &report.checks {
+        println!(
+            "{:<10}  {:>10}  {:>10}  {:>6}",
+            check.name,
+            check.expected,
+            check.actual,
+            if check.passed { "ok" } else { "failed" }
+        );
     }
-    result
+    Ok(report.passed())
 }
 
-fn validate_path<P: AsRef<Path>>(path: P) -> bool {
-    let path = path.as_ref();
-    let mut valid = true;
-    if let Some(extension) = path.extension() {
-        if extension != "mp4" {
-            valid = false;
+fn list_displays() {
+    let displays = DisplayInfo::enumerate();
+    if displays.is_empty() {
+        exit_with_error("No displays found!");
+    }
+    println!("Displays ({}):", displays.len());
+    for (i, display) in displays.iter().enumerate() {
+        println!("  {}", format_display(i, display));
+    }
+}
+
+fn format_display(index: usize, display: &DisplayInfo) -> String {
+    let bounds = display.bounds;
+    let mut line = format!(
+        "{} - {}: {}x{} at ({}, {})",
+        index, display.name, bounds.Width, bounds.Height, bounds.X, bounds.Y
+    );
+    if let Some(refresh_rate) = display.refresh_rate {
+        line.push_str(&format!(", {} Hz", refresh_rate));
+    }
+    if display.hdr {
+        line.push_str(", HDR");
+    }
+    line
+}
+
+fn list_windows() {
+    let windows = enumerate_windows();
+    if windows.is_empty() {
+        exit_with_error("No windows found!");
+    }
+    println!("Windows ({}):", windows.len());
+    for window in &windows {
+        // The handle can be passed to --window as is
+        println!("  0x{:X} - {}", window.handle.0, window.title);
+    }
+}
+
+fn repair(input_file: &str, output_file: Option<&str>) {
+    let output_path = match output_file {
+        Some(output_file) => PathBuf::from(output_file),
+        None => get_repaired_path(Path::new(input_file)),
+    };
+    if output_path == Path::new(input_file) {
+        exit_with_error("The repaired recording can't replace the interrupted one!");
+    }
+    match repair_mp4(input_file, &output_path) {
+        Ok(summary) => {
+            println!(
+                "Recovered {} frames ({:.1}s) to \"{}\".",
+                summary.video_frames,
+                summary.duration.as_secs_f64(),
+                output_path.display()
+            );
+            if summary.discarded_bytes > 0 {
+                println!(
+                    "Discarded {} bytes of incomplete data at the end of the recording.",
+                    summary.discarded_bytes
+                );
+            }
         }
-    } else {
-        valid = false;
+        Err(error) => exit_with_error(&error.message().to_string()),
+    }
+}
+
+fn get_repaired_path(input_path: &Path) -> PathBuf {
+    let stem = input_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    input_path.with_file_name(format!("{}.repaired.mp4", stem))
+}
+
+fn enum_adapters() -> Result<()> {
+    let adapters = GraphicsAdapter::enumerate()?;
+    if adapters.is_empty() {
+        exit_with_error("No hardware adapters found!");
+    }
+    println!("Adapters ({}):", adapters.len());
+    for (i, adapter) in adapters.iter().enumerate() {
+        println!("  {} - {}", i, adapter.display_name());
+    }
+    Ok(())
+}
+
+fn enum_audio_devices() -> Result<()> {
+    unsafe {
+        RoInitialize(RO_INIT_MULTITHREADED)?;
+    }
+    let capture_devices = AudioCaptureDevice::enumerate()?;
+    if capture_devices.is_empty() {
+        exit_with_error("No audio capture devices found!");
+    }
+    println!("Audio capture devices ({}):", capture_devices.len());
+    for (i, capture_device) in capture_devices.iter().enumerate() {
+        println!("  {} - {}", i, capture_device.display_name());
+    }
+    Ok(())
+}
+
+fn enum_webcams() -> Result<()> {
+    unsafe {
+        RoInitialize(RO_INIT_MULTITHREADED)?;
+    }
+    let webcams = WebcamDevice::enumerate()?;
+    if webcams.is_empty() {
+        exit_with_error("No webcams found!");
+    }
+    println!("Webcams ({}):", webcams.len());
+    for (i, webcam) in webcams.iter().enumerate() {
+        println!("  {} - {}", i, webcam.display_name());
+    }
+    Ok(())
+}
+
+fn take_screenshot(
+    output_file: &str,
+    display: usize,
+    window: Option<&str>,
+    region: Option<Region>,
+    no_cursor: bool,
+) -> Result<()> {
+    unsafe {
+        RoInitialize(RO_INIT_MULTITHREADED)?;
+    }
+    let mut builder = ScreenshotBuilder::new(output_file)
+        .display(display)
+        .capture_cursor(!no_cursor);
+    if let Some(window) = window {
+        let window_handle = if let Some(window_handle) = find_window(window) {
+            window_handle
+        } else {
+            exit_with_error("Could not find a window matching the provided title or handle!");
+        };
+        builder = builder.window(window_handle);
+    }
+    if let Some(region) = region {
+        builder = builder.region(region);
     }
-    valid
+    builder.save()?;
+    println!("Saved screenshot to \"{}\"", output_file);
+    Ok(())
+}
+
+fn validate_path<P: AsRef<Path>>(path: P) -> bool {
+    Container::from_path(path).is_some()
 }
 
 fn exit_with_error(message: &str) -> ! {
@@ -287,38 +1137,274 @@ fn exit_with_error(message: &str) -> ! {
     std::process::exit(1);
 }
 
-fn win32_programmatic_capture_supported() -> Result<bool> {
-    ApiInformation::IsApiContractPresentByMajor(
-        &HSTRING::from("Windows.Foundation.UniversalApiContract"),
-        8,
-    )
+// The index of each hotkey passed to the HotKeyListener
+const TOGGLE_HOT_KEY: usize = 0;
+const PAUSE_HOT_KEY: usize = 1;
+const KEYFRAME_HOT_KEY: usize = 2;
+const CHAPTER_HOT_KEY: usize = 3;
+
+// The chapter hotkey is only registered when saving chapters
+fn get_hot_keys(args: &Args) -> Vec<HotKeyBinding> {
+    let mut hot_keys = vec![args.toggle_hotkey, args.pause_hotkey, args.keyframe_hotkey];
+    if args.chapters {
+        hot_keys.push(args.chapter_hotkey);
+    }
+    hot_keys
+}
+
+fn handle_hot_keys(
+    args: &Args,
+    session: &mut RecordingSession,
+    is_recording: bool,
+) -> std::result::Result<(), RecorderError> {
+    let hot_keys = HotKeyListener::new(&get_hot_keys(args))?;
+    println!(
+        "Press {} to start/stop the recording, {} to pause/resume it, or {} to insert a keyframe...",
+        args.toggle_hotkey.to_string().to_uppercase(),
+        args.pause_hotkey.to_string().to_uppercase(),
+        args.keyframe_hotkey.to_string().to_uppercase()
+    );
+    if args.chapters {
+        println!(
+            "Press {} to add a chapter...",
+            args.chapter_hotkey.to_string().to_uppercase()
+        );
+    }
+    let mut is_recording = is_recording;
+    let mut status = StatusLine::new();
+    loop {
+        if is_stop_requested() {
+            status.clear();
+            return Ok(());
+        }
+        let hot_key = if is_recording {
+            match update_status(args, session, &mut status) {
+                None => return Ok(()),
+                Some(wait_time) => match hot_keys.wait_timeout(wait_time) {
+                    Some(hot_key) => hot_key,
+                    None => continue,
+                },
+            }
+        } else {
+            // Keeps checking whether the recording should stop
+            match hot_keys.wait_timeout(COUNTDOWN_INTERVAL) {
+                Some(hot_key) => hot_key,
+                None => continue,
+            }
+        };
+        status.clear();
+        match hot_key {
+            TOGGLE_HOT_KEY if !is_recording => {
+                is_recording = true;
+                println!("Starting recording...");
+                session.start()?;
+            }
+            TOGGLE_HOT_KEY => return Ok(()),
+            PAUSE_HOT_KEY if is_recording => {
+                if session.is_paused() {
+                    println!("Resuming recording...");
+                    session.resume();
+                } else {
+                    println!("Pausing recording...");
+                    session.pause();
+                }
+            }
+            KEYFRAME_HOT_KEY if is_recording => {
+                println!("Inserting keyframe...");
+                session.request_keyframe();
+            }
+            CHAPTER_HOT_KEY if is_recording => {
+                let time = session.add_marker("");
+                println!("Added a chapter at {:.1}s.", time.as_secs_f64());
+            }
+            _ => {}
+        }
+    }
 }
 
-fn required_capture_features_supported() -> Result<bool> {
-    let result = ApiInformation::IsTypePresent(&HSTRING::from(GraphicsCaptureSession::NAME))? && // Windows.Graphics.Capture is present
-    GraphicsCaptureSession::IsSupported()? && // The CaptureService is available
-    win32_programmatic_capture_supported()?;
-    Ok(result)
+fn is_remote_controlled(args: &Args) -> bool {
+    args.control_pipe.is_some() || args.http_control.is_some()
 }
 
-fn pump_messages<F: FnMut() -> Result<bool>>(mut hot_key_callback: F) -> Result<()> {
-    let _hot_key = HotKey::new(MOD_SHIFT | MOD_CONTROL, 0x52 /* R */)?;
-    println!("Press SHIFT+CTRL+R to start/stop the recording...");
-    unsafe {
-        let mut message = MSG::default();
-        while GetMessageW(&mut message, HWND(0), 0, 0).into() {
-            if message.message == WM_HOTKEY && hot_key_callback()? {
-                break;
+fn handle_control_commands(
+    args: &Args,
+    session: &mut RecordingSession,
+    is_recording: bool,
+) -> std::result::Result<(), RecorderError> {
+    let server = ControlServer::new();
+    if let Some(pipe_name) = &args.control_pipe {
+        server.listen_on_pipe(pipe_name)?;
+        println!("Listening for commands on {}...", pipe_name);
+    }
+    if let Some(address) = &args.http_control {
+        let token = server.listen_on_http(address)?;
+        println!(
+            "Listening for commands on http://{}/ (with Authorization: Bearer {})...",
+            address, token
+        );
+    }
+    let mut is_recording = is_recording;
+    // A stopped session can't be started again, a new one is created instead
+    let mut is_stopped = false;
+    let mut status = StatusLine::new();
+    loop {
+        if is_stop_requested() {
+            status.clear();
+            if is_recording {
+                println!("Stopping recording...");
+                stop_recording(args, session)?;
+            }
+            return Ok(());
+        }
+        let request = if is_recording {
+            match update_status(args, session, &mut status) {
+                None => {
+                    is_recording = false;
+                    is_stopped = true;
+                    println!("Stopping recording...");
+                    stop_recording(args, session)?;
+                    continue;
+                }
+                Some(wait_time) => match server.wait_timeout(wait_time) {
+                    Some(request) => request,
+                    None => continue,
+                },
+            }
+        } else {
+            match server.wait_timeout(COUNTDOWN_INTERVAL) {
+                Some(request) => request,
+                None => continue,
+            }
+        };
+        status.clear();
+        if request.method == "exit" {
+            request.respond(Ok(JsonValue::Null));
+            if is_recording {
+                println!("Stopping recording...");
+                stop_recording(args, session)?;
             }
-            DispatchMessageW(&message);
+            return Ok(());
         }
+        let result =
+            handle_control_request(args, &request, session, &mut is_recording, &mut is_stopped);
+        request.respond(result);
+    }
+}
+
+fn handle_control_request(
+    args: &Args,
+    request: &ControlRequest,
+    session: &mut RecordingSession,
+    is_recording: &mut bool,
+    is_stopped: &mut bool,
+) -> std::result::Result<JsonValue, ControlError> {
+    let not_recording = || ControlError::failed("Not recording!");
+    match request.method.as_str() {
+        "start" => {
+            if *is_recording {
+                return Err(ControlError::failed("Already recording!"));
+            }
+            let output = request.string_param("output")?;
+            if *is_stopped || output.is_some() {
+                let output = output.as_deref().unwrap_or(&args.output_file);
+                *session = create_recording_session(args, output)?;
+            }
+            println!("Starting recording...");
+            session.start()?;
+            *is_recording = true;
+            *is_stopped = false;
+            Ok(JsonValue::object([(
+                "output_paths",
+                get_output_paths(session),
+            )]))
+        }
+        "stop" => {
+            if !*is_recording {
+                return Err(not_recording());
+            }
+            println!("Stopping recording...");
+            stop_recording(args, session)?;
+            *is_recording = false;
+            *is_stopped = true;
+            Ok(JsonValue::object([
+                ("output_paths", get_output_paths(session)),
+                ("stats", get_stats_json(session)),
+            ]))
+        }
+        "pause" if *is_recording => {
+            println!("Pausing recording...");
+            session.pause();
+            Ok(JsonValue::Null)
+        }
+        "resume" if *is_recording => {
+            println!("Resuming recording...");
+            session.resume();
+            Ok(JsonValue::Null)
+        }
+        "marker" if *is_recording => {
+            let label = request.string_param("label")?.unwrap_or_default();
+            println!("Adding marker...");
+            let time = session.add_marker(label);
+            Ok(JsonValue::object([(
+                "time",
+                JsonValue::Number(time.as_secs_f64()),
+            )]))
+        }
+        "split" if *is_recording => {
+            println!("Splitting recording...");
+            session.split()?;
+            Ok(JsonValue::Null)
+        }
+        "keyframe" if *is_recording => {
+            println!("Inserting keyframe...");
+            session.request_keyframe();
+            Ok(JsonValue::Null)
+        }
+        "pause" | "resume" | "marker" | "split" | "keyframe" => Err(not_recording()),
+        "status" => {
+            let elapsed = session.elapsed();
+            let stats = session.stats();
+            Ok(JsonValue::object([
+                ("recording", JsonValue::Bool(*is_recording)),
+                (
+                    "paused",
+                    JsonValue::Bool(*is_recording && session.is_paused()),
+                ),
+                ("elapsed", JsonValue::Number(elapsed.as_secs_f64())),
+                ("frames", JsonValue::Number(stats.frames as f64)),
+                (
+                    "dropped_frames",
+                    JsonValue::Number(stats.dropped_frames as f64),
+                ),
+                ("late_frames", JsonValue::Number(stats.late_frames as f64)),
+                (
+                    "bit_rate",
+                    JsonValue::Number(stats.bit_rate(elapsed) as f64),
+                ),
+            ]))
+        }
+        method => Err(ControlError::method_not_found(method)),
     }
-    Ok(())
+}
+
+fn get_output_paths(session: &RecordingSession) -> JsonValue {
+    JsonValue::Array(
+        session
+            .output_paths()
+            .iter()
+            .map(|path| JsonValue::string(path.as_str()))
+            .collect(),
+    )
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::validate_path;
+    use displayrecorder::{DisplayInfo, EncoderCapabilities, RateControlMode, VideoCodec};
+    use windows::Graphics::{RectInt32, SizeInt32};
+
+    use std::path::Path;
+
+    use crate::{format_display, get_capabilities_json, get_repaired_path, validate_path};
 
     #[test]
     fn path_parsing_test() {
@@ -326,6 +1412,7 @@ mod tests {
         assert!(validate_path("somedir/something.mp4"));
         assert!(validate_path("somedir\\something.mp4"));
         assert!(validate_path("../something.mp4"));
+        assert!(validate_path("something.mkv"));
 
         assert!(!validate_path("."));
         assert!(!validate_path("*"));
@@ -334,4 +1421,59 @@ mod tests {
         assert!(!validate_path("mp4"));
         assert!(!validate_path("something.avi"));
     }
+
+    #[test]
+    fn repaired_path_test() {
+        assert_eq!(
+            get_repaired_path(Path::new("somedir/recording.mp4")),
+            Path::new("somedir/recording.repaired.mp4")
+        );
+        assert_eq!(
+            get_repaired_path(Path::new("recording")),
+            Path::new("recording.repaired.mp4")
+        );
+    }
+
+    #[test]
+    fn display_formatting_test() {
+        let mut display = DisplayInfo {
+            name: "DELL U2720Q".to_owned(),
+            bounds: RectInt32 {
+                X: -3840,
+                Y: 0,
+                Width: 3840,
+                Height: 2160,
+            },
+            refresh_rate: Some(60),
+            hdr: true,
+        };
+        assert_eq!(
+            format_display(1, &display),
+            "1 - DELL U2720Q: 3840x2160 at (-3840, 0), 60 Hz, HDR"
+        );
+        display.refresh_rate = None;
+        display.hdr = false;
+        assert_eq!(
+            format_display(0, &display),
+            "0 - DELL U2720Q: 3840x2160 at (-3840, 0)"
+        );
+    }
+
+    #[test]
+    fn capabilities_json_test() {
+        let capabilities = EncoderCapabilities {
+            codecs: vec![VideoCodec::H264, VideoCodec::Hevc],
+            max_resolution: Some(SizeInt32 {
+                Width: 3840,
+                Height: 2160,
+            }),
+            rate_control_modes: vec![RateControlMode::Cbr, RateControlMode::Vbr],
+            ten_bit: true,
+            b_frames: false,
+        };
+        assert_eq!(
+            get_capabilities_json("NVIDIA H.264 Encoder MFT", true, &capabilities).to_string(),
+            r#"{"name":"NVIDIA H.264 Encoder MFT","hardware":true,"codecs":["h264","hevc"],"max_resolution":{"width":3840,"height":2160},"rate_control":["cbr","vbr"],"ten_bit":true,"b_frames":false}"#
+        );
+    }
 }
@@ -1,8 +1,13 @@
+use std::mem::ManuallyDrop;
+
 use windows::{
     core::{Array, Result, GUID},
-    Win32::Media::MediaFoundation::{
-        IMFActivate, IMFAttributes, MFTEnumEx, MFT_ENUM_FLAG, MFT_REGISTER_TYPE_INFO,
-        MF_E_ATTRIBUTENOTFOUND,
+    Win32::{
+        Media::MediaFoundation::{
+            ICodecAPI, IMFActivate, IMFAttributes, IMFSample, MFSampleExtension_CleanPoint,
+            MFTEnum2, MFT_ENUM_FLAG, MFT_REGISTER_TYPE_INFO, MF_E_ATTRIBUTENOTFOUND,
+        },
+        System::Variant::{VARIANT, VARIANT_0, VARIANT_0_0, VARIANT_0_0_0, VT_UI4},
     },
 };
 
@@ -19,16 +24,19 @@ pub fn enumerate_mfts(
     flags: MFT_ENUM_FLAG,
     input_type: Option<&MFT_REGISTER_TYPE_INFO>,
     output_type: Option<&MFT_REGISTER_TYPE_INFO>,
+    attributes: Option<&IMFAttributes>,
 ) -> Result<Vec<IMFActivate>> {
     let mut transform_sources = Vec::new();
     let mfactivate_list = unsafe {
         let mut data = std::ptr::null_mut();
         let mut len = 0;
-        MFTEnumEx(
+        // Unlike MFTEnumEx, this can also filter by attributes (e.g. the adapter)
+        MFTEnum2(
             *category,
             flags,
             Some(type_info_to_ptr(input_type)),
             Some(type_info_to_ptr(output_type)),
+            attributes,
             &mut data,
             &mut len,
         )?;
@@ -66,6 +74,39 @@ pub fn get_string_attribute(
     }
 }
 
+pub fn set_codec_api_value(codec_api: &ICodecAPI, api: &GUID, value: u32) -> Result<()> {
+    let value = VARIANT {
+        Anonymous: VARIANT_0 {
+            Anonymous: ManuallyDrop::new(VARIANT_0_0 {
+                vt: VT_UI4,
+                Anonymous: VARIANT_0_0_0 { ulVal: value },
+                ..Default::default()
+            }),
+        },
+    };
+    unsafe { codec_api.SetValue(api, &value) }
+}
+
+/// Copies out the data of a sample, which may be spread across several buffers.
+pub fn get_sample_data(sample: &IMFSample) -> Result<Vec<u8>> {
+    unsafe {
+        let buffer = sample.ConvertToContiguousBuffer()?;
+        let mut bytes = std::ptr::null_mut();
+        let mut length = 0;
+        buffer.Lock(&mut bytes, None, Some(&mut length))?;
+        let data = std::slice::from_raw_parts(bytes, length as usize).to_vec();
+        buffer.Unlock()?;
+        Ok(data)
+    }
+}
+
+/// Encoders mark key frames as clean points.
+pub fn is_key_frame(sample: &IMFSample) -> bool {
+    unsafe { sample.GetUINT32(&MFSampleExtension_CleanPoint) }
+        .map(|value| value != 0)
+        .unwrap_or(false)
+}
+
 // These inlined helpers aren't represented in the metadata
 
 // This is the value for Win7+
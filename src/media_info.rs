@@ -0,0 +1,115 @@
+// Reads recordings back, to check what was written (see SelfTestBuilder and
+// RecorderBuilder::verify).
+
+use std::time::Duration;
+
+use windows::{
+    core::{Result, HSTRING},
+    Graphics::SizeInt32,
+    Win32::{
+        Media::MediaFoundation::{
+            IMFSample, IMFSourceReader, MFCreateSourceReaderFromURL, MFStartup, MFSTARTUP_FULL,
+            MF_MT_FRAME_SIZE, MF_MT_SUBTYPE, MF_PD_DURATION, MF_SOURCE_READERF_ENDOFSTREAM,
+            MF_SOURCE_READERF_ERROR, MF_SOURCE_READER_ALL_STREAMS,
+            MF_SOURCE_READER_FIRST_VIDEO_STREAM, MF_SOURCE_READER_MEDIASOURCE,
+        },
+        System::Variant::VT_UI8,
+    },
+};
+
+use crate::{media::MF_VERSION, video::codec::VideoCodec};
+
+/// The video stream of a recording, see read_media_info.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MediaInfo {
+    /// None if the stream isn't in one of the codecs that can be recorded.
+    pub codec: Option<VideoCodec>,
+    pub size: SizeInt32,
+    pub frames: u64,
+    /// From the start of the first frame to the end of the last one.
+    pub duration: Duration,
+    /// What the file says its duration is, if it says.
+    pub stated_duration: Option<Duration>,
+}
+
+impl MediaInfo {
+    /// The shorter of the stated duration and the span of the frames, since
+    /// either can fall short when a file is truncated.
+    pub fn shortest_duration(&self) -> Duration {
+        self.stated_duration
+            .map_or(self.duration, |stated_duration| {
+                stated_duration.min(self.duration)
+            })
+    }
+}
+
+/// Reads the first video stream of a recording with a source reader, going
+/// through every sample of it.
+pub fn read_media_info(path: &str) -> Result<MediaInfo> {
+    unsafe {
+        MFStartup(MF_VERSION, MFSTARTUP_FULL)?;
+        let reader = MFCreateSourceReaderFromURL(&HSTRING::from(path), None)?;
+        let stream_index = MF_SOURCE_READER_FIRST_VIDEO_STREAM.0 as u32;
+        reader.SetStreamSelection(MF_SOURCE_READER_ALL_STREAMS.0 as u32, false)?;
+        reader.SetStreamSelection(stream_index, true)?;
+
+        // Without a current media type, the samples are read as they were
+        // encoded rather than decoded
+        let media_type = reader.GetNativeMediaType(stream_index, 0)?;
+        let codec = VideoCodec::from_subtype(&media_type.GetGUID(&MF_MT_SUBTYPE)?);
+        let frame_size = media_type.GetUINT64(&MF_MT_FRAME_SIZE)?;
+        let size = SizeInt32 {
+            Width: (frame_size >> 32) as i32,
+            Height: frame_size as u32 as i32,
+        };
+
+        let mut frames = 0;
+        let mut range: Option<(i64, i64)> = None;
+        loop {
+            let mut flags = 0;
+            let mut sample: Option<IMFSample> = None;
+            reader.ReadSample(
+                stream_index,
+                0,
+                None,
+                Some(&mut flags),
+                None,
+                Some(&mut sample),
+            )?;
+            if flags & (MF_SOURCE_READERF_ENDOFSTREAM.0 | MF_SOURCE_READERF_ERROR.0) as u32 != 0 {
+                break;
+            }
+            // Stream ticks and format changes don't come with a sample
+            if let Some(sample) = sample {
+                let start = sample.GetSampleTime()?;
+                let end = start + sample.GetSampleDuration().unwrap_or(0);
+                range = Some(match range {
+                    Some((first, last)) => (first.min(start), last.max(end)),
+                    None => (start, end),
+                });
+                frames += 1;
+            }
+        }
+        let duration = range.map_or(0, |(first, last)| last - first);
+        Ok(MediaInfo {
+            codec,
+            size,
+            frames,
+            duration: Duration::from_nanos(duration.max(0) as u64 * 100),
+            stated_duration: get_stated_duration(&reader),
+        })
+    }
+}
+
+// The duration of the whole file, which a source reader gets from the
+// container (e.g. the moov box of an MP4)
+unsafe fn get_stated_duration(reader: &IMFSourceReader) -> Option<Duration> {
+    let value = reader
+        .GetPresentationAttribute(MF_SOURCE_READER_MEDIASOURCE.0 as u32, &MF_PD_DURATION)
+        .ok()?;
+    if value.Anonymous.Anonymous.vt != VT_UI8 {
+        return None;
+    }
+    let duration = value.Anonymous.Anonymous.Anonymous.uhVal;
+    Some(Duration::from_nanos(duration * 100))
+}
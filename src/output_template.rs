@@ -0,0 +1,139 @@
+use windows::Win32::{Foundation::SYSTEMTIME, System::SystemInformation::GetLocalTime};
+
+const INDEX_TOKEN: &str = "{index}";
+
+/// The values to fill into the tokens of an output path, e.g.
+/// captures/{date}_{time}_{display}.mp4.
+pub struct TemplateValues {
+    pub time: SYSTEMTIME,
+    pub display: Option<String>,
+    pub window: Option<String>,
+}
+
+impl TemplateValues {
+    pub fn now() -> Self {
+        Self {
+            time: unsafe { GetLocalTime() },
+            display: None,
+            window: None,
+        }
+    }
+}
+
+pub fn has_token(template: &str, token: &str) -> bool {
+    template.contains(&format!("{{{}}}", token))
+}
+
+/// Fills in every token other than {index}, which depends on the files that
+/// already exist (see resolve_index). Names are made safe for file names.
+pub fn expand_template(template: &str, values: &TemplateValues) -> String {
+    let time = &values.time;
+    template
+        .replace(
+            "{date}",
+            &format!("{:04}-{:02}-{:02}", time.wYear, time.wMonth, time.wDay),
+        )
+        // Colons aren't allowed in file names
+        .replace(
+            "{time}",
+            &format!("{:02}-{:02}-{:02}", time.wHour, time.wMinute, time.wSecond),
+        )
+        .replace(
+            "{display}",
+            &sanitize(values.display.as_deref().unwrap_or("display")),
+        )
+        .replace(
+            "{window}",
+            &sanitize(values.window.as_deref().unwrap_or("window")),
+        )
+}
+
+/// Replaces {index} in each path with the lowest number (starting at 001)
+/// for which none of the files exists yet, so that the paths of a recording
+/// share an index. The caller decides what exists, e.g. for segmented
+/// recordings it's the first segment rather than the path itself.
+pub fn resolve_index<F: Fn(&str) -> bool>(paths: &[String], exists: F) -> Vec<String> {
+    if !paths.iter().any(|path| path.contains(INDEX_TOKEN)) {
+        return paths.to_vec();
+    }
+    let mut index = 1;
+    loop {
+        let resolved: Vec<_> = paths
+            .iter()
+            .map(|path| path.replace(INDEX_TOKEN, &format!("{:03}", index)))
+            .collect();
+        if !resolved.iter().any(|path| exists(path)) {
+            return resolved;
+        }
+        index += 1;
+    }
+}
+
+// Window titles in particular can contain anything
+fn sanitize(name: &str) -> String {
+    let name: String = name
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    if name.is_empty() {
+        "_".to_owned()
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::Foundation::SYSTEMTIME;
+
+    use super::{expand_template, has_token, resolve_index, TemplateValues};
+
+    #[test]
+    fn template_expansion_test() {
+        let values = TemplateValues {
+            time: SYSTEMTIME {
+                wYear: 2024,
+                wMonth: 3,
+                wDay: 1,
+                wHour: 14,
+                wMinute: 5,
+                wSecond: 9,
+                ..Default::default()
+            },
+            display: Some("DELL U2720Q".to_owned()),
+            window: Some("Untitled - Notepad: *draft*".to_owned()),
+        };
+        assert_eq!(
+            expand_template("captures/{date}_{time}_{display}.mp4", &values),
+            "captures/2024-03-01_14-05-09_DELL U2720Q.mp4"
+        );
+        assert_eq!(
+            expand_template("{window}_{index}.mp4", &values),
+            "Untitled - Notepad_ _draft__{index}.mp4"
+        );
+        assert_eq!(expand_template("recording.mp4", &values), "recording.mp4");
+        assert!(has_token("captures/{display}.mp4", "display"));
+        assert!(!has_token("captures/display.mp4", "display"));
+    }
+
+    #[test]
+    fn index_resolution_test() {
+        let existing = ["take_001_a.mp4", "take_002_b.mp4"];
+        let exists = |path: &str| existing.contains(&path);
+        let paths = vec![
+            "take_{index}_a.mp4".to_owned(),
+            "take_{index}_b.mp4".to_owned(),
+        ];
+        assert_eq!(
+            resolve_index(&paths, exists),
+            vec!["take_003_a.mp4", "take_003_b.mp4"]
+        );
+        let paths = vec!["take.mp4".to_owned()];
+        assert_eq!(resolve_index(&paths, exists), vec!["take.mp4"]);
+    }
+}
@@ -0,0 +1,106 @@
+use std::io::{self, Write};
+
+use windows::{
+    core::{Error, Result, HSTRING},
+    Win32::{
+        Foundation::{CloseHandle, ERROR_PIPE_CONNECTED, HANDLE},
+        Storage::FileSystem::{FlushFileBuffers, WriteFile, PIPE_ACCESS_OUTBOUND},
+        System::{
+            Console::{GetStdHandle, SetStdHandle, STD_ERROR_HANDLE, STD_OUTPUT_HANDLE},
+            Pipes::{ConnectNamedPipe, CreateNamedPipeW, PIPE_TYPE_BYTE, PIPE_WAIT},
+        },
+    },
+};
+
+const STDOUT_PATH: &str = "-";
+const NAMED_PIPE_PREFIX: &str = r"\\.\pipe\";
+const PIPE_BUFFER_SIZE: u32 = 1024 * 1024;
+
+/// Whether the recording goes to stdout (-) or a named pipe (\\.\pipe\name)
+/// instead of a file, e.g. to be read by ffmpeg.
+pub fn is_pipe_path(path: &str) -> bool {
+    path == STDOUT_PATH || path.to_lowercase().starts_with(NAMED_PIPE_PREFIX)
+}
+
+/// Writes to stdout or a named pipe. Named pipes are created by us, and
+/// opening one waits for a reader to connect.
+pub struct PipeStream {
+    handle: HANDLE,
+}
+
+impl PipeStream {
+    pub fn open(path: &str) -> Result<Self> {
+        let handle = unsafe {
+            if path == STDOUT_PATH {
+                // From here on the console messages go to stderr, otherwise
+                // they would end up in the recording
+                let handle = GetStdHandle(STD_OUTPUT_HANDLE)?;
+                SetStdHandle(STD_OUTPUT_HANDLE, GetStdHandle(STD_ERROR_HANDLE)?)?;
+                handle
+            } else {
+                let handle = CreateNamedPipeW(
+                    &HSTRING::from(path),
+                    PIPE_ACCESS_OUTBOUND,
+                    PIPE_TYPE_BYTE | PIPE_WAIT,
+                    1,
+                    PIPE_BUFFER_SIZE,
+                    0,
+                    0,
+                    None,
+                );
+                if handle.is_invalid() {
+                    return Err(Error::from_win32());
+                }
+                println!("Waiting for a reader to connect to \"{}\"...", path);
+                // The reader may have connected before we started waiting
+                if let Err(error) = ConnectNamedPipe(handle, None) {
+                    if error.code() != ERROR_PIPE_CONNECTED.to_hresult() {
+                        let _ = CloseHandle(handle);
+                        return Err(error);
+                    }
+                }
+                handle
+            }
+        };
+        Ok(Self { handle })
+    }
+}
+
+impl Write for PipeStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut written = 0;
+        unsafe { WriteFile(self.handle, Some(buf), Some(&mut written), None) }
+            .map_err(io::Error::other)?;
+        Ok(written as usize)
+    }
+
+    // Nothing is buffered on our side
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for PipeStream {
+    fn drop(&mut self) {
+        // Closing the handle tells the reader that the recording is done, but
+        // anything it hasn't read yet would be lost
+        unsafe {
+            let _ = FlushFileBuffers(self.handle);
+            let _ = CloseHandle(self.handle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_pipe_path;
+
+    #[test]
+    fn pipe_path_test() {
+        assert!(is_pipe_path("-"));
+        assert!(is_pipe_path(r"\\.\pipe\recording"));
+        assert!(is_pipe_path(r"\\.\PIPE\recording"));
+        assert!(!is_pipe_path("recording.mp4"));
+        assert!(!is_pipe_path("-.mp4"));
+    }
+}
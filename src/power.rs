@@ -0,0 +1,72 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use windows::{
+    core::Result,
+    Win32::{
+        Foundation::HANDLE,
+        System::Power::{
+            PowerRegisterSuspendResumeNotification, PowerUnregisterSuspendResumeNotification,
+            DEVICE_NOTIFY_SUBSCRIBE_PARAMETERS, HPOWERNOTIFY,
+        },
+        UI::WindowsAndMessaging::{DEVICE_NOTIFY_CALLBACK, PBT_APMRESUMEAUTOMATIC},
+    },
+};
+
+/// Counts how many times the system resumed from sleep or hibernation, so
+/// that the capture can be started over (it doesn't survive a suspend).
+pub struct ResumeNotifier {
+    registration: HPOWERNOTIFY,
+    resume_count: Arc<AtomicU64>,
+    _parameters: Box<DEVICE_NOTIFY_SUBSCRIBE_PARAMETERS>,
+}
+
+unsafe impl Send for ResumeNotifier {}
+impl ResumeNotifier {
+    pub fn new() -> Result<Self> {
+        let resume_count = Arc::new(AtomicU64::new(0));
+        let mut parameters = Box::new(DEVICE_NOTIFY_SUBSCRIBE_PARAMETERS {
+            Callback: Some(on_power_event),
+            Context: Arc::as_ptr(&resume_count) as *mut _,
+        });
+        let mut registration = std::ptr::null_mut();
+        unsafe {
+            PowerRegisterSuspendResumeNotification(
+                DEVICE_NOTIFY_CALLBACK,
+                HANDLE(parameters.as_mut() as *mut _ as isize),
+                &mut registration,
+            )?;
+        }
+        Ok(Self {
+            registration: HPOWERNOTIFY(registration as isize),
+            resume_count,
+            _parameters: parameters,
+        })
+    }
+
+    pub fn resume_count(&self) -> u64 {
+        self.resume_count.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for ResumeNotifier {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = PowerUnregisterSuspendResumeNotification(self.registration);
+        }
+    }
+}
+
+unsafe extern "system" fn on_power_event(
+    context: *const std::ffi::c_void,
+    event: u32,
+    _setting: *const std::ffi::c_void,
+) -> u32 {
+    if event == PBT_APMRESUMEAUTOMATIC {
+        let resume_count = &*(context as *const AtomicU64);
+        resume_count.fetch_add(1, Ordering::SeqCst);
+    }
+    0
+}
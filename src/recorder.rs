@@ -0,0 +1,2645 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tracing::warn;
+use windows::{
+    core::{ComInterface, Error, Result, RuntimeName, HSTRING},
+    Foundation::Metadata::ApiInformation,
+    Graphics::{Capture::GraphicsCaptureSession, PointInt32, SizeInt32},
+    Storage::{
+        CreationCollisionOption, FileAccessMode, StorageFile, StorageFolder,
+        Streams::IRandomAccessStream,
+    },
+    Win32::{
+        Foundation::{E_INVALIDARG, HWND, MAX_PATH},
+        Graphics::Gdi::HMONITOR,
+        Media::MediaFoundation::{MFStartup, MFSTARTUP_FULL},
+        Storage::FileSystem::GetFullPathNameW,
+    },
+};
+
+use crate::{
+    adapter::{AdapterSelection, GraphicsAdapter},
+    audio::{
+        capture::AudioCapture,
+        device::AudioCaptureDevice,
+        effects::{AudioEffects, AudioGain},
+        encoding_session::AudioEncodingSession,
+        level::AudioLevelMeter,
+        track_layout::AudioTrackLayout,
+    },
+    capture::{create_capture_item_for_monitor, create_capture_item_for_window},
+    chapters::Chapters,
+    container::{
+        fragment_writer::{FragmentWriter, StreamOutput},
+        null::NullWriter,
+        to_error, AnnexBWriter, Container, ContainerWriter, Metadata,
+    },
+    d3d::{create_d3d_device, create_d3d_device_on_adapter},
+    disk::get_free_space,
+    displays::{
+        find_display_by_device_name, get_display_bounds, get_display_count,
+        get_display_handle_from_index, get_display_name, get_display_refresh_rate,
+        resolve_display_indices, DisplaySelection,
+    },
+    error::{in_stage, RecorderError, RecordingStage},
+    etw,
+    gif::encoding_session::{GifEncodingSession, GifSettings},
+    hls::output::{HlsOutput, PLAYLIST_NAME, SEGMENT_DURATION},
+    input_hook::{KeyboardHook, MouseHook},
+    key_combination::KeyCombination,
+    media::MF_VERSION,
+    media_info::read_media_info,
+    output_template::{expand_template, has_token, resolve_index, TemplateValues},
+    pipe::{is_pipe_path, PipeStream},
+    region::Region,
+    resolution::Resolution,
+    rtmp::writer::RtmpWriter,
+    sample_writer::SampleWriter,
+    segment::SegmentLimit,
+    srt::writer::SrtWriter,
+    stats::RecordingStats,
+    stream_url::StreamUrl,
+    test_pattern::TestPatternSource,
+    timeline::Timeline,
+    video::{
+        bit_depth::BitDepth,
+        canvas::CanvasItem,
+        canvas_layout::CanvasLayout,
+        codec::VideoCodec,
+        color_format::ColorFormat,
+        color_range::ColorRange,
+        encoder_device::{get_encoder_rank, get_no_encoders_message, VideoEncoderDevice},
+        encoding_session::VideoEncodingSession,
+        fit_mode::FitMode,
+        frame_pacing::FramePacing,
+        frame_rate_mode::FrameRateMode,
+        minimize_action::MinimizeAction,
+        orientation::{Flip, Orientation, Rotation},
+        overlay::{
+            ClickOverlay, ClockOverlay, ExcludedWindowsOverlay, KeyOverlay,
+            PictureInPictureSettings, PictureInPictureSource, RedactedWindows, RedactionStyle,
+            ScreenOrigin, WatermarkOverlay, WatermarkSettings, WebcamOverlay, WebcamSettings,
+        },
+        rate_control::RateControlMode,
+        sample_queue::{QueuePolicy, DEFAULT_QUEUE_SIZE},
+        scale_filter::ScaleFilter,
+        zoom::ZoomFactor,
+    },
+    webcam::{WebcamCapture, WebcamDevice},
+    window::{get_window_bounds, get_window_process_name, get_window_title},
+};
+
+// How much of a fragmented recording can be lost if it's interrupted
+const FRAGMENT_DURATION: Duration = Duration::from_secs(2);
+const DEFAULT_FRAME_RATE: u32 = 60;
+const DEVICE_LOST_LABEL: &str = "GPU reset";
+// How much shorter than what was recorded a verified recording can be (see
+// RecorderBuilder::verify), a fraction of it or the absolute minimum
+const TRUNCATION_TOLERANCE: f64 = 0.05;
+const MIN_TRUNCATION_TOLERANCE: Duration = Duration::from_secs(1);
+// The size of the test pattern, unless there's a resolution
+const TEST_PATTERN_SIZE: SizeInt32 = SizeInt32 {
+    Width: 1920,
+    Height: 1080,
+};
+
+/// Events raised by a recording session.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RecordingEvent {
+    Started,
+    Paused,
+    Resumed,
+    Marker { time: Duration, label: String },
+    Stopped { output_paths: Vec<String> },
+}
+
+type EventCallback = Arc<dyn Fn(RecordingEvent) + Send + Sync>;
+
+/// Configures a recording. Errors caused by the configuration are reported
+/// with E_INVALIDARG and a message describing the problem. The calling thread
+/// must be initialized for WinRT (RoInitialize with RO_INIT_MULTITHREADED).
+pub struct RecorderBuilder {
+    output_path: String,
+    format: Option<Container>,
+    displays: Vec<DisplaySelection>,
+    composite: bool,
+    layout: CanvasLayout,
+    window: Option<HWND>,
+    process_windows: Vec<HWND>,
+    region: Option<Region>,
+    capture_cursor: bool,
+    bit_rate: u32,
+    frame_rate: Option<u32>,
+    frame_rate_mode: FrameRateMode,
+    max_frame_rate: Option<u32>,
+    idle_frame_rate: Option<u32>,
+    idle_after: Duration,
+    frame_pacing: FramePacing,
+    queue_size: usize,
+    queue_policy: QueuePolicy,
+    rate_control: Option<RateControlMode>,
+    quality: Option<u32>,
+    gop_size: Option<u32>,
+    scene_keyframes: bool,
+    b_frames: Option<u32>,
+    hdr: bool,
+    bit_depth: BitDepth,
+    color_range: ColorRange,
+    orientation: Orientation,
+    scale_filter: Option<ScaleFilter>,
+    fit_mode: FitMode,
+    on_minimize: Option<MinimizeAction>,
+    on_secure_desktop: Option<MinimizeAction>,
+    zoom_follow: Option<ZoomFactor>,
+    segment: Option<SegmentLimit>,
+    splittable: bool,
+    fragmented: bool,
+    resolution: Resolution,
+    auto_downscale: bool,
+    test_pattern: bool,
+    codec: VideoCodec,
+    encoder_index: Option<usize>,
+    adapter: Option<AdapterSelection>,
+    allow_software_encoder: bool,
+    system_audio: bool,
+    audio_process: Option<u32>,
+    mic: Option<String>,
+    mic_effects: AudioEffects,
+    audio_tracks: AudioTrackLayout,
+    audio_sync_tolerance: Duration,
+    no_video: bool,
+    replay: Option<Duration>,
+    stream: Option<StreamUrl>,
+    hls: Option<String>,
+    ndi: Option<String>,
+    ndi_only: bool,
+    gif_settings: GifSettings,
+    preview: bool,
+    thumbnail: bool,
+    chapters: bool,
+    mark_device_loss: bool,
+    verify: bool,
+    record_on_change: bool,
+    pre_roll: Duration,
+    post_roll: Duration,
+    encode_later: bool,
+    scratch_dir: Option<String>,
+    metadata: Metadata,
+    clock_overlay: bool,
+    watermark: Option<WatermarkSettings>,
+    webcam: Option<WebcamSettings>,
+    picture_in_picture: Option<PictureInPictureSettings>,
+    show_clicks: bool,
+    show_keys: bool,
+    key_allowlist: Option<Vec<KeyCombination>>,
+    excluded_windows: Vec<HWND>,
+    redacted_apps: Vec<String>,
+    redaction_style: RedactionStyle,
+    blur_regions: Vec<Region>,
+    verbose: bool,
+    event_callback: Option<EventCallback>,
+    encode_progress_callback: Option<Arc<dyn Fn() + Send + Sync>>,
+}
+
+/// A recording that has been fully set up and is ready to start.
+pub struct RecordingSession {
+    timeline: Timeline,
+    sample_writers: Vec<Arc<SampleWriter>>,
+    sessions: Vec<VideoEncodingSession>,
+    gif_sessions: Vec<GifEncodingSession>,
+    // Audio-only recordings don't have a video session to encode the audio
+    audio_sessions: Vec<AudioEncodingSession>,
+    audio_level_meter: Option<AudioLevelMeter>,
+    output_paths: Vec<String>,
+    // Where the raw frames go until they're encoded, see encode_later
+    scratch_paths: Vec<String>,
+    // The paths that numbered segments are based on, when segmenting
+    segment_base_paths: Vec<String>,
+    // The markers, and where they're saved, when saving chapters
+    chapters: Arc<Mutex<Chapters>>,
+    chapter_paths: Vec<String>,
+    verify: bool,
+    event_callback: Option<EventCallback>,
+    started: bool,
+}
+
+impl RecorderBuilder {
+    pub fn new<S: Into<String>>(output_path: S) -> Self {
+        Self {
+            output_path: output_path.into(),
+            format: None,
+            displays: vec![DisplaySelection::Index(0)],
+            composite: false,
+            layout: CanvasLayout::Desktop,
+            window: None,
+            process_windows: Vec::new(),
+            region: None,
+            capture_cursor: true,
+            bit_rate: 18,
+            frame_rate: None,
+            frame_rate_mode: FrameRateMode::Constant,
+            max_frame_rate: None,
+            idle_frame_rate: None,
+            idle_after: Duration::from_secs(10),
+            frame_pacing: FramePacing::Log,
+            queue_size: DEFAULT_QUEUE_SIZE,
+            queue_policy: QueuePolicy::Block,
+            rate_control: None,
+            quality: None,
+            gop_size: None,
+            scene_keyframes: false,
+            b_frames: None,
+            hdr: false,
+            bit_depth: BitDepth::Eight,
+            color_range: ColorRange::Limited,
+            orientation: Orientation::default(),
+            scale_filter: None,
+            fit_mode: FitMode::Letterbox,
+            on_minimize: None,
+            on_secure_desktop: None,
+            zoom_follow: None,
+            segment: None,
+            splittable: false,
+            fragmented: false,
+            resolution: Resolution::Native,
+            auto_downscale: false,
+            test_pattern: false,
+            codec: VideoCodec::H264,
+            encoder_index: None,
+            allow_software_encoder: false,
+            adapter: None,
+            system_audio: false,
+            audio_process: None,
+            mic: None,
+            mic_effects: AudioEffects::default(),
+            audio_tracks: AudioTrackLayout::Mixed,
+            audio_sync_tolerance: Duration::from_millis(10),
+            no_video: false,
+            replay: None,
+            stream: None,
+            hls: None,
+            ndi: None,
+            ndi_only: false,
+            gif_settings: GifSettings {
+                frame_rate: 10,
+                max_width: 640,
+            },
+            preview: false,
+            thumbnail: false,
+            chapters: false,
+            mark_device_loss: false,
+            verify: false,
+            record_on_change: false,
+            pre_roll: Duration::from_secs(3),
+            post_roll: Duration::from_secs(5),
+            encode_later: false,
+            scratch_dir: None,
+            metadata: Metadata::default(),
+            clock_overlay: false,
+            watermark: None,
+            webcam: None,
+            picture_in_picture: None,
+            show_clicks: false,
+            show_keys: false,
+            key_allowlist: None,
+            excluded_windows: Vec::new(),
+            redacted_apps: Vec::new(),
+            redaction_style: RedactionStyle::Blank,
+            blur_regions: Vec::new(),
+            verbose: false,
+            event_callback: None,
+            encode_progress_callback: None,
+        }
+    }
+
+    /// The format of the recording. By default the format is picked based on
+    /// the extension of the output file, otherwise the extension is replaced.
+    pub fn format(mut self, format: Container) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    pub fn displays(mut self, displays: &[DisplaySelection]) -> Self {
+        self.displays = displays.to_vec();
+        self
+    }
+
+    /// Composites all of the displays into a single recording.
+    pub fn composite(mut self, composite: bool) -> Self {
+        self.composite = composite;
+        self
+    }
+
+    /// How the displays (when compositing) or the windows of a process are
+    /// placed in the recording, by default where they are on the desktop.
+    pub fn layout(mut self, layout: CanvasLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Records a window instead of the displays.
+    pub fn window(mut self, window: HWND) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    /// Records the windows of a process (see find_process_windows) together,
+    /// laid out the way they were on the desktop when recording started.
+    pub fn process_windows(mut self, windows: Vec<HWND>) -> Self {
+        self.process_windows = windows;
+        self
+    }
+
+    pub fn region(mut self, region: Region) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    pub fn capture_cursor(mut self, capture_cursor: bool) -> Self {
+        self.capture_cursor = capture_cursor;
+        self
+    }
+
+    /// The bit rate to encode at (in Mbps).
+    pub fn bit_rate(mut self, bit_rate: u32) -> Self {
+        self.bit_rate = bit_rate;
+        self
+    }
+
+    /// Defaults to the refresh rate of the recorded display (the fastest one
+    /// when compositing), or 60 for windows and when it isn't known.
+    pub fn frame_rate(mut self, frame_rate: u32) -> Self {
+        self.frame_rate = Some(frame_rate);
+        self
+    }
+
+    /// With a variable frame rate, the frame rate is the maximum frame rate.
+    pub fn frame_rate_mode(mut self, frame_rate_mode: FrameRateMode) -> Self {
+        self.frame_rate_mode = frame_rate_mode;
+        self
+    }
+
+    /// Drops frames that arrive faster than this, e.g. from high refresh rate displays.
+    pub fn max_frame_rate(mut self, max_frame_rate: u32) -> Self {
+        self.max_frame_rate = Some(max_frame_rate);
+        self
+    }
+
+    /// Drops to this frame rate while the screen doesn't change, e.g. to keep
+    /// long monitoring recordings small, see idle_after.
+    pub fn idle_frame_rate(mut self, idle_frame_rate: u32) -> Self {
+        self.idle_frame_rate = Some(idle_frame_rate);
+        self
+    }
+
+    /// How long the screen has to stay the same before dropping to the idle
+    /// frame rate. Defaults to 10 seconds.
+    pub fn idle_after(mut self, idle_after: Duration) -> Self {
+        self.idle_after = idle_after;
+        self
+    }
+
+    /// What to do with frames that arrive late (e.g. because the encoder can't keep up)
+    /// or leave gaps. Defaults to recording them anyway and logging late frames.
+    pub fn frame_pacing(mut self, frame_pacing: FramePacing) -> Self {
+        self.frame_pacing = frame_pacing;
+        self
+    }
+
+    /// How many frames can wait for the encoder. Defaults to 3.
+    pub fn queue_size(mut self, queue_size: usize) -> Self {
+        self.queue_size = queue_size;
+        self
+    }
+
+    /// What to do with new frames once the queue in front of the encoder is
+    /// full. Defaults to waiting for the encoder.
+    pub fn queue_policy(mut self, queue_policy: QueuePolicy) -> Self {
+        self.queue_policy = queue_policy;
+        self
+    }
+
+    /// Defaults to whatever the encoder uses.
+    pub fn rate_control(mut self, rate_control: RateControlMode) -> Self {
+        self.rate_control = Some(rate_control);
+        self
+    }
+
+    /// The quality (0-100) to encode at, requires constant quality (cqp) rate control.
+    pub fn quality(mut self, quality: u32) -> Self {
+        self.quality = Some(quality);
+        self
+    }
+
+    /// The number of frames between keyframes.
+    pub fn gop_size(mut self, gop_size: u32) -> Self {
+        self.gop_size = Some(gop_size);
+        self
+    }
+
+    /// Adds keyframes where the screen changes completely (e.g. a new slide),
+    /// so that seeking to them is quick.
+    pub fn scene_keyframes(mut self, scene_keyframes: bool) -> Self {
+        self.scene_keyframes = scene_keyframes;
+        self
+    }
+
+    /// The number of B-frames between reference frames, 0 disables them.
+    pub fn b_frames(mut self, b_frames: u32) -> Self {
+        self.b_frames = Some(b_frames);
+        self
+    }
+
+    /// Records HDR displays without clipping highlights, encoded as HDR10 HEVC.
+    pub fn hdr(mut self, hdr: bool) -> Self {
+        self.hdr = hdr;
+        self
+    }
+
+    /// 10-bit video reduces banding in gradients, encoded as HEVC Main10.
+    pub fn bit_depth(mut self, bit_depth: BitDepth) -> Self {
+        self.bit_depth = bit_depth;
+        self
+    }
+
+    /// Defaults to limited range, which is what most players expect.
+    pub fn color_range(mut self, color_range: ColorRange) -> Self {
+        self.color_range = color_range;
+        self
+    }
+
+    /// Rotates the recording clockwise, e.g. for portrait monitors. The
+    /// native resolution follows the rotation.
+    pub fn rotation(mut self, rotation: Rotation) -> Self {
+        self.orientation.rotation = rotation;
+        self
+    }
+
+    /// Mirrors the recording horizontally or vertically (before rotating it).
+    pub fn flip(mut self, flip: Flip) -> Self {
+        self.orientation.flip = Some(flip);
+        self
+    }
+
+    /// Scales the recording with the filter (e.g. lanczos) instead of the
+    /// video processor, which is soft when downscaling.
+    pub fn scale_filter(mut self, scale_filter: ScaleFilter) -> Self {
+        self.scale_filter = Some(scale_filter);
+        self
+    }
+
+    /// How the recording is fit into a resolution with a different aspect
+    /// ratio: letterboxed with black bars (the default), stretched, or cropped.
+    pub fn fit_mode(mut self, fit_mode: FitMode) -> Self {
+        self.fit_mode = fit_mode;
+        self
+    }
+
+    /// Zooms in on the mouse cursor by the factor, following it smoothly,
+    /// e.g. for tutorials.
+    pub fn zoom_follow(mut self, factor: ZoomFactor) -> Self {
+        self.zoom_follow = Some(factor);
+        self
+    }
+
+    /// What is recorded while the recorded window is minimized: the recording
+    /// is paused, the last frame is held, or a placeholder card is shown.
+    /// By default nothing is recorded until the window is restored.
+    pub fn on_minimize(mut self, action: MinimizeAction) -> Self {
+        self.on_minimize = Some(action);
+        self
+    }
+
+    /// What is recorded while the secure desktop (e.g. a UAC prompt or the
+    /// lock screen) is shown, which can't be captured: the same choices as
+    /// for minimized windows. By default the video has a gap, which is logged.
+    /// Either way the recording carries on once the secure desktop is gone.
+    pub fn on_secure_desktop(mut self, action: MinimizeAction) -> Self {
+        self.on_secure_desktop = Some(action);
+        self
+    }
+
+    /// Splits the recording into numbered files (e.g. recording_001.mp4) once each
+    /// file reaches the given duration or size. Not supported for replays or GIFs.
+    pub fn segment(mut self, segment: SegmentLimit) -> Self {
+        self.segment = Some(segment);
+        self
+    }
+
+    /// Lets RecordingSession::split start a new file at any time. The files are
+    /// numbered like segments, even if the recording is never split. Has no
+    /// effect on recordings that aren't written to files, replays, GIFs, or
+    /// fragmented recordings.
+    pub fn splittable(mut self, splittable: bool) -> Self {
+        self.splittable = splittable;
+        self
+    }
+
+    /// Writes MP4 files as fragmented MP4, so that all but the last couple
+    /// of seconds can be recovered (see repair_mp4) if the recording is
+    /// interrupted before it's finalized. Uses H.264 video and AAC audio.
+    pub fn fragmented(mut self, fragmented: bool) -> Self {
+        self.fragmented = fragmented;
+        self
+    }
+
+    pub fn resolution(mut self, resolution: Resolution) -> Self {
+        self.resolution = resolution;
+        self
+    }
+
+    /// Records at the largest size the encoder takes (keeping the aspect
+    /// ratio) when it can't encode the resolution, rather than failing.
+    pub fn auto_downscale(mut self, auto_downscale: bool) -> Self {
+        self.auto_downscale = auto_downscale;
+        self
+    }
+
+    /// Records an animated test pattern instead of capturing a display or
+    /// window, at the resolution (or 1080p).
+    pub fn test_pattern(mut self, test_pattern: bool) -> Self {
+        self.test_pattern = test_pattern;
+        self
+    }
+
+    pub fn codec(mut self, codec: VideoCodec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// The index of the encoder to use, see VideoEncoderDevice::enumerate.
+    /// With an adapter, it's the index among that adapter's encoders. By
+    /// default the encoders are ranked (see get_encoder_rank), and the next
+    /// one is used if an encoder can't be set up.
+    pub fn encoder(mut self, encoder_index: usize) -> Self {
+        self.encoder_index = Some(encoder_index);
+        self
+    }
+
+    /// The GPU to capture and encode with, defaults to the default adapter.
+    pub fn adapter(mut self, adapter: AdapterSelection) -> Self {
+        self.adapter = Some(adapter);
+        self
+    }
+
+    /// Also uses software encoders (see VideoEncoderDevice::enumerate_software),
+    /// after any hardware encoders, e.g. on machines without a hardware encoder.
+    pub fn allow_software_encoder(mut self, allow_software_encoder: bool) -> Self {
+        self.allow_software_encoder = allow_software_encoder;
+        self
+    }
+
+    pub fn system_audio(mut self, system_audio: bool) -> Self {
+        self.system_audio = system_audio;
+        self
+    }
+
+    /// Records only the audio played by the process (and the processes it
+    /// started) with the id, in place of the system audio.
+    pub fn audio_process(mut self, process_id: u32) -> Self {
+        self.audio_process = Some(process_id);
+        self
+    }
+
+    /// The name of a microphone to record, see AudioCaptureDevice::find.
+    pub fn mic<S: Into<String>>(mut self, mic: S) -> Self {
+        self.mic = Some(mic.into());
+        self
+    }
+
+    /// Changes the volume of the microphone.
+    pub fn mic_gain(mut self, gain: AudioGain) -> Self {
+        self.mic_effects.gain = Some(gain);
+        self
+    }
+
+    /// Turns down the background noise of the microphone between speech.
+    pub fn mic_noise_suppression(mut self, noise_suppression: bool) -> Self {
+        self.mic_effects.noise_suppression = noise_suppression;
+        self
+    }
+
+    pub fn audio_tracks(mut self, audio_tracks: AudioTrackLayout) -> Self {
+        self.audio_tracks = audio_tracks;
+        self
+    }
+
+    /// How far the audio may drift from the video before it's corrected, as
+    /// the audio device's clock can run slightly faster or slower than the
+    /// system clock. Defaults to 10ms.
+    pub fn audio_sync_tolerance(mut self, audio_sync_tolerance: Duration) -> Self {
+        self.audio_sync_tolerance = audio_sync_tolerance;
+        self
+    }
+
+    /// Records only the system audio and/or microphone, to an M4A or FLAC
+    /// file. Nothing is captured from the screen.
+    pub fn no_video(mut self, no_video: bool) -> Self {
+        self.no_video = no_video;
+        self
+    }
+
+    /// Streams the recording to an RTMP or SRT server instead of writing it
+    /// to the output file. Streams use H.264 video and AAC audio.
+    pub fn stream(mut self, url: StreamUrl) -> Self {
+        self.stream = Some(url);
+        self
+    }
+
+    /// Writes the recording into the directory as HLS, fragmented MP4
+    /// segments and a playlist that can be watched while recording, instead
+    /// of writing the output file. Uses H.264 video and AAC audio.
+    pub fn hls<S: Into<String>>(mut self, directory: S) -> Self {
+        self.hls = Some(directory.into());
+        self
+    }
+
+    /// Also sends the frames to NDI as a source with the given name, so that
+    /// they can be picked up on the local network. When recording several
+    /// displays separately, each one gets its own numbered source.
+    pub fn ndi<S: Into<String>>(mut self, name: S) -> Self {
+        self.ndi = Some(name.into());
+        self
+    }
+
+    /// Only sends the frames to NDI, nothing is written to the output file.
+    pub fn ndi_only(mut self, ndi_only: bool) -> Self {
+        self.ndi_only = ndi_only;
+        self
+    }
+
+    /// Only keeps the last part of the recording, which is saved when stopped.
+    pub fn replay(mut self, replay: Duration) -> Self {
+        self.replay = Some(replay);
+        self
+    }
+
+    pub fn gif_settings(mut self, gif_settings: GifSettings) -> Self {
+        self.gif_settings = gif_settings;
+        self
+    }
+
+    /// Shows what is being recorded in a window while recording.
+    pub fn preview(mut self, preview: bool) -> Self {
+        self.preview = preview;
+        self
+    }
+
+    /// Saves a small JPEG of a frame from each recording next to it once the
+    /// recording is stopped (e.g. recording.jpg for recording.mp4).
+    pub fn thumbnail(mut self, thumbnail: bool) -> Self {
+        self.thumbnail = thumbnail;
+        self
+    }
+
+    /// Saves the markers (see RecordingSession::add_marker) as WebVTT chapters
+    /// next to each recording once it's stopped (e.g. recording.chapters.vtt
+    /// for recording.mp4). The chapters' times don't include pauses, like
+    /// the video's.
+    pub fn chapters(mut self, chapters: bool) -> Self {
+        self.chapters = chapters;
+        self
+    }
+
+    /// Adds a marker (see RecordingSession::add_marker) wherever the video
+    /// has a gap because the GPU was reset, so that it's easy to find. The
+    /// recording carries on after a reset either way.
+    pub fn mark_device_loss(mut self, mark_device_loss: bool) -> Self {
+        self.mark_device_loss = mark_device_loss;
+        self
+    }
+
+    /// Reads each recording back once it's finalized, and warns if it's
+    /// shorter than what was recorded (i.e. if the file was truncated).
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Only writes to the file while the screen is changing, along with the
+    /// pre-roll and post-roll around the changes. The time in between is
+    /// left out, e.g. for monitoring kiosks and dashboards.
+    pub fn record_on_change(mut self, record_on_change: bool) -> Self {
+        self.record_on_change = record_on_change;
+        self
+    }
+
+    /// How much of what came before a change is kept when recording on
+    /// change. Defaults to 3 seconds.
+    pub fn pre_roll(mut self, pre_roll: Duration) -> Self {
+        self.pre_roll = pre_roll;
+        self
+    }
+
+    /// How long to keep recording after the last change when recording on
+    /// change. Defaults to 5 seconds.
+    pub fn post_roll(mut self, post_roll: Duration) -> Self {
+        self.post_roll = post_roll;
+        self
+    }
+
+    /// Writes raw frames to a scratch file while recording, and only encodes
+    /// them once the recording is stopped, for when the encoder can't keep
+    /// up (e.g. with 4K at high frame rates). The disk has to keep up instead.
+    pub fn encode_later(mut self, encode_later: bool) -> Self {
+        self.encode_later = encode_later;
+        self
+    }
+
+    /// The callback is invoked for each frame of encode_later that's been
+    /// encoded after the recording is stopped, e.g. to tell that stopping is
+    /// still making progress.
+    pub fn on_encode_progress<F: Fn() + Send + Sync + 'static>(mut self, callback: F) -> Self {
+        self.encode_progress_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Where the scratch files of encode_later go, preferably a fast disk.
+    /// Defaults to the temp folder.
+    pub fn scratch_dir<S: Into<String>>(mut self, scratch_dir: S) -> Self {
+        self.scratch_dir = Some(scratch_dir.into());
+        self
+    }
+
+    /// Writes a title, author, and comment into the recording, so that media
+    /// libraries can show them. Only MP4, MKV, and WebM files have metadata.
+    pub fn metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Burns the current wall-clock time into the top left corner of the video.
+    pub fn clock_overlay(mut self, clock_overlay: bool) -> Self {
+        self.clock_overlay = clock_overlay;
+        self
+    }
+
+    /// Draws text or an image on top of the video, e.g. a logo.
+    pub fn watermark(mut self, watermark: WatermarkSettings) -> Self {
+        self.watermark = Some(watermark);
+        self
+    }
+
+    /// Draws a webcam feed on top of the video (picture-in-picture).
+    pub fn webcam(mut self, webcam: WebcamSettings) -> Self {
+        self.webcam = Some(webcam);
+        self
+    }
+
+    /// Shows another display or a window in an inset on top of the video
+    /// (picture-in-picture).
+    pub fn picture_in_picture(mut self, picture_in_picture: PictureInPictureSettings) -> Self {
+        self.picture_in_picture = Some(picture_in_picture);
+        self
+    }
+
+    /// Highlights mouse clicks with a circle, e.g. for tutorials.
+    pub fn show_clicks(mut self, show_clicks: bool) -> Self {
+        self.show_clicks = show_clicks;
+        self
+    }
+
+    /// Shows the last key combination pressed, e.g. for tutorials. Only shortcuts
+    /// (e.g. with ctrl, alt, or win) are shown, so that typed text such as
+    /// passwords isn't recorded.
+    pub fn show_keys(mut self, show_keys: bool) -> Self {
+        self.show_keys = show_keys;
+        self
+    }
+
+    /// Only shows these key combinations instead of all shortcuts.
+    pub fn key_allowlist(mut self, key_allowlist: Vec<KeyCombination>) -> Self {
+        self.key_allowlist = Some(key_allowlist);
+        self
+    }
+
+    /// Covers other windows (e.g. a chat or a password manager) wherever they
+    /// are in the recording. The recorder's own windows (e.g. the preview)
+    /// are always left out.
+    pub fn exclude_windows(mut self, windows: Vec<HWND>) -> Self {
+        self.excluded_windows = windows;
+        self
+    }
+
+    /// Covers the windows of the apps (named by their executable, e.g. slack),
+    /// including ones that open while recording, e.g. notifications.
+    pub fn redact_apps(mut self, apps: Vec<String>) -> Self {
+        self.redacted_apps = apps;
+        self
+    }
+
+    /// How excluded windows and the windows of redacted apps are covered.
+    pub fn redaction_style(mut self, style: RedactionStyle) -> Self {
+        self.redaction_style = style;
+        self
+    }
+
+    /// Pixelates regions of the recording (relative to its top left corner,
+    /// e.g. of the region being recorded), e.g. ones that show sensitive data.
+    pub fn blur_regions(mut self, regions: Vec<Region>) -> Self {
+        self.blur_regions = regions;
+        self
+    }
+
+    /// Prints what is being recorded and which encoder is used.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// The callback is invoked on the thread that starts or stops the recording.
+    pub fn on_event<F: Fn(RecordingEvent) + Send + Sync + 'static>(mut self, callback: F) -> Self {
+        self.event_callback = Some(Arc::new(callback));
+        self
+    }
+
+    pub fn build(self) -> std::result::Result<RecordingSession, RecorderError> {
+        unsafe { MFStartup(MF_VERSION, MFSTARTUP_FULL)? }
+
+        // An explicit format wins over the extension of the output file
+        let pipe = is_pipe_path(&self.output_path);
+        let output_path = match self.format {
+            Some(format) if !pipe => Path::new(&self.output_path)
+                .with_extension(format.extension())
+                .to_str()
+                .unwrap()
+                .to_owned(),
+            _ => self.output_path.clone(),
+        };
+        let output_path = output_path.as_str();
+        let container = if pipe {
+            // Pipes don't have an extension, so they default to fragmented MP4
+            self.format.unwrap_or(Container::Mp4)
+        } else if let Some(container) = Container::from_path(output_path) {
+            container
+        } else {
+            return Err(configuration_error("Invalid path specified!").into());
+        };
+        self.validate(container)?;
+        if self.no_video {
+            return Ok(self.build_audio_only(output_path, container)?);
+        }
+
+        // Check to make sure Windows.Graphics.Capture is available
+        if !self.test_pattern && !required_capture_features_supported()? {
+            return Err(configuration_error("The required screen capture features are not supported on this device for this release of Windows!\nPlease update your operating system (minimum: Windows 10 Version 1903, Build 18362).").into());
+        }
+
+        if !self.test_pattern && !self.capture_cursor && !cursor_capture_toggle_supported()? {
+            return Err(configuration_error("Excluding the cursor from the recording is not supported on this release of Windows!\nPlease update your operating system (minimum: Windows 10 Version 2004, Build 19041).").into());
+        }
+
+        // Each capture item is recorded to its own file, along with where the
+        // recording is on the desktop (for highlighting clicks). Pipes aren't
+        // files, so their paths aren't templates.
+        let verbose = self.verbose;
+        let region = self.region;
+        let template_values = TemplateValues::now();
+        let expand_output_path = |display: Option<String>, window: Option<String>| {
+            if pipe {
+                return output_path.to_owned();
+            }
+            let values = TemplateValues {
+                display,
+                window,
+                ..template_values
+            };
+            expand_template(output_path, &values)
+        };
+        let mut targets = Vec::new();
+        if self.test_pattern {
+            let test_pattern_output_path =
+                expand_output_path(Some("test-pattern".to_owned()), None);
+            if verbose {
+                println!(
+                    "Using a test pattern and path \"{}\".",
+                    test_pattern_output_path
+                );
+            }
+            targets.push((
+                Vec::new(),
+                test_pattern_output_path,
+                ScreenOrigin::point(PointInt32 { X: 0, Y: 0 }),
+            ));
+        } else if let Some(window) = self.window {
+            let window_output_path = expand_output_path(None, Some(get_window_title(window)));
+            if verbose {
+                println!(
+                    "Using window \"{:?}\" and path \"{}\".",
+                    window, window_output_path
+                );
+            }
+            let item = create_capture_item_for_window(window)?;
+            targets.push((
+                vec![CanvasItem::new(item)],
+                window_output_path,
+                ScreenOrigin::window(window),
+            ));
+        } else if !self.process_windows.is_empty() {
+            // The process is named after its executable, e.g. notepad
+            let process_name = get_window_process_name(self.process_windows[0])
+                .map(|name| name.trim_end_matches(".exe").to_owned());
+            let process_output_path = expand_output_path(None, process_name);
+            if verbose {
+                println!(
+                    "Compositing windows {:?} to path \"{}\".",
+                    self.process_windows, process_output_path
+                );
+            }
+
+            let mut items = Vec::new();
+            for &window in &self.process_windows {
+                let bounds = get_window_bounds(window).ok_or_else(|| {
+                    configuration_error("Could not get the bounds of the window!")
+                })?;
+                items.push((create_capture_item_for_window(window)?, bounds));
+            }
+            let origin = PointInt32 {
+                X: items.iter().map(|(_, bounds)| bounds.X).min().unwrap_or(0),
+                Y: items.iter().map(|(_, bounds)| bounds.Y).min().unwrap_or(0),
+            };
+            targets.push((
+                CanvasItem::arrange(items, self.layout),
+                process_output_path,
+                ScreenOrigin::point(origin),
+            ));
+        } else if self.composite {
+            let display_indices = resolve_display_indices(&self.displays, get_display_count());
+            let composite_output_path = expand_output_path(Some("composite".to_owned()), None);
+            if verbose {
+                println!(
+                    "Compositing displays {:?} to path \"{}\".",
+                    display_indices, composite_output_path
+                );
+            }
+
+            // Place each display based on where it is on the desktop
+            let mut items = Vec::new();
+            let mut display_handles = Vec::new();
+            for display_index in display_indices {
+                let display_handle = get_display_handle(display_index)?;
+                let bounds = get_display_bounds(display_handle).ok_or_else(|| {
+                    configuration_error("Could not get the bounds of the display!")
+                })?;
+                items.push((create_capture_item_for_monitor(display_handle)?, bounds));
+                display_handles.push(display_handle);
+            }
+            let origin = PointInt32 {
+                X: items.iter().map(|(_, bounds)| bounds.X).min().unwrap_or(0),
+                Y: items.iter().map(|(_, bounds)| bounds.Y).min().unwrap_or(0),
+            };
+            let items = CanvasItem::arrange(items, self.layout)
+                .into_iter()
+                .zip(display_handles)
+                .map(|(item, display_handle)| item.with_display(display_handle))
+                .collect();
+            targets.push((items, composite_output_path, ScreenOrigin::point(origin)));
+        } else {
+            let display_indices = resolve_display_indices(&self.displays, get_display_count());
+            // Monitors of the same model share a name, so names alone may
+            // not tell the files apart
+            let display_handles = display_indices
+                .iter()
+                .map(|&display_index| get_display_handle(display_index))
+                .collect::<Result<Vec<_>>>()?;
+            let display_output_paths: Vec<_> = display_handles
+                .iter()
+                .map(|&display_handle| expand_output_path(get_display_name(display_handle), None))
+                .collect();
+            let unique = display_output_paths
+                .iter()
+                .enumerate()
+                .all(|(i, path)| !display_output_paths[..i].contains(path));
+            for ((&display_index, display_handle), display_output_path) in display_indices
+                .iter()
+                .zip(display_handles)
+                .zip(display_output_paths)
+            {
+                let display_output_path = if display_indices.len() > 1
+                    && !(unique && has_token(output_path, "display"))
+                {
+                    get_output_path_for_display(&display_output_path, display_index)
+                } else {
+                    display_output_path
+                };
+                if verbose {
+                    println!(
+                        "Using index \"{}\" and path \"{}\".",
+                        display_index, display_output_path
+                    );
+                }
+
+                let item = create_capture_item_for_monitor(display_handle)?;
+                let bounds = get_display_bounds(display_handle).ok_or_else(|| {
+                    configuration_error("Could not get the bounds of the display!")
+                })?;
+                targets.push((
+                    vec![CanvasItem::new(item).with_display(display_handle)],
+                    display_output_path,
+                    ScreenOrigin::point(PointInt32 {
+                        X: bounds.X,
+                        Y: bounds.Y,
+                    }),
+                ));
+            }
+        }
+
+        if let Some(url) = &self.stream {
+            if targets.len() > 1 {
+                return Err(configuration_error(
+                    "Only one display can be streamed! Use --composite to stream several displays.",
+                )
+                .into());
+            }
+            if verbose {
+                println!("Streaming to \"{}\".", url.server());
+            }
+        }
+        if pipe && targets.len() > 1 {
+            return Err(configuration_error(
+                "Only one display can be written to a pipe! Use --composite to record several displays.",
+            ).into());
+        }
+        if self.hls.is_some() && targets.len() > 1 {
+            return Err(configuration_error(
+                "Only one display can be recorded to HLS! Use --composite to record several displays.",
+            ).into());
+        }
+
+        // Make sure the region fits within the capture items
+        if let Some(region) = region {
+            for canvas_item in targets.iter().flat_map(|(items, _, _)| items) {
+                if !region.fits_in(canvas_item.item.Size()?) {
+                    return Err(configuration_error(
+                        "The provided region is outside the bounds of the capture target!",
+                    )
+                    .into());
+                }
+            }
+        }
+
+        // Resolve encoding settings, GIFs don't use the hardware encoders
+        let bit_rate = self.bit_rate * 1000000;
+        let adapter = if let Some(selection) = &self.adapter {
+            let adapter = GraphicsAdapter::find(selection)?.ok_or_else(|| {
+                configuration_error(
+                    "Could not find an adapter matching the provided index or name! Use the enum-adapters command for a list of adapters.",
+                )
+            })?;
+            if verbose {
+                println!("Using adapter: {}", adapter.display_name());
+            }
+            Some(adapter)
+        } else {
+            None
+        };
+        let encoder_devices = if container != Container::Gif {
+            get_encoder_devices(
+                self.codec,
+                self.encoder_index,
+                adapter.as_ref(),
+                self.allow_software_encoder,
+                verbose,
+            )?
+        } else {
+            Vec::new()
+        };
+        // The webcam is shared by all of the files
+        let webcam_capture = if let Some(webcam) = &self.webcam {
+            if let Some(webcam_device) = WebcamDevice::find(&webcam.device)? {
+                if verbose {
+                    println!("Using webcam: {}", webcam_device.display_name());
+                }
+                Some(Arc::new(WebcamCapture::new(&webcam_device)?))
+            } else {
+                return Err(configuration_error(
+                    "Could not find a webcam matching the provided name!",
+                )
+                .into());
+            }
+        } else {
+            None
+        };
+
+        let mouse_hook = if self.show_clicks {
+            Some(Arc::new(MouseHook::new()?))
+        } else {
+            None
+        };
+
+        let keyboard_hook = if self.show_keys {
+            Some(Arc::new(KeyboardHook::new(self.key_allowlist.clone())?))
+        } else {
+            None
+        };
+
+        // Audio is only written to the first file
+        let audio_captures = self.create_audio_captures()?;
+        let mut audio = if !audio_captures.is_empty() {
+            Some((audio_captures, self.audio_tracks, self.audio_sync_tolerance))
+        } else {
+            None
+        };
+
+        // All of the files share a timeline so that they cover the same time range
+        let timeline = Timeline::new();
+        // Displays that are connected to a different adapter still work, as
+        // the capture copies their frames across adapters when it has to.
+        let d3d_device = if let Some(adapter) = &adapter {
+            create_d3d_device_on_adapter(&adapter.adapter().cast()?)?
+        } else {
+            create_d3d_device()?
+        };
+        let mut sample_writers = Vec::new();
+        let mut sessions = Vec::new();
+        let mut gif_sessions = Vec::new();
+        let mut output_paths = Vec::new();
+        let mut scratch_paths = Vec::new();
+        let mut segment_base_paths = Vec::new();
+        let mut chapter_paths = Vec::new();
+        let chapters = Arc::new(Mutex::new(Chapters::default()));
+        let target_count = targets.len();
+        let segmented = self.segment.is_some()
+            || (self.splittable
+                && container != Container::Gif
+                && self.replay.is_none()
+                && self.stream.is_none()
+                && self.hls.is_none()
+                && !pipe
+                && !self.ndi_only
+                && !self.fragmented);
+        // The files of a recording share an index, which is picked so that
+        // none of them are overwritten
+        let paths: Vec<_> = targets.iter().map(|(_, path, _)| path.clone()).collect();
+        let paths = resolve_index(&paths, |path| {
+            if segmented {
+                Path::new(&get_segment_output_path(path, 0)).exists()
+            } else {
+                Path::new(path).exists()
+            }
+        });
+        for (target, path) in targets.iter_mut().zip(paths) {
+            target.1 = path;
+        }
+        for (target_index, (mut items, output_path, origin)) in targets.into_iter().enumerate() {
+            if segmented {
+                output_paths.push(get_segment_output_path(&output_path, 0));
+                segment_base_paths.push(output_path.clone());
+            } else if let Some(directory) = &self.hls {
+                let playlist_path = Path::new(directory).join(PLAYLIST_NAME);
+                output_paths.push(playlist_path.to_str().unwrap().to_owned());
+            } else if self.stream.is_none() && !self.ndi_only {
+                output_paths.push(output_path.clone());
+            }
+            if self.chapters {
+                chapter_paths.push(get_chapters_path(&output_path));
+            }
+            if container == Container::Gif {
+                gif_sessions.push(GifEncodingSession::new(
+                    d3d_device.clone(),
+                    items.remove(0).item,
+                    region.map(|region| region.to_rect()),
+                    self.capture_cursor,
+                    self.gif_settings,
+                    timeline.clone(),
+                    output_path,
+                )?);
+                continue;
+            }
+
+            let sample_writer = if let Some(url) = &self.stream {
+                // Nothing is written to the output file
+                let writer: Box<dyn ContainerWriter> = match url {
+                    StreamUrl::Rtmp(url) => Box::new(RtmpWriter::new(url.clone())),
+                    StreamUrl::Srt(url) => Box::new(SrtWriter::new(url.clone())),
+                };
+                SampleWriter::new_live(writer, timeline.clone())
+            } else if let Some(directory) = &self.hls {
+                let output = Box::new(HlsOutput::new(directory)?);
+                let writer = FragmentWriter::new(output, Some(SEGMENT_DURATION));
+                SampleWriter::new_live(Box::new(writer), timeline.clone())
+            } else if pipe {
+                let stream = PipeStream::open(&output_path)?;
+                let writer: Box<dyn ContainerWriter> = if container == Container::H264 {
+                    Box::new(AnnexBWriter::new(Box::new(stream)))
+                } else {
+                    let output = Box::new(StreamOutput::new(stream));
+                    Box::new(FragmentWriter::new(output, None))
+                };
+                SampleWriter::new_live(writer, timeline.clone())
+            } else if self.ndi_only {
+                SampleWriter::new_live(Box::new(NullWriter::new()), timeline.clone())
+            } else if self.fragmented {
+                let file = create_std_file(output_paths.last().unwrap())?;
+                let output = Box::new(StreamOutput::new(file));
+                let writer = FragmentWriter::new(output, Some(FRAGMENT_DURATION));
+                SampleWriter::new_live(Box::new(writer), timeline.clone())
+            } else {
+                let stream = open_stream(output_paths.last().unwrap())?;
+                let mut sample_writer = SampleWriter::new(
+                    stream,
+                    container,
+                    timeline.clone(),
+                    self.replay,
+                    self.metadata.clone(),
+                )?;
+                if segmented {
+                    let base_path = output_path.clone();
+                    sample_writer = sample_writer.with_segments(
+                        self.segment,
+                        Box::new(move |index| {
+                            open_stream(&get_segment_output_path(&base_path, index))
+                        }),
+                    );
+                }
+                if self.record_on_change {
+                    sample_writer = sample_writer.with_change_gate(self.pre_roll, self.post_roll);
+                }
+                sample_writer
+            };
+            let sample_writer = Arc::new(sample_writer);
+            let frame_rate = match self.frame_rate {
+                Some(frame_rate) => frame_rate,
+                None => {
+                    let frame_rate = get_default_frame_rate(&items);
+                    if verbose {
+                        println!("Recording at {} fps.", frame_rate);
+                    }
+                    frame_rate
+                }
+            };
+            let mut builder =
+                VideoEncodingSession::builder(d3d_device.clone(), items, sample_writer.clone())
+                    .encoders(&encoder_devices)
+                    .bitrate(bit_rate)
+                    .frame_rate(frame_rate)
+                    .frame_rate_mode(self.frame_rate_mode)
+                    .frame_pacing(self.frame_pacing)
+                    .sample_queue(self.queue_size, self.queue_policy)
+                    .hdr(self.hdr)
+                    .bit_depth(self.bit_depth)
+                    .color_range(self.color_range)
+                    .orientation(self.orientation)
+                    .fit_mode(self.fit_mode)
+                    .auto_downscale(self.auto_downscale)
+                    .scene_change_keyframes(self.scene_keyframes)
+                    .capture_cursor(self.capture_cursor);
+            if let Some(resolution) = self.resolution.get_size() {
+                builder = builder.resolution(resolution);
+            }
+            if self.test_pattern {
+                let size = self.resolution.get_size().unwrap_or(TEST_PATTERN_SIZE);
+                let source = TestPatternSource::new(
+                    &d3d_device,
+                    size,
+                    ColorFormat::new(self.hdr, self.bit_depth).texture_format(),
+                    frame_rate,
+                    None,
+                )?
+                .real_time(true);
+                builder = builder.frame_source(Box::new(source), size);
+            }
+            if let Some(max_frame_rate) = self.max_frame_rate {
+                builder = builder.max_frame_rate(max_frame_rate);
+            }
+            if let Some(idle_frame_rate) = self.idle_frame_rate {
+                builder = builder.idle_frame_rate(idle_frame_rate, self.idle_after);
+            }
+            if self.record_on_change {
+                let sample_writer = sample_writer.clone();
+                builder =
+                    builder.on_content_change(move |time| sample_writer.content_changed(time));
+            }
+            if self.encode_later {
+                let scratch_path = get_scratch_path(self.scratch_dir.as_deref(), &output_path);
+                builder = builder.encode_later(scratch_path.as_str());
+                scratch_paths.push(scratch_path);
+                if let Some(callback) = self.encode_progress_callback.clone() {
+                    builder = builder.on_encode_progress(move || callback());
+                }
+            }
+            if let Some(scale_filter) = self.scale_filter {
+                builder = builder.scale_filter(scale_filter);
+            }
+            if let Some(rate_control) = self.rate_control {
+                builder = builder.rate_control(rate_control);
+            }
+            if let Some(quality) = self.quality {
+                builder = builder.quality(quality);
+            }
+            // Segments can only start at key frames, so there needs to be one per segment
+            let gop_size = self.gop_size.or_else(|| {
+                if self.hls.is_some() {
+                    Some(frame_rate * SEGMENT_DURATION.as_secs() as u32)
+                } else if self.fragmented {
+                    Some(frame_rate * FRAGMENT_DURATION.as_secs() as u32)
+                } else if self.record_on_change {
+                    // So that the pre-roll starts close to where it should
+                    Some(frame_rate)
+                } else {
+                    None
+                }
+            });
+            if let Some(gop_size) = gop_size {
+                builder = builder.gop_size(gop_size);
+            }
+            if let Some(b_frames) = self.b_frames {
+                builder = builder.b_frames(b_frames);
+            }
+            if let Some(region) = region {
+                builder = builder.region(region.to_rect());
+            }
+            if let (Some(window), Some(action)) = (self.window, self.on_minimize) {
+                builder = builder.on_minimize(window, action);
+            }
+            if let Some(action) = self.on_secure_desktop {
+                builder = builder.on_secure_desktop(action);
+            }
+            if let Some((audio_captures, audio_tracks, sync_tolerance)) = audio.take() {
+                builder = builder.audio(audio_captures, audio_tracks, sync_tolerance);
+            }
+            if self.preview {
+                builder = builder.preview(format!("Preview - {}", output_path));
+            }
+            if self.mark_device_loss {
+                let timeline = timeline.clone();
+                let chapters = self.chapters.then(|| chapters.clone());
+                let event_callback = self.event_callback.clone();
+                builder = builder.on_device_lost(move || {
+                    let time = Duration::from_nanos(timeline.elapsed() as u64 * 100);
+                    if let Some(chapters) = &chapters {
+                        chapters.lock().unwrap().add(time, DEVICE_LOST_LABEL);
+                    }
+                    if let Some(callback) = &event_callback {
+                        callback(RecordingEvent::Marker {
+                            time,
+                            label: DEVICE_LOST_LABEL.to_owned(),
+                        });
+                    }
+                });
+            }
+            if self.thumbnail {
+                builder = builder.thumbnail(get_thumbnail_path(&output_path));
+            }
+            if let Some(name) = &self.ndi {
+                builder = builder.ndi(get_ndi_name(name, target_index, target_count));
+            }
+            let origin = if let Some(region) = region {
+                origin.offset(region.x, region.y)
+            } else {
+                origin
+            };
+            let redacted_windows =
+                RedactedWindows::new(self.excluded_windows.clone(), self.redacted_apps.clone());
+            if !redacted_windows.is_empty() && self.redaction_style == RedactionStyle::Pixelate {
+                builder = builder.blur_windows(redacted_windows.clone(), origin);
+            }
+            if !self.blur_regions.is_empty() {
+                builder = builder.blur_regions(
+                    self.blur_regions
+                        .iter()
+                        .map(|region| region.to_rect())
+                        .collect(),
+                );
+            }
+            if self.clock_overlay {
+                builder = builder.overlay(Box::new(ClockOverlay::new()?));
+            }
+            if let Some(watermark) = &self.watermark {
+                builder = builder.overlay(Box::new(WatermarkOverlay::new(watermark)?));
+            }
+            if let Some(factor) = self.zoom_follow {
+                builder = builder.zoom_follow(factor, origin);
+            }
+            if let Some(picture_in_picture) = &self.picture_in_picture {
+                let item = match picture_in_picture.source {
+                    PictureInPictureSource::Display(display_index) => {
+                        create_capture_item_for_monitor(get_display_handle(display_index)?)?
+                    }
+                    PictureInPictureSource::Window(window) => {
+                        create_capture_item_for_window(window)?
+                    }
+                };
+                builder = builder.picture_in_picture(
+                    item,
+                    picture_in_picture.position,
+                    picture_in_picture.size,
+                );
+            }
+            if let Some(mouse_hook) = &mouse_hook {
+                builder = builder.overlay(Box::new(ClickOverlay::new(mouse_hook.clone(), origin)));
+            }
+            // Also covers clicks on the excluded windows
+            if !redacted_windows.is_empty() && self.redaction_style == RedactionStyle::Blank {
+                builder = builder.overlay(Box::new(ExcludedWindowsOverlay::new(
+                    redacted_windows,
+                    origin,
+                )));
+            }
+            if let Some(keyboard_hook) = &keyboard_hook {
+                builder = builder.overlay(Box::new(KeyOverlay::new(keyboard_hook.clone())?));
+            }
+            if let (Some(webcam), Some(webcam_capture)) = (&self.webcam, &webcam_capture) {
+                builder =
+                    builder.overlay(Box::new(WebcamOverlay::new(webcam_capture.clone(), webcam)));
+            }
+            let session = builder.build();
+            if session.is_err() {
+                println!("Error during encoder setup, try another set of encoding settings.");
+            }
+            sample_writers.push(sample_writer);
+            sessions.push(session?);
+        }
+
+        Ok(RecordingSession {
+            timeline,
+            sample_writers,
+            audio_level_meter: sessions
+                .iter()
+                .find_map(|session| session.audio_level_meter().cloned()),
+            sessions,
+            gif_sessions,
+            audio_sessions: Vec::new(),
+            output_paths,
+            scratch_paths,
+            segment_base_paths,
+            chapters,
+            chapter_paths,
+            verify: self.verify,
+            event_callback: self.event_callback,
+            started: false,
+        })
+    }
+
+    // Audio-only recordings skip the screen capture entirely, and are
+    // written to a single file
+    fn build_audio_only(self, output_path: &str, container: Container) -> Result<RecordingSession> {
+        let output_path = expand_template(output_path, &TemplateValues::now());
+        let output_path = resolve_index(&[output_path], |path| Path::new(path).exists()).remove(0);
+        if self.verbose {
+            println!("Recording audio to path \"{}\".", output_path);
+        }
+        let audio_captures = self.create_audio_captures()?;
+
+        let timeline = Timeline::new();
+        let sample_writer = Arc::new(SampleWriter::new(
+            open_stream(&output_path)?,
+            container,
+            timeline.clone(),
+            None,
+            self.metadata.clone(),
+        )?);
+        let audio_session = AudioEncodingSession::new(
+            audio_captures,
+            self.audio_tracks,
+            self.audio_sync_tolerance,
+            sample_writer.clone(),
+        )?;
+        let chapter_paths = if self.chapters {
+            vec![get_chapters_path(&output_path)]
+        } else {
+            Vec::new()
+        };
+
+        Ok(RecordingSession {
+            timeline,
+            sample_writers: vec![sample_writer],
+            sessions: Vec::new(),
+            gif_sessions: Vec::new(),
+            audio_level_meter: Some(audio_session.level_meter().clone()),
+            audio_sessions: vec![audio_session],
+            output_paths: vec![output_path],
+            scratch_paths: Vec::new(),
+            segment_base_paths: Vec::new(),
+            chapters: Arc::new(Mutex::new(Chapters::default())),
+            chapter_paths,
+            verify: false,
+            event_callback: self.event_callback,
+            started: false,
+        })
+    }
+
+    fn create_audio_captures(&self) -> Result<Vec<AudioCapture>> {
+        let mut audio_captures = Vec::new();
+        if self.system_audio {
+            audio_captures.push(AudioCapture::new_loopback()?);
+        }
+        if let Some(process_id) = self.audio_process {
+            audio_captures.push(AudioCapture::new_for_process(process_id)?);
+        }
+        if let Some(mic) = &self.mic {
+            let mic_device = AudioCaptureDevice::find(mic)?.ok_or_else(|| {
+                configuration_error("Could not find a microphone matching the provided name!")
+            })?;
+            if self.verbose {
+                println!("Using microphone: {}", mic_device.display_name());
+            }
+            audio_captures
+                .push(AudioCapture::new_for_device(&mic_device)?.with_effects(self.mic_effects));
+        }
+        Ok(audio_captures)
+    }
+
+    // The system audio, or the audio of a single app
+    fn has_app_audio(&self) -> bool {
+        self.system_audio || self.audio_process.is_some()
+    }
+
+    fn has_overlays(&self) -> bool {
+        self.clock_overlay
+            || self.watermark.is_some()
+            || self.webcam.is_some()
+            || self.show_clicks
+            || self.show_keys
+            || !self.excluded_windows.is_empty()
+            || !self.redacted_apps.is_empty()
+            || !self.blur_regions.is_empty()
+    }
+
+    fn validate(&self, container: Container) -> Result<()> {
+        let codec = self.codec;
+        let has_audio = self.has_app_audio() || self.mic.is_some();
+        match container {
+            Container::Mp4 if codec == VideoCodec::Vp9 => {
+                return Err(configuration_error(
+                    "VP9 can't be recorded to MP4! Use a .webm or .mkv output file instead.",
+                ))
+            }
+            Container::Mkv if codec != VideoCodec::H264 && codec != VideoCodec::Vp9 => {
+                return Err(configuration_error(
+                    "MKV recordings currently only support H.264 or VP9 video!",
+                ))
+            }
+            Container::Webm if codec != VideoCodec::Vp9 => {
+                return Err(configuration_error(
+                    "WebM recordings require VP9 video! Use --codec vp9.",
+                ))
+            }
+            Container::Webm if has_audio => return Err(configuration_error(
+                "WebM recordings don't support audio yet! Use a .mkv or .mp4 output file instead.",
+            )),
+            Container::Gif if has_audio || self.replay.is_some() || self.composite => {
+                return Err(configuration_error(
+                    "GIF recordings don't support audio, replays, or compositing!",
+                ))
+            }
+            Container::Gif if self.preview || self.thumbnail || self.has_overlays() => {
+                return Err(configuration_error(
+                    "GIF recordings don't support previews, thumbnails, or overlays!",
+                ))
+            }
+            Container::Gif if self.segment.is_some() => {
+                return Err(configuration_error("GIF recordings can't be segmented!"))
+            }
+            Container::Gif if self.ndi.is_some() => {
+                return Err(configuration_error("GIF recordings can't be sent to NDI!"))
+            }
+            Container::Gif if self.frame_pacing != FramePacing::Log => {
+                return Err(configuration_error(
+                    "GIF recordings don't support --frame-pacing!",
+                ))
+            }
+            Container::Gif
+                if self.queue_size != DEFAULT_QUEUE_SIZE
+                    || self.queue_policy != QueuePolicy::Block =>
+            {
+                return Err(configuration_error(
+                    "GIF recordings don't support --queue-size or --queue-policy!",
+                ))
+            }
+            Container::Gif if self.mark_device_loss => {
+                return Err(configuration_error(
+                    "GIF recordings don't support --mark-device-loss!",
+                ))
+            }
+            Container::Gif if self.auto_downscale => {
+                return Err(configuration_error(
+                    "GIF recordings don't support --auto-downscale!",
+                ))
+            }
+            Container::Gif if self.test_pattern => {
+                return Err(configuration_error(
+                    "GIF recordings don't support --test-pattern!",
+                ))
+            }
+            Container::Gif if self.orientation != Orientation::default() => {
+                return Err(configuration_error(
+                    "GIF recordings don't support --rotate or --flip!",
+                ))
+            }
+            Container::Gif if self.scale_filter.is_some() => {
+                return Err(configuration_error(
+                    "GIF recordings don't support --scale-filter!",
+                ))
+            }
+            Container::Gif if self.fit_mode != FitMode::Letterbox => {
+                return Err(configuration_error("GIF recordings don't support --fit!"))
+            }
+            Container::Gif if self.picture_in_picture.is_some() => {
+                return Err(configuration_error(
+                    "GIF recordings don't support picture-in-picture!",
+                ))
+            }
+            Container::Gif if self.zoom_follow.is_some() => {
+                return Err(configuration_error(
+                    "GIF recordings don't support --zoom-follow!",
+                ))
+            }
+            Container::Gif if self.on_minimize.is_some() => {
+                return Err(configuration_error(
+                    "GIF recordings don't support --on-minimize!",
+                ))
+            }
+            Container::Gif if self.on_secure_desktop.is_some() => {
+                return Err(configuration_error(
+                    "GIF recordings don't support --on-secure-desktop!",
+                ))
+            }
+            Container::M4a | Container::Flac if !self.no_video => return Err(configuration_error(
+                "M4A and FLAC files can only hold audio! Use --no-video to record just the audio.",
+            )),
+            Container::Flac
+                if self.audio_tracks == AudioTrackLayout::Separate
+                    && self.has_app_audio()
+                    && self.mic.is_some() =>
+            {
+                return Err(configuration_error(
+                    "FLAC files only support a single audio track! Use --audio-tracks mixed.",
+                ))
+            }
+            Container::H264 if codec != VideoCodec::H264 => {
+                return Err(configuration_error(
+                    "Raw H.264 recordings require the H.264 codec! Use --codec h264.",
+                ))
+            }
+            Container::H264 if has_audio => {
+                return Err(configuration_error(
+                    "Raw H.264 recordings can't have audio! Use an .mp4 output file instead.",
+                ))
+            }
+            Container::Gif if self.hdr || self.bit_depth != BitDepth::Eight => {
+                return Err(configuration_error(
+                    "GIF recordings don't support HDR or 10-bit video!",
+                ))
+            }
+            _ if (self.hdr || self.bit_depth == BitDepth::Ten) && codec != VideoCodec::Hevc => {
+                return Err(configuration_error(
+                    "HDR and 10-bit recordings require the HEVC codec! Use --codec hevc.",
+                ))
+            }
+            _ => {}
+        }
+        if self.system_audio && self.audio_process.is_some() {
+            return Err(configuration_error(
+                "--audio-process records a single app in place of the system audio! Use either --system-audio or --audio-process.",
+            ));
+        }
+        if self.queue_size == 0 {
+            return Err(configuration_error("--queue-size must be at least 1!"));
+        }
+        if self.test_pattern
+            && (self.window.is_some() || !self.process_windows.is_empty() || self.composite)
+        {
+            return Err(configuration_error(
+                "--test-pattern records in place of a display! It can't be used with --window, --process, or --composite.",
+            ));
+        }
+        if !self.mic_effects.is_empty() && self.mic.is_none() {
+            return Err(configuration_error(
+                "--mic-gain and --mic-noise-suppression require a microphone! Use --mic.",
+            ));
+        }
+        if self.no_video {
+            if !has_audio {
+                return Err(configuration_error(
+                    "Audio-only recordings need audio! Use --system-audio, --audio-process, or --mic.",
+                ));
+            }
+            if !container.is_audio_only() {
+                return Err(configuration_error(
+                    "Audio-only recordings can only be saved as M4A or FLAC files! Use an .m4a or .flac output file.",
+                ));
+            }
+            if self.stream.is_some()
+                || self.hls.is_some()
+                || self.ndi.is_some()
+                || self.segment.is_some()
+                || self.replay.is_some()
+                || self.fragmented
+            {
+                return Err(configuration_error(
+                    "Audio-only recordings can't be streamed, written as HLS, sent to NDI, segmented, replayed, or fragmented!",
+                ));
+            }
+        }
+        if let Some(watermark) = &self.watermark {
+            if !(0.0..=1.0).contains(&watermark.opacity) {
+                return Err(configuration_error(
+                    "The watermark opacity must be between 0 and 1!",
+                ));
+            }
+        }
+        if let Some(picture_in_picture) = &self.picture_in_picture {
+            if picture_in_picture.size == 0 || picture_in_picture.size > 100 {
+                return Err(configuration_error(
+                    "The picture-in-picture size must be between 1 and 100 percent!",
+                ));
+            }
+        }
+        if let Some(webcam) = &self.webcam {
+            if webcam.size == 0 || webcam.size > 100 {
+                return Err(configuration_error(
+                    "The webcam size must be between 1 and 100 percent!",
+                ));
+            }
+        }
+        if self.ndi.is_some() && self.hdr {
+            return Err(configuration_error(
+                "NDI output isn't supported for HDR recordings!",
+            ));
+        }
+        if self.ndi_only {
+            if self.ndi.is_none() {
+                return Err(configuration_error(
+                    "NDI-only recordings need a source name! Use --ndi.",
+                ));
+            }
+            if self.stream.is_some()
+                || self.segment.is_some()
+                || self.replay.is_some()
+                || self.thumbnail
+            {
+                return Err(configuration_error(
+                    "NDI-only recordings can't be streamed, segmented, replayed, or have thumbnails!",
+                ));
+            }
+        }
+        if self.stream.is_some() {
+            if container == Container::Gif {
+                return Err(configuration_error("GIF recordings can't be streamed!"));
+            }
+            if codec != VideoCodec::H264 {
+                return Err(configuration_error(
+                    "Streaming requires the H.264 codec! Use --codec h264.",
+                ));
+            }
+            if self.segment.is_some() || self.replay.is_some() || self.thumbnail {
+                return Err(configuration_error(
+                    "Streams can't be segmented, replayed, or have thumbnails!",
+                ));
+            }
+            if self.audio_tracks == AudioTrackLayout::Separate
+                && self.has_app_audio()
+                && self.mic.is_some()
+            {
+                return Err(configuration_error(
+                    "Streams only support a single audio track! Use --audio-tracks mixed.",
+                ));
+            }
+        }
+        if self.hls.is_some() {
+            if container == Container::Gif {
+                return Err(configuration_error(
+                    "GIF recordings can't be written as HLS!",
+                ));
+            }
+            if codec != VideoCodec::H264 {
+                return Err(configuration_error(
+                    "HLS recordings require the H.264 codec! Use --codec h264.",
+                ));
+            }
+            if self.stream.is_some() || self.ndi_only {
+                return Err(configuration_error(
+                    "HLS recordings can't also be streamed or only sent to NDI!",
+                ));
+            }
+            if self.segment.is_some() || self.replay.is_some() || self.thumbnail {
+                return Err(configuration_error(
+                    "HLS recordings are already segmented, and can't be replayed or have thumbnails!",
+                ));
+            }
+            if self.audio_tracks == AudioTrackLayout::Separate
+                && self.has_app_audio()
+                && self.mic.is_some()
+            {
+                return Err(configuration_error(
+                    "HLS recordings only support a single audio track! Use --audio-tracks mixed.",
+                ));
+            }
+        }
+        if is_pipe_path(&self.output_path) {
+            if container != Container::Mp4 && container != Container::H264 {
+                return Err(configuration_error(
+                    "Only MP4 and raw H.264 recordings can be written to a pipe! Use --format mp4 or --format h264.",
+                ));
+            }
+            if codec != VideoCodec::H264 {
+                return Err(configuration_error(
+                    "Pipes require the H.264 codec! Use --codec h264.",
+                ));
+            }
+            if self.stream.is_some() || self.hls.is_some() || self.ndi_only {
+                return Err(configuration_error(
+                    "Recordings written to a pipe can't also be streamed, written as HLS, or only sent to NDI!",
+                ));
+            }
+            if self.segment.is_some() || self.replay.is_some() || self.thumbnail {
+                return Err(configuration_error(
+                    "Recordings written to a pipe can't be segmented, replayed, or have thumbnails!",
+                ));
+            }
+            if self.audio_tracks == AudioTrackLayout::Separate
+                && self.has_app_audio()
+                && self.mic.is_some()
+            {
+                return Err(configuration_error(
+                    "Pipes only support a single audio track! Use --audio-tracks mixed.",
+                ));
+            }
+        }
+        if !self.metadata.is_empty() {
+            if !matches!(
+                container,
+                Container::Mp4 | Container::M4a | Container::Mkv | Container::Webm
+            ) {
+                return Err(configuration_error(
+                    "Metadata can only be written to MP4, M4A, MKV, and WebM files!",
+                ));
+            }
+            if is_pipe_path(&self.output_path)
+                || self.stream.is_some()
+                || self.hls.is_some()
+                || self.ndi_only
+                || self.fragmented
+            {
+                return Err(configuration_error(
+                    "Metadata can't be written to pipes, streams, HLS, NDI, or fragmented recordings!",
+                ));
+            }
+        }
+        if self.chapters {
+            if is_pipe_path(&self.output_path)
+                || self.stream.is_some()
+                || self.hls.is_some()
+                || self.ndi_only
+            {
+                return Err(configuration_error(
+                    "Chapters are only saved for recordings to files!",
+                ));
+            }
+            // The chapters wouldn't line up with the files
+            if self.segment.is_some() || self.replay.is_some() || self.record_on_change {
+                return Err(configuration_error(
+                    "Chapters can't be saved for segmented, replayed, or change-triggered recordings!",
+                ));
+            }
+        }
+        if self.verify {
+            if is_pipe_path(&self.output_path)
+                || self.stream.is_some()
+                || self.hls.is_some()
+                || self.ndi_only
+            {
+                return Err(configuration_error(
+                    "Only recordings to files can be verified!",
+                ));
+            }
+            if !matches!(container, Container::Mp4 | Container::Mkv | Container::Webm) {
+                return Err(configuration_error(
+                    "Only MP4, MKV, and WebM recordings can be verified!",
+                ));
+            }
+            // Only the end of the recording is saved
+            if self.replay.is_some() {
+                return Err(configuration_error("Replays can't be verified!"));
+            }
+            if self.record_on_change {
+                return Err(configuration_error(
+                    "Recordings made with --record-on-change can't be verified!",
+                ));
+            }
+        }
+        if self.record_on_change {
+            if is_pipe_path(&self.output_path)
+                || self.stream.is_some()
+                || self.hls.is_some()
+                || self.ndi_only
+                || self.fragmented
+            {
+                return Err(configuration_error(
+                    "Only recordings to (unfragmented) files can be made with --record-on-change!",
+                ));
+            }
+            if self.no_video || container == Container::Gif {
+                return Err(configuration_error(
+                    "Audio-only and GIF recordings can't be made with --record-on-change!",
+                ));
+            }
+            if self.replay.is_some() {
+                return Err(configuration_error(
+                    "Replays can't be made with --record-on-change!",
+                ));
+            }
+        }
+        if self.encode_later {
+            if is_pipe_path(&self.output_path)
+                || self.stream.is_some()
+                || self.hls.is_some()
+                || self.ndi_only
+            {
+                return Err(configuration_error(
+                    "Only recordings to files can be made with --encode-later!",
+                ));
+            }
+            if self.no_video || container == Container::Gif {
+                return Err(configuration_error(
+                    "Audio-only and GIF recordings can't be made with --encode-later!",
+                ));
+            }
+            // Both only work with samples as they're recorded
+            if self.replay.is_some() || self.record_on_change {
+                return Err(configuration_error(
+                    "Replays and --record-on-change can't be made with --encode-later!",
+                ));
+            }
+        }
+        if self.fragmented {
+            if container != Container::Mp4 || is_pipe_path(&self.output_path) {
+                return Err(configuration_error(
+                    "Only MP4 files can be fragmented! Use an .mp4 output file.",
+                ));
+            }
+            if codec != VideoCodec::H264 {
+                return Err(configuration_error(
+                    "Fragmented recordings require the H.264 codec! Use --codec h264.",
+                ));
+            }
+            if self.stream.is_some() || self.hls.is_some() || self.ndi_only {
+                return Err(configuration_error(
+                    "Fragmented recordings can't also be streamed, written as HLS, or only sent to NDI!",
+                ));
+            }
+            if self.segment.is_some() || self.replay.is_some() {
+                return Err(configuration_error(
+                    "Fragmented recordings can't be segmented or replayed!",
+                ));
+            }
+            if self.audio_tracks == AudioTrackLayout::Separate
+                && self.has_app_audio()
+                && self.mic.is_some()
+            {
+                return Err(configuration_error(
+                    "Fragmented recordings only support a single audio track! Use --audio-tracks mixed.",
+                ));
+            }
+        }
+        if self.thumbnail && self.hdr {
+            return Err(configuration_error(
+                "Thumbnails aren't supported for HDR recordings!",
+            ));
+        }
+        if self.segment.is_some() && self.replay.is_some() {
+            return Err(configuration_error("Replays can't be segmented!"));
+        }
+        if let Some(window) = self.window {
+            if self.excluded_windows.contains(&window) {
+                return Err(configuration_error(
+                    "The window being recorded can't be excluded!",
+                ));
+            }
+        }
+        if !self.process_windows.is_empty() {
+            if self.window.is_some() {
+                return Err(configuration_error(
+                    "Either a window or a process can be recorded, not both!",
+                ));
+            }
+            if self
+                .process_windows
+                .iter()
+                .any(|window| self.excluded_windows.contains(window))
+            {
+                return Err(configuration_error(
+                    "The windows being recorded can't be excluded!",
+                ));
+            }
+            if self.composite {
+                return Err(configuration_error("Only displays can be composited!"));
+            }
+            if self.region.is_some() {
+                return Err(configuration_error(
+                    "A region can't be recorded when recording a process!",
+                ));
+            }
+        }
+        if self.zoom_follow.is_some() && self.scale_filter.is_some() {
+            return Err(configuration_error(
+                "Zooming doesn't support --scale-filter!",
+            ));
+        }
+        if self.on_minimize.is_some() && self.window.is_none() {
+            return Err(configuration_error(
+                "Only window recordings support --on-minimize!",
+            ));
+        }
+        if self.layout != CanvasLayout::Desktop {
+            if !self.composite && self.process_windows.is_empty() {
+                return Err(configuration_error(
+                    "--layout requires --composite or --process!",
+                ));
+            }
+            // Things on the desktop can't be mapped onto the frame
+            if self.show_clicks
+                || self.zoom_follow.is_some()
+                || !self.excluded_windows.is_empty()
+                || !self.redacted_apps.is_empty()
+            {
+                return Err(configuration_error(
+                    "--layout doesn't support --show-clicks, --zoom-follow, or redacting windows!",
+                ));
+            }
+        }
+        if self.composite && self.window.is_some() {
+            return Err(configuration_error("Only displays can be composited!"));
+        }
+        if self.composite && self.region.is_some() {
+            return Err(configuration_error(
+                "A region can't be recorded when compositing displays!",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl RecordingSession {
+    /// The files that are being recorded to.
+    pub fn output_paths(&self) -> &[String] {
+        &self.output_paths
+    }
+
+    /// Starts capturing ahead of start, e.g. while counting down to it, so
+    /// that the first frames aren't late. Also done by start if it wasn't.
+    pub fn start_capture(&mut self) -> std::result::Result<(), RecorderError> {
+        for session in &mut self.sessions {
+            session
+                .start_capture()
+                .map_err(in_stage(RecordingStage::Capture))?;
+        }
+        Ok(())
+    }
+
+    pub fn start(&mut self) -> std::result::Result<(), RecorderError> {
+        etw::register_provider();
+        self.start_capture()?;
+        for sample_writer in &self.sample_writers {
+            sample_writer
+                .start()
+                .map_err(in_stage(RecordingStage::Write))?;
+        }
+        self.timeline.start();
+        for session in &mut self.sessions {
+            session.start().map_err(in_stage(RecordingStage::Capture))?;
+        }
+        for audio_session in &mut self.audio_sessions {
+            audio_session
+                .start()
+                .map_err(in_stage(RecordingStage::Audio))?;
+        }
+        for gif_session in &mut self.gif_sessions {
+            gif_session
+                .start()
+                .map_err(in_stage(RecordingStage::Capture))?;
+        }
+        self.started = true;
+        self.raise_event(RecordingEvent::Started);
+        Ok(())
+    }
+
+    /// Pausing removes the time spent paused from the recording.
+    pub fn pause(&mut self) {
+        if self.started && !self.timeline.is_paused() {
+            self.timeline.pause();
+            self.raise_event(RecordingEvent::Paused);
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if self.started && self.timeline.is_paused() {
+            self.timeline.resume();
+            self.raise_event(RecordingEvent::Resumed);
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.timeline.is_paused()
+    }
+
+    /// How long the recording has been running, not including the time spent
+    /// paused. Once stopped, this is the length of the recording.
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.timeline.elapsed() as u64 * 100)
+    }
+
+    /// How much has been encoded so far, summed over all of the files (or
+    /// streams) being recorded to. GIF recordings aren't counted.
+    pub fn stats(&self) -> RecordingStats {
+        RecordingStats::combine(
+            self.sample_writers
+                .iter()
+                .map(|sample_writer| sample_writer.stats()),
+        )
+    }
+
+    /// The peak level of each audio source (in dBFS, the system audio before
+    /// the microphone) since the levels were last taken. Empty if no audio
+    /// is recorded.
+    pub fn take_audio_levels(&self) -> Vec<f32> {
+        self.audio_level_meter
+            .as_ref()
+            .map(|level_meter| level_meter.take_levels())
+            .unwrap_or_default()
+    }
+
+    /// How long no audio has been heard from any source, not including the
+    /// time spent paused. None if no audio is recorded.
+    pub fn silence_duration(&self) -> Option<Duration> {
+        self.audio_level_meter
+            .as_ref()
+            .map(|level_meter| level_meter.silence_duration(self.timeline.elapsed()))
+    }
+
+    /// The least space left on the volumes being recorded to (including the
+    /// scratch files of encode_later), or None if nothing is recorded to
+    /// files (e.g. when streaming).
+    pub fn free_space(&self) -> Option<u64> {
+        self.output_paths
+            .iter()
+            .filter(|path| !is_pipe_path(path))
+            .chain(&self.scratch_paths)
+            .filter_map(get_free_space)
+            .min()
+    }
+
+    /// Encodes the next frame of each video as a keyframe, e.g. to mark a chapter
+    /// or a segment boundary. Has no effect on GIF recordings.
+    pub fn request_keyframe(&self) {
+        if self.started {
+            for session in &self.sessions {
+                session.request_keyframe();
+            }
+        }
+    }
+
+    /// Marks the current time of the recording, which is reported with a
+    /// Marker event (and saved as a chapter, see RecorderBuilder::chapters).
+    /// The next frame is encoded as a keyframe so that players can seek
+    /// right to it.
+    pub fn add_marker<S: Into<String>>(&self, label: S) -> Duration {
+        let time = self.elapsed();
+        let label = label.into();
+        self.request_keyframe();
+        if !self.chapter_paths.is_empty() {
+            self.chapters.lock().unwrap().add(time, &label);
+        }
+        self.raise_event(RecordingEvent::Marker { time, label });
+        time
+    }
+
+    /// Continues the recording in new files, starting at the next keyframe
+    /// (which is requested right away). Only segmented or splittable
+    /// recordings can be split.
+    pub fn split(&self) -> std::result::Result<(), RecorderError> {
+        let mut split = false;
+        for sample_writer in &self.sample_writers {
+            split |= sample_writer.split();
+        }
+        if !split {
+            return Err(RecorderError::InvalidSettings(
+                "Only recordings to segmented or splittable files can be split!".to_owned(),
+            ));
+        }
+        self.request_keyframe();
+        Ok(())
+    }
+
+    pub fn stop(&mut self) -> std::result::Result<(), RecorderError> {
+        if !self.started {
+            return Ok(());
+        }
+        self.started = false;
+        for session in &mut self.sessions {
+            session.stop()?;
+        }
+        for audio_session in &mut self.audio_sessions {
+            audio_session
+                .stop()
+                .map_err(in_stage(RecordingStage::Audio))?;
+        }
+        for gif_session in &mut self.gif_sessions {
+            gif_session
+                .stop()
+                .map_err(in_stage(RecordingStage::Encode))?;
+        }
+        for sample_writer in &self.sample_writers {
+            sample_writer
+                .stop()
+                .map_err(in_stage(RecordingStage::Write))?;
+        }
+        // Keeps elapsed at the length of the recording
+        self.timeline.pause();
+        self.save_chapters()
+            .map_err(in_stage(RecordingStage::Write))?;
+        if !self.segment_base_paths.is_empty() {
+            self.output_paths = self
+                .segment_base_paths
+                .iter()
+                .zip(&self.sample_writers)
+                .flat_map(|(base_path, sample_writer)| {
+                    (0..sample_writer.segment_count())
+                        .map(|index| get_segment_output_path(base_path, index))
+                })
+                .collect();
+        }
+        if self.verify {
+            self.verify_outputs();
+        }
+        self.raise_event(RecordingEvent::Stopped {
+            output_paths: self.output_paths.clone(),
+        });
+        Ok(())
+    }
+
+    // The segments of a recording are verified together, since it's split
+    // wherever the writer was
+    fn verify_outputs(&self) {
+        let recordings: Vec<Vec<String>> = if self.segment_base_paths.is_empty() {
+            self.output_paths
+                .iter()
+                .map(|path| vec![path.clone()])
+                .collect()
+        } else {
+            self.segment_base_paths
+                .iter()
+                .zip(&self.sample_writers)
+                .map(|(base_path, sample_writer)| {
+                    (0..sample_writer.segment_count())
+                        .map(|index| get_segment_output_path(base_path, index))
+                        .collect()
+                })
+                .collect()
+        };
+        let elapsed = self.elapsed();
+        for paths in &recordings {
+            let duration: Result<Duration> = paths
+                .iter()
+                .map(|path| read_media_info(path).map(|info| info.shortest_duration()))
+                .sum();
+            match duration {
+                Ok(duration) if is_truncated(duration, elapsed) => warn!(
+                    "\"{}\" is only {:.1}s long, but {:.1}s were recorded! The file may be truncated.",
+                    paths[0],
+                    duration.as_secs_f64(),
+                    elapsed.as_secs_f64()
+                ),
+                Ok(_) => {}
+                Err(error) => warn!(
+                    "Error reading back \"{}\": {:?} - {}",
+                    paths[0],
+                    error.code(),
+                    error.message()
+                ),
+            }
+        }
+    }
+
+    fn save_chapters(&self) -> Result<()> {
+        let mut chapters = self.chapters.lock().unwrap();
+        if chapters.is_empty() {
+            return Ok(());
+        }
+        chapters.set_duration(self.elapsed());
+        for chapter_path in &self.chapter_paths {
+            std::fs::write(chapter_path, chapters.to_string()).map_err(to_error)?;
+        }
+        Ok(())
+    }
+
+    fn raise_event(&self, event: RecordingEvent) {
+        if let Some(callback) = &self.event_callback {
+            callback(event);
+        }
+    }
+}
+
+pub fn configuration_error(message: &str) -> Error {
+    Error::new(E_INVALIDARG, message.into())
+}
+
+pub fn get_display_handle(display_index: usize) -> Result<HMONITOR> {
+    get_display_handle_from_index(display_index)
+        .ok_or_else(|| configuration_error("The provided display index was out of bounds!"))
+}
+
+// Returns the encoders to try, in order
+fn get_encoder_devices(
+    codec: VideoCodec,
+    encoder_index: Option<usize>,
+    adapter: Option<&GraphicsAdapter>,
+    allow_software_encoder: bool,
+    verbose: bool,
+) -> Result<Vec<VideoEncoderDevice>> {
+    // The hardware encoder has to be on the same adapter as the textures it encodes
+    let mut encoder_devices = if let Some(adapter) = adapter {
+        VideoEncoderDevice::enumerate_for_adapter(codec, adapter)?
+    } else {
+        VideoEncoderDevice::enumerate(codec)?
+    };
+    let software_encoder_devices = VideoEncoderDevice::enumerate_software(codec)?;
+    if encoder_devices.is_empty() && !allow_software_encoder {
+        let mut message = if let Some(adapter) = adapter {
+            format!(
+                "No hardware {} encoders found on {}! Try a different --adapter or --codec.",
+                codec.display_name(),
+                adapter.display_name()
+            )
+        } else {
+            get_no_encoders_message(codec)?
+        };
+        if !software_encoder_devices.is_empty() {
+            message.push_str(" Use --allow-software-encoder to encode on the CPU instead.");
+        }
+        return Err(configuration_error(&message));
+    }
+    if allow_software_encoder {
+        encoder_devices.extend(software_encoder_devices);
+    }
+    if encoder_devices.is_empty() {
+        return Err(configuration_error(&format!(
+            "No hardware or software {} encoders found! Try a different --codec.",
+            codec.display_name()
+        )));
+    }
+    if verbose {
+        println!("Encoders ({}):", encoder_devices.len());
+        for encoder_device in &encoder_devices {
+            println!("  {}", encoder_device.display_name());
+        }
+    }
+    let encoder_devices = if let Some(encoder_index) = encoder_index {
+        if let Some(encoder_device) = encoder_devices.into_iter().nth(encoder_index) {
+            vec![encoder_device]
+        } else {
+            return Err(configuration_error("Encoder index is out of bounds!"));
+        }
+    } else {
+        // Without an adapter, the frames are on the default adapter
+        let preferred = if adapter.is_none() {
+            if let Some(default_adapter) = GraphicsAdapter::enumerate()?.first() {
+                VideoEncoderDevice::enumerate_for_adapter(codec, default_adapter)?
+                    .iter()
+                    .map(|encoder_device| encoder_device.display_name().to_owned())
+                    .collect()
+            } else {
+                Vec::new()
+            }
+        } else {
+            Vec::new()
+        };
+        let mut encoder_devices = encoder_devices;
+        encoder_devices.sort_by_key(|encoder_device| {
+            let name = encoder_device.display_name();
+            (
+                !encoder_device.is_hardware(),
+                adapter.is_none() && !preferred.iter().any(|preferred| preferred == name),
+                get_encoder_rank(name),
+            )
+        });
+        encoder_devices
+    };
+    if verbose {
+        println!("Using: {}", encoder_devices[0].display_name());
+    }
+    Ok(encoder_devices)
+}
+
+fn create_file(output_path: &str) -> Result<StorageFile> {
+    let path = unsafe {
+        let mut new_path = vec![0u16; MAX_PATH as usize];
+        let length = GetFullPathNameW(&HSTRING::from(output_path), Some(&mut new_path), None);
+        new_path.resize(length as usize, 0);
+        String::from_utf16(&new_path).unwrap()
+    };
+    let path = Path::new(&path);
+    let parent_folder_path = path.parent().unwrap();
+    // Templates (e.g. captures/{date}/recording.mp4) may name new folders
+    std::fs::create_dir_all(parent_folder_path).map_err(to_error)?;
+    let parent_folder = StorageFolder::GetFolderFromPathAsync(&HSTRING::from(
+        parent_folder_path.as_os_str().to_str().unwrap(),
+    ))?
+    .get()?;
+    let file_name = path.file_name().unwrap();
+    parent_folder
+        .CreateFileAsync(
+            &HSTRING::from(file_name.to_str().unwrap()),
+            CreationCollisionOption::ReplaceExisting,
+        )?
+        .get()
+}
+
+// For writers that don't need a Windows stream
+fn create_std_file(output_path: &str) -> Result<std::fs::File> {
+    if let Some(parent_folder_path) = Path::new(output_path).parent() {
+        std::fs::create_dir_all(parent_folder_path).map_err(to_error)?;
+    }
+    std::fs::File::create(output_path).map_err(to_error)
+}
+
+fn open_stream(output_path: &str) -> Result<IRandomAccessStream> {
+    let file = create_file(output_path)?;
+    file.OpenAsync(FileAccessMode::ReadWrite)?.get()
+}
+
+fn get_output_path_for_display(output_path: &str, display_index: usize) -> String {
+    append_to_file_stem(output_path, &display_index.to_string())
+}
+
+// Segments are numbered from 1 so that the files sort in order
+fn get_segment_output_path(output_path: &str, segment_index: usize) -> String {
+    append_to_file_stem(output_path, &format!("{:03}", segment_index + 1))
+}
+
+// Matches the fastest of the recorded displays, so that every frame they
+// show is recorded
+fn get_default_frame_rate(items: &[CanvasItem]) -> u32 {
+    items
+        .iter()
+        .filter_map(|item| item.display.as_deref())
+        .filter_map(find_display_by_device_name)
+        .filter_map(get_display_refresh_rate)
+        .max()
+        .unwrap_or(DEFAULT_FRAME_RATE)
+}
+
+// The first frame takes a moment to arrive, and the last frame may be the
+// one dropped when stopping, neither of which is a truncated file
+fn is_truncated(duration: Duration, elapsed: Duration) -> bool {
+    let tolerance = elapsed
+        .mul_f64(TRUNCATION_TOLERANCE)
+        .max(MIN_TRUNCATION_TOLERANCE);
+    elapsed.saturating_sub(duration) > tolerance
+}
+
+// Segmented recordings share a single thumbnail, named after the base path
+fn get_thumbnail_path(output_path: &str) -> String {
+    Path::new(output_path)
+        .with_extension("jpg")
+        .to_str()
+        .unwrap()
+        .to_owned()
+}
+
+// Named after the recording, so that recordings to another folder don't share it
+fn get_scratch_path(scratch_dir: Option<&str>, output_path: &str) -> String {
+    let scratch_dir = match scratch_dir {
+        Some(scratch_dir) => Path::new(scratch_dir).to_owned(),
+        None => std::env::temp_dir(),
+    };
+    let file_name = Path::new(output_path)
+        .file_name()
+        .and_then(|file_name| file_name.to_str())
+        .unwrap_or("recording");
+    scratch_dir
+        .join(format!("{}.frames", file_name))
+        .to_str()
+        .unwrap()
+        .to_owned()
+}
+
+fn get_chapters_path(output_path: &str) -> String {
+    Path::new(output_path)
+        .with_extension("chapters.vtt")
+        .to_str()
+        .unwrap()
+        .to_owned()
+}
+
+fn get_ndi_name(name: &str, target_index: usize, target_count: usize) -> String {
+    if target_count > 1 {
+        format!("{} ({})", name, target_index + 1)
+    } else {
+        name.to_owned()
+    }
+}
+
+fn append_to_file_stem(output_path: &str, suffix: &str) -> String {
+    let path = Path::new(output_path);
+    let stem = path.file_stem().unwrap().to_str().unwrap();
+    let file_name = if let Some(extension) = path.extension() {
+        format!("{}_{}.{}", stem, suffix, extension.to_str().unwrap())
+    } else {
+        format!("{}_{}", stem, suffix)
+    };
+    path.with_file_name(file_name).to_str().unwrap().to_owned()
+}
+
+fn win32_programmatic_capture_supported() -> Result<bool> {
+    ApiInformation::IsApiContractPresentByMajor(
+        &HSTRING::from("Windows.Foundation.UniversalApiContract"),
+        8,
+    )
+}
+
+pub fn required_capture_features_supported() -> Result<bool> {
+    let result = ApiInformation::IsTypePresent(&HSTRING::from(GraphicsCaptureSession::NAME))? && // Windows.Graphics.Capture is present
+    GraphicsCaptureSession::IsSupported()? && // The CaptureService is available
+    win32_programmatic_capture_supported()?;
+    Ok(result)
+}
+
+pub fn cursor_capture_toggle_supported() -> Result<bool> {
+    ApiInformation::IsPropertyPresent(
+        &HSTRING::from(GraphicsCaptureSession::NAME),
+        &HSTRING::from("IsCursorCaptureEnabled"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{
+        get_chapters_path, get_ndi_name, get_output_path_for_display, get_scratch_path,
+        get_segment_output_path, get_thumbnail_path, is_truncated,
+    };
+
+    #[test]
+    fn display_output_path_test() {
+        assert_eq!(
+            get_output_path_for_display("recording.mp4", 1),
+            "recording_1.mp4"
+        );
+        assert_eq!(
+            get_output_path_for_display("somedir/something.mp4", 0),
+            "somedir/something_0.mp4"
+        );
+    }
+
+    #[test]
+    fn truncation_test() {
+        let seconds = Duration::from_secs_f64;
+        assert!(!is_truncated(seconds(9.5), seconds(10.0)));
+        assert!(!is_truncated(seconds(12.0), seconds(10.0)));
+        assert!(is_truncated(seconds(8.0), seconds(10.0)));
+        // Longer recordings can be off by more
+        assert!(!is_truncated(seconds(3550.0), seconds(3600.0)));
+        assert!(is_truncated(seconds(3000.0), seconds(3600.0)));
+    }
+
+    #[test]
+    fn segment_output_path_test() {
+        assert_eq!(
+            get_segment_output_path("recording.mp4", 0),
+            "recording_001.mp4"
+        );
+        assert_eq!(
+            get_segment_output_path("recording_1.mkv", 11),
+            "recording_1_012.mkv"
+        );
+    }
+
+    #[test]
+    fn thumbnail_path_test() {
+        assert_eq!(get_thumbnail_path("recording.mp4"), "recording.jpg");
+        assert_eq!(
+            get_thumbnail_path("somedir/recording_1.mkv"),
+            "somedir/recording_1.jpg"
+        );
+    }
+
+    #[test]
+    fn chapters_path_test() {
+        assert_eq!(
+            get_chapters_path("somedir/recording.mp4"),
+            "somedir/recording.chapters.vtt"
+        );
+    }
+
+    #[test]
+    fn scratch_path_test() {
+        assert_eq!(
+            get_scratch_path(Some("D:/scratch"), "somedir/recording.mp4"),
+            "D:/scratch/recording.mp4.frames"
+        );
+        assert!(get_scratch_path(None, "recording.mkv").ends_with("recording.mkv.frames"));
+    }
+
+    #[test]
+    fn ndi_name_test() {
+        assert_eq!(get_ndi_name("Desktop", 0, 1), "Desktop");
+        assert_eq!(get_ndi_name("Desktop", 1, 2), "Desktop (2)");
+    }
+}
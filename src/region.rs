@@ -0,0 +1,114 @@
+use std::{fmt::Display, str::FromStr};
+
+use windows::Graphics::{RectInt32, SizeInt32};
+
+/// A rectangle of the capture item to record, in pixels.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Region {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseRegionError(&'static str);
+
+impl FromStr for Region {
+    type Err = ParseRegionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const ERROR: ParseRegionError = ParseRegionError(
+            "Invalid region value! Expecting: x,y,width,height (e.g. 0,0,1280,720).",
+        );
+        let values: Vec<i32> = s
+            .split(',')
+            .map(|value| value.trim().parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| ERROR)?;
+        match values.as_slice() {
+            &[x, y, width, height] if x >= 0 && y >= 0 && width > 0 && height > 0 => Ok(Region {
+                x,
+                y,
+                width,
+                height,
+            }),
+            _ => Err(ERROR),
+        }
+    }
+}
+
+impl Display for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{},{},{},{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+impl Display for ParseRegionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseRegionError {}
+
+impl Region {
+    /// Whether the region lies entirely within something of the given size.
+    pub fn fits_in(&self, size: SizeInt32) -> bool {
+        self.x + self.width <= size.Width && self.y + self.height <= size.Height
+    }
+
+    pub fn size(&self) -> SizeInt32 {
+        SizeInt32 {
+            Width: self.width,
+            Height: self.height,
+        }
+    }
+
+    pub fn to_rect(self) -> RectInt32 {
+        RectInt32 {
+            X: self.x,
+            Y: self.y,
+            Width: self.width,
+            Height: self.height,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Graphics::SizeInt32;
+
+    use super::Region;
+
+    #[test]
+    fn region_parsing_test() {
+        assert_eq!(
+            "10,20,1280,720".parse::<Region>(),
+            Ok(Region {
+                x: 10,
+                y: 20,
+                width: 1280,
+                height: 720
+            })
+        );
+        assert!(" 0, 0, 100, 100 ".parse::<Region>().is_ok());
+
+        assert!("0,0,1280".parse::<Region>().is_err());
+        assert!("0,0,1280,720,1".parse::<Region>().is_err());
+        assert!("-1,0,1280,720".parse::<Region>().is_err());
+        assert!("0,0,0,720".parse::<Region>().is_err());
+        assert!("a,b,c,d".parse::<Region>().is_err());
+    }
+
+    #[test]
+    fn region_bounds_test() {
+        let size = SizeInt32 {
+            Width: 1920,
+            Height: 1080,
+        };
+        assert!("0,0,1920,1080".parse::<Region>().unwrap().fits_in(size));
+        assert!("100,100,800,600".parse::<Region>().unwrap().fits_in(size));
+        assert!(!"1000,0,1000,1080".parse::<Region>().unwrap().fits_in(size));
+        assert!(!"0,500,1920,600".parse::<Region>().unwrap().fits_in(size));
+    }
+}
@@ -0,0 +1,160 @@
+use std::{collections::VecDeque, time::Duration};
+
+use windows::{
+    core::Result,
+    Win32::Media::MediaFoundation::{IMFSample, MFSampleExtension_CleanPoint},
+};
+
+/// Keeps the most recent samples written to each stream in memory so that
+/// only the last part of a recording ends up in the file.
+pub struct ReplayBuffer {
+    // The length of the window to keep (in 100ns units)
+    window: i64,
+    samples: VecDeque<BufferedSample>,
+    latest_time: i64,
+}
+
+struct BufferedSample {
+    stream_index: u32,
+    time: i64,
+    key_frame: bool,
+    sample: IMFSample,
+}
+
+impl ReplayBuffer {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window: (window.as_nanos() / 100) as i64,
+            samples: VecDeque::new(),
+            latest_time: 0,
+        }
+    }
+
+    pub fn push(&mut self, stream_index: u32, sample: &IMFSample) -> Result<()> {
+        let time = unsafe { sample.GetSampleTime()? };
+        // Only compressed video samples are marked as clean points, and those
+        // are the only places we can safely start playback from.
+        let key_frame = unsafe { sample.GetUINT32(&MFSampleExtension_CleanPoint) }
+            .map(|value| value != 0)
+            .unwrap_or(false);
+        self.samples.push_back(BufferedSample {
+            stream_index,
+            time,
+            key_frame,
+            sample: sample.clone(),
+        });
+        self.latest_time = self.latest_time.max(time);
+        self.trim();
+        Ok(())
+    }
+
+    /// Removes the buffered samples, rebased so that the first sample starts at 0.
+    pub fn drain(&mut self) -> Result<Vec<(u32, IMFSample)>> {
+        let samples = self.take();
+        if let Some((_, first_sample)) = samples.first() {
+            let start_time = unsafe { first_sample.GetSampleTime()? };
+            for (_, sample) in &samples {
+                unsafe { sample.SetSampleTime(sample.GetSampleTime()? - start_time)? };
+            }
+        }
+        Ok(samples)
+    }
+
+    /// Removes the buffered samples from the first key frame on, keeping
+    /// their times.
+    pub fn take(&mut self) -> Vec<(u32, IMFSample)> {
+        let start_index = find_start_index(self.entries());
+        let start_time = self
+            .samples
+            .get(start_index)
+            .map(|sample| sample.time)
+            .unwrap_or_default();
+
+        let mut result = Vec::new();
+        for sample in self.samples.drain(..).skip(start_index) {
+            // Drop anything (e.g. audio) from before the first key frame
+            if sample.time >= start_time {
+                result.push((sample.stream_index, sample.sample));
+            }
+        }
+        result
+    }
+
+    fn trim(&mut self) {
+        let cutoff = self.latest_time - self.window;
+        let trim_index = find_trim_index(self.entries(), cutoff);
+        self.samples.drain(..trim_index);
+    }
+
+    fn entries(&self) -> impl Entries + '_ {
+        self.samples
+            .iter()
+            .map(|sample| (sample.time, sample.key_frame))
+    }
+}
+
+/// (time, key_frame) pairs in the order they were written.
+trait Entries: DoubleEndedIterator<Item = (i64, bool)> + ExactSizeIterator + Clone {}
+impl<T: DoubleEndedIterator<Item = (i64, bool)> + ExactSizeIterator + Clone> Entries for T {}
+
+/// Finds how many entries can be removed while still keeping everything
+/// after the cutoff playable.
+fn find_trim_index(mut entries: impl Entries, cutoff: i64) -> usize {
+    if entries.clone().any(|(_, key_frame)| key_frame) {
+        // Keep the last key frame at or before the cutoff so that the
+        // frames that follow it can still be decoded.
+        entries
+            .rposition(|(time, key_frame)| key_frame && time <= cutoff)
+            .unwrap_or(0)
+    } else {
+        let length = entries.len();
+        entries
+            .position(|(time, _)| time >= cutoff)
+            .unwrap_or(length)
+    }
+}
+
+/// Finds the entry that playback should start from.
+fn find_start_index(mut entries: impl Entries) -> usize {
+    entries.position(|(_, key_frame)| key_frame).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_start_index, find_trim_index};
+
+    #[test]
+    fn trim_with_key_frames_test() {
+        let entries = [
+            (0, true),
+            (10, false),
+            (20, false),
+            (30, true),
+            (40, false),
+            (50, true),
+            (60, false),
+        ];
+        assert_eq!(find_trim_index(entries.iter().copied(), -10), 0);
+        assert_eq!(find_trim_index(entries.iter().copied(), 25), 0);
+        assert_eq!(find_trim_index(entries.iter().copied(), 30), 3);
+        assert_eq!(find_trim_index(entries.iter().copied(), 45), 3);
+        assert_eq!(find_trim_index(entries.iter().copied(), 100), 5);
+    }
+
+    #[test]
+    fn trim_without_key_frames_test() {
+        let entries = [(0, false), (10, false), (20, false)];
+        assert_eq!(find_trim_index(entries.iter().copied(), 0), 0);
+        assert_eq!(find_trim_index(entries.iter().copied(), 15), 2);
+        assert_eq!(find_trim_index(entries.iter().copied(), 100), 3);
+    }
+
+    #[test]
+    fn start_index_test() {
+        let entries = [(5, false), (0, true), (10, false)];
+        assert_eq!(find_start_index(entries.iter().copied()), 1);
+        let entries = [(5, false), (10, false)];
+        assert_eq!(find_start_index(entries.iter().copied()), 0);
+        assert_eq!(find_start_index([].iter().copied()), 0);
+    }
+}
@@ -0,0 +1,204 @@
+// RTMP commands are encoded with AMF0, see the "Action Message Format -- AMF 0"
+// specification. Only the types that show up in RTMP commands are supported.
+
+const NUMBER_MARKER: u8 = 0x00;
+const BOOLEAN_MARKER: u8 = 0x01;
+const STRING_MARKER: u8 = 0x02;
+const OBJECT_MARKER: u8 = 0x03;
+const NULL_MARKER: u8 = 0x05;
+const UNDEFINED_MARKER: u8 = 0x06;
+const ECMA_ARRAY_MARKER: u8 = 0x08;
+const OBJECT_END_MARKER: u8 = 0x09;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AmfValue {
+    Number(f64),
+    Boolean(bool),
+    String(String),
+    Object(Vec<(String, AmfValue)>),
+    Null,
+    Undefined,
+    EcmaArray(Vec<(String, AmfValue)>),
+}
+
+impl AmfValue {
+    pub fn string<S: Into<String>>(value: S) -> Self {
+        AmfValue::String(value.into())
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            AmfValue::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            AmfValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Looks up a property of an object or ECMA array.
+    pub fn get(&self, key: &str) -> Option<&AmfValue> {
+        match self {
+            AmfValue::Object(properties) | AmfValue::EcmaArray(properties) => properties
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self, buffer: &mut Vec<u8>) {
+        match self {
+            AmfValue::Number(value) => {
+                buffer.push(NUMBER_MARKER);
+                buffer.extend_from_slice(&value.to_be_bytes());
+            }
+            AmfValue::Boolean(value) => {
+                buffer.push(BOOLEAN_MARKER);
+                buffer.push(*value as u8);
+            }
+            AmfValue::String(value) => {
+                buffer.push(STRING_MARKER);
+                encode_string(buffer, value);
+            }
+            AmfValue::Object(properties) => {
+                buffer.push(OBJECT_MARKER);
+                encode_properties(buffer, properties);
+            }
+            AmfValue::Null => buffer.push(NULL_MARKER),
+            AmfValue::Undefined => buffer.push(UNDEFINED_MARKER),
+            AmfValue::EcmaArray(properties) => {
+                buffer.push(ECMA_ARRAY_MARKER);
+                buffer.extend_from_slice(&(properties.len() as u32).to_be_bytes());
+                encode_properties(buffer, properties);
+            }
+        }
+    }
+}
+
+pub fn encode_values(values: &[AmfValue]) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    for value in values {
+        value.encode(&mut buffer);
+    }
+    buffer
+}
+
+/// Returns None if the data is malformed or uses an unsupported type.
+pub fn decode_values(mut data: &[u8]) -> Option<Vec<AmfValue>> {
+    let mut values = Vec::new();
+    while !data.is_empty() {
+        values.push(decode_value(&mut data)?);
+    }
+    Some(values)
+}
+
+fn encode_string(buffer: &mut Vec<u8>, value: &str) {
+    buffer.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(value.as_bytes());
+}
+
+fn encode_properties(buffer: &mut Vec<u8>, properties: &[(String, AmfValue)]) {
+    for (name, value) in properties {
+        encode_string(buffer, name);
+        value.encode(buffer);
+    }
+    // An empty name followed by the end marker
+    buffer.extend_from_slice(&[0, 0, OBJECT_END_MARKER]);
+}
+
+fn take<'a>(data: &mut &'a [u8], length: usize) -> Option<&'a [u8]> {
+    if data.len() < length {
+        return None;
+    }
+    let (bytes, rest) = data.split_at(length);
+    *data = rest;
+    Some(bytes)
+}
+
+fn decode_string(data: &mut &[u8]) -> Option<String> {
+    let length = u16::from_be_bytes(take(data, 2)?.try_into().ok()?);
+    let bytes = take(data, length as usize)?;
+    Some(String::from_utf8_lossy(bytes).into_owned())
+}
+
+fn decode_properties(data: &mut &[u8]) -> Option<Vec<(String, AmfValue)>> {
+    let mut properties = Vec::new();
+    loop {
+        let name = decode_string(data)?;
+        if name.is_empty() && data.first() == Some(&OBJECT_END_MARKER) {
+            *data = &data[1..];
+            return Some(properties);
+        }
+        properties.push((name, decode_value(data)?));
+    }
+}
+
+fn decode_value(data: &mut &[u8]) -> Option<AmfValue> {
+    let marker = take(data, 1)?[0];
+    let value = match marker {
+        NUMBER_MARKER => AmfValue::Number(f64::from_be_bytes(take(data, 8)?.try_into().ok()?)),
+        BOOLEAN_MARKER => AmfValue::Boolean(take(data, 1)?[0] != 0),
+        STRING_MARKER => AmfValue::String(decode_string(data)?),
+        OBJECT_MARKER => AmfValue::Object(decode_properties(data)?),
+        NULL_MARKER => AmfValue::Null,
+        UNDEFINED_MARKER => AmfValue::Undefined,
+        ECMA_ARRAY_MARKER => {
+            // The count is only a hint, the properties are terminated like an object's
+            take(data, 4)?;
+            AmfValue::EcmaArray(decode_properties(data)?)
+        }
+        _ => return None,
+    };
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_values, encode_values, AmfValue};
+
+    #[test]
+    fn amf_encoding_test() {
+        let data = encode_values(&[
+            AmfValue::string("connect"),
+            AmfValue::Number(1.0),
+            AmfValue::Object(vec![("app".to_owned(), AmfValue::string("live"))]),
+        ]);
+        assert_eq!(
+            data,
+            vec![
+                0x02, 0, 7, b'c', b'o', b'n', b'n', b'e', b'c', b't', // "connect"
+                0x00, 0x3F, 0xF0, 0, 0, 0, 0, 0, 0, // 1.0
+                0x03, 0, 3, b'a', b'p', b'p', 0x02, 0, 4, b'l', b'i', b'v', b'e', 0, 0, 0x09,
+            ]
+        );
+    }
+
+    #[test]
+    fn amf_decoding_test() {
+        let values = vec![
+            AmfValue::string("_result"),
+            AmfValue::Number(4.0),
+            AmfValue::Null,
+            AmfValue::EcmaArray(vec![
+                (
+                    "code".to_owned(),
+                    AmfValue::string("NetStream.Publish.Start"),
+                ),
+                ("live".to_owned(), AmfValue::Boolean(true)),
+            ]),
+        ];
+        let decoded = decode_values(&encode_values(&values)).unwrap();
+        assert_eq!(decoded, values);
+        assert_eq!(
+            decoded[3].get("code").and_then(|code| code.as_str()),
+            Some("NetStream.Publish.Start")
+        );
+        assert_eq!(decode_values(&[0x02, 0, 5, b'a']), None);
+        assert_eq!(decode_values(&[0x0B]), None);
+    }
+}
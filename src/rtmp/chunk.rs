@@ -0,0 +1,257 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+};
+
+// See the "RTMP Message Formats" and "Chunking" sections of the RTMP specification
+
+pub const MESSAGE_SET_CHUNK_SIZE: u8 = 1;
+pub const MESSAGE_USER_CONTROL: u8 = 4;
+pub const MESSAGE_AUDIO: u8 = 8;
+pub const MESSAGE_VIDEO: u8 = 9;
+pub const MESSAGE_DATA: u8 = 18;
+pub const MESSAGE_COMMAND: u8 = 20;
+
+const DEFAULT_CHUNK_SIZE: usize = 128;
+const EXTENDED_TIMESTAMP: u32 = 0xFFFFFF;
+const MAX_CHUNK_SIZE: u32 = 0x7FFFFFFF;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub type_id: u8,
+    pub stream_id: u32,
+    /// In milliseconds
+    pub timestamp: u32,
+    pub payload: Vec<u8>,
+}
+
+/// Splits messages into chunks. Each message starts with a full (type 0)
+/// header, which is always valid and saves us from tracking what was sent.
+pub struct ChunkWriter {
+    chunk_size: usize,
+}
+
+/// Reassembles the messages sent by the server.
+pub struct ChunkReader {
+    chunk_size: usize,
+    chunk_streams: HashMap<u32, ChunkStream>,
+}
+
+#[derive(Default)]
+struct ChunkStream {
+    type_id: u8,
+    stream_id: u32,
+    timestamp: u32,
+    timestamp_delta: u32,
+    length: usize,
+    has_extended_timestamp: bool,
+    payload: Vec<u8>,
+}
+
+impl ChunkWriter {
+    pub fn new() -> Self {
+        Self {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    /// Only takes effect once the server has been told, see MESSAGE_SET_CHUNK_SIZE.
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.chunk_size = chunk_size;
+    }
+
+    pub fn write(&self, buffer: &mut Vec<u8>, chunk_stream_id: u8, message: &Message) {
+        // ID 2 is for protocol control messages, higher ones need a longer basic header
+        assert!((2..64).contains(&chunk_stream_id));
+        let has_extended_timestamp = message.timestamp >= EXTENDED_TIMESTAMP;
+        buffer.push(chunk_stream_id);
+        buffer.extend_from_slice(&message.timestamp.min(EXTENDED_TIMESTAMP).to_be_bytes()[1..]);
+        buffer.extend_from_slice(&(message.payload.len() as u32).to_be_bytes()[1..]);
+        buffer.push(message.type_id);
+        buffer.extend_from_slice(&message.stream_id.to_le_bytes());
+        if has_extended_timestamp {
+            buffer.extend_from_slice(&message.timestamp.to_be_bytes());
+        }
+        for (i, chunk) in message.payload.chunks(self.chunk_size).enumerate() {
+            // The rest of the message follows in type 3 chunks
+            if i > 0 {
+                buffer.push(0xC0 | chunk_stream_id);
+                if has_extended_timestamp {
+                    buffer.extend_from_slice(&message.timestamp.to_be_bytes());
+                }
+            }
+            buffer.extend_from_slice(chunk);
+        }
+    }
+}
+
+impl ChunkReader {
+    pub fn new() -> Self {
+        Self {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            chunk_streams: HashMap::new(),
+        }
+    }
+
+    /// Blocks until a whole message has arrived. Chunk size changes requested
+    /// by the server are applied before the message is returned.
+    pub fn read<R: Read>(&mut self, reader: &mut R) -> io::Result<Message> {
+        loop {
+            let [first] = read_bytes::<R, 1>(reader)?;
+            let format = first >> 6;
+            let chunk_stream_id = match first & 0x3F {
+                0 => 64 + read_bytes::<R, 1>(reader)?[0] as u32,
+                1 => {
+                    let bytes = read_bytes::<R, 2>(reader)?;
+                    64 + bytes[0] as u32 + ((bytes[1] as u32) << 8)
+                }
+                id => id as u32,
+            };
+
+            let chunk_size = self.chunk_size;
+            let chunk_stream = self.chunk_streams.entry(chunk_stream_id).or_default();
+            let starts_message = chunk_stream.payload.is_empty();
+            if format < 3 {
+                let timestamp = read_u24(reader)?;
+                if format < 2 {
+                    chunk_stream.length = read_u24(reader)? as usize;
+                    chunk_stream.type_id = read_bytes::<R, 1>(reader)?[0];
+                }
+                if format == 0 {
+                    chunk_stream.stream_id = u32::from_le_bytes(read_bytes(reader)?);
+                }
+                chunk_stream.has_extended_timestamp = timestamp == EXTENDED_TIMESTAMP;
+                let timestamp = if chunk_stream.has_extended_timestamp {
+                    u32::from_be_bytes(read_bytes(reader)?)
+                } else {
+                    timestamp
+                };
+                // Type 0 chunks have an absolute timestamp, the others a delta
+                if format == 0 {
+                    chunk_stream.timestamp = timestamp;
+                    chunk_stream.timestamp_delta = 0;
+                } else {
+                    chunk_stream.timestamp_delta = timestamp;
+                    chunk_stream.timestamp = chunk_stream.timestamp.wrapping_add(timestamp);
+                }
+            } else {
+                if chunk_stream.has_extended_timestamp {
+                    read_bytes::<R, 4>(reader)?;
+                }
+                // A type 3 chunk that starts a message repeats the previous delta
+                if starts_message {
+                    chunk_stream.timestamp = chunk_stream
+                        .timestamp
+                        .wrapping_add(chunk_stream.timestamp_delta);
+                }
+            }
+
+            let remaining = chunk_stream.length - chunk_stream.payload.len();
+            let start = chunk_stream.payload.len();
+            chunk_stream
+                .payload
+                .resize(start + remaining.min(chunk_size), 0);
+            reader.read_exact(&mut chunk_stream.payload[start..])?;
+            if chunk_stream.payload.len() < chunk_stream.length {
+                continue;
+            }
+
+            let message = Message {
+                type_id: chunk_stream.type_id,
+                stream_id: chunk_stream.stream_id,
+                timestamp: chunk_stream.timestamp,
+                payload: std::mem::take(&mut chunk_stream.payload),
+            };
+            if message.type_id == MESSAGE_SET_CHUNK_SIZE && message.payload.len() >= 4 {
+                let chunk_size = u32::from_be_bytes(message.payload[..4].try_into().unwrap());
+                self.chunk_size = (chunk_size & MAX_CHUNK_SIZE).max(1) as usize;
+            }
+            return Ok(message);
+        }
+    }
+}
+
+fn read_bytes<R: Read, const N: usize>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u24<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let bytes = read_bytes::<R, 3>(reader)?;
+    Ok(u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChunkReader, ChunkWriter, Message, MESSAGE_COMMAND, MESSAGE_SET_CHUNK_SIZE};
+
+    #[test]
+    fn chunk_round_trip_test() {
+        let mut writer = ChunkWriter::new();
+        writer.set_chunk_size(4);
+        let messages = vec![
+            Message {
+                type_id: MESSAGE_COMMAND,
+                stream_id: 1,
+                timestamp: 0x1000000,
+                payload: (0..10).collect(),
+            },
+            Message {
+                type_id: MESSAGE_SET_CHUNK_SIZE,
+                stream_id: 0,
+                timestamp: 3,
+                payload: vec![0, 0, 0, 4],
+            },
+            Message {
+                type_id: MESSAGE_COMMAND,
+                stream_id: 1,
+                timestamp: 5,
+                payload: vec![],
+            },
+        ];
+        let mut data = Vec::new();
+        for message in &messages {
+            writer.write(&mut data, 3, message);
+        }
+        // The first message takes three chunks, each repeating the extended timestamp
+        assert_eq!(
+            data.len(),
+            (12 + 4 + 4) + (1 + 4 + 4) + (1 + 4 + 2) + (12 + 4) + 12
+        );
+
+        let mut reader = ChunkReader::new();
+        let mut data = data.as_slice();
+        // The server has to announce smaller chunks before using them
+        let mut announce = Vec::new();
+        ChunkWriter::new().write(&mut announce, 3, &messages[1]);
+        assert_eq!(reader.read(&mut announce.as_slice()).unwrap(), messages[1]);
+        for message in &messages {
+            assert_eq!(&reader.read(&mut data).unwrap(), message);
+        }
+        assert!(reader.read(&mut data).is_err());
+    }
+
+    #[test]
+    fn compressed_header_test() {
+        // A type 0 chunk followed by a type 2 chunk (timestamp delta only)
+        // and a type 3 chunk that repeats the delta
+        let data = [
+            0x03, 0, 0, 10, 0, 0, 1, 20, 0, 0, 0, 0, 0xAA, // Type 0
+            0x83, 0, 0, 5, 0xBB, // Type 2
+            0xC3, 0xCC, // Type 3
+        ];
+        let mut reader = ChunkReader::new();
+        let mut data = &data[..];
+        let timestamps: Vec<_> = (0..3)
+            .map(|_| {
+                let message = reader.read(&mut data).unwrap();
+                (message.timestamp, message.payload)
+            })
+            .collect();
+        assert_eq!(
+            timestamps,
+            vec![(10, vec![0xAA]), (15, vec![0xBB]), (20, vec![0xCC])]
+        );
+    }
+}
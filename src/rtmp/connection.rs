@@ -0,0 +1,342 @@
+use std::{
+    io::{self, Write},
+    net::{Shutdown, TcpStream, ToSocketAddrs},
+    sync::mpsc::{channel, Receiver},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    amf::{decode_values, encode_values, AmfValue},
+    chunk::{
+        ChunkReader, ChunkWriter, Message, MESSAGE_AUDIO, MESSAGE_COMMAND, MESSAGE_DATA,
+        MESSAGE_SET_CHUNK_SIZE, MESSAGE_USER_CONTROL, MESSAGE_VIDEO,
+    },
+    url::RtmpUrl,
+};
+
+const RTMP_VERSION: u8 = 3;
+const HANDSHAKE_SIZE: usize = 1536;
+// Larger chunks mean less overhead for video frames
+const CHUNK_SIZE: usize = 4096;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// Writes that take longer than this mean the connection is gone
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const CONTROL_CHUNK_STREAM: u8 = 2;
+const COMMAND_CHUNK_STREAM: u8 = 3;
+const AUDIO_CHUNK_STREAM: u8 = 4;
+const VIDEO_CHUNK_STREAM: u8 = 6;
+const DATA_CHUNK_STREAM: u8 = 5;
+
+const PING_REQUEST: u16 = 6;
+const PING_RESPONSE: u16 = 7;
+
+const CONNECT_TRANSACTION: f64 = 1.0;
+const CREATE_STREAM_TRANSACTION: f64 = 4.0;
+
+/// The kinds of FLV tag bodies that can be published.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TagKind {
+    Audio,
+    Video,
+    Data,
+}
+
+/// A connection to an RTMP server that is publishing a stream, see connect.
+pub struct RtmpConnection {
+    socket: TcpStream,
+    writer: ChunkWriter,
+    stream_id: u32,
+    stream_key: String,
+    // Replies to pings from the server, which are read on another thread
+    control_messages: Option<Receiver<Message>>,
+}
+
+impl RtmpConnection {
+    /// Connects to the server and starts publishing to the stream key.
+    pub fn connect(url: &RtmpUrl) -> io::Result<Self> {
+        let address = (url.host.as_str(), url.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::other("Could not resolve the host of the stream URL!"))?;
+        let socket = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+        socket.set_nodelay(true)?;
+        socket.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+        socket.set_write_timeout(Some(WRITE_TIMEOUT))?;
+
+        let mut connection = Self {
+            socket,
+            writer: ChunkWriter::new(),
+            stream_id: 0,
+            stream_key: url.stream_key.clone(),
+            control_messages: None,
+        };
+        let mut reader = ChunkReader::new();
+        connection.handshake()?;
+        connection.send_message(
+            CONTROL_CHUNK_STREAM,
+            MESSAGE_SET_CHUNK_SIZE,
+            0,
+            0,
+            (CHUNK_SIZE as u32).to_be_bytes().to_vec(),
+        )?;
+        connection.writer.set_chunk_size(CHUNK_SIZE);
+
+        connection.send_command(
+            "connect",
+            CONNECT_TRANSACTION,
+            vec![AmfValue::Object(vec![
+                ("app".to_owned(), AmfValue::string(&url.app)),
+                ("type".to_owned(), AmfValue::string("nonprivate")),
+                ("flashVer".to_owned(), AmfValue::string("FMLE/3.0")),
+                ("tcUrl".to_owned(), AmfValue::string(url.tc_url())),
+            ])],
+        )?;
+        connection.wait_for_result(&mut reader, CONNECT_TRANSACTION)?;
+
+        // Some services expect these before the stream is created
+        let stream_key = AmfValue::string(&url.stream_key);
+        connection.send_command(
+            "releaseStream",
+            2.0,
+            vec![AmfValue::Null, stream_key.clone()],
+        )?;
+        connection.send_command("FCPublish", 3.0, vec![AmfValue::Null, stream_key.clone()])?;
+        connection.send_command(
+            "createStream",
+            CREATE_STREAM_TRANSACTION,
+            vec![AmfValue::Null],
+        )?;
+        let result = connection.wait_for_result(&mut reader, CREATE_STREAM_TRANSACTION)?;
+        connection.stream_id = result
+            .get(3)
+            .and_then(|stream_id| stream_id.as_number())
+            .ok_or_else(|| io::Error::other("The RTMP server didn't create a stream!"))?
+            as u32;
+
+        connection.send_message(
+            COMMAND_CHUNK_STREAM,
+            MESSAGE_COMMAND,
+            connection.stream_id,
+            0,
+            encode_values(&[
+                AmfValue::string("publish"),
+                AmfValue::Number(0.0),
+                AmfValue::Null,
+                stream_key,
+                AmfValue::string("live"),
+            ]),
+        )?;
+        connection.wait_for_publish(&mut reader)?;
+
+        // From now on we only write, apart from answering pings
+        connection.socket.set_read_timeout(None)?;
+        connection.start_reading(reader)?;
+        Ok(connection)
+    }
+
+    /// Sends the body of an FLV tag, the timestamp is in milliseconds.
+    pub fn send(&mut self, kind: TagKind, timestamp: u32, data: &[u8]) -> io::Result<()> {
+        self.answer_pings()?;
+        let (chunk_stream_id, type_id) = match kind {
+            TagKind::Audio => (AUDIO_CHUNK_STREAM, MESSAGE_AUDIO),
+            TagKind::Video => (VIDEO_CHUNK_STREAM, MESSAGE_VIDEO),
+            TagKind::Data => (DATA_CHUNK_STREAM, MESSAGE_DATA),
+        };
+        self.send_message(
+            chunk_stream_id,
+            type_id,
+            self.stream_id,
+            timestamp,
+            data.to_vec(),
+        )
+    }
+
+    /// Tells the server that the stream has ended and disconnects.
+    pub fn close(mut self) -> io::Result<()> {
+        let stream_key = AmfValue::string(&self.stream_key);
+        self.send_command("FCUnpublish", 5.0, vec![AmfValue::Null, stream_key])?;
+        self.send_command(
+            "deleteStream",
+            6.0,
+            vec![AmfValue::Null, AmfValue::Number(self.stream_id as f64)],
+        )?;
+        self.socket.flush()?;
+        self.socket.shutdown(Shutdown::Both)
+    }
+
+    fn handshake(&mut self) -> io::Result<()> {
+        // C0 and C1, the random data can be anything
+        let mut handshake = vec![RTMP_VERSION];
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_millis() as u32)
+            .unwrap_or_default();
+        handshake.extend_from_slice(&time.to_be_bytes());
+        handshake.extend_from_slice(&[0; 4]);
+        handshake.extend((0..HANDSHAKE_SIZE - 8).map(|i| (i * 7 + 13) as u8));
+        self.socket.write_all(&handshake)?;
+
+        // S0 and S1, then echo S1 back as C2
+        let mut response = vec![0; 1 + HANDSHAKE_SIZE];
+        std::io::Read::read_exact(&mut self.socket, &mut response)?;
+        if response[0] != RTMP_VERSION {
+            return Err(io::Error::other(
+                "The RTMP server doesn't support the requested version!",
+            ));
+        }
+        self.socket.write_all(&response[1..])?;
+        // S2, which echoes C1
+        let mut echo = vec![0; HANDSHAKE_SIZE];
+        std::io::Read::read_exact(&mut self.socket, &mut echo)
+    }
+
+    fn send_message(
+        &mut self,
+        chunk_stream_id: u8,
+        type_id: u8,
+        stream_id: u32,
+        timestamp: u32,
+        payload: Vec<u8>,
+    ) -> io::Result<()> {
+        let mut buffer = Vec::new();
+        self.writer.write(
+            &mut buffer,
+            chunk_stream_id,
+            &Message {
+                type_id,
+                stream_id,
+                timestamp,
+                payload,
+            },
+        );
+        self.socket.write_all(&buffer)
+    }
+
+    fn send_command(
+        &mut self,
+        name: &str,
+        transaction_id: f64,
+        arguments: Vec<AmfValue>,
+    ) -> io::Result<()> {
+        let mut values = vec![AmfValue::string(name), AmfValue::Number(transaction_id)];
+        values.extend(arguments);
+        self.send_message(
+            COMMAND_CHUNK_STREAM,
+            MESSAGE_COMMAND,
+            0,
+            0,
+            encode_values(&values),
+        )
+    }
+
+    fn read_command(&mut self, reader: &mut ChunkReader) -> io::Result<Vec<AmfValue>> {
+        loop {
+            let message = reader.read(&mut self.socket)?;
+            match message.type_id {
+                MESSAGE_USER_CONTROL => self.answer_ping(&message)?,
+                MESSAGE_COMMAND => {
+                    if let Some(values) = decode_values(&message.payload) {
+                        return Ok(values);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn wait_for_result(
+        &mut self,
+        reader: &mut ChunkReader,
+        transaction_id: f64,
+    ) -> io::Result<Vec<AmfValue>> {
+        loop {
+            let values = self.read_command(reader)?;
+            if values.get(1).and_then(|id| id.as_number()) != Some(transaction_id) {
+                continue;
+            }
+            match values[0].as_str() {
+                Some("_result") => return Ok(values),
+                Some("_error") => return Err(get_status_error(&values)),
+                _ => {}
+            }
+        }
+    }
+
+    fn wait_for_publish(&mut self, reader: &mut ChunkReader) -> io::Result<()> {
+        loop {
+            let values = self.read_command(reader)?;
+            if values[0].as_str() != Some("onStatus") {
+                continue;
+            }
+            let code = values
+                .get(3)
+                .and_then(|info| info.get("code"))
+                .and_then(|code| code.as_str());
+            match code {
+                Some("NetStream.Publish.Start") => return Ok(()),
+                _ => return Err(get_status_error(&values)),
+            }
+        }
+    }
+
+    fn start_reading(&mut self, mut reader: ChunkReader) -> io::Result<()> {
+        let mut socket = self.socket.try_clone()?;
+        let (sender, receiver) = channel();
+        // The thread exits once the socket is closed
+        std::thread::spawn(move || {
+            while let Ok(message) = reader.read(&mut socket) {
+                if message.type_id == MESSAGE_USER_CONTROL && sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        self.control_messages = Some(receiver);
+        Ok(())
+    }
+
+    fn answer_pings(&mut self) -> io::Result<()> {
+        let messages: Vec<_> = self
+            .control_messages
+            .as_ref()
+            .map(|receiver| receiver.try_iter().collect())
+            .unwrap_or_default();
+        for message in messages {
+            self.answer_ping(&message)?;
+        }
+        Ok(())
+    }
+
+    // Servers disconnect clients that don't answer their pings
+    fn answer_ping(&mut self, message: &Message) -> io::Result<()> {
+        let payload = &message.payload;
+        if payload.len() < 2 || u16::from_be_bytes([payload[0], payload[1]]) != PING_REQUEST {
+            return Ok(());
+        }
+        let mut response = PING_RESPONSE.to_be_bytes().to_vec();
+        response.extend_from_slice(&payload[2..]);
+        self.send_message(CONTROL_CHUNK_STREAM, MESSAGE_USER_CONTROL, 0, 0, response)
+    }
+}
+
+impl Drop for RtmpConnection {
+    fn drop(&mut self) {
+        let _ = self.socket.shutdown(Shutdown::Both);
+    }
+}
+
+fn get_status_error(values: &[AmfValue]) -> io::Error {
+    let info = values.get(3);
+    let description = info
+        .and_then(|info| info.get("description"))
+        .and_then(|description| description.as_str())
+        .or_else(|| {
+            info.and_then(|info| info.get("code"))
+                .and_then(|code| code.as_str())
+        })
+        .unwrap_or("unknown error");
+    io::Error::other(format!(
+        "The RTMP server refused the stream: {}",
+        description
+    ))
+}
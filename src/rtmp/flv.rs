@@ -0,0 +1,125 @@
+use super::amf::{encode_values, AmfValue};
+
+// Audio and video messages carry the body of an FLV tag, see Annex E of the
+// FLV specification. Decoders need a sequence header before the first frame.
+
+const AVC_CODEC_ID: u8 = 7;
+const KEY_FRAME: u8 = 1;
+const INTER_FRAME: u8 = 2;
+const AAC_SOUND_FORMAT: u8 = 10;
+// AAC is always signaled as 44 kHz, 16-bit stereo, the real format is in the
+// AudioSpecificConfig
+const AAC_SOUND_FLAGS: u8 = (AAC_SOUND_FORMAT << 4) | 0x0F;
+const SEQUENCE_HEADER: u8 = 0;
+// NAL units for video, raw frames for audio
+const CODED_DATA: u8 = 1;
+
+/// What the stream contains, sent to the server before any audio or video.
+pub struct StreamInfo {
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: f64,
+    // The sample rate and channel count
+    pub audio: Option<(u32, u32)>,
+}
+
+/// The decoder configuration is the AVCDecoderConfigurationRecord.
+pub fn video_sequence_header(decoder_configuration: &[u8]) -> Vec<u8> {
+    let mut body = vec![(KEY_FRAME << 4) | AVC_CODEC_ID, SEQUENCE_HEADER, 0, 0, 0];
+    body.extend_from_slice(decoder_configuration);
+    body
+}
+
+/// The data must be length prefixed NAL units. The composition time is the
+/// difference between the presentation and decode times (in milliseconds).
+pub fn video_frame(key_frame: bool, composition_time: i32, data: &[u8]) -> Vec<u8> {
+    let frame_type = if key_frame { KEY_FRAME } else { INTER_FRAME };
+    let mut body = vec![(frame_type << 4) | AVC_CODEC_ID, CODED_DATA];
+    body.extend_from_slice(&composition_time.to_be_bytes()[1..]);
+    body.extend_from_slice(data);
+    body
+}
+
+pub fn audio_sequence_header(audio_specific_config: &[u8]) -> Vec<u8> {
+    let mut body = vec![AAC_SOUND_FLAGS, SEQUENCE_HEADER];
+    body.extend_from_slice(audio_specific_config);
+    body
+}
+
+pub fn audio_frame(data: &[u8]) -> Vec<u8> {
+    let mut body = vec![AAC_SOUND_FLAGS, CODED_DATA];
+    body.extend_from_slice(data);
+    body
+}
+
+/// The onMetaData script data, which services use to show the stream settings.
+pub fn metadata(info: &StreamInfo) -> Vec<u8> {
+    let mut properties = vec![
+        ("width".to_owned(), AmfValue::Number(info.width as f64)),
+        ("height".to_owned(), AmfValue::Number(info.height as f64)),
+        ("framerate".to_owned(), AmfValue::Number(info.frame_rate)),
+        (
+            "videocodecid".to_owned(),
+            AmfValue::Number(AVC_CODEC_ID as f64),
+        ),
+    ];
+    if let Some((sample_rate, channels)) = info.audio {
+        properties.extend([
+            (
+                "audiocodecid".to_owned(),
+                AmfValue::Number(AAC_SOUND_FORMAT as f64),
+            ),
+            (
+                "audiosamplerate".to_owned(),
+                AmfValue::Number(sample_rate as f64),
+            ),
+            ("stereo".to_owned(), AmfValue::Boolean(channels > 1)),
+        ]);
+    }
+    properties.push((
+        "encoder".to_owned(),
+        AmfValue::string(env!("CARGO_PKG_NAME")),
+    ));
+    encode_values(&[
+        AmfValue::string("@setDataFrame"),
+        AmfValue::string("onMetaData"),
+        AmfValue::EcmaArray(properties),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rtmp::amf::decode_values;
+
+    use super::{audio_frame, metadata, video_frame, video_sequence_header, StreamInfo};
+
+    #[test]
+    fn flv_tag_test() {
+        assert_eq!(video_sequence_header(&[1, 2]), vec![0x17, 0, 0, 0, 0, 1, 2]);
+        assert_eq!(
+            video_frame(false, 33, &[0, 0, 0, 1, 0x41]),
+            vec![0x27, 1, 0, 0, 33, 0, 0, 0, 1, 0x41]
+        );
+        assert_eq!(video_frame(true, -1, &[])[..5], [0x17, 1, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(audio_frame(&[0x21]), vec![0xAF, 1, 0x21]);
+    }
+
+    #[test]
+    fn metadata_test() {
+        let values = decode_values(&metadata(&StreamInfo {
+            width: 1920,
+            height: 1080,
+            frame_rate: 60.0,
+            audio: None,
+        }))
+        .unwrap();
+        assert_eq!(values[1].as_str(), Some("onMetaData"));
+        assert_eq!(
+            values[2]
+                .get("height")
+                .and_then(|height| height.as_number()),
+            Some(1080.0)
+        );
+        assert_eq!(values[2].get("audiocodecid"), None);
+    }
+}
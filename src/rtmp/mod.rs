@@ -0,0 +1,6 @@
+mod amf;
+mod chunk;
+mod connection;
+mod flv;
+pub mod url;
+pub mod writer;
@@ -0,0 +1,115 @@
+use std::{fmt::Display, str::FromStr};
+
+const DEFAULT_PORT: u16 = 1935;
+
+/// Where to stream to: rtmp://host[:port]/app/stream-key. Most services
+/// show the server URL and the stream key separately, these are joined
+/// with a slash.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RtmpUrl {
+    pub host: String,
+    pub port: u16,
+    pub app: String,
+    pub stream_key: String,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseRtmpUrlError(&'static str);
+
+impl RtmpUrl {
+    /// The URL of the application, which is sent when connecting.
+    pub fn tc_url(&self) -> String {
+        format!("rtmp://{}:{}/{}", self.host, self.port, self.app)
+    }
+}
+
+impl FromStr for RtmpUrl {
+    type Err = ParseRtmpUrlError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const ERROR: ParseRtmpUrlError =
+            ParseRtmpUrlError("Invalid stream URL! Expecting rtmp://host[:port]/app/stream-key.");
+        let s = s.trim();
+        let scheme_end = s.find("://").ok_or(ERROR)?;
+        match s[..scheme_end].to_lowercase().as_str() {
+            "rtmp" => {}
+            "rtmps" => {
+                return Err(ParseRtmpUrlError(
+                    "RTMPS isn't supported yet! Use an rtmp:// URL instead.",
+                ))
+            }
+            _ => return Err(ERROR),
+        }
+
+        let (authority, path) = s[scheme_end + 3..].split_once('/').ok_or(ERROR)?;
+        let (host, port) = if let Some((host, port)) = authority.rsplit_once(':') {
+            (host, port.parse().map_err(|_| ERROR)?)
+        } else {
+            (authority, DEFAULT_PORT)
+        };
+        // The app can contain slashes, the stream key can't
+        let (app, stream_key) = path.trim_end_matches('/').rsplit_once('/').ok_or(ERROR)?;
+        if host.is_empty() || app.is_empty() || stream_key.is_empty() {
+            return Err(ERROR);
+        }
+        Ok(Self {
+            host: host.to_owned(),
+            port,
+            app: app.to_owned(),
+            stream_key: stream_key.to_owned(),
+        })
+    }
+}
+
+impl Display for RtmpUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.tc_url(), self.stream_key)
+    }
+}
+
+impl Display for ParseRtmpUrlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseRtmpUrlError {}
+
+#[cfg(test)]
+mod tests {
+    use super::RtmpUrl;
+
+    #[test]
+    fn rtmp_url_parsing_test() {
+        let url: RtmpUrl = "rtmp://live.example.com/app/abc123".parse().unwrap();
+        assert_eq!(
+            url,
+            RtmpUrl {
+                host: "live.example.com".to_owned(),
+                port: 1935,
+                app: "app".to_owned(),
+                stream_key: "abc123".to_owned(),
+            }
+        );
+        assert_eq!(url.tc_url(), "rtmp://live.example.com:1935/app");
+
+        let url: RtmpUrl = "RTMP://localhost:1936/live/2/key?bandwidthtest=true"
+            .parse()
+            .unwrap();
+        assert_eq!(url.port, 1936);
+        assert_eq!(url.app, "live/2");
+        assert_eq!(url.stream_key, "key?bandwidthtest=true");
+        assert_eq!(
+            url.to_string(),
+            "rtmp://localhost:1936/live/2/key?bandwidthtest=true"
+        );
+
+        assert!("rtmps://live.example.com/app/key"
+            .parse::<RtmpUrl>()
+            .is_err());
+        assert!("rtmp://live.example.com/app".parse::<RtmpUrl>().is_err());
+        assert!("rtmp://live.example.com:port/app/key"
+            .parse::<RtmpUrl>()
+            .is_err());
+        assert!("live.example.com/app/key".parse::<RtmpUrl>().is_err());
+    }
+}
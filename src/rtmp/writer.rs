@@ -0,0 +1,396 @@
+use std::{
+    io,
+    sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use tracing::{info, warn};
+use windows::{
+    core::{Error, Result},
+    Win32::{
+        Foundation::E_FAIL,
+        Media::MediaFoundation::{
+            IMFMediaType, IMFSample, MFAudioFormat_PCM, MFMediaType_Audio, MFMediaType_Video,
+            MFSampleExtension_DecodeTimestamp, MFVideoFormat_H264, MF_E_INVALIDMEDIATYPE,
+            MF_MT_AUDIO_NUM_CHANNELS, MF_MT_AUDIO_SAMPLES_PER_SECOND, MF_MT_FRAME_RATE,
+            MF_MT_FRAME_SIZE, MF_MT_MAJOR_TYPE, MF_MT_SUBTYPE,
+        },
+    },
+};
+
+use crate::{
+    audio::aac_encoder::AacEncoder,
+    container::{
+        avc::{create_decoder_configuration, to_length_prefixed},
+        to_error, ContainerWriter,
+    },
+    media::{get_sample_data, is_key_frame},
+};
+
+use super::{
+    connection::{RtmpConnection, TagKind},
+    flv::{
+        audio_frame, audio_sequence_header, metadata, video_frame, video_sequence_header,
+        StreamInfo,
+    },
+    url::RtmpUrl,
+};
+
+// About two seconds of 60 fps video and its audio
+const QUEUE_CAPACITY: usize = 240;
+const MAX_RECONNECT_ATTEMPTS: u32 = 5;
+const FIRST_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const HUNDRED_NANOSECONDS_PER_MILLISECOND: i64 = 10_000;
+
+/// Streams H.264 video and AAC audio to an RTMP server instead of writing a
+/// file. Tags are sent on a separate thread through a bounded queue, if the
+/// connection can't keep up frames are dropped rather than delaying the
+/// recording. A lost connection is re-established a few times before giving up.
+pub struct RtmpWriter {
+    url: RtmpUrl,
+    tracks: Vec<Track>,
+    sender: Option<SyncSender<Tag>>,
+    thread_handle: Option<JoinHandle<Result<()>>>,
+    // After a video frame is dropped, the video can only continue from a key frame
+    waiting_for_key_frame: bool,
+}
+
+enum Track {
+    Video {
+        width: u32,
+        height: u32,
+        frame_rate: f64,
+        sequence_header_sent: bool,
+    },
+    Audio {
+        encoder: AacEncoder,
+        sample_rate: u32,
+        channels: u32,
+        sequence_header_sent: bool,
+    },
+}
+
+struct Tag {
+    kind: TagKind,
+    // In milliseconds
+    timestamp: u32,
+    key_frame: bool,
+    // Sequence headers and metadata are sent again after reconnecting
+    is_header: bool,
+    data: Vec<u8>,
+}
+
+unsafe impl Send for RtmpWriter {}
+impl RtmpWriter {
+    pub fn new(url: RtmpUrl) -> Self {
+        Self {
+            url,
+            tracks: Vec::new(),
+            sender: None,
+            thread_handle: None,
+            waiting_for_key_frame: false,
+        }
+    }
+
+    fn stream_info(&self) -> StreamInfo {
+        let mut info = StreamInfo {
+            width: 0,
+            height: 0,
+            frame_rate: 0.0,
+            audio: None,
+        };
+        for track in &self.tracks {
+            match track {
+                Track::Video {
+                    width,
+                    height,
+                    frame_rate,
+                    ..
+                } => {
+                    info.width = *width;
+                    info.height = *height;
+                    info.frame_rate = *frame_rate;
+                }
+                Track::Audio {
+                    sample_rate,
+                    channels,
+                    ..
+                } => info.audio = Some((*sample_rate, *channels)),
+            }
+        }
+        info
+    }
+
+    fn queue(&mut self, tag: Tag) -> Result<()> {
+        let is_video = tag.kind == TagKind::Video;
+        if is_video && !tag.is_header {
+            if tag.key_frame {
+                self.waiting_for_key_frame = false;
+            } else if self.waiting_for_key_frame {
+                return Ok(());
+            }
+        }
+
+        let sender = self.sender.as_ref().unwrap();
+        let result = if tag.is_header {
+            sender.send(tag).map_err(|_| ())
+        } else {
+            match sender.try_send(tag) {
+                Err(TrySendError::Full(_)) => {
+                    if is_video && !self.waiting_for_key_frame {
+                        warn!("The connection can't keep up with the stream, dropping frames...");
+                        self.waiting_for_key_frame = true;
+                    }
+                    Ok(())
+                }
+                result => result.map_err(|_| ()),
+            }
+        };
+        // The sending thread only stops early if the connection was lost for good
+        if result.is_err() {
+            self.sender = None;
+            return Err(self.join_thread().err().unwrap_or_else(|| {
+                Error::new(E_FAIL, "The connection to the RTMP server was lost!".into())
+            }));
+        }
+        Ok(())
+    }
+
+    fn join_thread(&mut self) -> Result<()> {
+        if let Some(thread_handle) = self.thread_handle.take() {
+            thread_handle.join().unwrap()?;
+        }
+        Ok(())
+    }
+
+    fn write_video(&mut self, stream_index: u32, sample: &IMFSample) -> Result<()> {
+        let (time, decode_time, key_frame, data) = unsafe {
+            let time = sample.GetSampleTime()?;
+            let decode_time = sample
+                .GetUINT64(&MFSampleExtension_DecodeTimestamp)
+                .map(|time| time as i64)
+                .unwrap_or(time);
+            (
+                time,
+                decode_time,
+                is_key_frame(sample),
+                get_sample_data(sample)?,
+            )
+        };
+
+        // The stream has to start with the parameter sets from a key frame
+        if let Track::Video {
+            sequence_header_sent,
+            ..
+        } = &mut self.tracks[stream_index as usize]
+        {
+            if !*sequence_header_sent {
+                let decoder_configuration = if key_frame {
+                    create_decoder_configuration(&data)
+                } else {
+                    None
+                };
+                let decoder_configuration = match decoder_configuration {
+                    Some(decoder_configuration) => decoder_configuration,
+                    None => return Ok(()),
+                };
+                *sequence_header_sent = true;
+                self.queue(Tag {
+                    kind: TagKind::Video,
+                    timestamp: 0,
+                    key_frame: true,
+                    is_header: true,
+                    data: video_sequence_header(&decoder_configuration),
+                })?;
+            }
+        }
+
+        let composition_time = ((time - decode_time) / HUNDRED_NANOSECONDS_PER_MILLISECOND) as i32;
+        self.queue(Tag {
+            kind: TagKind::Video,
+            timestamp: to_milliseconds(decode_time),
+            key_frame,
+            is_header: false,
+            data: video_frame(key_frame, composition_time, &to_length_prefixed(&data)),
+        })
+    }
+
+    fn write_audio(&mut self, stream_index: u32, sample: Option<&IMFSample>) -> Result<()> {
+        let (frames, header) = if let Track::Audio {
+            encoder,
+            sequence_header_sent,
+            ..
+        } = &mut self.tracks[stream_index as usize]
+        {
+            let frames = if let Some(sample) = sample {
+                encoder.encode(sample)?
+            } else {
+                encoder.flush()?
+            };
+            let header = if !*sequence_header_sent && !frames.is_empty() {
+                *sequence_header_sent = true;
+                Some(audio_sequence_header(&encoder.audio_specific_config()))
+            } else {
+                None
+            };
+            (frames, header)
+        } else {
+            return Ok(());
+        };
+
+        if let Some(header) = header {
+            self.queue(Tag {
+                kind: TagKind::Audio,
+                timestamp: 0,
+                key_frame: true,
+                is_header: true,
+                data: header,
+            })?;
+        }
+        for frame in frames {
+            self.queue(Tag {
+                kind: TagKind::Audio,
+                timestamp: to_milliseconds(frame.time),
+                key_frame: true,
+                is_header: false,
+                data: audio_frame(&frame.data),
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl ContainerWriter for RtmpWriter {
+    fn add_stream(&mut self, output_type: &IMFMediaType, input_type: &IMFMediaType) -> Result<u32> {
+        let track = unsafe {
+            let major_type = input_type.GetGUID(&MF_MT_MAJOR_TYPE)?;
+            let subtype = input_type.GetGUID(&MF_MT_SUBTYPE)?;
+            let has_audio = self
+                .tracks
+                .iter()
+                .any(|track| matches!(track, Track::Audio { .. }));
+            if major_type == MFMediaType_Video && subtype == MFVideoFormat_H264 {
+                let frame_size = input_type.GetUINT64(&MF_MT_FRAME_SIZE)?;
+                let frame_rate = input_type.GetUINT64(&MF_MT_FRAME_RATE)?;
+                Track::Video {
+                    width: (frame_size >> 32) as u32,
+                    height: frame_size as u32,
+                    frame_rate: (frame_rate >> 32) as f64 / (frame_rate as u32).max(1) as f64,
+                    sequence_header_sent: false,
+                }
+            } else if major_type == MFMediaType_Audio && subtype == MFAudioFormat_PCM && !has_audio
+            {
+                // FLV only has room for a single audio track
+                Track::Audio {
+                    encoder: AacEncoder::new(output_type, input_type)?,
+                    sample_rate: output_type.GetUINT32(&MF_MT_AUDIO_SAMPLES_PER_SECOND)?,
+                    channels: output_type.GetUINT32(&MF_MT_AUDIO_NUM_CHANNELS)?,
+                    sequence_header_sent: false,
+                }
+            } else {
+                return Err(Error::from(MF_E_INVALIDMEDIATYPE));
+            }
+        };
+        self.tracks.push(track);
+        Ok(self.tracks.len() as u32 - 1)
+    }
+
+    fn start(&mut self) -> Result<()> {
+        // Connecting up front means a bad URL or stream key is reported right away
+        let mut connection = RtmpConnection::connect(&self.url).map_err(to_error)?;
+        let metadata = metadata(&self.stream_info());
+        connection
+            .send(TagKind::Data, 0, &metadata)
+            .map_err(to_error)?;
+
+        let (sender, receiver) = sync_channel(QUEUE_CAPACITY);
+        let url = self.url.clone();
+        self.sender = Some(sender);
+        self.thread_handle = Some(std::thread::spawn(move || -> Result<()> {
+            send_tags(connection, &url, receiver, metadata).map_err(to_error)
+        }));
+        Ok(())
+    }
+
+    fn write_sample(&mut self, stream_index: u32, sample: &IMFSample) -> Result<()> {
+        match self.tracks[stream_index as usize] {
+            Track::Video { .. } => self.write_video(stream_index, sample),
+            Track::Audio { .. } => self.write_audio(stream_index, Some(sample)),
+        }
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        if self.sender.is_none() {
+            return self.join_thread();
+        }
+        for stream_index in 0..self.tracks.len() as u32 {
+            self.write_audio(stream_index, None)?;
+        }
+        // Closing the queue lets the thread send what's left and disconnect
+        self.sender = None;
+        self.join_thread()
+    }
+}
+
+fn send_tags(
+    mut connection: RtmpConnection,
+    url: &RtmpUrl,
+    receiver: Receiver<Tag>,
+    metadata: Vec<u8>,
+) -> io::Result<()> {
+    let mut headers = vec![(TagKind::Data, metadata)];
+    let mut waiting_for_key_frame = false;
+    for tag in receiver {
+        if tag.is_header {
+            headers.push((tag.kind, tag.data.clone()));
+        } else if tag.kind == TagKind::Video {
+            if tag.key_frame {
+                waiting_for_key_frame = false;
+            } else if waiting_for_key_frame {
+                continue;
+            }
+        }
+
+        if let Err(error) = connection.send(tag.kind, tag.timestamp, &tag.data) {
+            warn!(
+                "Lost the connection to the RTMP server ({}), reconnecting...",
+                error
+            );
+            connection = reconnect(url, &headers)?;
+            // Whatever was being sent is lost, so the video has to restart
+            // from a key frame
+            waiting_for_key_frame = true;
+        }
+    }
+    connection.close()
+}
+
+fn reconnect(url: &RtmpUrl, headers: &[(TagKind, Vec<u8>)]) -> io::Result<RtmpConnection> {
+    let mut delay = FIRST_RECONNECT_DELAY;
+    let mut attempt = 1;
+    loop {
+        std::thread::sleep(delay);
+        let result = RtmpConnection::connect(url).and_then(|mut connection| {
+            for (kind, data) in headers {
+                connection.send(*kind, 0, data)?;
+            }
+            Ok(connection)
+        });
+        match result {
+            Ok(connection) => {
+                info!("Reconnected to the RTMP server.");
+                return Ok(connection);
+            }
+            Err(error) if attempt >= MAX_RECONNECT_ATTEMPTS => return Err(error),
+            Err(_) => {
+                attempt += 1;
+                delay *= 2;
+            }
+        }
+    }
+}
+
+fn to_milliseconds(time: i64) -> u32 {
+    (time.max(0) / HUNDRED_NANOSECONDS_PER_MILLISECOND) as u32
+}
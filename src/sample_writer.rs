@@ -0,0 +1,275 @@
+use std::{sync::Mutex, time::Duration};
+
+use windows::{
+    core::Result,
+    Storage::Streams::IRandomAccessStream,
+    Win32::Media::MediaFoundation::{IMFMediaType, IMFSample},
+};
+
+use crate::{
+    change_gate::ChangeGate,
+    container::{create_container_writer, Container, ContainerWriter, Metadata},
+    etw,
+    media::is_key_frame,
+    replay_buffer::ReplayBuffer,
+    segment::SegmentLimit,
+    stats::StatsCounter,
+    timeline::Timeline,
+};
+
+/// Creates the stream for a segment, given its index (starting at 0).
+pub type SegmentStreamFactory = Box<dyn Fn(usize) -> Result<IRandomAccessStream>>;
+
+pub struct SampleWriter {
+    // Live writers (see new_live) don't have a stream or container
+    stream: Mutex<Option<IRandomAccessStream>>,
+    writer: Mutex<Box<dyn ContainerWriter>>,
+    container: Option<Container>,
+    // Every segment gets the same metadata
+    metadata: Metadata,
+    timeline: Timeline,
+    replay_buffer: Option<Mutex<ReplayBuffer>>,
+    change_gate: Option<Mutex<ChangeGate>>,
+    // The (output_type, input_type) of each stream, so that they can be
+    // added again to the writer for each new segment
+    stream_types: Mutex<Vec<(IMFMediaType, IMFMediaType)>>,
+    segmenter: Option<Mutex<Segmenter>>,
+    stats: StatsCounter,
+}
+
+struct Segmenter {
+    limit: Option<SegmentLimit>,
+    // Set by split, the next video key frame starts a new segment
+    split_requested: bool,
+    create_stream: SegmentStreamFactory,
+    index: usize,
+    // In 100ns units, relative to the timeline
+    start_time: i64,
+}
+
+unsafe impl Send for SampleWriter {}
+unsafe impl Sync for SampleWriter {}
+impl SampleWriter {
+    /// Samples written to the writer should be timestamped using the provided
+    /// timeline, which the caller is responsible for starting. If a replay window
+    /// is provided, samples are held in memory and only the last part of the
+    /// recording is written to the stream when stopped.
+    pub fn new(
+        stream: IRandomAccessStream,
+        container: Container,
+        timeline: Timeline,
+        replay_window: Option<Duration>,
+        metadata: Metadata,
+    ) -> Result<Self> {
+        let writer = create_container_writer(container, &stream, &metadata)?;
+
+        Ok(Self {
+            stream: Mutex::new(Some(stream)),
+            writer: Mutex::new(writer),
+            container: Some(container),
+            metadata,
+            timeline,
+            replay_buffer: replay_window.map(|window| Mutex::new(ReplayBuffer::new(window))),
+            change_gate: None,
+            stream_types: Mutex::new(Vec::new()),
+            segmenter: None,
+            stats: StatsCounter::default(),
+        })
+    }
+
+    /// Hands samples straight to a writer that sends them somewhere other
+    /// than a file, e.g. an RtmpWriter. Live writers can't be segmented.
+    pub fn new_live(writer: Box<dyn ContainerWriter>, timeline: Timeline) -> Self {
+        Self {
+            stream: Mutex::new(None),
+            writer: Mutex::new(writer),
+            container: None,
+            metadata: Metadata::default(),
+            timeline,
+            replay_buffer: None,
+            change_gate: None,
+            stream_types: Mutex::new(Vec::new()),
+            segmenter: None,
+            stats: StatsCounter::default(),
+        }
+    }
+
+    /// Splits the recording into multiple files. Once the limit is reached (or
+    /// split is called), the current file is finalized at the next video key
+    /// frame and the recording continues in a stream created by the factory.
+    /// Each segment starts at 0.
+    pub fn with_segments(
+        mut self,
+        limit: Option<SegmentLimit>,
+        create_stream: SegmentStreamFactory,
+    ) -> Self {
+        assert!(self.container.is_some());
+        self.segmenter = Some(Mutex::new(Segmenter {
+            limit,
+            split_requested: false,
+            create_stream,
+            index: 0,
+            start_time: 0,
+        }));
+        self
+    }
+
+    /// Only writes the samples from around the times the screen changes (see
+    /// content_changed), leaving out the gaps in between. Can't be combined
+    /// with a replay window.
+    pub fn with_change_gate(mut self, pre_roll: Duration, post_roll: Duration) -> Self {
+        assert!(self.container.is_some() && self.replay_buffer.is_none());
+        self.change_gate = Some(Mutex::new(ChangeGate::new(pre_roll, post_roll)));
+        self
+    }
+
+    /// Notes that the screen changed at the time (relative to the timeline),
+    /// see with_change_gate.
+    pub fn content_changed(&self, time: i64) {
+        if let Some(change_gate) = &self.change_gate {
+            change_gate.lock().unwrap().changed(time);
+        }
+    }
+
+    /// Adds a stream to the container. If the input type differs from the
+    /// output type, the container writer may encode the samples for the stream.
+    /// All streams must be added before calling start.
+    pub fn add_stream(&self, output_type: &IMFMediaType, input_type: &IMFMediaType) -> Result<u32> {
+        self.stream_types
+            .lock()
+            .unwrap()
+            .push((output_type.clone(), input_type.clone()));
+        self.writer
+            .lock()
+            .unwrap()
+            .add_stream(output_type, input_type)
+    }
+
+    /// The timeline that all streams should use when timestamping samples.
+    /// The container being written, live writers don't have one.
+    pub fn container(&self) -> Option<Container> {
+        self.container
+    }
+
+    pub fn timeline(&self) -> &Timeline {
+        &self.timeline
+    }
+
+    /// Counts the samples written to the writer. The video stream also
+    /// reports the frames it drops here.
+    pub fn stats(&self) -> &StatsCounter {
+        &self.stats
+    }
+
+    /// The number of files written to so far.
+    pub fn segment_count(&self) -> usize {
+        self.segmenter
+            .as_ref()
+            .map(|segmenter| segmenter.lock().unwrap().index + 1)
+            .unwrap_or(1)
+    }
+
+    /// Requests a new segment, returns false if the writer isn't segmented.
+    pub fn split(&self) -> bool {
+        if let Some(segmenter) = &self.segmenter {
+            segmenter.lock().unwrap().split_requested = true;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn start(&self) -> Result<()> {
+        self.writer.lock().unwrap().start()
+    }
+
+    pub fn stop(&self) -> Result<()> {
+        if let Some(replay_buffer) = &self.replay_buffer {
+            let samples = replay_buffer.lock().unwrap().drain()?;
+            for (stream_index, sample) in samples {
+                self.write_to_sink(stream_index, &sample)?;
+            }
+        }
+        if let Some(change_gate) = &self.change_gate {
+            let samples = change_gate.lock().unwrap().finish()?;
+            for (stream_index, sample) in samples {
+                self.write_to_sink(stream_index, &sample)?;
+            }
+        }
+        self.writer.lock().unwrap().finalize()
+    }
+
+    pub fn write(&self, stream_index: u32, sample: &IMFSample) -> Result<()> {
+        let size = unsafe { sample.GetTotalLength()? } as u64;
+        etw::sample_written(stream_index, size);
+        if stream_index == 0 {
+            self.stats.add_frame(size);
+        } else {
+            self.stats.add_bytes(size);
+        }
+        if let Some(replay_buffer) = &self.replay_buffer {
+            replay_buffer.lock().unwrap().push(stream_index, sample)
+        } else if let Some(change_gate) = &self.change_gate {
+            let samples = change_gate.lock().unwrap().push(stream_index, sample)?;
+            for (stream_index, sample) in samples {
+                self.write_to_sink(stream_index, &sample)?;
+            }
+            Ok(())
+        } else {
+            self.write_to_sink(stream_index, sample)
+        }
+    }
+
+    fn write_to_sink(&self, stream_index: u32, sample: &IMFSample) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        if let Some(segmenter) = &self.segmenter {
+            let mut segmenter = segmenter.lock().unwrap();
+            let time = unsafe { sample.GetSampleTime()? };
+            // The video stream is always added first, and new segments have to
+            // start with one of its key frames
+            if stream_index == 0 && is_key_frame(sample) {
+                let size = match self.stream.lock().unwrap().as_ref() {
+                    Some(stream) => stream.Size()?,
+                    None => 0,
+                };
+                let limit_reached = segmenter
+                    .limit
+                    .map(|limit| limit.is_reached(time - segmenter.start_time, size))
+                    .unwrap_or(false);
+                if segmenter.split_requested || limit_reached {
+                    self.start_next_segment(&mut writer, &mut segmenter, time)?;
+                }
+            }
+            // Drop anything (e.g. audio) that arrives late for the new segment
+            if time < segmenter.start_time {
+                return Ok(());
+            }
+            unsafe { sample.SetSampleTime(time - segmenter.start_time)? };
+        }
+        writer.write_sample(stream_index, sample)
+    }
+
+    fn start_next_segment(
+        &self,
+        writer: &mut Box<dyn ContainerWriter>,
+        segmenter: &mut Segmenter,
+        start_time: i64,
+    ) -> Result<()> {
+        writer.finalize()?;
+
+        let stream = (segmenter.create_stream)(segmenter.index + 1)?;
+        let mut new_writer =
+            create_container_writer(self.container.unwrap(), &stream, &self.metadata)?;
+        for (output_type, input_type) in self.stream_types.lock().unwrap().iter() {
+            new_writer.add_stream(output_type, input_type)?;
+        }
+        new_writer.start()?;
+
+        *writer = new_writer;
+        *self.stream.lock().unwrap() = Some(stream);
+        segmenter.index += 1;
+        segmenter.start_time = start_time;
+        segmenter.split_requested = false;
+        Ok(())
+    }
+}
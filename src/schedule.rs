@@ -0,0 +1,112 @@
+use std::{fmt::Display, str::FromStr, time::Duration};
+
+use windows::Win32::System::SystemInformation::GetLocalTime;
+
+const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+
+/// A local wall-clock time, e.g. 14:30 or 14:30:15.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ClockTime {
+    // Seconds since midnight
+    seconds: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseClockTimeError(&'static str);
+
+impl ClockTime {
+    pub fn now() -> Self {
+        let time = unsafe { GetLocalTime() };
+        Self {
+            seconds: time.wHour as u32 * 3600 + time.wMinute as u32 * 60 + time.wSecond as u32,
+        }
+    }
+
+    /// The time until this clock time is next reached, which may be tomorrow.
+    pub fn duration_from(&self, now: ClockTime) -> Duration {
+        let seconds = (self.seconds + SECONDS_PER_DAY - now.seconds) % SECONDS_PER_DAY;
+        Duration::from_secs(seconds as u64)
+    }
+}
+
+impl FromStr for ClockTime {
+    type Err = ParseClockTimeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const ERROR: ParseClockTimeError = ParseClockTimeError(
+            "Invalid time value! Expecting a 24-hour time: HH:MM or HH:MM:SS (e.g. 14:30).",
+        );
+        let parts: Vec<_> = s
+            .trim()
+            .split(':')
+            .map(|part| part.parse::<u32>().map_err(|_| ERROR))
+            .collect::<Result<_, _>>()?;
+        let (hours, minutes, seconds) = match parts.as_slice() {
+            [hours, minutes] => (*hours, *minutes, 0),
+            [hours, minutes, seconds] => (*hours, *minutes, *seconds),
+            _ => return Err(ERROR),
+        };
+        if hours > 23 || minutes > 59 || seconds > 59 {
+            return Err(ERROR);
+        }
+        Ok(Self {
+            seconds: hours * 3600 + minutes * 60 + seconds,
+        })
+    }
+}
+
+impl Display for ClockTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}:{:02}",
+            self.seconds / 3600,
+            (self.seconds / 60) % 60,
+            self.seconds % 60
+        )
+    }
+}
+
+impl Display for ParseClockTimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseClockTimeError {}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::ClockTime;
+
+    #[test]
+    fn clock_time_parsing_test() {
+        assert_eq!(
+            "14:30".parse::<ClockTime>().unwrap().to_string(),
+            "14:30:00"
+        );
+        assert_eq!(
+            "7:05:09".parse::<ClockTime>().unwrap().to_string(),
+            "07:05:09"
+        );
+        assert!("24:00".parse::<ClockTime>().is_err());
+        assert!("12:60".parse::<ClockTime>().is_err());
+        assert!("12".parse::<ClockTime>().is_err());
+        assert!("noon".parse::<ClockTime>().is_err());
+    }
+
+    #[test]
+    fn duration_from_test() {
+        let time: ClockTime = "14:30".parse().unwrap();
+        let now: ClockTime = "14:00".parse().unwrap();
+        assert_eq!(time.duration_from(now), Duration::from_secs(30 * 60));
+        // Times that have already passed today are scheduled for tomorrow
+        let now: ClockTime = "15:00".parse().unwrap();
+        assert_eq!(
+            time.duration_from(now),
+            Duration::from_secs(23 * 60 * 60 + 30 * 60)
+        );
+        assert_eq!(time.duration_from(time), Duration::ZERO);
+    }
+}
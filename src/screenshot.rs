@@ -0,0 +1,118 @@
+use std::path::Path;
+
+use windows::{
+    core::Result,
+    Win32::{Foundation::HWND, Graphics::Gdi::HMONITOR},
+};
+
+use crate::{
+    capture::{
+        create_capture_item_for_monitor, create_capture_item_for_window, CaptureFrameGenerator,
+        FrameReader, DEFAULT_PIXEL_FORMAT,
+    },
+    d3d::create_d3d_device,
+    image::save_png,
+    recorder::{
+        configuration_error, cursor_capture_toggle_supported, get_display_handle,
+        required_capture_features_supported,
+    },
+    region::Region,
+};
+
+/// Saves a single frame of a display or window as a PNG, without setting up
+/// any encoders. The calling thread must be initialized for WinRT, see
+/// RecorderBuilder.
+pub struct ScreenshotBuilder {
+    output_path: String,
+    display: usize,
+    window: Option<HWND>,
+    region: Option<Region>,
+    capture_cursor: bool,
+}
+
+impl ScreenshotBuilder {
+    pub fn new<S: Into<String>>(output_path: S) -> Self {
+        Self {
+            output_path: output_path.into(),
+            display: 0,
+            window: None,
+            region: None,
+            capture_cursor: true,
+        }
+    }
+
+    /// The index of the display to capture, defaults to the first display.
+    pub fn display(mut self, display_index: usize) -> Self {
+        self.display = display_index;
+        self
+    }
+
+    /// Captures a window instead of a display.
+    pub fn window(mut self, window: HWND) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    /// Only saves part of the display or window.
+    pub fn region(mut self, region: Region) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    pub fn capture_cursor(mut self, capture_cursor: bool) -> Self {
+        self.capture_cursor = capture_cursor;
+        self
+    }
+
+    pub fn save(self) -> Result<()> {
+        let is_png = Path::new(&self.output_path)
+            .extension()
+            .map(|extension| extension.eq_ignore_ascii_case("png"))
+            .unwrap_or(false);
+        if !is_png {
+            return Err(configuration_error(
+                "Screenshots can only be saved as .png files!",
+            ));
+        }
+        if !required_capture_features_supported()? {
+            return Err(configuration_error("The required screen capture features are not supported on this device for this release of Windows!\nPlease update your operating system (minimum: Windows 10 Version 1903, Build 18362)."));
+        }
+        if !self.capture_cursor && !cursor_capture_toggle_supported()? {
+            return Err(configuration_error("Excluding the cursor from the screenshot is not supported on this release of Windows!\nPlease update your operating system (minimum: Windows 10 Version 2004, Build 19041)."));
+        }
+
+        let item = if let Some(window) = self.window {
+            create_capture_item_for_window(window)?
+        } else {
+            let display_handle: HMONITOR = get_display_handle(self.display)?;
+            create_capture_item_for_monitor(display_handle)?
+        };
+        let item_size = item.Size()?;
+        if let Some(region) = self.region {
+            if !region.fits_in(item_size) {
+                return Err(configuration_error(
+                    "The provided region is outside the bounds of the capture target!",
+                ));
+            }
+        }
+
+        // The first frame is all we need
+        let d3d_device = create_d3d_device()?;
+        let mut frame_reader = FrameReader::new(d3d_device.clone())?;
+        let mut frame_generator = CaptureFrameGenerator::new(
+            d3d_device,
+            vec![(item, item_size)],
+            DEFAULT_PIXEL_FORMAT,
+            self.capture_cursor,
+        )?;
+        let session = frame_generator.sessions().remove(0);
+        session.StartCapture()?;
+        let (_, frame) = frame_generator.try_get_next_frame()?.unwrap();
+        let (pixels, size) =
+            frame_reader.read(&frame, self.region.map(|region| region.to_rect()))?;
+        frame.Close()?;
+        frame_generator.stop_capture()?;
+
+        save_png(&self.output_path, &pixels, size)
+    }
+}
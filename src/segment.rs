@@ -0,0 +1,93 @@
+use std::{fmt::Display, str::FromStr, time::Duration};
+
+use crate::{duration::parse_duration, size::parse_size};
+
+/// When to finish the current file and continue the recording in a new one.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SegmentLimit {
+    Duration(Duration),
+    /// In bytes
+    Size(u64),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseSegmentLimitError(&'static str);
+
+impl SegmentLimit {
+    /// Segments can only start on a key frame, so a segment runs a little
+    /// past its limit until the next key frame arrives.
+    pub fn is_reached(&self, duration: i64, size: u64) -> bool {
+        match self {
+            // The duration is in 100ns units
+            SegmentLimit::Duration(limit) => duration >= (limit.as_nanos() / 100) as i64,
+            SegmentLimit::Size(limit) => size >= *limit,
+        }
+    }
+}
+
+impl FromStr for SegmentLimit {
+    type Err = ParseSegmentLimitError;
+
+    /// Accepts either a duration (e.g. 10min) or a size (e.g. 2GB).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const ERROR: ParseSegmentLimitError = ParseSegmentLimitError(
+            "Invalid segment value! Expecting a duration (e.g. 10min) or a size in KB, MB, or GB (e.g. 2GB).",
+        );
+        let value = s.trim().to_lowercase();
+        if value.ends_with('b') {
+            parse_size(&value)
+                .map(SegmentLimit::Size)
+                .map_err(|_| ERROR)
+        } else {
+            parse_duration(&value)
+                .map(SegmentLimit::Duration)
+                .map_err(|_| ERROR)
+        }
+    }
+}
+
+impl Display for SegmentLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SegmentLimit::Duration(duration) => write!(f, "{}s", duration.as_secs_f64()),
+            SegmentLimit::Size(size) => write!(f, "{}B", size),
+        }
+    }
+}
+
+impl Display for ParseSegmentLimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseSegmentLimitError {}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::SegmentLimit;
+
+    #[test]
+    fn segment_parsing_test() {
+        assert_eq!(
+            "10min".parse(),
+            Ok(SegmentLimit::Duration(Duration::from_secs(600)))
+        );
+        assert_eq!("2GB".parse(), Ok(SegmentLimit::Size(2 << 30)));
+        assert_eq!("1.5 mb".parse(), Ok(SegmentLimit::Size(3 << 19)));
+        assert!("0GB".parse::<SegmentLimit>().is_err());
+        assert!("2TB".parse::<SegmentLimit>().is_err());
+        assert!("soon".parse::<SegmentLimit>().is_err());
+    }
+
+    #[test]
+    fn segment_limit_test() {
+        let limit = SegmentLimit::Duration(Duration::from_secs(1));
+        assert!(!limit.is_reached(9_999_999, u64::MAX));
+        assert!(limit.is_reached(10_000_000, 0));
+        let limit = SegmentLimit::Size(1000);
+        assert!(!limit.is_reached(i64::MAX, 999));
+        assert!(limit.is_reached(0, 1000));
+    }
+}
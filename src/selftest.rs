@@ -0,0 +1,251 @@
+use std::time::Duration;
+
+use windows::Graphics::SizeInt32;
+
+use crate::{
+    error::RecorderError,
+    media_info::{read_media_info, MediaInfo},
+    recorder::{configuration_error, RecorderBuilder},
+    resolution::Resolution,
+    video::codec::VideoCodec,
+};
+
+// How far the frame count and duration can be from what was recorded, since
+// the first frame takes a moment to arrive
+const TOLERANCE: f64 = 0.1;
+
+/// Records a few seconds of the test pattern (see RecorderBuilder::test_pattern)
+/// and reads the recording back, to check that the encoders and the writers
+/// work on this machine. The calling thread must be initialized for WinRT,
+/// see RecorderBuilder.
+pub struct SelfTestBuilder {
+    output_path: String,
+    codec: VideoCodec,
+    resolution: Resolution,
+    frame_rate: u32,
+    duration: Duration,
+    allow_software_encoder: bool,
+}
+
+/// What the recording looked like when it was read back, see SelfTestBuilder::run.
+pub struct SelfTestReport {
+    pub output_path: String,
+    pub media_info: MediaInfo,
+    pub checks: Vec<SelfTestCheck>,
+}
+
+/// A property of the recording, compared with what was recorded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelfTestCheck {
+    pub name: &'static str,
+    pub expected: String,
+    pub actual: String,
+    pub passed: bool,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+}
+
+impl SelfTestBuilder {
+    /// The recording is left at the output path, which must be an MP4.
+    pub fn new<S: Into<String>>(output_path: S) -> Self {
+        Self {
+            output_path: output_path.into(),
+            codec: VideoCodec::H264,
+            resolution: Resolution::_1080p,
+            frame_rate: 30,
+            duration: Duration::from_secs(3),
+            allow_software_encoder: false,
+        }
+    }
+
+    pub fn codec(mut self, codec: VideoCodec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// The size of the test pattern, which can't be native since nothing is
+    /// captured.
+    pub fn resolution(mut self, resolution: Resolution) -> Self {
+        self.resolution = resolution;
+        self
+    }
+
+    pub fn frame_rate(mut self, frame_rate: u32) -> Self {
+        self.frame_rate = frame_rate;
+        self
+    }
+
+    /// How long to record for.
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Falls back to a software encoder when there's no hardware encoder.
+    pub fn allow_software_encoder(mut self, allow_software_encoder: bool) -> Self {
+        self.allow_software_encoder = allow_software_encoder;
+        self
+    }
+
+    /// Records the test pattern in real time, then reads the recording back.
+    /// A recording that doesn't match is reported in the checks rather than
+    /// failing the self-test.
+    pub fn run(self) -> std::result::Result<SelfTestReport, RecorderError> {
+        let size = if let Some(size) = self.resolution.get_size() {
+            size
+        } else {
+            return Err(configuration_error(
+                "Self-tests need a --resolution other than native, since nothing is captured!",
+            )
+            .into());
+        };
+        if self.duration.is_zero() {
+            return Err(configuration_error("The --duration must be longer than 0s!").into());
+        }
+        let mut session = RecorderBuilder::new(self.output_path.as_str())
+            .test_pattern(true)
+            .codec(self.codec)
+            .resolution(self.resolution)
+            .frame_rate(self.frame_rate)
+            .allow_software_encoder(self.allow_software_encoder)
+            .build()?;
+        session.start()?;
+        std::thread::sleep(self.duration);
+        session.stop()?;
+
+        let output_path = session.output_paths()[0].clone();
+        let media_info = read_media_info(&output_path)?;
+        let checks = check_recording(
+            &media_info,
+            self.codec,
+            size,
+            self.frame_rate,
+            session.elapsed(),
+        );
+        Ok(SelfTestReport {
+            output_path,
+            media_info,
+            checks,
+        })
+    }
+}
+
+// Compares the recording with what was recorded for the elapsed time
+fn check_recording(
+    media_info: &MediaInfo,
+    codec: VideoCodec,
+    size: SizeInt32,
+    frame_rate: u32,
+    elapsed: Duration,
+) -> Vec<SelfTestCheck> {
+    let within_tolerance =
+        |actual: f64, expected: f64| (actual - expected).abs() <= expected * TOLERANCE;
+    let format_size = |size: SizeInt32| format!("{}x{}", size.Width, size.Height);
+    let expected_frames = (elapsed.as_secs_f64() * frame_rate as f64).round();
+    let duration = media_info.shortest_duration();
+    vec![
+        SelfTestCheck {
+            name: "Codec",
+            expected: codec.display_name().to_owned(),
+            actual: media_info
+                .codec
+                .map_or("unknown", |codec| codec.display_name())
+                .to_owned(),
+            passed: media_info.codec == Some(codec),
+        },
+        SelfTestCheck {
+            name: "Resolution",
+            expected: format_size(size),
+            actual: format_size(media_info.size),
+            passed: media_info.size == size,
+        },
+        SelfTestCheck {
+            name: "Frames",
+            expected: format!("{}", expected_frames),
+            actual: format!("{}", media_info.frames),
+            passed: within_tolerance(media_info.frames as f64, expected_frames),
+        },
+        SelfTestCheck {
+            name: "Duration",
+            expected: format!("{:.2}s", elapsed.as_secs_f64()),
+            actual: format!("{:.2}s", duration.as_secs_f64()),
+            passed: within_tolerance(duration.as_secs_f64(), elapsed.as_secs_f64()),
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use windows::Graphics::SizeInt32;
+
+    use crate::{media_info::MediaInfo, video::codec::VideoCodec};
+
+    use super::check_recording;
+
+    #[test]
+    fn check_recording_test() {
+        let size = SizeInt32 {
+            Width: 1920,
+            Height: 1080,
+        };
+        let media_info = MediaInfo {
+            codec: Some(VideoCodec::H264),
+            size,
+            frames: 88,
+            duration: Duration::from_millis(2933),
+            stated_duration: Some(Duration::from_millis(2933)),
+        };
+        let elapsed = Duration::from_secs(3);
+        let passed = |media_info: &MediaInfo| {
+            check_recording(media_info, VideoCodec::H264, size, 30, elapsed)
+                .iter()
+                .map(|check| check.passed)
+                .collect::<Vec<_>>()
+        };
+
+        // The first frame arriving late is within the tolerance
+        assert_eq!(passed(&media_info), [true; 4]);
+        let checks = check_recording(&media_info, VideoCodec::H264, size, 30, elapsed);
+        assert_eq!(checks[2].expected, "90");
+        assert_eq!(checks[3].actual, "2.93s");
+
+        // Missing frames aren't
+        let short = MediaInfo {
+            frames: 45,
+            duration: Duration::from_millis(1500),
+            ..media_info
+        };
+        assert_eq!(passed(&short), [true, true, false, false]);
+        // Including a track that says it ended early
+        let truncated = MediaInfo {
+            stated_duration: Some(Duration::from_millis(1500)),
+            ..media_info
+        };
+        assert_eq!(passed(&truncated), [true, true, true, false]);
+        assert_eq!(
+            check_recording(&truncated, VideoCodec::H264, size, 30, elapsed)[3].actual,
+            "1.50s"
+        );
+
+        // Nor is the wrong codec or size
+        let wrong = MediaInfo {
+            codec: None,
+            size: SizeInt32 {
+                Width: 1280,
+                Height: 720,
+            },
+            ..media_info
+        };
+        assert_eq!(passed(&wrong), [false, false, true, true]);
+        assert_eq!(
+            check_recording(&wrong, VideoCodec::H264, size, 30, elapsed)[0].actual,
+            "unknown"
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Condvar, Mutex,
+    },
+    time::Duration,
+};
+
+use tracing::{info, warn};
+use windows::{
+    core::Result,
+    Win32::{
+        Foundation::BOOL,
+        System::Console::{SetConsoleCtrlHandler, CTRL_BREAK_EVENT, CTRL_C_EVENT},
+    },
+};
+
+// How long a recording gets to finish after Ctrl+C before the process exits anyway
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+// Windows ends the process 5 seconds after the console window is closed
+// (and a little later on logoff or shutdown), so this needs to be shorter
+const CLOSE_TIMEOUT: Duration = Duration::from_millis(4500);
+
+// Whether there's a recording that would be corrupted by exiting
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+// Counts up while stopping takes long on purpose, see report_progress
+static PROGRESS: AtomicU64 = AtomicU64::new(0);
+static FINISHED: Mutex<bool> = Mutex::new(false);
+static FINISHED_CHANGED: Condvar = Condvar::new();
+
+/// While this is alive, Ctrl+C (or closing the console, logging off, or
+/// shutting down) asks the recording to stop instead of ending the process
+/// in the middle of writing the file, see is_stop_requested. Drop it once
+/// the recording is finalized.
+pub struct ShutdownGuard;
+
+impl ShutdownGuard {
+    pub fn new() -> Result<Self> {
+        unsafe { SetConsoleCtrlHandler(Some(console_handler), true)? };
+        *FINISHED.lock().unwrap() = false;
+        ACTIVE.store(true, Ordering::SeqCst);
+        Ok(Self)
+    }
+}
+
+impl Drop for ShutdownGuard {
+    fn drop(&mut self) {
+        ACTIVE.store(false, Ordering::SeqCst);
+        *FINISHED.lock().unwrap() = true;
+        FINISHED_CHANGED.notify_all();
+    }
+}
+
+pub fn is_stop_requested() -> bool {
+    STOP_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Gives the recording another STOP_TIMEOUT to finish after Ctrl+C, e.g.
+/// for each frame that's encoded with --encode-later, which can take long.
+pub fn report_progress() {
+    PROGRESS.fetch_add(1, Ordering::SeqCst);
+}
+
+// Called on a thread of its own
+unsafe extern "system" fn console_handler(ctrl_type: u32) -> BOOL {
+    if !ACTIVE.load(Ordering::SeqCst) {
+        // Exits as usual
+        return false.into();
+    }
+    let already_requested = STOP_REQUESTED.swap(true, Ordering::SeqCst);
+    if ctrl_type == CTRL_C_EVENT || ctrl_type == CTRL_BREAK_EVENT {
+        if already_requested {
+            info!("Still stopping the recording...");
+        } else {
+            std::thread::spawn(|| loop {
+                let progress = PROGRESS.load(Ordering::SeqCst);
+                if wait_for_finish(STOP_TIMEOUT) {
+                    break;
+                }
+                if PROGRESS.load(Ordering::SeqCst) == progress {
+                    warn!("The recording didn't stop in time, exiting anyway!");
+                    std::process::exit(1);
+                }
+            });
+        }
+    } else {
+        // The process ends as soon as this returns
+        wait_for_finish(CLOSE_TIMEOUT);
+    }
+    true.into()
+}
+
+// Returns false if the recording didn't finish in time
+fn wait_for_finish(timeout: Duration) -> bool {
+    let finished = FINISHED.lock().unwrap();
+    let (finished, _) = FINISHED_CHANGED
+        .wait_timeout_while(finished, timeout, |finished| !*finished)
+        .unwrap();
+    *finished
+}
@@ -0,0 +1,52 @@
+use std::fmt::Display;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseSizeError(&'static str);
+
+/// Parses sizes such as "500MB" or "2GB" into bytes. Sizes use binary
+/// multiples, matching what Explorer shows.
+pub fn parse_size(value: &str) -> Result<u64, ParseSizeError> {
+    const ERROR: ParseSizeError =
+        ParseSizeError("Invalid size value! Expecting a size in B, KB, MB, or GB (e.g. 2GB).");
+    let value = value.trim().to_lowercase();
+    let unit_start = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(unit_start);
+    let number: f64 = number.parse().map_err(|_| ERROR)?;
+    let bytes_per_unit = match unit.trim() {
+        "b" => 1,
+        "kb" => 1 << 10,
+        "mb" => 1 << 20,
+        "gb" => 1 << 30,
+        _ => return Err(ERROR),
+    };
+    let size = (number * bytes_per_unit as f64) as u64;
+    if size == 0 {
+        Err(ERROR)
+    } else {
+        Ok(size)
+    }
+}
+
+impl Display for ParseSizeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseSizeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_size;
+
+    #[test]
+    fn size_parsing_test() {
+        assert_eq!(parse_size("500MB"), Ok(500 << 20));
+        assert_eq!(parse_size("2gb"), Ok(2 << 30));
+        assert_eq!(parse_size("1.5 KB"), Ok(1536));
+        assert!(parse_size("0MB").is_err());
+        assert!(parse_size("500").is_err());
+        assert!(parse_size("2TB").is_err());
+    }
+}
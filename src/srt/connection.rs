@@ -0,0 +1,380 @@
+use std::{
+    collections::{hash_map::RandomState, VecDeque},
+    hash::{BuildHasher, Hasher},
+    io,
+    net::{ToSocketAddrs, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    packet::{
+        create_hsreq_extension, create_stream_id_extension, decode_loss_list, encode_address,
+        encode_data_packet, sequence_offset, ControlPacket, Handshake, CONTROL_ACK, CONTROL_ACKACK,
+        CONTROL_HANDSHAKE, CONTROL_KEEPALIVE, CONTROL_NAK, CONTROL_SHUTDOWN, EXTENSION_FLAG_CONFIG,
+        EXTENSION_FLAG_HSREQ, HANDSHAKE_CONCLUSION, HANDSHAKE_INDUCTION, HANDSHAKE_MAGIC,
+        HANDSHAKE_REJECTION_BASE, SEQUENCE_NUMBER_MASK,
+    },
+    url::SrtUrl,
+};
+
+/// The most payload that fits in a packet, seven MPEG-TS packets.
+pub const MAX_PAYLOAD_SIZE: usize = 1316;
+const MTU: u32 = 1500;
+const FLOW_WINDOW: u32 = 8192;
+// The UDT version and socket type (datagram) that start every caller handshake
+const INDUCTION_VERSION: u32 = 4;
+const INDUCTION_EXTENSION_FIELD: u16 = 2;
+const SRT_HANDSHAKE_VERSION: u32 = 5;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const HANDSHAKE_RETRY_INTERVAL: Duration = Duration::from_millis(250);
+// The reader wakes up this often to send keepalives and drop old packets
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
+// Receivers acknowledge packets every 10ms, this much silence means they're gone
+const PEER_IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+// Packets are kept for retransmission until the receiver would have given up
+// on them anyway, plus some slack for the round trip
+const RETRANSMISSION_MARGIN: Duration = Duration::from_millis(500);
+
+/// A connection to an SRT listener in live mode, see connect. Packets are
+/// kept until they are acknowledged so that lost ones can be sent again.
+pub struct SrtConnection {
+    shared: Arc<Shared>,
+    reader_handle: Option<JoinHandle<()>>,
+}
+
+struct Shared {
+    socket: UdpSocket,
+    peer_socket_id: u32,
+    start_time: Instant,
+    retention: Duration,
+    closed: AtomicBool,
+    state: Mutex<SendState>,
+}
+
+struct SendState {
+    next_sequence_number: u32,
+    next_message_number: u32,
+    unacknowledged: VecDeque<SentPacket>,
+    last_sent: Instant,
+    // Set by the reader when the connection is lost
+    error: Option<&'static str>,
+}
+
+struct SentPacket {
+    sequence_number: u32,
+    message_number: u32,
+    timestamp: u32,
+    sent: Instant,
+    payload: Vec<u8>,
+}
+
+impl SrtConnection {
+    /// Connects to the listener, which may refuse the stream id.
+    pub fn connect(url: &SrtUrl) -> io::Result<Self> {
+        let address = (url.host.as_str(), url.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::other("Could not resolve the host of the stream URL!"))?;
+        let socket = UdpSocket::bind(if address.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        })?;
+        socket.connect(address)?;
+        socket.set_read_timeout(Some(HANDSHAKE_RETRY_INTERVAL))?;
+
+        // libsrt only uses socket ids below 2^30
+        let socket_id = random_u32() & 0x3FFFFFFF;
+        let initial_sequence_number = random_u32() & SEQUENCE_NUMBER_MASK;
+        let start_time = Instant::now();
+        let mut handshake = Handshake {
+            version: INDUCTION_VERSION,
+            encryption: 0,
+            extension_field: INDUCTION_EXTENSION_FIELD,
+            initial_sequence_number,
+            mtu: MTU,
+            flow_window: FLOW_WINDOW,
+            handshake_type: HANDSHAKE_INDUCTION,
+            socket_id,
+            cookie: 0,
+            peer_address: encode_address(address.ip()),
+            extensions: Vec::new(),
+        };
+
+        // The listener answers the induction with a cookie, which proves to it
+        // that we can receive what it sends
+        let response = exchange_handshake(&socket, &handshake, start_time)?;
+        if response.version < SRT_HANDSHAKE_VERSION || response.extension_field != HANDSHAKE_MAGIC {
+            return Err(io::Error::other(
+                "The SRT server doesn't support version 5 handshakes!",
+            ));
+        }
+
+        let latency = url.latency.as_millis().min(u16::MAX as u128) as u16;
+        handshake.version = SRT_HANDSHAKE_VERSION;
+        handshake.extension_field = EXTENSION_FLAG_HSREQ;
+        handshake.handshake_type = HANDSHAKE_CONCLUSION;
+        handshake.cookie = response.cookie;
+        handshake.extensions.push(create_hsreq_extension(latency));
+        if let Some(stream_id) = &url.stream_id {
+            handshake.extension_field |= EXTENSION_FLAG_CONFIG;
+            handshake
+                .extensions
+                .push(create_stream_id_extension(stream_id));
+        }
+        let response = exchange_handshake(&socket, &handshake, start_time)?;
+        if response.handshake_type >= HANDSHAKE_REJECTION_BASE
+            && response.handshake_type != HANDSHAKE_CONCLUSION
+        {
+            return Err(io::Error::other(format!(
+                "The SRT server refused the stream (reason {})!",
+                response.handshake_type - HANDSHAKE_REJECTION_BASE
+            )));
+        }
+
+        socket.set_read_timeout(Some(READ_TIMEOUT))?;
+        let shared = Arc::new(Shared {
+            socket,
+            peer_socket_id: response.socket_id,
+            start_time,
+            retention: url.latency + RETRANSMISSION_MARGIN,
+            closed: AtomicBool::new(false),
+            state: Mutex::new(SendState {
+                next_sequence_number: initial_sequence_number,
+                next_message_number: 1,
+                unacknowledged: VecDeque::new(),
+                last_sent: Instant::now(),
+                error: None,
+            }),
+        });
+        let reader_shared = shared.clone();
+        let reader_handle = std::thread::spawn(move || read_packets(&reader_shared));
+        Ok(Self {
+            shared,
+            reader_handle: Some(reader_handle),
+        })
+    }
+
+    /// Sends a payload of at most MAX_PAYLOAD_SIZE bytes.
+    pub fn send(&self, payload: &[u8]) -> io::Result<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(error) = state.error {
+            return Err(io::Error::other(error));
+        }
+        let packet = SentPacket {
+            sequence_number: state.next_sequence_number,
+            message_number: state.next_message_number,
+            timestamp: self.shared.timestamp(),
+            sent: Instant::now(),
+            payload: payload.to_vec(),
+        };
+        self.shared.send_data(&packet, false)?;
+        state.next_sequence_number = (state.next_sequence_number + 1) & SEQUENCE_NUMBER_MASK;
+        // Message numbers start at 1
+        state.next_message_number = state.next_message_number % 0x03FFFFFF + 1;
+        state.last_sent = packet.sent;
+        state.unacknowledged.push_back(packet);
+        Ok(())
+    }
+
+    /// Tells the listener that the stream has ended.
+    pub fn close(mut self) -> io::Result<()> {
+        let result = self
+            .shared
+            .send_control(ControlPacket::new(CONTROL_SHUTDOWN, 0, vec![0; 4]));
+        self.stop_reading();
+        result
+    }
+
+    fn stop_reading(&mut self) {
+        self.shared.closed.store(true, Ordering::SeqCst);
+        if let Some(reader_handle) = self.reader_handle.take() {
+            let _ = reader_handle.join();
+        }
+    }
+}
+
+impl Drop for SrtConnection {
+    fn drop(&mut self) {
+        self.stop_reading();
+    }
+}
+
+impl Shared {
+    // In microseconds since the connection started, wrapping around
+    fn timestamp(&self) -> u32 {
+        self.start_time.elapsed().as_micros() as u32
+    }
+
+    fn send_data(&self, packet: &SentPacket, retransmitted: bool) -> io::Result<()> {
+        let data = encode_data_packet(
+            packet.sequence_number,
+            packet.message_number,
+            retransmitted,
+            packet.timestamp,
+            self.peer_socket_id,
+            &packet.payload,
+        );
+        self.socket.send(&data).map(|_| ())
+    }
+
+    fn send_control(&self, mut packet: ControlPacket) -> io::Result<()> {
+        packet.timestamp = self.timestamp();
+        packet.destination = self.peer_socket_id;
+        self.socket.send(&packet.encode()).map(|_| ())
+    }
+
+    fn handle_packet(&self, packet: ControlPacket) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        match packet.control_type {
+            CONTROL_ACK if packet.contents.len() >= 4 => {
+                // Everything before this sequence number has arrived
+                let acknowledged = u32::from_be_bytes(packet.contents[..4].try_into().unwrap());
+                while let Some(sent) = state.unacknowledged.front() {
+                    if sequence_offset(sent.sequence_number, acknowledged) <= 0 {
+                        break;
+                    }
+                    state.unacknowledged.pop_front();
+                }
+                // Only full acknowledgements (not the light ones) are acknowledged
+                // in turn, which lets the receiver measure the round trip time
+                if packet.contents.len() > 4 {
+                    drop(state);
+                    self.send_control(ControlPacket::new(
+                        CONTROL_ACKACK,
+                        packet.type_information,
+                        Vec::new(),
+                    ))?;
+                }
+            }
+            CONTROL_NAK => {
+                for (first, last) in decode_loss_list(&packet.contents) {
+                    for sent in &state.unacknowledged {
+                        if sequence_offset(first, sent.sequence_number) >= 0
+                            && sequence_offset(sent.sequence_number, last) >= 0
+                        {
+                            self.send_data(sent, true)?;
+                        }
+                    }
+                }
+            }
+            CONTROL_SHUTDOWN => state.error = Some("The SRT server closed the connection!"),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn maintain(&self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        while let Some(sent) = state.unacknowledged.front() {
+            if sent.sent.elapsed() < self.retention {
+                break;
+            }
+            state.unacknowledged.pop_front();
+        }
+        if state.last_sent.elapsed() >= KEEPALIVE_INTERVAL {
+            state.last_sent = Instant::now();
+            drop(state);
+            self.send_control(ControlPacket::new(CONTROL_KEEPALIVE, 0, Vec::new()))?;
+        }
+        Ok(())
+    }
+}
+
+fn read_packets(shared: &Shared) {
+    let mut buffer = vec![0; MTU as usize];
+    let mut last_received = Instant::now();
+    while !shared.closed.load(Ordering::SeqCst) {
+        // Errors (e.g. the listener's port being unreachable) are only fatal
+        // if they go on for longer than the idle timeout
+        match shared.socket.recv(&mut buffer) {
+            Ok(length) => {
+                if let Some(packet) = ControlPacket::decode(&buffer[..length]) {
+                    last_received = Instant::now();
+                    let _ = shared.handle_packet(packet);
+                }
+            }
+            Err(error) if !is_timeout(&error) => std::thread::sleep(READ_TIMEOUT),
+            Err(_) => {}
+        }
+        let _ = shared.maintain();
+
+        let mut state = shared.state.lock().unwrap();
+        if state.error.is_none() && last_received.elapsed() >= PEER_IDLE_TIMEOUT {
+            state.error = Some("The SRT server stopped responding!");
+        }
+        if state.error.is_some() {
+            break;
+        }
+    }
+}
+
+// Sends the handshake until the listener answers with one of its own
+fn exchange_handshake(
+    socket: &UdpSocket,
+    handshake: &Handshake,
+    start_time: Instant,
+) -> io::Result<Handshake> {
+    let mut packet = ControlPacket::new(CONTROL_HANDSHAKE, 0, handshake.encode());
+    let deadline = Instant::now() + CONNECT_TIMEOUT;
+    let mut buffer = vec![0; MTU as usize];
+    while Instant::now() < deadline {
+        packet.timestamp = start_time.elapsed().as_micros() as u32;
+        socket.send(&packet.encode())?;
+        let retry_time = Instant::now() + HANDSHAKE_RETRY_INTERVAL;
+        while Instant::now() < retry_time {
+            let length = match socket.recv(&mut buffer) {
+                Ok(length) => length,
+                Err(error) => {
+                    // Don't hammer a port that isn't open yet
+                    if !is_timeout(&error) {
+                        std::thread::sleep(retry_time.saturating_duration_since(Instant::now()));
+                    }
+                    break;
+                }
+            };
+            let response = ControlPacket::decode(&buffer[..length])
+                .filter(|response| response.control_type == CONTROL_HANDSHAKE)
+                .and_then(|response| Handshake::decode(&response.contents));
+            // Repeated answers to the induction can arrive after we've moved on
+            match response {
+                Some(response)
+                    if handshake.handshake_type == HANDSHAKE_INDUCTION
+                        || response.handshake_type != HANDSHAKE_INDUCTION =>
+                {
+                    return Ok(response)
+                }
+                _ => {}
+            }
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        "The SRT server didn't respond! Make sure it's listening for callers.",
+    ))
+}
+
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+// Socket ids and sequence numbers should be hard to guess, std doesn't have a
+// random number generator but it does seed its hashers randomly
+fn random_u32() -> u32 {
+    let mut hasher = RandomState::new().build_hasher();
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_nanos())
+        .unwrap_or_default();
+    hasher.write_u128(time);
+    hasher.finish() as u32
+}
@@ -0,0 +1,4 @@
+mod connection;
+mod packet;
+pub mod url;
+pub mod writer;
@@ -0,0 +1,286 @@
+// The SRT packet formats, see https://datatracker.ietf.org/doc/html/draft-sharabayko-srt
+// Everything is big endian, apart from the quirks noted below.
+
+use std::net::IpAddr;
+
+pub const HEADER_SIZE: usize = 16;
+
+pub const CONTROL_HANDSHAKE: u16 = 0;
+pub const CONTROL_KEEPALIVE: u16 = 1;
+pub const CONTROL_ACK: u16 = 2;
+pub const CONTROL_NAK: u16 = 3;
+pub const CONTROL_SHUTDOWN: u16 = 5;
+pub const CONTROL_ACKACK: u16 = 6;
+
+pub const HANDSHAKE_INDUCTION: u32 = 1;
+pub const HANDSHAKE_CONCLUSION: u32 = 0xFFFFFFFF;
+// Listeners reject a conclusion with a handshake type of 1000 + the reason
+pub const HANDSHAKE_REJECTION_BASE: u32 = 1000;
+pub const HANDSHAKE_MAGIC: u16 = 0x4A17;
+
+pub const EXTENSION_HSREQ: u16 = 1;
+pub const EXTENSION_STREAM_ID: u16 = 5;
+// The extension field flags of a conclusion handshake
+pub const EXTENSION_FLAG_HSREQ: u16 = 0x1;
+pub const EXTENSION_FLAG_CONFIG: u16 = 0x4;
+
+const SRT_VERSION: u32 = 0x010500;
+// TSBPDSND | TSBPDRCV | CRYPT | TLPKTDROP | PERIODICNAK | REXMITFLG
+const SRT_FLAGS: u32 = 0x3F;
+const HANDSHAKE_SIZE: usize = 48;
+// The packet is a single message, which is all the MPEG-TS payloads need
+const PACKET_POSITION_SOLO: u32 = 0b11 << 30;
+const RETRANSMITTED_FLAG: u32 = 1 << 26;
+const MESSAGE_NUMBER_MASK: u32 = 0x03FFFFFF;
+pub const SEQUENCE_NUMBER_MASK: u32 = 0x7FFFFFFF;
+
+pub struct ControlPacket {
+    pub control_type: u16,
+    pub type_information: u32,
+    pub timestamp: u32,
+    pub destination: u32,
+    pub contents: Vec<u8>,
+}
+
+pub struct Handshake {
+    pub version: u32,
+    pub encryption: u16,
+    pub extension_field: u16,
+    pub initial_sequence_number: u32,
+    pub mtu: u32,
+    pub flow_window: u32,
+    pub handshake_type: u32,
+    pub socket_id: u32,
+    pub cookie: u32,
+    pub peer_address: [u8; 16],
+    pub extensions: Vec<(u16, Vec<u8>)>,
+}
+
+impl ControlPacket {
+    pub fn new(control_type: u16, type_information: u32, contents: Vec<u8>) -> Self {
+        Self {
+            control_type,
+            type_information,
+            timestamp: 0,
+            destination: 0,
+            contents,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(HEADER_SIZE + self.contents.len());
+        packet.extend_from_slice(&(0x80000000 | (self.control_type as u32) << 16).to_be_bytes());
+        packet.extend_from_slice(&self.type_information.to_be_bytes());
+        packet.extend_from_slice(&self.timestamp.to_be_bytes());
+        packet.extend_from_slice(&self.destination.to_be_bytes());
+        packet.extend_from_slice(&self.contents);
+        packet
+    }
+
+    /// Returns None for data packets, which we never expect to receive.
+    pub fn decode(packet: &[u8]) -> Option<Self> {
+        if packet.len() < HEADER_SIZE || packet[0] & 0x80 == 0 {
+            return None;
+        }
+        Some(Self {
+            control_type: u16::from_be_bytes([packet[0], packet[1]]) & 0x7FFF,
+            type_information: read_u32(packet, 4),
+            timestamp: read_u32(packet, 8),
+            destination: read_u32(packet, 12),
+            contents: packet[HEADER_SIZE..].to_vec(),
+        })
+    }
+}
+
+impl Handshake {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut contents = Vec::with_capacity(HANDSHAKE_SIZE);
+        contents.extend_from_slice(&self.version.to_be_bytes());
+        contents.extend_from_slice(&self.encryption.to_be_bytes());
+        contents.extend_from_slice(&self.extension_field.to_be_bytes());
+        contents.extend_from_slice(&self.initial_sequence_number.to_be_bytes());
+        contents.extend_from_slice(&self.mtu.to_be_bytes());
+        contents.extend_from_slice(&self.flow_window.to_be_bytes());
+        contents.extend_from_slice(&self.handshake_type.to_be_bytes());
+        contents.extend_from_slice(&self.socket_id.to_be_bytes());
+        contents.extend_from_slice(&self.cookie.to_be_bytes());
+        contents.extend_from_slice(&self.peer_address);
+        for (extension_type, extension) in &self.extensions {
+            // The length is in 32-bit words
+            contents.extend_from_slice(&extension_type.to_be_bytes());
+            contents.extend_from_slice(&((extension.len() / 4) as u16).to_be_bytes());
+            contents.extend_from_slice(extension);
+        }
+        contents
+    }
+
+    pub fn decode(contents: &[u8]) -> Option<Self> {
+        if contents.len() < HANDSHAKE_SIZE {
+            return None;
+        }
+        let mut extensions = Vec::new();
+        let mut offset = HANDSHAKE_SIZE;
+        while offset + 4 <= contents.len() {
+            let extension_type = u16::from_be_bytes([contents[offset], contents[offset + 1]]);
+            let length = u16::from_be_bytes([contents[offset + 2], contents[offset + 3]]) as usize;
+            let extension = contents.get(offset + 4..offset + 4 + length * 4)?;
+            extensions.push((extension_type, extension.to_vec()));
+            offset += 4 + length * 4;
+        }
+        Some(Self {
+            version: read_u32(contents, 0),
+            encryption: u16::from_be_bytes([contents[4], contents[5]]),
+            extension_field: u16::from_be_bytes([contents[6], contents[7]]),
+            initial_sequence_number: read_u32(contents, 8),
+            mtu: read_u32(contents, 12),
+            flow_window: read_u32(contents, 16),
+            handshake_type: read_u32(contents, 20),
+            socket_id: read_u32(contents, 24),
+            cookie: read_u32(contents, 28),
+            peer_address: contents[32..48].try_into().unwrap(),
+            extensions,
+        })
+    }
+}
+
+pub fn encode_data_packet(
+    sequence_number: u32,
+    message_number: u32,
+    retransmitted: bool,
+    timestamp: u32,
+    destination: u32,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut flags = PACKET_POSITION_SOLO | (message_number & MESSAGE_NUMBER_MASK);
+    if retransmitted {
+        flags |= RETRANSMITTED_FLAG;
+    }
+    let mut packet = Vec::with_capacity(HEADER_SIZE + payload.len());
+    packet.extend_from_slice(&(sequence_number & SEQUENCE_NUMBER_MASK).to_be_bytes());
+    packet.extend_from_slice(&flags.to_be_bytes());
+    packet.extend_from_slice(&timestamp.to_be_bytes());
+    packet.extend_from_slice(&destination.to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// Asks the receiver to buffer packets for the latency, in milliseconds.
+pub fn create_hsreq_extension(latency: u16) -> (u16, Vec<u8>) {
+    let mut extension = Vec::new();
+    extension.extend_from_slice(&SRT_VERSION.to_be_bytes());
+    extension.extend_from_slice(&SRT_FLAGS.to_be_bytes());
+    // The receiver latency, then the sender latency
+    extension.extend_from_slice(&latency.to_be_bytes());
+    extension.extend_from_slice(&latency.to_be_bytes());
+    (EXTENSION_HSREQ, extension)
+}
+
+pub fn create_stream_id_extension(stream_id: &str) -> (u16, Vec<u8>) {
+    let mut extension = stream_id.as_bytes().to_vec();
+    extension.resize(extension.len().div_ceil(4) * 4, 0);
+    (EXTENSION_STREAM_ID, reverse_words(&extension))
+}
+
+/// The address of the peer as libsrt expects it.
+pub fn encode_address(address: IpAddr) -> [u8; 16] {
+    let mut bytes = [0; 16];
+    match address {
+        IpAddr::V4(address) => bytes[..4].copy_from_slice(&address.octets()),
+        IpAddr::V6(address) => bytes.copy_from_slice(&address.octets()),
+    }
+    reverse_words(&bytes).try_into().unwrap()
+}
+
+/// Lost sequence numbers come as (first, last) ranges, a single lost
+/// packet is a range of one.
+pub fn decode_loss_list(contents: &[u8]) -> Vec<(u32, u32)> {
+    let mut ranges = Vec::new();
+    let mut words = contents
+        .chunks_exact(4)
+        .map(|word| u32::from_be_bytes(word.try_into().unwrap()));
+    while let Some(word) = words.next() {
+        // The high bit marks the start of a range
+        if word & 0x80000000 != 0 {
+            let first = word & SEQUENCE_NUMBER_MASK;
+            let last = words.next().unwrap_or(first) & SEQUENCE_NUMBER_MASK;
+            ranges.push((first, last));
+        } else {
+            ranges.push((word, word));
+        }
+    }
+    ranges
+}
+
+/// How far b is ahead of a, taking into account that sequence numbers
+/// wrap around after 31 bits.
+pub fn sequence_offset(a: u32, b: u32) -> i32 {
+    let difference = b.wrapping_sub(a) & SEQUENCE_NUMBER_MASK;
+    if difference > SEQUENCE_NUMBER_MASK / 2 {
+        difference as i32 - (SEQUENCE_NUMBER_MASK as i32) - 1
+    } else {
+        difference as i32
+    }
+}
+
+// libsrt copies some fields as host order (little endian) 32-bit words
+fn reverse_words(bytes: &[u8]) -> Vec<u8> {
+    bytes
+        .chunks(4)
+        .flat_map(|word| word.iter().rev().copied())
+        .collect()
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        create_stream_id_extension, decode_loss_list, encode_data_packet, sequence_offset,
+        ControlPacket, CONTROL_ACK,
+    };
+
+    #[test]
+    fn packet_test() {
+        let packet = encode_data_packet(5, 1, true, 1000, 0x1234, &[0xAA]);
+        assert_eq!(
+            packet,
+            [0, 0, 0, 5, 0xC4, 0, 0, 1, 0, 0, 0x03, 0xE8, 0, 0, 0x12, 0x34, 0xAA]
+        );
+        assert!(ControlPacket::decode(&packet).is_none());
+
+        let packet = ControlPacket::new(CONTROL_ACK, 7, vec![0, 0, 0, 6]).encode();
+        assert_eq!(&packet[..8], &[0x80, 0x02, 0, 0, 0, 0, 0, 7]);
+        let packet = ControlPacket::decode(&packet).unwrap();
+        assert_eq!(packet.control_type, CONTROL_ACK);
+        assert_eq!(packet.type_information, 7);
+        assert_eq!(packet.contents, [0, 0, 0, 6]);
+    }
+
+    #[test]
+    fn extension_test() {
+        assert_eq!(
+            create_stream_id_extension("live/abc").1,
+            b"evilcba/".to_vec()
+        );
+        assert_eq!(
+            create_stream_id_extension("abcde").1,
+            b"dcba\0\0\0e".to_vec()
+        );
+    }
+
+    #[test]
+    fn loss_list_test() {
+        let contents = [0, 0, 0, 3, 0x80, 0, 0, 5, 0, 0, 0, 8];
+        assert_eq!(decode_loss_list(&contents), [(3, 3), (5, 8)]);
+    }
+
+    #[test]
+    fn sequence_offset_test() {
+        assert_eq!(sequence_offset(5, 8), 3);
+        assert_eq!(sequence_offset(8, 5), -3);
+        assert_eq!(sequence_offset(0x7FFFFFFF, 1), 2);
+        assert_eq!(sequence_offset(1, 0x7FFFFFFF), -2);
+    }
+}
@@ -0,0 +1,168 @@
+use std::{fmt::Display, str::FromStr, time::Duration};
+
+// The default of most SRT implementations
+const DEFAULT_LATENCY: Duration = Duration::from_millis(120);
+
+/// Where to send to: srt://host:port[?streamid=...&latency=ms]. Only caller
+/// mode is supported, so the host has to be listening for connections.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SrtUrl {
+    pub host: String,
+    pub port: u16,
+    pub stream_id: Option<String>,
+    /// How long the receiver buffers packets, which bounds how long lost
+    /// packets can be retransmitted for.
+    pub latency: Duration,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseSrtUrlError(&'static str);
+
+impl FromStr for SrtUrl {
+    type Err = ParseSrtUrlError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const ERROR: ParseSrtUrlError = ParseSrtUrlError(
+            "Invalid stream URL! Expecting srt://host:port[?streamid=...&latency=ms].",
+        );
+        const MODE_ERROR: ParseSrtUrlError = ParseSrtUrlError(
+            "Only the SRT caller mode is supported! Start the receiver in listener mode.",
+        );
+        const PASSPHRASE_ERROR: ParseSrtUrlError =
+            ParseSrtUrlError("Encrypted SRT streams aren't supported yet! Remove the passphrase.");
+        let s = s.trim();
+        let scheme_end = s.find("://").ok_or(ERROR)?;
+        if s[..scheme_end].to_lowercase() != "srt" {
+            return Err(ERROR);
+        }
+
+        let rest = &s[scheme_end + 3..];
+        let (authority, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let (host, port) = authority
+            .trim_end_matches('/')
+            .rsplit_once(':')
+            .ok_or(ERROR)?;
+        let port = port.parse().map_err(|_| ERROR)?;
+        if host.is_empty() {
+            return Err(ERROR);
+        }
+
+        let mut url = Self {
+            host: host.to_owned(),
+            port,
+            stream_id: None,
+            latency: DEFAULT_LATENCY,
+        };
+        for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+            let (name, value) = parameter.split_once('=').ok_or(ERROR)?;
+            let value = percent_decode(value).ok_or(ERROR)?;
+            match name.to_lowercase().as_str() {
+                "streamid" => url.stream_id = Some(value),
+                "latency" => {
+                    let latency = value.parse().map_err(|_| ERROR)?;
+                    url.latency = Duration::from_millis(latency);
+                }
+                "mode" if value != "caller" => return Err(MODE_ERROR),
+                "passphrase" => return Err(PASSPHRASE_ERROR),
+                // Anything else is meant for other implementations
+                _ => {}
+            }
+        }
+        Ok(url)
+    }
+}
+
+impl Display for SrtUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "srt://{}:{}?latency={}",
+            self.host,
+            self.port,
+            self.latency.as_millis()
+        )?;
+        if let Some(stream_id) = &self.stream_id {
+            write!(f, "&streamid={}", percent_encode(stream_id))?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for ParseSrtUrlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseSrtUrlError {}
+
+// Stream ids often use the access control syntax (e.g. #!::r=live,m=publish),
+// which has to be escaped in URLs
+fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = value.get(i + 1..i + 3)?;
+            result.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            result.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(result).ok()
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::SrtUrl;
+
+    #[test]
+    fn srt_url_parsing_test() {
+        let url: SrtUrl = "srt://ingest.example.com:9000".parse().unwrap();
+        assert_eq!(
+            url,
+            SrtUrl {
+                host: "ingest.example.com".to_owned(),
+                port: 9000,
+                stream_id: None,
+                latency: Duration::from_millis(120),
+            }
+        );
+
+        let url: SrtUrl = "SRT://10.0.0.2:9000?streamid=%23!%3A%3Ar%3Dlive,m%3Dpublish&latency=500"
+            .parse()
+            .unwrap();
+        assert_eq!(url.stream_id.as_deref(), Some("#!::r=live,m=publish"));
+        assert_eq!(url.latency, Duration::from_millis(500));
+        assert_eq!(url.to_string().parse::<SrtUrl>(), Ok(url));
+
+        assert!("srt://10.0.0.2:9000?mode=caller".parse::<SrtUrl>().is_ok());
+        assert!("srt://10.0.0.2:9000?mode=listener"
+            .parse::<SrtUrl>()
+            .is_err());
+        assert!("srt://10.0.0.2:9000?passphrase=secret"
+            .parse::<SrtUrl>()
+            .is_err());
+        assert!("srt://10.0.0.2".parse::<SrtUrl>().is_err());
+        assert!("srt://10.0.0.2:9000?latency=soon"
+            .parse::<SrtUrl>()
+            .is_err());
+        assert!("rtmp://10.0.0.2:9000".parse::<SrtUrl>().is_err());
+    }
+}
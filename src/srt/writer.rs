@@ -0,0 +1,164 @@
+use windows::{
+    core::{Error, Result},
+    Win32::Media::MediaFoundation::{
+        IMFMediaType, IMFSample, MFAudioFormat_PCM, MFMediaType_Audio, MFMediaType_Video,
+        MFSampleExtension_DecodeTimestamp, MFVideoFormat_H264, MF_E_INVALIDMEDIATYPE,
+        MF_MT_MAJOR_TYPE, MF_MT_SUBTYPE,
+    },
+};
+
+use crate::{
+    audio::aac_encoder::AacEncoder,
+    container::{mpeg_ts::TsMuxer, to_error, ContainerWriter},
+    media::{get_sample_data, is_key_frame},
+};
+
+use super::{
+    connection::{SrtConnection, MAX_PAYLOAD_SIZE},
+    url::SrtUrl,
+};
+
+/// Sends H.264 video and AAC audio to an SRT listener, multiplexed as MPEG-TS,
+/// instead of writing a file. Packets go out as soon as each sample is
+/// written, SRT takes care of retransmitting the ones that get lost.
+pub struct SrtWriter {
+    url: SrtUrl,
+    tracks: Vec<Track>,
+    muxer: Option<TsMuxer>,
+    connection: Option<SrtConnection>,
+    // Receivers can only start decoding at a key frame, anything before
+    // the first one is dropped
+    started: bool,
+}
+
+enum Track {
+    Video,
+    Audio { encoder: AacEncoder },
+}
+
+unsafe impl Send for SrtWriter {}
+impl SrtWriter {
+    pub fn new(url: SrtUrl) -> Self {
+        Self {
+            url,
+            tracks: Vec::new(),
+            muxer: None,
+            connection: None,
+            started: false,
+        }
+    }
+
+    fn send(&mut self, data: &[u8]) -> Result<()> {
+        let connection = self.connection.as_ref().unwrap();
+        for payload in data.chunks(MAX_PAYLOAD_SIZE) {
+            connection.send(payload).map_err(to_error)?;
+        }
+        Ok(())
+    }
+
+    fn write_video(&mut self, sample: &IMFSample) -> Result<()> {
+        let key_frame = is_key_frame(sample);
+        if !key_frame && !self.started {
+            return Ok(());
+        }
+        self.started = true;
+        let (time, decode_time) = unsafe {
+            let time = sample.GetSampleTime()?;
+            let decode_time = sample
+                .GetUINT64(&MFSampleExtension_DecodeTimestamp)
+                .map(|time| time as i64)
+                .unwrap_or(time);
+            (time, decode_time)
+        };
+        let data = get_sample_data(sample)?;
+        let packets = self
+            .muxer
+            .as_mut()
+            .unwrap()
+            .write_video(time, decode_time, key_frame, &data);
+        self.send(&packets)
+    }
+
+    fn write_audio(&mut self, stream_index: u32, sample: Option<&IMFSample>) -> Result<()> {
+        let frames = if let Track::Audio { encoder } = &mut self.tracks[stream_index as usize] {
+            let frames = if let Some(sample) = sample {
+                encoder.encode(sample)?
+            } else {
+                encoder.flush()?
+            };
+            frames
+                .into_iter()
+                .map(|frame| {
+                    let mut data = encoder.adts_header(frame.data.len()).to_vec();
+                    data.extend_from_slice(&frame.data);
+                    (frame.time, data)
+                })
+                .collect::<Vec<_>>()
+        } else {
+            return Ok(());
+        };
+        if !self.started {
+            return Ok(());
+        }
+
+        let mut packets = Vec::new();
+        for (time, data) in frames {
+            packets.extend(self.muxer.as_mut().unwrap().write_audio(time, &data));
+        }
+        self.send(&packets)
+    }
+}
+
+impl ContainerWriter for SrtWriter {
+    fn add_stream(&mut self, output_type: &IMFMediaType, input_type: &IMFMediaType) -> Result<u32> {
+        let track = unsafe {
+            let major_type = input_type.GetGUID(&MF_MT_MAJOR_TYPE)?;
+            let subtype = input_type.GetGUID(&MF_MT_SUBTYPE)?;
+            let has_audio = self
+                .tracks
+                .iter()
+                .any(|track| matches!(track, Track::Audio { .. }));
+            if major_type == MFMediaType_Video && subtype == MFVideoFormat_H264 {
+                Track::Video
+            } else if major_type == MFMediaType_Audio && subtype == MFAudioFormat_PCM && !has_audio
+            {
+                // The program only has room for a single audio stream
+                Track::Audio {
+                    encoder: AacEncoder::new(output_type, input_type)?,
+                }
+            } else {
+                return Err(Error::from(MF_E_INVALIDMEDIATYPE));
+            }
+        };
+        self.tracks.push(track);
+        Ok(self.tracks.len() as u32 - 1)
+    }
+
+    fn start(&mut self) -> Result<()> {
+        // Connecting up front means a bad URL or stream id is reported right away
+        self.connection = Some(SrtConnection::connect(&self.url).map_err(to_error)?);
+        let has_audio = self
+            .tracks
+            .iter()
+            .any(|track| matches!(track, Track::Audio { .. }));
+        self.muxer = Some(TsMuxer::new(has_audio));
+        Ok(())
+    }
+
+    fn write_sample(&mut self, stream_index: u32, sample: &IMFSample) -> Result<()> {
+        match self.tracks[stream_index as usize] {
+            Track::Video => self.write_video(sample),
+            Track::Audio { .. } => self.write_audio(stream_index, Some(sample)),
+        }
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        if self.connection.is_none() {
+            return Ok(());
+        }
+        for stream_index in 0..self.tracks.len() as u32 {
+            self.write_audio(stream_index, None)?;
+        }
+        self.connection.take().unwrap().close().map_err(to_error)
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+// Encode latencies are counted in buckets this wide, so that measuring them
+// takes the same memory however long the recording. Anything longer than
+// the last bucket counts as the last bucket.
+const LATENCY_BUCKET_WIDTH: Duration = Duration::from_micros(100);
+const LATENCY_BUCKETS: usize = 2500;
+
+/// A snapshot of how a recording is going, see RecordingSession::stats.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RecordingStats {
+    /// The number of frames that arrived from the capture while recording.
+    pub captured_frames: u64,
+    /// The number of video frames that have been encoded.
+    pub frames: u64,
+    /// The number of captured frames that weren't recorded because they
+    /// arrived faster than the maximum frame rate, or too late.
+    pub dropped_frames: u64,
+    /// The number of frames that arrived late, see FramePacing.
+    pub late_frames: u64,
+    /// The number of repeated frames that filled gaps, see FramePacing.
+    pub duplicated_frames: u64,
+    /// The size of all of the encoded video and audio.
+    pub bytes: u64,
+    /// How long the encoder took to turn frames into samples.
+    pub encode_latency: LatencyPercentiles,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LatencyPercentiles {
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+}
+
+impl RecordingStats {
+    /// The average bit rate (in bits per second) of a recording that has
+    /// been running for the elapsed time.
+    pub fn bit_rate(&self, elapsed: Duration) -> u64 {
+        if elapsed.is_zero() {
+            return 0;
+        }
+        (self.bytes as f64 * 8.0 / elapsed.as_secs_f64()) as u64
+    }
+
+    /// Sums up the counts of several recordings, e.g. one per display.
+    pub fn combine<'a, I: IntoIterator<Item = &'a StatsCounter>>(counters: I) -> Self {
+        let mut stats = Self::default();
+        let mut latencies = LatencyHistogram::default();
+        for counter in counters {
+            stats.captured_frames += counter.captured_frames.load(Ordering::Relaxed);
+            stats.frames += counter.frames.load(Ordering::Relaxed);
+            stats.dropped_frames += counter.dropped_frames.load(Ordering::Relaxed);
+            stats.late_frames += counter.late_frames.load(Ordering::Relaxed);
+            stats.duplicated_frames += counter.duplicated_frames.load(Ordering::Relaxed);
+            stats.bytes += counter.bytes.load(Ordering::Relaxed);
+            latencies.add_all(&counter.latencies.lock().unwrap().encoded);
+        }
+        stats.encode_latency = LatencyPercentiles::new(&latencies);
+        stats
+    }
+}
+
+impl LatencyPercentiles {
+    fn new(latencies: &LatencyHistogram) -> Self {
+        Self {
+            p50: latencies.percentile(50),
+            p95: latencies.percentile(95),
+            p99: latencies.percentile(99),
+        }
+    }
+}
+
+// How many latencies fell into each bucket
+#[derive(Clone)]
+struct LatencyHistogram {
+    counts: Vec<u64>,
+}
+
+impl LatencyHistogram {
+    fn add(&mut self, latency: Duration) {
+        let bucket = latency.as_nanos() / LATENCY_BUCKET_WIDTH.as_nanos();
+        self.counts[(bucket as usize).min(LATENCY_BUCKETS - 1)] += 1;
+    }
+
+    fn add_all(&mut self, other: &LatencyHistogram) {
+        for (count, other_count) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other_count;
+        }
+    }
+
+    // Uses the nearest rank, rounded down to the start of its bucket
+    fn percentile(&self, percentile: u64) -> Duration {
+        let total: u64 = self.counts.iter().sum();
+        if total == 0 {
+            return Duration::ZERO;
+        }
+        let rank = (percentile * total).div_ceil(100).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return LATENCY_BUCKET_WIDTH * bucket as u32;
+            }
+        }
+        unreachable!()
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; LATENCY_BUCKETS],
+        }
+    }
+}
+
+/// Counts what goes through the pipelines of a recording. Clones share
+/// the same counts, so that they can be updated from any thread.
+#[derive(Clone, Default)]
+pub struct StatsCounter {
+    captured_frames: Arc<AtomicU64>,
+    frames: Arc<AtomicU64>,
+    dropped_frames: Arc<AtomicU64>,
+    late_frames: Arc<AtomicU64>,
+    duplicated_frames: Arc<AtomicU64>,
+    bytes: Arc<AtomicU64>,
+    latencies: Arc<Mutex<Latencies>>,
+}
+
+#[derive(Default)]
+struct Latencies {
+    // When each frame still in the encoder was submitted, by timestamp
+    pending: HashMap<i64, Instant>,
+    encoded: LatencyHistogram,
+}
+
+impl StatsCounter {
+    pub fn add_captured_frame(&self) {
+        self.captured_frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_frame(&self, size: u64) {
+        self.frames.fetch_add(1, Ordering::Relaxed);
+        self.add_bytes(size);
+    }
+
+    pub fn add_bytes(&self, size: u64) {
+        self.bytes.fetch_add(size, Ordering::Relaxed);
+    }
+
+    pub fn add_dropped_frame(&self) {
+        self.dropped_frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_late_frame(&self) {
+        self.late_frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_duplicated_frames(&self, count: u64) {
+        self.duplicated_frames.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Call when the frame with the timestamp (in 100ns units) is handed to
+    /// the encoder, and again with the same timestamp once it's encoded.
+    pub fn start_encode(&self, timestamp: i64) {
+        let mut latencies = self.latencies.lock().unwrap();
+        latencies.pending.insert(timestamp, Instant::now());
+    }
+
+    pub fn finish_encode(&self, timestamp: i64) {
+        let mut latencies = self.latencies.lock().unwrap();
+        if let Some(start) = latencies.pending.remove(&timestamp) {
+            latencies.encoded.add(start.elapsed());
+        }
+    }
+
+    pub fn stats(&self) -> RecordingStats {
+        RecordingStats::combine([self])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{LatencyHistogram, RecordingStats, StatsCounter};
+
+    #[test]
+    fn stats_test() {
+        let counter = StatsCounter::default();
+        let shared = counter.clone();
+        shared.add_captured_frame();
+        shared.add_captured_frame();
+        shared.add_captured_frame();
+        shared.add_frame(100_000);
+        shared.add_frame(25_000);
+        shared.add_bytes(125_000);
+        counter.add_dropped_frame();
+        counter.add_late_frame();
+        counter.add_duplicated_frames(2);
+        counter.start_encode(0);
+        counter.finish_encode(0);
+        // Frames are only measured once
+        counter.finish_encode(0);
+
+        let stats = counter.stats();
+        assert_eq!(stats.captured_frames, 3);
+        assert_eq!(stats.frames, 2);
+        assert_eq!(stats.dropped_frames, 1);
+        assert_eq!(stats.late_frames, 1);
+        assert_eq!(stats.duplicated_frames, 2);
+        assert_eq!(stats.bytes, 250_000);
+        assert_eq!(stats.bit_rate(Duration::from_secs(2)), 1_000_000);
+        assert_eq!(stats.bit_rate(Duration::ZERO), 0);
+        assert!(stats.encode_latency.p99 >= stats.encode_latency.p50);
+
+        let combined = RecordingStats::combine([&counter, &shared]);
+        assert_eq!(combined.frames, 4);
+        assert_eq!(combined.bytes, 500_000);
+        assert_eq!(combined.duplicated_frames, 4);
+    }
+
+    #[test]
+    fn percentile_test() {
+        let mut latencies = LatencyHistogram::default();
+        assert_eq!(latencies.percentile(50), Duration::ZERO);
+        latencies.add(Duration::from_millis(1));
+        assert_eq!(latencies.percentile(99), Duration::from_millis(1));
+        for latency in 2..=200 {
+            latencies.add(Duration::from_millis(latency));
+        }
+        assert_eq!(latencies.percentile(50), Duration::from_millis(100));
+        assert_eq!(latencies.percentile(95), Duration::from_millis(190));
+        assert_eq!(latencies.percentile(99), Duration::from_millis(198));
+
+        // Within a bucket, and past the last one
+        let mut latencies = LatencyHistogram::default();
+        latencies.add(Duration::from_micros(1_234));
+        assert_eq!(latencies.percentile(50), Duration::from_micros(1_200));
+        latencies.add(Duration::from_secs(10));
+        assert_eq!(latencies.percentile(99), Duration::from_micros(249_900));
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    io::{IsTerminal, Write},
+    time::Duration,
+};
+
+use displayrecorder::RecordingSession;
+
+use crate::get_countdown_seconds;
+
+/// Keeps a single console line up to date with how the recording is going,
+/// so that it's clear that it's progressing. The frame rate, bit rate, and
+/// audio levels are measured since the previous update. Nothing is shown if the output isn't
+/// a console (e.g. it's redirected to a file).
+pub struct StatusLine {
+    enabled: bool,
+    // The length of the line that's currently shown, if any
+    width: Option<usize>,
+    // The elapsed time, frame count, and byte count of the previous update
+    previous: Option<(Duration, u64, u64)>,
+}
+
+impl StatusLine {
+    pub fn new() -> Self {
+        Self {
+            enabled: std::io::stdout().is_terminal(),
+            width: None,
+            previous: None,
+        }
+    }
+
+    /// Also shows the time left if there's a maximum duration.
+    pub fn update(&mut self, session: &RecordingSession, remaining: Option<Duration>) {
+        let elapsed = session.elapsed();
+        let stats = session.stats();
+        let (frame_rate, bit_rate) = match self.previous {
+            // Nothing is recorded while paused
+            Some((previous_elapsed, previous_frames, previous_bytes))
+                if elapsed > previous_elapsed =>
+            {
+                let seconds = (elapsed - previous_elapsed).as_secs_f64();
+                (
+                    stats.frames.saturating_sub(previous_frames) as f64 / seconds,
+                    stats.bytes.saturating_sub(previous_bytes) as f64 * 8.0 / seconds,
+                )
+            }
+            _ => (0.0, 0.0),
+        };
+        self.previous = Some((elapsed, stats.frames, stats.bytes));
+        if !self.enabled {
+            return;
+        }
+
+        let mut line = format_status(
+            session.is_paused(),
+            elapsed,
+            frame_rate,
+            bit_rate,
+            stats.dropped_frames,
+            stats.late_frames,
+            remaining,
+        );
+        line.push_str(&format_audio_levels(&session.take_audio_levels()));
+        // Pad over whatever is left of a longer previous line
+        let padding = self.width.unwrap_or(0).saturating_sub(line.len());
+        print!("\r{}{}", line, " ".repeat(padding));
+        let _ = std::io::stdout().flush();
+        self.width = Some(line.len());
+    }
+
+    /// Removes the line, so that other messages can be printed.
+    pub fn clear(&mut self) {
+        if let Some(width) = self.width.take() {
+            print!("\r{}\r", " ".repeat(width));
+            let _ = std::io::stdout().flush();
+        }
+    }
+}
+
+fn format_status(
+    paused: bool,
+    elapsed: Duration,
+    frame_rate: f64,
+    bit_rate: f64,
+    dropped_frames: u64,
+    late_frames: u64,
+    remaining: Option<Duration>,
+) -> String {
+    let seconds = elapsed.as_secs();
+    let mut line = format!(
+        "{} {}:{:02}:{:02} | {:.1} fps | {:.1} Mbps | {} dropped",
+        if paused { "Paused" } else { "Recording" },
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60,
+        frame_rate,
+        bit_rate / 1_000_000.0,
+        dropped_frames
+    );
+    // Only worth mentioning when the encoder is falling behind
+    if late_frames > 0 {
+        line.push_str(&format!(" | {} late", late_frames));
+    }
+    if let Some(remaining) = remaining {
+        line.push_str(&format!(
+            " | stopping in {}s",
+            get_countdown_seconds(remaining)
+        ));
+    }
+    line
+}
+
+// One level per audio source, nothing without audio
+fn format_audio_levels(audio_levels: &[f32]) -> String {
+    if audio_levels.is_empty() {
+        return String::new();
+    }
+    let levels: Vec<_> = audio_levels
+        .iter()
+        .map(|level| format!("{:.0} dB", level))
+        .collect();
+    format!(" | audio {}", levels.join(" / "))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{format_audio_levels, format_status};
+
+    #[test]
+    fn status_formatting_test() {
+        assert_eq!(
+            format_status(
+                false,
+                Duration::from_millis(3_723_500),
+                59.94,
+                17_640_000.0,
+                2,
+                0,
+                None
+            ),
+            "Recording 1:02:03 | 59.9 fps | 17.6 Mbps | 2 dropped"
+        );
+        assert_eq!(
+            format_status(
+                true,
+                Duration::from_secs(83),
+                0.0,
+                0.0,
+                0,
+                5,
+                Some(Duration::from_millis(36_200))
+            ),
+            "Paused 0:01:23 | 0.0 fps | 0.0 Mbps | 0 dropped | 5 late | stopping in 37s"
+        );
+        assert_eq!(
+            format_audio_levels(&[-12.4, -60.0]),
+            " | audio -12 dB / -60 dB"
+        );
+        assert_eq!(format_audio_levels(&[]), "");
+    }
+}
@@ -0,0 +1,96 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::{
+    rtmp::url::{ParseRtmpUrlError, RtmpUrl},
+    srt::url::{ParseSrtUrlError, SrtUrl},
+};
+
+/// Where to stream the recording to, picked by the scheme of the URL.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StreamUrl {
+    Rtmp(RtmpUrl),
+    Srt(SrtUrl),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ParseStreamUrlError {
+    Scheme,
+    Rtmp(ParseRtmpUrlError),
+    Srt(ParseSrtUrlError),
+}
+
+impl StreamUrl {
+    /// The server part of the URL, leaving out the stream key or id, which
+    /// shouldn't end up in logs.
+    pub fn server(&self) -> String {
+        match self {
+            StreamUrl::Rtmp(url) => url.tc_url(),
+            StreamUrl::Srt(url) => format!("srt://{}:{}", url.host, url.port),
+        }
+    }
+}
+
+impl FromStr for StreamUrl {
+    type Err = ParseStreamUrlError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let scheme = s
+            .trim()
+            .split_once("://")
+            .map(|(scheme, _)| scheme.to_lowercase());
+        match scheme.as_deref() {
+            Some("rtmp") | Some("rtmps") => s
+                .parse()
+                .map(StreamUrl::Rtmp)
+                .map_err(ParseStreamUrlError::Rtmp),
+            Some("srt") => s
+                .parse()
+                .map(StreamUrl::Srt)
+                .map_err(ParseStreamUrlError::Srt),
+            _ => Err(ParseStreamUrlError::Scheme),
+        }
+    }
+}
+
+impl Display for StreamUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreamUrl::Rtmp(url) => url.fmt(f),
+            StreamUrl::Srt(url) => url.fmt(f),
+        }
+    }
+}
+
+impl Display for ParseStreamUrlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseStreamUrlError::Scheme => {
+                write!(f, "Invalid stream URL! Expecting an rtmp:// or srt:// URL.")
+            }
+            ParseStreamUrlError::Rtmp(error) => error.fmt(f),
+            ParseStreamUrlError::Srt(error) => error.fmt(f),
+        }
+    }
+}
+impl std::error::Error for ParseStreamUrlError {}
+
+#[cfg(test)]
+mod tests {
+    use super::StreamUrl;
+
+    #[test]
+    fn stream_url_parsing_test() {
+        assert!(matches!(
+            "rtmp://live.example.com/app/key".parse(),
+            Ok(StreamUrl::Rtmp(_))
+        ));
+        assert!(matches!(
+            "SRT://10.0.0.2:9000".parse(),
+            Ok(StreamUrl::Srt(_))
+        ));
+        assert!("rtmps://live.example.com/app/key"
+            .parse::<StreamUrl>()
+            .is_err());
+        assert!("udp://10.0.0.2:9000".parse::<StreamUrl>().is_err());
+    }
+}
@@ -0,0 +1,224 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use windows::{
+    core::{ComInterface, Result},
+    Foundation::TimeSpan,
+    Graphics::{
+        Capture::{GraphicsCaptureItem, GraphicsCaptureSession},
+        SizeInt32,
+    },
+    Win32::{
+        Foundation::RECT,
+        Graphics::{
+            Direct3D11::{
+                ID3D11Device, ID3D11DeviceContext, ID3D11DeviceContext1, ID3D11RenderTargetView,
+                ID3D11Texture2D,
+            },
+            Dxgi::Common::DXGI_FORMAT,
+        },
+    },
+};
+
+use crate::{
+    capture::{CaptureFrameWait, CaptureStopHandle, CapturedFrame, FrameSource},
+    timeline::get_system_relative_time,
+    video::encoding_session::create_compose_texture,
+};
+
+// How long the background takes to go through all of the hues, and the bar
+// to sweep across the frame (in seconds)
+const HUE_PERIOD: f32 = 4.0;
+const BAR_PERIOD: f32 = 2.0;
+
+/// Generates an animated test pattern in place of a capture, so that a
+/// recording can be made (e.g. in tests) without anything to capture. Every
+/// frame differs, and the same frames come out every time.
+pub struct TestPatternSource {
+    d3d_context: ID3D11DeviceContext1,
+    texture: ID3D11Texture2D,
+    render_target_view: ID3D11RenderTargetView,
+    size: SizeInt32,
+    format: DXGI_FORMAT,
+    frame_rate: u32,
+    frame_count: Option<u64>,
+    frame_index: u64,
+    // When the first frame was asked for
+    start_time: Option<i64>,
+    real_time: bool,
+    stopped: Arc<AtomicBool>,
+}
+
+unsafe impl Send for TestPatternSource {}
+impl TestPatternSource {
+    /// Frames are timestamped at the frame rate, from when the first one is
+    /// asked for, and rendered as soon as they're asked for (see real_time).
+    /// The source stops after the frame count, if there is one.
+    pub fn new(
+        d3d_device: &ID3D11Device,
+        size: SizeInt32,
+        format: DXGI_FORMAT,
+        frame_rate: u32,
+        frame_count: Option<u64>,
+    ) -> Result<Self> {
+        let d3d_context: ID3D11DeviceContext = unsafe { d3d_device.GetImmediateContext()? };
+        let (texture, render_target_view) = create_compose_texture(d3d_device, size, format)?;
+        Ok(Self {
+            d3d_context: d3d_context.cast()?,
+            texture,
+            render_target_view,
+            size,
+            format,
+            frame_rate: frame_rate.max(1),
+            frame_count,
+            frame_index: 0,
+            start_time: None,
+            real_time: false,
+            stopped: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Holds each frame back until its time comes, like a capture would,
+    /// rather than making the recording as fast as the encoder goes.
+    pub fn real_time(mut self, real_time: bool) -> Self {
+        self.real_time = real_time;
+        self
+    }
+}
+
+impl FrameSource for TestPatternSource {
+    // The texture is drawn over for the next frame, after the GPU is done
+    // with what was submitted for this one
+    fn wait_for_frame(&mut self, timeout: Duration) -> Result<CaptureFrameWait<CapturedFrame>> {
+        if self.stopped.load(Ordering::SeqCst) || Some(self.frame_index) == self.frame_count {
+            return Ok(CaptureFrameWait::Stopped);
+        }
+        let start_time = *self.start_time.get_or_insert_with(get_system_relative_time);
+        let frame_duration = 10_000_000 / self.frame_rate as i64;
+        let time = TimeSpan {
+            Duration: start_time + self.frame_index as i64 * frame_duration,
+        };
+        if self.real_time {
+            let wait = Duration::from_nanos(
+                (time.Duration - get_system_relative_time()).max(0) as u64 * 100,
+            );
+            std::thread::sleep(wait.min(timeout));
+            if wait > timeout {
+                return Ok(CaptureFrameWait::Timeout);
+            }
+        }
+        draw_test_pattern(
+            &self.d3d_context,
+            &self.render_target_view,
+            self.frame_index,
+            self.frame_rate,
+            self.size,
+        );
+        self.frame_index += 1;
+        Ok(CaptureFrameWait::Frame(
+            0,
+            CapturedFrame::new(self.texture.clone(), self.size, time),
+        ))
+    }
+
+    fn stop_handle(&self) -> CaptureStopHandle {
+        let stopped = self.stopped.clone();
+        CaptureStopHandle::new(move || stopped.store(true, Ordering::SeqCst))
+    }
+
+    fn stop_capture(&mut self) -> Result<()> {
+        self.stopped.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn reset_device(&mut self, d3d_device: ID3D11Device) -> Result<()> {
+        let d3d_context: ID3D11DeviceContext = unsafe { d3d_device.GetImmediateContext()? };
+        let (texture, render_target_view) =
+            create_compose_texture(&d3d_device, self.size, self.format)?;
+        self.d3d_context = d3d_context.cast()?;
+        self.texture = texture;
+        self.render_target_view = render_target_view;
+        Ok(())
+    }
+
+    // There are no items to close
+    fn replace_item(&mut self, _index: usize, _item: GraphicsCaptureItem) -> Result<()> {
+        Ok(())
+    }
+
+    fn sessions(&self) -> Vec<GraphicsCaptureSession> {
+        Vec::new()
+    }
+}
+
+/// Draws a frame of the test pattern: a background that cycles through the
+/// hues, with a bar sweeping across it.
+pub fn draw_test_pattern(
+    d3d_context: &ID3D11DeviceContext1,
+    render_target_view: &ID3D11RenderTargetView,
+    frame_index: u64,
+    frame_rate: u32,
+    size: SizeInt32,
+) {
+    let (background, bar) = get_pattern(frame_index, frame_rate, size);
+    unsafe {
+        d3d_context.ClearRenderTargetView(render_target_view, &background);
+        d3d_context.ClearView(render_target_view, &[1.0; 4], Some(&[bar]));
+    }
+}
+
+// The background color and where the bar is in the frame
+fn get_pattern(frame_index: u64, frame_rate: u32, size: SizeInt32) -> ([f32; 4], RECT) {
+    let seconds = frame_index as f32 / frame_rate as f32;
+    let hue = (seconds / HUE_PERIOD).fract() * 6.0;
+    let channel = |offset: f32| (2.0 - (hue - offset).abs()).clamp(0.0, 1.0);
+    let background = [
+        ((hue - 3.0).abs() - 1.0).clamp(0.0, 1.0),
+        channel(2.0),
+        channel(4.0),
+        1.0,
+    ];
+
+    let bar_width = (size.Width / 10).max(1);
+    let left = ((seconds / BAR_PERIOD).fract() * (size.Width - bar_width) as f32) as i32;
+    let bar = RECT {
+        left,
+        top: 0,
+        right: left + bar_width,
+        bottom: size.Height,
+    };
+    (background, bar)
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::{Graphics::SizeInt32, Win32::Foundation::RECT};
+
+    use super::get_pattern;
+
+    #[test]
+    fn test_pattern_test() {
+        let size = SizeInt32 {
+            Width: 1920,
+            Height: 1080,
+        };
+        let bar = |left| RECT {
+            left,
+            top: 0,
+            right: left + 192,
+            bottom: 1080,
+        };
+
+        // Starts red, with the bar on the left
+        assert_eq!(get_pattern(0, 60, size), ([1.0, 0.0, 0.0, 1.0], bar(0)));
+        // A second in, it's between yellow and green, with the bar halfway
+        assert_eq!(get_pattern(60, 60, size), ([0.5, 1.0, 0.0, 1.0], bar(864)));
+        // Both start over
+        assert_eq!(get_pattern(240, 60, size), get_pattern(0, 60, size));
+    }
+}
@@ -0,0 +1,163 @@
+use std::sync::{
+    atomic::{AtomicI64, Ordering},
+    Arc,
+};
+
+use windows::Win32::System::Performance::{QueryPerformanceCounter, QueryPerformanceFrequency};
+
+// Sentinel used before the timeline has been started
+const NOT_STARTED: i64 = i64::MIN;
+// Sentinel used while the timeline isn't paused
+const NOT_PAUSED: i64 = i64::MIN;
+
+/// A timeline shared between the audio and video pipelines. Both capture
+/// APIs report timestamps derived from the QPC in 100ns units, so converting
+/// them relative to the same start time keeps the streams in sync.
+/// Time spent paused is removed from the timeline.
+#[derive(Clone)]
+pub struct Timeline {
+    start_time: Arc<AtomicI64>,
+    pause_time: Arc<AtomicI64>,
+    paused_duration: Arc<AtomicI64>,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn start(&self) {
+        self.start_time
+            .store(get_system_relative_time(), Ordering::SeqCst);
+    }
+
+    pub fn pause(&self) {
+        let _ = self.pause_time.compare_exchange(
+            NOT_PAUSED,
+            get_system_relative_time(),
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        );
+    }
+
+    pub fn resume(&self) {
+        let pause_time = self.pause_time.load(Ordering::SeqCst);
+        if pause_time != NOT_PAUSED {
+            self.paused_duration
+                .fetch_add(get_system_relative_time() - pause_time, Ordering::SeqCst);
+            self.pause_time.store(NOT_PAUSED, Ordering::SeqCst);
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.pause_time.load(Ordering::SeqCst) != NOT_PAUSED
+    }
+
+    /// How long the timeline has been running (in 100ns units), not including
+    /// the time spent paused.
+    pub fn elapsed(&self) -> i64 {
+        let start_time = self.start_time.load(Ordering::SeqCst);
+        if start_time == NOT_STARTED {
+            return 0;
+        }
+        let pause_time = self.pause_time.load(Ordering::SeqCst);
+        let now = if pause_time != NOT_PAUSED {
+            pause_time
+        } else {
+            get_system_relative_time()
+        };
+        let paused_duration = self.paused_duration.load(Ordering::SeqCst);
+        compute_relative_time(now, start_time, paused_duration)
+    }
+
+    /// Whether a QPC based timestamp (in 100ns units) is from before the
+    /// timeline was started, which it can't be if it hasn't been yet.
+    pub fn is_before_start(&self, system_relative_time: i64) -> bool {
+        let start_time = self.start_time.load(Ordering::SeqCst);
+        start_time != NOT_STARTED && system_relative_time < start_time
+    }
+
+    /// Converts a QPC based timestamp (in 100ns units) to a time relative
+    /// to the start of the timeline. Returns None if the timeline hasn't
+    /// been started yet or is paused. Timestamps from before the start are
+    /// clamped to 0.
+    pub fn relative_time(&self, system_relative_time: i64) -> Option<i64> {
+        let start_time = self.start_time.load(Ordering::SeqCst);
+        if start_time == NOT_STARTED || self.is_paused() {
+            None
+        } else {
+            let paused_duration = self.paused_duration.load(Ordering::SeqCst);
+            Some(compute_relative_time(
+                system_relative_time,
+                start_time,
+                paused_duration,
+            ))
+        }
+    }
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Self {
+            start_time: Arc::new(AtomicI64::new(NOT_STARTED)),
+            pause_time: Arc::new(AtomicI64::new(NOT_PAUSED)),
+            paused_duration: Arc::new(AtomicI64::new(0)),
+        }
+    }
+}
+
+fn compute_relative_time(system_relative_time: i64, start_time: i64, paused_duration: i64) -> i64 {
+    (system_relative_time - start_time - paused_duration).max(0)
+}
+
+/// Returns the current QPC value in 100ns units, the same time base used
+/// by Direct3D11CaptureFrame::SystemRelativeTime and WASAPI QPC positions.
+pub fn get_system_relative_time() -> i64 {
+    let (counter, frequency) = unsafe {
+        let mut counter = 0;
+        let mut frequency = 0;
+        QueryPerformanceCounter(&mut counter).unwrap();
+        QueryPerformanceFrequency(&mut frequency).unwrap();
+        (counter, frequency)
+    };
+    qpc_to_hundred_nanoseconds(counter, frequency)
+}
+
+fn qpc_to_hundred_nanoseconds(counter: i64, frequency: i64) -> i64 {
+    ((counter as i128 * 10_000_000) / frequency as i128) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compute_relative_time, qpc_to_hundred_nanoseconds, Timeline};
+
+    #[test]
+    fn qpc_conversion_test() {
+        assert_eq!(
+            qpc_to_hundred_nanoseconds(10_000_000, 10_000_000),
+            10_000_000
+        );
+        assert_eq!(qpc_to_hundred_nanoseconds(3_000_000, 3_000_000), 10_000_000);
+        assert_eq!(qpc_to_hundred_nanoseconds(1, 10_000_000), 1);
+        // Large counter values shouldn't overflow
+        assert_eq!(
+            qpc_to_hundred_nanoseconds(i64::MAX / 2, 10_000_000),
+            i64::MAX / 2
+        );
+    }
+
+    #[test]
+    fn unstarted_timeline_test() {
+        let timeline = Timeline::new();
+        assert_eq!(timeline.relative_time(1234), None);
+        assert!(!timeline.is_before_start(1234));
+    }
+
+    #[test]
+    fn paused_time_test() {
+        assert_eq!(compute_relative_time(500, 100, 0), 400);
+        // Time spent paused is skipped
+        assert_eq!(compute_relative_time(500, 100, 150), 250);
+        assert_eq!(compute_relative_time(500, 100, 1000), 0);
+    }
+}
@@ -0,0 +1,56 @@
+use std::{fmt::Display, str::FromStr};
+
+/// The number of bits used for each color component of the encoded video.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BitDepth {
+    Eight,
+    /// Reduces banding in gradients, but requires HEVC.
+    Ten,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseBitDepthError(&'static str);
+
+impl FromStr for BitDepth {
+    type Err = ParseBitDepthError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "8" => Ok(BitDepth::Eight),
+            "10" => Ok(BitDepth::Ten),
+            _ => Err(ParseBitDepthError(
+                "Invalid bit depth value! Expecting: 8 or 10.",
+            )),
+        }
+    }
+}
+
+impl Display for BitDepth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = match self {
+            BitDepth::Eight => "8",
+            BitDepth::Ten => "10",
+        };
+        write!(f, "{}", string)
+    }
+}
+
+impl Display for ParseBitDepthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseBitDepthError {}
+
+#[cfg(test)]
+mod tests {
+    use super::BitDepth;
+
+    #[test]
+    fn bit_depth_parsing_test() {
+        assert_eq!("8".parse(), Ok(BitDepth::Eight));
+        assert_eq!("10".parse(), Ok(BitDepth::Ten));
+        assert!("12".parse::<BitDepth>().is_err());
+        assert_eq!(BitDepth::Ten.to_string(), "10");
+    }
+}
@@ -0,0 +1,199 @@
+use windows::{
+    core::Result,
+    Graphics::{Capture::GraphicsCaptureItem, PointInt32, RectInt32, SizeInt32},
+    Win32::Graphics::Gdi::HMONITOR,
+};
+
+use crate::displays::get_display_device_name;
+
+use super::canvas_layout::CanvasLayout;
+
+/// A capture item and where its frames are placed within the recording.
+pub struct CanvasItem {
+    pub item: GraphicsCaptureItem,
+    pub position: PointInt32,
+    /// The name of the display the item captures (e.g. \\.\DISPLAY1), so
+    /// that it can be captured again if it comes back after going away.
+    pub display: Option<String>,
+}
+
+impl CanvasItem {
+    pub fn new(item: GraphicsCaptureItem) -> Self {
+        Self {
+            item,
+            position: PointInt32 { X: 0, Y: 0 },
+            display: None,
+        }
+    }
+
+    pub fn with_display(mut self, display_handle: HMONITOR) -> Self {
+        self.display = get_display_device_name(display_handle);
+        self
+    }
+
+    /// Places items based on their bounds (e.g. the layout of the virtual
+    /// desktop), so that the top left most item starts at the origin. Other
+    /// layouts only use the size of the bounds, and keep the items in order.
+    pub fn arrange(
+        items: Vec<(GraphicsCaptureItem, RectInt32)>,
+        layout: CanvasLayout,
+    ) -> Vec<Self> {
+        let bounds: Vec<_> = items.iter().map(|(_, bounds)| *bounds).collect();
+        let positions = match layout {
+            CanvasLayout::Desktop => arrange_on_canvas(&bounds),
+            _ => lay_out_on_canvas(&bounds, layout),
+        };
+        items
+            .into_iter()
+            .zip(positions)
+            .map(|((item, _), position)| Self {
+                item,
+                position,
+                display: None,
+            })
+            .collect()
+    }
+}
+
+pub fn get_canvas_size(items: &[CanvasItem]) -> Result<SizeInt32> {
+    let mut bounds = Vec::new();
+    for canvas_item in items {
+        let size = canvas_item.item.Size()?;
+        bounds.push(RectInt32 {
+            X: canvas_item.position.X,
+            Y: canvas_item.position.Y,
+            Width: size.Width,
+            Height: size.Height,
+        });
+    }
+    Ok(compute_canvas_size(&bounds))
+}
+
+fn arrange_on_canvas(bounds: &[RectInt32]) -> Vec<PointInt32> {
+    let left = bounds.iter().map(|rect| rect.X).min().unwrap_or(0);
+    let top = bounds.iter().map(|rect| rect.Y).min().unwrap_or(0);
+    bounds
+        .iter()
+        .map(|rect| PointInt32 {
+            X: rect.X - left,
+            Y: rect.Y - top,
+        })
+        .collect()
+}
+
+// Grids have as many columns as rows (or one more), with cells that fit the
+// largest item
+fn lay_out_on_canvas(bounds: &[RectInt32], layout: CanvasLayout) -> Vec<PointInt32> {
+    let cell_width = bounds.iter().map(|rect| rect.Width).max().unwrap_or(0);
+    let cell_height = bounds.iter().map(|rect| rect.Height).max().unwrap_or(0);
+    let columns = (bounds.len() as f64).sqrt().ceil().max(1.0) as usize;
+    let mut x = 0;
+    let mut y = 0;
+    bounds
+        .iter()
+        .enumerate()
+        .map(|(i, rect)| match layout {
+            CanvasLayout::Horizontal => {
+                let position = PointInt32 { X: x, Y: 0 };
+                x += rect.Width;
+                position
+            }
+            CanvasLayout::Vertical => {
+                let position = PointInt32 { X: 0, Y: y };
+                y += rect.Height;
+                position
+            }
+            _ => PointInt32 {
+                X: (i % columns) as i32 * cell_width,
+                Y: (i / columns) as i32 * cell_height,
+            },
+        })
+        .collect()
+}
+
+fn compute_canvas_size(bounds: &[RectInt32]) -> SizeInt32 {
+    let right = bounds.iter().map(|rect| rect.X + rect.Width).max();
+    let bottom = bounds.iter().map(|rect| rect.Y + rect.Height).max();
+    SizeInt32 {
+        Width: right.unwrap_or(0).max(0),
+        Height: bottom.unwrap_or(0).max(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Graphics::{PointInt32, RectInt32, SizeInt32};
+
+    use crate::video::canvas_layout::CanvasLayout;
+
+    use super::{arrange_on_canvas, compute_canvas_size, lay_out_on_canvas};
+
+    fn rect(x: i32, y: i32, width: i32, height: i32) -> RectInt32 {
+        RectInt32 {
+            X: x,
+            Y: y,
+            Width: width,
+            Height: height,
+        }
+    }
+
+    #[test]
+    fn arrange_test() {
+        // A secondary display to the left of and slightly above the primary
+        let bounds = [rect(0, 0, 1920, 1080), rect(-1280, -200, 1280, 1024)];
+        let positions = arrange_on_canvas(&bounds);
+        assert_eq!(
+            positions,
+            vec![PointInt32 { X: 1280, Y: 200 }, PointInt32 { X: 0, Y: 0 }]
+        );
+
+        let positioned: Vec<_> = positions
+            .iter()
+            .zip(&bounds)
+            .map(|(position, bounds)| rect(position.X, position.Y, bounds.Width, bounds.Height))
+            .collect();
+        assert_eq!(
+            compute_canvas_size(&positioned),
+            SizeInt32 {
+                Width: 3200,
+                Height: 1280
+            }
+        );
+    }
+
+    #[test]
+    fn layout_test() {
+        let bounds = [
+            rect(0, 0, 1920, 1080),
+            rect(-1280, -200, 1280, 1024),
+            rect(1920, 0, 2560, 1440),
+        ];
+        let point = |x, y| PointInt32 { X: x, Y: y };
+        assert_eq!(
+            lay_out_on_canvas(&bounds, CanvasLayout::Horizontal),
+            vec![point(0, 0), point(1920, 0), point(3200, 0)]
+        );
+        assert_eq!(
+            lay_out_on_canvas(&bounds, CanvasLayout::Vertical),
+            vec![point(0, 0), point(0, 1080), point(0, 2104)]
+        );
+        // Three items fill two rows of two columns
+        assert_eq!(
+            lay_out_on_canvas(&bounds, CanvasLayout::Grid),
+            vec![point(0, 0), point(2560, 0), point(0, 1440)]
+        );
+    }
+
+    #[test]
+    fn single_item_test() {
+        let bounds = [rect(1920, 0, 2560, 1440)];
+        assert_eq!(arrange_on_canvas(&bounds), vec![PointInt32 { X: 0, Y: 0 }]);
+        assert_eq!(
+            compute_canvas_size(&[rect(0, 0, 2560, 1440)]),
+            SizeInt32 {
+                Width: 2560,
+                Height: 1440
+            }
+        );
+    }
+}
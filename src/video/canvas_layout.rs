@@ -0,0 +1,67 @@
+use std::{fmt::Display, str::FromStr};
+
+/// How several capture items are placed within a single recording.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum CanvasLayout {
+    /// Where the items are on the desktop.
+    #[default]
+    Desktop,
+    /// In rows and columns of equally sized cells.
+    Grid,
+    /// Side by side, from left to right.
+    Horizontal,
+    /// Stacked, from top to bottom.
+    Vertical,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseCanvasLayoutError(&'static str);
+
+impl FromStr for CanvasLayout {
+    type Err = ParseCanvasLayoutError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "desktop" => Ok(CanvasLayout::Desktop),
+            "grid" => Ok(CanvasLayout::Grid),
+            "horizontal" => Ok(CanvasLayout::Horizontal),
+            "vertical" => Ok(CanvasLayout::Vertical),
+            _ => Err(ParseCanvasLayoutError(
+                "Invalid layout value! Expecting: desktop, grid, horizontal, or vertical.",
+            )),
+        }
+    }
+}
+
+impl Display for CanvasLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = match self {
+            CanvasLayout::Desktop => "desktop",
+            CanvasLayout::Grid => "grid",
+            CanvasLayout::Horizontal => "horizontal",
+            CanvasLayout::Vertical => "vertical",
+        };
+        write!(f, "{}", string)
+    }
+}
+
+impl Display for ParseCanvasLayoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseCanvasLayoutError {}
+
+#[cfg(test)]
+mod tests {
+    use super::CanvasLayout;
+
+    #[test]
+    fn canvas_layout_parsing_test() {
+        assert_eq!("grid".parse(), Ok(CanvasLayout::Grid));
+        assert_eq!("Horizontal".parse(), Ok(CanvasLayout::Horizontal));
+        assert_eq!("desktop".parse(), Ok(CanvasLayout::Desktop));
+        assert!("diagonal".parse::<CanvasLayout>().is_err());
+        assert_eq!(CanvasLayout::Vertical.to_string(), "vertical");
+    }
+}
@@ -0,0 +1,162 @@
+use std::{fmt::Display, str::FromStr};
+
+use windows::{
+    core::GUID,
+    Graphics::SizeInt32,
+    Win32::Media::MediaFoundation::{
+        eAVEncH265VLevel, eAVEncH265VLevel3_1, eAVEncH265VLevel4, eAVEncH265VLevel4_1,
+        eAVEncH265VLevel5_1, eAVEncH265VLevel5_2, eAVEncH265VLevel6_1, eAVEncH265VLevel6_2,
+        MFVideoFormat_AV1, MFVideoFormat_H264, MFVideoFormat_HEVC, MFVideoFormat_VP90,
+    },
+};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum VideoCodec {
+    H264,
+    Hevc,
+    Av1,
+    Vp9,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseVideoCodecError(&'static str);
+
+impl FromStr for VideoCodec {
+    type Err = ParseVideoCodecError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "h264" => Ok(VideoCodec::H264),
+            "hevc" | "h265" => Ok(VideoCodec::Hevc),
+            "av1" => Ok(VideoCodec::Av1),
+            "vp9" => Ok(VideoCodec::Vp9),
+            _ => Err(ParseVideoCodecError(
+                "Invalid codec value! Expecting: h264, hevc, av1, or vp9.",
+            )),
+        }
+    }
+}
+
+impl Display for VideoCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = match self {
+            VideoCodec::H264 => "h264",
+            VideoCodec::Hevc => "hevc",
+            VideoCodec::Av1 => "av1",
+            VideoCodec::Vp9 => "vp9",
+        };
+        write!(f, "{}", string)
+    }
+}
+
+impl Display for ParseVideoCodecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseVideoCodecError {}
+
+impl VideoCodec {
+    pub const ALL: [VideoCodec; 4] = [
+        VideoCodec::H264,
+        VideoCodec::Hevc,
+        VideoCodec::Av1,
+        VideoCodec::Vp9,
+    ];
+
+    /// The codec of an encoded video subtype, if it's one of the codecs.
+    pub fn from_subtype(subtype: &GUID) -> Option<VideoCodec> {
+        Self::ALL
+            .into_iter()
+            .find(|codec| codec.subtype() == *subtype)
+    }
+
+    pub fn subtype(&self) -> GUID {
+        match self {
+            VideoCodec::H264 => MFVideoFormat_H264,
+            VideoCodec::Hevc => MFVideoFormat_HEVC,
+            VideoCodec::Av1 => MFVideoFormat_AV1,
+            VideoCodec::Vp9 => MFVideoFormat_VP90,
+        }
+    }
+
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "H264",
+            VideoCodec::Hevc => "HEVC",
+            VideoCodec::Av1 => "AV1",
+            VideoCodec::Vp9 => "VP9",
+        }
+    }
+}
+
+// Maximum luma picture size and luma sample rate for each HEVC level (Table A.8)
+const HEVC_LEVELS: [(eAVEncH265VLevel, u64, u64); 7] = [
+    (eAVEncH265VLevel3_1, 983_040, 33_177_600),
+    (eAVEncH265VLevel4, 2_228_224, 66_846_720),
+    (eAVEncH265VLevel4_1, 2_228_224, 133_693_440),
+    (eAVEncH265VLevel5_1, 8_912_896, 534_773_760),
+    (eAVEncH265VLevel5_2, 8_912_896, 1_069_547_520),
+    (eAVEncH265VLevel6_1, 35_651_584, 2_139_095_040),
+    (eAVEncH265VLevel6_2, 35_651_584, 4_278_190_080),
+];
+
+/// Picks the lowest HEVC level that can hold the given resolution and frame rate.
+pub fn get_hevc_level(resolution: SizeInt32, frame_rate: u32) -> eAVEncH265VLevel {
+    let picture_size = resolution.Width as u64 * resolution.Height as u64;
+    let sample_rate = picture_size * frame_rate as u64;
+    HEVC_LEVELS
+        .iter()
+        .find(|(_, max_picture_size, max_sample_rate)| {
+            picture_size <= *max_picture_size && sample_rate <= *max_sample_rate
+        })
+        .map(|(level, _, _)| *level)
+        .unwrap_or(eAVEncH265VLevel6_2)
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::{
+        Graphics::SizeInt32,
+        Win32::Media::MediaFoundation::{
+            eAVEncH265VLevel3_1, eAVEncH265VLevel4_1, eAVEncH265VLevel5_1, eAVEncH265VLevel5_2,
+            eAVEncH265VLevel6_1, MFVideoFormat_NV12,
+        },
+    };
+
+    use super::{get_hevc_level, VideoCodec};
+
+    fn size(width: i32, height: i32) -> SizeInt32 {
+        SizeInt32 {
+            Width: width,
+            Height: height,
+        }
+    }
+
+    #[test]
+    fn codec_parsing_test() {
+        assert_eq!("h264".parse::<VideoCodec>(), Ok(VideoCodec::H264));
+        assert_eq!("HEVC".parse::<VideoCodec>(), Ok(VideoCodec::Hevc));
+        assert_eq!("h265".parse::<VideoCodec>(), Ok(VideoCodec::Hevc));
+        assert_eq!("Av1".parse::<VideoCodec>(), Ok(VideoCodec::Av1));
+        assert_eq!("vp9".parse::<VideoCodec>(), Ok(VideoCodec::Vp9));
+        assert!("vp8".parse::<VideoCodec>().is_err());
+    }
+
+    #[test]
+    fn codec_subtype_test() {
+        for codec in VideoCodec::ALL {
+            assert_eq!(VideoCodec::from_subtype(&codec.subtype()), Some(codec));
+        }
+        assert_eq!(VideoCodec::from_subtype(&MFVideoFormat_NV12), None);
+    }
+
+    #[test]
+    fn hevc_level_test() {
+        assert_eq!(get_hevc_level(size(1280, 720), 30), eAVEncH265VLevel3_1);
+        assert_eq!(get_hevc_level(size(1920, 1080), 60), eAVEncH265VLevel4_1);
+        assert_eq!(get_hevc_level(size(3840, 2160), 60), eAVEncH265VLevel5_1);
+        assert_eq!(get_hevc_level(size(3840, 2160), 120), eAVEncH265VLevel5_2);
+        assert_eq!(get_hevc_level(size(7680, 4320), 60), eAVEncH265VLevel6_1);
+    }
+}
@@ -0,0 +1,153 @@
+use windows::{
+    core::GUID,
+    Graphics::DirectX::DirectXPixelFormat,
+    Win32::{
+        Graphics::Dxgi::Common::{
+            DXGI_COLOR_SPACE_RGB_FULL_G10_NONE_P709, DXGI_COLOR_SPACE_RGB_FULL_G22_NONE_P709,
+            DXGI_COLOR_SPACE_TYPE, DXGI_COLOR_SPACE_YCBCR_FULL_G22_LEFT_P709,
+            DXGI_COLOR_SPACE_YCBCR_STUDIO_G2084_LEFT_P2020,
+            DXGI_COLOR_SPACE_YCBCR_STUDIO_G22_LEFT_P709, DXGI_FORMAT, DXGI_FORMAT_B8G8R8A8_UNORM,
+            DXGI_FORMAT_NV12, DXGI_FORMAT_P010, DXGI_FORMAT_R16G16B16A16_FLOAT,
+        },
+        Media::MediaFoundation::{
+            MFNominalRange_0_255, MFNominalRange_16_235, MFVideoFormat_NV12, MFVideoFormat_P010,
+            MFVideoPrimaries_BT2020, MFVideoPrimaries_BT709, MFVideoTransFunc_2084,
+            MFVideoTransFunc_709, MFVideoTransferMatrix_BT2020_10, MFVideoTransferMatrix_BT709,
+            MF_MT_TRANSFER_FUNCTION, MF_MT_VIDEO_NOMINAL_RANGE, MF_MT_VIDEO_PRIMARIES,
+            MF_MT_YUV_MATRIX,
+        },
+    },
+};
+
+use super::{bit_depth::BitDepth, color_range::ColorRange};
+
+/// How frames are represented on their way from the capture API to the encoder.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColorFormat {
+    /// 8-bit BGRA frames encoded as 8-bit 4:2:0 video.
+    Sdr,
+    /// 8-bit BGRA frames encoded as 10-bit 4:2:0 video.
+    Sdr10,
+    /// FP16 scRGB frames encoded as 10-bit 4:2:0 video with the PQ transfer function.
+    Hdr10,
+}
+
+impl ColorFormat {
+    /// HDR is always encoded with 10 bits.
+    pub fn new(hdr: bool, bit_depth: BitDepth) -> Self {
+        match (hdr, bit_depth) {
+            (true, _) => ColorFormat::Hdr10,
+            (false, BitDepth::Ten) => ColorFormat::Sdr10,
+            (false, BitDepth::Eight) => ColorFormat::Sdr,
+        }
+    }
+
+    pub fn capture_pixel_format(&self) -> DirectXPixelFormat {
+        match self {
+            ColorFormat::Sdr | ColorFormat::Sdr10 => DirectXPixelFormat::B8G8R8A8UIntNormalized,
+            ColorFormat::Hdr10 => DirectXPixelFormat::R16G16B16A16Float,
+        }
+    }
+
+    /// The format frames are composed in before being converted for the encoder.
+    pub fn texture_format(&self) -> DXGI_FORMAT {
+        match self {
+            ColorFormat::Sdr | ColorFormat::Sdr10 => DXGI_FORMAT_B8G8R8A8_UNORM,
+            ColorFormat::Hdr10 => DXGI_FORMAT_R16G16B16A16_FLOAT,
+        }
+    }
+
+    /// The format the video processor converts to, which the encoder consumes.
+    pub fn encoder_texture_format(&self) -> DXGI_FORMAT {
+        match self {
+            ColorFormat::Sdr => DXGI_FORMAT_NV12,
+            ColorFormat::Sdr10 | ColorFormat::Hdr10 => DXGI_FORMAT_P010,
+        }
+    }
+
+    pub fn encoder_subtype(&self) -> GUID {
+        match self {
+            ColorFormat::Sdr => MFVideoFormat_NV12,
+            ColorFormat::Sdr10 | ColorFormat::Hdr10 => MFVideoFormat_P010,
+        }
+    }
+
+    pub fn is_ten_bit(&self) -> bool {
+        self.encoder_texture_format() == DXGI_FORMAT_P010
+    }
+
+    /// The input and output color spaces for the video processor. Captured
+    /// SDR frames are full range sRGB, HDR frames are linear scRGB.
+    pub fn processor_color_spaces(
+        &self,
+        range: ColorRange,
+    ) -> (DXGI_COLOR_SPACE_TYPE, DXGI_COLOR_SPACE_TYPE) {
+        match (self, range) {
+            (ColorFormat::Hdr10, _) => (
+                DXGI_COLOR_SPACE_RGB_FULL_G10_NONE_P709,
+                DXGI_COLOR_SPACE_YCBCR_STUDIO_G2084_LEFT_P2020,
+            ),
+            (_, ColorRange::Limited) => (
+                DXGI_COLOR_SPACE_RGB_FULL_G22_NONE_P709,
+                DXGI_COLOR_SPACE_YCBCR_STUDIO_G22_LEFT_P709,
+            ),
+            (_, ColorRange::Full) => (
+                DXGI_COLOR_SPACE_RGB_FULL_G22_NONE_P709,
+                DXGI_COLOR_SPACE_YCBCR_FULL_G22_LEFT_P709,
+            ),
+        }
+    }
+
+    /// The attributes that tell players how to interpret the encoded video, so
+    /// that it doesn't look washed out or crushed.
+    pub fn media_type_attributes(&self, range: ColorRange) -> [(GUID, u32); 4] {
+        let (primaries, transfer_function, matrix) = match self {
+            ColorFormat::Sdr | ColorFormat::Sdr10 => (
+                MFVideoPrimaries_BT709.0,
+                MFVideoTransFunc_709.0,
+                MFVideoTransferMatrix_BT709.0,
+            ),
+            ColorFormat::Hdr10 => (
+                MFVideoPrimaries_BT2020.0,
+                MFVideoTransFunc_2084.0,
+                MFVideoTransferMatrix_BT2020_10.0,
+            ),
+        };
+        let nominal_range = match range {
+            ColorRange::Limited => MFNominalRange_16_235,
+            ColorRange::Full => MFNominalRange_0_255,
+        };
+        [
+            (MF_MT_VIDEO_PRIMARIES, primaries as u32),
+            (MF_MT_TRANSFER_FUNCTION, transfer_function as u32),
+            (MF_MT_YUV_MATRIX, matrix as u32),
+            (MF_MT_VIDEO_NOMINAL_RANGE, nominal_range.0 as u32),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::Media::MediaFoundation::{
+        MFNominalRange_0_255, MFVideoTransFunc_2084, MF_MT_TRANSFER_FUNCTION,
+        MF_MT_VIDEO_NOMINAL_RANGE,
+    };
+
+    use crate::video::{bit_depth::BitDepth, color_range::ColorRange};
+
+    use super::ColorFormat;
+
+    #[test]
+    fn color_format_test() {
+        assert_eq!(ColorFormat::new(false, BitDepth::Eight), ColorFormat::Sdr);
+        assert_eq!(ColorFormat::new(false, BitDepth::Ten), ColorFormat::Sdr10);
+        assert_eq!(ColorFormat::new(true, BitDepth::Eight), ColorFormat::Hdr10);
+        assert!(!ColorFormat::Sdr.is_ten_bit());
+        assert!(ColorFormat::Sdr10.is_ten_bit());
+
+        let attributes = ColorFormat::Sdr.media_type_attributes(ColorRange::Full);
+        assert!(attributes.contains(&(MF_MT_VIDEO_NOMINAL_RANGE, MFNominalRange_0_255.0 as u32)));
+        let attributes = ColorFormat::Hdr10.media_type_attributes(ColorRange::Limited);
+        assert!(attributes.contains(&(MF_MT_TRANSFER_FUNCTION, MFVideoTransFunc_2084.0 as u32)));
+    }
+}
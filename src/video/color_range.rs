@@ -0,0 +1,58 @@
+use std::{fmt::Display, str::FromStr};
+
+/// The range of values used for each component of the encoded video.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColorRange {
+    /// 16-235 for 8-bit video, which is what most players expect.
+    Limited,
+    /// 0-255 for 8-bit video.
+    Full,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseColorRangeError(&'static str);
+
+impl FromStr for ColorRange {
+    type Err = ParseColorRangeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "limited" | "tv" => Ok(ColorRange::Limited),
+            "full" | "pc" => Ok(ColorRange::Full),
+            _ => Err(ParseColorRangeError(
+                "Invalid color range value! Expecting: limited or full.",
+            )),
+        }
+    }
+}
+
+impl Display for ColorRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = match self {
+            ColorRange::Limited => "limited",
+            ColorRange::Full => "full",
+        };
+        write!(f, "{}", string)
+    }
+}
+
+impl Display for ParseColorRangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseColorRangeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::ColorRange;
+
+    #[test]
+    fn color_range_parsing_test() {
+        assert_eq!("limited".parse(), Ok(ColorRange::Limited));
+        assert_eq!("Full".parse(), Ok(ColorRange::Full));
+        assert_eq!("pc".parse(), Ok(ColorRange::Full));
+        assert!("auto".parse::<ColorRange>().is_err());
+        assert_eq!(ColorRange::Full.to_string(), "full");
+    }
+}
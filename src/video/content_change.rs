@@ -0,0 +1,135 @@
+use windows::{
+    core::Result,
+    Graphics::{RectInt32, SizeInt32},
+    Win32::Graphics::Direct3D11::{ID3D11Device, ID3D11Texture2D, D3D11_TEXTURE2D_DESC},
+};
+
+use super::downsampler::FrameDownsampler;
+
+// Small changes (e.g. a typed character, or the cursor blinking) still show
+// at this width, and the thumbnails are cheap enough to read every frame
+const THUMBNAIL_WIDTH: u32 = 256;
+
+/// Tells whether the recorded part of a frame changed since the last one that
+/// was recorded of the same item, by comparing their thumbnails. Frames must
+/// be BGRA.
+pub struct ContentChangeDetector {
+    d3d_device: ID3D11Device,
+    items: Vec<Option<ItemThumbnails>>,
+}
+
+struct ItemThumbnails {
+    downsampler: FrameDownsampler,
+    // Of the last frame that was recorded, and of the frame being looked at
+    recorded: Option<Vec<u8>>,
+    current: Option<Vec<u8>>,
+}
+
+impl ContentChangeDetector {
+    pub fn new(d3d_device: ID3D11Device) -> Self {
+        Self {
+            d3d_device,
+            items: Vec::new(),
+        }
+    }
+
+    /// Compares the frames of a new device from now on, e.g. once the old one
+    /// was lost. The next frame of each item counts as changed.
+    pub fn reset_device(&mut self, d3d_device: ID3D11Device) {
+        *self = Self::new(d3d_device);
+    }
+
+    /// Whether the rect of the frame of the item differs from the last frame
+    /// of it that was recorded, see record.
+    pub fn has_changed(
+        &mut self,
+        index: usize,
+        texture: &ID3D11Texture2D,
+        rect: RectInt32,
+    ) -> Result<bool> {
+        let desc = unsafe {
+            let mut desc = D3D11_TEXTURE2D_DESC::default();
+            texture.GetDesc(&mut desc);
+            desc
+        };
+        let rect = clip_rect(rect, desc.Width as i32, desc.Height as i32);
+        if rect.Width == 0 || rect.Height == 0 {
+            return Ok(true);
+        }
+        let size = SizeInt32 {
+            Width: rect.Width,
+            Height: rect.Height,
+        };
+        if self.items.len() <= index {
+            self.items.resize_with(index + 1, || None);
+        }
+        // Frames of another size can't be compared, so they count as changed
+        let item = match &mut self.items[index] {
+            Some(item) if item.downsampler.size() == size => item,
+            item => item.insert(ItemThumbnails {
+                downsampler: FrameDownsampler::new(&self.d3d_device, size, THUMBNAIL_WIDTH)?,
+                recorded: None,
+                current: None,
+            }),
+        };
+        let thumbnail = item.downsampler.read(texture, Some(rect))?;
+        let changed = item.recorded.as_ref() != Some(&thumbnail);
+        item.current = Some(thumbnail);
+        Ok(changed)
+    }
+
+    /// The last frame of the item that was looked at was recorded, so later
+    /// frames are compared with it.
+    pub fn record(&mut self, index: usize) {
+        if let Some(Some(item)) = self.items.get_mut(index) {
+            if let Some(current) = item.current.take() {
+                item.recorded = Some(current);
+            }
+        }
+    }
+}
+
+// The part of the rect that's within a texture of the size
+fn clip_rect(rect: RectInt32, width: i32, height: i32) -> RectInt32 {
+    let right = (rect.X + rect.Width).clamp(0, width);
+    let bottom = (rect.Y + rect.Height).clamp(0, height);
+    let left = rect.X.clamp(0, right);
+    let top = rect.Y.clamp(0, bottom);
+    RectInt32 {
+        X: left,
+        Y: top,
+        Width: right - left,
+        Height: bottom - top,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Graphics::RectInt32;
+
+    use super::clip_rect;
+
+    #[test]
+    fn clip_rect_test() {
+        let rect = |x, y, width, height| RectInt32 {
+            X: x,
+            Y: y,
+            Width: width,
+            Height: height,
+        };
+        assert_eq!(
+            clip_rect(rect(100, 100, 200, 200), 1920, 1080),
+            rect(100, 100, 200, 200)
+        );
+        // The content can be larger than the texture while a window grows
+        assert_eq!(
+            clip_rect(rect(0, 0, 2000, 1200), 1920, 1080),
+            rect(0, 0, 1920, 1080)
+        );
+        assert_eq!(
+            clip_rect(rect(1900, -10, 100, 100), 1920, 1080),
+            rect(1900, 0, 20, 90)
+        );
+        assert_eq!(clip_rect(rect(2000, 0, 100, 100), 1920, 1080).Width, 0);
+    }
+}
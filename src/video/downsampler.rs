@@ -0,0 +1,197 @@
+use windows::{
+    core::Result,
+    Graphics::{RectInt32, SizeInt32},
+    Win32::Graphics::{
+        Direct3D11::{
+            ID3D11Device, ID3D11DeviceContext, ID3D11ShaderResourceView, ID3D11Texture2D,
+            D3D11_BIND_RENDER_TARGET, D3D11_BIND_SHADER_RESOURCE, D3D11_BOX, D3D11_CPU_ACCESS_READ,
+            D3D11_MAPPED_SUBRESOURCE, D3D11_MAP_READ, D3D11_RESOURCE_MISC_GENERATE_MIPS,
+            D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT, D3D11_USAGE_STAGING,
+        },
+        Dxgi::Common::{DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_SAMPLE_DESC},
+    },
+};
+
+/// Reads BGRA frames back to the CPU as small thumbnails, so that they can
+/// be compared every frame. The GPU shrinks each frame through its mipmaps,
+/// so only the thumbnail is copied back.
+pub struct FrameDownsampler {
+    d3d_context: ID3D11DeviceContext,
+    // The frame is copied here, and shrunk through its mipmaps
+    mip_texture: ID3D11Texture2D,
+    mip_view: ID3D11ShaderResourceView,
+    mip_level: u32,
+    staging_texture: ID3D11Texture2D,
+    size: SizeInt32,
+    thumbnail_size: SizeInt32,
+}
+
+impl FrameDownsampler {
+    /// Frames (or the parts of them that are read) must be of the size, and
+    /// their thumbnails are at most the width.
+    pub fn new(d3d_device: &ID3D11Device, size: SizeInt32, max_width: u32) -> Result<Self> {
+        let d3d_context = unsafe { d3d_device.GetImmediateContext()? };
+        let (mip_level, thumbnail_size) = get_thumbnail_level(size, max_width);
+        let mip_texture = create_texture(
+            d3d_device,
+            &D3D11_TEXTURE2D_DESC {
+                Width: size.Width.max(1) as u32,
+                Height: size.Height.max(1) as u32,
+                MipLevels: mip_level + 1,
+                Usage: D3D11_USAGE_DEFAULT,
+                BindFlags: (D3D11_BIND_RENDER_TARGET.0 | D3D11_BIND_SHADER_RESOURCE.0) as u32,
+                MiscFlags: D3D11_RESOURCE_MISC_GENERATE_MIPS.0 as u32,
+                ..texture_desc()
+            },
+        )?;
+        let mip_view = unsafe {
+            let mut view = None;
+            d3d_device.CreateShaderResourceView(&mip_texture, None, Some(&mut view))?;
+            view.unwrap()
+        };
+        let staging_texture = create_texture(
+            d3d_device,
+            &D3D11_TEXTURE2D_DESC {
+                Width: thumbnail_size.Width as u32,
+                Height: thumbnail_size.Height as u32,
+                MipLevels: 1,
+                Usage: D3D11_USAGE_STAGING,
+                CPUAccessFlags: D3D11_CPU_ACCESS_READ.0 as u32,
+                ..texture_desc()
+            },
+        )?;
+        Ok(Self {
+            d3d_context,
+            mip_texture,
+            mip_view,
+            mip_level,
+            staging_texture,
+            size,
+            thumbnail_size,
+        })
+    }
+
+    /// The size of the frames that are read.
+    pub fn size(&self) -> SizeInt32 {
+        self.size
+    }
+
+    /// Reads the thumbnail of the texture (or of the rect of it, which has to
+    /// be of the size) as tightly packed BGRA pixels.
+    pub fn read(&self, texture: &ID3D11Texture2D, rect: Option<RectInt32>) -> Result<Vec<u8>> {
+        let source_box = rect.map(|rect| D3D11_BOX {
+            left: rect.X as u32,
+            top: rect.Y as u32,
+            front: 0,
+            right: (rect.X + rect.Width) as u32,
+            bottom: (rect.Y + rect.Height) as u32,
+            back: 1,
+        });
+        unsafe {
+            self.d3d_context.CopySubresourceRegion(
+                &self.mip_texture,
+                0,
+                0,
+                0,
+                0,
+                texture,
+                0,
+                source_box.as_ref().map(|source_box| source_box as *const _),
+            );
+            self.d3d_context.GenerateMips(&self.mip_view);
+            self.d3d_context.CopySubresourceRegion(
+                &self.staging_texture,
+                0,
+                0,
+                0,
+                0,
+                &self.mip_texture,
+                self.mip_level,
+                None,
+            );
+            let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+            self.d3d_context.Map(
+                &self.staging_texture,
+                0,
+                D3D11_MAP_READ,
+                0,
+                Some(&mut mapped),
+            )?;
+            let row_size = self.thumbnail_size.Width as usize * 4;
+            let height = self.thumbnail_size.Height as usize;
+            let data = std::slice::from_raw_parts(
+                mapped.pData as *const u8,
+                mapped.RowPitch as usize * (height - 1) + row_size,
+            );
+            let mut thumbnail = Vec::with_capacity(row_size * height);
+            for row in data.chunks(mapped.RowPitch as usize) {
+                thumbnail.extend_from_slice(&row[..row_size]);
+            }
+            self.d3d_context.Unmap(&self.staging_texture, 0);
+            Ok(thumbnail)
+        }
+    }
+}
+
+// The first mip level that's at most the width, and its size
+fn get_thumbnail_level(size: SizeInt32, max_width: u32) -> (u32, SizeInt32) {
+    let mut level = 0;
+    let (mut width, mut height) = (size.Width.max(1) as u32, size.Height.max(1) as u32);
+    while width > max_width {
+        width = (width / 2).max(1);
+        height = (height / 2).max(1);
+        level += 1;
+    }
+    (
+        level,
+        SizeInt32 {
+            Width: width as i32,
+            Height: height as i32,
+        },
+    )
+}
+
+fn texture_desc() -> D3D11_TEXTURE2D_DESC {
+    D3D11_TEXTURE2D_DESC {
+        ArraySize: 1,
+        Format: DXGI_FORMAT_B8G8R8A8_UNORM,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+fn create_texture(
+    d3d_device: &ID3D11Device,
+    desc: &D3D11_TEXTURE2D_DESC,
+) -> Result<ID3D11Texture2D> {
+    unsafe {
+        let mut texture = None;
+        d3d_device.CreateTexture2D(desc, None, Some(&mut texture))?;
+        Ok(texture.unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Graphics::SizeInt32;
+
+    use super::get_thumbnail_level;
+
+    #[test]
+    fn thumbnail_level_test() {
+        let size = |width, height| SizeInt32 {
+            Width: width,
+            Height: height,
+        };
+        assert_eq!(get_thumbnail_level(size(1920, 1080), 64), (5, size(60, 33)));
+        assert_eq!(get_thumbnail_level(size(3840, 2160), 64), (6, size(60, 33)));
+        assert_eq!(get_thumbnail_level(size(64, 48), 64), (0, size(64, 48)));
+        assert_eq!(
+            get_thumbnail_level(size(1920, 1080), 256),
+            (3, size(240, 135))
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    mem::ManuallyDrop,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
@@ -6,49 +7,132 @@ use std::{
     thread::JoinHandle,
 };
 
+use tracing::{error, info_span};
 use windows::{
     core::{ComInterface, Error, Result},
     Foundation::TimeSpan,
     Graphics::SizeInt32,
     Win32::{
         Foundation::E_NOTIMPL,
-        Graphics::Direct3D11::{ID3D11Device, ID3D11Texture2D},
+        Graphics::Direct3D11::{ID3D11Device, ID3D11Multithread, ID3D11Texture2D},
         Media::MediaFoundation::{
-            IMFAttributes, IMFDXGIDeviceManager, IMFMediaEventGenerator, IMFMediaType, IMFSample,
-            IMFTransform, METransformHaveOutput, METransformNeedInput, MFCreateDXGIDeviceManager,
-            MFCreateDXGISurfaceBuffer, MFCreateMediaType, MFCreateSample, MFMediaType_Video,
-            MFStartup, MFVideoFormat_H264, MFVideoFormat_NV12, MFVideoInterlace_Progressive,
-            MEDIA_EVENT_GENERATOR_GET_EVENT_FLAGS, MFSTARTUP_FULL, MFT_MESSAGE_COMMAND_FLUSH,
+            eAVEncH265VProfile_Main_420_10, eAVEncH265VProfile_Main_420_8,
+            CODECAPI_AVEncCommonMeanBitRate, CODECAPI_AVEncCommonQuality,
+            CODECAPI_AVEncCommonRateControlMode, CODECAPI_AVEncMPVDefaultBPictureCount,
+            CODECAPI_AVEncMPVGOPSize, CODECAPI_AVEncVideoForceKeyFrame, ICodecAPI, IMFAttributes,
+            IMFDXGIDeviceManager, IMFMediaEventGenerator, IMFMediaType, IMFSample, IMFTransform,
+            METransformDrainComplete, METransformHaveOutput, METransformNeedInput,
+            MFCreateDXGIDeviceManager, MFCreateDXGISurfaceBuffer, MFCreateMediaType,
+            MFCreateMemoryBuffer, MFCreateSample, MFMediaType_Video, MFStartup,
+            MFVideoInterlace_Progressive, MEDIA_EVENT_GENERATOR_GET_EVENT_FLAGS, MFSTARTUP_FULL,
+            MFT_MESSAGE_COMMAND_DRAIN, MFT_MESSAGE_COMMAND_FLUSH,
             MFT_MESSAGE_NOTIFY_BEGIN_STREAMING, MFT_MESSAGE_NOTIFY_END_OF_STREAM,
             MFT_MESSAGE_NOTIFY_END_STREAMING, MFT_MESSAGE_NOTIFY_START_OF_STREAM,
-            MFT_MESSAGE_SET_D3D_MANAGER, MFT_OUTPUT_DATA_BUFFER, MFT_SET_TYPE_TEST_ONLY,
-            MF_EVENT_TYPE, MF_E_INVALIDMEDIATYPE, MF_E_NO_MORE_TYPES, MF_E_TRANSFORM_TYPE_NOT_SET,
+            MFT_MESSAGE_SET_D3D_MANAGER, MFT_OUTPUT_DATA_BUFFER,
+            MFT_OUTPUT_STREAM_CAN_PROVIDE_SAMPLES, MFT_OUTPUT_STREAM_PROVIDES_SAMPLES,
+            MFT_SET_TYPE_TEST_ONLY, MF_EVENT_TYPE, MF_E_INVALIDMEDIATYPE, MF_E_NO_MORE_TYPES,
+            MF_E_TRANSFORM_NEED_MORE_INPUT, MF_E_TRANSFORM_TYPE_NOT_SET,
             MF_MT_ALL_SAMPLES_INDEPENDENT, MF_MT_AVG_BITRATE, MF_MT_FRAME_RATE, MF_MT_FRAME_SIZE,
-            MF_MT_INTERLACE_MODE, MF_MT_MAJOR_TYPE, MF_MT_PIXEL_ASPECT_RATIO, MF_MT_SUBTYPE,
-            MF_READWRITE_ENABLE_HARDWARE_TRANSFORMS, MF_TRANSFORM_ASYNC_UNLOCK,
+            MF_MT_INTERLACE_MODE, MF_MT_MAJOR_TYPE, MF_MT_MPEG2_LEVEL, MF_MT_MPEG2_PROFILE,
+            MF_MT_PIXEL_ASPECT_RATIO, MF_MT_SUBTYPE, MF_READWRITE_ENABLE_HARDWARE_TRANSFORMS,
+            MF_TRANSFORM_ASYNC_UNLOCK,
         },
     },
 };
 
-use crate::media::{MFSetAttributeRatio, MFSetAttributeSize, MF_VERSION};
-
-use super::encoder_device::VideoEncoderDevice;
+use crate::media::{set_codec_api_value, MFSetAttributeRatio, MFSetAttributeSize, MF_VERSION};
+
+use super::{
+    codec::{get_hevc_level, VideoCodec},
+    color_format::ColorFormat,
+    color_range::ColorRange,
+    encoder_device::VideoEncoderDevice,
+    fit_mode::FitMode,
+    frame_rate_mode::FrameRateMode,
+    gpu_sync::GpuSyncPoint,
+    orientation::Orientation,
+    rate_control::RateControlMode,
+    scale_filter::ScaleFilter,
+};
 
 pub struct VideoEncoderInputSample {
     timestamp: TimeSpan,
+    duration: TimeSpan,
     texture: ID3D11Texture2D,
+    // The GPU work that writes the texture, which the encoder waits for
+    ready: Option<GpuSyncPoint>,
+    keyframe: bool,
 }
 
 impl VideoEncoderInputSample {
-    pub fn new(timestamp: TimeSpan, texture: ID3D11Texture2D) -> Self {
-        Self { timestamp, texture }
+    pub fn new(timestamp: TimeSpan, duration: TimeSpan, texture: ID3D11Texture2D) -> Self {
+        Self {
+            timestamp,
+            duration,
+            texture,
+            ready: None,
+            keyframe: false,
+        }
+    }
+
+    /// The encoder doesn't read the texture until the GPU reaches the point.
+    pub fn set_ready(&mut self, ready: GpuSyncPoint) {
+        self.ready = Some(ready);
+    }
+
+    pub fn timestamp(&self) -> TimeSpan {
+        self.timestamp
+    }
+
+    pub fn duration(&self) -> TimeSpan {
+        self.duration
     }
+
+    pub fn texture(&self) -> &ID3D11Texture2D {
+        &self.texture
+    }
+
+    pub fn is_keyframe(&self) -> bool {
+        self.keyframe
+    }
+
+    /// Encodes the sample as a keyframe, e.g. at a scene change.
+    pub fn request_keyframe(&mut self) {
+        self.keyframe = true;
+    }
+
+    pub fn set_duration(&mut self, duration: TimeSpan) {
+        self.duration = duration;
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct VideoEncoderSettings {
+    // In bits per second
+    pub bit_rate: u32,
+    pub frame_rate: u32,
+    pub frame_rate_mode: FrameRateMode,
+    // The encoder's default is used when not provided
+    pub rate_control: Option<RateControlMode>,
+    // 0-100, only used with constant quality rate control
+    pub quality: Option<u32>,
+    // The number of frames between keyframes
+    pub gop_size: Option<u32>,
+    // The number of B-frames between each pair of reference frames
+    pub b_frames: Option<u32>,
+    pub color_format: ColorFormat,
+    pub color_range: ColorRange,
+    pub orientation: Orientation,
+    // The video processor scales the frames when not provided
+    pub scale_filter: Option<ScaleFilter>,
+    pub fit_mode: FitMode,
 }
 
 pub struct VideoEncoderOutputSample {
     sample: IMFSample,
 }
 
+unsafe impl Send for VideoEncoderOutputSample {}
 impl VideoEncoderOutputSample {
     pub fn sample(&self) -> &IMFSample {
         &self.sample
@@ -60,24 +144,41 @@ pub struct VideoEncoder {
     output_type: IMFMediaType,
     started: AtomicBool,
     should_stop: Arc<AtomicBool>,
+    keyframe_requested: Arc<AtomicBool>,
     encoder_thread_handle: Option<JoinHandle<Result<()>>>,
 }
 
 struct VideoEncoderInner {
-    _d3d_device: ID3D11Device,
+    d3d_device: ID3D11Device,
     _media_device_manager: IMFDXGIDeviceManager,
     _device_manager_reset_token: u32,
 
     transform: IMFTransform,
-    event_generator: IMFMediaEventGenerator,
+    // Only hardware encoders are asynchronous, software encoders are driven
+    // by calling ProcessInput and ProcessOutput in turn
+    event_generator: Option<IMFMediaEventGenerator>,
     input_stream_id: u32,
     output_stream_id: u32,
+    // The size of the output samples to allocate, if the encoder doesn't
+    // provide its own
+    output_sample_size: Option<u32>,
+    // What the encoder was created with, so that it can be created again on
+    // a new device
+    encoder_device: VideoEncoderDevice,
+    input_resolution: SizeInt32,
+    output_resolution: SizeInt32,
+    settings: VideoEncoderSettings,
+    // A sample from a new device, which goes to the encoder created for it
+    pending_input: Option<VideoEncoderInputSample>,
+    // Output is thrown away while warming up, see VideoEncoder::warm_up
+    warming_up: bool,
 
     sample_requested_callback:
         Option<Box<dyn Send + FnMut() -> Result<Option<VideoEncoderInputSample>>>>,
     sample_rendered_callback: Option<Box<dyn Send + FnMut(VideoEncoderOutputSample) -> Result<()>>>,
 
     should_stop: Arc<AtomicBool>,
+    keyframe_requested: Arc<AtomicBool>,
 }
 
 impl VideoEncoder {
@@ -86,9 +187,10 @@ impl VideoEncoder {
         d3d_device: ID3D11Device,
         input_resolution: SizeInt32,
         output_resolution: SizeInt32,
-        bit_rate: u32,
-        frame_rate: u32,
+        settings: VideoEncoderSettings,
     ) -> Result<Self> {
+        let bit_rate = settings.bit_rate;
+        let frame_rate = settings.frame_rate;
         let transform = encoder_device.create_transform()?;
 
         // Create MF device manager
@@ -104,13 +206,23 @@ impl VideoEncoder {
             media_device_manager.unwrap()
         };
         unsafe { media_device_manager.ResetDevice(&d3d_device, device_manager_reset_token)? };
+        // Samples are generated on another thread, which shares the
+        // immediate context with the encoder
+        unsafe {
+            let multithread: ID3D11Multithread = d3d_device.cast()?;
+            multithread.SetMultithreadProtected(true);
+        }
 
         // Setup MFTransform
-        let event_generator: IMFMediaEventGenerator = transform.cast()?;
-        let attributes = unsafe { transform.GetAttributes()? };
-        unsafe {
-            attributes.SetUINT32(&MF_TRANSFORM_ASYNC_UNLOCK, 1)?;
-            attributes.SetUINT32(&MF_READWRITE_ENABLE_HARDWARE_TRANSFORMS, 1)?;
+        let event_generator: Option<IMFMediaEventGenerator> = if encoder_device.is_hardware() {
+            let attributes = unsafe { transform.GetAttributes()? };
+            unsafe {
+                attributes.SetUINT32(&MF_TRANSFORM_ASYNC_UNLOCK, 1)?;
+                attributes.SetUINT32(&MF_READWRITE_ENABLE_HARDWARE_TRANSFORMS, 1)?;
+            };
+            Some(transform.cast()?)
+        } else {
+            None
         };
 
         let mut number_of_input_streams = 0;
@@ -151,16 +263,30 @@ impl VideoEncoder {
         let output_stream_id = output_stream_ids[0];
 
         // TOOD: Avoid this AddRef?
-        unsafe {
+        let result = unsafe {
             let temp = media_device_manager.clone();
-            transform.ProcessMessage(MFT_MESSAGE_SET_D3D_MANAGER, std::mem::transmute(temp))?;
+            transform.ProcessMessage(MFT_MESSAGE_SET_D3D_MANAGER, std::mem::transmute(temp))
         };
+        match result {
+            Ok(_) => {}
+            // Software encoders read the frames from system memory instead
+            Err(error) if error.code() == E_NOTIMPL && !encoder_device.is_hardware() => {}
+            Err(error) => return Err(error),
+        }
+
+        // Rate control needs to be configured before the output type is set
+        if let Some(rate_control) = settings.rate_control {
+            configure_rate_control(&transform.cast()?, rate_control, &settings)?;
+        }
+        if settings.gop_size.is_some() || settings.b_frames.is_some() {
+            configure_gop(&transform.cast()?, &settings)?;
+        }
 
         let output_type = unsafe {
             let output_type = MFCreateMediaType()?;
             let attributes: IMFAttributes = output_type.cast()?;
             output_type.SetGUID(&MF_MT_MAJOR_TYPE, &MFMediaType_Video)?;
-            output_type.SetGUID(&MF_MT_SUBTYPE, &MFVideoFormat_H264)?;
+            output_type.SetGUID(&MF_MT_SUBTYPE, &encoder_device.codec().subtype())?;
             output_type.SetUINT32(&MF_MT_AVG_BITRATE, bit_rate)?;
             MFSetAttributeSize(
                 &attributes,
@@ -172,6 +298,19 @@ impl VideoEncoder {
             MFSetAttributeRatio(&attributes, &MF_MT_PIXEL_ASPECT_RATIO, 1, 1)?;
             output_type.SetUINT32(&MF_MT_INTERLACE_MODE, MFVideoInterlace_Progressive.0 as u32)?;
             output_type.SetUINT32(&MF_MT_ALL_SAMPLES_INDEPENDENT, 1)?;
+            set_color_attributes(&output_type, &settings)?;
+            if encoder_device.codec() == VideoCodec::Hevc {
+                let profile = if settings.color_format.is_ten_bit() {
+                    eAVEncH265VProfile_Main_420_10
+                } else {
+                    eAVEncH265VProfile_Main_420_8
+                };
+                output_type.SetUINT32(&MF_MT_MPEG2_PROFILE, profile.0 as u32)?;
+                output_type.SetUINT32(
+                    &MF_MT_MPEG2_LEVEL,
+                    get_hevc_level(output_resolution, frame_rate).0 as u32,
+                )?;
+            }
             transform.SetOutputType(output_stream_id, &output_type, 0)?;
             output_type
         };
@@ -188,14 +327,20 @@ impl VideoEncoder {
                 let input_type = result?;
                 let attributes: IMFAttributes = input_type.cast()?;
                 input_type.SetGUID(&MF_MT_MAJOR_TYPE, &MFMediaType_Video)?;
-                input_type.SetGUID(&MF_MT_SUBTYPE, &MFVideoFormat_NV12)?;
+                input_type.SetGUID(&MF_MT_SUBTYPE, &settings.color_format.encoder_subtype())?;
+                set_color_attributes(&input_type, &settings)?;
                 MFSetAttributeSize(
                     &attributes,
                     &MF_MT_FRAME_SIZE,
                     input_resolution.Width as u32,
                     input_resolution.Height as u32,
                 )?;
-                MFSetAttributeRatio(&attributes, &MF_MT_FRAME_RATE, 60, 1)?;
+                // With a variable frame rate, frames arrive at up to the frame rate
+                let input_frame_rate = match settings.frame_rate_mode {
+                    FrameRateMode::Constant => 60,
+                    FrameRateMode::Variable => frame_rate,
+                };
+                MFSetAttributeRatio(&attributes, &MF_MT_FRAME_RATE, input_frame_rate, 1)?;
                 let result = transform.SetInputType(
                     input_stream_id,
                     &input_type,
@@ -220,9 +365,22 @@ impl VideoEncoder {
             ));
         }
 
+        let output_sample_size = unsafe {
+            let info = transform.GetOutputStreamInfo(output_stream_id)?;
+            let provides_samples = (MFT_OUTPUT_STREAM_PROVIDES_SAMPLES.0
+                | MFT_OUTPUT_STREAM_CAN_PROVIDE_SAMPLES.0)
+                as u32;
+            if info.dwFlags & provides_samples == 0 {
+                Some(info.cbSize)
+            } else {
+                None
+            }
+        };
+
         let should_stop = Arc::new(AtomicBool::new(false));
+        let keyframe_requested = Arc::new(AtomicBool::new(false));
         let inner = VideoEncoderInner {
-            _d3d_device: d3d_device,
+            d3d_device,
             _media_device_manager: media_device_manager,
             _device_manager_reset_token: device_manager_reset_token,
 
@@ -230,11 +388,19 @@ impl VideoEncoder {
             event_generator,
             input_stream_id,
             output_stream_id,
+            output_sample_size,
+            encoder_device: encoder_device.clone(),
+            input_resolution,
+            output_resolution,
+            settings,
+            pending_input: None,
+            warming_up: false,
 
             sample_requested_callback: None,
             sample_rendered_callback: None,
 
             should_stop: should_stop.clone(),
+            keyframe_requested: keyframe_requested.clone(),
         };
 
         Ok(Self {
@@ -242,6 +408,7 @@ impl VideoEncoder {
             output_type,
             started: AtomicBool::new(false),
             should_stop,
+            keyframe_requested,
             encoder_thread_handle: None,
         })
     }
@@ -263,9 +430,11 @@ impl VideoEncoder {
 
             // Start a seperate thread to drive the transform
             self.encoder_thread_handle = Some(std::thread::spawn(move || -> Result<()> {
+                let _span = info_span!("encode").entered();
                 unsafe { MFStartup(MF_VERSION, MFSTARTUP_FULL)? }
                 let result = inner.encode();
-                if result.is_err() {
+                if let Err(error) = &result {
+                    error!("Encoding failed: {:?} - {}", error.code(), error.message());
                     println!("Recording stopped unexpectedly!");
                 }
                 result
@@ -275,6 +444,19 @@ impl VideoEncoder {
         Ok(result)
     }
 
+    /// Encodes the sample and throws the output away, before the encoder is
+    /// started. Hardware encoders take a while to spool up on their first
+    /// frame, which would otherwise delay (or drop) the first frames of the
+    /// recording. The first frame after is encoded as a keyframe.
+    pub fn warm_up(&mut self, sample: VideoEncoderInputSample) -> Result<()> {
+        let inner = self.inner.as_mut().unwrap();
+        inner.warming_up = true;
+        let result = inner.warm_up(sample);
+        inner.warming_up = false;
+        self.keyframe_requested.store(true, Ordering::SeqCst);
+        result
+    }
+
     pub fn stop(&mut self) -> Result<()> {
         if self.started.load(Ordering::SeqCst) {
             assert!(self
@@ -286,6 +468,15 @@ impl VideoEncoder {
         Ok(())
     }
 
+    /// Waits for the encoder to finish, once the sample requested callback
+    /// has run out of samples.
+    pub fn finish(&mut self) -> Result<()> {
+        if self.started.load(Ordering::SeqCst) {
+            self.wait_for_completion()?;
+        }
+        Ok(())
+    }
+
     fn wait_for_completion(&mut self) -> Result<()> {
         let handle = self.encoder_thread_handle.take().unwrap();
         handle.join().unwrap()
@@ -309,6 +500,11 @@ impl VideoEncoder {
         self.inner.as_mut().unwrap().sample_rendered_callback = Some(Box::new(callback));
     }
 
+    /// The next frame sent to the encoder will be encoded as a keyframe.
+    pub fn request_keyframe(&self) {
+        self.keyframe_requested.store(true, Ordering::SeqCst);
+    }
+
     pub fn output_type(&self) -> &IMFMediaType {
         &self.output_type
     }
@@ -325,8 +521,24 @@ unsafe impl Send for VideoEncoderInner {}
 //        = note: `#[warn(non_upper_case_globals)]` on by default
 const MEDIA_ENGINE_TRANFORM_NEED_INPUT: MF_EVENT_TYPE = METransformNeedInput;
 const MEDIA_ENGINE_TRANFORM_HAVE_OUTPUT: MF_EVENT_TYPE = METransformHaveOutput;
+const MEDIA_ENGINE_TRANFORM_DRAIN_COMPLETE: MF_EVENT_TYPE = METransformDrainComplete;
 impl VideoEncoderInner {
+    // Once the device is lost (e.g. to a driver reset), the encoder is
+    // created again on the device the samples are generated on from then on
     fn encode(&mut self) -> Result<()> {
+        loop {
+            let result = self.encode_stream();
+            if self.pending_input.is_none() && !self.is_device_lost() {
+                return result;
+            }
+            match self.next_sample_on_new_device()? {
+                Some(sample) => self.reset_device(sample)?,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn encode_stream(&mut self) -> Result<()> {
         unsafe {
             self.transform
                 .ProcessMessage(MFT_MESSAGE_COMMAND_FLUSH, 0)?;
@@ -335,28 +547,38 @@ impl VideoEncoderInner {
             self.transform
                 .ProcessMessage(MFT_MESSAGE_NOTIFY_START_OF_STREAM, 0)?;
 
-            let mut should_exit = false;
-            while !should_exit {
-                let event = self
-                    .event_generator
-                    .GetEvent(MEDIA_EVENT_GENERATOR_GET_EVENT_FLAGS(0))?;
+            if let Some(event_generator) = self.event_generator.clone() {
+                let mut should_exit = false;
+                while !should_exit {
+                    let event =
+                        event_generator.GetEvent(MEDIA_EVENT_GENERATOR_GET_EVENT_FLAGS(0))?;
 
-                let event_type = MF_EVENT_TYPE(event.GetType()? as i32);
-                match event_type {
-                    MEDIA_ENGINE_TRANFORM_NEED_INPUT => {
-                        should_exit = self.on_transform_input_requested()?;
-                    }
-                    MEDIA_ENGINE_TRANFORM_HAVE_OUTPUT => {
-                        self.on_transform_output_ready()?;
-                    }
-                    _ => {
-                        panic!("Unknown media event type: {}", event_type.0);
+                    let event_type = MF_EVENT_TYPE(event.GetType()? as i32);
+                    match event_type {
+                        MEDIA_ENGINE_TRANFORM_NEED_INPUT => {
+                            should_exit = self.on_transform_input_requested()?;
+                        }
+                        MEDIA_ENGINE_TRANFORM_HAVE_OUTPUT => {
+                            self.on_transform_output_ready()?;
+                        }
+                        _ => {
+                            panic!("Unknown media event type: {}", event_type.0);
+                        }
                     }
                 }
+            } else {
+                while !self.on_transform_input_requested()? {
+                    self.process_available_output()?;
+                }
             }
 
+            // What the encoder still holds was lost along with its device
+            if self.pending_input.is_some() {
+                return Ok(());
+            }
             self.transform
                 .ProcessMessage(MFT_MESSAGE_NOTIFY_END_OF_STREAM, 0)?;
+            self.drain()?;
             self.transform
                 .ProcessMessage(MFT_MESSAGE_NOTIFY_END_STREAMING, 0)?;
             self.transform
@@ -365,42 +587,272 @@ impl VideoEncoderInner {
         Ok(())
     }
 
+    // A stream of its own, so that the encoder starts over for the recording
+    fn warm_up(&mut self, sample: VideoEncoderInputSample) -> Result<()> {
+        unsafe {
+            self.transform
+                .ProcessMessage(MFT_MESSAGE_NOTIFY_BEGIN_STREAMING, 0)?;
+            self.transform
+                .ProcessMessage(MFT_MESSAGE_NOTIFY_START_OF_STREAM, 0)?;
+            // Asynchronous encoders only take input once they ask for it
+            if let Some(event_generator) = self.event_generator.clone() {
+                loop {
+                    let event =
+                        event_generator.GetEvent(MEDIA_EVENT_GENERATOR_GET_EVENT_FLAGS(0))?;
+                    match MF_EVENT_TYPE(event.GetType()? as i32) {
+                        MEDIA_ENGINE_TRANFORM_NEED_INPUT => break,
+                        MEDIA_ENGINE_TRANFORM_HAVE_OUTPUT => self.on_transform_output_ready()?,
+                        event_type => {
+                            panic!("Unknown media event type: {}", event_type.0);
+                        }
+                    }
+                }
+            }
+            self.process_input(&sample)?;
+            self.transform
+                .ProcessMessage(MFT_MESSAGE_NOTIFY_END_OF_STREAM, 0)?;
+            self.drain()?;
+            self.transform
+                .ProcessMessage(MFT_MESSAGE_NOTIFY_END_STREAMING, 0)?;
+        }
+        Ok(())
+    }
+
+    // The encoder may still be holding on to frames (e.g. for B-frames or
+    // lookahead), which would otherwise be lost at the end of the recording.
+    fn drain(&mut self) -> Result<()> {
+        unsafe {
+            self.transform
+                .ProcessMessage(MFT_MESSAGE_COMMAND_DRAIN, 0)?;
+            let event_generator = if let Some(event_generator) = self.event_generator.clone() {
+                event_generator
+            } else {
+                return self.process_available_output();
+            };
+            loop {
+                let event = event_generator.GetEvent(MEDIA_EVENT_GENERATOR_GET_EVENT_FLAGS(0))?;
+
+                let event_type = MF_EVENT_TYPE(event.GetType()? as i32);
+                match event_type {
+                    MEDIA_ENGINE_TRANFORM_HAVE_OUTPUT => self.on_transform_output_ready()?,
+                    MEDIA_ENGINE_TRANFORM_DRAIN_COMPLETE => break,
+                    // There is nothing left to give the encoder
+                    MEDIA_ENGINE_TRANFORM_NEED_INPUT => {}
+                    _ => {
+                        panic!("Unknown media event type: {}", event_type.0);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn is_device_lost(&self) -> bool {
+        unsafe { self.d3d_device.GetDeviceRemovedReason() }.is_err()
+    }
+
+    fn is_on_device(&self, sample: &VideoEncoderInputSample) -> Result<bool> {
+        Ok(unsafe { sample.texture.GetDevice()? } == self.d3d_device)
+    }
+
+    // Samples from the lost device can't be encoded, so they're skipped
+    // until the first one from the new device
+    fn next_sample_on_new_device(&mut self) -> Result<Option<VideoEncoderInputSample>> {
+        if let Some(sample) = self.pending_input.take() {
+            return Ok(Some(sample));
+        }
+        while !self.should_stop.load(Ordering::SeqCst) {
+            match self.sample_requested_callback.as_mut().unwrap()()? {
+                Some(sample) if !self.is_on_device(&sample)? => return Ok(Some(sample)),
+                Some(_) => {}
+                None => break,
+            }
+        }
+        Ok(None)
+    }
+
+    fn reset_device(&mut self, sample: VideoEncoderInputSample) -> Result<()> {
+        let d3d_device = unsafe { sample.texture.GetDevice()? };
+        self.encoder_device.detach_transform()?;
+        let encoder = VideoEncoder::new(
+            &self.encoder_device,
+            d3d_device,
+            self.input_resolution,
+            self.output_resolution,
+            self.settings,
+        )?;
+        let mut inner = encoder.inner.unwrap();
+        inner.sample_requested_callback = self.sample_requested_callback.take();
+        inner.sample_rendered_callback = self.sample_rendered_callback.take();
+        inner.should_stop = self.should_stop.clone();
+        inner.keyframe_requested = self.keyframe_requested.clone();
+        inner.pending_input = Some(sample);
+        *self = inner;
+        Ok(())
+    }
+
     fn on_transform_input_requested(&mut self) -> Result<bool> {
         let mut should_exit = true;
         if !self.should_stop.load(Ordering::SeqCst) {
-            if let Some(sample) = self.sample_requested_callback.as_mut().unwrap()()? {
-                let input_buffer = unsafe {
-                    MFCreateDXGISurfaceBuffer(&ID3D11Texture2D::IID, &sample.texture, 0, false)?
-                };
-                let mf_sample = unsafe { MFCreateSample()? };
-                unsafe {
-                    mf_sample.AddBuffer(&input_buffer)?;
-                    mf_sample.SetSampleTime(sample.timestamp.Duration)?;
-                    self.transform
-                        .ProcessInput(self.input_stream_id, &mf_sample, 0)?;
-                };
+            let sample = match self.pending_input.take() {
+                Some(sample) => Some(sample),
+                None => self.sample_requested_callback.as_mut().unwrap()()?,
+            };
+            if let Some(sample) = sample {
+                // The generator moved to a new device, which needs a new encoder
+                if !self.is_on_device(&sample)? {
+                    self.pending_input = Some(sample);
+                    return Ok(true);
+                }
+                self.process_input(&sample)?;
                 should_exit = false;
             }
         }
         Ok(should_exit)
     }
 
+    fn process_input(&mut self, sample: &VideoEncoderInputSample) -> Result<()> {
+        if let Some(ready) = &sample.ready {
+            ready.wait()?;
+        }
+        let input_buffer =
+            unsafe { MFCreateDXGISurfaceBuffer(&ID3D11Texture2D::IID, &sample.texture, 0, false)? };
+        let mf_sample = unsafe { MFCreateSample()? };
+        unsafe {
+            mf_sample.AddBuffer(&input_buffer)?;
+            mf_sample.SetSampleTime(sample.timestamp.Duration)?;
+            mf_sample.SetSampleDuration(sample.duration.Duration)?;
+            let requested = self.keyframe_requested.swap(false, Ordering::SeqCst);
+            if requested || sample.keyframe {
+                self.force_keyframe()?;
+            }
+            self.transform
+                .ProcessInput(self.input_stream_id, &mf_sample, 0)?;
+        }
+        Ok(())
+    }
+
+    // Used with synchronous encoders, which have output until they ask for
+    // more input
+    fn process_available_output(&mut self) -> Result<()> {
+        loop {
+            match self.on_transform_output_ready() {
+                Ok(()) => {}
+                Err(error) if error.code() == MF_E_TRANSFORM_NEED_MORE_INPUT => return Ok(()),
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    fn force_keyframe(&self) -> Result<()> {
+        let codec_api: ICodecAPI = self.transform.cast()?;
+        set_codec_api_value(&codec_api, &CODECAPI_AVEncVideoForceKeyFrame, 1)
+    }
+
     fn on_transform_output_ready(&mut self) -> Result<()> {
         let mut status = 0;
+        let sample = if let Some(output_sample_size) = self.output_sample_size {
+            unsafe {
+                let sample = MFCreateSample()?;
+                sample.AddBuffer(&MFCreateMemoryBuffer(output_sample_size)?)?;
+                Some(sample)
+            }
+        } else {
+            None
+        };
         let output_buffer = MFT_OUTPUT_DATA_BUFFER {
             dwStreamID: self.output_stream_id,
+            pSample: ManuallyDrop::new(sample),
             ..Default::default()
         };
 
         let sample = unsafe {
             let mut output_buffers = [output_buffer];
-            self.transform
-                .ProcessOutput(0, &mut output_buffers, &mut status)?;
-            output_buffers[0].pSample.as_ref().unwrap().clone()
+            let result = self
+                .transform
+                .ProcessOutput(0, &mut output_buffers, &mut status);
+            let sample = ManuallyDrop::take(&mut output_buffers[0].pSample);
+            result?;
+            sample.unwrap()
         };
 
+        if self.warming_up {
+            return Ok(());
+        }
         let output_sample = VideoEncoderOutputSample { sample };
         self.sample_rendered_callback.as_mut().unwrap()(output_sample)?;
         Ok(())
     }
 }
+
+// 0-100, used for constant quality when no quality is provided
+const DEFAULT_QUALITY: u32 = 70;
+
+fn configure_rate_control(
+    codec_api: &ICodecAPI,
+    rate_control: RateControlMode,
+    settings: &VideoEncoderSettings,
+) -> Result<()> {
+    let result = (|| -> Result<()> {
+        unsafe { codec_api.IsSupported(&CODECAPI_AVEncCommonRateControlMode)? };
+        set_codec_api_value(
+            codec_api,
+            &CODECAPI_AVEncCommonRateControlMode,
+            rate_control.to_mf_mode().0 as u32,
+        )?;
+        match rate_control {
+            RateControlMode::Cbr | RateControlMode::Vbr => set_codec_api_value(
+                codec_api,
+                &CODECAPI_AVEncCommonMeanBitRate,
+                settings.bit_rate,
+            ),
+            RateControlMode::Cqp => set_codec_api_value(
+                codec_api,
+                &CODECAPI_AVEncCommonQuality,
+                settings.quality.unwrap_or(DEFAULT_QUALITY),
+            ),
+        }
+    })();
+    result.map_err(|error| {
+        Error::new(
+            error.code(),
+            format!(
+                "The encoder doesn't support {} rate control! Try a different rate control mode.",
+                rate_control
+            )
+            .into(),
+        )
+    })
+}
+
+fn configure_gop(codec_api: &ICodecAPI, settings: &VideoEncoderSettings) -> Result<()> {
+    if let Some(gop_size) = settings.gop_size {
+        set_codec_api_value(codec_api, &CODECAPI_AVEncMPVGOPSize, gop_size).map_err(|error| {
+            Error::new(
+                error.code(),
+                "The encoder doesn't support setting the GOP size!".into(),
+            )
+        })?;
+    }
+    if let Some(b_frames) = settings.b_frames {
+        set_codec_api_value(codec_api, &CODECAPI_AVEncMPVDefaultBPictureCount, b_frames).map_err(
+            |error| {
+                Error::new(
+                    error.code(),
+                    "The encoder doesn't support setting the number of B-frames!".into(),
+                )
+            },
+        )?;
+    }
+    Ok(())
+}
+
+fn set_color_attributes(media_type: &IMFMediaType, settings: &VideoEncoderSettings) -> Result<()> {
+    for (key, value) in settings
+        .color_format
+        .media_type_attributes(settings.color_range)
+    {
+        unsafe { media_type.SetUINT32(&key, value)? };
+    }
+    Ok(())
+}
@@ -1,30 +1,127 @@
 use windows::{
-    core::{ComInterface, Result},
-    Win32::Media::MediaFoundation::{
-        IMFActivate, IMFTransform, MFMediaType_Video, MFT_FRIENDLY_NAME_Attribute,
-        MFVideoFormat_H264, MFT_CATEGORY_VIDEO_ENCODER, MFT_ENUM_FLAG_HARDWARE,
-        MFT_ENUM_FLAG_SORTANDFILTER, MFT_ENUM_FLAG_TRANSCODE_ONLY, MFT_REGISTER_TYPE_INFO,
+    core::{ComInterface, Result, GUID},
+    Graphics::SizeInt32,
+    Win32::{
+        Foundation::LUID,
+        Media::MediaFoundation::{
+            CODECAPI_AVEncCommonRateControlMode, CODECAPI_AVEncMPVDefaultBPictureCount, ICodecAPI,
+            IMFActivate, IMFAttributes, IMFTransform, MFCreateAttributes, MFCreateMediaType,
+            MFMediaType_Video, MFStartup, MFT_FRIENDLY_NAME_Attribute, MFT_INPUT_TYPES_Attributes,
+            MFT_OUTPUT_TYPES_Attributes, MFVideoFormat_P010, MFVideoInterlace_Progressive,
+            MFSTARTUP_FULL, MFT_CATEGORY_VIDEO_ENCODER, MFT_ENUM_ADAPTER_LUID, MFT_ENUM_FLAG,
+            MFT_ENUM_FLAG_HARDWARE, MFT_ENUM_FLAG_SORTANDFILTER, MFT_ENUM_FLAG_SYNCMFT,
+            MFT_ENUM_FLAG_TRANSCODE_ONLY, MFT_REGISTER_TYPE_INFO, MFT_SET_TYPE_TEST_ONLY,
+            MF_E_ATTRIBUTENOTFOUND, MF_MT_AVG_BITRATE, MF_MT_FRAME_RATE, MF_MT_FRAME_SIZE,
+            MF_MT_INTERLACE_MODE, MF_MT_MAJOR_TYPE, MF_MT_SUBTYPE, MF_TRANSFORM_ASYNC_UNLOCK,
+        },
     },
 };
 
-use crate::media::{enumerate_mfts, get_string_attribute};
+use crate::{
+    adapter::GraphicsAdapter,
+    media::{
+        enumerate_mfts, get_string_attribute, set_codec_api_value, MFSetAttributeRatio,
+        MFSetAttributeSize, MF_VERSION,
+    },
+};
+
+use super::{codec::VideoCodec, rate_control::RateControlMode};
+
+// The resolutions that are tried to find an encoder's maximum, from largest to smallest
+const PROBED_RESOLUTIONS: [(i32, i32); 6] = [
+    (7680, 4320),
+    (5120, 2880),
+    (3840, 2160),
+    (2560, 1440),
+    (1920, 1080),
+    (1280, 720),
+];
+// What the resolutions are probed with
+const PROBED_FRAME_RATE: u32 = 30;
+const PROBED_BIT_RATE: u32 = 10_000_000;
+// The frame rates that are tried when an encoder doesn't take the one asked for
+const CHECKED_FRAME_RATES: [u32; 4] = [120, 60, 30, 24];
 
+#[derive(Clone)]
 pub struct VideoEncoderDevice {
     source: IMFActivate,
     display_name: String,
+    codec: VideoCodec,
+    hardware: bool,
+}
+
+/// Whether an encoder takes a recording's output, see
+/// VideoEncoderDevice::check_output.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OutputSupport {
+    Supported,
+    /// The frames are too large, but the encoder takes them at this size
+    /// (the largest one that keeps the aspect ratio).
+    TooLarge(SizeInt32),
+    /// The encoder takes frames of the size, but at most at this frame rate.
+    FrameRateTooHigh(u32),
+    /// The encoder doesn't take the output, for reasons of its own.
+    Unsupported,
+}
+
+/// What an encoder supports, see VideoEncoderDevice::probe.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EncoderCapabilities {
+    pub codecs: Vec<VideoCodec>,
+    /// The largest of the common 16:9 resolutions that the encoder accepts.
+    pub max_resolution: Option<SizeInt32>,
+    pub rate_control_modes: Vec<RateControlMode>,
+    pub ten_bit: bool,
+    pub b_frames: bool,
 }
 
 impl VideoEncoderDevice {
-    pub fn enumerate() -> Result<Vec<VideoEncoderDevice>> {
+    pub fn enumerate(codec: VideoCodec) -> Result<Vec<VideoEncoderDevice>> {
+        Self::enumerate_with_attributes(codec, MFT_ENUM_FLAG_HARDWARE, None)
+    }
+
+    /// Lists the software encoders (e.g. the Microsoft H.264 Encoder MFT),
+    /// which run on the CPU and work without a hardware encoder.
+    pub fn enumerate_software(codec: VideoCodec) -> Result<Vec<VideoEncoderDevice>> {
+        Self::enumerate_with_attributes(codec, MFT_ENUM_FLAG_SYNCMFT, None)
+    }
+
+    /// Only lists the encoders that live on the adapter, which can encode
+    /// the textures of a D3D device created on the same adapter.
+    pub fn enumerate_for_adapter(
+        codec: VideoCodec,
+        adapter: &GraphicsAdapter,
+    ) -> Result<Vec<VideoEncoderDevice>> {
+        let luid = adapter.luid();
+        let attributes = unsafe {
+            let mut attributes: Option<IMFAttributes> = None;
+            MFCreateAttributes(&mut attributes, 1)?;
+            let attributes = attributes.unwrap();
+            let luid = std::slice::from_raw_parts(
+                &luid as *const LUID as *const u8,
+                std::mem::size_of::<LUID>(),
+            );
+            attributes.SetBlob(&MFT_ENUM_ADAPTER_LUID, luid)?;
+            attributes
+        };
+        Self::enumerate_with_attributes(codec, MFT_ENUM_FLAG_HARDWARE, Some(&attributes))
+    }
+
+    fn enumerate_with_attributes(
+        codec: VideoCodec,
+        flags: MFT_ENUM_FLAG,
+        attributes: Option<&IMFAttributes>,
+    ) -> Result<Vec<VideoEncoderDevice>> {
         let output_info = MFT_REGISTER_TYPE_INFO {
             guidMajorType: MFMediaType_Video,
-            guidSubtype: MFVideoFormat_H264,
+            guidSubtype: codec.subtype(),
         };
         let encoders = enumerate_mfts(
             &MFT_CATEGORY_VIDEO_ENCODER,
-            MFT_ENUM_FLAG_HARDWARE | MFT_ENUM_FLAG_TRANSCODE_ONLY | MFT_ENUM_FLAG_SORTANDFILTER,
+            flags | MFT_ENUM_FLAG_TRANSCODE_ONLY | MFT_ENUM_FLAG_SORTANDFILTER,
             None,
             Some(&output_info),
+            attributes,
         )?;
         let mut encoder_devices = Vec::new();
         for encoder in encoders {
@@ -38,6 +135,8 @@ impl VideoEncoderDevice {
             let encoder_device = VideoEncoderDevice {
                 source: encoder,
                 display_name,
+                codec,
+                hardware: flags == MFT_ENUM_FLAG_HARDWARE,
             };
             encoder_devices.push(encoder_device);
         }
@@ -48,7 +147,293 @@ impl VideoEncoderDevice {
         &self.display_name
     }
 
+    pub fn codec(&self) -> VideoCodec {
+        self.codec
+    }
+
+    pub fn is_hardware(&self) -> bool {
+        self.hardware
+    }
+
     pub fn create_transform(&self) -> Result<IMFTransform> {
         unsafe { self.source.ActivateObject() }
     }
+
+    /// Lets go of the transform, so that create_transform creates a new one
+    /// next time (e.g. once the device it was using was lost).
+    pub fn detach_transform(&self) -> Result<()> {
+        unsafe { self.source.DetachObject() }
+    }
+
+    /// Creates the encoder to ask it what it supports, so it shouldn't be
+    /// used for a recording at the same time.
+    pub fn probe(&self) -> Result<EncoderCapabilities> {
+        unsafe { MFStartup(MF_VERSION, MFSTARTUP_FULL)? }
+        let attributes: IMFAttributes = self.source.cast()?;
+        let mut codecs = Vec::new();
+        for info in get_type_infos(&attributes, &MFT_OUTPUT_TYPES_Attributes)? {
+            if let Some(codec) = VideoCodec::from_subtype(&info.guidSubtype) {
+                if !codecs.contains(&codec) {
+                    codecs.push(codec);
+                }
+            }
+        }
+        let ten_bit = get_type_infos(&attributes, &MFT_INPUT_TYPES_Attributes)?
+            .iter()
+            .any(|info| info.guidSubtype == MFVideoFormat_P010);
+
+        let transform = self.create_probe_transform()?;
+        let max_resolution = get_probed_resolutions().find(|resolution| {
+            is_output_supported(
+                &transform,
+                self.codec,
+                *resolution,
+                PROBED_FRAME_RATE,
+                PROBED_BIT_RATE,
+            )
+        });
+        // The settings are only changed on this instance of the encoder
+        let (rate_control_modes, b_frames) = if let Ok(codec_api) = transform.cast::<ICodecAPI>() {
+            let is_settable = |api: &GUID, value: u32| unsafe {
+                codec_api.IsSupported(api).is_ok()
+                    && set_codec_api_value(&codec_api, api, value).is_ok()
+            };
+            let rate_control_modes = RateControlMode::ALL
+                .into_iter()
+                .filter(|mode| {
+                    is_settable(
+                        &CODECAPI_AVEncCommonRateControlMode,
+                        mode.to_mf_mode().0 as u32,
+                    )
+                })
+                .collect();
+            (
+                rate_control_modes,
+                is_settable(&CODECAPI_AVEncMPVDefaultBPictureCount, 1),
+            )
+        } else {
+            (Vec::new(), false)
+        };
+        unsafe { self.source.ShutdownObject()? };
+
+        Ok(EncoderCapabilities {
+            codecs,
+            max_resolution,
+            rate_control_modes,
+            ten_bit,
+            b_frames,
+        })
+    }
+
+    /// Checks whether the encoder takes frames of the size at the frame rate
+    /// and bit rate, so that a recording it can't encode fails before it's
+    /// set up. Like probe, this creates the encoder.
+    pub fn check_output(
+        &self,
+        size: SizeInt32,
+        frame_rate: u32,
+        bit_rate: u32,
+    ) -> Result<OutputSupport> {
+        unsafe { MFStartup(MF_VERSION, MFSTARTUP_FULL)? }
+        let transform = self.create_probe_transform()?;
+        let is_supported = |size: SizeInt32, frame_rate: u32| {
+            is_output_supported(&transform, self.codec, size, frame_rate, bit_rate)
+        };
+        let support = if is_supported(size, frame_rate) {
+            OutputSupport::Supported
+        } else if let Some(max_frame_rate) = CHECKED_FRAME_RATES
+            .into_iter()
+            .filter(|&checked| checked < frame_rate)
+            .find(|&checked| is_supported(size, checked))
+        {
+            OutputSupport::FrameRateTooHigh(max_frame_rate)
+        } else if let Some(max_size) = get_probed_resolutions()
+            .map(|resolution| fit_within(size, resolution))
+            .filter(|fitted| fitted.Width < size.Width || fitted.Height < size.Height)
+            .find(|fitted| is_supported(*fitted, frame_rate))
+        {
+            OutputSupport::TooLarge(max_size)
+        } else {
+            OutputSupport::Unsupported
+        };
+        unsafe { self.source.ShutdownObject()? };
+        Ok(support)
+    }
+
+    // Hardware encoders are async, and have to be unlocked to set them up
+    fn create_probe_transform(&self) -> Result<IMFTransform> {
+        let transform = self.create_transform()?;
+        if self.hardware {
+            unsafe {
+                transform
+                    .GetAttributes()?
+                    .SetUINT32(&MF_TRANSFORM_ASYNC_UNLOCK, 1)?
+            };
+        }
+        Ok(transform)
+    }
+}
+
+fn get_probed_resolutions() -> impl Iterator<Item = SizeInt32> {
+    PROBED_RESOLUTIONS
+        .into_iter()
+        .map(|(width, height)| SizeInt32 {
+            Width: width,
+            Height: height,
+        })
+}
+
+// Scales the size down to fit within the bounds, keeping its aspect ratio
+// (and the even dimensions encoders need)
+fn fit_within(size: SizeInt32, bounds: SizeInt32) -> SizeInt32 {
+    let scale = (bounds.Width as f64 / size.Width as f64)
+        .min(bounds.Height as f64 / size.Height as f64)
+        .min(1.0);
+    let fit = |value: i32| (((value as f64 * scale) as i32) & !1).max(2);
+    SizeInt32 {
+        Width: fit(size.Width),
+        Height: fit(size.Height),
+    }
+}
+
+// The input or output types that the encoder was registered with
+fn get_type_infos(attributes: &IMFAttributes, key: &GUID) -> Result<Vec<MFT_REGISTER_TYPE_INFO>> {
+    let blob = unsafe {
+        let size = match attributes.GetBlobSize(key) {
+            Ok(size) => size,
+            Err(error) if error.code() == MF_E_ATTRIBUTENOTFOUND => return Ok(Vec::new()),
+            Err(error) => return Err(error),
+        };
+        let mut blob = vec![0u8; size as usize];
+        attributes.GetBlob(key, &mut blob, None)?;
+        blob
+    };
+    Ok(blob
+        .chunks_exact(std::mem::size_of::<MFT_REGISTER_TYPE_INFO>())
+        .map(|chunk| unsafe {
+            std::ptr::read_unaligned(chunk.as_ptr() as *const MFT_REGISTER_TYPE_INFO)
+        })
+        .collect())
+}
+
+fn is_output_supported(
+    transform: &IMFTransform,
+    codec: VideoCodec,
+    size: SizeInt32,
+    frame_rate: u32,
+    bit_rate: u32,
+) -> bool {
+    let result = (|| -> Result<()> {
+        unsafe {
+            let output_type = MFCreateMediaType()?;
+            let attributes: IMFAttributes = output_type.cast()?;
+            output_type.SetGUID(&MF_MT_MAJOR_TYPE, &MFMediaType_Video)?;
+            output_type.SetGUID(&MF_MT_SUBTYPE, &codec.subtype())?;
+            output_type.SetUINT32(&MF_MT_AVG_BITRATE, bit_rate)?;
+            MFSetAttributeSize(
+                &attributes,
+                &MF_MT_FRAME_SIZE,
+                size.Width as u32,
+                size.Height as u32,
+            )?;
+            MFSetAttributeRatio(&attributes, &MF_MT_FRAME_RATE, frame_rate, 1)?;
+            output_type.SetUINT32(&MF_MT_INTERLACE_MODE, MFVideoInterlace_Progressive.0 as u32)?;
+            // Encoders have a single output stream, which is numbered 0
+            transform.SetOutputType(0, &output_type, MFT_SET_TYPE_TEST_ONLY.0 as u32)
+        }
+    })();
+    result.is_ok()
+}
+
+/// How much an encoder is preferred (lower is better) when none was picked.
+/// The encoders of dedicated GPUs are usually the fastest, followed by the
+/// ones built into CPUs.
+pub fn get_encoder_rank(display_name: &str) -> u32 {
+    let name = display_name.to_lowercase();
+    if name.contains("nvidia") || name.contains("nvenc") {
+        0
+    } else if name.contains("amd") || name.contains("radeon") {
+        1
+    } else if name.contains("intel") || name.contains("quick sync") {
+        2
+    } else {
+        3
+    }
+}
+
+/// Describes the lack of encoders for a codec, and which codecs could be used instead.
+pub fn get_no_encoders_message(codec: VideoCodec) -> Result<String> {
+    let mut available_codecs = Vec::new();
+    for other_codec in VideoCodec::ALL {
+        if other_codec != codec && !VideoEncoderDevice::enumerate(other_codec)?.is_empty() {
+            available_codecs.push(other_codec.to_string());
+        }
+    }
+    let mut message = if available_codecs.is_empty() {
+        format!("No hardware {} encoders found!", codec.display_name())
+    } else {
+        format!(
+            "No hardware {} encoders found! Hardware encoders are available for: {}.",
+            codec.display_name(),
+            available_codecs.join(", ")
+        )
+    };
+    if codec == VideoCodec::Vp9 {
+        // WebM requires VP9, so point users at a container that works with the other codecs
+        message.push_str(" Record to a .mkv or .mp4 file with another codec instead.");
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Graphics::SizeInt32;
+
+    use super::{fit_within, get_encoder_rank};
+
+    #[test]
+    fn encoder_rank_test() {
+        let mut names = vec![
+            "Intel® Quick Sync Video H.264 Encoder MFT",
+            "Unknown",
+            "NVIDIA H.264 Encoder MFT",
+            "AMDh264Encoder",
+        ];
+        names.sort_by_key(|name| get_encoder_rank(name));
+        assert_eq!(
+            names,
+            vec![
+                "NVIDIA H.264 Encoder MFT",
+                "AMDh264Encoder",
+                "Intel® Quick Sync Video H.264 Encoder MFT",
+                "Unknown"
+            ]
+        );
+    }
+
+    #[test]
+    fn fit_within_test() {
+        let size = |width, height| SizeInt32 {
+            Width: width,
+            Height: height,
+        };
+        assert_eq!(
+            fit_within(size(7680, 4320), size(3840, 2160)),
+            size(3840, 2160)
+        );
+        // Ultrawide frames are limited by their width
+        assert_eq!(
+            fit_within(size(7680, 2160), size(3840, 2160)),
+            size(3840, 1080)
+        );
+        // Odd sizes are rounded down to even ones, smaller sizes are kept
+        assert_eq!(
+            fit_within(size(4001, 3001), size(2001, 2001)),
+            size(2000, 1500)
+        );
+        assert_eq!(
+            fit_within(size(1280, 720), size(3840, 2160)),
+            size(1280, 720)
+        );
+    }
 }
@@ -1,225 +1,2051 @@
-use std::sync::Arc;
+use std::{
+    collections::VecDeque,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{sync_channel, Receiver},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
 
+use tracing::{debug, error, info, info_span, trace_span, warn};
 use windows::{
-    core::{Result, HSTRING},
+    core::{ComInterface, Error, Result},
     Foundation::TimeSpan,
     Graphics::{
-        Capture::{Direct3D11CaptureFrame, GraphicsCaptureItem, GraphicsCaptureSession},
-        SizeInt32,
+        Capture::{GraphicsCaptureItem, GraphicsCaptureSession},
+        PointInt32, RectInt32, SizeInt32,
     },
-    Storage::Streams::IRandomAccessStream,
     Win32::{
+        Foundation::{E_ABORT, E_INVALIDARG, E_UNEXPECTED, HWND},
         Graphics::{
             Direct3D11::{
-                ID3D11Device, ID3D11DeviceContext, ID3D11RenderTargetView, ID3D11Texture2D,
-                D3D11_BIND_RENDER_TARGET, D3D11_BIND_SHADER_RESOURCE, D3D11_BOX,
+                ID3D11Device, ID3D11DeviceContext, ID3D11Multithread, ID3D11RenderTargetView,
+                ID3D11Texture2D, D3D11_BIND_RENDER_TARGET, D3D11_BIND_SHADER_RESOURCE, D3D11_BOX,
                 D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT,
             },
-            Dxgi::Common::{DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_NV12, DXGI_SAMPLE_DESC},
-        },
-        Media::MediaFoundation::{
-            IMFMediaType, IMFSample, IMFSinkWriter, MFCreateAttributes,
-            MFCreateMFByteStreamOnStreamEx, MFCreateSinkWriterFromURL,
+            Dxgi::Common::{DXGI_FORMAT, DXGI_SAMPLE_DESC},
         },
+        Media::MediaFoundation::{MFStartup, MFSTARTUP_FULL},
+        UI::WindowsAndMessaging::IsIconic,
     },
 };
 
-use crate::{capture::CaptureFrameGenerator, d3d::get_d3d_interface_from_object};
+use crate::{
+    audio::{
+        capture::AudioCapture, encoding_session::AudioEncodingSession, level::AudioLevelMeter,
+        track_layout::AudioTrackLayout,
+    },
+    capture::{
+        create_capture_item_for_monitor, CaptureFrameGenerator, CaptureFrameWait,
+        CaptureStopHandle, CapturedFrame, FrameSource,
+    },
+    d3d::recreate_d3d_device,
+    desktop::is_secure_desktop_shown,
+    displays::find_display_by_device_name,
+    error::{in_stage, RecorderError, RecordingStage},
+    etw,
+    media::MF_VERSION,
+    power::ResumeNotifier,
+    sample_writer::SampleWriter,
+    stats::StatsCounter,
+    timeline::{get_system_relative_time, Timeline},
+};
 
 use super::{
-    encoder::{VideoEncoder, VideoEncoderInputSample},
-    encoder_device::VideoEncoderDevice,
+    bit_depth::BitDepth,
+    canvas::{get_canvas_size, CanvasItem},
+    codec::VideoCodec,
+    color_format::ColorFormat,
+    color_range::ColorRange,
+    content_change::ContentChangeDetector,
+    encoder::{
+        VideoEncoder, VideoEncoderInputSample, VideoEncoderOutputSample, VideoEncoderSettings,
+    },
+    encoder_device::{OutputSupport, VideoEncoderDevice},
+    fit_mode::FitMode,
+    frame_pacing::{FramePacer, FramePacing, PacingAction},
+    frame_rate_mode::FrameRateMode,
+    frame_spool::{FrameSpoolReader, FrameSpoolWriter},
+    gpu_sync::GpuSync,
+    idle_frame_rate::IdleFrameRate,
+    minimize_action::MinimizeAction,
+    ndi::NdiSender,
+    orientation::Orientation,
+    overlay::{
+        position::OverlayPosition, Overlay, OverlayRenderer, PictureInPictureOverlay,
+        PlaceholderOverlay, RedactedWindows, RegionBlur, ScreenOrigin,
+    },
+    preview::Preview,
     processor::VideoProcessor,
+    rate_control::RateControlMode,
+    sample_queue::{PushResult, QueuePolicy, SampleQueue, DEFAULT_QUEUE_SIZE},
+    scale_filter::ScaleFilter,
+    scaler::{get_scaled_size, FrameScaler},
+    scene_change::SceneChangeDetector,
+    thumbnail::Thumbnail,
+    timestamp_sanitizer::{FrameGrid, TimestampSanitizer},
+    zoom::{ZoomFactor, ZoomFollow},
 };
 
+// 18 Mbps
+const DEFAULT_BIT_RATE: u32 = 18_000_000;
+const DEFAULT_FRAME_RATE: u32 = 60;
+const HUNDRED_NANOSECONDS_PER_SECOND: i64 = 10_000_000;
+// Encoded samples are small, so the writer can fall a few seconds behind
+// (e.g. while the disk or network is busy) before it holds up the encoder
+const WRITE_QUEUE_SIZE: usize = 256;
+// A driver reset takes a couple of seconds, during which devices can't be
+// created yet
+const DEVICE_RECOVERY_ATTEMPTS: u32 = 10;
+const DEVICE_RECOVERY_DELAY: Duration = Duration::from_millis(500);
+// How often to look for a display that was disconnected
+const DISPLAY_RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Records the items as a pipeline of threads: frames are captured on the
+/// capture's own thread, converted into samples on the generator thread,
+/// fed to the encoder on the encoder thread, and written on the writer
+/// thread. Each stage hands its output to the next through a queue, so a
+/// stall in one of them doesn't hold up the ones before it right away.
 pub struct VideoEncodingSession {
     video_encoder: VideoEncoder,
-    capture_session: GraphicsCaptureSession,
+    capture_sessions: Vec<GraphicsCaptureSession>,
+    capture_started: bool,
+    // Samples are generated on their own thread, which hands them to the
+    // encoder through the queue
+    sample_generator: Option<SampleGenerator>,
+    sample_queue: SampleQueue<VideoEncoderInputSample>,
+    stop_handle: CaptureStopHandle,
+    generator_thread_handle: Option<JoinHandle<Result<()>>>,
+    // The encoder hands its output to the writer thread
+    write_receiver: Option<Receiver<VideoEncoderOutputSample>>,
+    sample_writer: Arc<SampleWriter>,
+    stream_index: u32,
+    writer_thread_handle: Option<JoinHandle<Result<()>>>,
+    audio_session: Option<AudioEncodingSession>,
+    thumbnail: Option<(Arc<Mutex<Thumbnail>>, String)>,
+    // Samples go to the spool instead of the encoder until the recording
+    // stops, see SessionBuilder::encode_later
+    spool: Option<FrameSpool>,
+    // Ends with the device the frames should be encoded on, see
+    // FrameSpoolWriter::finish
+    spool_thread_handle: Option<JoinHandle<(Option<ID3D11Device>, Result<()>)>>,
+}
+
+// The scratch file, and the frames that are written to it
+struct FrameSpool {
+    path: String,
+    size: SizeInt32,
+    format: DXGI_FORMAT,
+    // Keyframes asked for while recording are marked on the next frame
+    keyframe_requested: Arc<AtomicBool>,
+    on_progress: Option<Box<dyn Fn() + Send>>,
+}
+
+/// Collects the settings for a VideoEncodingSession, see VideoEncodingSession::builder.
+pub struct SessionBuilder<'a> {
+    d3d_device: ID3D11Device,
+    items: Vec<CanvasItem>,
     sample_writer: Arc<SampleWriter>,
+    encoder_devices: &'a [VideoEncoderDevice],
+    resolution: Option<SizeInt32>,
+    settings: VideoEncoderSettings,
+    max_frame_rate: Option<u32>,
+    frame_pacing: FramePacing,
+    region: Option<RectInt32>,
+    capture_cursor: bool,
+    audio: Option<(Vec<AudioCapture>, AudioTrackLayout, Duration)>,
+    preview_title: Option<String>,
+    hdr: bool,
+    bit_depth: BitDepth,
+    overlays: Vec<Box<dyn Overlay>>,
+    blur_regions: Vec<RectInt32>,
+    blur_windows: Option<(RedactedWindows, ScreenOrigin)>,
+    thumbnail_path: Option<String>,
+    ndi_name: Option<String>,
+    minimize: Option<(HWND, MinimizeAction)>,
+    secure_desktop: Option<MinimizeAction>,
+    zoom: Option<(ZoomFactor, ScreenOrigin)>,
+    picture_in_picture: Option<(GraphicsCaptureItem, OverlayPosition, u32)>,
+    queue: (usize, QueuePolicy),
+    on_device_lost: Option<Box<dyn Fn() + Send>>,
+    auto_downscale: bool,
+    frame_source: Option<(Box<dyn FrameSource>, SizeInt32)>,
+    idle_frame_rate: Option<(u32, Duration)>,
+    on_content_change: Option<Box<dyn Fn(i64) + Send>>,
+    scene_change_keyframes: bool,
+    spool_path: Option<String>,
+    on_encode_progress: Option<Box<dyn Fn() + Send>>,
+}
+
+// What the generator composes its frames from, and where each of the
+// source's items goes on the canvas
+struct CanvasSource {
+    frame_source: Box<dyn FrameSource>,
+    size: SizeInt32,
+    positions: Vec<PointInt32>,
+    displays: Vec<Option<String>>,
 }
 
 struct SampleGenerator {
     d3d_device: ID3D11Device,
     d3d_context: ID3D11DeviceContext,
+    gpu_sync: GpuSync,
 
     video_processor: VideoProcessor,
+    scaler: Option<FrameScaler>,
     compose_texture: ID3D11Texture2D,
     render_target_view: ID3D11RenderTargetView,
+    preview: Option<Preview>,
+    overlay_renderer: Option<OverlayRenderer>,
+    thumbnail: Option<Arc<Mutex<Thumbnail>>>,
+    ndi_sender: Option<NdiSender>,
+    zoom: Option<ZoomFollow>,
 
-    frame_generator: CaptureFrameGenerator,
-
-    seen_first_time_stamp: bool,
-    first_timestamp: TimeSpan,
-}
+    input_size: SizeInt32,
+    output_size: SizeInt32,
+    settings: VideoEncoderSettings,
+    frame_source: Box<dyn FrameSource>,
+    positions: Vec<PointInt32>,
+    region: Option<RectInt32>,
+    // The displays of the items (None for windows), which are captured again
+    // once they come back after being disconnected or after a sleep
+    displays: Vec<Option<String>>,
+    disconnected: Vec<usize>,
+    last_reconnect: Instant,
+    resume_notifier: Option<ResumeNotifier>,
+    resume_count: u64,
 
-struct SampleWriter {
-    _stream: IRandomAccessStream,
-    sink_writer: IMFSinkWriter,
-    sink_writer_stream_index: u32,
+    timeline: Timeline,
+    stats: StatsCounter,
+    capture_times: TimestampSanitizer,
+    last_timestamp: Option<i64>,
+    // The timestamp of the last sample that was generated
+    last_sample_timestamp: Option<i64>,
+    // Frames closer together than this are dropped (in 100ns units)
+    min_frame_interval: Option<i64>,
+    frame_rate_mode: FrameRateMode,
+    // The nominal duration of a frame (in 100ns units)
+    frame_duration: i64,
+    // Where the frames fall at a constant frame rate
+    frame_grid: Option<FrameGrid>,
+    pending_sample: Option<VideoEncoderInputSample>,
+    frame_pacer: FramePacer,
+    // Lowers the frame rate while nothing changes, see SessionBuilder::idle_frame_rate
+    idle_frame_rate: Option<IdleFrameRate>,
+    // Called with the timestamps of frames that changed what's recorded
+    on_content_change: Option<Box<dyn Fn(i64) + Send>>,
+    // Compares what's recorded of each frame with the last recorded one
+    content_change_detector: Option<ContentChangeDetector>,
+    // Asks for keyframes at scene changes, see SessionBuilder::scene_change_keyframes
+    scene_change_detector: Option<SceneChangeDetector>,
+    // Whether the frame being recorded changed anything since the last one
+    frame_changed: bool,
+    // Whether frames that changed something were dropped since then
+    unrecorded_changes: bool,
+    // The texture of the last sample, which is repeated to fill gaps
+    last_texture: Option<ID3D11Texture2D>,
+    // Samples that are ready to be encoded, e.g. repeats before a new frame
+    queued_samples: VecDeque<VideoEncoderInputSample>,
+    // The recorded window, and what to record while it's minimized
+    minimize: Option<(HWND, MinimizeAction)>,
+    // What to record while the secure desktop is shown
+    secure_desktop: Option<MinimizeAction>,
+    // Why frames aren't being captured, and since when
+    interruption: Option<(Interruption, Instant)>,
+    // Whether the timeline was paused because of the interruption
+    paused_for_interruption: bool,
+    placeholder_texture: Option<ID3D11Texture2D>,
+    // Called once the recording carries on after the device was lost
+    on_device_lost: Option<Box<dyn Fn() + Send>>,
 }
 
 impl VideoEncodingSession {
-    pub fn new(
+    /// Starts configuring a session that records the items to the sample writer.
+    pub fn builder(
         d3d_device: ID3D11Device,
+        items: Vec<CanvasItem>,
+        sample_writer: Arc<SampleWriter>,
+    ) -> SessionBuilder<'static> {
+        SessionBuilder {
+            d3d_device,
+            items,
+            sample_writer,
+            encoder_devices: &[],
+            resolution: None,
+            settings: VideoEncoderSettings {
+                bit_rate: DEFAULT_BIT_RATE,
+                frame_rate: DEFAULT_FRAME_RATE,
+                frame_rate_mode: FrameRateMode::Constant,
+                rate_control: None,
+                quality: None,
+                gop_size: None,
+                b_frames: None,
+                color_format: ColorFormat::Sdr,
+                color_range: ColorRange::Limited,
+                orientation: Orientation::default(),
+                scale_filter: None,
+                fit_mode: FitMode::Letterbox,
+            },
+            max_frame_rate: None,
+            frame_pacing: FramePacing::Log,
+            region: None,
+            capture_cursor: true,
+            audio: None,
+            preview_title: None,
+            hdr: false,
+            bit_depth: BitDepth::Eight,
+            overlays: Vec::new(),
+            blur_regions: Vec::new(),
+            blur_windows: None,
+            thumbnail_path: None,
+            ndi_name: None,
+            minimize: None,
+            secure_desktop: None,
+            zoom: None,
+            picture_in_picture: None,
+            queue: (DEFAULT_QUEUE_SIZE, QueuePolicy::Block),
+            on_device_lost: None,
+            auto_downscale: false,
+            frame_source: None,
+            idle_frame_rate: None,
+            on_content_change: None,
+            scene_change_keyframes: false,
+            spool_path: None,
+            on_encode_progress: None,
+        }
+    }
+
+    /// Starts capturing ahead of the recording's timeline, so that the first
+    /// frame is already on its way when the recording starts. Frames captured
+    /// before the timeline starts are dropped, unless there's nothing newer
+    /// by then, in which case they're recorded at its start.
+    pub fn start_capture(&mut self) -> Result<()> {
+        if !self.capture_started {
+            for capture_session in &self.capture_sessions {
+                capture_session.StartCapture()?;
+            }
+            self.capture_started = true;
+        }
+        Ok(())
+    }
+
+    pub fn start(&mut self) -> Result<()> {
+        self.start_capture()?;
+        let mut sample_generator = self.sample_generator.take().unwrap();
+        let sample_queue = self.sample_queue.clone();
+        self.generator_thread_handle = Some(std::thread::spawn(move || -> Result<()> {
+            let _span = info_span!("capture").entered();
+            let result = sample_generator.run(&sample_queue);
+            // The encoder finishes whatever is left in the queue
+            sample_queue.close();
+            if let Err(error) = &result {
+                error!("Capture failed: {:?} - {}", error.code(), error.message());
+                println!("Recording stopped unexpectedly!");
+            }
+            result
+        }));
+        if let Some(spool) = &self.spool {
+            // Nothing is written until the frames are encoded
+            let mut spool_writer =
+                FrameSpoolWriter::create(Path::new(&spool.path), spool.size, spool.format)?;
+            let sample_queue = self.sample_queue.clone();
+            let keyframe_requested = spool.keyframe_requested.clone();
+            self.spool_thread_handle = Some(std::thread::spawn(move || {
+                let _span = info_span!("spool").entered();
+                let mut result = Ok(());
+                while let Some(mut sample) = sample_queue.pop() {
+                    if keyframe_requested.swap(false, Ordering::SeqCst) {
+                        sample.request_keyframe();
+                    }
+                    if let Err(error) = spool_writer.write(&sample) {
+                        error!(
+                            "Writing frames failed: {:?} - {}",
+                            error.code(),
+                            error.message()
+                        );
+                        println!("Recording stopped unexpectedly!");
+                        // Stops capturing, the frames that were written
+                        // before are still encoded
+                        sample_queue.close();
+                        result = Err(error);
+                        break;
+                    }
+                }
+                let (d3d_device, finished) = spool_writer.finish();
+                (d3d_device, result.and(finished))
+            }));
+        } else {
+            self.start_writer();
+            assert!(self.video_encoder.try_start()?);
+        }
+        if let Some(audio_session) = self.audio_session.as_mut() {
+            audio_session.start()?;
+        }
+        Ok(())
+    }
+
+    // Writes what the encoder hands over on its own thread
+    fn start_writer(&mut self) {
+        let write_receiver = self.write_receiver.take().unwrap();
+        let sample_writer = self.sample_writer.clone();
+        let stream_index = self.stream_index;
+        self.writer_thread_handle = Some(std::thread::spawn(move || -> Result<()> {
+            let _span = info_span!("write", stream_index).entered();
+            unsafe { MFStartup(MF_VERSION, MFSTARTUP_FULL)? }
+            // Ends once the encoder is done and its sender is dropped
+            for sample in write_receiver {
+                if let Err(error) = sample_writer.write(stream_index, sample.sample()) {
+                    error!("Writing failed: {:?} - {}", error.code(), error.message());
+                    println!("Recording stopped unexpectedly!");
+                    return Err(error);
+                }
+            }
+            Ok(())
+        }));
+    }
+
+    /// Measures the recorded audio, if there is any.
+    pub fn audio_level_meter(&self) -> Option<&AudioLevelMeter> {
+        self.audio_session
+            .as_ref()
+            .map(|audio_session| audio_session.level_meter())
+    }
+
+    /// Encodes the next frame as a keyframe (IDR frame).
+    pub fn request_keyframe(&self) {
+        match &self.spool {
+            Some(spool) => spool.keyframe_requested.store(true, Ordering::SeqCst),
+            None => self.video_encoder.request_keyframe(),
+        }
+    }
+
+    pub fn stop(&mut self) -> std::result::Result<(), RecorderError> {
+        // Closing the queue wakes up both the encoder and the generator,
+        // which may be waiting for a frame
+        self.sample_queue.close();
+        // Capturing stops before spooled frames are encoded, so that it
+        // doesn't hold up the encoder. What was recorded before a capture
+        // error is still encoded.
+        let mut capture_result = Ok(());
+        let mut spool_result = Ok(());
+        let result = match self.spool_thread_handle.take() {
+            Some(handle) => {
+                capture_result = self.stop_generator();
+                let (d3d_device, result) = handle.join().unwrap_or_else(|_| {
+                    let error = Error::new(E_UNEXPECTED, "Writing frames panicked".into());
+                    (None, Err(error))
+                });
+                spool_result = result;
+                // A failed write only lost the frames after it, so what's in
+                // the scratch file is encoded before the error is reported
+                let result = match d3d_device {
+                    Some(d3d_device) => self.encode_spooled_frames(&d3d_device),
+                    // There's no device when nothing was recorded
+                    None => Ok(()),
+                };
+                self.remove_spool();
+                result
+            }
+            None => self.video_encoder.stop(),
+        };
+        // The writer gets through what the encoder left it, and its error
+        // comes first since the encoder only fails because of it
+        if let Some(handle) = self.writer_thread_handle.take() {
+            handle
+                .join()
+                .unwrap()
+                .map_err(in_stage(RecordingStage::Write))?;
+        }
+        spool_result.map_err(in_stage(RecordingStage::Write))?;
+        result.map_err(in_stage(RecordingStage::Encode))?;
+        capture_result?;
+        self.stop_generator()?;
+        if let Some(audio_session) = self.audio_session.as_mut() {
+            audio_session
+                .stop()
+                .map_err(in_stage(RecordingStage::Audio))?;
+        }
+        if let Some((thumbnail, path)) = &self.thumbnail {
+            // The thumbnail is only a convenience, so it shouldn't fail the recording
+            if let Err(error) = thumbnail.lock().unwrap().save(path) {
+                warn!(
+                    "Error saving the thumbnail: {:?} - {}",
+                    error.code(),
+                    error.message()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn stop_generator(&mut self) -> std::result::Result<(), RecorderError> {
+        if let Some(handle) = self.generator_thread_handle.take() {
+            self.stop_handle.stop();
+            handle
+                .join()
+                .unwrap()
+                .map_err(in_stage(RecordingStage::Capture))?;
+        }
+        Ok(())
+    }
+
+    // Feeds the frames in the scratch file to the encoder, and waits for it
+    // to get through them
+    fn encode_spooled_frames(&mut self, d3d_device: &ID3D11Device) -> Result<()> {
+        let spool = self.spool.as_mut().unwrap();
+        let on_progress = spool.on_progress.take();
+        let mut spool_reader =
+            FrameSpoolReader::open(d3d_device, Path::new(&spool.path), spool.size, spool.format)?;
+        println!("Encoding the recorded frames...");
+        self.video_encoder.set_sample_requested_callback(move || {
+            let sample = spool_reader.read()?;
+            if let Some(on_progress) = &on_progress {
+                on_progress();
+            }
+            Ok(sample)
+        });
+        self.start_writer();
+        assert!(self.video_encoder.try_start()?);
+        self.video_encoder.finish()
+    }
+
+    fn remove_spool(&self) {
+        if let Some(spool) = &self.spool {
+            // Leaving the file behind only costs disk space
+            if let Err(error) = std::fs::remove_file(&spool.path) {
+                warn!("Error removing the scratch file: {}", error);
+            }
+        }
+    }
+}
+
+impl<'a> SessionBuilder<'a> {
+    /// The encoders to try in order, each one is only used if the ones before
+    /// it can't be set up. They must all be for the same codec. Defaults to
+    /// the hardware H.264 encoders.
+    pub fn encoders<'b>(self, encoder_devices: &'b [VideoEncoderDevice]) -> SessionBuilder<'b> {
+        SessionBuilder {
+            d3d_device: self.d3d_device,
+            items: self.items,
+            sample_writer: self.sample_writer,
+            encoder_devices,
+            resolution: self.resolution,
+            settings: self.settings,
+            max_frame_rate: self.max_frame_rate,
+            frame_pacing: self.frame_pacing,
+            region: self.region,
+            capture_cursor: self.capture_cursor,
+            audio: self.audio,
+            preview_title: self.preview_title,
+            hdr: self.hdr,
+            bit_depth: self.bit_depth,
+            overlays: self.overlays,
+            blur_regions: self.blur_regions,
+            blur_windows: self.blur_windows,
+            thumbnail_path: self.thumbnail_path,
+            ndi_name: self.ndi_name,
+            minimize: self.minimize,
+            secure_desktop: self.secure_desktop,
+            zoom: self.zoom,
+            picture_in_picture: self.picture_in_picture,
+            queue: self.queue,
+            on_device_lost: self.on_device_lost,
+            auto_downscale: self.auto_downscale,
+            frame_source: self.frame_source,
+            idle_frame_rate: self.idle_frame_rate,
+            on_content_change: self.on_content_change,
+            scene_change_keyframes: self.scene_change_keyframes,
+            spool_path: self.spool_path,
+            on_encode_progress: self.on_encode_progress,
+        }
+    }
+
+    /// The size of the video, defaults to the size of the region or canvas.
+    pub fn resolution(mut self, resolution: SizeInt32) -> Self {
+        self.resolution = Some(resolution);
+        self
+    }
+
+    /// The bit rate to encode at (in bits per second).
+    pub fn bitrate(mut self, bit_rate: u32) -> Self {
+        self.settings.bit_rate = bit_rate;
+        self
+    }
+
+    pub fn frame_rate(mut self, frame_rate: u32) -> Self {
+        self.settings.frame_rate = frame_rate;
+        self
+    }
+
+    /// With a variable frame rate, the frame rate is the maximum frame rate.
+    pub fn frame_rate_mode(mut self, frame_rate_mode: FrameRateMode) -> Self {
+        self.settings.frame_rate_mode = frame_rate_mode;
+        self
+    }
+
+    /// Defaults to whatever the encoder uses, which is usually a constant bit rate.
+    pub fn rate_control(mut self, rate_control: RateControlMode) -> Self {
+        self.settings.rate_control = Some(rate_control);
+        self
+    }
+
+    /// The quality (0-100) to encode at with constant quality rate control.
+    pub fn quality(mut self, quality: u32) -> Self {
+        self.settings.quality = Some(quality);
+        self
+    }
+
+    /// The number of frames between keyframes. Defaults to whatever the encoder uses.
+    pub fn gop_size(mut self, gop_size: u32) -> Self {
+        self.settings.gop_size = Some(gop_size);
+        self
+    }
+
+    /// The number of B-frames between reference frames. Defaults to whatever the encoder uses.
+    pub fn b_frames(mut self, b_frames: u32) -> Self {
+        self.settings.b_frames = Some(b_frames);
+        self
+    }
+
+    /// Captures and encodes HDR10 video, which requires an HEVC encoder.
+    pub fn hdr(mut self, hdr: bool) -> Self {
+        self.hdr = hdr;
+        self
+    }
+
+    /// 10-bit video reduces banding and requires an HEVC encoder. HDR is always 10-bit.
+    pub fn bit_depth(mut self, bit_depth: BitDepth) -> Self {
+        self.bit_depth = bit_depth;
+        self
+    }
+
+    /// Defaults to limited range, which is what most players expect.
+    pub fn color_range(mut self, color_range: ColorRange) -> Self {
+        self.settings.color_range = color_range;
+        self
+    }
+
+    /// Rotates and/or flips the frames before they're encoded. Without a
+    /// resolution, the output size follows the rotation.
+    pub fn orientation(mut self, orientation: Orientation) -> Self {
+        self.settings.orientation = orientation;
+        self
+    }
+
+    /// Scales the frames with a compute shader instead of the video processor,
+    /// which is sharper when downscaling (e.g. 4K to 1080p).
+    pub fn scale_filter(mut self, scale_filter: ScaleFilter) -> Self {
+        self.settings.scale_filter = Some(scale_filter);
+        self
+    }
+
+    /// How the frames are fit into a resolution with a different aspect
+    /// ratio. Defaults to letterboxing.
+    pub fn fit_mode(mut self, fit_mode: FitMode) -> Self {
+        self.settings.fit_mode = fit_mode;
+        self
+    }
+
+    /// Drops frames that arrive faster than this, e.g. from high refresh rate displays.
+    pub fn max_frame_rate(mut self, max_frame_rate: u32) -> Self {
+        self.max_frame_rate = Some(max_frame_rate);
+        self
+    }
+
+    /// What to do with frames that arrive late or leave gaps, defaults to logging late frames.
+    pub fn frame_pacing(mut self, frame_pacing: FramePacing) -> Self {
+        self.frame_pacing = frame_pacing;
+        self
+    }
+
+    /// Drops to the idle frame rate once nothing that's recorded changed for
+    /// a while, and goes back to the full frame rate on the next change.
+    /// Frames are compared as thumbnails to tell.
+    pub fn idle_frame_rate(mut self, idle_frame_rate: u32, idle_after: Duration) -> Self {
+        self.idle_frame_rate = Some((idle_frame_rate, idle_after));
+        self
+    }
+
+    /// Called with the timestamp of each recorded frame that changed what's
+    /// recorded. Like with idle frame rates, frames are compared as
+    /// thumbnails to tell.
+    pub fn on_content_change<F: Fn(i64) + Send + 'static>(mut self, on_content_change: F) -> Self {
+        self.on_content_change = Some(Box::new(on_content_change));
+        self
+    }
+
+    /// Encodes frames that look nothing like the one before them (e.g. a new
+    /// slide) as keyframes, so that seeking lands right on them.
+    pub fn scene_change_keyframes(mut self, scene_change_keyframes: bool) -> Self {
+        self.scene_change_keyframes = scene_change_keyframes;
+        self
+    }
+
+    /// Writes the samples to a scratch file at the path as raw frames while
+    /// recording, and only encodes them once the recording is stopped, e.g.
+    /// when the encoder can't keep up with the frame rate. The file is
+    /// deleted once it's encoded.
+    pub fn encode_later<S: Into<String>>(mut self, spool_path: S) -> Self {
+        self.spool_path = Some(spool_path.into());
+        self
+    }
+
+    /// The callback is invoked for each frame of encode_later that's been
+    /// encoded, on the encoder's thread.
+    pub fn on_encode_progress<F: 'static + Fn() + Send>(mut self, callback: F) -> Self {
+        self.on_encode_progress = Some(Box::new(callback));
+        self
+    }
+
+    /// Only records part of the canvas.
+    pub fn region(mut self, region: RectInt32) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    pub fn capture_cursor(mut self, capture_cursor: bool) -> Self {
+        self.capture_cursor = capture_cursor;
+        self
+    }
+
+    /// Records the audio captures to the same sample writer as the video,
+    /// within the sync tolerance of it.
+    pub fn audio(
+        mut self,
+        audio_captures: Vec<AudioCapture>,
+        layout: AudioTrackLayout,
+        sync_tolerance: Duration,
+    ) -> Self {
+        self.audio = Some((audio_captures, layout, sync_tolerance));
+        self
+    }
+
+    /// Shows what is being recorded in a window with the provided title.
+    pub fn preview<S: Into<String>>(mut self, title: S) -> Self {
+        self.preview_title = Some(title.into());
+        self
+    }
+
+    /// Draws the overlay on top of each frame. Overlays are drawn in the order
+    /// they are added, and are also visible in the preview.
+    pub fn overlay(mut self, overlay: Box<dyn Overlay>) -> Self {
+        self.overlays.push(overlay);
+        self
+    }
+
+    /// Pixelates regions of the frame (relative to its top left corner)
+    /// before the overlays are drawn.
+    pub fn blur_regions(mut self, regions: Vec<RectInt32>) -> Self {
+        self.blur_regions = regions;
+        self
+    }
+
+    /// Pixelates the windows wherever they are in each frame, along with the
+    /// blurred regions.
+    pub fn blur_windows(mut self, windows: RedactedWindows, origin: ScreenOrigin) -> Self {
+        self.blur_windows = Some((windows, origin));
+        self
+    }
+
+    /// Saves a small JPEG of a frame from the recording once it is stopped.
+    /// Not supported for HDR recordings.
+    pub fn thumbnail<S: Into<String>>(mut self, path: S) -> Self {
+        self.thumbnail_path = Some(path.into());
+        self
+    }
+
+    /// Sends the frames (with their overlays) as an NDI source with the
+    /// given name before they're encoded. Not supported for HDR recordings.
+    pub fn ndi<S: Into<String>>(mut self, name: S) -> Self {
+        self.ndi_name = Some(name.into());
+        self
+    }
+
+    /// Shows the latest frame of a second item (another display or a window)
+    /// in an inset, the size of which is a percentage of the width of the video.
+    pub fn picture_in_picture(
+        mut self,
         item: GraphicsCaptureItem,
-        encoder_device: &VideoEncoderDevice,
-        resolution: SizeInt32,
-        bit_rate: u32,
-        frame_rate: u32,
-        stream: IRandomAccessStream,
-    ) -> Result<Self> {
-        let item_size = item.Size()?;
-        let input_size = ensure_even_size(item_size);
+        position: OverlayPosition,
+        size: u32,
+    ) -> Self {
+        self.picture_in_picture = Some((item, position, size));
+        self
+    }
+
+    /// Zooms in on the mouse cursor, which is mapped onto the frames with
+    /// the origin.
+    pub fn zoom_follow(mut self, factor: ZoomFactor, origin: ScreenOrigin) -> Self {
+        self.zoom = Some((factor, origin));
+        self
+    }
+
+    /// What to record while the window is minimized, when recording a window.
+    pub fn on_minimize(mut self, window: HWND, action: MinimizeAction) -> Self {
+        self.minimize = Some((window, action));
+        self
+    }
+
+    /// What to record while the secure desktop (e.g. a UAC prompt or the
+    /// lock screen) is shown, since nothing can be captured. By default the
+    /// video just has a gap, which is logged.
+    pub fn on_secure_desktop(mut self, action: MinimizeAction) -> Self {
+        self.secure_desktop = Some(action);
+        self
+    }
+
+    /// How many samples can wait for the encoder, and what happens to new
+    /// ones once that many are waiting. Defaults to blocking at 3 samples.
+    pub fn sample_queue(mut self, size: usize, policy: QueuePolicy) -> Self {
+        self.queue = (size, policy);
+        self
+    }
+
+    /// Called whenever the GPU was reset (or removed) and the recording
+    /// carries on with a new device, i.e. where the video has a gap.
+    pub fn on_device_lost<F: 'static + Fn() + Send>(mut self, callback: F) -> Self {
+        self.on_device_lost = Some(Box::new(callback));
+        self
+    }
+
+    /// Records at a smaller size (with the same aspect ratio) when the
+    /// encoders can't take frames as large as the resolution, rather than
+    /// failing.
+    pub fn auto_downscale(mut self, auto_downscale: bool) -> Self {
+        self.auto_downscale = auto_downscale;
+        self
+    }
+
+    /// Records the frames of the source (of the size) instead of capturing
+    /// the items, e.g. those of a TestPatternSource in tests.
+    pub fn frame_source(mut self, frame_source: Box<dyn FrameSource>, size: SizeInt32) -> Self {
+        self.frame_source = Some((frame_source, size));
+        self
+    }
+
+    pub fn build(mut self) -> std::result::Result<VideoEncodingSession, RecorderError> {
+        self.settings.color_format = ColorFormat::new(self.hdr, self.bit_depth);
+        let canvas_size = if let Some((_, size)) = &self.frame_source {
+            *size
+        } else if self.items.is_empty() {
+            return Err(invalid_setting("There is nothing to record!").into());
+        } else {
+            get_canvas_size(&self.items)?
+        };
+
+        // When recording a region, the video processor scales from the cropped size
+        let source_size = if let Some(region) = self.region {
+            SizeInt32 {
+                Width: region.Width,
+                Height: region.Height,
+            }
+        } else {
+            canvas_size
+        };
+        let resolution = self
+            .resolution
+            .unwrap_or_else(|| self.settings.orientation.rotation.rotate_size(source_size));
+        validate_settings(
+            canvas_size,
+            self.region,
+            resolution,
+            &self.settings,
+            self.max_frame_rate,
+        )
+        .map_err(invalid_setting)?;
         let output_size = ensure_even_size(resolution);
 
-        let mut video_encoder = VideoEncoder::new(
-            encoder_device,
-            d3d_device.clone(),
+        let default_encoder_devices;
+        let encoder_devices = if !self.encoder_devices.is_empty() {
+            self.encoder_devices
+        } else {
+            default_encoder_devices = VideoEncoderDevice::enumerate(VideoCodec::H264)?;
+            if default_encoder_devices.is_empty() {
+                return Err(invalid_setting("No hardware H.264 encoders found!").into());
+            }
+            &default_encoder_devices
+        };
+        let codec = encoder_devices[0].codec();
+        if self.frame_pacing == FramePacing::Duplicate
+            && self.settings.frame_rate_mode != FrameRateMode::Constant
+        {
+            return Err(invalid_setting(
+                "Duplicating frames requires a constant frame rate! Use --frame-rate-mode cfr.",
+            )
+            .into());
+        }
+        if self.thumbnail_path.is_some() && self.settings.color_format == ColorFormat::Hdr10 {
+            return Err(invalid_setting("Thumbnails aren't supported for HDR recordings!").into());
+        }
+        if self.ndi_name.is_some() && self.settings.color_format == ColorFormat::Hdr10 {
+            return Err(invalid_setting("NDI output isn't supported for HDR recordings!").into());
+        }
+        if self.settings.color_format.is_ten_bit() && codec != VideoCodec::Hevc {
+            return Err(invalid_setting(
+                "HDR and 10-bit recordings require the HEVC codec! Use --codec hevc.",
+            )
+            .into());
+        }
+        // The audio would have to wait for the video
+        if self.spool_path.is_some() && self.audio.is_some() {
+            return Err(invalid_setting("Audio can't be recorded with --encode-later!").into());
+        }
+        let (queue_size, queue_policy) = self.queue;
+        if queue_size == 0 {
+            return Err(
+                invalid_setting("The sample queue needs room for at least one sample!").into(),
+            );
+        }
+
+        let output_size = check_output_support(
+            encoder_devices,
             output_size,
+            &self.settings,
+            self.auto_downscale,
+        )?;
+        let mut video_encoder = create_video_encoder(
+            encoder_devices,
+            &self.d3d_device,
             output_size,
-            bit_rate,
-            frame_rate,
+            self.settings,
         )?;
         let output_type = video_encoder.output_type().clone();
 
-        let mut sample_generator = SampleGenerator::new(d3d_device, item, input_size, output_size)?;
-        let capture_session = sample_generator.capture_session().clone();
-        video_encoder.set_sample_requested_callback(
-            move || -> Result<Option<VideoEncoderInputSample>> { sample_generator.generate() },
+        let canvas = if let Some((frame_source, size)) = self.frame_source {
+            CanvasSource {
+                frame_source,
+                size,
+                positions: vec![PointInt32 { X: 0, Y: 0 }],
+                displays: vec![None],
+            }
+        } else {
+            CanvasSource::capture(
+                &self.d3d_device,
+                self.items,
+                self.settings.color_format,
+                self.capture_cursor,
+            )?
+        };
+        let mut sample_generator = SampleGenerator::new(
+            self.d3d_device,
+            canvas,
+            output_size,
+            self.region,
+            self.sample_writer.timeline().clone(),
+            &self.settings,
+        )?;
+        sample_generator.stats = self.sample_writer.stats().clone();
+        sample_generator.minimize = self.minimize;
+        sample_generator.secure_desktop = self.secure_desktop;
+        sample_generator.on_device_lost = self.on_device_lost;
+        if let Some((factor, origin)) = self.zoom {
+            if self.settings.scale_filter.is_some() {
+                return Err(invalid_setting("Zooming doesn't support --scale-filter!").into());
+            }
+            sample_generator.zoom = Some(ZoomFollow::new(factor, origin));
+        }
+        if let Some((idle_frame_rate, idle_after)) = self.idle_frame_rate {
+            if idle_frame_rate == 0 || idle_frame_rate >= self.settings.frame_rate {
+                return Err(invalid_setting(
+                    "The --idle-fps must be at least 1 and lower than the frame rate!",
+                )
+                .into());
+            }
+            sample_generator.idle_frame_rate =
+                Some(IdleFrameRate::new(idle_frame_rate, idle_after));
+        }
+        sample_generator.on_content_change = self.on_content_change;
+        if sample_generator.idle_frame_rate.is_some()
+            || sample_generator.on_content_change.is_some()
+        {
+            // Frames are compared as BGRA
+            if self.settings.color_format == ColorFormat::Hdr10 {
+                return Err(
+                    invalid_setting("Changes to the screen can't be detected with --hdr!").into(),
+                );
+            }
+            sample_generator.content_change_detector = Some(ContentChangeDetector::new(
+                sample_generator.d3d_device.clone(),
+            ));
+        }
+        if self.scene_change_keyframes {
+            // Frames are compared as BGRA
+            if self.settings.color_format == ColorFormat::Hdr10 {
+                return Err(invalid_setting("Scene changes can't be detected with --hdr!").into());
+            }
+            sample_generator.scene_change_detector = Some(SceneChangeDetector::new(
+                &sample_generator.d3d_device,
+                sample_generator.input_size,
+            )?);
+        }
+        sample_generator.set_frame_timing(
+            self.settings.frame_rate_mode,
+            self.settings.frame_rate,
+            self.max_frame_rate,
+            self.frame_pacing,
         );
+        let blur = if !self.blur_regions.is_empty() || self.blur_windows.is_some() {
+            Some(RegionBlur::new(
+                sample_generator.input_size,
+                self.settings.color_format.texture_format(),
+                self.blur_regions,
+                self.blur_windows,
+            ))
+        } else {
+            None
+        };
+        // The inset goes under the other overlays, and is captured alongside the items
+        let mut capture_sessions = Vec::new();
+        if let Some((item, position, size)) = self.picture_in_picture {
+            let overlay = PictureInPictureOverlay::new(
+                sample_generator.d3d_device.clone(),
+                item,
+                self.settings.color_format.capture_pixel_format(),
+                self.capture_cursor,
+                position,
+                size,
+            )?;
+            capture_sessions = overlay.sessions();
+            self.overlays.insert(0, Box::new(overlay));
+        }
+        if !self.overlays.is_empty() || blur.is_some() {
+            sample_generator.overlay_renderer = Some(OverlayRenderer::new(
+                &sample_generator.d3d_device,
+                sample_generator.input_size,
+                self.settings.color_format.texture_format(),
+                self.overlays,
+                blur,
+            )?);
+        }
+        if let Some(title) = &self.preview_title {
+            sample_generator.preview = Some(Preview::new(
+                &sample_generator.d3d_device,
+                sample_generator.input_size,
+                self.settings.color_format.texture_format(),
+                title,
+            )?);
+        }
+        let thumbnail = if let Some(path) = self.thumbnail_path {
+            let thumbnail = Arc::new(Mutex::new(Thumbnail::new(
+                sample_generator.d3d_device.clone(),
+            )?));
+            sample_generator.thumbnail = Some(thumbnail.clone());
+            Some((thumbnail, path))
+        } else {
+            None
+        };
+        if let Some(name) = &self.ndi_name {
+            sample_generator.ndi_sender = Some(NdiSender::new(
+                sample_generator.d3d_device.clone(),
+                name,
+                self.settings.frame_rate,
+            )?);
+        }
+        capture_sessions.extend(sample_generator.capture_sessions());
+        // Before the callbacks are set, so that nothing is written
+        video_encoder.warm_up(sample_generator.black_sample()?)?;
+        let stop_handle = sample_generator.frame_source.stop_handle();
+        let sample_queue: SampleQueue<VideoEncoderInputSample> =
+            SampleQueue::new(queue_size, queue_policy);
+        // Spooled frames are handed to the encoder once the recording stops
+        let spool = self.spool_path.map(|path| FrameSpool {
+            path,
+            size: output_size,
+            format: self.settings.color_format.encoder_texture_format(),
+            keyframe_requested: Arc::new(AtomicBool::new(false)),
+            on_progress: self.on_encode_progress,
+        });
+        if spool.is_none() {
+            let stats = self.sample_writer.stats().clone();
+            let encoder_queue = sample_queue.clone();
+            video_encoder.set_sample_requested_callback(
+                move || -> Result<Option<VideoEncoderInputSample>> {
+                    let sample = encoder_queue.pop();
+                    if let Some(sample) = &sample {
+                        stats.start_encode(sample.timestamp().Duration);
+                    }
+                    Ok(sample)
+                },
+            );
+        }
 
-        let sample_writer = Arc::new(SampleWriter::new(stream, &output_type)?);
-        video_encoder.set_sample_rendered_callback({
-            let sample_writer = sample_writer.clone();
-            move |sample| -> Result<()> { sample_writer.write(sample.sample()) }
+        // The encoder hands us compressed samples, so the sink writer
+        // doesn't need to do any additional encoding. The encoder keeps the
+        // timestamps, which the sink writer may change (e.g. for segments).
+        let stream_index = self.sample_writer.add_stream(&output_type, &output_type)?;
+        let (write_sender, write_receiver) = sync_channel(WRITE_QUEUE_SIZE);
+        let stats = self.sample_writer.stats().clone();
+        video_encoder.set_sample_rendered_callback(move |sample| -> Result<()> {
+            let timestamp = unsafe { sample.sample().GetSampleTime()? };
+            stats.finish_encode(timestamp);
+            etw::frame_encoded(timestamp, unsafe { sample.sample().GetTotalLength()? });
+            // Only fails once the writer stopped because of an error
+            write_sender.send(sample).map_err(|_| Error::from(E_ABORT))
         });
 
-        Ok(Self {
+        // Audio streams are added after the video stream
+        let audio_session = if let Some((audio_captures, layout, sync_tolerance)) = self.audio {
+            Some(AudioEncodingSession::new(
+                audio_captures,
+                layout,
+                sync_tolerance,
+                self.sample_writer.clone(),
+            )?)
+        } else {
+            None
+        };
+
+        Ok(VideoEncodingSession {
             video_encoder,
-            capture_session,
-            sample_writer,
+            capture_sessions,
+            capture_started: false,
+            sample_generator: Some(sample_generator),
+            sample_queue,
+            stop_handle,
+            generator_thread_handle: None,
+            write_receiver: Some(write_receiver),
+            sample_writer: self.sample_writer,
+            stream_index,
+            writer_thread_handle: None,
+            audio_session,
+            thumbnail,
+            spool,
+            spool_thread_handle: None,
         })
     }
+}
 
-    pub fn start(&mut self) -> Result<()> {
-        self.sample_writer.start()?;
-        self.capture_session.StartCapture()?;
-        assert!(self.video_encoder.try_start()?);
-        Ok(())
-    }
+// Why nothing is being captured
+#[derive(Copy, Clone, PartialEq)]
+enum Interruption {
+    Minimized,
+    SecureDesktop,
+}
 
-    pub fn stop(&mut self) -> Result<()> {
-        self.video_encoder.stop()?;
-        self.sample_writer.stop()?;
-        Ok(())
+impl Interruption {
+    // Shown on the placeholder card
+    fn description(&self) -> &'static str {
+        match self {
+            Interruption::Minimized => "Window minimized",
+            Interruption::SecureDesktop => "Secure desktop shown",
+        }
     }
 }
 
 unsafe impl Send for SampleGenerator {}
+impl CanvasSource {
+    fn capture(
+        d3d_device: &ID3D11Device,
+        items: Vec<CanvasItem>,
+        color_format: ColorFormat,
+        capture_cursor: bool,
+    ) -> Result<Self> {
+        let size = get_canvas_size(&items)?;
+        let positions = items.iter().map(|item| item.position).collect();
+        let displays = items.iter().map(|item| item.display.clone()).collect();
+        let mut capture_items = Vec::new();
+        for canvas_item in items {
+            let capture_size = ensure_even_size(canvas_item.item.Size()?);
+            capture_items.push((canvas_item.item, capture_size));
+        }
+        let frame_source = CaptureFrameGenerator::new(
+            d3d_device.clone(),
+            capture_items,
+            color_format.capture_pixel_format(),
+            capture_cursor,
+        )?;
+        Ok(Self {
+            frame_source: Box::new(frame_source),
+            size,
+            positions,
+            displays,
+        })
+    }
+}
+
 impl SampleGenerator {
     pub fn new(
         d3d_device: ID3D11Device,
-        item: GraphicsCaptureItem,
-        input_size: SizeInt32,
+        canvas: CanvasSource,
         output_size: SizeInt32,
+        region: Option<RectInt32>,
+        timeline: Timeline,
+        settings: &VideoEncoderSettings,
     ) -> Result<Self> {
+        let color_format = settings.color_format;
+        let frame_duration = HUNDRED_NANOSECONDS_PER_SECOND / DEFAULT_FRAME_RATE as i64;
         let d3d_context = unsafe { d3d_device.GetImmediateContext()? };
+        let gpu_sync = GpuSync::new(&d3d_device)?;
+
+        // The video processor scales from either the region or the whole canvas
+        let input_size = ensure_even_size(if let Some(region) = region {
+            SizeInt32 {
+                Width: region.Width,
+                Height: region.Height,
+            }
+        } else {
+            canvas.size
+        });
 
+        let (scaler, processor_input_size) =
+            create_scaler(&d3d_device, input_size, output_size, settings)?;
         let video_processor = VideoProcessor::new(
             d3d_device.clone(),
-            DXGI_FORMAT_B8G8R8A8_UNORM,
-            input_size,
-            DXGI_FORMAT_NV12,
+            processor_input_size,
             output_size,
+            settings,
         )?;
+        let (compose_texture, render_target_view) =
+            create_compose_texture(&d3d_device, input_size, color_format.texture_format())?;
 
-        let texture_desc = D3D11_TEXTURE2D_DESC {
-            Width: input_size.Width as u32,
-            Height: input_size.Height as u32,
-            ArraySize: 1,
-            MipLevels: 1,
-            Format: DXGI_FORMAT_B8G8R8A8_UNORM,
-            SampleDesc: DXGI_SAMPLE_DESC {
-                Count: 1,
-                ..Default::default()
-            },
-            Usage: D3D11_USAGE_DEFAULT,
-            BindFlags: (D3D11_BIND_RENDER_TARGET.0 | D3D11_BIND_SHADER_RESOURCE.0) as u32,
-            ..Default::default()
-        };
-        let compose_texture = unsafe {
-            let mut texture = None;
-            d3d_device.CreateTexture2D(&texture_desc, None, Some(&mut texture))?;
-            texture.unwrap()
-        };
-        let render_target_view = unsafe {
-            let mut rtv = None;
-            d3d_device.CreateRenderTargetView(&compose_texture, None, Some(&mut rtv))?;
-            rtv.unwrap()
+        let CanvasSource {
+            frame_source,
+            positions,
+            displays,
+            ..
+        } = canvas;
+        // Recording carries on without it, just not across a sleep
+        let resume_notifier = if displays.iter().any(Option::is_some) {
+            ResumeNotifier::new().ok()
+        } else {
+            None
         };
 
-        let frame_generator = CaptureFrameGenerator::new(d3d_device.clone(), item, input_size)?;
-
         Ok(Self {
             d3d_device,
             d3d_context,
+            gpu_sync,
 
             video_processor,
+            scaler,
             compose_texture,
             render_target_view,
+            preview: None,
+            overlay_renderer: None,
+            thumbnail: None,
+            ndi_sender: None,
+            zoom: None,
 
-            frame_generator,
+            input_size,
+            output_size,
+            settings: *settings,
+            frame_source,
+            positions,
+            region,
+            displays,
+            disconnected: Vec::new(),
+            last_reconnect: Instant::now(),
+            resume_notifier,
+            resume_count: 0,
 
-            seen_first_time_stamp: false,
-            first_timestamp: TimeSpan::default(),
+            timeline,
+            stats: StatsCounter::default(),
+            capture_times: TimestampSanitizer::new(frame_duration),
+            last_timestamp: None,
+            last_sample_timestamp: None,
+            min_frame_interval: None,
+            frame_rate_mode: FrameRateMode::Constant,
+            frame_duration,
+            frame_grid: Some(FrameGrid::new(DEFAULT_FRAME_RATE)),
+            pending_sample: None,
+            frame_pacer: FramePacer::new(FramePacing::Log, frame_duration, StatsCounter::default()),
+            idle_frame_rate: None,
+            on_content_change: None,
+            content_change_detector: None,
+            scene_change_detector: None,
+            frame_changed: true,
+            unrecorded_changes: false,
+            last_texture: None,
+            queued_samples: VecDeque::new(),
+            minimize: None,
+            secure_desktop: None,
+            interruption: None,
+            paused_for_interruption: false,
+            placeholder_texture: None,
+            on_device_lost: None,
         })
     }
 
-    pub fn capture_session(&self) -> &GraphicsCaptureSession {
-        self.frame_generator.session()
+    pub fn capture_sessions(&self) -> Vec<GraphicsCaptureSession> {
+        self.frame_source.sessions()
     }
 
-    pub fn generate(&mut self) -> Result<Option<VideoEncoderInputSample>> {
-        if let Some(frame) = self.frame_generator.try_get_next_frame()? {
-            let result = self.generate_from_frame(&frame);
-            match result {
-                Ok(sample) => Ok(Some(sample)),
-                Err(error) => {
-                    eprintln!(
-                        "Error during input sample generation: {:?} - {}",
-                        error.code(),
-                        error.message()
-                    );
-                    self.stop_capture()?;
-                    Ok(None)
+    pub fn set_frame_timing(
+        &mut self,
+        frame_rate_mode: FrameRateMode,
+        frame_rate: u32,
+        max_frame_rate: Option<u32>,
+        frame_pacing: FramePacing,
+    ) {
+        self.frame_rate_mode = frame_rate_mode;
+        self.frame_duration = HUNDRED_NANOSECONDS_PER_SECOND / frame_rate.max(1) as i64;
+        self.capture_times = TimestampSanitizer::new(self.frame_duration);
+        self.frame_grid = if frame_rate_mode == FrameRateMode::Constant {
+            Some(FrameGrid::new(frame_rate))
+        } else {
+            None
+        };
+        self.min_frame_interval = max_frame_rate
+            .map(|max_frame_rate| HUNDRED_NANOSECONDS_PER_SECOND / max_frame_rate.max(1) as i64);
+        self.frame_pacer = FramePacer::new(frame_pacing, self.frame_duration, self.stats.clone());
+    }
+
+    // Generates samples until the capture stops or the queue is closed
+    fn run(&mut self, queue: &SampleQueue<VideoEncoderInputSample>) -> Result<()> {
+        loop {
+            let sample = match self.generate() {
+                Ok(Some(sample)) => sample,
+                Ok(None) => break,
+                Err(_) if self.is_device_lost() => {
+                    self.recover_device()?;
+                    continue;
                 }
+                Err(error) => return Err(error),
+            };
+            match queue.push(sample) {
+                PushResult::Queued => {}
+                PushResult::Dropped(_) => self.stats.add_dropped_frame(),
+                PushResult::Closed => break,
             }
-        } else {
-            self.stop_capture()?;
-            Ok(None)
+            etw::queue_depth(queue.len());
         }
+        Ok(())
     }
 
-    fn stop_capture(&mut self) -> Result<()> {
-        self.frame_generator.stop_capture()
-    }
+    pub fn generate(&mut self) -> Result<Option<VideoEncoderInputSample>> {
+        loop {
+            let sample = self.generate_next()?;
+            // Samples that don't move forward (e.g. a repeat at the time of a
+            // frame that was clamped to the last one) would fail the sink writer
+            if let Some(sample) = &sample {
+                let timestamp = sample.timestamp().Duration;
+                if self
+                    .last_sample_timestamp
+                    .is_some_and(|last_timestamp| timestamp <= last_timestamp)
+                {
+                    self.stats.add_dropped_frame();
+                    continue;
+                }
+                self.last_sample_timestamp = Some(timestamp);
+            }
+            if self.frame_rate_mode == FrameRateMode::Constant {
+                return Ok(sample);
+            }
+
+            // Each frame lasts until the next one was captured, so we hold
+            // on to a frame until we know when the next one starts.
+            match (self.pending_sample.take(), sample) {
+                (Some(mut pending_sample), Some(sample)) => {
+                    let duration =
+                        (sample.timestamp().Duration - pending_sample.timestamp().Duration).max(1);
+                    pending_sample.set_duration(TimeSpan { Duration: duration });
+                    self.pending_sample = Some(sample);
+                    return Ok(Some(pending_sample));
+                }
+                (None, Some(sample)) => self.pending_sample = Some(sample),
+                // The last frame keeps the nominal frame duration
+                (pending_sample, None) => return Ok(pending_sample),
+            }
+        }
+    }
+
+    fn generate_next(&mut self) -> Result<Option<VideoEncoderInputSample>> {
+        if let Some(sample) = self.queued_samples.pop_front() {
+            return Ok(Some(sample));
+        }
+        let mut next_frame = self.next_frame()?;
+        while let CaptureFrameWait::Frame(index, frame) = &next_frame {
+            if !self.should_drop_frame(*index, frame)? {
+                break;
+            }
+            // When compositing, a dropped frame may be the only update for its
+            // item, so it still needs to make it onto the compose texture.
+            if self.positions.len() > 1 && !self.timeline.is_paused() {
+                self.compose_frame(*index, frame)?;
+                self.gpu_sync.signal()?.wait()?;
+            }
+            frame.close()?;
+            next_frame = self.next_frame()?;
+        }
+        if let CaptureFrameWait::Frame(index, frame) = next_frame {
+            let result = self.generate_from_frame(index, &frame);
+            match result {
+                // Any repeats of the previous frame go first
+                Ok(sample) => {
+                    self.queued_samples.push_back(sample);
+                    Ok(self.queued_samples.pop_front())
+                }
+                // Recovered from in run
+                Err(error) if self.is_device_lost() => Err(error),
+                Err(error) => {
+                    error!(
+                        "Error during input sample generation: {:?} - {}",
+                        error.code(),
+                        error.message()
+                    );
+                    self.stop_capture()?;
+                    Ok(None)
+                }
+            }
+        } else if let CaptureFrameWait::Timeout = next_frame {
+            // Filled in while capture is interrupted
+            Ok(self.queued_samples.pop_front())
+        } else {
+            self.stop_capture()?;
+            Ok(None)
+        }
+    }
+
+    // Only times out once there are samples queued for an interruption
+    fn next_frame(&mut self) -> Result<CaptureFrameWait<CapturedFrame>> {
+        // Nothing is captured while the window is minimized or the secure
+        // desktop is shown, so we check on both whenever a frame is overdue
+        let timeout = Duration::from_nanos(self.frame_duration as u64 * 100);
+        let mut next_frame = loop {
+            self.reconnect_displays();
+            match self.frame_source.wait_for_frame(timeout)? {
+                CaptureFrameWait::Timeout => {
+                    if self.handle_interruption()? {
+                        return Ok(CaptureFrameWait::Timeout);
+                    }
+                }
+                CaptureFrameWait::Closed(index) => self.disconnect_display(index),
+                next_frame => break next_frame,
+            }
+        };
+        // Capturing starts ahead of the recording, so frames that waited
+        // since before it started are stale, unless there's nothing newer
+        while let CaptureFrameWait::Frame(index, frame) = &next_frame {
+            if !self.timeline.is_before_start(frame.time().Duration) {
+                break;
+            }
+            match self.frame_source.wait_for_frame(Duration::ZERO)? {
+                newer_frame @ CaptureFrameWait::Frame(..) => {
+                    // Another item's frame doesn't replace this one
+                    if self.positions.len() > 1 {
+                        self.compose_frame(*index, frame)?;
+                        self.gpu_sync.signal()?.wait()?;
+                    }
+                    frame.close()?;
+                    next_frame = newer_frame;
+                }
+                CaptureFrameWait::Closed(index) => self.disconnect_display(index),
+                CaptureFrameWait::Timeout => break,
+                CaptureFrameWait::Stopped => {
+                    frame.close()?;
+                    next_frame = CaptureFrameWait::Stopped;
+                }
+            }
+        }
+        if let CaptureFrameWait::Frame(index, frame) = &mut next_frame {
+            frame.set_time(TimeSpan {
+                Duration: self.capture_times.sanitize(frame.time().Duration),
+            });
+            etw::frame_captured(*index, frame.time().Duration);
+            self.restore_from_interruption();
+            if !self.timeline.is_paused() {
+                self.stats.add_captured_frame();
+            }
+        }
+        Ok(next_frame)
+    }
+
+    // A window that was closed is simply no longer updated
+    fn disconnect_display(&mut self, index: usize) {
+        if let Some(display) = &self.displays[index] {
+            if !self.disconnected.contains(&index) {
+                println!(
+                    "Display {} was disconnected, waiting for it to come back...",
+                    display.trim_start_matches(r"\\.\")
+                );
+                self.disconnected.push(index);
+            }
+        }
+    }
+
+    // The capture doesn't survive a sleep, so all displays are captured
+    // again once the system resumes, as are disconnected ones once they're
+    // back. The recording carries on in the same file, with frames of a new
+    // size handled like those of a resized window.
+    fn reconnect_displays(&mut self) {
+        if let Some(resume_notifier) = &self.resume_notifier {
+            let resume_count = resume_notifier.resume_count();
+            if resume_count != self.resume_count {
+                self.resume_count = resume_count;
+                println!("The system resumed from sleep, restarting the capture.");
+                for index in 0..self.displays.len() {
+                    if self.displays[index].is_some()
+                        && !self.disconnected.contains(&index)
+                        && !self.reconnect_display(index)
+                    {
+                        self.disconnected.push(index);
+                    }
+                }
+            }
+        }
+
+        if self.disconnected.is_empty()
+            || self.last_reconnect.elapsed() < DISPLAY_RECONNECT_INTERVAL
+        {
+            return;
+        }
+        self.last_reconnect = Instant::now();
+        for index in std::mem::take(&mut self.disconnected) {
+            if self.reconnect_display(index) {
+                if let Some(display) = &self.displays[index] {
+                    println!(
+                        "Display {} is back, capture resumed.",
+                        display.trim_start_matches(r"\\.\")
+                    );
+                }
+            } else {
+                self.disconnected.push(index);
+            }
+        }
+    }
+
+    // Returns whether the display is being captured again
+    fn reconnect_display(&mut self, index: usize) -> bool {
+        let display_handle = match self.displays[index]
+            .as_deref()
+            .and_then(find_display_by_device_name)
+        {
+            Some(display_handle) => display_handle,
+            None => return false,
+        };
+        // The display may not be ready yet, e.g. right after it was connected
+        let result = create_capture_item_for_monitor(display_handle)
+            .and_then(|item| self.frame_source.replace_item(index, item));
+        if let Err(error) = &result {
+            debug!(
+                "Could not capture the display again: {:?} - {}",
+                error.code(),
+                error.message()
+            );
+        }
+        result.is_ok()
+    }
+
+    // Returns whether any samples were queued
+    fn handle_interruption(&mut self) -> Result<bool> {
+        let (interruption, action) = if is_secure_desktop_shown() {
+            (Interruption::SecureDesktop, self.secure_desktop)
+        } else if let Some((_, action)) = self
+            .minimize
+            .filter(|(window, _)| unsafe { IsIconic(*window).as_bool() })
+        {
+            (Interruption::Minimized, Some(action))
+        } else {
+            self.restore_from_interruption();
+            return Ok(false);
+        };
+        if self.interruption.map(|(current, _)| current) != Some(interruption) {
+            // E.g. the screen was locked while the window was minimized
+            self.restore_from_interruption();
+            self.interruption = Some((interruption, Instant::now()));
+            if interruption == Interruption::SecureDesktop {
+                println!("Capture was interrupted by the secure desktop (e.g. a UAC prompt or the lock screen).");
+            }
+            match action {
+                Some(MinimizeAction::Pause) => {
+                    // Don't take over a pause that was already requested
+                    if !self.timeline.is_paused() {
+                        self.timeline.pause();
+                        self.paused_for_interruption = true;
+                    }
+                }
+                Some(MinimizeAction::Hold) | None => {}
+                Some(MinimizeAction::Placeholder) => {
+                    self.placeholder_texture = Some(self.render_placeholder()?);
+                }
+            }
+        }
+
+        let texture = match action {
+            Some(MinimizeAction::Pause) | None => return Ok(false),
+            Some(MinimizeAction::Hold) => self.last_texture.clone(),
+            Some(MinimizeAction::Placeholder) => self.placeholder_texture.clone(),
+        };
+        if let Some(texture) = texture {
+            if self.timeline.is_paused() {
+                return Ok(false);
+            }
+            let timestamp = self.timeline.elapsed();
+            if let Some(last_timestamp) = self.last_timestamp {
+                if timestamp - last_timestamp < self.frame_duration {
+                    return Ok(false);
+                }
+            }
+            self.last_timestamp = Some(timestamp);
+            self.frame_pacer.record(timestamp);
+            self.queued_samples.push_back(VideoEncoderInputSample::new(
+                TimeSpan {
+                    Duration: timestamp,
+                },
+                TimeSpan {
+                    Duration: self.frame_duration,
+                },
+                texture,
+            ));
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn restore_from_interruption(&mut self) {
+        if let Some((interruption, since)) = self.interruption.take() {
+            if interruption == Interruption::SecureDesktop {
+                println!(
+                    "Capture resumed after a gap of {:.1}s.",
+                    since.elapsed().as_secs_f32()
+                );
+            }
+            self.placeholder_texture = None;
+            if self.paused_for_interruption {
+                self.timeline.resume();
+                self.paused_for_interruption = false;
+            }
+        }
+    }
+
+    // A black frame, processed like a captured one, to warm up the encoder
+    // with (see VideoEncoder::warm_up)
+    fn black_sample(&mut self) -> Result<VideoEncoderInputSample> {
+        unsafe {
+            self.d3d_context
+                .ClearRenderTargetView(&self.render_target_view, &CLEAR_COLOR);
+        }
+        let texture = if let Some(scaler) = self.scaler.as_mut() {
+            let scaled_texture = scaler.scale(&self.compose_texture)?;
+            self.video_processor.process_texture(&scaled_texture)?
+        } else {
+            self.video_processor
+                .process_texture(&self.compose_texture)?
+        };
+        let mut sample = VideoEncoderInputSample::new(
+            TimeSpan { Duration: 0 },
+            TimeSpan {
+                Duration: self.frame_duration,
+            },
+            texture,
+        );
+        sample.set_ready(self.gpu_sync.signal()?);
+        Ok(sample)
+    }
+
+    // The card is drawn over the whole frame, then processed like one
+    fn render_placeholder(&mut self) -> Result<ID3D11Texture2D> {
+        let mut renderer = OverlayRenderer::new(
+            &self.d3d_device,
+            self.input_size,
+            self.settings.color_format.texture_format(),
+            vec![Box::new(PlaceholderOverlay::new(
+                self.interruption.unwrap().0.description(),
+            )?)],
+            None,
+        )?;
+        let card_texture = renderer.render(&self.compose_texture)?;
+        if let Some(scaler) = self.scaler.as_mut() {
+            let scaled_texture = scaler.scale(&card_texture)?;
+            self.video_processor.process_texture(&scaled_texture)
+        } else {
+            self.video_processor.process_texture(&card_texture)
+        }
+    }
+
+    fn should_drop_frame(&mut self, index: usize, frame: &CapturedFrame) -> Result<bool> {
+        // Frames that arrive while the recording is paused are dropped
+        if self.timeline.is_paused() {
+            self.unrecorded_changes = true;
+            return Ok(true);
+        }
+        // What changed in a dropped frame still has to be recorded, even if
+        // the next frame doesn't change anything
+        let changed = self.has_frame_changed(index, frame)?;
+        let changed = changed || self.unrecorded_changes;
+        let drop = self.should_drop_changed_frame(frame, changed)?;
+        if drop {
+            self.unrecorded_changes = changed;
+        } else {
+            self.frame_changed = changed;
+            self.unrecorded_changes = false;
+            if let Some(detector) = self.content_change_detector.as_mut() {
+                detector.record(index);
+            }
+        }
+        Ok(drop)
+    }
+
+    // Whether the recorded part of the frame changed since the last recorded
+    // frame of its item
+    fn has_frame_changed(&mut self, index: usize, frame: &CapturedFrame) -> Result<bool> {
+        let source_rect = self.get_source_rect(frame);
+        match self.content_change_detector.as_mut() {
+            Some(detector) => detector.has_changed(index, frame.texture(), source_rect),
+            None => Ok(true),
+        }
+    }
+
+    fn should_drop_changed_frame(&mut self, frame: &CapturedFrame, changed: bool) -> Result<bool> {
+        let frame_time = frame.time();
+        let timestamp = self
+            .timeline
+            .relative_time(frame_time.Duration)
+            .unwrap_or_default();
+        // As are frames that arrive faster than the maximum frame rate
+        if let (Some(min_frame_interval), Some(last_timestamp)) =
+            (self.min_frame_interval, self.last_timestamp)
+        {
+            if timestamp - last_timestamp < min_frame_interval {
+                self.stats.add_dropped_frame();
+                return Ok(true);
+            }
+        }
+
+        let timestamp = timestamp.max(self.last_timestamp.unwrap_or_default());
+        let latency = get_system_relative_time() - frame_time.Duration;
+        match self.frame_pacer.pace(timestamp, latency) {
+            PacingAction::Drop => Ok(true),
+            PacingAction::Encode(duplicates) => {
+                if let Some(texture) = &self.last_texture {
+                    for duplicate in duplicates {
+                        // Gaps are only filled at the idle frame rate while idle
+                        if let Some(idle_frame_rate) = self.idle_frame_rate.as_mut() {
+                            if !idle_frame_rate.should_encode(duplicate, false) {
+                                continue;
+                            }
+                        }
+                        self.queued_samples.push_back(VideoEncoderInputSample::new(
+                            TimeSpan {
+                                Duration: duplicate,
+                            },
+                            TimeSpan {
+                                Duration: self.frame_duration,
+                            },
+                            texture.clone(),
+                        ));
+                    }
+                }
+                // Unchanged frames only make it in at the idle frame rate,
+                // nothing is lost by the others
+                if let Some(idle_frame_rate) = self.idle_frame_rate.as_mut() {
+                    return Ok(!idle_frame_rate.should_encode(timestamp, changed));
+                }
+                Ok(false)
+            }
+        }
+    }
+
+    fn is_device_lost(&self) -> bool {
+        unsafe { self.d3d_device.GetDeviceRemovedReason() }.is_err()
+    }
+
+    // Waits for the GPU to come back (e.g. from a driver reset), and moves
+    // everything over to a new device. What was captured in the meantime is lost.
+    fn recover_device(&mut self) -> Result<()> {
+        println!("The GPU was reset, recovering...");
+        if let Err(reason) = unsafe { self.d3d_device.GetDeviceRemovedReason() } {
+            info!(
+                "The device was removed: {:?} - {}",
+                reason.code(),
+                reason.message()
+            );
+        }
+        let mut attempt = 1;
+        let d3d_device = loop {
+            match recreate_d3d_device(&self.d3d_device) {
+                Ok(d3d_device) => break d3d_device,
+                Err(error) if attempt < DEVICE_RECOVERY_ATTEMPTS => {
+                    debug!(
+                        "Could not recreate the device (attempt {}): {:?} - {}",
+                        attempt,
+                        error.code(),
+                        error.message()
+                    );
+                    attempt += 1;
+                    std::thread::sleep(DEVICE_RECOVERY_DELAY);
+                }
+                Err(error) => return Err(error),
+            }
+        };
+        self.reset_device(d3d_device)?;
+        println!("Recording resumed on the new device.");
+        if let Some(on_device_lost) = &self.on_device_lost {
+            on_device_lost();
+        }
+        Ok(())
+    }
+
+    fn reset_device(&mut self, d3d_device: ID3D11Device) -> Result<()> {
+        // The encoder shares the immediate context, as it did with the old device
+        unsafe {
+            let multithread: ID3D11Multithread = d3d_device.cast()?;
+            multithread.SetMultithreadProtected(true);
+        }
+        let format = self.settings.color_format.texture_format();
+        let (compose_texture, render_target_view) =
+            create_compose_texture(&d3d_device, self.input_size, format)?;
+        let (scaler, processor_input_size) = create_scaler(
+            &d3d_device,
+            self.input_size,
+            self.output_size,
+            &self.settings,
+        )?;
+        self.video_processor = VideoProcessor::new(
+            d3d_device.clone(),
+            processor_input_size,
+            self.output_size,
+            &self.settings,
+        )?;
+        self.frame_source.reset_device(d3d_device.clone())?;
+        if let Some(detector) = self.content_change_detector.as_mut() {
+            detector.reset_device(d3d_device.clone());
+        }
+        if self.scene_change_detector.is_some() {
+            self.scene_change_detector =
+                Some(SceneChangeDetector::new(&d3d_device, self.input_size)?);
+        }
+        if let Some(overlay_renderer) = self.overlay_renderer.as_mut() {
+            overlay_renderer.reset_device(&d3d_device)?;
+        }
+        if let Some(preview) = self.preview.as_mut() {
+            if let Err(error) = preview.reset_device(&d3d_device, self.input_size, format) {
+                warn!(
+                    "Error during preview: {:?} - {}",
+                    error.code(),
+                    error.message()
+                );
+                self.preview = None;
+            }
+        }
+        if let Some(thumbnail) = &self.thumbnail {
+            thumbnail.lock().unwrap().reset_device(d3d_device.clone())?;
+        }
+        if let Some(ndi_sender) = self.ndi_sender.as_mut() {
+            if let Err(error) = ndi_sender.reset_device(d3d_device.clone()) {
+                warn!(
+                    "Error sending to NDI: {:?} - {}",
+                    error.code(),
+                    error.message()
+                );
+                self.ndi_sender = None;
+            }
+        }
+        self.d3d_context = unsafe { d3d_device.GetImmediateContext()? };
+        self.gpu_sync = GpuSync::new(&d3d_device)?;
+        self.d3d_device = d3d_device;
+        self.compose_texture = compose_texture;
+        self.render_target_view = render_target_view;
+        self.scaler = scaler;
+
+        // Samples that weren't handed to the encoder yet are on the old device
+        self.pending_sample = None;
+        self.queued_samples.clear();
+        self.last_texture = None;
+        if self.placeholder_texture.is_some() {
+            self.placeholder_texture = Some(self.render_placeholder()?);
+        }
+        Ok(())
+    }
+
+    fn stop_capture(&mut self) -> Result<()> {
+        self.preview = None;
+        self.ndi_sender = None;
+        self.frame_source.stop_capture()
+    }
 
     fn generate_from_frame(
         &mut self,
-        frame: &Direct3D11CaptureFrame,
+        index: usize,
+        frame: &CapturedFrame,
     ) -> Result<VideoEncoderInputSample> {
-        let frame_time = frame.SystemRelativeTime()?;
+        let _span = trace_span!("convert", index).entered();
+        let frame_time = frame.time();
+
+        // The timeline is started before capture begins. When compositing, frames
+        // from different items can arrive slightly out of order, so make sure
+        // time never goes backwards.
+        let timestamp = self
+            .timeline
+            .relative_time(frame_time.Duration)
+            .unwrap_or_default();
+        let timestamp = match self.frame_grid.as_mut() {
+            Some(frame_grid) => frame_grid.snap(timestamp),
+            None => timestamp,
+        }
+        .max(self.last_timestamp.unwrap_or_default());
+        self.last_timestamp = Some(timestamp);
+        if self.frame_changed {
+            if let Some(on_content_change) = &self.on_content_change {
+                on_content_change(timestamp);
+            }
+        }
 
-        if !self.seen_first_time_stamp {
-            self.first_timestamp = frame_time;
-            self.seen_first_time_stamp = true;
+        // Nothing that's recorded changed, so the last sample is repeated
+        // without copying or processing the frame. Overlays and zooming can
+        // change the output on their own.
+        if self.overlay_renderer.is_none() && self.zoom.is_none() && !self.frame_changed {
+            if let Some(texture) = self.last_texture.clone() {
+                frame.close()?;
+                self.stats.add_duplicated_frames(1);
+                return Ok(VideoEncoderInputSample::new(
+                    TimeSpan {
+                        Duration: timestamp,
+                    },
+                    TimeSpan {
+                        Duration: self.frame_duration,
+                    },
+                    texture,
+                ));
+            }
         }
 
+        self.compose_frame(index, frame)?;
+        let copied = self.gpu_sync.signal()?;
+        let frame_texture = if let Some(overlay_renderer) = self.overlay_renderer.as_mut() {
+            overlay_renderer.render(&self.compose_texture)?
+        } else {
+            self.compose_texture.clone()
+        };
+        if let Some(thumbnail) = &self.thumbnail {
+            thumbnail
+                .lock()
+                .unwrap()
+                .update(&frame_texture, self.input_size, timestamp)?;
+        }
+        let scene_change = match self.scene_change_detector.as_mut() {
+            Some(detector) if self.frame_changed => {
+                detector.is_scene_change(&frame_texture, timestamp)?
+            }
+            _ => false,
+        };
         let timestamp = TimeSpan {
-            Duration: frame_time.Duration - self.first_timestamp.Duration,
+            Duration: timestamp,
+        };
+
+        if let Some(preview) = self.preview.as_mut() {
+            // The preview is only a convenience, so it shouldn't end the recording
+            if let Err(error) = preview.present(&frame_texture) {
+                warn!(
+                    "Error during preview: {:?} - {}",
+                    error.code(),
+                    error.message()
+                );
+                self.preview = None;
+            }
+        }
+        if let Some(ndi_sender) = self.ndi_sender.as_mut() {
+            // Same for NDI, the recording carries on without it
+            if let Err(error) = ndi_sender.send(&frame_texture, self.input_size) {
+                warn!(
+                    "Error sending to NDI: {:?} - {}",
+                    error.code(),
+                    error.message()
+                );
+                self.ndi_sender = None;
+            }
+        }
+
+        if let Some(zoom) = self.zoom.as_mut() {
+            let output_size = self
+                .settings
+                .orientation
+                .rotation
+                .rotate_size(self.output_size);
+            let source_rect = zoom.next_rect(timestamp.Duration, self.input_size, output_size)?;
+            self.video_processor.set_source_rect(source_rect);
+        }
+
+        // Process our back buffer, straight into the texture of the sample
+        // (NV12 or P010). It isn't reused until the encoder is done with it.
+        let sample_texture = if let Some(scaler) = self.scaler.as_mut() {
+            let scaled_texture = scaler.scale(&frame_texture)?;
+            self.video_processor.process_texture(&scaled_texture)?
+        } else {
+            self.video_processor.process_texture(&frame_texture)?
         };
-        let content_size = frame.ContentSize()?;
-        let frame_texture: ID3D11Texture2D = get_d3d_interface_from_object(&frame.Surface()?)?;
+
+        let processed = self.gpu_sync.signal()?;
+
+        // Release the frame back to the frame pool, once it's been copied so
+        // that the capture can't render over it first
+        copied.wait()?;
+        frame.close()?;
+        self.last_texture = Some(sample_texture.clone());
+
+        let mut sample = VideoEncoderInputSample::new(
+            timestamp,
+            TimeSpan {
+                Duration: self.frame_duration,
+            },
+            sample_texture,
+        );
+        sample.set_ready(processed);
+        if scene_change {
+            sample.request_keyframe();
+        }
+        Ok(sample)
+    }
+
+    fn resize_input(&mut self, input_size: SizeInt32) -> Result<()> {
+        let format = self.settings.color_format.texture_format();
+        let (compose_texture, render_target_view) =
+            create_compose_texture(&self.d3d_device, input_size, format)?;
+        let (scaler, processor_input_size) = create_scaler(
+            &self.d3d_device,
+            input_size,
+            self.output_size,
+            &self.settings,
+        )?;
+        self.video_processor.resize_input(processor_input_size)?;
+        if let Some(overlay_renderer) = self.overlay_renderer.as_mut() {
+            overlay_renderer.resize(input_size)?;
+        }
+        if let Some(preview) = self.preview.as_mut() {
+            if let Err(error) = preview.resize(input_size) {
+                warn!(
+                    "Error during preview: {:?} - {}",
+                    error.code(),
+                    error.message()
+                );
+                self.preview = None;
+            }
+        }
+        if self.scene_change_detector.is_some() {
+            self.scene_change_detector =
+                Some(SceneChangeDetector::new(&self.d3d_device, input_size)?);
+        }
+        self.compose_texture = compose_texture;
+        self.render_target_view = render_target_view;
+        self.scaler = scaler;
+        self.input_size = input_size;
+        Ok(())
+    }
+
+    // The part of the frame that's recorded
+    fn get_source_rect(&self, frame: &CapturedFrame) -> RectInt32 {
+        self.region.unwrap_or(RectInt32 {
+            X: 0,
+            Y: 0,
+            Width: frame.content_size().Width,
+            Height: frame.content_size().Height,
+        })
+    }
+
+    fn compose_frame(&mut self, index: usize, frame: &CapturedFrame) -> Result<()> {
+        let content_size = frame.content_size();
+        // A resized window (or display) is scaled to fit the output, rather
+        // than cut off or padded. Regions and composites keep their layout.
+        if self.positions.len() == 1 && self.region.is_none() {
+            let size = ensure_even_size(content_size);
+            if size != self.input_size && size.Width > 0 && size.Height > 0 {
+                self.resize_input(size)?;
+            }
+        }
+        let frame_texture = frame.texture();
         let desc = unsafe {
             let mut desc = D3D11_TEXTURE2D_DESC::default();
             frame_texture.GetDesc(&mut desc);
@@ -230,108 +2056,123 @@ impl SampleGenerator {
         // the buffer that contains the window. If the window is smaller than the buffer,
         // then it's a straight forward copy using the ContentSize. If the window is larger,
         // we need to clamp to the size of the buffer. For simplicity, we always clamp.
-        let width = content_size.Width.clamp(0, desc.Width as i32) as u32;
-        let height = content_size.Height.clamp(0, desc.Height as i32) as u32;
+        let max_width = content_size.Width.clamp(0, desc.Width as i32);
+        let max_height = content_size.Height.clamp(0, desc.Height as i32);
+
+        // If we're recording a region, only copy out that part of the frame.
+        let (left, top, right, bottom) = if let Some(region) = self.region {
+            (
+                region.X,
+                region.Y,
+                region.X + region.Width,
+                region.Y + region.Height,
+            )
+        } else {
+            (0, 0, max_width, max_height)
+        };
+        // Items are placed on the compose texture based on their position, which
+        // we also clamp to so that we never copy outside of the texture.
+        let position = self.positions[index];
+        let right = right
+            .clamp(0, max_width)
+            .min(left + self.input_size.Width - position.X);
+        let bottom = bottom
+            .clamp(0, max_height)
+            .min(top + self.input_size.Height - position.Y);
 
         let region = D3D11_BOX {
-            left: 0,
-            right: width,
-            top: 0,
-            bottom: height,
+            left: left.clamp(0, right) as u32,
+            right: right as u32,
+            top: top.clamp(0, bottom) as u32,
+            bottom: bottom as u32,
             back: 1,
             front: 0,
         };
 
         unsafe {
-            self.d3d_context
-                .ClearRenderTargetView(&self.render_target_view, &CLEAR_COLOR);
+            // When compositing, the compose texture keeps the last frame from
+            // each item so that items that haven't changed stay visible.
+            if self.positions.len() == 1 {
+                self.d3d_context
+                    .ClearRenderTargetView(&self.render_target_view, &CLEAR_COLOR);
+            }
             self.d3d_context.CopySubresourceRegion(
                 &self.compose_texture,
                 0,
+                position.X as u32,
+                position.Y as u32,
                 0,
-                0,
-                0,
-                &frame_texture,
+                frame_texture,
                 0,
                 Some(&region),
             );
-
-            // Process our back buffer
-            self.video_processor
-                .process_texture(&self.compose_texture)?;
-
-            // Get our NV12 texture
-            let video_output_texture = self.video_processor.output_texture();
-
-            // Make a copy for the sample
-            let desc = {
-                let mut desc = D3D11_TEXTURE2D_DESC::default();
-                video_output_texture.GetDesc(&mut desc);
-                desc
-            };
-            let sample_texture = {
-                let mut texture = None;
-                self.d3d_device
-                    .CreateTexture2D(&desc, None, Some(&mut texture))?;
-                texture.unwrap()
-            };
-            self.d3d_context
-                .CopyResource(&sample_texture, video_output_texture);
-
-            // Release the frame back to the frame pool
-            frame.Close()?;
-
-            Ok(VideoEncoderInputSample::new(timestamp, sample_texture))
         }
+        Ok(())
     }
 }
 
-unsafe impl Send for SampleWriter {}
-unsafe impl Sync for SampleWriter {}
-impl SampleWriter {
-    pub fn new(stream: IRandomAccessStream, output_type: &IMFMediaType) -> Result<Self> {
-        let empty_attributes = unsafe {
-            let mut attributes = None;
-            MFCreateAttributes(&mut attributes, 0)?;
-            attributes.unwrap()
-        };
-        let sink_writer = unsafe {
-            let byte_stream = MFCreateMFByteStreamOnStreamEx(&stream)?;
-            MFCreateSinkWriterFromURL(&HSTRING::from(".mp4"), &byte_stream, &empty_attributes)?
-        };
-        let sink_writer_stream_index = unsafe { sink_writer.AddStream(output_type)? };
-        unsafe {
-            sink_writer.SetInputMediaType(
-                sink_writer_stream_index,
-                output_type,
-                &empty_attributes,
-            )?
-        };
-
-        Ok(Self {
-            _stream: stream,
-            sink_writer,
-            sink_writer_stream_index,
-        })
-    }
-
-    pub fn start(&self) -> Result<()> {
-        unsafe { self.sink_writer.BeginWriting() }
-    }
-
-    pub fn stop(&self) -> Result<()> {
-        unsafe { self.sink_writer.Finalize() }
-    }
+const CLEAR_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
 
-    pub fn write(&self, sample: &IMFSample) -> Result<()> {
-        unsafe {
-            self.sink_writer
-                .WriteSample(self.sink_writer_stream_index, sample)
+// Scaling ahead of the video processor leaves it with only the conversion,
+// returns the size of the textures the processor is given
+fn create_scaler(
+    d3d_device: &ID3D11Device,
+    input_size: SizeInt32,
+    output_size: SizeInt32,
+    settings: &VideoEncoderSettings,
+) -> Result<(Option<FrameScaler>, SizeInt32)> {
+    if let Some(scale_filter) = settings.scale_filter {
+        let scaled_size = get_scaled_size(
+            input_size,
+            output_size,
+            settings.orientation.rotation,
+            settings.fit_mode,
+        );
+        if scaled_size != input_size {
+            let scaler = FrameScaler::new(
+                d3d_device,
+                settings.color_format.texture_format(),
+                input_size,
+                scaled_size,
+                scale_filter,
+            )?;
+            return Ok((Some(scaler), scaled_size));
         }
     }
+    Ok((None, input_size))
 }
 
-const CLEAR_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+pub fn create_compose_texture(
+    d3d_device: &ID3D11Device,
+    size: SizeInt32,
+    format: DXGI_FORMAT,
+) -> Result<(ID3D11Texture2D, ID3D11RenderTargetView)> {
+    let texture_desc = D3D11_TEXTURE2D_DESC {
+        Width: size.Width as u32,
+        Height: size.Height as u32,
+        ArraySize: 1,
+        MipLevels: 1,
+        Format: format,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            ..Default::default()
+        },
+        Usage: D3D11_USAGE_DEFAULT,
+        BindFlags: (D3D11_BIND_RENDER_TARGET.0 | D3D11_BIND_SHADER_RESOURCE.0) as u32,
+        ..Default::default()
+    };
+    let texture = unsafe {
+        let mut texture = None;
+        d3d_device.CreateTexture2D(&texture_desc, None, Some(&mut texture))?;
+        texture.unwrap()
+    };
+    let render_target_view = unsafe {
+        let mut rtv = None;
+        d3d_device.CreateRenderTargetView(&texture, None, Some(&mut rtv))?;
+        rtv.unwrap()
+    };
+    Ok((texture, render_target_view))
+}
 
 fn ensure_even(value: i32) -> i32 {
     if value % 2 == 0 {
@@ -347,3 +2188,358 @@ fn ensure_even_size(size: SizeInt32) -> SizeInt32 {
         Height: ensure_even(size.Height),
     }
 }
+
+// Falls back to the next encoder if one can't be set up, e.g. because its
+// driver doesn't support the settings
+fn create_video_encoder(
+    encoder_devices: &[VideoEncoderDevice],
+    d3d_device: &ID3D11Device,
+    output_size: SizeInt32,
+    settings: VideoEncoderSettings,
+) -> std::result::Result<VideoEncoder, RecorderError> {
+    let mut candidates = encoder_devices.iter().peekable();
+    while let Some(encoder_device) = candidates.next() {
+        let result = VideoEncoder::new(
+            encoder_device,
+            d3d_device.clone(),
+            output_size,
+            output_size,
+            settings,
+        );
+        match (result, candidates.peek()) {
+            (Ok(video_encoder), _) => {
+                debug!(
+                    "Encoding {}x{} with {}.",
+                    output_size.Width,
+                    output_size.Height,
+                    encoder_device.display_name()
+                );
+                return Ok(video_encoder);
+            }
+            (Err(error), Some(next_encoder_device)) => warn!(
+                "Could not set up {} ({}), trying {} instead.",
+                encoder_device.display_name(),
+                error.message(),
+                next_encoder_device.display_name()
+            ),
+            (Err(error), None) => {
+                return Err(get_encoder_error(encoder_device, output_size, error))
+            }
+        }
+    }
+    Err(invalid_setting("There are no encoders to record with!").into())
+}
+
+// Checks the output with the encoders before any of them is set up, so that
+// settings that none of them take fail with a message that says why, rather
+// than somewhere in the encoder's setup. Returns the size to record at.
+fn check_output_support(
+    encoder_devices: &[VideoEncoderDevice],
+    output_size: SizeInt32,
+    settings: &VideoEncoderSettings,
+    auto_downscale: bool,
+) -> std::result::Result<SizeInt32, RecorderError> {
+    // The first encoder is the one that's asked for, or the preferred one
+    let mut first_problem = None;
+    for encoder_device in encoder_devices {
+        match encoder_device.check_output(output_size, settings.frame_rate, settings.bit_rate) {
+            Ok(support @ (OutputSupport::TooLarge(_) | OutputSupport::FrameRateTooHigh(_))) => {
+                first_problem.get_or_insert((encoder_device, support));
+            }
+            // Whatever else keeps an encoder from taking the settings is
+            // reported when it's set up
+            _ => return Ok(output_size),
+        }
+    }
+
+    let (encoder_device, support) = match first_problem {
+        Some(problem) => problem,
+        None => return Ok(output_size),
+    };
+    let encoder = encoder_device.display_name().to_owned();
+    let message = match support {
+        OutputSupport::TooLarge(max_size) if auto_downscale => {
+            warn!(
+                "{} can't encode {}x{}, recording at {}x{} instead.",
+                encoder, output_size.Width, output_size.Height, max_size.Width, max_size.Height
+            );
+            return Ok(max_size);
+        }
+        OutputSupport::TooLarge(max_size) => format!(
+            "{} rejected {}x{}; the largest it supports is {}x{}. Use --resolution or --auto-downscale to record smaller.",
+            encoder, output_size.Width, output_size.Height, max_size.Width, max_size.Height
+        ),
+        OutputSupport::FrameRateTooHigh(max_frame_rate) => format!(
+            "{} rejected {}x{} at {} fps; the most it supports is {} fps. Use --frame-rate to record at a lower one.",
+            encoder, output_size.Width, output_size.Height, settings.frame_rate, max_frame_rate
+        ),
+        OutputSupport::Supported | OutputSupport::Unsupported => unreachable!(),
+    };
+    Err(RecorderError::Encoder {
+        codec: encoder_device.codec(),
+        encoder,
+        message,
+    })
+}
+
+// Explains why the encoder didn't take the settings, where that's known
+fn get_encoder_error(
+    encoder_device: &VideoEncoderDevice,
+    output_size: SizeInt32,
+    error: Error,
+) -> RecorderError {
+    let encoder = encoder_device.display_name().to_owned();
+    let max_resolution = encoder_device
+        .probe()
+        .ok()
+        .and_then(|capabilities| capabilities.max_resolution);
+    let message = match max_resolution {
+        Some(max_resolution)
+            if output_size.Width > max_resolution.Width
+                || output_size.Height > max_resolution.Height =>
+        {
+            format!(
+                "{} rejected {}x{}; the largest it supports is {}x{}. Use --resolution to record smaller.",
+                encoder,
+                output_size.Width,
+                output_size.Height,
+                max_resolution.Width,
+                max_resolution.Height
+            )
+        }
+        _ => format!(
+            "{} rejected the settings ({}).",
+            encoder,
+            error.message().to_string().trim_end()
+        ),
+    };
+    RecorderError::Encoder {
+        codec: encoder_device.codec(),
+        encoder,
+        message,
+    }
+}
+
+fn invalid_setting(message: &str) -> Error {
+    Error::new(E_INVALIDARG, message.into())
+}
+
+fn validate_settings(
+    canvas_size: SizeInt32,
+    region: Option<RectInt32>,
+    resolution: SizeInt32,
+    settings: &VideoEncoderSettings,
+    max_frame_rate: Option<u32>,
+) -> std::result::Result<(), &'static str> {
+    if let Some(region) = region {
+        if region.Width <= 0
+            || region.Height <= 0
+            || region.X < 0
+            || region.Y < 0
+            || region.X + region.Width > canvas_size.Width
+            || region.Y + region.Height > canvas_size.Height
+        {
+            return Err("The provided region is outside the bounds of the capture target!");
+        }
+    }
+    if resolution.Width <= 0 || resolution.Height <= 0 {
+        return Err("The resolution must be larger than zero!");
+    }
+    if settings.bit_rate == 0 {
+        return Err("The bit rate must be larger than zero!");
+    }
+    if settings.frame_rate == 0 {
+        return Err("The frame rate must be larger than zero!");
+    }
+    if max_frame_rate == Some(0) {
+        return Err("The maximum frame rate must be larger than zero!");
+    }
+    if let Some(quality) = settings.quality {
+        if settings.rate_control != Some(RateControlMode::Cqp) {
+            return Err("The quality can only be set with constant quality (cqp) rate control!");
+        }
+        if quality > 100 {
+            return Err("The quality must be between 0 and 100!");
+        }
+    }
+    if settings.gop_size == Some(0) {
+        return Err("The GOP size must be larger than zero!");
+    }
+    if settings.color_format == ColorFormat::Hdr10 && settings.color_range == ColorRange::Full {
+        return Err("HDR recordings only support the limited color range!");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Graphics::{RectInt32, SizeInt32};
+
+    use crate::video::{
+        color_format::ColorFormat,
+        color_range::ColorRange,
+        encoder::VideoEncoderSettings,
+        fit_mode::FitMode,
+        frame_rate_mode::FrameRateMode,
+        orientation::{Orientation, Rotation},
+        rate_control::RateControlMode,
+    };
+
+    use super::validate_settings;
+
+    const CANVAS: SizeInt32 = SizeInt32 {
+        Width: 1920,
+        Height: 1080,
+    };
+
+    const SETTINGS: VideoEncoderSettings = VideoEncoderSettings {
+        bit_rate: 18_000_000,
+        frame_rate: 60,
+        frame_rate_mode: FrameRateMode::Constant,
+        rate_control: None,
+        quality: None,
+        gop_size: None,
+        b_frames: None,
+        color_format: ColorFormat::Sdr,
+        color_range: ColorRange::Limited,
+        orientation: Orientation {
+            rotation: Rotation::None,
+            flip: None,
+        },
+        scale_filter: None,
+        fit_mode: FitMode::Letterbox,
+    };
+
+    #[test]
+    fn settings_validation_test() {
+        assert!(validate_settings(CANVAS, None, CANVAS, &SETTINGS, None).is_ok());
+        let region = RectInt32 {
+            X: 100,
+            Y: 100,
+            Width: 1820,
+            Height: 980,
+        };
+        assert!(validate_settings(CANVAS, Some(region), CANVAS, &SETTINGS, None).is_ok());
+        let region = RectInt32 {
+            Width: 1821,
+            ..region
+        };
+        assert!(validate_settings(CANVAS, Some(region), CANVAS, &SETTINGS, None).is_err());
+        let empty = SizeInt32 {
+            Width: 0,
+            Height: 0,
+        };
+        assert!(validate_settings(CANVAS, None, empty, &SETTINGS, None).is_err());
+        let settings = VideoEncoderSettings {
+            bit_rate: 0,
+            ..SETTINGS
+        };
+        assert!(validate_settings(CANVAS, None, CANVAS, &settings, None).is_err());
+        let settings = VideoEncoderSettings {
+            frame_rate: 0,
+            ..SETTINGS
+        };
+        assert!(validate_settings(CANVAS, None, CANVAS, &settings, None).is_err());
+        assert!(validate_settings(CANVAS, None, CANVAS, &SETTINGS, Some(30)).is_ok());
+        assert!(validate_settings(CANVAS, None, CANVAS, &SETTINGS, Some(0)).is_err());
+
+        // The quality only applies to constant quality rate control
+        let settings = VideoEncoderSettings {
+            rate_control: Some(RateControlMode::Cqp),
+            quality: Some(80),
+            ..SETTINGS
+        };
+        assert!(validate_settings(CANVAS, None, CANVAS, &settings, None).is_ok());
+        let settings = VideoEncoderSettings {
+            quality: Some(101),
+            ..settings
+        };
+        assert!(validate_settings(CANVAS, None, CANVAS, &settings, None).is_err());
+        let settings = VideoEncoderSettings {
+            rate_control: Some(RateControlMode::Vbr),
+            quality: Some(80),
+            ..SETTINGS
+        };
+        assert!(validate_settings(CANVAS, None, CANVAS, &settings, None).is_err());
+
+        let settings = VideoEncoderSettings {
+            gop_size: Some(0),
+            ..SETTINGS
+        };
+        assert!(validate_settings(CANVAS, None, CANVAS, &settings, None).is_err());
+        let settings = VideoEncoderSettings {
+            gop_size: Some(30),
+            b_frames: Some(0),
+            ..SETTINGS
+        };
+        assert!(validate_settings(CANVAS, None, CANVAS, &settings, None).is_ok());
+
+        let settings = VideoEncoderSettings {
+            color_format: ColorFormat::Hdr10,
+            color_range: ColorRange::Full,
+            ..SETTINGS
+        };
+        assert!(validate_settings(CANVAS, None, CANVAS, &settings, None).is_err());
+    }
+
+    // Records the test pattern end to end, with the software encoder (which
+    // doesn't need a GPU, WARP is enough) and nothing to capture
+    #[cfg(windows)]
+    #[test]
+    fn test_pattern_recording_test() {
+        use std::{
+            sync::Arc,
+            time::{Duration, Instant},
+        };
+
+        use windows::Win32::{
+            Graphics::Dxgi::Common::DXGI_FORMAT_B8G8R8A8_UNORM,
+            Media::MediaFoundation::{MFStartup, MFSTARTUP_FULL},
+        };
+
+        use crate::{
+            container::null::NullWriter, d3d::create_d3d_device, media::MF_VERSION,
+            sample_writer::SampleWriter, stats::RecordingStats, test_pattern::TestPatternSource,
+            timeline::Timeline, video::codec::VideoCodec,
+            video::encoder_device::VideoEncoderDevice,
+        };
+
+        use super::VideoEncodingSession;
+
+        unsafe { MFStartup(MF_VERSION, MFSTARTUP_FULL).unwrap() };
+        let d3d_device = create_d3d_device().unwrap();
+        let size = SizeInt32 {
+            Width: 640,
+            Height: 360,
+        };
+        let source =
+            TestPatternSource::new(&d3d_device, size, DXGI_FORMAT_B8G8R8A8_UNORM, 30, Some(30))
+                .unwrap();
+        let timeline = Timeline::new();
+        let sample_writer = Arc::new(SampleWriter::new_live(
+            Box::new(NullWriter::new()),
+            timeline.clone(),
+        ));
+        let encoder_devices = VideoEncoderDevice::enumerate_software(VideoCodec::H264).unwrap();
+        let mut session =
+            VideoEncodingSession::builder(d3d_device, Vec::new(), sample_writer.clone())
+                .encoders(&encoder_devices)
+                .frame_rate(30)
+                .frame_source(Box::new(source), size)
+                .build()
+                .unwrap();
+        timeline.start();
+        sample_writer.start().unwrap();
+        session.start().unwrap();
+
+        // Every frame of the pattern is written before the source runs out
+        let start = Instant::now();
+        while RecordingStats::combine([sample_writer.stats()]).frames < 30 {
+            assert!(start.elapsed() < Duration::from_secs(30));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        session.stop().unwrap();
+        sample_writer.stop().unwrap();
+        assert_eq!(RecordingStats::combine([sample_writer.stats()]).frames, 30);
+    }
+}
@@ -0,0 +1,65 @@
+use std::{fmt::Display, str::FromStr};
+
+/// How the captured frames are fit into an output resolution with a
+/// different aspect ratio.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum FitMode {
+    /// Scales the frames to fit, with black bars on the sides or the top and
+    /// bottom.
+    #[default]
+    Letterbox,
+    /// Scales the frames to the output resolution, distorting them.
+    Stretch,
+    /// Scales the frames to fill the output, cutting off the edges.
+    Crop,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseFitModeError(&'static str);
+
+impl FromStr for FitMode {
+    type Err = ParseFitModeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "letterbox" | "fit" => Ok(FitMode::Letterbox),
+            "stretch" => Ok(FitMode::Stretch),
+            "crop" | "fill" => Ok(FitMode::Crop),
+            _ => Err(ParseFitModeError(
+                "Invalid fit value! Expecting: letterbox, stretch, or crop.",
+            )),
+        }
+    }
+}
+
+impl Display for FitMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = match self {
+            FitMode::Letterbox => "letterbox",
+            FitMode::Stretch => "stretch",
+            FitMode::Crop => "crop",
+        };
+        write!(f, "{}", string)
+    }
+}
+
+impl Display for ParseFitModeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseFitModeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::FitMode;
+
+    #[test]
+    fn fit_mode_parsing_test() {
+        assert_eq!("letterbox".parse(), Ok(FitMode::Letterbox));
+        assert_eq!("Stretch".parse(), Ok(FitMode::Stretch));
+        assert_eq!("fill".parse(), Ok(FitMode::Crop));
+        assert!("zoom".parse::<FitMode>().is_err());
+        assert_eq!(FitMode::Crop.to_string(), "crop");
+    }
+}
@@ -0,0 +1,184 @@
+use std::{fmt::Display, str::FromStr};
+
+use tracing::warn;
+
+use crate::stats::StatsCounter;
+
+// Frames that were captured more than this many frame durations ago are late
+const LATE_FRAME_DURATIONS: i64 = 3;
+// Late frames are still recorded if nothing was recorded for this long (in
+// 100ns units), so that the video keeps moving when the encoder is always behind
+const MAX_DROPPED_TIME: i64 = 10_000_000;
+
+/// What to do when frames arrive late (because the encoder can't keep up)
+/// or when there are gaps between frames.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FramePacing {
+    /// Late frames are recorded anyway and reported.
+    Log,
+    /// Late frames are dropped, so that the encoder can catch up.
+    Drop,
+    /// Gaps are filled by repeating the previous frame, so that the video
+    /// keeps a constant frame rate. Requires a constant frame rate.
+    Duplicate,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseFramePacingError(&'static str);
+
+/// What should happen to a frame, see FramePacer::pace.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PacingAction {
+    Drop,
+    /// The previous frame is repeated at each of the timestamps before the
+    /// frame is encoded.
+    Encode(Vec<i64>),
+}
+
+/// Applies a FramePacing policy to the frames of a recording and counts
+/// the late and duplicated frames.
+pub struct FramePacer {
+    pacing: FramePacing,
+    // The nominal duration of a frame (in 100ns units)
+    frame_duration: i64,
+    stats: StatsCounter,
+    last_timestamp: Option<i64>,
+    // Only the first frame of a late streak is logged
+    behind: bool,
+}
+
+impl FramePacer {
+    pub fn new(pacing: FramePacing, frame_duration: i64, stats: StatsCounter) -> Self {
+        Self {
+            pacing,
+            frame_duration: frame_duration.max(1),
+            stats,
+            last_timestamp: None,
+            behind: false,
+        }
+    }
+
+    /// Notes a sample that was recorded without being paced, e.g. a repeat
+    /// while the recorded window is minimized.
+    pub fn record(&mut self, timestamp: i64) {
+        self.last_timestamp = Some(timestamp);
+    }
+
+    /// Decides what to do with a frame that will be recorded at the
+    /// timestamp, and was captured the latency (both in 100ns units) ago.
+    pub fn pace(&mut self, timestamp: i64, latency: i64) -> PacingAction {
+        let late = latency > self.frame_duration * LATE_FRAME_DURATIONS;
+        if late {
+            self.stats.add_late_frame();
+            if self.pacing == FramePacing::Log && !self.behind {
+                warn!(
+                    "Frames are arriving {}ms late, the encoder may not be keeping up.",
+                    latency / 10_000
+                );
+            }
+        }
+        self.behind = late;
+
+        if late && self.pacing == FramePacing::Drop {
+            let recently_recorded = self
+                .last_timestamp
+                .map(|last_timestamp| timestamp - last_timestamp < MAX_DROPPED_TIME)
+                .unwrap_or(false);
+            if recently_recorded {
+                self.stats.add_dropped_frame();
+                return PacingAction::Drop;
+            }
+        }
+
+        let mut duplicates = Vec::new();
+        if let (FramePacing::Duplicate, Some(last_timestamp)) = (self.pacing, self.last_timestamp) {
+            // Gaps of more than half a frame get a repeat
+            let mut duplicate = last_timestamp + self.frame_duration;
+            while timestamp - duplicate >= self.frame_duration / 2 {
+                duplicates.push(duplicate);
+                duplicate += self.frame_duration;
+            }
+            self.stats.add_duplicated_frames(duplicates.len() as u64);
+        }
+        self.last_timestamp = Some(timestamp);
+        PacingAction::Encode(duplicates)
+    }
+}
+
+impl FromStr for FramePacing {
+    type Err = ParseFramePacingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "log" => Ok(FramePacing::Log),
+            "drop" => Ok(FramePacing::Drop),
+            "duplicate" => Ok(FramePacing::Duplicate),
+            _ => Err(ParseFramePacingError(
+                "Invalid frame pacing! Expecting: log, drop, or duplicate.",
+            )),
+        }
+    }
+}
+
+impl Display for FramePacing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = match self {
+            FramePacing::Log => "log",
+            FramePacing::Drop => "drop",
+            FramePacing::Duplicate => "duplicate",
+        };
+        write!(f, "{}", string)
+    }
+}
+
+impl Display for ParseFramePacingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseFramePacingError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::stats::StatsCounter;
+
+    use super::{FramePacer, FramePacing, PacingAction};
+
+    #[test]
+    fn frame_pacing_parsing_test() {
+        assert_eq!("log".parse(), Ok(FramePacing::Log));
+        assert_eq!("Drop".parse(), Ok(FramePacing::Drop));
+        assert_eq!("duplicate".parse(), Ok(FramePacing::Duplicate));
+        assert!("repeat".parse::<FramePacing>().is_err());
+        assert_eq!(FramePacing::Duplicate.to_string(), "duplicate");
+    }
+
+    #[test]
+    fn frame_pacer_test() {
+        let stats = StatsCounter::default();
+        let mut pacer = FramePacer::new(FramePacing::Drop, 100, stats.clone());
+        assert_eq!(pacer.pace(0, 1_000), PacingAction::Encode(Vec::new()));
+        assert_eq!(pacer.pace(100, 1_000), PacingAction::Drop);
+        assert_eq!(pacer.pace(200, 50), PacingAction::Encode(Vec::new()));
+        // Late frames are recorded if nothing else was for too long
+        assert_eq!(
+            pacer.pace(10_000_200, 1_000),
+            PacingAction::Encode(Vec::new())
+        );
+        let counts = stats.stats();
+        assert_eq!(counts.late_frames, 3);
+        assert_eq!(counts.dropped_frames, 1);
+
+        let stats = StatsCounter::default();
+        let mut pacer = FramePacer::new(FramePacing::Duplicate, 100, stats.clone());
+        assert_eq!(pacer.pace(0, 0), PacingAction::Encode(Vec::new()));
+        assert_eq!(pacer.pace(140, 0), PacingAction::Encode(Vec::new()));
+        assert_eq!(pacer.pace(400, 0), PacingAction::Encode(vec![240, 340]));
+        // Late frames are still recorded
+        assert_eq!(pacer.pace(500, 1_000), PacingAction::Encode(Vec::new()));
+        let counts = stats.stats();
+        assert_eq!(counts.duplicated_frames, 2);
+        assert_eq!(counts.late_frames, 1);
+        assert_eq!(counts.dropped_frames, 0);
+    }
+}
@@ -0,0 +1,59 @@
+use std::{fmt::Display, str::FromStr};
+
+/// How frames are timed in the encoded video.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FrameRateMode {
+    /// Every frame lasts for the same amount of time, based on the frame rate.
+    Constant,
+    /// Frames last until the next frame was captured, so static content
+    /// produces fewer frames. The frame rate becomes the maximum frame rate.
+    Variable,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseFrameRateModeError(&'static str);
+
+impl FromStr for FrameRateMode {
+    type Err = ParseFrameRateModeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "cfr" | "constant" => Ok(FrameRateMode::Constant),
+            "vfr" | "variable" => Ok(FrameRateMode::Variable),
+            _ => Err(ParseFrameRateModeError(
+                "Invalid frame rate mode! Expecting: cfr or vfr.",
+            )),
+        }
+    }
+}
+
+impl Display for FrameRateMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = match self {
+            FrameRateMode::Constant => "cfr",
+            FrameRateMode::Variable => "vfr",
+        };
+        write!(f, "{}", string)
+    }
+}
+
+impl Display for ParseFrameRateModeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseFrameRateModeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::FrameRateMode;
+
+    #[test]
+    fn frame_rate_mode_parsing_test() {
+        assert_eq!("cfr".parse(), Ok(FrameRateMode::Constant));
+        assert_eq!("VFR".parse(), Ok(FrameRateMode::Variable));
+        assert_eq!("variable".parse(), Ok(FrameRateMode::Variable));
+        assert!("auto".parse::<FrameRateMode>().is_err());
+        assert_eq!(FrameRateMode::Variable.to_string(), "vfr");
+    }
+}
@@ -0,0 +1,374 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use windows::{
+    core::Result,
+    Foundation::TimeSpan,
+    Graphics::SizeInt32,
+    Win32::Graphics::{
+        Direct3D11::{
+            ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D, D3D11_BIND_RENDER_TARGET,
+            D3D11_BIND_VIDEO_ENCODER, D3D11_CPU_ACCESS_READ, D3D11_CPU_ACCESS_WRITE, D3D11_MAP,
+            D3D11_MAPPED_SUBRESOURCE, D3D11_MAP_READ, D3D11_MAP_WRITE, D3D11_TEXTURE2D_DESC,
+            D3D11_USAGE_DEFAULT, D3D11_USAGE_STAGING,
+        },
+        Dxgi::Common::{DXGI_FORMAT, DXGI_FORMAT_P010, DXGI_SAMPLE_DESC},
+    },
+};
+
+use crate::container::to_error;
+
+use super::{encoder::VideoEncoderInputSample, texture_pool::TexturePool};
+
+// The timestamp, the duration, and whether the frame is a keyframe
+const FRAME_HEADER_SIZE: usize = 17;
+// The disk sees large writes (and reads), rather than one per plane row
+const BUFFER_SIZE: usize = 8 * 1024 * 1024;
+
+/// Writes the samples that would have gone to the encoder to a scratch file,
+/// as raw NV12 (or P010) frames, so that they can be encoded once the
+/// recording is stopped (see FrameSpoolReader). For machines whose encoder
+/// can't keep up, as long as the disk can.
+pub struct FrameSpoolWriter {
+    file: BufWriter<File>,
+    size: SizeInt32,
+    format: DXGI_FORMAT,
+    layout: FrameLayout,
+    // The samples move to a new device if the old one is lost
+    staging: Option<(ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D)>,
+    frame: Vec<u8>,
+}
+
+/// Reads the frames written by a FrameSpoolWriter back as samples for the
+/// encoder, with their timestamps.
+pub struct FrameSpoolReader {
+    file: BufReader<File>,
+    layout: FrameLayout,
+    d3d_context: ID3D11DeviceContext,
+    staging_texture: ID3D11Texture2D,
+    // The encoder holds on to a few samples at a time
+    texture_pool: TexturePool<()>,
+    frame: Vec<u8>,
+}
+
+// What goes ahead of the pixels of each frame in the scratch file
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct FrameHeader {
+    timestamp: i64,
+    duration: i64,
+    keyframe: bool,
+}
+
+// Both planes of an NV12 or P010 frame have rows of the same size, and the
+// chroma plane has half as many of them
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct FrameLayout {
+    row_size: usize,
+    rows: usize,
+}
+
+unsafe impl Send for FrameSpoolWriter {}
+impl FrameSpoolWriter {
+    /// Frames must be of the size and format, e.g. NV12 samples from the video processor.
+    pub fn create(path: &Path, size: SizeInt32, format: DXGI_FORMAT) -> Result<Self> {
+        let file = File::create(path).map_err(to_error)?;
+        let layout = get_frame_layout(size, format);
+        Ok(Self {
+            file: BufWriter::with_capacity(BUFFER_SIZE, file),
+            size,
+            format,
+            layout,
+            staging: None,
+            frame: vec![0; layout.frame_size()],
+        })
+    }
+
+    /// Reads the sample back from the GPU, and appends it to the file.
+    pub fn write(&mut self, sample: &VideoEncoderInputSample) -> Result<()> {
+        let d3d_device = unsafe { sample.texture().GetDevice()? };
+        let (d3d_context, staging_texture) = match &self.staging {
+            Some((staging_device, d3d_context, staging_texture))
+                if *staging_device == d3d_device =>
+            {
+                (d3d_context.clone(), staging_texture.clone())
+            }
+            _ => {
+                let d3d_context = unsafe { d3d_device.GetImmediateContext()? };
+                let staging_texture = create_staging_texture(
+                    &d3d_device,
+                    self.size,
+                    self.format,
+                    D3D11_CPU_ACCESS_READ.0 as u32,
+                )?;
+                self.staging = Some((d3d_device, d3d_context.clone(), staging_texture.clone()));
+                (d3d_context, staging_texture)
+            }
+        };
+        unsafe {
+            d3d_context.CopyResource(&staging_texture, sample.texture());
+            let (data, pitch) = map(&d3d_context, &staging_texture, D3D11_MAP_READ, self.layout)?;
+            copy_rows(
+                data,
+                pitch,
+                &mut self.frame,
+                self.layout.row_size,
+                self.layout,
+            );
+            d3d_context.Unmap(&staging_texture, 0);
+        }
+        let header = FrameHeader {
+            timestamp: sample.timestamp().Duration,
+            duration: sample.duration().Duration,
+            keyframe: sample.is_keyframe(),
+        };
+        self.file.write_all(&header.to_bytes()).map_err(to_error)?;
+        self.file.write_all(&self.frame).map_err(to_error)
+    }
+
+    /// Flushes the file, and returns the device of the last sample, on which
+    /// the frames should be encoded. There's no device if nothing was written.
+    /// The device is returned even if flushing fails, since the frames that
+    /// made it to the file can still be read.
+    pub fn finish(mut self) -> (Option<ID3D11Device>, Result<()>) {
+        let result = self.file.flush().map_err(to_error);
+        (self.staging.map(|(d3d_device, _, _)| d3d_device), result)
+    }
+}
+
+unsafe impl Send for FrameSpoolReader {}
+impl FrameSpoolReader {
+    /// The size and format must be the ones the frames were written with.
+    pub fn open(
+        d3d_device: &ID3D11Device,
+        path: &Path,
+        size: SizeInt32,
+        format: DXGI_FORMAT,
+    ) -> Result<Self> {
+        let file = File::open(path).map_err(to_error)?;
+        let layout = get_frame_layout(size, format);
+        let d3d_context = unsafe { d3d_device.GetImmediateContext()? };
+        let staging_texture =
+            create_staging_texture(d3d_device, size, format, D3D11_CPU_ACCESS_WRITE.0 as u32)?;
+        // Like the video processor's output, which the encoder would have had
+        let desc = D3D11_TEXTURE2D_DESC {
+            Usage: D3D11_USAGE_DEFAULT,
+            BindFlags: (D3D11_BIND_RENDER_TARGET.0 | D3D11_BIND_VIDEO_ENCODER.0) as u32,
+            CPUAccessFlags: 0,
+            ..texture_desc(size, format)
+        };
+        Ok(Self {
+            file: BufReader::with_capacity(BUFFER_SIZE, file),
+            layout,
+            d3d_context,
+            staging_texture,
+            texture_pool: TexturePool::new(d3d_device.clone(), desc, Box::new(|_| Ok(()))),
+            frame: vec![0; layout.frame_size()],
+        })
+    }
+
+    /// Returns None once all of the frames were read. A frame that was cut
+    /// short (e.g. because the disk ran out of space) ends the file.
+    pub fn read(&mut self) -> Result<Option<VideoEncoderInputSample>> {
+        let mut header = [0; FRAME_HEADER_SIZE];
+        if !read_all(&mut self.file, &mut header)? || !read_all(&mut self.file, &mut self.frame)? {
+            return Ok(None);
+        }
+        let header = FrameHeader::from_bytes(&header);
+        let (texture, _) = self.texture_pool.acquire()?;
+        unsafe {
+            let (data, pitch) = map(
+                &self.d3d_context,
+                &self.staging_texture,
+                D3D11_MAP_WRITE,
+                self.layout,
+            )?;
+            copy_rows(&self.frame, self.layout.row_size, data, pitch, self.layout);
+            self.d3d_context.Unmap(&self.staging_texture, 0);
+            self.d3d_context
+                .CopyResource(&texture, &self.staging_texture);
+        }
+        let mut sample = VideoEncoderInputSample::new(
+            TimeSpan {
+                Duration: header.timestamp,
+            },
+            TimeSpan {
+                Duration: header.duration,
+            },
+            texture,
+        );
+        if header.keyframe {
+            sample.request_keyframe();
+        }
+        Ok(Some(sample))
+    }
+}
+
+impl FrameHeader {
+    fn to_bytes(self) -> [u8; FRAME_HEADER_SIZE] {
+        let mut bytes = [0; FRAME_HEADER_SIZE];
+        bytes[..8].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.duration.to_le_bytes());
+        bytes[16] = self.keyframe as u8;
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; FRAME_HEADER_SIZE]) -> Self {
+        Self {
+            timestamp: i64::from_le_bytes(bytes[..8].try_into().unwrap()),
+            duration: i64::from_le_bytes(bytes[8..16].try_into().unwrap()),
+            keyframe: bytes[16] != 0,
+        }
+    }
+}
+
+impl FrameLayout {
+    fn frame_size(&self) -> usize {
+        self.row_size * self.rows
+    }
+}
+
+fn get_frame_layout(size: SizeInt32, format: DXGI_FORMAT) -> FrameLayout {
+    let bytes_per_sample = if format == DXGI_FORMAT_P010 { 2 } else { 1 };
+    let height = size.Height as usize;
+    FrameLayout {
+        row_size: size.Width as usize * bytes_per_sample,
+        rows: height + height / 2,
+    }
+}
+
+// Copies the rows of a frame between buffers with different pitches, e.g.
+// a mapped texture and the tightly packed frames of the file
+fn copy_rows(
+    source: &[u8],
+    source_pitch: usize,
+    dest: &mut [u8],
+    dest_pitch: usize,
+    layout: FrameLayout,
+) {
+    for row in 0..layout.rows {
+        let source_row = &source[row * source_pitch..][..layout.row_size];
+        dest[row * dest_pitch..][..layout.row_size].copy_from_slice(source_row);
+    }
+}
+
+// Maps the planes of the texture, which follow one another at the same pitch
+unsafe fn map<'a>(
+    d3d_context: &ID3D11DeviceContext,
+    texture: &ID3D11Texture2D,
+    map_type: D3D11_MAP,
+    layout: FrameLayout,
+) -> Result<(&'a mut [u8], usize)> {
+    let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+    d3d_context.Map(texture, 0, map_type, 0, Some(&mut mapped))?;
+    let pitch = mapped.RowPitch as usize;
+    let len = pitch * (layout.rows - 1) + layout.row_size;
+    Ok((
+        std::slice::from_raw_parts_mut(mapped.pData as *mut u8, len),
+        pitch,
+    ))
+}
+
+// Whether the buffer was filled, rather than the file ending first
+fn read_all(file: &mut BufReader<File>, buffer: &mut [u8]) -> Result<bool> {
+    match file.read_exact(buffer) {
+        Ok(()) => Ok(true),
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(error) => Err(to_error(error)),
+    }
+}
+
+fn create_staging_texture(
+    d3d_device: &ID3D11Device,
+    size: SizeInt32,
+    format: DXGI_FORMAT,
+    cpu_access: u32,
+) -> Result<ID3D11Texture2D> {
+    let desc = D3D11_TEXTURE2D_DESC {
+        Usage: D3D11_USAGE_STAGING,
+        CPUAccessFlags: cpu_access,
+        ..texture_desc(size, format)
+    };
+    unsafe {
+        let mut texture = None;
+        d3d_device.CreateTexture2D(&desc, None, Some(&mut texture))?;
+        Ok(texture.unwrap())
+    }
+}
+
+fn texture_desc(size: SizeInt32, format: DXGI_FORMAT) -> D3D11_TEXTURE2D_DESC {
+    D3D11_TEXTURE2D_DESC {
+        Width: size.Width as u32,
+        Height: size.Height as u32,
+        ArraySize: 1,
+        MipLevels: 1,
+        Format: format,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::{
+        Graphics::SizeInt32,
+        Win32::Graphics::Dxgi::Common::{DXGI_FORMAT_NV12, DXGI_FORMAT_P010},
+    };
+
+    use super::{copy_rows, get_frame_layout, FrameHeader, FrameLayout};
+
+    #[test]
+    fn frame_header_test() {
+        let header = FrameHeader {
+            timestamp: 123_456_789,
+            duration: 166_667,
+            keyframe: true,
+        };
+        assert_eq!(FrameHeader::from_bytes(&header.to_bytes()), header);
+        let header = FrameHeader {
+            timestamp: -1,
+            keyframe: false,
+            ..header
+        };
+        assert_eq!(FrameHeader::from_bytes(&header.to_bytes()), header);
+    }
+
+    #[test]
+    fn frame_layout_test() {
+        let size = SizeInt32 {
+            Width: 3840,
+            Height: 2160,
+        };
+        let nv12 = get_frame_layout(size, DXGI_FORMAT_NV12);
+        assert_eq!(
+            nv12,
+            FrameLayout {
+                row_size: 3840,
+                rows: 3240
+            }
+        );
+        assert_eq!(nv12.frame_size(), 3840 * 2160 * 3 / 2);
+        assert_eq!(
+            get_frame_layout(size, DXGI_FORMAT_P010).frame_size(),
+            3840 * 2160 * 3
+        );
+
+        // The padding at the end of the mapped rows is left out of the file
+        let layout = FrameLayout {
+            row_size: 2,
+            rows: 3,
+        };
+        let mapped = [1, 2, 0, 3, 4, 0, 5, 6];
+        let mut frame = [0; 6];
+        copy_rows(&mapped, 3, &mut frame, 2, layout);
+        assert_eq!(frame, [1, 2, 3, 4, 5, 6]);
+        let mut remapped = [9; 8];
+        copy_rows(&frame, 2, &mut remapped, 3, layout);
+        assert_eq!(remapped, [1, 2, 9, 3, 4, 9, 5, 6]);
+    }
+}
@@ -0,0 +1,136 @@
+use std::time::{Duration, Instant};
+
+use windows::{
+    core::{ComInterface, Error, Result},
+    Win32::{
+        Foundation::{CloseHandle, BOOL, WAIT_OBJECT_0},
+        Graphics::{
+            Direct3D11::{
+                ID3D11Device, ID3D11Device5, ID3D11DeviceContext, ID3D11DeviceContext4,
+                ID3D11Fence, ID3D11Query, D3D11_FENCE_FLAG_NONE, D3D11_QUERY_DESC,
+                D3D11_QUERY_EVENT,
+            },
+            Dxgi::DXGI_ERROR_WAIT_TIMEOUT,
+        },
+        System::Threading::{CreateEventW, WaitForSingleObject},
+    },
+};
+
+// The GPU finishes a frame's work in milliseconds, so a wait this long means
+// it's hung (or was reset) and the caller should fail rather than block
+const WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Lets the CPU wait for the GPU work that was submitted to the device's
+/// immediate context. The immediate context keeps GPU work in order, but
+/// not work outside of it: capture frames go back to the capture (which
+/// renders the next frame into them) as soon as they're closed, and the
+/// encoder may read its input from another engine. Without waiting, a busy
+/// GPU can still be copying a frame or writing a sample when that happens,
+/// which shows up as torn or partial frames.
+///
+/// Uses a fence where the device supports them (Windows 10 1703 and later)
+/// and event queries elsewhere.
+pub struct GpuSync {
+    d3d_device: ID3D11Device,
+    d3d_context: ID3D11DeviceContext,
+    fence: Option<(ID3D11Fence, ID3D11DeviceContext4)>,
+    last_value: u64,
+}
+
+/// A point in the GPU work submitted so far, see GpuSync::signal.
+pub enum GpuSyncPoint {
+    Fence(ID3D11Fence, u64),
+    Query(ID3D11DeviceContext, ID3D11Query),
+}
+
+impl GpuSync {
+    pub fn new(d3d_device: &ID3D11Device) -> Result<Self> {
+        let d3d_context = unsafe { d3d_device.GetImmediateContext()? };
+        let fence = (|| -> Result<(ID3D11Fence, ID3D11DeviceContext4)> {
+            let device: ID3D11Device5 = d3d_device.cast()?;
+            let context: ID3D11DeviceContext4 = d3d_context.cast()?;
+            let mut fence: Option<ID3D11Fence> = None;
+            unsafe { device.CreateFence(0, D3D11_FENCE_FLAG_NONE, &mut fence)? };
+            Ok((fence.unwrap(), context))
+        })()
+        .ok();
+        Ok(Self {
+            d3d_device: d3d_device.clone(),
+            d3d_context,
+            fence,
+            last_value: 0,
+        })
+    }
+
+    /// Marks the work submitted so far, so that it can be waited on.
+    pub fn signal(&mut self) -> Result<GpuSyncPoint> {
+        if let Some((fence, context)) = &self.fence {
+            self.last_value += 1;
+            unsafe {
+                context.Signal(fence, self.last_value)?;
+                // The signal has to reach the GPU for the wait to end, even
+                // if nothing else is submitted before it
+                self.d3d_context.Flush();
+            }
+            return Ok(GpuSyncPoint::Fence(fence.clone(), self.last_value));
+        }
+        let desc = D3D11_QUERY_DESC {
+            Query: D3D11_QUERY_EVENT,
+            MiscFlags: 0,
+        };
+        let query = unsafe {
+            let mut query = None;
+            self.d3d_device.CreateQuery(&desc, Some(&mut query))?;
+            let query = query.unwrap();
+            self.d3d_context.End(&query);
+            query
+        };
+        Ok(GpuSyncPoint::Query(self.d3d_context.clone(), query))
+    }
+}
+
+unsafe impl Send for GpuSyncPoint {}
+impl GpuSyncPoint {
+    /// Blocks until the GPU is done with the work before the point. Can be
+    /// called from any thread. Fails if the GPU takes too long.
+    pub fn wait(&self) -> Result<()> {
+        match self {
+            GpuSyncPoint::Fence(fence, value) => unsafe {
+                if fence.GetCompletedValue() >= *value {
+                    return Ok(());
+                }
+                let event = CreateEventW(None, false, false, None)?;
+                let mut result = fence.SetEventOnCompletion(*value, event);
+                if result.is_ok()
+                    && WaitForSingleObject(event, WAIT_TIMEOUT.as_millis() as u32) != WAIT_OBJECT_0
+                {
+                    result = Err(Error::from(DXGI_ERROR_WAIT_TIMEOUT));
+                }
+                CloseHandle(event)?;
+                result
+            },
+            GpuSyncPoint::Query(context, query) => {
+                let start = Instant::now();
+                loop {
+                    // Flushes the context, so the query is sure to finish
+                    let mut done = BOOL(0);
+                    unsafe {
+                        context.GetData(
+                            query,
+                            Some(&mut done as *mut _ as *mut _),
+                            std::mem::size_of::<BOOL>() as u32,
+                            0,
+                        )?;
+                    }
+                    if done.as_bool() {
+                        return Ok(());
+                    }
+                    if start.elapsed() > WAIT_TIMEOUT {
+                        return Err(Error::from(DXGI_ERROR_WAIT_TIMEOUT));
+                    }
+                    std::thread::yield_now();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+const HUNDRED_NANOSECONDS_PER_SECOND: i64 = 10_000_000;
+
+/// Lowers the frame rate while nothing that's recorded changes, e.g. to keep
+/// hours of monitoring a mostly static screen small, and goes back to the
+/// full frame rate as soon as something does.
+pub struct IdleFrameRate {
+    // How long nothing has to change for (in 100ns units)
+    idle_after: i64,
+    // How far apart samples are while idle (in 100ns units)
+    idle_frame_interval: i64,
+    last_change: Option<i64>,
+    last_sample: Option<i64>,
+}
+
+impl IdleFrameRate {
+    pub fn new(idle_frame_rate: u32, idle_after: Duration) -> Self {
+        Self {
+            idle_after: (idle_after.as_nanos() / 100) as i64,
+            idle_frame_interval: HUNDRED_NANOSECONDS_PER_SECOND / idle_frame_rate.max(1) as i64,
+            last_change: None,
+            last_sample: None,
+        }
+    }
+
+    /// Whether a sample at the timestamp (in 100ns units) should be encoded,
+    /// given whether it changed anything since the last one. Changes always
+    /// are, so nothing is lost by the ones that aren't.
+    pub fn should_encode(&mut self, timestamp: i64, changed: bool) -> bool {
+        if changed || self.last_change.is_none() {
+            self.last_change = Some(timestamp);
+        }
+        let idle = self
+            .last_change
+            .is_some_and(|last_change| timestamp - last_change >= self.idle_after);
+        if idle
+            && self
+                .last_sample
+                .is_some_and(|last_sample| timestamp - last_sample < self.idle_frame_interval)
+        {
+            return false;
+        }
+        self.last_sample = Some(timestamp);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::IdleFrameRate;
+
+    #[test]
+    fn idle_frame_rate_test() {
+        // 5 fps after a second without changes, with frames every 100ms
+        let mut idle_frame_rate = IdleFrameRate::new(5, Duration::from_secs(1));
+        let frame = |index: i64| index * 1_000_000;
+        let mut encoded = |range: std::ops::Range<i64>, changed: bool| {
+            range
+                .filter(|&index| idle_frame_rate.should_encode(frame(index), changed))
+                .collect::<Vec<_>>()
+        };
+
+        // Everything is encoded until the second is up
+        assert_eq!(encoded(0..1, true), [0]);
+        assert_eq!(encoded(1..10, false), (1..10).collect::<Vec<_>>());
+        // Then only every other frame, at the idle frame rate
+        assert_eq!(encoded(10..16, false), [11, 13, 15]);
+        // A change goes back to the full frame rate right away
+        assert_eq!(encoded(17..18, true), [17]);
+        assert_eq!(encoded(18..21, false), [18, 19, 20]);
+    }
+}
@@ -0,0 +1,63 @@
+use std::{fmt::Display, str::FromStr};
+
+/// What is recorded while the recorded window is minimized (or while the
+/// secure desktop is shown), since no frames are captured for it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MinimizeAction {
+    /// Pauses the recording until the window is restored.
+    Pause,
+    /// Repeats the last frame.
+    Hold,
+    /// Shows a "Window minimized" card.
+    Placeholder,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseMinimizeActionError(&'static str);
+
+impl FromStr for MinimizeAction {
+    type Err = ParseMinimizeActionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pause" => Ok(MinimizeAction::Pause),
+            "hold" => Ok(MinimizeAction::Hold),
+            "placeholder" => Ok(MinimizeAction::Placeholder),
+            _ => Err(ParseMinimizeActionError(
+                "Invalid minimize action! Expecting: pause, hold, or placeholder.",
+            )),
+        }
+    }
+}
+
+impl Display for MinimizeAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = match self {
+            MinimizeAction::Pause => "pause",
+            MinimizeAction::Hold => "hold",
+            MinimizeAction::Placeholder => "placeholder",
+        };
+        write!(f, "{}", string)
+    }
+}
+
+impl Display for ParseMinimizeActionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseMinimizeActionError {}
+
+#[cfg(test)]
+mod tests {
+    use super::MinimizeAction;
+
+    #[test]
+    fn minimize_action_parsing_test() {
+        assert_eq!("pause".parse(), Ok(MinimizeAction::Pause));
+        assert_eq!("Hold".parse(), Ok(MinimizeAction::Hold));
+        assert_eq!("placeholder".parse(), Ok(MinimizeAction::Placeholder));
+        assert!("skip".parse::<MinimizeAction>().is_err());
+        assert_eq!(MinimizeAction::Placeholder.to_string(), "placeholder");
+    }
+}
@@ -1,4 +1,32 @@
+pub mod bit_depth;
+pub mod canvas;
+pub mod canvas_layout;
+pub mod codec;
+pub mod color_format;
+pub mod color_range;
+mod content_change;
+mod downsampler;
 pub mod encoder;
 pub mod encoder_device;
 pub mod encoding_session;
-mod processor;
+pub mod fit_mode;
+pub mod frame_pacing;
+pub mod frame_rate_mode;
+mod frame_spool;
+pub mod gpu_sync;
+mod idle_frame_rate;
+pub mod minimize_action;
+mod ndi;
+pub mod orientation;
+pub mod overlay;
+mod preview;
+pub mod processor;
+pub mod rate_control;
+pub mod sample_queue;
+pub mod scale_filter;
+mod scaler;
+mod scene_change;
+mod texture_pool;
+mod thumbnail;
+mod timestamp_sanitizer;
+pub mod zoom;
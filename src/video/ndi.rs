@@ -0,0 +1,229 @@
+use std::{
+    ffi::{c_char, c_void, CString},
+    path::{Path, PathBuf},
+    sync::mpsc::{sync_channel, SyncSender, TrySendError},
+    thread::JoinHandle,
+};
+
+use windows::{
+    core::{s, Error, Result, HSTRING, PCSTR},
+    Graphics::SizeInt32,
+    Win32::{
+        Foundation::{E_FAIL, E_INVALIDARG, HMODULE},
+        Graphics::Direct3D11::{ID3D11Device, ID3D11Texture2D},
+        System::LibraryLoader::{GetProcAddress, LoadLibraryW},
+    },
+};
+
+use crate::capture::FrameReader;
+
+// The NDI runtime can't be redistributed with us, so it's loaded from
+// wherever it was installed (see the NDI SDK's documentation on dynamic loading)
+const NDI_LIBRARY_NAME: &str = "Processing.NDI.Lib.x64.dll";
+const NDI_RUNTIME_DIR_VARIABLES: [&str; 3] = [
+    "NDI_RUNTIME_DIR_V6",
+    "NDI_RUNTIME_DIR_V5",
+    "NDI_RUNTIME_DIR_V4",
+];
+const FOURCC_BGRA: u32 = u32::from_le_bytes(*b"BGRA");
+const FRAME_FORMAT_PROGRESSIVE: i32 = 1;
+// Lets the runtime fill in the timecode when the frame is sent
+const TIMECODE_SYNTHESIZE: i64 = i64::MAX;
+
+// See NDIlib_send_create_t
+#[repr(C)]
+struct SendCreateSettings {
+    ndi_name: *const c_char,
+    groups: *const c_char,
+    clock_video: bool,
+    clock_audio: bool,
+}
+
+// See NDIlib_video_frame_v2_t
+#[repr(C)]
+struct VideoFrame {
+    xres: i32,
+    yres: i32,
+    fourcc: u32,
+    frame_rate_n: i32,
+    frame_rate_d: i32,
+    // Zero means square pixels
+    picture_aspect_ratio: f32,
+    frame_format_type: i32,
+    timecode: i64,
+    data: *const u8,
+    line_stride_in_bytes: i32,
+    metadata: *const c_char,
+    timestamp: i64,
+}
+
+type InitializeFn = unsafe extern "C" fn() -> bool;
+type SendCreateFn = unsafe extern "C" fn(*const SendCreateSettings) -> *mut c_void;
+type SendVideoFn = unsafe extern "C" fn(*mut c_void, *const VideoFrame);
+type SendDestroyFn = unsafe extern "C" fn(*mut c_void);
+
+/// Publishes frames as an NDI source, so that video mixers on the local
+/// network can pick up the recording before it's encoded. Frames are sent
+/// on another thread, and dropped if the previous one is still being sent.
+pub struct NdiSender {
+    frame_reader: FrameReader,
+    sender: Option<SyncSender<(Vec<u8>, SizeInt32)>>,
+    thread_handle: Option<JoinHandle<()>>,
+}
+
+struct SendInstance {
+    instance: *mut c_void,
+    send_video: SendVideoFn,
+    send_destroy: SendDestroyFn,
+    frame_rate: u32,
+}
+
+unsafe impl Send for NdiSender {}
+impl NdiSender {
+    /// Fails if the NDI runtime isn't installed.
+    pub fn new(d3d_device: ID3D11Device, name: &str, frame_rate: u32) -> Result<Self> {
+        let name = CString::new(name).map_err(|_| {
+            Error::new(
+                E_INVALIDARG,
+                "NDI source names can't contain null characters!".into(),
+            )
+        })?;
+        let instance = unsafe { SendInstance::new(&name, frame_rate)? };
+
+        // One frame can wait while another is being sent
+        let (sender, receiver) = sync_channel::<(Vec<u8>, SizeInt32)>(1);
+        let thread_handle = std::thread::spawn(move || {
+            for (pixels, size) in receiver {
+                instance.send(&pixels, size);
+            }
+        });
+        Ok(Self {
+            frame_reader: FrameReader::new(d3d_device)?,
+            sender: Some(sender),
+            thread_handle: Some(thread_handle),
+        })
+    }
+
+    /// Reads the frames of a new device from now on.
+    pub fn reset_device(&mut self, d3d_device: ID3D11Device) -> Result<()> {
+        self.frame_reader.reset_device(d3d_device)
+    }
+
+    /// Sends the frame, which must be BGRA.
+    pub fn send(&mut self, texture: &ID3D11Texture2D, size: SizeInt32) -> Result<()> {
+        let frame = self.frame_reader.read_texture(texture, size, None)?;
+        match self.sender.as_ref().unwrap().try_send(frame) {
+            Ok(()) | Err(TrySendError::Full(_)) => Ok(()),
+            Err(TrySendError::Disconnected(_)) => Err(Error::new(
+                E_FAIL,
+                "The NDI source stopped unexpectedly!".into(),
+            )),
+        }
+    }
+}
+
+impl Drop for NdiSender {
+    fn drop(&mut self) {
+        // Closing the channel lets the thread finish and destroy the source
+        self.sender = None;
+        if let Some(thread_handle) = self.thread_handle.take() {
+            let _ = thread_handle.join();
+        }
+    }
+}
+
+unsafe impl Send for SendInstance {}
+impl SendInstance {
+    unsafe fn new(name: &CString, frame_rate: u32) -> Result<Self> {
+        let library = load_library()?;
+        let initialize: InitializeFn = get_function(library, s!("NDIlib_initialize"))?;
+        let send_create: SendCreateFn = get_function(library, s!("NDIlib_send_create"))?;
+        let send_video: SendVideoFn = get_function(library, s!("NDIlib_send_send_video_v2"))?;
+        let send_destroy: SendDestroyFn = get_function(library, s!("NDIlib_send_destroy"))?;
+
+        if !initialize() {
+            return Err(Error::new(
+                E_FAIL,
+                "NDI isn't supported on this CPU!".into(),
+            ));
+        }
+        // We send frames as they're captured, the receivers take care of timing
+        let settings = SendCreateSettings {
+            ndi_name: name.as_ptr(),
+            groups: std::ptr::null(),
+            clock_video: false,
+            clock_audio: false,
+        };
+        let instance = send_create(&settings);
+        if instance.is_null() {
+            return Err(Error::new(
+                E_FAIL,
+                "Could not create the NDI source!".into(),
+            ));
+        }
+        Ok(Self {
+            instance,
+            send_video,
+            send_destroy,
+            frame_rate,
+        })
+    }
+
+    fn send(&self, pixels: &[u8], size: SizeInt32) {
+        let frame = VideoFrame {
+            xres: size.Width,
+            yres: size.Height,
+            fourcc: FOURCC_BGRA,
+            frame_rate_n: self.frame_rate as i32,
+            frame_rate_d: 1,
+            picture_aspect_ratio: 0.0,
+            frame_format_type: FRAME_FORMAT_PROGRESSIVE,
+            timecode: TIMECODE_SYNTHESIZE,
+            data: pixels.as_ptr(),
+            line_stride_in_bytes: size.Width * 4,
+            metadata: std::ptr::null(),
+            timestamp: 0,
+        };
+        // The pixels only need to stay alive for the duration of the call
+        unsafe { (self.send_video)(self.instance, &frame) };
+    }
+}
+
+impl Drop for SendInstance {
+    fn drop(&mut self) {
+        unsafe { (self.send_destroy)(self.instance) };
+    }
+}
+
+fn load_library() -> Result<HMODULE> {
+    let mut paths: Vec<PathBuf> = NDI_RUNTIME_DIR_VARIABLES
+        .iter()
+        .filter_map(std::env::var_os)
+        .map(|directory| Path::new(&directory).join(NDI_LIBRARY_NAME))
+        .collect();
+    // Falls back to the usual search path, e.g. next to the executable
+    paths.push(PathBuf::from(NDI_LIBRARY_NAME));
+    paths
+        .iter()
+        .find_map(|path| {
+            unsafe { LoadLibraryW(&HSTRING::from(path.to_string_lossy().as_ref())) }.ok()
+        })
+        .ok_or_else(|| {
+            Error::new(
+                E_FAIL,
+                "The NDI runtime could not be found! Install it from https://ndi.video/tools/."
+                    .into(),
+            )
+        })
+}
+
+unsafe fn get_function<T>(library: HMODULE, name: PCSTR) -> Result<T> {
+    let function = GetProcAddress(library, name).ok_or_else(|| {
+        Error::new(
+            E_FAIL,
+            "The installed NDI runtime is too old! Install the latest version from https://ndi.video/tools/."
+                .into(),
+        )
+    })?;
+    Ok(std::mem::transmute_copy(&function))
+}
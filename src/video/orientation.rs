@@ -0,0 +1,163 @@
+use std::{fmt::Display, str::FromStr};
+
+use windows::{
+    Graphics::SizeInt32,
+    Win32::Graphics::Direct3D11::{
+        D3D11_VIDEO_PROCESSOR_ROTATION, D3D11_VIDEO_PROCESSOR_ROTATION_180,
+        D3D11_VIDEO_PROCESSOR_ROTATION_270, D3D11_VIDEO_PROCESSOR_ROTATION_90,
+        D3D11_VIDEO_PROCESSOR_ROTATION_IDENTITY,
+    },
+};
+
+/// How far the captured frames are rotated clockwise, e.g. to correct the
+/// orientation of a portrait monitor.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Rotation {
+    #[default]
+    None,
+    _90,
+    _180,
+    _270,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseRotationError(&'static str);
+
+/// Which way the captured frames are mirrored, before they're rotated.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Flip {
+    Horizontal,
+    Vertical,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseFlipError(&'static str);
+
+/// How the captured frames are turned before they're encoded.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Orientation {
+    pub rotation: Rotation,
+    pub flip: Option<Flip>,
+}
+
+impl Rotation {
+    /// The size of a frame once it's rotated.
+    pub fn rotate_size(&self, size: SizeInt32) -> SizeInt32 {
+        match self {
+            Rotation::None | Rotation::_180 => size,
+            Rotation::_90 | Rotation::_270 => SizeInt32 {
+                Width: size.Height,
+                Height: size.Width,
+            },
+        }
+    }
+
+    pub fn processor_rotation(&self) -> D3D11_VIDEO_PROCESSOR_ROTATION {
+        match self {
+            Rotation::None => D3D11_VIDEO_PROCESSOR_ROTATION_IDENTITY,
+            Rotation::_90 => D3D11_VIDEO_PROCESSOR_ROTATION_90,
+            Rotation::_180 => D3D11_VIDEO_PROCESSOR_ROTATION_180,
+            Rotation::_270 => D3D11_VIDEO_PROCESSOR_ROTATION_270,
+        }
+    }
+}
+
+impl FromStr for Rotation {
+    type Err = ParseRotationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "0" => Ok(Rotation::None),
+            "90" => Ok(Rotation::_90),
+            "180" => Ok(Rotation::_180),
+            "270" => Ok(Rotation::_270),
+            _ => Err(ParseRotationError(
+                "Invalid rotation value! Expecting: 0, 90, 180, or 270.",
+            )),
+        }
+    }
+}
+
+impl Display for Rotation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = match self {
+            Rotation::None => "0",
+            Rotation::_90 => "90",
+            Rotation::_180 => "180",
+            Rotation::_270 => "270",
+        };
+        write!(f, "{}", string)
+    }
+}
+
+impl Display for ParseRotationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseRotationError {}
+
+impl FromStr for Flip {
+    type Err = ParseFlipError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "h" | "horizontal" => Ok(Flip::Horizontal),
+            "v" | "vertical" => Ok(Flip::Vertical),
+            _ => Err(ParseFlipError("Invalid flip value! Expecting: h or v.")),
+        }
+    }
+}
+
+impl Display for Flip {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = match self {
+            Flip::Horizontal => "h",
+            Flip::Vertical => "v",
+        };
+        write!(f, "{}", string)
+    }
+}
+
+impl Display for ParseFlipError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseFlipError {}
+
+#[cfg(test)]
+mod tests {
+    use windows::Graphics::SizeInt32;
+
+    use super::{Flip, Rotation};
+
+    #[test]
+    fn orientation_parsing_test() {
+        assert_eq!("90".parse(), Ok(Rotation::_90));
+        assert_eq!("0".parse(), Ok(Rotation::None));
+        assert!("45".parse::<Rotation>().is_err());
+        assert_eq!(Rotation::_270.to_string(), "270");
+
+        assert_eq!("h".parse(), Ok(Flip::Horizontal));
+        assert_eq!("Vertical".parse(), Ok(Flip::Vertical));
+        assert!("x".parse::<Flip>().is_err());
+        assert_eq!(Flip::Vertical.to_string(), "v");
+    }
+
+    #[test]
+    fn rotate_size_test() {
+        let size = SizeInt32 {
+            Width: 1920,
+            Height: 1080,
+        };
+        let rotated = SizeInt32 {
+            Width: 1080,
+            Height: 1920,
+        };
+        assert_eq!(Rotation::None.rotate_size(size), size);
+        assert_eq!(Rotation::_90.rotate_size(size), rotated);
+        assert_eq!(Rotation::_180.rotate_size(size), size);
+        assert_eq!(Rotation::_270.rotate_size(size), rotated);
+    }
+}
@@ -0,0 +1,264 @@
+use windows::{
+    core::{ComInterface, Result},
+    Graphics::{RectInt32, SizeInt32},
+    Win32::Graphics::{
+        Direct2D::{
+            Common::{D2D1_ALPHA_MODE_PREMULTIPLIED, D2D1_PIXEL_FORMAT, D2D_RECT_F, D2D_SIZE_U},
+            ID2D1Bitmap1, ID2D1DeviceContext, D2D1_BITMAP_INTERPOLATION_MODE_LINEAR,
+            D2D1_BITMAP_INTERPOLATION_MODE_NEAREST_NEIGHBOR, D2D1_BITMAP_OPTIONS,
+            D2D1_BITMAP_OPTIONS_NONE, D2D1_BITMAP_OPTIONS_TARGET, D2D1_BITMAP_PROPERTIES1,
+        },
+        Direct3D11::ID3D11Texture2D,
+        Dxgi::{Common::DXGI_FORMAT, IDXGISurface},
+    },
+};
+
+use super::{RedactedWindows, ScreenOrigin};
+
+// The size of the blocks is relative to the height of the frame, so that
+// text is unreadable regardless of the resolution
+const BLOCKS_PER_FRAME_HEIGHT: i32 = 60;
+const MIN_BLOCK_SIZE: i32 = 8;
+// Redacted windows can be resized, so only so many bitmaps are kept around
+const MAX_CACHED_BITMAPS: usize = 16;
+
+/// Pixelates regions of the frame (e.g. ones that show sensitive data)
+/// before the overlays are drawn. The regions are scaled down into small
+/// bitmaps, which are then scaled back up without smoothing.
+pub struct RegionBlur {
+    size: SizeInt32,
+    format: DXGI_FORMAT,
+    block_size: i32,
+    regions: Vec<RectInt32>,
+    // Windows are pixelated wherever they are in each frame
+    windows: Option<(RedactedWindows, ScreenOrigin)>,
+    // The bitmaps that regions are scaled down into, by their size
+    bitmaps: Vec<(D2D_SIZE_U, ID2D1Bitmap1)>,
+    // The frame texture, and a bitmap that draws from it
+    source: Option<(ID3D11Texture2D, ID2D1Bitmap1)>,
+}
+
+unsafe impl Send for RegionBlur {}
+impl RegionBlur {
+    pub fn new(
+        size: SizeInt32,
+        format: DXGI_FORMAT,
+        regions: Vec<RectInt32>,
+        windows: Option<(RedactedWindows, ScreenOrigin)>,
+    ) -> Self {
+        Self {
+            size,
+            format,
+            block_size: get_block_size(size),
+            regions,
+            windows,
+            bitmaps: Vec::new(),
+            source: None,
+        }
+    }
+
+    /// Changes the size of the frames, e.g. once a recorded window is resized.
+    pub fn resize(&mut self, size: SizeInt32) {
+        self.size = size;
+        self.block_size = get_block_size(size);
+        self.bitmaps.clear();
+        self.source = None;
+    }
+
+    /// Lets go of the bitmaps, which belong to the old device.
+    pub fn reset_device(&mut self) {
+        self.bitmaps.clear();
+        self.source = None;
+    }
+
+    fn get_bitmap(
+        &mut self,
+        context: &ID2D1DeviceContext,
+        size: D2D_SIZE_U,
+    ) -> Result<ID2D1Bitmap1> {
+        if let Some((_, bitmap)) = self
+            .bitmaps
+            .iter()
+            .find(|(other, _)| other.width == size.width && other.height == size.height)
+        {
+            return Ok(bitmap.clone());
+        }
+        if self.bitmaps.len() >= MAX_CACHED_BITMAPS {
+            self.bitmaps.clear();
+        }
+        let properties = get_bitmap_properties(self.format, D2D1_BITMAP_OPTIONS_TARGET);
+        let bitmap = unsafe { context.CreateBitmap2(size, None, 0, &properties)? };
+        self.bitmaps.push((size, bitmap.clone()));
+        Ok(bitmap)
+    }
+
+    /// Draws the pixelated regions of the frame texture onto the target,
+    /// which has to be a copy of the frame texture.
+    pub fn draw(
+        &mut self,
+        context: &ID2D1DeviceContext,
+        target: &ID2D1Bitmap1,
+        frame_texture: &ID3D11Texture2D,
+    ) -> Result<()> {
+        let source = match &self.source {
+            Some((texture, source)) if texture == frame_texture => source.clone(),
+            _ => {
+                let properties = get_bitmap_properties(self.format, D2D1_BITMAP_OPTIONS_NONE);
+                let source = unsafe {
+                    let surface: IDXGISurface = frame_texture.cast()?;
+                    context.CreateBitmapFromDxgiSurface(&surface, Some(&properties))?
+                };
+                self.source = Some((frame_texture.clone(), source.clone()));
+                source
+            }
+        };
+        let mut regions = self.regions.clone();
+        if let Some((windows, origin)) = self.windows.as_mut() {
+            regions.extend(windows.get_rects(origin.resolve()?));
+        }
+        let mut pixelated_regions = Vec::new();
+        for region in regions {
+            if let Some(region) = clamp_region(region, self.size) {
+                let bitmap =
+                    self.get_bitmap(context, get_pixelated_size(region, self.block_size))?;
+                let rect = D2D_RECT_F {
+                    left: region.X as f32,
+                    top: region.Y as f32,
+                    right: (region.X + region.Width) as f32,
+                    bottom: (region.Y + region.Height) as f32,
+                };
+                pixelated_regions.push((rect, bitmap));
+            }
+        }
+        if pixelated_regions.is_empty() {
+            return Ok(());
+        }
+        unsafe {
+            for (rect, bitmap) in &pixelated_regions {
+                context.SetTarget(bitmap);
+                context.BeginDraw();
+                context.DrawBitmap(
+                    &source,
+                    None,
+                    1.0,
+                    D2D1_BITMAP_INTERPOLATION_MODE_LINEAR,
+                    Some(rect),
+                );
+                context.EndDraw(None, None)?;
+            }
+            context.SetTarget(target);
+            context.BeginDraw();
+            for (rect, bitmap) in &pixelated_regions {
+                context.DrawBitmap(
+                    bitmap,
+                    Some(rect),
+                    1.0,
+                    D2D1_BITMAP_INTERPOLATION_MODE_NEAREST_NEIGHBOR,
+                    None,
+                );
+            }
+            context.EndDraw(None, None)
+        }
+    }
+}
+
+fn get_bitmap_properties(
+    format: DXGI_FORMAT,
+    options: D2D1_BITMAP_OPTIONS,
+) -> D2D1_BITMAP_PROPERTIES1 {
+    D2D1_BITMAP_PROPERTIES1 {
+        pixelFormat: D2D1_PIXEL_FORMAT {
+            format,
+            alphaMode: D2D1_ALPHA_MODE_PREMULTIPLIED,
+        },
+        dpiX: 96.0,
+        dpiY: 96.0,
+        bitmapOptions: options,
+        ..Default::default()
+    }
+}
+
+fn get_block_size(size: SizeInt32) -> i32 {
+    (size.Height / BLOCKS_PER_FRAME_HEIGHT).max(MIN_BLOCK_SIZE)
+}
+
+// Regions that are partly outside of the frame are cut off at its edges
+fn clamp_region(region: RectInt32, size: SizeInt32) -> Option<RectInt32> {
+    let left = region.X.max(0);
+    let top = region.Y.max(0);
+    let right = (region.X + region.Width).min(size.Width);
+    let bottom = (region.Y + region.Height).min(size.Height);
+    if left >= right || top >= bottom {
+        return None;
+    }
+    Some(RectInt32 {
+        X: left,
+        Y: top,
+        Width: right - left,
+        Height: bottom - top,
+    })
+}
+
+// Every block becomes a single pixel, with at least one in each direction
+fn get_pixelated_size(region: RectInt32, block_size: i32) -> D2D_SIZE_U {
+    D2D_SIZE_U {
+        width: ((region.Width + block_size - 1) / block_size).max(1) as u32,
+        height: ((region.Height + block_size - 1) / block_size).max(1) as u32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::{
+        Graphics::{RectInt32, SizeInt32},
+        Win32::Graphics::Direct2D::Common::D2D_SIZE_U,
+    };
+
+    use super::{clamp_region, get_pixelated_size};
+
+    fn rect(x: i32, y: i32, width: i32, height: i32) -> RectInt32 {
+        RectInt32 {
+            X: x,
+            Y: y,
+            Width: width,
+            Height: height,
+        }
+    }
+
+    #[test]
+    fn region_test() {
+        let size = SizeInt32 {
+            Width: 1920,
+            Height: 1080,
+        };
+        assert_eq!(
+            clamp_region(rect(100, 100, 200, 50), size),
+            Some(rect(100, 100, 200, 50))
+        );
+        assert_eq!(
+            clamp_region(rect(1800, 1000, 200, 200), size),
+            Some(rect(1800, 1000, 120, 80))
+        );
+        assert_eq!(clamp_region(rect(1920, 0, 100, 100), size), None);
+        // Windows can be partly off the left or the top of the frame
+        assert_eq!(
+            clamp_region(rect(-50, -10, 100, 100), size),
+            Some(rect(0, 0, 50, 90))
+        );
+
+        assert_eq!(
+            get_pixelated_size(rect(0, 0, 200, 50), 18),
+            D2D_SIZE_U {
+                width: 12,
+                height: 3
+            }
+        );
+        assert_eq!(
+            get_pixelated_size(rect(0, 0, 4, 4), 18),
+            D2D_SIZE_U {
+                width: 1,
+                height: 1
+            }
+        );
+    }
+}
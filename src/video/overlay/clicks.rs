@@ -0,0 +1,147 @@
+use std::{sync::Arc, time::Duration};
+
+use windows::{
+    core::Result,
+    Graphics::{PointInt32, SizeInt32},
+    Win32::{
+        Foundation::{HWND, RECT},
+        Graphics::{
+            Direct2D::{
+                Common::{D2D1_COLOR_F, D2D_POINT_2F},
+                ID2D1DeviceContext, D2D1_ELLIPSE,
+            },
+            Dwm::{DwmGetWindowAttribute, DWMWA_EXTENDED_FRAME_BOUNDS},
+        },
+    },
+};
+
+use crate::input_hook::MouseHook;
+
+use super::{get_font_size, Overlay};
+
+// How long a click stays visible
+const CLICK_DURATION: Duration = Duration::from_millis(500);
+const HIGHLIGHT_COLOR: D2D1_COLOR_F = D2D1_COLOR_F {
+    r: 1.0,
+    g: 0.85,
+    b: 0.0,
+    a: 1.0,
+};
+
+/// Where the top left corner of the frame is on the desktop, so that
+/// clicks can be mapped onto the frame.
+#[derive(Copy, Clone, Debug)]
+pub struct ScreenOrigin {
+    // Windows can move while recording, so their position is looked up for each frame
+    window: Option<HWND>,
+    offset: PointInt32,
+}
+
+impl ScreenOrigin {
+    pub fn point(point: PointInt32) -> Self {
+        Self {
+            window: None,
+            offset: point,
+        }
+    }
+
+    pub fn window(window: HWND) -> Self {
+        Self {
+            window: Some(window),
+            offset: PointInt32 { X: 0, Y: 0 },
+        }
+    }
+
+    /// Moves the origin, e.g. to the top left corner of a region.
+    pub fn offset(self, x: i32, y: i32) -> Self {
+        Self {
+            offset: PointInt32 {
+                X: self.offset.X + x,
+                Y: self.offset.Y + y,
+            },
+            ..self
+        }
+    }
+
+    pub fn resolve(&self) -> Result<PointInt32> {
+        let mut origin = self.offset;
+        if let Some(window) = self.window {
+            // The extended frame bounds match what is captured, unlike GetWindowRect
+            let mut rect = RECT::default();
+            unsafe {
+                DwmGetWindowAttribute(
+                    window,
+                    DWMWA_EXTENDED_FRAME_BOUNDS,
+                    &mut rect as *mut _ as *mut _,
+                    std::mem::size_of::<RECT>() as u32,
+                )?;
+            }
+            origin.X += rect.left;
+            origin.Y += rect.top;
+        }
+        Ok(origin)
+    }
+}
+
+/// Highlights mouse clicks with a circle that grows and fades out.
+pub struct ClickOverlay {
+    mouse_hook: Arc<MouseHook>,
+    origin: ScreenOrigin,
+}
+
+unsafe impl Send for ClickOverlay {}
+impl ClickOverlay {
+    /// The hook can be shared between overlays, e.g. one per display.
+    pub fn new(mouse_hook: Arc<MouseHook>, origin: ScreenOrigin) -> Self {
+        Self { mouse_hook, origin }
+    }
+}
+
+impl Overlay for ClickOverlay {
+    fn draw(&mut self, context: &ID2D1DeviceContext, size: SizeInt32) -> Result<()> {
+        let clicks = self.mouse_hook.recent_clicks(CLICK_DURATION);
+        if clicks.is_empty() {
+            return Ok(());
+        }
+        let origin = self.origin.resolve()?;
+        let base_radius = get_font_size(size);
+        unsafe {
+            let brush = context.CreateSolidColorBrush(&HIGHLIGHT_COLOR, None)?;
+            for click in clicks {
+                let progress = get_progress(click.time.elapsed());
+                let ellipse = D2D1_ELLIPSE {
+                    point: D2D_POINT_2F {
+                        x: (click.point.X - origin.X) as f32,
+                        y: (click.point.Y - origin.Y) as f32,
+                    },
+                    radiusX: base_radius * (1.0 + progress),
+                    radiusY: base_radius * (1.0 + progress),
+                };
+                brush.SetOpacity(0.4 * (1.0 - progress));
+                context.FillEllipse(&ellipse, &brush);
+                brush.SetOpacity(1.0 - progress);
+                context.DrawEllipse(&ellipse, &brush, base_radius / 6.0, None);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How far along its animation a click is, from 0.0 to 1.0.
+fn get_progress(age: Duration) -> f32 {
+    (age.as_secs_f32() / CLICK_DURATION.as_secs_f32()).min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::get_progress;
+
+    #[test]
+    fn click_progress_test() {
+        assert_eq!(get_progress(Duration::ZERO), 0.0);
+        assert_eq!(get_progress(Duration::from_millis(250)), 0.5);
+        assert_eq!(get_progress(Duration::from_secs(5)), 1.0);
+    }
+}
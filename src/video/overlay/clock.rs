@@ -0,0 +1,149 @@
+use windows::{
+    core::{w, Result},
+    Graphics::SizeInt32,
+    Win32::{
+        Foundation::SYSTEMTIME,
+        Graphics::{
+            Direct2D::{
+                Common::{D2D1_COLOR_F, D2D_POINT_2F, D2D_RECT_F},
+                ID2D1DeviceContext, D2D1_DRAW_TEXT_OPTIONS_NONE,
+            },
+            DirectWrite::{
+                DWriteCreateFactory, IDWriteFactory, IDWriteTextFormat, DWRITE_FACTORY_TYPE_SHARED,
+                DWRITE_FONT_STRETCH_NORMAL, DWRITE_FONT_STYLE_NORMAL, DWRITE_FONT_WEIGHT_NORMAL,
+                DWRITE_TEXT_METRICS,
+            },
+        },
+        System::SystemInformation::GetLocalTime,
+    },
+};
+
+use super::{get_font_size, Overlay};
+
+const TEXT_COLOR: D2D1_COLOR_F = D2D1_COLOR_F {
+    r: 1.0,
+    g: 1.0,
+    b: 1.0,
+    a: 1.0,
+};
+const BACKGROUND_COLOR: D2D1_COLOR_F = D2D1_COLOR_F {
+    r: 0.0,
+    g: 0.0,
+    b: 0.0,
+    a: 0.6,
+};
+
+/// Burns the current wall-clock time into the top left corner of each
+/// frame, so that recordings can be correlated with logs.
+pub struct ClockOverlay {
+    dwrite_factory: IDWriteFactory,
+    text_format: Option<(f32, IDWriteTextFormat)>,
+}
+
+unsafe impl Send for ClockOverlay {}
+impl ClockOverlay {
+    pub fn new() -> Result<Self> {
+        let dwrite_factory = unsafe { DWriteCreateFactory(DWRITE_FACTORY_TYPE_SHARED)? };
+        Ok(Self {
+            dwrite_factory,
+            text_format: None,
+        })
+    }
+
+    fn get_text_format(&mut self, font_size: f32) -> Result<IDWriteTextFormat> {
+        if let Some((size, text_format)) = &self.text_format {
+            if *size == font_size {
+                return Ok(text_format.clone());
+            }
+        }
+        let text_format = unsafe {
+            self.dwrite_factory.CreateTextFormat(
+                w!("Consolas"),
+                None,
+                DWRITE_FONT_WEIGHT_NORMAL,
+                DWRITE_FONT_STYLE_NORMAL,
+                DWRITE_FONT_STRETCH_NORMAL,
+                font_size,
+                w!(""),
+            )?
+        };
+        self.text_format = Some((font_size, text_format.clone()));
+        Ok(text_format)
+    }
+}
+
+impl Overlay for ClockOverlay {
+    fn draw(&mut self, context: &ID2D1DeviceContext, size: SizeInt32) -> Result<()> {
+        let font_size = get_font_size(size);
+        let text_format = self.get_text_format(font_size)?;
+        let text: Vec<u16> = format_time(&unsafe { GetLocalTime() })
+            .encode_utf16()
+            .collect();
+        unsafe {
+            let layout = self.dwrite_factory.CreateTextLayout(
+                &text,
+                &text_format,
+                size.Width as f32,
+                size.Height as f32,
+            )?;
+            let mut metrics = DWRITE_TEXT_METRICS::default();
+            layout.GetMetrics(&mut metrics)?;
+
+            let padding = font_size / 4.0;
+            let background_rect = D2D_RECT_F {
+                left: 0.0,
+                top: 0.0,
+                right: metrics.width + padding * 2.0,
+                bottom: metrics.height + padding * 2.0,
+            };
+            let background_brush = context.CreateSolidColorBrush(&BACKGROUND_COLOR, None)?;
+            context.FillRectangle(&background_rect, &background_brush);
+            let text_brush = context.CreateSolidColorBrush(&TEXT_COLOR, None)?;
+            context.DrawTextLayout(
+                D2D_POINT_2F {
+                    x: padding,
+                    y: padding,
+                },
+                &layout,
+                &text_brush,
+                D2D1_DRAW_TEXT_OPTIONS_NONE,
+            );
+        }
+        Ok(())
+    }
+}
+
+fn format_time(time: &SYSTEMTIME) -> String {
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
+        time.wYear,
+        time.wMonth,
+        time.wDay,
+        time.wHour,
+        time.wMinute,
+        time.wSecond,
+        time.wMilliseconds
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::Foundation::SYSTEMTIME;
+
+    use super::format_time;
+
+    #[test]
+    fn time_format_test() {
+        let time = SYSTEMTIME {
+            wYear: 2023,
+            wMonth: 9,
+            wDay: 4,
+            wHour: 14,
+            wMinute: 5,
+            wSecond: 7,
+            wMilliseconds: 42,
+            ..Default::default()
+        };
+        assert_eq!(format_time(&time), "2023-09-04 14:05:07.042");
+    }
+}
@@ -0,0 +1,204 @@
+use std::{
+    fmt::Display,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use windows::{
+    core::Result,
+    Graphics::{PointInt32, RectInt32, SizeInt32},
+    Win32::{
+        Foundation::{HWND, RECT},
+        Graphics::{
+            Direct2D::{
+                Common::{D2D1_COLOR_F, D2D_RECT_F},
+                ID2D1DeviceContext,
+            },
+            Dwm::{DwmGetWindowAttribute, DWMWA_EXTENDED_FRAME_BOUNDS},
+        },
+        UI::WindowsAndMessaging::{IsIconic, IsWindow, IsWindowVisible},
+    },
+};
+
+use crate::window::{find_app_windows, is_cloaked};
+
+use super::{Overlay, ScreenOrigin};
+
+// How often the windows of redacted apps are looked up, so that new
+// windows (e.g. notifications) are redacted soon after they show up
+const APP_LOOKUP_INTERVAL: Duration = Duration::from_millis(250);
+const MASK_COLOR: D2D1_COLOR_F = D2D1_COLOR_F {
+    r: 0.0,
+    g: 0.0,
+    b: 0.0,
+    a: 1.0,
+};
+
+/// How redacted windows are hidden in the recording.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RedactionStyle {
+    /// Covered with black.
+    Blank,
+    /// Pixelated like a blurred region, so that it's still clear that
+    /// something is there.
+    Pixelate,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseRedactionStyleError(&'static str);
+
+/// Other processes' windows to hide in the recording, which can't be
+/// excluded from capture like the recorder's own windows (see
+/// SetWindowDisplayAffinity). Anything on top of them is hidden too.
+#[derive(Clone, Debug, Default)]
+pub struct RedactedWindows {
+    windows: Vec<HWND>,
+    apps: Vec<String>,
+    app_windows: Vec<HWND>,
+    last_lookup: Option<Instant>,
+}
+
+unsafe impl Send for RedactedWindows {}
+impl RedactedWindows {
+    /// The apps are named by their executable, see find_app_windows.
+    pub fn new(windows: Vec<HWND>, apps: Vec<String>) -> Self {
+        Self {
+            windows,
+            apps,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty() && self.apps.is_empty()
+    }
+
+    /// The bounds of the redacted windows that are on the screen, relative to
+    /// the origin.
+    pub fn get_rects(&mut self, origin: PointInt32) -> Vec<RectInt32> {
+        if !self.apps.is_empty()
+            && self
+                .last_lookup
+                .map(|last_lookup| last_lookup.elapsed() >= APP_LOOKUP_INTERVAL)
+                .unwrap_or(true)
+        {
+            self.app_windows = find_app_windows(&self.apps);
+            self.last_lookup = Some(Instant::now());
+        }
+        self.windows
+            .iter()
+            .chain(&self.app_windows)
+            .filter_map(|window| get_window_bounds(*window))
+            .map(|rect| RectInt32 {
+                X: rect.left - origin.X,
+                Y: rect.top - origin.Y,
+                Width: rect.right - rect.left,
+                Height: rect.bottom - rect.top,
+            })
+            .collect()
+    }
+}
+
+// Closed, hidden, or minimized windows aren't on the screen
+fn get_window_bounds(window: HWND) -> Option<RECT> {
+    unsafe {
+        if !IsWindow(window).as_bool()
+            || !IsWindowVisible(window).as_bool()
+            || IsIconic(window).as_bool()
+            || is_cloaked(window)
+        {
+            return None;
+        }
+        let mut rect = RECT::default();
+        DwmGetWindowAttribute(
+            window,
+            DWMWA_EXTENDED_FRAME_BOUNDS,
+            &mut rect as *mut _ as *mut _,
+            std::mem::size_of::<RECT>() as u32,
+        )
+        .ok()?;
+        Some(rect)
+    }
+}
+
+/// Covers redacted windows wherever they are in the recording, see
+/// RedactionStyle::Blank.
+pub struct ExcludedWindowsOverlay {
+    windows: RedactedWindows,
+    origin: ScreenOrigin,
+}
+
+unsafe impl Send for ExcludedWindowsOverlay {}
+impl ExcludedWindowsOverlay {
+    pub fn new(windows: RedactedWindows, origin: ScreenOrigin) -> Self {
+        Self { windows, origin }
+    }
+}
+
+impl Overlay for ExcludedWindowsOverlay {
+    fn draw(&mut self, context: &ID2D1DeviceContext, _size: SizeInt32) -> Result<()> {
+        let origin = self.origin.resolve()?;
+        let rects = self.windows.get_rects(origin);
+        if rects.is_empty() {
+            return Ok(());
+        }
+        unsafe {
+            let brush = context.CreateSolidColorBrush(&MASK_COLOR, None)?;
+            for rect in rects {
+                let rect = D2D_RECT_F {
+                    left: rect.X as f32,
+                    top: rect.Y as f32,
+                    right: (rect.X + rect.Width) as f32,
+                    bottom: (rect.Y + rect.Height) as f32,
+                };
+                context.FillRectangle(&rect, &brush);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for RedactionStyle {
+    type Err = ParseRedactionStyleError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "blank" => Ok(RedactionStyle::Blank),
+            "pixelate" | "blur" => Ok(RedactionStyle::Pixelate),
+            _ => Err(ParseRedactionStyleError(
+                "Invalid redaction style! Expecting: blank or pixelate.",
+            )),
+        }
+    }
+}
+
+impl Display for RedactionStyle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = match self {
+            RedactionStyle::Blank => "blank",
+            RedactionStyle::Pixelate => "pixelate",
+        };
+        write!(f, "{}", string)
+    }
+}
+
+impl Display for ParseRedactionStyleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseRedactionStyleError {}
+
+#[cfg(test)]
+mod tests {
+    use super::RedactionStyle;
+
+    #[test]
+    fn redaction_style_parsing_test() {
+        assert_eq!("blank".parse(), Ok(RedactionStyle::Blank));
+        assert_eq!("Pixelate".parse(), Ok(RedactionStyle::Pixelate));
+        assert_eq!("blur".parse(), Ok(RedactionStyle::Pixelate));
+        assert!("hide".parse::<RedactionStyle>().is_err());
+        assert_eq!(RedactionStyle::Pixelate.to_string(), "pixelate");
+    }
+}
@@ -0,0 +1,145 @@
+use std::{sync::Arc, time::Duration};
+
+use windows::{
+    core::{w, Result},
+    Graphics::SizeInt32,
+    Win32::Graphics::{
+        Direct2D::{
+            Common::{D2D1_COLOR_F, D2D_POINT_2F, D2D_RECT_F},
+            ID2D1DeviceContext, D2D1_DRAW_TEXT_OPTIONS_NONE, D2D1_ROUNDED_RECT,
+        },
+        DirectWrite::{
+            DWriteCreateFactory, IDWriteFactory, IDWriteTextFormat, DWRITE_FACTORY_TYPE_SHARED,
+            DWRITE_FONT_STRETCH_NORMAL, DWRITE_FONT_STYLE_NORMAL, DWRITE_FONT_WEIGHT_SEMI_BOLD,
+            DWRITE_TEXT_METRICS,
+        },
+    },
+};
+
+use crate::input_hook::KeyboardHook;
+
+use super::{get_font_size, position::OverlayPosition, Overlay};
+
+// How long a key combination stays visible, the last part of which it fades out
+const KEY_DURATION: Duration = Duration::from_millis(1500);
+const FADE_DURATION: Duration = Duration::from_millis(500);
+const TEXT_COLOR: D2D1_COLOR_F = D2D1_COLOR_F {
+    r: 1.0,
+    g: 1.0,
+    b: 1.0,
+    a: 1.0,
+};
+const BACKGROUND_COLOR: D2D1_COLOR_F = D2D1_COLOR_F {
+    r: 0.0,
+    g: 0.0,
+    b: 0.0,
+    a: 0.7,
+};
+
+/// Shows the last key combination pressed (e.g. Ctrl+Shift+P) near the
+/// bottom of the frame.
+pub struct KeyOverlay {
+    keyboard_hook: Arc<KeyboardHook>,
+    dwrite_factory: IDWriteFactory,
+    text_format: Option<IDWriteTextFormat>,
+}
+
+unsafe impl Send for KeyOverlay {}
+impl KeyOverlay {
+    /// The hook can be shared between overlays, e.g. one per display.
+    pub fn new(keyboard_hook: Arc<KeyboardHook>) -> Result<Self> {
+        let dwrite_factory = unsafe { DWriteCreateFactory(DWRITE_FACTORY_TYPE_SHARED)? };
+        Ok(Self {
+            keyboard_hook,
+            dwrite_factory,
+            text_format: None,
+        })
+    }
+}
+
+impl Overlay for KeyOverlay {
+    fn draw(&mut self, context: &ID2D1DeviceContext, size: SizeInt32) -> Result<()> {
+        let key_press = match self.keyboard_hook.last_key_press() {
+            Some(key_press) if key_press.time.elapsed() < KEY_DURATION => key_press,
+            _ => return Ok(()),
+        };
+        let opacity = get_opacity(key_press.time.elapsed());
+        let font_size = get_font_size(size) * 2.0;
+        unsafe {
+            // The frame size never changes, so the format can be reused
+            if self.text_format.is_none() {
+                self.text_format = Some(self.dwrite_factory.CreateTextFormat(
+                    w!("Segoe UI"),
+                    None,
+                    DWRITE_FONT_WEIGHT_SEMI_BOLD,
+                    DWRITE_FONT_STYLE_NORMAL,
+                    DWRITE_FONT_STRETCH_NORMAL,
+                    font_size,
+                    w!(""),
+                )?);
+            }
+            let text: Vec<u16> = key_press.combination.to_string().encode_utf16().collect();
+            let layout = self.dwrite_factory.CreateTextLayout(
+                &text,
+                self.text_format.as_ref().unwrap(),
+                size.Width as f32,
+                size.Height as f32,
+            )?;
+            let mut metrics = DWRITE_TEXT_METRICS::default();
+            layout.GetMetrics(&mut metrics)?;
+
+            let padding = font_size / 3.0;
+            let (width, height) = (
+                metrics.width + padding * 2.0,
+                metrics.height + padding * 2.0,
+            );
+            let (x, _) = OverlayPosition::Center.origin(size, (width, height), 0.0);
+            let (_, y) = OverlayPosition::BottomLeft.origin(size, (width, height), font_size);
+            let background = D2D1_ROUNDED_RECT {
+                rect: D2D_RECT_F {
+                    left: x,
+                    top: y,
+                    right: x + width,
+                    bottom: y + height,
+                },
+                radiusX: padding,
+                radiusY: padding,
+            };
+            let background_brush = context.CreateSolidColorBrush(&BACKGROUND_COLOR, None)?;
+            background_brush.SetOpacity(opacity);
+            context.FillRoundedRectangle(&background, &background_brush);
+            let text_brush = context.CreateSolidColorBrush(&TEXT_COLOR, None)?;
+            text_brush.SetOpacity(opacity);
+            context.DrawTextLayout(
+                D2D_POINT_2F {
+                    x: x + padding,
+                    y: y + padding,
+                },
+                &layout,
+                &text_brush,
+                D2D1_DRAW_TEXT_OPTIONS_NONE,
+            );
+        }
+        Ok(())
+    }
+}
+
+fn get_opacity(age: Duration) -> f32 {
+    let remaining = KEY_DURATION.saturating_sub(age);
+    (remaining.as_secs_f32() / FADE_DURATION.as_secs_f32()).min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::get_opacity;
+
+    #[test]
+    fn key_opacity_test() {
+        assert_eq!(get_opacity(Duration::ZERO), 1.0);
+        assert_eq!(get_opacity(Duration::from_millis(1000)), 1.0);
+        assert_eq!(get_opacity(Duration::from_millis(1250)), 0.5);
+        assert_eq!(get_opacity(Duration::from_secs(2)), 0.0);
+    }
+}
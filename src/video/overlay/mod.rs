@@ -0,0 +1,215 @@
+mod blur;
+mod clicks;
+mod clock;
+mod excluded_windows;
+mod keys;
+mod pip;
+mod placeholder;
+pub mod position;
+mod watermark;
+mod webcam;
+
+use windows::{
+    core::{ComInterface, Result},
+    Graphics::SizeInt32,
+    Win32::Graphics::{
+        Direct2D::{
+            Common::{D2D1_ALPHA_MODE_PREMULTIPLIED, D2D1_PIXEL_FORMAT},
+            D2D1CreateFactory, ID2D1Bitmap1, ID2D1DeviceContext, ID2D1Factory1,
+            D2D1_BITMAP_OPTIONS_CANNOT_DRAW, D2D1_BITMAP_OPTIONS_TARGET, D2D1_BITMAP_PROPERTIES1,
+            D2D1_DEVICE_CONTEXT_OPTIONS_NONE, D2D1_FACTORY_TYPE_SINGLE_THREADED,
+        },
+        Direct3D11::{
+            ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D, D3D11_BIND_RENDER_TARGET,
+            D3D11_BIND_SHADER_RESOURCE, D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT,
+        },
+        Dxgi::{
+            Common::{DXGI_FORMAT, DXGI_SAMPLE_DESC},
+            IDXGIDevice, IDXGISurface,
+        },
+    },
+};
+
+pub use self::{
+    blur::RegionBlur,
+    clicks::{ClickOverlay, ScreenOrigin},
+    clock::ClockOverlay,
+    excluded_windows::{
+        ExcludedWindowsOverlay, ParseRedactionStyleError, RedactedWindows, RedactionStyle,
+    },
+    keys::KeyOverlay,
+    pip::{PictureInPictureOverlay, PictureInPictureSettings, PictureInPictureSource},
+    placeholder::PlaceholderOverlay,
+    watermark::{WatermarkContent, WatermarkOverlay, WatermarkSettings},
+    webcam::{WebcamOverlay, WebcamSettings},
+};
+
+// Text is sized relative to the height of the frame
+const TEXT_HEIGHT_RATIO: f32 = 1.0 / 36.0;
+const MIN_FONT_SIZE: f32 = 12.0;
+
+/// Something drawn on top of every frame before it is encoded.
+pub trait Overlay: Send {
+    /// Draws onto the context, whose target is the size of the frame.
+    fn draw(&mut self, context: &ID2D1DeviceContext, size: SizeInt32) -> Result<()>;
+
+    /// Moves the overlay over to a new device, e.g. once the old one was
+    /// lost. Resources created from the old context have to be let go.
+    fn reset_device(&mut self, _d3d_device: &ID3D11Device) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn get_font_size(frame_size: SizeInt32) -> f32 {
+    (frame_size.Height as f32 * TEXT_HEIGHT_RATIO).max(MIN_FONT_SIZE)
+}
+
+/// Draws overlays onto a copy of the composed frame, after pixelating any
+/// blurred regions. The compose texture can't be drawn on directly since,
+/// when compositing, it keeps the last frame from each item.
+pub struct OverlayRenderer {
+    d3d_device: ID3D11Device,
+    d3d_context: ID3D11DeviceContext,
+    texture: ID3D11Texture2D,
+    d2d_context: ID2D1DeviceContext,
+    target: ID2D1Bitmap1,
+    size: SizeInt32,
+    format: DXGI_FORMAT,
+    blur: Option<RegionBlur>,
+    overlays: Vec<Box<dyn Overlay>>,
+}
+
+unsafe impl Send for OverlayRenderer {}
+impl OverlayRenderer {
+    pub fn new(
+        d3d_device: &ID3D11Device,
+        size: SizeInt32,
+        format: DXGI_FORMAT,
+        overlays: Vec<Box<dyn Overlay>>,
+        blur: Option<RegionBlur>,
+    ) -> Result<Self> {
+        let d2d_context = create_d2d_context(d3d_device)?;
+        let (texture, target) = create_target(d3d_device, &d2d_context, size, format)?;
+
+        Ok(Self {
+            d3d_device: d3d_device.clone(),
+            d3d_context: unsafe { d3d_device.GetImmediateContext()? },
+            texture,
+            d2d_context,
+            target,
+            size,
+            format,
+            blur,
+            overlays,
+        })
+    }
+
+    /// Changes the size of the frames, e.g. once a recorded window is resized.
+    pub fn resize(&mut self, size: SizeInt32) -> Result<()> {
+        let (texture, target) =
+            create_target(&self.d3d_device, &self.d2d_context, size, self.format)?;
+        self.texture = texture;
+        self.target = target;
+        self.size = size;
+        if let Some(blur) = self.blur.as_mut() {
+            blur.resize(size);
+        }
+        Ok(())
+    }
+
+    /// Moves the renderer and its overlays over to a new device.
+    pub fn reset_device(&mut self, d3d_device: &ID3D11Device) -> Result<()> {
+        let d2d_context = create_d2d_context(d3d_device)?;
+        let (texture, target) = create_target(d3d_device, &d2d_context, self.size, self.format)?;
+        self.d3d_device = d3d_device.clone();
+        self.d3d_context = unsafe { d3d_device.GetImmediateContext()? };
+        self.texture = texture;
+        self.d2d_context = d2d_context;
+        self.target = target;
+        if let Some(blur) = self.blur.as_mut() {
+            blur.reset_device();
+        }
+        for overlay in &mut self.overlays {
+            overlay.reset_device(d3d_device)?;
+        }
+        Ok(())
+    }
+
+    /// Copies the frame and draws the overlays on top of it. The returned
+    /// texture is reused for the next frame.
+    pub fn render(&mut self, frame_texture: &ID3D11Texture2D) -> Result<ID3D11Texture2D> {
+        unsafe {
+            self.d3d_context.CopyResource(&self.texture, frame_texture);
+            if let Some(blur) = self.blur.as_mut() {
+                blur.draw(&self.d2d_context, &self.target, frame_texture)?;
+            }
+            self.d2d_context.BeginDraw();
+            let mut result = Ok(());
+            for overlay in &mut self.overlays {
+                result = overlay.draw(&self.d2d_context, self.size);
+                if result.is_err() {
+                    break;
+                }
+            }
+            // Drawing has to be ended even if an overlay failed
+            self.d2d_context.EndDraw(None, None)?;
+            result?;
+        }
+        Ok(self.texture.clone())
+    }
+}
+
+fn create_d2d_context(d3d_device: &ID3D11Device) -> Result<ID2D1DeviceContext> {
+    // Frames are only drawn on the thread that generates samples
+    unsafe {
+        let factory: ID2D1Factory1 = D2D1CreateFactory(D2D1_FACTORY_TYPE_SINGLE_THREADED, None)?;
+        let dxgi_device: IDXGIDevice = d3d_device.cast()?;
+        let d2d_device = factory.CreateDevice(&dxgi_device)?;
+        d2d_device.CreateDeviceContext(D2D1_DEVICE_CONTEXT_OPTIONS_NONE)
+    }
+}
+
+// The texture the overlays are drawn on, and the bitmap that draws to it
+fn create_target(
+    d3d_device: &ID3D11Device,
+    d2d_context: &ID2D1DeviceContext,
+    size: SizeInt32,
+    format: DXGI_FORMAT,
+) -> Result<(ID3D11Texture2D, ID2D1Bitmap1)> {
+    let texture_desc = D3D11_TEXTURE2D_DESC {
+        Width: size.Width as u32,
+        Height: size.Height as u32,
+        ArraySize: 1,
+        MipLevels: 1,
+        Format: format,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            ..Default::default()
+        },
+        Usage: D3D11_USAGE_DEFAULT,
+        BindFlags: (D3D11_BIND_RENDER_TARGET.0 | D3D11_BIND_SHADER_RESOURCE.0) as u32,
+        ..Default::default()
+    };
+    let texture = unsafe {
+        let mut texture = None;
+        d3d_device.CreateTexture2D(&texture_desc, None, Some(&mut texture))?;
+        texture.unwrap()
+    };
+    let target = unsafe {
+        let surface: IDXGISurface = texture.cast()?;
+        let properties = D2D1_BITMAP_PROPERTIES1 {
+            pixelFormat: D2D1_PIXEL_FORMAT {
+                format,
+                alphaMode: D2D1_ALPHA_MODE_PREMULTIPLIED,
+            },
+            dpiX: 96.0,
+            dpiY: 96.0,
+            bitmapOptions: D2D1_BITMAP_OPTIONS_TARGET | D2D1_BITMAP_OPTIONS_CANNOT_DRAW,
+            ..Default::default()
+        };
+        let target = d2d_context.CreateBitmapFromDxgiSurface(&surface, Some(&properties))?;
+        d2d_context.SetTarget(&target);
+        target
+    };
+    Ok((texture, target))
+}
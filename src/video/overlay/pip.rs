@@ -0,0 +1,175 @@
+use std::time::Duration;
+
+use windows::{
+    core::Result,
+    Graphics::{
+        Capture::{Direct3D11CaptureFrame, GraphicsCaptureItem, GraphicsCaptureSession},
+        DirectX::DirectXPixelFormat,
+        SizeInt32,
+    },
+    Win32::{
+        Foundation::HWND,
+        Graphics::{
+            Direct2D::{
+                Common::{D2D1_ALPHA_MODE_PREMULTIPLIED, D2D1_PIXEL_FORMAT, D2D_RECT_F},
+                ID2D1Bitmap1, ID2D1DeviceContext, D2D1_BITMAP_INTERPOLATION_MODE_LINEAR,
+                D2D1_BITMAP_OPTIONS_NONE, D2D1_BITMAP_PROPERTIES1,
+            },
+            Direct3D11::ID3D11Device,
+            Dxgi::{IDXGISurface, DXGI_SURFACE_DESC},
+        },
+    },
+};
+
+use crate::{
+    capture::{CaptureFrameGenerator, CaptureFrameWait},
+    d3d::get_d3d_interface_from_object,
+};
+
+use super::{get_font_size, position::OverlayPosition, Overlay};
+
+/// What is shown in a picture-in-picture inset.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PictureInPictureSource {
+    /// The index of a display.
+    Display(usize),
+    Window(HWND),
+}
+
+#[derive(Clone, Debug)]
+pub struct PictureInPictureSettings {
+    pub source: PictureInPictureSource,
+    pub position: OverlayPosition,
+    /// The width of the inset as a percentage of the width of the video.
+    pub size: u32,
+}
+
+/// Draws the latest frame of a second capture item (another display or a
+/// window) into a corner of each frame.
+pub struct PictureInPictureOverlay {
+    frame_generator: CaptureFrameGenerator,
+    position: OverlayPosition,
+    size: u32,
+    // The latest frame is kept open (along with the bitmap that draws it
+    // and its content size) until a newer one arrives
+    latest: Option<(Direct3D11CaptureFrame, ID2D1Bitmap1, SizeInt32)>,
+}
+
+unsafe impl Send for PictureInPictureOverlay {}
+impl PictureInPictureOverlay {
+    /// The inset is captured in the same pixel format as the recording.
+    pub fn new(
+        d3d_device: ID3D11Device,
+        item: GraphicsCaptureItem,
+        pixel_format: DirectXPixelFormat,
+        capture_cursor: bool,
+        position: OverlayPosition,
+        size: u32,
+    ) -> Result<Self> {
+        let item_size = item.Size()?;
+        let frame_generator = CaptureFrameGenerator::new(
+            d3d_device,
+            vec![(item, item_size)],
+            pixel_format,
+            capture_cursor,
+        )?;
+        Ok(Self {
+            frame_generator,
+            position,
+            size,
+            latest: None,
+        })
+    }
+
+    /// The capture sessions, which have to be started along with the recording.
+    pub fn sessions(&self) -> Vec<GraphicsCaptureSession> {
+        self.frame_generator.sessions()
+    }
+
+    // Takes whatever frames arrived since the last time, without waiting
+    fn update(&mut self, context: &ID2D1DeviceContext) -> Result<()> {
+        while let CaptureFrameWait::Frame(_, frame) =
+            self.frame_generator.wait_for_frame(Duration::ZERO)?
+        {
+            let bitmap = create_frame_bitmap(context, &frame)?;
+            let content_size = frame.ContentSize()?;
+            if let Some((previous_frame, _, _)) = self.latest.replace((frame, bitmap, content_size))
+            {
+                previous_frame.Close()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Overlay for PictureInPictureOverlay {
+    fn draw(&mut self, context: &ID2D1DeviceContext, size: SizeInt32) -> Result<()> {
+        self.update(context)?;
+        // Nothing is drawn until the item produces its first frame
+        let (bitmap, content_size) = match &self.latest {
+            Some((_, bitmap, content_size)) => (bitmap, *content_size),
+            None => return Ok(()),
+        };
+        // The frame may be bigger than its content, e.g. after a resize
+        let bitmap_size = unsafe { bitmap.GetPixelSize() };
+        let content_width = (content_size.Width.max(1) as u32).min(bitmap_size.width) as f32;
+        let content_height = (content_size.Height.max(1) as u32).min(bitmap_size.height) as f32;
+
+        let width = size.Width as f32 * self.size as f32 / 100.0;
+        let height = width * content_height / content_width;
+        let margin = get_font_size(size);
+        let (x, y) = self.position.origin(size, (width, height), margin);
+        let rect = D2D_RECT_F {
+            left: x,
+            top: y,
+            right: x + width,
+            bottom: y + height,
+        };
+        let source_rect = D2D_RECT_F {
+            left: 0.0,
+            top: 0.0,
+            right: content_width,
+            bottom: content_height,
+        };
+        unsafe {
+            context.DrawBitmap(
+                bitmap,
+                Some(&rect),
+                1.0,
+                D2D1_BITMAP_INTERPOLATION_MODE_LINEAR,
+                Some(&source_rect),
+            );
+        }
+        Ok(())
+    }
+
+    fn reset_device(&mut self, d3d_device: &ID3D11Device) -> Result<()> {
+        if let Some((frame, _, _)) = self.latest.take() {
+            frame.Close()?;
+        }
+        self.frame_generator.reset_device(d3d_device.clone())
+    }
+}
+
+// Draws straight from the frame's surface, rather than copying it
+fn create_frame_bitmap(
+    context: &ID2D1DeviceContext,
+    frame: &Direct3D11CaptureFrame,
+) -> Result<ID2D1Bitmap1> {
+    let surface: IDXGISurface = get_d3d_interface_from_object(&frame.Surface()?)?;
+    unsafe {
+        let mut desc = DXGI_SURFACE_DESC::default();
+        surface.GetDesc(&mut desc)?;
+        let properties = D2D1_BITMAP_PROPERTIES1 {
+            pixelFormat: D2D1_PIXEL_FORMAT {
+                format: desc.Format,
+                alphaMode: D2D1_ALPHA_MODE_PREMULTIPLIED,
+            },
+            dpiX: 96.0,
+            dpiY: 96.0,
+            bitmapOptions: D2D1_BITMAP_OPTIONS_NONE,
+            ..Default::default()
+        };
+        context.CreateBitmapFromDxgiSurface(&surface, Some(&properties))
+    }
+}
@@ -0,0 +1,81 @@
+use windows::{
+    core::{w, Result},
+    Graphics::SizeInt32,
+    Win32::Graphics::{
+        Direct2D::{
+            Common::{D2D1_COLOR_F, D2D_POINT_2F},
+            ID2D1DeviceContext, D2D1_DRAW_TEXT_OPTIONS_NONE,
+        },
+        DirectWrite::{
+            DWriteCreateFactory, IDWriteFactory, DWRITE_FACTORY_TYPE_SHARED,
+            DWRITE_FONT_STRETCH_NORMAL, DWRITE_FONT_STYLE_NORMAL, DWRITE_FONT_WEIGHT_NORMAL,
+            DWRITE_PARAGRAPH_ALIGNMENT_CENTER, DWRITE_TEXT_ALIGNMENT_CENTER,
+        },
+    },
+};
+
+use super::{get_font_size, Overlay};
+
+const BACKGROUND_COLOR: D2D1_COLOR_F = D2D1_COLOR_F {
+    r: 0.0,
+    g: 0.0,
+    b: 0.0,
+    a: 1.0,
+};
+const TEXT_COLOR: D2D1_COLOR_F = D2D1_COLOR_F {
+    r: 0.8,
+    g: 0.8,
+    b: 0.8,
+    a: 1.0,
+};
+
+/// Covers the whole frame with a card that reads the text, e.g. while the
+/// recorded window is minimized.
+pub struct PlaceholderOverlay {
+    dwrite_factory: IDWriteFactory,
+    text: Vec<u16>,
+}
+
+unsafe impl Send for PlaceholderOverlay {}
+impl PlaceholderOverlay {
+    pub fn new(text: &str) -> Result<Self> {
+        let dwrite_factory = unsafe { DWriteCreateFactory(DWRITE_FACTORY_TYPE_SHARED)? };
+        Ok(Self {
+            dwrite_factory,
+            text: text.encode_utf16().collect(),
+        })
+    }
+}
+
+impl Overlay for PlaceholderOverlay {
+    fn draw(&mut self, context: &ID2D1DeviceContext, size: SizeInt32) -> Result<()> {
+        unsafe {
+            context.Clear(Some(&BACKGROUND_COLOR));
+            let text_format = self.dwrite_factory.CreateTextFormat(
+                w!("Segoe UI"),
+                None,
+                DWRITE_FONT_WEIGHT_NORMAL,
+                DWRITE_FONT_STYLE_NORMAL,
+                DWRITE_FONT_STRETCH_NORMAL,
+                get_font_size(size) * 2.0,
+                w!(""),
+            )?;
+            text_format.SetTextAlignment(DWRITE_TEXT_ALIGNMENT_CENTER)?;
+            text_format.SetParagraphAlignment(DWRITE_PARAGRAPH_ALIGNMENT_CENTER)?;
+            let layout = self.dwrite_factory.CreateTextLayout(
+                &self.text,
+                &text_format,
+                size.Width as f32,
+                size.Height as f32,
+            )?;
+            let text_brush = context.CreateSolidColorBrush(&TEXT_COLOR, None)?;
+            context.DrawTextLayout(
+                D2D_POINT_2F { x: 0.0, y: 0.0 },
+                &layout,
+                &text_brush,
+                D2D1_DRAW_TEXT_OPTIONS_NONE,
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+use std::{fmt::Display, str::FromStr};
+
+use windows::Graphics::SizeInt32;
+
+/// Where an overlay is placed within the frame.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OverlayPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseOverlayPositionError(&'static str);
+
+impl OverlayPosition {
+    /// The top left corner of content of the given size, keeping it the margin
+    /// away from the edges of the frame.
+    pub fn origin(
+        &self,
+        frame_size: SizeInt32,
+        content_size: (f32, f32),
+        margin: f32,
+    ) -> (f32, f32) {
+        let (width, height) = content_size;
+        let right = frame_size.Width as f32 - width - margin;
+        let bottom = frame_size.Height as f32 - height - margin;
+        match self {
+            OverlayPosition::TopLeft => (margin, margin),
+            OverlayPosition::TopRight => (right, margin),
+            OverlayPosition::BottomLeft => (margin, bottom),
+            OverlayPosition::BottomRight => (right, bottom),
+            OverlayPosition::Center => (
+                (frame_size.Width as f32 - width) / 2.0,
+                (frame_size.Height as f32 - height) / 2.0,
+            ),
+        }
+    }
+}
+
+impl FromStr for OverlayPosition {
+    type Err = ParseOverlayPositionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "top-left" => Ok(OverlayPosition::TopLeft),
+            "top-right" => Ok(OverlayPosition::TopRight),
+            "bottom-left" => Ok(OverlayPosition::BottomLeft),
+            "bottom-right" => Ok(OverlayPosition::BottomRight),
+            "center" => Ok(OverlayPosition::Center),
+            _ => Err(ParseOverlayPositionError(
+                "Invalid position value! Expecting: top-left, top-right, bottom-left, bottom-right, or center.",
+            )),
+        }
+    }
+}
+
+impl Display for OverlayPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = match self {
+            OverlayPosition::TopLeft => "top-left",
+            OverlayPosition::TopRight => "top-right",
+            OverlayPosition::BottomLeft => "bottom-left",
+            OverlayPosition::BottomRight => "bottom-right",
+            OverlayPosition::Center => "center",
+        };
+        write!(f, "{}", string)
+    }
+}
+
+impl Display for ParseOverlayPositionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseOverlayPositionError {}
+
+#[cfg(test)]
+mod tests {
+    use windows::Graphics::SizeInt32;
+
+    use super::OverlayPosition;
+
+    #[test]
+    fn overlay_position_parsing_test() {
+        assert_eq!("top-left".parse(), Ok(OverlayPosition::TopLeft));
+        assert_eq!("Bottom-Right".parse(), Ok(OverlayPosition::BottomRight));
+        assert!("middle".parse::<OverlayPosition>().is_err());
+        assert_eq!(OverlayPosition::TopRight.to_string(), "top-right");
+    }
+
+    #[test]
+    fn overlay_position_origin_test() {
+        let frame_size = SizeInt32 {
+            Width: 1920,
+            Height: 1080,
+        };
+        let content_size = (200.0, 100.0);
+        assert_eq!(
+            OverlayPosition::TopLeft.origin(frame_size, content_size, 10.0),
+            (10.0, 10.0)
+        );
+        assert_eq!(
+            OverlayPosition::BottomRight.origin(frame_size, content_size, 10.0),
+            (1710.0, 970.0)
+        );
+        assert_eq!(
+            OverlayPosition::Center.origin(frame_size, content_size, 10.0),
+            (860.0, 490.0)
+        );
+    }
+}
@@ -0,0 +1,223 @@
+use windows::{
+    core::{w, ComInterface, Result, HSTRING},
+    Graphics::SizeInt32,
+    Win32::{
+        Foundation::GENERIC_READ,
+        Graphics::{
+            Direct2D::{
+                Common::{D2D1_COLOR_F, D2D_POINT_2F, D2D_RECT_F},
+                ID2D1Bitmap, ID2D1DeviceContext, D2D1_BITMAP_INTERPOLATION_MODE_LINEAR,
+                D2D1_DRAW_TEXT_OPTIONS_NONE,
+            },
+            Direct3D11::ID3D11Device,
+            DirectWrite::{
+                DWriteCreateFactory, IDWriteFactory, IDWriteTextLayout, DWRITE_FACTORY_TYPE_SHARED,
+                DWRITE_FONT_STRETCH_NORMAL, DWRITE_FONT_STYLE_NORMAL, DWRITE_FONT_WEIGHT_SEMI_BOLD,
+                DWRITE_TEXT_METRICS,
+            },
+            Imaging::{
+                GUID_WICPixelFormat32bppPBGRA, IWICBitmapSource, IWICPalette,
+                WICBitmapDitherTypeNone, WICBitmapPaletteTypeMedianCut,
+                WICDecodeMetadataCacheOnDemand,
+            },
+        },
+    },
+};
+
+use crate::image::create_imaging_factory;
+
+use super::{get_font_size, position::OverlayPosition, Overlay};
+
+const TEXT_COLOR: D2D1_COLOR_F = D2D1_COLOR_F {
+    r: 1.0,
+    g: 1.0,
+    b: 1.0,
+    a: 1.0,
+};
+// A shadow keeps the text readable on light backgrounds
+const SHADOW_COLOR: D2D1_COLOR_F = D2D1_COLOR_F {
+    r: 0.0,
+    g: 0.0,
+    b: 0.0,
+    a: 1.0,
+};
+
+/// What a watermark shows.
+#[derive(Clone, Debug)]
+pub enum WatermarkContent {
+    Text(String),
+    /// The path to an image file (e.g. PNG) that Windows can decode.
+    Image(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct WatermarkSettings {
+    pub content: WatermarkContent,
+    pub position: OverlayPosition,
+    /// Between 0.0 (invisible) and 1.0 (opaque).
+    pub opacity: f32,
+}
+
+/// Draws text or an image on top of each frame.
+pub struct WatermarkOverlay {
+    content: Content,
+    position: OverlayPosition,
+    opacity: f32,
+}
+
+enum Content {
+    Text {
+        dwrite_factory: IDWriteFactory,
+        text: Vec<u16>,
+        layout: Option<IDWriteTextLayout>,
+    },
+    Image {
+        source: IWICBitmapSource,
+        bitmap: Option<ID2D1Bitmap>,
+    },
+}
+
+unsafe impl Send for WatermarkOverlay {}
+impl WatermarkOverlay {
+    /// Images are decoded up front so that problems with the file are
+    /// reported before recording starts.
+    pub fn new(settings: &WatermarkSettings) -> Result<Self> {
+        let content = match &settings.content {
+            WatermarkContent::Text(text) => Content::Text {
+                dwrite_factory: unsafe { DWriteCreateFactory(DWRITE_FACTORY_TYPE_SHARED)? },
+                text: text.encode_utf16().collect(),
+                layout: None,
+            },
+            WatermarkContent::Image(path) => Content::Image {
+                source: load_image(path)?,
+                bitmap: None,
+            },
+        };
+        Ok(Self {
+            content,
+            position: settings.position,
+            opacity: settings.opacity,
+        })
+    }
+}
+
+impl Overlay for WatermarkOverlay {
+    fn draw(&mut self, context: &ID2D1DeviceContext, size: SizeInt32) -> Result<()> {
+        let font_size = get_font_size(size);
+        let margin = font_size;
+        match &mut self.content {
+            Content::Text {
+                dwrite_factory,
+                text,
+                layout,
+            } => unsafe {
+                // The frame size never changes, so the layout can be reused
+                if layout.is_none() {
+                    let text_format = dwrite_factory.CreateTextFormat(
+                        w!("Segoe UI"),
+                        None,
+                        DWRITE_FONT_WEIGHT_SEMI_BOLD,
+                        DWRITE_FONT_STYLE_NORMAL,
+                        DWRITE_FONT_STRETCH_NORMAL,
+                        font_size,
+                        w!(""),
+                    )?;
+                    *layout = Some(dwrite_factory.CreateTextLayout(
+                        text,
+                        &text_format,
+                        size.Width as f32,
+                        size.Height as f32,
+                    )?);
+                }
+                let layout = layout.as_ref().unwrap();
+                let mut metrics = DWRITE_TEXT_METRICS::default();
+                layout.GetMetrics(&mut metrics)?;
+                let (x, y) = self.position.origin(
+                    size,
+                    (metrics.widthIncludingTrailingWhitespace, metrics.height),
+                    margin,
+                );
+
+                let shadow_brush = context.CreateSolidColorBrush(&SHADOW_COLOR, None)?;
+                shadow_brush.SetOpacity(self.opacity);
+                let shadow_offset = (font_size / 16.0).max(1.0);
+                context.DrawTextLayout(
+                    D2D_POINT_2F {
+                        x: x + shadow_offset,
+                        y: y + shadow_offset,
+                    },
+                    layout,
+                    &shadow_brush,
+                    D2D1_DRAW_TEXT_OPTIONS_NONE,
+                );
+                let text_brush = context.CreateSolidColorBrush(&TEXT_COLOR, None)?;
+                text_brush.SetOpacity(self.opacity);
+                context.DrawTextLayout(
+                    D2D_POINT_2F { x, y },
+                    layout,
+                    &text_brush,
+                    D2D1_DRAW_TEXT_OPTIONS_NONE,
+                );
+            },
+            Content::Image { source, bitmap } => unsafe {
+                if bitmap.is_none() {
+                    *bitmap = Some(context.CreateBitmapFromWicBitmap(&*source, None)?);
+                }
+                let bitmap = bitmap.as_ref().unwrap();
+                let image_size = bitmap.GetSize();
+                // Images that don't fit are scaled down
+                let scale = 1.0_f32
+                    .min((size.Width as f32 - margin * 2.0) / image_size.width)
+                    .min((size.Height as f32 - margin * 2.0) / image_size.height)
+                    .max(0.0);
+                let (width, height) = (image_size.width * scale, image_size.height * scale);
+                let (x, y) = self.position.origin(size, (width, height), margin);
+                let rect = D2D_RECT_F {
+                    left: x,
+                    top: y,
+                    right: x + width,
+                    bottom: y + height,
+                };
+                context.DrawBitmap(
+                    bitmap,
+                    Some(&rect),
+                    self.opacity,
+                    D2D1_BITMAP_INTERPOLATION_MODE_LINEAR,
+                    None,
+                );
+            },
+        }
+        Ok(())
+    }
+
+    fn reset_device(&mut self, _d3d_device: &ID3D11Device) -> Result<()> {
+        if let Content::Image { bitmap, .. } = &mut self.content {
+            *bitmap = None;
+        }
+        Ok(())
+    }
+}
+
+fn load_image(path: &str) -> Result<IWICBitmapSource> {
+    unsafe {
+        let factory = create_imaging_factory()?;
+        let decoder = factory.CreateDecoderFromFilename(
+            &HSTRING::from(path),
+            None,
+            GENERIC_READ,
+            WICDecodeMetadataCacheOnDemand,
+        )?;
+        let frame = decoder.GetFrame(0)?;
+        // Direct2D expects premultiplied alpha
+        let converter = factory.CreateFormatConverter()?;
+        converter.Initialize(
+            &frame,
+            &GUID_WICPixelFormat32bppPBGRA,
+            WICBitmapDitherTypeNone,
+            None::<&IWICPalette>,
+            0.0,
+            WICBitmapPaletteTypeMedianCut,
+        )?;
+        converter.cast()
+    }
+}
@@ -0,0 +1,120 @@
+use std::sync::Arc;
+
+use windows::{
+    core::Result,
+    Graphics::SizeInt32,
+    Win32::Graphics::{
+        Direct2D::{
+            Common::{D2D1_ALPHA_MODE_IGNORE, D2D1_PIXEL_FORMAT, D2D_RECT_F, D2D_SIZE_U},
+            ID2D1Bitmap, ID2D1DeviceContext, D2D1_BITMAP_INTERPOLATION_MODE_LINEAR,
+            D2D1_BITMAP_PROPERTIES,
+        },
+        Direct3D11::ID3D11Device,
+        Dxgi::Common::DXGI_FORMAT_B8G8R8A8_UNORM,
+    },
+};
+
+use crate::webcam::{WebcamCapture, WebcamFrame};
+
+use super::{get_font_size, position::OverlayPosition, Overlay};
+
+#[derive(Clone, Debug)]
+pub struct WebcamSettings {
+    /// The name of the webcam, see WebcamDevice::find.
+    pub device: String,
+    pub position: OverlayPosition,
+    /// The width of the webcam feed as a percentage of the width of the video.
+    pub size: u32,
+}
+
+/// Draws the latest frame from a webcam into a corner of each frame
+/// (picture-in-picture).
+pub struct WebcamOverlay {
+    capture: Arc<WebcamCapture>,
+    position: OverlayPosition,
+    size: u32,
+    bitmap: Option<ID2D1Bitmap>,
+    last_frame_index: u64,
+}
+
+unsafe impl Send for WebcamOverlay {}
+impl WebcamOverlay {
+    /// The capture can be shared between overlays, e.g. one per display.
+    pub fn new(capture: Arc<WebcamCapture>, settings: &WebcamSettings) -> Self {
+        Self {
+            capture,
+            position: settings.position,
+            size: settings.size,
+            bitmap: None,
+            last_frame_index: 0,
+        }
+    }
+
+    fn update_bitmap(&mut self, context: &ID2D1DeviceContext, frame: &WebcamFrame) -> Result<()> {
+        let data = frame.data.as_ptr() as *const _;
+        unsafe {
+            if let Some(bitmap) = &self.bitmap {
+                bitmap.CopyFromMemory(None, data, frame.pitch())?;
+            } else {
+                let properties = D2D1_BITMAP_PROPERTIES {
+                    pixelFormat: D2D1_PIXEL_FORMAT {
+                        format: DXGI_FORMAT_B8G8R8A8_UNORM,
+                        alphaMode: D2D1_ALPHA_MODE_IGNORE,
+                    },
+                    dpiX: 96.0,
+                    dpiY: 96.0,
+                };
+                let size = D2D_SIZE_U {
+                    width: frame.size.Width as u32,
+                    height: frame.size.Height as u32,
+                };
+                self.bitmap =
+                    Some(context.CreateBitmap(size, Some(data), frame.pitch(), &properties)?);
+            }
+        }
+        self.last_frame_index = frame.index;
+        Ok(())
+    }
+}
+
+impl Overlay for WebcamOverlay {
+    fn draw(&mut self, context: &ID2D1DeviceContext, size: SizeInt32) -> Result<()> {
+        // Nothing is drawn until the webcam produces its first frame
+        let frame = match self.capture.latest_frame() {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
+        if frame.index != self.last_frame_index {
+            self.update_bitmap(context, &frame)?;
+        }
+        let bitmap = self.bitmap.as_ref().unwrap();
+
+        let width = size.Width as f32 * self.size as f32 / 100.0;
+        let height = width * frame.size.Height as f32 / frame.size.Width as f32;
+        let margin = get_font_size(size);
+        let (x, y) = self.position.origin(size, (width, height), margin);
+        let rect = D2D_RECT_F {
+            left: x,
+            top: y,
+            right: x + width,
+            bottom: y + height,
+        };
+        unsafe {
+            context.DrawBitmap(
+                bitmap,
+                Some(&rect),
+                1.0,
+                D2D1_BITMAP_INTERPOLATION_MODE_LINEAR,
+                None,
+            );
+        }
+        Ok(())
+    }
+
+    fn reset_device(&mut self, _d3d_device: &ID3D11Device) -> Result<()> {
+        // The bitmap is created again with the next frame
+        self.bitmap = None;
+        self.last_frame_index = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    sync::{mpsc::channel, Once},
+    thread::JoinHandle,
+};
+
+use windows::{
+    core::{w, ComInterface, Result, HSTRING, PCWSTR},
+    Graphics::SizeInt32,
+    Win32::{
+        Foundation::{HWND, LPARAM, LRESULT, RECT, WPARAM},
+        Graphics::{
+            Direct3D11::{ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D},
+            Dxgi::{
+                Common::{
+                    DXGI_ALPHA_MODE_IGNORE, DXGI_FORMAT, DXGI_FORMAT_UNKNOWN, DXGI_SAMPLE_DESC,
+                },
+                IDXGIAdapter, IDXGIDevice, IDXGIFactory2, IDXGISwapChain1, DXGI_SCALING_STRETCH,
+                DXGI_SWAP_CHAIN_DESC1, DXGI_SWAP_EFFECT_FLIP_SEQUENTIAL,
+                DXGI_USAGE_RENDER_TARGET_OUTPUT,
+            },
+        },
+        System::LibraryLoader::GetModuleHandleW,
+        UI::WindowsAndMessaging::{
+            AdjustWindowRect, CreateWindowExW, DefWindowProcW, DispatchMessageW, GetMessageW,
+            IsWindow, LoadCursorW, PostMessageW, PostQuitMessage, RegisterClassW,
+            SetWindowDisplayAffinity, ShowWindow, TranslateMessage, CW_USEDEFAULT, IDC_ARROW, MSG,
+            SW_SHOWNOACTIVATE, WDA_EXCLUDEFROMCAPTURE, WINDOW_EX_STYLE, WM_CLOSE, WM_DESTROY,
+            WNDCLASSW, WS_OVERLAPPEDWINDOW,
+        },
+    },
+};
+
+const WINDOW_CLASS_NAME: PCWSTR = w!("DisplayRecorder.PreviewWindow");
+// The preview window is scaled down to fit within this size
+const MAX_WINDOW_WIDTH: i32 = 960;
+const MAX_WINDOW_HEIGHT: i32 = 540;
+
+/// Shows what is being recorded in a window. The window runs its own
+/// message loop on a dedicated thread, while frames are presented from
+/// the thread that composes them.
+pub struct Preview {
+    d3d_context: ID3D11DeviceContext,
+    swap_chain: Option<IDXGISwapChain1>,
+    window: HWND,
+    thread_handle: Option<JoinHandle<()>>,
+}
+
+impl Preview {
+    pub fn new(
+        d3d_device: &ID3D11Device,
+        size: SizeInt32,
+        format: DXGI_FORMAT,
+        title: &str,
+    ) -> Result<Self> {
+        let window_size = compute_window_size(size);
+        let title = HSTRING::from(title);
+        let (sender, receiver) = channel();
+        let thread_handle = std::thread::spawn(move || {
+            let window = create_window(window_size, &title);
+            let succeeded = window.is_ok();
+            sender.send(window).unwrap();
+            if succeeded {
+                unsafe {
+                    let mut message = MSG::default();
+                    while GetMessageW(&mut message, HWND(0), 0, 0).into() {
+                        TranslateMessage(&message);
+                        DispatchMessageW(&message);
+                    }
+                }
+            }
+        });
+        let window = receiver.recv().unwrap()?;
+        let swap_chain = create_swap_chain(d3d_device, window, size, format)?;
+
+        Ok(Self {
+            d3d_context: unsafe { d3d_device.GetImmediateContext()? },
+            swap_chain: Some(swap_chain),
+            window,
+            thread_handle: Some(thread_handle),
+        })
+    }
+
+    /// Presents the textures of a new device from now on, in the same window.
+    pub fn reset_device(
+        &mut self,
+        d3d_device: &ID3D11Device,
+        size: SizeInt32,
+        format: DXGI_FORMAT,
+    ) -> Result<()> {
+        // The old swap chain has to go before the window can get a new one
+        self.swap_chain = None;
+        self.d3d_context = unsafe { d3d_device.GetImmediateContext()? };
+        if unsafe { IsWindow(self.window).as_bool() } {
+            self.swap_chain = Some(create_swap_chain(d3d_device, self.window, size, format)?);
+        }
+        Ok(())
+    }
+
+    /// Changes the size of the textures that are presented. The window keeps
+    /// its size, the frames are stretched to fit it.
+    pub fn resize(&mut self, size: SizeInt32) -> Result<()> {
+        if let Some(swap_chain) = &self.swap_chain {
+            unsafe {
+                swap_chain.ResizeBuffers(
+                    0,
+                    size.Width as u32,
+                    size.Height as u32,
+                    DXGI_FORMAT_UNKNOWN,
+                    0,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Presents the texture, which must match the size (see resize) and format the preview was created with.
+    /// Once the window has been closed by the user this does nothing.
+    pub fn present(&mut self, texture: &ID3D11Texture2D) -> Result<()> {
+        if !unsafe { IsWindow(self.window).as_bool() } {
+            self.swap_chain = None;
+        }
+        if let Some(swap_chain) = &self.swap_chain {
+            unsafe {
+                let back_buffer: ID3D11Texture2D = swap_chain.GetBuffer(0)?;
+                self.d3d_context.CopyResource(&back_buffer, texture);
+                swap_chain.Present(0, 0).ok()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Preview {
+    fn drop(&mut self) {
+        self.swap_chain = None;
+        if let Some(handle) = self.thread_handle.take() {
+            // The window may have already been closed
+            let _ = unsafe { PostMessageW(self.window, WM_CLOSE, WPARAM(0), LPARAM(0)) };
+            handle.join().unwrap();
+        }
+    }
+}
+
+fn create_swap_chain(
+    d3d_device: &ID3D11Device,
+    window: HWND,
+    size: SizeInt32,
+    format: DXGI_FORMAT,
+) -> Result<IDXGISwapChain1> {
+    unsafe {
+        let dxgi_device: IDXGIDevice = d3d_device.cast()?;
+        let adapter: IDXGIAdapter = dxgi_device.GetAdapter()?;
+        let factory: IDXGIFactory2 = adapter.GetParent()?;
+        let desc = DXGI_SWAP_CHAIN_DESC1 {
+            Width: size.Width as u32,
+            Height: size.Height as u32,
+            Format: format,
+            SampleDesc: DXGI_SAMPLE_DESC {
+                Count: 1,
+                ..Default::default()
+            },
+            BufferUsage: DXGI_USAGE_RENDER_TARGET_OUTPUT,
+            BufferCount: 2,
+            Scaling: DXGI_SCALING_STRETCH,
+            SwapEffect: DXGI_SWAP_EFFECT_FLIP_SEQUENTIAL,
+            AlphaMode: DXGI_ALPHA_MODE_IGNORE,
+            ..Default::default()
+        };
+        factory.CreateSwapChainForHwnd(d3d_device, window, &desc, None, None)
+    }
+}
+
+fn create_window(size: SizeInt32, title: &HSTRING) -> Result<HWND> {
+    static REGISTER_CLASS: Once = Once::new();
+    unsafe {
+        let instance = GetModuleHandleW(None)?;
+        REGISTER_CLASS.call_once(|| {
+            let class = WNDCLASSW {
+                hCursor: LoadCursorW(None, IDC_ARROW).unwrap(),
+                hInstance: instance.into(),
+                lpszClassName: WINDOW_CLASS_NAME,
+                lpfnWndProc: Some(window_proc),
+                ..Default::default()
+            };
+            assert_ne!(RegisterClassW(&class), 0);
+        });
+
+        let mut rect = RECT {
+            left: 0,
+            top: 0,
+            right: size.Width,
+            bottom: size.Height,
+        };
+        AdjustWindowRect(&mut rect, WS_OVERLAPPEDWINDOW, false)?;
+        let window = CreateWindowExW(
+            WINDOW_EX_STYLE::default(),
+            WINDOW_CLASS_NAME,
+            title,
+            WS_OVERLAPPEDWINDOW,
+            CW_USEDEFAULT,
+            CW_USEDEFAULT,
+            rect.right - rect.left,
+            rect.bottom - rect.top,
+            None,
+            None,
+            instance,
+            None,
+        );
+        if window.0 == 0 {
+            return Err(windows::core::Error::from_win32());
+        }
+        // Keeps the preview out of the recording (and out of the preview
+        // itself). Windows versions before 10 2004 don't support excluding
+        // windows, so the preview shows up there.
+        let _ = SetWindowDisplayAffinity(window, WDA_EXCLUDEFROMCAPTURE);
+        // Don't steal focus from whatever is being recorded
+        ShowWindow(window, SW_SHOWNOACTIVATE);
+        Ok(window)
+    }
+}
+
+extern "system" fn window_proc(
+    window: HWND,
+    message: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    unsafe {
+        if message == WM_DESTROY {
+            PostQuitMessage(0);
+            return LRESULT(0);
+        }
+        DefWindowProcW(window, message, wparam, lparam)
+    }
+}
+
+fn compute_window_size(content_size: SizeInt32) -> SizeInt32 {
+    let width = content_size.Width.max(1) as i64;
+    let height = content_size.Height.max(1) as i64;
+    // Use whichever dimension needs to shrink the most
+    let (numerator, denominator) =
+        if width * MAX_WINDOW_HEIGHT as i64 > height * MAX_WINDOW_WIDTH as i64 {
+            (MAX_WINDOW_WIDTH as i64, width)
+        } else {
+            (MAX_WINDOW_HEIGHT as i64, height)
+        };
+    if numerator >= denominator {
+        content_size
+    } else {
+        SizeInt32 {
+            Width: ((width * numerator) / denominator).max(1) as i32,
+            Height: ((height * numerator) / denominator).max(1) as i32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Graphics::SizeInt32;
+
+    use super::compute_window_size;
+
+    fn size(width: i32, height: i32) -> SizeInt32 {
+        SizeInt32 {
+            Width: width,
+            Height: height,
+        }
+    }
+
+    #[test]
+    fn window_size_test() {
+        assert_eq!(compute_window_size(size(1920, 1080)), size(960, 540));
+        assert_eq!(compute_window_size(size(800, 600)), size(720, 540));
+        assert_eq!(compute_window_size(size(3840, 1080)), size(960, 270));
+        assert_eq!(compute_window_size(size(640, 480)), size(640, 480));
+    }
+}
@@ -7,11 +7,12 @@ use windows::{
         Graphics::{
             Direct3D11::{
                 ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D, ID3D11VideoContext,
-                ID3D11VideoDevice, ID3D11VideoProcessor, ID3D11VideoProcessorInputView,
+                ID3D11VideoContext1, ID3D11VideoContext2, ID3D11VideoDevice, ID3D11VideoProcessor,
+                ID3D11VideoProcessorEnumerator, ID3D11VideoProcessorInputView,
                 ID3D11VideoProcessorOutputView, D3D11_BIND_RENDER_TARGET,
                 D3D11_BIND_SHADER_RESOURCE, D3D11_BIND_VIDEO_ENCODER, D3D11_TEX2D_VPIV,
-                D3D11_TEX2D_VPOV, D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT,
-                D3D11_VIDEO_FRAME_FORMAT_PROGRESSIVE, D3D11_VIDEO_PROCESSOR_COLOR_SPACE,
+                D3D11_TEX2D_VPOV, D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT, D3D11_VIDEO_COLOR,
+                D3D11_VIDEO_COLOR_0, D3D11_VIDEO_COLOR_RGBA, D3D11_VIDEO_FRAME_FORMAT_PROGRESSIVE,
                 D3D11_VIDEO_PROCESSOR_CONTENT_DESC, D3D11_VIDEO_PROCESSOR_INPUT_VIEW_DESC,
                 D3D11_VIDEO_PROCESSOR_INPUT_VIEW_DESC_0, D3D11_VIDEO_PROCESSOR_OUTPUT_VIEW_DESC,
                 D3D11_VIDEO_PROCESSOR_OUTPUT_VIEW_DESC_0, D3D11_VIDEO_PROCESSOR_STREAM,
@@ -23,27 +24,44 @@ use windows::{
     },
 };
 
+use super::{
+    encoder::VideoEncoderSettings,
+    fit_mode::FitMode,
+    orientation::{Flip, Orientation, Rotation},
+    texture_pool::TexturePool,
+};
+
 pub struct VideoProcessor {
-    _d3d_device: ID3D11Device,
+    d3d_device: ID3D11Device,
     d3d_context: ID3D11DeviceContext,
 
-    _video_device: ID3D11VideoDevice,
+    video_device: ID3D11VideoDevice,
     video_context: ID3D11VideoContext,
+    video_enum: ID3D11VideoProcessorEnumerator,
     video_processor: ID3D11VideoProcessor,
-    video_output_texture: ID3D11Texture2D,
-    video_output: ID3D11VideoProcessorOutputView,
+    // The processor renders straight to the textures of the samples
+    output_pool: TexturePool<ID3D11VideoProcessorOutputView>,
     video_input_texture: ID3D11Texture2D,
     video_input: ID3D11VideoProcessorInputView,
+
+    input_format: DXGI_FORMAT,
+    output_size: SizeInt32,
+    rotation: Rotation,
+    fit_mode: FitMode,
 }
 
 impl VideoProcessor {
+    /// Converts the input (in the color format's texture format) into the
+    /// encoder's format, rotating and scaling it to the output size.
     pub fn new(
         d3d_device: ID3D11Device,
-        input_format: DXGI_FORMAT,
         input_size: SizeInt32,
-        output_format: DXGI_FORMAT,
         output_size: SizeInt32,
+        settings: &VideoEncoderSettings,
     ) -> Result<Self> {
+        let input_format = settings.color_format.texture_format();
+        let output_format = settings.color_format.encoder_texture_format();
+        let Orientation { rotation, flip } = settings.orientation;
         let d3d_context = unsafe { d3d_device.GetImmediateContext()? };
 
         // Setup video conversion
@@ -70,36 +88,63 @@ impl VideoProcessor {
 
         let video_processor = unsafe { video_device.CreateVideoProcessor(&video_enum, 0)? };
 
-        let mut color_space = D3D11_VIDEO_PROCESSOR_COLOR_SPACE {
-            _bitfield: 17, // Usage: 1 (Video processing), Nominal_Range: D3D11_VIDEO_PROCESSOR_NOMINAL_RANGE_16_235
-        };
-        unsafe { video_context.VideoProcessorSetOutputColorSpace(&video_processor, &color_space) };
-        color_space._bitfield = 33; // Usage: 1 (Video processing), Nominal_Range: D3D11_VIDEO_PROCESSOR_NOMINAL_RANGE_0_255
+        // The newer interface can describe both the range and the transfer function
+        let (input_color_space, output_color_space) = settings
+            .color_format
+            .processor_color_spaces(settings.color_range);
+        let video_context1: ID3D11VideoContext1 = video_context.cast()?;
         unsafe {
-            video_context.VideoProcessorSetStreamColorSpace(&video_processor, 0, &color_space)
-        };
+            video_context1.VideoProcessorSetStreamColorSpace1(
+                &video_processor,
+                0,
+                input_color_space,
+            );
+            video_context1.VideoProcessorSetOutputColorSpace1(&video_processor, output_color_space);
+        }
 
-        // If the input and output resolutions don't match, setup the
-        // video processor to preserve the aspect ratio when scaling.
-        if input_size.Width != output_size.Width || input_size.Height != output_size.Height {
-            let dest_rect = compute_dest_rect(&output_size, &input_size);
-            let rect = RECT {
-                left: dest_rect.X,
-                top: dest_rect.Y,
-                right: dest_rect.X + dest_rect.Width,
-                bottom: dest_rect.Y + dest_rect.Height,
+        if rotation != Rotation::None {
+            unsafe {
+                video_context.VideoProcessorSetStreamRotation(
+                    &video_processor,
+                    0,
+                    true,
+                    rotation.processor_rotation(),
+                )
             };
+        }
+        if let Some(flip) = flip {
+            let video_context2: ID3D11VideoContext2 = video_context.cast()?;
             unsafe {
-                video_context.VideoProcessorSetStreamDestRect(
+                video_context2.VideoProcessorSetStreamMirror(
                     &video_processor,
                     0,
                     true,
-                    Some(&rect),
+                    flip == Flip::Horizontal,
+                    flip == Flip::Vertical,
                 )
             };
         }
 
-        let mut texture_desc = D3D11_TEXTURE2D_DESC {
+        // Whatever the frames don't cover (e.g. letterboxing) is black, rather
+        // than what was left in the reused output textures
+        unsafe {
+            video_context.VideoProcessorSetOutputBackgroundColor(
+                &video_processor,
+                false,
+                &D3D11_VIDEO_COLOR {
+                    Anonymous: D3D11_VIDEO_COLOR_0 {
+                        RGBA: D3D11_VIDEO_COLOR_RGBA {
+                            R: 0.0,
+                            G: 0.0,
+                            B: 0.0,
+                            A: 1.0,
+                        },
+                    },
+                },
+            )
+        };
+
+        let output_desc = D3D11_TEXTURE2D_DESC {
             Width: output_size.Width as u32,
             Height: output_size.Height as u32,
             ArraySize: 1,
@@ -113,88 +158,151 @@ impl VideoProcessor {
             BindFlags: (D3D11_BIND_RENDER_TARGET.0 | D3D11_BIND_VIDEO_ENCODER.0) as u32,
             ..Default::default()
         };
-        let video_output_texture = unsafe {
-            let mut texture = None;
-            d3d_device.CreateTexture2D(&texture_desc, None, Some(&mut texture))?;
-            texture.unwrap()
-        };
-
-        let output_view_desc = D3D11_VIDEO_PROCESSOR_OUTPUT_VIEW_DESC {
-            ViewDimension: D3D11_VPOV_DIMENSION_TEXTURE2D,
-            Anonymous: D3D11_VIDEO_PROCESSOR_OUTPUT_VIEW_DESC_0 {
-                Texture2D: D3D11_TEX2D_VPOV { MipSlice: 0 },
-            },
-        };
-        let video_output = unsafe {
-            let mut output = None;
-            video_device.CreateVideoProcessorOutputView(
-                &video_output_texture,
-                &video_enum,
-                &output_view_desc,
-                Some(&mut output),
-            )?;
-            output.unwrap()
-        };
-
-        texture_desc.Width = input_size.Width as u32;
-        texture_desc.Height = input_size.Height as u32;
-        texture_desc.Format = input_format;
-        texture_desc.BindFlags = (D3D11_BIND_RENDER_TARGET.0 | D3D11_BIND_SHADER_RESOURCE.0) as u32;
-        let video_input_texture = unsafe {
-            let mut texture = None;
-            d3d_device.CreateTexture2D(&texture_desc, None, Some(&mut texture))?;
-            texture.unwrap()
-        };
-
-        let input_view_desc = D3D11_VIDEO_PROCESSOR_INPUT_VIEW_DESC {
-            ViewDimension: D3D11_VPIV_DIMENSION_TEXTURE2D,
-            Anonymous: D3D11_VIDEO_PROCESSOR_INPUT_VIEW_DESC_0 {
-                Texture2D: D3D11_TEX2D_VPIV {
-                    MipSlice: 0,
-                    ..Default::default()
+        let output_device = video_device.clone();
+        let output_enum = video_enum.clone();
+        let create_output_view = move |texture: &ID3D11Texture2D| {
+            let output_view_desc = D3D11_VIDEO_PROCESSOR_OUTPUT_VIEW_DESC {
+                ViewDimension: D3D11_VPOV_DIMENSION_TEXTURE2D,
+                Anonymous: D3D11_VIDEO_PROCESSOR_OUTPUT_VIEW_DESC_0 {
+                    Texture2D: D3D11_TEX2D_VPOV { MipSlice: 0 },
                 },
-            },
-            ..Default::default()
-        };
-        let video_input = unsafe {
-            let mut input = None;
-            video_device.CreateVideoProcessorInputView(
-                &video_input_texture,
-                &video_enum,
-                &input_view_desc,
-                Some(&mut input),
-            )?;
-            input.unwrap()
+            };
+            unsafe {
+                let mut output = None;
+                output_device.CreateVideoProcessorOutputView(
+                    texture,
+                    &output_enum,
+                    &output_view_desc,
+                    Some(&mut output),
+                )?;
+                Ok(output.unwrap())
+            }
         };
+        let output_pool = TexturePool::new(
+            d3d_device.clone(),
+            output_desc,
+            Box::new(create_output_view),
+        );
+
+        let (video_input_texture, video_input) = create_input(
+            &d3d_device,
+            &video_device,
+            &video_enum,
+            input_format,
+            input_size,
+        )?;
 
-        Ok(Self {
-            _d3d_device: d3d_device,
+        let processor = Self {
+            d3d_device,
             d3d_context,
 
-            _video_device: video_device,
+            video_device,
             video_context,
+            video_enum,
             video_processor,
-            video_output_texture,
-            video_output,
+            output_pool,
             video_input_texture,
             video_input,
-        })
+
+            input_format,
+            output_size,
+            rotation,
+            fit_mode: settings.fit_mode,
+        };
+        processor.set_stream_rects(input_size);
+        Ok(processor)
+    }
+
+    /// Changes the size of the textures the processor is given, e.g. once a
+    /// recorded window is resized. The output size stays the same.
+    pub fn resize_input(&mut self, input_size: SizeInt32) -> Result<()> {
+        let (video_input_texture, video_input) = create_input(
+            &self.d3d_device,
+            &self.video_device,
+            &self.video_enum,
+            self.input_format,
+            input_size,
+        )?;
+        self.video_input_texture = video_input_texture;
+        self.video_input = video_input;
+        self.set_stream_rects(input_size);
+        Ok(())
+    }
+
+    // If the (rotated) input and output resolutions don't match, setup
+    // the video processor to fit one into the other. Stretching is what
+    // it does by default.
+    fn set_stream_rects(&self, input_size: SizeInt32) {
+        let rotated_size = self.rotation.rotate_size(input_size);
+        let (source_rect, dest_rect) = if rotated_size == self.output_size {
+            (None, None)
+        } else {
+            match self.fit_mode {
+                FitMode::Letterbox => (
+                    None,
+                    Some(to_rect(compute_dest_rect(&self.output_size, &rotated_size))),
+                ),
+                // The source is in the input's orientation
+                FitMode::Crop => (
+                    Some(to_rect(compute_source_rect(
+                        &input_size,
+                        &self.rotation.rotate_size(self.output_size),
+                    ))),
+                    None,
+                ),
+                FitMode::Stretch => (None, None),
+            }
+        };
+        unsafe {
+            self.video_context.VideoProcessorSetStreamSourceRect(
+                &self.video_processor,
+                0,
+                source_rect.is_some(),
+                source_rect.as_ref().map(|rect| rect as *const _),
+            );
+            self.video_context.VideoProcessorSetStreamDestRect(
+                &self.video_processor,
+                0,
+                dest_rect.is_some(),
+                dest_rect.as_ref().map(|rect| rect as *const _),
+            );
+        }
     }
 
-    pub fn output_texture(&self) -> &ID3D11Texture2D {
-        &self.video_output_texture
+    /// Only records part of the input for the next frames, scaled to fill
+    /// the output (e.g. to zoom in). The rect is in the input's orientation
+    /// and should have the output's aspect ratio.
+    pub fn set_source_rect(&self, source_rect: RectInt32) {
+        let source_rect = to_rect(source_rect);
+        unsafe {
+            self.video_context.VideoProcessorSetStreamSourceRect(
+                &self.video_processor,
+                0,
+                true,
+                Some(&source_rect),
+            );
+            self.video_context.VideoProcessorSetStreamDestRect(
+                &self.video_processor,
+                0,
+                false,
+                None,
+            );
+        }
     }
 
-    pub fn process_texture(&mut self, input_texture: &ID3D11Texture2D) -> Result<()> {
+    /// Returns the converted texture, which isn't reused until the caller
+    /// (e.g. the encoder) releases it.
+    pub fn process_texture(&mut self, input_texture: &ID3D11Texture2D) -> Result<ID3D11Texture2D> {
         // The caller is responsible for making sure they give us a
         // texture that matches the input size we were initialized with.
+        let (output_texture, output_view) = self.output_pool.acquire()?;
 
         unsafe {
             // Copy the texture to the video input texture
             self.d3d_context
                 .CopyResource(&self.video_input_texture, input_texture);
 
-            // Convert to NV12
+            // Convert to the encoder's format (NV12 or P010)
             let video_stream = D3D11_VIDEO_PROCESSOR_STREAM {
                 Enable: true.into(),
                 OutputIndex: 0,
@@ -204,11 +312,12 @@ impl VideoProcessor {
             };
             self.video_context.VideoProcessorBlt(
                 &self.video_processor,
-                &self.video_output,
+                &output_view,
                 0,
                 &[video_stream],
-            )
+            )?;
         }
+        Ok(output_texture)
     }
 }
 
@@ -224,7 +333,7 @@ fn compute_scale_factor(output_size: Vector2, input_size: Vector2) -> f32 {
     scale_factor
 }
 
-fn compute_dest_rect(output_size: &SizeInt32, input_size: &SizeInt32) -> RectInt32 {
+pub fn compute_dest_rect(output_size: &SizeInt32, input_size: &SizeInt32) -> RectInt32 {
     let scale = compute_scale_factor(
         Vector2 {
             X: output_size.Width as f32,
@@ -254,3 +363,142 @@ fn compute_dest_rect(output_size: &SizeInt32, input_size: &SizeInt32) -> RectInt
         Height: new_size.Height,
     }
 }
+
+/// The centered part of the input with the output's aspect ratio, which is
+/// what's left of it once it's scaled to fill the output.
+pub fn compute_source_rect(input_size: &SizeInt32, output_size: &SizeInt32) -> RectInt32 {
+    let (input_width, input_height) = (input_size.Width as i64, input_size.Height as i64);
+    let (output_width, output_height) = (output_size.Width as i64, output_size.Height as i64);
+    let (width, height) = if input_width * output_height > input_height * output_width {
+        (input_height * output_width / output_height, input_height)
+    } else {
+        (input_width, input_width * output_height / output_width)
+    };
+    RectInt32 {
+        X: ((input_width - width) / 2) as i32,
+        Y: ((input_height - height) / 2) as i32,
+        Width: width.max(1) as i32,
+        Height: height.max(1) as i32,
+    }
+}
+
+/// The size of the input once it's scaled to fill the output, see
+/// compute_source_rect.
+pub fn compute_fill_size(output_size: &SizeInt32, input_size: &SizeInt32) -> SizeInt32 {
+    let (input_width, input_height) = (input_size.Width as i64, input_size.Height as i64);
+    let (output_width, output_height) = (output_size.Width as i64, output_size.Height as i64);
+    if input_width * output_height > input_height * output_width {
+        SizeInt32 {
+            Width: ((input_width * output_height + input_height / 2) / input_height) as i32,
+            Height: output_size.Height,
+        }
+    } else {
+        SizeInt32 {
+            Width: output_size.Width,
+            Height: ((input_height * output_width + input_width / 2) / input_width) as i32,
+        }
+    }
+}
+
+fn create_input(
+    d3d_device: &ID3D11Device,
+    video_device: &ID3D11VideoDevice,
+    video_enum: &ID3D11VideoProcessorEnumerator,
+    format: DXGI_FORMAT,
+    size: SizeInt32,
+) -> Result<(ID3D11Texture2D, ID3D11VideoProcessorInputView)> {
+    let texture_desc = D3D11_TEXTURE2D_DESC {
+        Width: size.Width as u32,
+        Height: size.Height as u32,
+        ArraySize: 1,
+        MipLevels: 1,
+        Format: format,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            ..Default::default()
+        },
+        Usage: D3D11_USAGE_DEFAULT,
+        BindFlags: (D3D11_BIND_RENDER_TARGET.0 | D3D11_BIND_SHADER_RESOURCE.0) as u32,
+        ..Default::default()
+    };
+    let texture = unsafe {
+        let mut texture = None;
+        d3d_device.CreateTexture2D(&texture_desc, None, Some(&mut texture))?;
+        texture.unwrap()
+    };
+
+    let input_view_desc = D3D11_VIDEO_PROCESSOR_INPUT_VIEW_DESC {
+        ViewDimension: D3D11_VPIV_DIMENSION_TEXTURE2D,
+        Anonymous: D3D11_VIDEO_PROCESSOR_INPUT_VIEW_DESC_0 {
+            Texture2D: D3D11_TEX2D_VPIV {
+                MipSlice: 0,
+                ..Default::default()
+            },
+        },
+        ..Default::default()
+    };
+    let input = unsafe {
+        let mut input = None;
+        video_device.CreateVideoProcessorInputView(
+            &texture,
+            video_enum,
+            &input_view_desc,
+            Some(&mut input),
+        )?;
+        input.unwrap()
+    };
+    Ok((texture, input))
+}
+
+fn to_rect(rect: RectInt32) -> RECT {
+    RECT {
+        left: rect.X,
+        top: rect.Y,
+        right: rect.X + rect.Width,
+        bottom: rect.Y + rect.Height,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Graphics::{RectInt32, SizeInt32};
+
+    use super::{compute_dest_rect, compute_fill_size, compute_source_rect};
+
+    fn size(width: i32, height: i32) -> SizeInt32 {
+        SizeInt32 {
+            Width: width,
+            Height: height,
+        }
+    }
+
+    fn rect(x: i32, y: i32, width: i32, height: i32) -> RectInt32 {
+        RectInt32 {
+            X: x,
+            Y: y,
+            Width: width,
+            Height: height,
+        }
+    }
+
+    #[test]
+    fn fit_rects_test() {
+        // A 16:10 display in a 16:9 recording
+        let input = size(1920, 1200);
+        let output = size(1920, 1080);
+        assert_eq!(compute_dest_rect(&output, &input), rect(96, 0, 1728, 1080));
+        assert_eq!(
+            compute_source_rect(&input, &output),
+            rect(0, 60, 1920, 1080)
+        );
+        assert_eq!(compute_fill_size(&output, &input), size(1920, 1200));
+
+        // A 21:9 display in a 16:9 recording
+        let input = size(3440, 1440);
+        assert_eq!(
+            compute_source_rect(&input, &output),
+            rect(440, 0, 2560, 1440)
+        );
+        assert_eq!(compute_fill_size(&output, &input), size(2580, 1080));
+    }
+}
@@ -0,0 +1,82 @@
+use std::{fmt::Display, str::FromStr};
+
+use windows::Win32::Media::MediaFoundation::{
+    eAVEncCommonRateControlMode, eAVEncCommonRateControlMode_CBR,
+    eAVEncCommonRateControlMode_Quality, eAVEncCommonRateControlMode_UnconstrainedVBR,
+};
+
+/// How the encoder decides how many bits to spend on each frame.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RateControlMode {
+    /// Constant bit rate.
+    Cbr,
+    /// Variable bit rate, the bit rate is the average.
+    Vbr,
+    /// Constant quality, the bit rate is ignored.
+    Cqp,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseRateControlModeError(&'static str);
+
+impl FromStr for RateControlMode {
+    type Err = ParseRateControlModeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "cbr" => Ok(RateControlMode::Cbr),
+            "vbr" => Ok(RateControlMode::Vbr),
+            "cqp" => Ok(RateControlMode::Cqp),
+            _ => Err(ParseRateControlModeError(
+                "Invalid rate control value! Expecting: cqp, vbr, or cbr.",
+            )),
+        }
+    }
+}
+
+impl Display for RateControlMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = match self {
+            RateControlMode::Cbr => "cbr",
+            RateControlMode::Vbr => "vbr",
+            RateControlMode::Cqp => "cqp",
+        };
+        write!(f, "{}", string)
+    }
+}
+
+impl Display for ParseRateControlModeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseRateControlModeError {}
+
+impl RateControlMode {
+    pub const ALL: [RateControlMode; 3] = [
+        RateControlMode::Cbr,
+        RateControlMode::Vbr,
+        RateControlMode::Cqp,
+    ];
+
+    pub fn to_mf_mode(&self) -> eAVEncCommonRateControlMode {
+        match self {
+            RateControlMode::Cbr => eAVEncCommonRateControlMode_CBR,
+            RateControlMode::Vbr => eAVEncCommonRateControlMode_UnconstrainedVBR,
+            RateControlMode::Cqp => eAVEncCommonRateControlMode_Quality,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateControlMode;
+
+    #[test]
+    fn rate_control_parsing_test() {
+        assert_eq!("cbr".parse(), Ok(RateControlMode::Cbr));
+        assert_eq!("VBR".parse(), Ok(RateControlMode::Vbr));
+        assert_eq!("cqp".parse(), Ok(RateControlMode::Cqp));
+        assert!("crf".parse::<RateControlMode>().is_err());
+    }
+}
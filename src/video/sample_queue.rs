@@ -0,0 +1,218 @@
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    str::FromStr,
+    sync::{Arc, Condvar, Mutex},
+};
+
+/// Enough to ride out a slow frame without the encoder holding on to more
+/// textures than the pools keep around.
+pub const DEFAULT_QUEUE_SIZE: usize = 3;
+
+/// What to do with a new sample when the queue in front of the encoder is
+/// full, i.e. when the encoder can't keep up.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum QueuePolicy {
+    /// Sample generation waits for the encoder, so nothing is lost but new
+    /// frames pile up in the capture (which drops them once it's full).
+    Block,
+    /// The oldest queued sample is dropped to make room, so the recording
+    /// stays current.
+    DropOldest,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseQueuePolicyError(&'static str);
+
+/// What happened to a sample that was pushed, see SampleQueue::push.
+#[derive(Debug, PartialEq)]
+pub enum PushResult<T> {
+    Queued,
+    /// The sample was queued in place of the oldest one.
+    Dropped(T),
+    /// The queue was closed, nothing more is taken.
+    Closed,
+}
+
+/// A bounded queue between the thread that generates samples and the one
+/// that encodes them. Clones share the same queue.
+pub struct SampleQueue<T> {
+    shared: Arc<(Mutex<QueueState<T>>, Condvar)>,
+    capacity: usize,
+    policy: QueuePolicy,
+}
+
+struct QueueState<T> {
+    items: VecDeque<T>,
+    closed: bool,
+}
+
+impl<T> SampleQueue<T> {
+    pub fn new(capacity: usize, policy: QueuePolicy) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            shared: Arc::new((
+                Mutex::new(QueueState {
+                    items: VecDeque::with_capacity(capacity),
+                    closed: false,
+                }),
+                Condvar::new(),
+            )),
+            capacity,
+            policy,
+        }
+    }
+
+    /// Adds a sample, either waiting for room or making room for it when the
+    /// queue is full.
+    pub fn push(&self, item: T) -> PushResult<T> {
+        let (state, condvar) = &*self.shared;
+        let mut state = state.lock().unwrap();
+        let mut dropped = None;
+        while !state.closed && state.items.len() >= self.capacity {
+            match self.policy {
+                QueuePolicy::Block => state = condvar.wait(state).unwrap(),
+                QueuePolicy::DropOldest => dropped = state.items.pop_front(),
+            }
+        }
+        if state.closed {
+            return PushResult::Closed;
+        }
+        state.items.push_back(item);
+        condvar.notify_all();
+        match dropped {
+            Some(dropped) => PushResult::Dropped(dropped),
+            None => PushResult::Queued,
+        }
+    }
+
+    /// Waits for the next sample. Returns None once the queue is closed and
+    /// everything in it was taken.
+    pub fn pop(&self) -> Option<T> {
+        let (state, condvar) = &*self.shared;
+        let mut state = state.lock().unwrap();
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                condvar.notify_all();
+                return Some(item);
+            }
+            if state.closed {
+                return None;
+            }
+            state = condvar.wait(state).unwrap();
+        }
+    }
+
+    /// How many samples are waiting to be taken.
+    pub fn len(&self) -> usize {
+        self.shared.0.lock().unwrap().items.len()
+    }
+
+    /// Wakes up both sides, e.g. once generation ends or the encoder stops.
+    pub fn close(&self) {
+        let (state, condvar) = &*self.shared;
+        state.lock().unwrap().closed = true;
+        condvar.notify_all();
+    }
+}
+
+impl<T> Clone for SampleQueue<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            capacity: self.capacity,
+            policy: self.policy,
+        }
+    }
+}
+
+impl FromStr for QueuePolicy {
+    type Err = ParseQueuePolicyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "block" => Ok(QueuePolicy::Block),
+            "drop-oldest" => Ok(QueuePolicy::DropOldest),
+            _ => Err(ParseQueuePolicyError(
+                "Invalid queue policy! Expecting: block or drop-oldest.",
+            )),
+        }
+    }
+}
+
+impl Display for QueuePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = match self {
+            QueuePolicy::Block => "block",
+            QueuePolicy::DropOldest => "drop-oldest",
+        };
+        write!(f, "{}", string)
+    }
+}
+
+impl Display for ParseQueuePolicyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseQueuePolicyError {}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::{PushResult, QueuePolicy, SampleQueue};
+
+    #[test]
+    fn queue_policy_parsing_test() {
+        assert_eq!("block".parse(), Ok(QueuePolicy::Block));
+        assert_eq!("Drop-Oldest".parse(), Ok(QueuePolicy::DropOldest));
+        assert!("drop".parse::<QueuePolicy>().is_err());
+        assert_eq!(QueuePolicy::DropOldest.to_string(), "drop-oldest");
+    }
+
+    #[test]
+    fn sample_queue_test() {
+        // The oldest samples make room for new ones
+        let queue = SampleQueue::new(2, QueuePolicy::DropOldest);
+        assert_eq!(queue.push(1), PushResult::Queued);
+        assert_eq!(queue.push(2), PushResult::Queued);
+        assert_eq!(queue.push(3), PushResult::Dropped(1));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pop(), Some(2));
+
+        // What's left is still taken after the queue is closed
+        queue.close();
+        assert_eq!(queue.push(4), PushResult::Closed);
+        assert_eq!(queue.pop(), Some(3));
+        assert_eq!(queue.pop(), None);
+
+        // Blocking waits until the encoder takes a sample
+        let queue = SampleQueue::new(1, QueuePolicy::Block);
+        assert_eq!(queue.push(1), PushResult::Queued);
+        let consumer = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                let first = queue.pop();
+                let second = queue.pop();
+                (first, second)
+            })
+        };
+        assert_eq!(queue.push(2), PushResult::Queued);
+        assert_eq!(consumer.join().unwrap(), (Some(1), Some(2)));
+
+        // Closing wakes up a blocked push
+        let queue = SampleQueue::new(1, QueuePolicy::Block);
+        assert_eq!(queue.push(1), PushResult::Queued);
+        let closer = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                queue.close();
+            })
+        };
+        assert_eq!(queue.push(2), PushResult::Closed);
+        closer.join().unwrap();
+    }
+}
@@ -0,0 +1,75 @@
+use std::{fmt::Display, str::FromStr};
+
+/// How frames are resampled when the recording is scaled (e.g. from 4K to
+/// 1080p). Without a filter, the video processor's own scaling is used,
+/// which is soft when downscaling.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ScaleFilter {
+    /// Nearest neighbor, keeps hard edges (e.g. pixel art) but aliases.
+    Point,
+    /// A triangle filter that is widened when downscaling.
+    Linear,
+    /// A 3-lobed Lanczos filter, the sharpest of the three.
+    Lanczos,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseScaleFilterError(&'static str);
+
+impl ScaleFilter {
+    /// The value the scaling shader expects.
+    pub fn shader_value(&self) -> u32 {
+        match self {
+            ScaleFilter::Point => 0,
+            ScaleFilter::Linear => 1,
+            ScaleFilter::Lanczos => 2,
+        }
+    }
+}
+
+impl FromStr for ScaleFilter {
+    type Err = ParseScaleFilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "point" | "nearest" => Ok(ScaleFilter::Point),
+            "linear" | "bilinear" => Ok(ScaleFilter::Linear),
+            "lanczos" => Ok(ScaleFilter::Lanczos),
+            _ => Err(ParseScaleFilterError(
+                "Invalid scale filter value! Expecting: point, linear, or lanczos.",
+            )),
+        }
+    }
+}
+
+impl Display for ScaleFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = match self {
+            ScaleFilter::Point => "point",
+            ScaleFilter::Linear => "linear",
+            ScaleFilter::Lanczos => "lanczos",
+        };
+        write!(f, "{}", string)
+    }
+}
+
+impl Display for ParseScaleFilterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseScaleFilterError {}
+
+#[cfg(test)]
+mod tests {
+    use super::ScaleFilter;
+
+    #[test]
+    fn scale_filter_parsing_test() {
+        assert_eq!("point".parse(), Ok(ScaleFilter::Point));
+        assert_eq!("Lanczos".parse(), Ok(ScaleFilter::Lanczos));
+        assert_eq!("bilinear".parse(), Ok(ScaleFilter::Linear));
+        assert!("bicubic".parse::<ScaleFilter>().is_err());
+        assert_eq!(ScaleFilter::Lanczos.to_string(), "lanczos");
+    }
+}
@@ -0,0 +1,446 @@
+use windows::{
+    core::{s, Error, Result},
+    Graphics::SizeInt32,
+    Win32::{
+        Foundation::E_FAIL,
+        Graphics::{
+            Direct3D::{
+                Fxc::{D3DCompile, D3DCOMPILE_OPTIMIZATION_LEVEL3},
+                ID3DBlob,
+            },
+            Direct3D11::{
+                ID3D11Buffer, ID3D11ComputeShader, ID3D11Device, ID3D11DeviceContext,
+                ID3D11ShaderResourceView, ID3D11Texture2D, ID3D11UnorderedAccessView,
+                D3D11_BIND_CONSTANT_BUFFER, D3D11_BIND_SHADER_RESOURCE,
+                D3D11_BIND_UNORDERED_ACCESS, D3D11_BUFFER_DESC,
+                D3D11_FORMAT_SUPPORT_TYPED_UNORDERED_ACCESS_VIEW, D3D11_SUBRESOURCE_DATA,
+                D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT, D3D11_USAGE_IMMUTABLE,
+            },
+            Dxgi::{
+                Common::{DXGI_FORMAT, DXGI_FORMAT_R16G16B16A16_FLOAT, DXGI_SAMPLE_DESC},
+                DXGI_ERROR_UNSUPPORTED,
+            },
+        },
+    },
+};
+
+use super::{
+    fit_mode::FitMode,
+    orientation::Rotation,
+    processor::{compute_dest_rect, compute_fill_size},
+    scale_filter::ScaleFilter,
+};
+
+// Each pass resamples in one direction, first horizontally and then
+// vertically, so that the wide Lanczos kernels stay cheap
+const SHADER: &str = r#"
+cbuffer Constants : register(b0)
+{
+    uint2 InputSize;
+    uint2 OutputSize;
+    uint Vertical;
+    uint Filter;
+};
+
+Texture2D<float4> Input : register(t0);
+RWTexture2D<float4> Output : register(u0);
+
+static const float PI = 3.14159265f;
+static const float LANCZOS_LOBES = 3.0f;
+
+float Weight(float x)
+{
+    x = abs(x);
+    if (Filter == 1)
+    {
+        return max(1.0f - x, 0.0f);
+    }
+    if (x < 1e-5f)
+    {
+        return 1.0f;
+    }
+    if (x >= LANCZOS_LOBES)
+    {
+        return 0.0f;
+    }
+    float px = PI * x;
+    return LANCZOS_LOBES * sin(px) * sin(px / LANCZOS_LOBES) / (px * px);
+}
+
+[numthreads(8, 8, 1)]
+void main(uint3 id : SV_DispatchThreadID)
+{
+    if (id.x >= OutputSize.x || id.y >= OutputSize.y)
+    {
+        return;
+    }
+    bool vertical = Vertical != 0;
+    int inputLength = vertical ? InputSize.y : InputSize.x;
+    float scale = (float)inputLength / (vertical ? OutputSize.y : OutputSize.x);
+    float center = ((vertical ? id.y : id.x) + 0.5f) * scale;
+    uint2 position = id.xy;
+    if (Filter == 0)
+    {
+        uint source = min((uint)center, (uint)inputLength - 1);
+        if (vertical) { position.y = source; } else { position.x = source; }
+        Output[id.xy] = Input[position];
+        return;
+    }
+
+    // The kernel is widened when downscaling, so that every input pixel contributes
+    float stretch = max(scale, 1.0f);
+    float radius = (Filter == 1 ? 1.0f : LANCZOS_LOBES) * stretch;
+    int first = (int)floor(center - radius);
+    int last = (int)ceil(center + radius);
+    float4 sum = 0;
+    float total = 0;
+    for (int i = first; i <= last; i++)
+    {
+        float weight = Weight((i + 0.5f - center) / stretch);
+        uint source = (uint)clamp(i, 0, inputLength - 1);
+        if (vertical) { position.y = source; } else { position.x = source; }
+        sum += Input[position] * weight;
+        total += weight;
+    }
+    // Lanczos rings a little below black, which float textures would keep
+    Output[id.xy] = max(sum / total, 0.0f);
+}
+"#;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Constants {
+    input_size: [u32; 2],
+    output_size: [u32; 2],
+    vertical: u32,
+    filter: u32,
+    // Constant buffers are sized in multiples of 16 bytes
+    _padding: [u32; 2],
+}
+
+struct ScalePass {
+    constants: ID3D11Buffer,
+    output_view: ID3D11UnorderedAccessView,
+    output_size: SizeInt32,
+}
+
+/// Scales frames with a compute shader before the video processor converts
+/// them (e.g. to NV12), for a sharper result than the processor's own
+/// scaling. The output texture is reused for every frame.
+pub struct FrameScaler {
+    d3d_device: ID3D11Device,
+    d3d_context: ID3D11DeviceContext,
+    shader: ID3D11ComputeShader,
+    horizontal: ScalePass,
+    vertical: ScalePass,
+    intermediate_view: ID3D11ShaderResourceView,
+    output_texture: ID3D11Texture2D,
+    // The last input texture, and the view the shader reads it through
+    input: Option<(ID3D11Texture2D, ID3D11ShaderResourceView)>,
+}
+
+impl FrameScaler {
+    pub fn new(
+        d3d_device: &ID3D11Device,
+        format: DXGI_FORMAT,
+        input_size: SizeInt32,
+        output_size: SizeInt32,
+        filter: ScaleFilter,
+    ) -> Result<Self> {
+        let support = unsafe { d3d_device.CheckFormatSupport(format)? };
+        if support & D3D11_FORMAT_SUPPORT_TYPED_UNORDERED_ACCESS_VIEW.0 as u32 == 0 {
+            return Err(Error::new(
+                DXGI_ERROR_UNSUPPORTED,
+                "This graphics adapter can't scale frames with --scale-filter!".into(),
+            ));
+        }
+        let d3d_context = unsafe { d3d_device.GetImmediateContext()? };
+        let shader = create_shader(d3d_device)?;
+
+        let intermediate_size = SizeInt32 {
+            Width: output_size.Width,
+            Height: input_size.Height,
+        };
+        let intermediate_texture = create_texture(
+            d3d_device,
+            DXGI_FORMAT_R16G16B16A16_FLOAT,
+            intermediate_size,
+        )?;
+        let output_texture = create_texture(d3d_device, format, output_size)?;
+        let horizontal = ScalePass {
+            constants: create_constants(d3d_device, input_size, intermediate_size, false, filter)?,
+            output_view: create_unordered_access_view(d3d_device, &intermediate_texture)?,
+            output_size: intermediate_size,
+        };
+        let vertical = ScalePass {
+            constants: create_constants(d3d_device, intermediate_size, output_size, true, filter)?,
+            output_view: create_unordered_access_view(d3d_device, &output_texture)?,
+            output_size,
+        };
+        let intermediate_view = create_shader_resource_view(d3d_device, &intermediate_texture)?;
+
+        Ok(Self {
+            d3d_device: d3d_device.clone(),
+            d3d_context,
+            shader,
+            horizontal,
+            vertical,
+            intermediate_view,
+            output_texture,
+            input: None,
+        })
+    }
+
+    /// Returns the scaled frame, which is overwritten by the next call.
+    pub fn scale(&mut self, input_texture: &ID3D11Texture2D) -> Result<ID3D11Texture2D> {
+        let input_view = match &self.input {
+            Some((texture, view)) if texture == input_texture => view.clone(),
+            _ => {
+                let view = create_shader_resource_view(&self.d3d_device, input_texture)?;
+                self.input = Some((input_texture.clone(), view.clone()));
+                view
+            }
+        };
+        unsafe {
+            self.d3d_context.CSSetShader(&self.shader, None);
+        }
+        self.dispatch(&input_view, &self.horizontal);
+        self.dispatch(&self.intermediate_view, &self.vertical);
+        unsafe {
+            self.d3d_context.CSSetShader(None, None);
+        }
+        Ok(self.output_texture.clone())
+    }
+
+    fn dispatch(&self, input_view: &ID3D11ShaderResourceView, pass: &ScalePass) {
+        unsafe {
+            self.d3d_context
+                .CSSetConstantBuffers(0, Some(&[Some(pass.constants.clone())]));
+            self.d3d_context
+                .CSSetShaderResources(0, Some(&[Some(input_view.clone())]));
+            self.d3d_context.CSSetUnorderedAccessViews(
+                0,
+                1,
+                Some(&Some(pass.output_view.clone())),
+                None,
+            );
+            self.d3d_context.Dispatch(
+                (pass.output_size.Width as u32).div_ceil(8),
+                (pass.output_size.Height as u32).div_ceil(8),
+                1,
+            );
+            // Unbind the views, so that the textures can be read (or written) elsewhere
+            self.d3d_context.CSSetShaderResources(0, Some(&[None]));
+            self.d3d_context
+                .CSSetUnorderedAccessViews(0, 1, Some(&None), None);
+        }
+    }
+}
+
+/// The size frames are scaled to before the video processor, which then
+/// only has to rotate them and fit them into the output without scaling.
+pub fn get_scaled_size(
+    input_size: SizeInt32,
+    output_size: SizeInt32,
+    rotation: Rotation,
+    fit_mode: FitMode,
+) -> SizeInt32 {
+    let rotated_size = rotation.rotate_size(input_size);
+    let scaled_size = match fit_mode {
+        FitMode::Letterbox => {
+            let dest_rect = compute_dest_rect(&output_size, &rotated_size);
+            SizeInt32 {
+                Width: dest_rect.Width.max(1),
+                Height: dest_rect.Height.max(1),
+            }
+        }
+        FitMode::Stretch => output_size,
+        FitMode::Crop => compute_fill_size(&output_size, &rotated_size),
+    };
+    rotation.rotate_size(scaled_size)
+}
+
+fn create_shader(d3d_device: &ID3D11Device) -> Result<ID3D11ComputeShader> {
+    let mut code: Option<ID3DBlob> = None;
+    let mut errors: Option<ID3DBlob> = None;
+    let result = unsafe {
+        D3DCompile(
+            SHADER.as_ptr() as *const _,
+            SHADER.len(),
+            s!("scale.hlsl"),
+            None,
+            None,
+            s!("main"),
+            s!("cs_5_0"),
+            D3DCOMPILE_OPTIMIZATION_LEVEL3,
+            0,
+            &mut code,
+            Some(&mut errors),
+        )
+    };
+    if let Err(error) = result {
+        let message = errors
+            .map(|errors| String::from_utf8_lossy(get_blob_bytes(&errors)).into_owned())
+            .unwrap_or_else(|| error.message().to_string());
+        return Err(Error::new(E_FAIL, message.into()));
+    }
+    let code = code.unwrap();
+    unsafe {
+        let mut shader = None;
+        d3d_device.CreateComputeShader(get_blob_bytes(&code), None, Some(&mut shader))?;
+        Ok(shader.unwrap())
+    }
+}
+
+fn get_blob_bytes(blob: &ID3DBlob) -> &[u8] {
+    unsafe {
+        std::slice::from_raw_parts(blob.GetBufferPointer() as *const u8, blob.GetBufferSize())
+    }
+}
+
+fn create_texture(
+    d3d_device: &ID3D11Device,
+    format: DXGI_FORMAT,
+    size: SizeInt32,
+) -> Result<ID3D11Texture2D> {
+    let desc = D3D11_TEXTURE2D_DESC {
+        Width: size.Width as u32,
+        Height: size.Height as u32,
+        ArraySize: 1,
+        MipLevels: 1,
+        Format: format,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            ..Default::default()
+        },
+        Usage: D3D11_USAGE_DEFAULT,
+        BindFlags: (D3D11_BIND_UNORDERED_ACCESS.0 | D3D11_BIND_SHADER_RESOURCE.0) as u32,
+        ..Default::default()
+    };
+    unsafe {
+        let mut texture = None;
+        d3d_device.CreateTexture2D(&desc, None, Some(&mut texture))?;
+        Ok(texture.unwrap())
+    }
+}
+
+fn create_constants(
+    d3d_device: &ID3D11Device,
+    input_size: SizeInt32,
+    output_size: SizeInt32,
+    vertical: bool,
+    filter: ScaleFilter,
+) -> Result<ID3D11Buffer> {
+    let constants = Constants {
+        input_size: [input_size.Width as u32, input_size.Height as u32],
+        output_size: [output_size.Width as u32, output_size.Height as u32],
+        vertical: vertical as u32,
+        filter: filter.shader_value(),
+        _padding: [0; 2],
+    };
+    let desc = D3D11_BUFFER_DESC {
+        ByteWidth: std::mem::size_of::<Constants>() as u32,
+        Usage: D3D11_USAGE_IMMUTABLE,
+        BindFlags: D3D11_BIND_CONSTANT_BUFFER.0 as u32,
+        ..Default::default()
+    };
+    let data = D3D11_SUBRESOURCE_DATA {
+        pSysMem: &constants as *const _ as *const _,
+        ..Default::default()
+    };
+    unsafe {
+        let mut buffer = None;
+        d3d_device.CreateBuffer(&desc, Some(&data), Some(&mut buffer))?;
+        Ok(buffer.unwrap())
+    }
+}
+
+fn create_shader_resource_view(
+    d3d_device: &ID3D11Device,
+    texture: &ID3D11Texture2D,
+) -> Result<ID3D11ShaderResourceView> {
+    unsafe {
+        let mut view = None;
+        d3d_device.CreateShaderResourceView(texture, None, Some(&mut view))?;
+        Ok(view.unwrap())
+    }
+}
+
+fn create_unordered_access_view(
+    d3d_device: &ID3D11Device,
+    texture: &ID3D11Texture2D,
+) -> Result<ID3D11UnorderedAccessView> {
+    unsafe {
+        let mut view = None;
+        d3d_device.CreateUnorderedAccessView(texture, None, Some(&mut view))?;
+        Ok(view.unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Graphics::SizeInt32;
+
+    use crate::video::{fit_mode::FitMode, orientation::Rotation};
+
+    use super::get_scaled_size;
+
+    fn size(width: i32, height: i32) -> SizeInt32 {
+        SizeInt32 {
+            Width: width,
+            Height: height,
+        }
+    }
+
+    #[test]
+    fn scaled_size_test() {
+        assert_eq!(
+            get_scaled_size(
+                size(3840, 2160),
+                size(1920, 1080),
+                Rotation::None,
+                FitMode::Letterbox
+            ),
+            size(1920, 1080)
+        );
+        // Letterboxed into the output
+        assert_eq!(
+            get_scaled_size(
+                size(3840, 2400),
+                size(1920, 1080),
+                Rotation::None,
+                FitMode::Letterbox
+            ),
+            size(1728, 1080)
+        );
+        // Stretched, or filling the output to be cropped by the video processor
+        assert_eq!(
+            get_scaled_size(
+                size(3840, 2400),
+                size(1920, 1080),
+                Rotation::None,
+                FitMode::Stretch
+            ),
+            size(1920, 1080)
+        );
+        assert_eq!(
+            get_scaled_size(
+                size(3840, 2400),
+                size(1920, 1080),
+                Rotation::None,
+                FitMode::Crop
+            ),
+            size(1920, 1200)
+        );
+        // Scaled before it's rotated into a portrait output
+        assert_eq!(
+            get_scaled_size(
+                size(3840, 2160),
+                size(1080, 1920),
+                Rotation::_90,
+                FitMode::Letterbox
+            ),
+            size(1920, 1080)
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use windows::{
+    core::Result,
+    Graphics::SizeInt32,
+    Win32::Graphics::Direct3D11::{ID3D11Device, ID3D11Texture2D},
+};
+
+use super::downsampler::FrameDownsampler;
+
+// Frames are compared at about this width, which is plenty to tell slides
+// apart and small enough to read back every frame
+const THUMBNAIL_WIDTH: u32 = 64;
+// How much the brightness of a thumbnail pixel has to change by (out of
+// 255), and how many of them have to, for a frame to be a new scene
+const PIXEL_THRESHOLD: u8 = 48;
+const SCENE_THRESHOLD: f32 = 0.4;
+// How far apart forced keyframes are at the least (in 100ns units), so that
+// e.g. scrolling or a video doesn't turn every frame into one
+const MIN_KEYFRAME_INTERVAL: i64 = 10_000_000;
+
+/// Tells when a frame looks nothing like the last one, e.g. when the slide
+/// of a presentation changes, so that it can be encoded as a keyframe. The
+/// GPU shrinks each frame down to a thumbnail, which is compared on the CPU.
+pub struct SceneChangeDetector {
+    downsampler: FrameDownsampler,
+    last_thumbnail: Option<Vec<u8>>,
+    last_scene_change: Option<i64>,
+}
+
+impl SceneChangeDetector {
+    /// Frames must be BGRA, and of the size.
+    pub fn new(d3d_device: &ID3D11Device, size: SizeInt32) -> Result<Self> {
+        Ok(Self {
+            downsampler: FrameDownsampler::new(d3d_device, size, THUMBNAIL_WIDTH)?,
+            last_thumbnail: None,
+            last_scene_change: None,
+        })
+    }
+
+    /// Whether the frame at the timestamp (in 100ns units) is a new scene,
+    /// compared with the frame before it. Scene changes that come too soon
+    /// after the last one aren't reported.
+    pub fn is_scene_change(&mut self, texture: &ID3D11Texture2D, timestamp: i64) -> Result<bool> {
+        let thumbnail = self.read_thumbnail(texture)?;
+        let scene_change = self
+            .last_thumbnail
+            .as_ref()
+            .is_some_and(|last_thumbnail| is_scene_change(last_thumbnail, &thumbnail))
+            && !matches!(self.last_scene_change,
+                Some(last_scene_change) if timestamp - last_scene_change < MIN_KEYFRAME_INTERVAL);
+        if scene_change {
+            self.last_scene_change = Some(timestamp);
+        }
+        self.last_thumbnail = Some(thumbnail);
+        Ok(scene_change)
+    }
+
+    // Reads back the brightness of each pixel of the thumbnail
+    fn read_thumbnail(&self, texture: &ID3D11Texture2D) -> Result<Vec<u8>> {
+        let thumbnail = self.downsampler.read(texture, None)?;
+        Ok(thumbnail.chunks_exact(4).map(get_brightness).collect())
+    }
+}
+
+// The luma of a BGRA pixel
+fn get_brightness(pixel: &[u8]) -> u8 {
+    let (b, g, r) = (pixel[0] as u32, pixel[1] as u32, pixel[2] as u32);
+    ((r * 54 + g * 183 + b * 19) / 256) as u8
+}
+
+fn is_scene_change(last_thumbnail: &[u8], thumbnail: &[u8]) -> bool {
+    if last_thumbnail.len() != thumbnail.len() || thumbnail.is_empty() {
+        return false;
+    }
+    let changed = last_thumbnail
+        .iter()
+        .zip(thumbnail)
+        .filter(|(last, current)| last.abs_diff(**current) >= PIXEL_THRESHOLD)
+        .count();
+    changed as f32 / thumbnail.len() as f32 >= SCENE_THRESHOLD
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_brightness, is_scene_change};
+
+    #[test]
+    fn scene_change_test() {
+        assert_eq!(get_brightness(&[255, 255, 255, 255]), 255);
+        assert_eq!(get_brightness(&[0, 0, 255, 255]), 53);
+
+        let white = vec![255; 100];
+        // A cursor moving or a few words changing isn't a new scene
+        let mut edited = white.clone();
+        edited[..10].fill(0);
+        assert!(!is_scene_change(&white, &edited));
+        // A slide with different content is
+        let mut slide = white.clone();
+        slide[..50].fill(100);
+        assert!(is_scene_change(&white, &slide));
+        // As is a fade to black, but not a slight change in brightness
+        assert!(is_scene_change(&white, &[0; 100]));
+        assert!(!is_scene_change(&white, &[230; 100]));
+        // A resized frame can't be compared
+        assert!(!is_scene_change(&white, &[0; 50]));
+    }
+}
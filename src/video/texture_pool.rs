@@ -0,0 +1,110 @@
+use windows::{
+    core::{ComInterface, Interface, Result},
+    Win32::Graphics::Direct3D11::{ID3D11Device, ID3D11Texture2D, D3D11_TEXTURE2D_DESC},
+};
+
+// The encoder rarely holds on to more samples than this, any extra textures
+// are created as needed and not kept around
+const MAX_POOL_SIZE: usize = 8;
+
+type CreateView<T> = Box<dyn Fn(&ID3D11Texture2D) -> Result<T>>;
+
+/// A ring of textures that are reused once nothing else holds on to them,
+/// e.g. once the encoder is done with a sample, so that every frame doesn't
+/// create a new texture. Each texture comes with a view (e.g. for the video
+/// processor to render to) that is created along with it. Everything that
+/// uses the textures shares the device's immediate context, so the GPU work
+/// on a reused texture is already ordered after the work of its previous
+/// user and no keyed mutexes or fences are needed.
+pub struct TexturePool<T: Clone> {
+    d3d_device: ID3D11Device,
+    desc: D3D11_TEXTURE2D_DESC,
+    create_view: CreateView<T>,
+    textures: Vec<PooledTexture<T>>,
+    // Where to start looking for a free texture, so that they're used in turn
+    next: usize,
+}
+
+struct PooledTexture<T> {
+    texture: ID3D11Texture2D,
+    view: T,
+    // The reference count while only the pool (and the view) use the texture
+    unused_count: u32,
+}
+
+impl<T: Clone> TexturePool<T> {
+    pub fn new(
+        d3d_device: ID3D11Device,
+        desc: D3D11_TEXTURE2D_DESC,
+        create_view: CreateView<T>,
+    ) -> Self {
+        Self {
+            d3d_device,
+            desc,
+            create_view,
+            textures: Vec::new(),
+            next: 0,
+        }
+    }
+
+    pub fn acquire(&mut self) -> Result<(ID3D11Texture2D, T)> {
+        let textures = &self.textures;
+        if let Some(index) = find_free(textures.len(), self.next, |index| {
+            get_ref_count(&textures[index].texture) == textures[index].unused_count
+        }) {
+            self.next = (index + 1) % self.textures.len();
+            let pooled = &self.textures[index];
+            return Ok((pooled.texture.clone(), pooled.view.clone()));
+        }
+
+        let texture = unsafe {
+            let mut texture = None;
+            self.d3d_device
+                .CreateTexture2D(&self.desc, None, Some(&mut texture))?;
+            texture.unwrap()
+        };
+        let view = (self.create_view)(&texture)?;
+        if self.textures.len() < MAX_POOL_SIZE {
+            let unused_count = get_ref_count(&texture);
+            self.textures.push(PooledTexture {
+                unused_count,
+                texture: texture.clone(),
+                view: view.clone(),
+            });
+        }
+        Ok((texture, view))
+    }
+}
+
+// Looks at every index once, beginning with the start
+fn find_free<F: Fn(usize) -> bool>(len: usize, start: usize, is_free: F) -> Option<usize> {
+    (0..len)
+        .map(|offset| (start + offset) % len)
+        .find(|index| is_free(*index))
+}
+
+fn get_ref_count(texture: &ID3D11Texture2D) -> u32 {
+    let unknown = texture.as_unknown();
+    unsafe {
+        let vtable = unknown.vtable();
+        (vtable.AddRef)(unknown.as_raw());
+        (vtable.Release)(unknown.as_raw())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_free;
+
+    #[test]
+    fn find_free_test() {
+        let free = [false, true, false, true];
+        let is_free = |index: usize| free[index];
+        assert_eq!(find_free(4, 0, is_free), Some(1));
+        assert_eq!(find_free(4, 2, is_free), Some(3));
+        // Wraps around to the beginning
+        assert_eq!(find_free(4, 3, |index| index == 1), Some(1));
+        assert_eq!(find_free(4, 0, |_| false), None);
+        assert_eq!(find_free(0, 0, |_| true), None);
+    }
+}
@@ -0,0 +1,60 @@
+use windows::{
+    core::Result,
+    Graphics::SizeInt32,
+    Win32::Graphics::Direct3D11::{ID3D11Device, ID3D11Texture2D},
+};
+
+use crate::{capture::FrameReader, image::save_jpeg};
+
+// The first frames often show the recorder being started, so the thumbnail
+// is taken a few seconds in when possible (in 100ns units)
+const THUMBNAIL_TIME: i64 = 3 * 10_000_000;
+const THUMBNAIL_MAX_WIDTH: i32 = 320;
+
+/// Keeps a copy of a representative frame of the recording, which is saved
+/// as a small JPEG once the recording is stopped.
+pub struct Thumbnail {
+    frame_reader: FrameReader,
+    pixels: Option<(Vec<u8>, SizeInt32)>,
+    is_final: bool,
+}
+
+unsafe impl Send for Thumbnail {}
+impl Thumbnail {
+    pub fn new(d3d_device: ID3D11Device) -> Result<Self> {
+        Ok(Self {
+            frame_reader: FrameReader::new(d3d_device)?,
+            pixels: None,
+            is_final: false,
+        })
+    }
+
+    /// Offers the next frame, which is only read back from the GPU if it
+    /// would make a better thumbnail. The texture must be BGRA.
+    pub fn update(
+        &mut self,
+        texture: &ID3D11Texture2D,
+        size: SizeInt32,
+        timestamp: i64,
+    ) -> Result<()> {
+        if self.is_final || (self.pixels.is_some() && timestamp < THUMBNAIL_TIME) {
+            return Ok(());
+        }
+        self.pixels = Some(self.frame_reader.read_texture(texture, size, None)?);
+        self.is_final = timestamp >= THUMBNAIL_TIME;
+        Ok(())
+    }
+
+    /// Reads the frames of a new device from now on, keeping the frame it has.
+    pub fn reset_device(&mut self, d3d_device: ID3D11Device) -> Result<()> {
+        self.frame_reader.reset_device(d3d_device)
+    }
+
+    /// Does nothing if no frames were recorded.
+    pub fn save(&self, path: &str) -> Result<()> {
+        if let Some((pixels, size)) = &self.pixels {
+            save_jpeg(path, pixels, *size, THUMBNAIL_MAX_WIDTH)?;
+        }
+        Ok(())
+    }
+}
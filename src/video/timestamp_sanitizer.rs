@@ -0,0 +1,136 @@
+use tracing::{debug, warn};
+
+// Times that go back further than this (in 100ns units) are a jump of the
+// capture's clock rather than frames arriving slightly out of order
+const MAX_REORDER_TIME: i64 = 5_000_000;
+
+const HUNDRED_NANOSECONDS_PER_SECOND: i128 = 10_000_000;
+
+/// Keeps the capture times of frames strictly increasing. They're derived
+/// from QPC, but can repeat or go backwards (e.g. after a display mode
+/// change), and the sink writer fails on samples that don't move forward.
+pub struct TimestampSanitizer {
+    // The nominal duration of a frame (in 100ns units)
+    frame_duration: i64,
+    // Added to every time since the clock jumped backwards
+    offset: i64,
+    last_time: Option<i64>,
+}
+
+impl TimestampSanitizer {
+    pub fn new(frame_duration: i64) -> Self {
+        Self {
+            frame_duration: frame_duration.max(1),
+            offset: 0,
+            last_time: None,
+        }
+    }
+
+    /// Returns the time to use for a frame captured at the time (both in
+    /// 100ns units). Repeated or slightly earlier times are moved to just
+    /// after the last one, and after a jump backwards the times are shifted
+    /// to carry on a frame after it.
+    pub fn sanitize(&mut self, time: i64) -> i64 {
+        let mut time = time + self.offset;
+        if let Some(last_time) = self.last_time {
+            if last_time - time > MAX_REORDER_TIME {
+                warn!(
+                    "Capture timestamps jumped back by {}ms, continuing from the last frame.",
+                    (last_time - time) / 10_000
+                );
+                let corrected_time = last_time + self.frame_duration;
+                self.offset += corrected_time - time;
+                time = corrected_time;
+            } else if time <= last_time {
+                debug!(
+                    "Capture timestamp {} isn't after {}, moving it.",
+                    time, last_time
+                );
+                time = last_time + 1;
+            }
+        }
+        self.last_time = Some(time);
+        time
+    }
+}
+
+/// Moves the timestamps of frames onto the frames of a constant frame rate,
+/// so that frames which are presented unevenly (or at a rate slightly off
+/// the frame rate, e.g. 59.94 Hz) don't judder in the recording.
+pub struct FrameGrid {
+    frame_rate: i128,
+    last_frame: Option<i128>,
+}
+
+impl FrameGrid {
+    pub fn new(frame_rate: u32) -> Self {
+        Self {
+            frame_rate: frame_rate.max(1) as i128,
+            last_frame: None,
+        }
+    }
+
+    /// Returns the timestamp of the nearest frame (both in 100ns units). A
+    /// frame whose nearest frame is taken gets the next one, unless that's
+    /// a whole frame away, in which case the taken one is returned (and the
+    /// sample should be dropped, as it doesn't move forward).
+    pub fn snap(&mut self, timestamp: i64) -> i64 {
+        let position = timestamp as i128 * self.frame_rate;
+        let mut frame = (position + HUNDRED_NANOSECONDS_PER_SECOND / 2)
+            .div_euclid(HUNDRED_NANOSECONDS_PER_SECOND);
+        if let Some(last_frame) = self.last_frame {
+            if frame <= last_frame {
+                frame = last_frame;
+                if (last_frame + 1) * HUNDRED_NANOSECONDS_PER_SECOND - position
+                    < HUNDRED_NANOSECONDS_PER_SECOND
+                {
+                    frame += 1;
+                }
+            }
+        }
+        self.last_frame = Some(frame);
+        (frame * HUNDRED_NANOSECONDS_PER_SECOND / self.frame_rate) as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FrameGrid, TimestampSanitizer};
+
+    #[test]
+    fn timestamp_sanitizer_test() {
+        let mut sanitizer = TimestampSanitizer::new(100);
+        assert_eq!(sanitizer.sanitize(1_000), 1_000);
+        assert_eq!(sanitizer.sanitize(1_100), 1_100);
+        // Repeats and frames that are slightly out of order move forward
+        assert_eq!(sanitizer.sanitize(1_100), 1_101);
+        assert_eq!(sanitizer.sanitize(1_050), 1_102);
+        assert_eq!(sanitizer.sanitize(1_200), 1_200);
+
+        // A jump backwards carries on a frame later, and so does what follows
+        assert_eq!(sanitizer.sanitize(-10_000_000), 1_300);
+        assert_eq!(sanitizer.sanitize(-9_999_900), 1_400);
+        assert_eq!(sanitizer.sanitize(-9_999_950), 1_401);
+    }
+
+    #[test]
+    fn frame_grid_test() {
+        let mut grid = FrameGrid::new(60);
+        assert_eq!(grid.snap(0), 0);
+        // Frames from a 59.94 Hz display land on the nearest frame
+        assert_eq!(grid.snap(166_833), 166_666);
+        assert_eq!(grid.snap(333_667), 333_333);
+        // A frame that's late for its frame takes the next one
+        assert_eq!(grid.snap(400_000), 500_000);
+        assert_eq!(grid.snap(900_000), 833_333);
+        // Frames arriving faster than the frame rate don't run ahead
+        assert_eq!(grid.snap(910_000), 1_000_000);
+        assert_eq!(grid.snap(920_000), 1_000_000);
+        assert_eq!(grid.snap(10_000_000), 10_000_000);
+
+        // The frames of a 144 Hz display
+        let mut grid = FrameGrid::new(144);
+        assert_eq!(grid.snap(69_500), 69_444);
+        assert_eq!(grid.snap(10_000_100), 10_000_000);
+    }
+}
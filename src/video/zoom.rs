@@ -0,0 +1,216 @@
+use std::{fmt::Display, str::FromStr};
+
+use windows::{
+    core::Result,
+    Foundation::Numerics::Vector2,
+    Graphics::{RectInt32, SizeInt32},
+    Win32::{Foundation::POINT, UI::WindowsAndMessaging::GetCursorPos},
+};
+
+use super::{overlay::ScreenOrigin, processor::compute_source_rect};
+
+const MAX_ZOOM: f32 = 8.0;
+// How quickly the zoomed in part catches up with the cursor (in 100ns units),
+// after this long it has covered about two thirds of the distance
+const FOLLOW_TIME_CONSTANT: f32 = 2_500_000.0;
+
+/// How far a recording is zoomed in, e.g. 2x.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ZoomFactor(f32);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParseZoomFactorError(&'static str);
+
+impl ZoomFactor {
+    pub fn value(&self) -> f32 {
+        self.0
+    }
+}
+
+impl FromStr for ZoomFactor {
+    type Err = ParseZoomFactorError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let s = s.trim();
+        let value = s.strip_suffix(['x', 'X']).unwrap_or(s);
+        match value.parse::<f32>() {
+            Ok(value) if (1.0..=MAX_ZOOM).contains(&value) => Ok(ZoomFactor(value)),
+            _ => Err(ParseZoomFactorError(
+                "Invalid zoom value! Expecting a factor from 1x to 8x, e.g. 2x.",
+            )),
+        }
+    }
+}
+
+impl Display for ZoomFactor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x", self.0)
+    }
+}
+
+impl Display for ParseZoomFactorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::error::Error for ParseZoomFactorError {}
+
+/// Zooms in on the part of the frame around the mouse cursor, following it
+/// smoothly rather than jumping with every movement.
+pub struct ZoomFollow {
+    factor: ZoomFactor,
+    origin: ScreenOrigin,
+    // Where the zoomed in part is centered on the frame, and when it was
+    center: Option<(Vector2, i64)>,
+}
+
+impl ZoomFollow {
+    pub fn new(factor: ZoomFactor, origin: ScreenOrigin) -> Self {
+        Self {
+            factor,
+            origin,
+            center: None,
+        }
+    }
+
+    /// The part of the input to record for the frame at the timestamp (in
+    /// 100ns units), with the aspect ratio of the output (in the input's
+    /// orientation).
+    pub fn next_rect(
+        &mut self,
+        timestamp: i64,
+        input_size: SizeInt32,
+        output_size: SizeInt32,
+    ) -> Result<RectInt32> {
+        let origin = self.origin.resolve()?;
+        let mut cursor = POINT::default();
+        unsafe { GetCursorPos(&mut cursor)? };
+        let target = Vector2 {
+            X: (cursor.x - origin.X) as f32,
+            Y: (cursor.y - origin.Y) as f32,
+        };
+        let center = match self.center {
+            Some((center, last_timestamp)) => {
+                let elapsed = (timestamp - last_timestamp).max(0) as f32;
+                follow(center, target, elapsed)
+            }
+            None => target,
+        };
+        self.center = Some((center, timestamp));
+        Ok(compute_zoom_rect(
+            &input_size,
+            &output_size,
+            center,
+            self.factor.value(),
+        ))
+    }
+}
+
+// Moves towards the target, the further the longer it has been
+fn follow(center: Vector2, target: Vector2, elapsed: f32) -> Vector2 {
+    let progress = 1.0 - (-elapsed / FOLLOW_TIME_CONSTANT).exp();
+    Vector2 {
+        X: center.X + (target.X - center.X) * progress,
+        Y: center.Y + (target.Y - center.Y) * progress,
+    }
+}
+
+// The part of the input that fills the output once it's zoomed in, kept
+// within the input
+fn compute_zoom_rect(
+    input_size: &SizeInt32,
+    output_size: &SizeInt32,
+    center: Vector2,
+    factor: f32,
+) -> RectInt32 {
+    let fill_rect = compute_source_rect(input_size, output_size);
+    let width = ((fill_rect.Width as f32 / factor).round() as i32).max(1);
+    let height = ((fill_rect.Height as f32 / factor).round() as i32).max(1);
+    let x = (center.X - width as f32 / 2.0).round() as i32;
+    let y = (center.Y - height as f32 / 2.0).round() as i32;
+    RectInt32 {
+        X: x.clamp(0, (input_size.Width - width).max(0)),
+        Y: y.clamp(0, (input_size.Height - height).max(0)),
+        Width: width,
+        Height: height,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::{
+        Foundation::Numerics::Vector2,
+        Graphics::{RectInt32, SizeInt32},
+    };
+
+    use super::{compute_zoom_rect, follow, ZoomFactor};
+
+    #[test]
+    fn zoom_factor_parsing_test() {
+        assert_eq!("2x".parse::<ZoomFactor>().map(|zoom| zoom.value()), Ok(2.0));
+        assert_eq!(
+            "1.5".parse::<ZoomFactor>().map(|zoom| zoom.value()),
+            Ok(1.5)
+        );
+        assert!("0.5x".parse::<ZoomFactor>().is_err());
+        assert!("10x".parse::<ZoomFactor>().is_err());
+        assert!("big".parse::<ZoomFactor>().is_err());
+        assert_eq!("2x".parse::<ZoomFactor>().unwrap().to_string(), "2x");
+    }
+
+    #[test]
+    fn zoom_rect_test() {
+        let size = SizeInt32 {
+            Width: 1920,
+            Height: 1080,
+        };
+        let center = Vector2 { X: 960.0, Y: 540.0 };
+        assert_eq!(
+            compute_zoom_rect(&size, &size, center, 2.0),
+            RectInt32 {
+                X: 480,
+                Y: 270,
+                Width: 960,
+                Height: 540,
+            }
+        );
+
+        // The rect stays within the input near the edges
+        let corner = Vector2 { X: 1900.0, Y: 10.0 };
+        assert_eq!(
+            compute_zoom_rect(&size, &size, corner, 2.0),
+            RectInt32 {
+                X: 960,
+                Y: 0,
+                Width: 960,
+                Height: 540,
+            }
+        );
+
+        // And has the output's aspect ratio
+        let square = SizeInt32 {
+            Width: 1080,
+            Height: 1080,
+        };
+        assert_eq!(
+            compute_zoom_rect(&size, &square, center, 2.0),
+            RectInt32 {
+                X: 690,
+                Y: 270,
+                Width: 540,
+                Height: 540,
+            }
+        );
+    }
+
+    #[test]
+    fn follow_test() {
+        let start = Vector2 { X: 0.0, Y: 0.0 };
+        let target = Vector2 { X: 100.0, Y: 50.0 };
+        assert_eq!(follow(start, target, 0.0), start);
+        let partway = follow(start, target, 2_500_000.0);
+        assert!(partway.X > 60.0 && partway.X < 70.0);
+        let later = follow(start, target, 100_000_000.0);
+        assert!((later.X - 100.0).abs() < 0.01 && (later.Y - 50.0).abs() < 0.01);
+    }
+}
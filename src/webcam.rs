@@ -0,0 +1,275 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+};
+
+use tracing::error;
+use windows::{
+    core::{Array, ComInterface, Result},
+    Graphics::SizeInt32,
+    Win32::Media::MediaFoundation::{
+        IMFActivate, IMFMediaSource, IMFSample, IMFSourceReader, MFCreateAttributes,
+        MFCreateMediaType, MFCreateSourceReaderFromMediaSource, MFEnumDeviceSources,
+        MFMediaType_Video, MFStartup, MFVideoFormat_RGB32, MFSTARTUP_FULL,
+        MF_DEVSOURCE_ATTRIBUTE_FRIENDLY_NAME, MF_DEVSOURCE_ATTRIBUTE_SOURCE_TYPE,
+        MF_DEVSOURCE_ATTRIBUTE_SOURCE_TYPE_VIDCAP_GUID, MF_MT_DEFAULT_STRIDE, MF_MT_FRAME_SIZE,
+        MF_MT_MAJOR_TYPE, MF_MT_SUBTYPE, MF_SOURCE_READERF_ENDOFSTREAM, MF_SOURCE_READERF_ERROR,
+        MF_SOURCE_READER_ENABLE_VIDEO_PROCESSING, MF_SOURCE_READER_FIRST_VIDEO_STREAM,
+    },
+};
+
+use crate::{
+    audio::device::find_device_index,
+    media::{get_string_attribute, MF_VERSION},
+};
+
+const BYTES_PER_PIXEL: usize = 4;
+
+pub struct WebcamDevice {
+    activate: IMFActivate,
+    display_name: String,
+}
+
+impl WebcamDevice {
+    pub fn enumerate() -> Result<Vec<WebcamDevice>> {
+        let activates = unsafe {
+            MFStartup(MF_VERSION, MFSTARTUP_FULL)?;
+            let mut attributes = None;
+            MFCreateAttributes(&mut attributes, 1)?;
+            let attributes = attributes.unwrap();
+            attributes.SetGUID(
+                &MF_DEVSOURCE_ATTRIBUTE_SOURCE_TYPE,
+                &MF_DEVSOURCE_ATTRIBUTE_SOURCE_TYPE_VIDCAP_GUID,
+            )?;
+            let mut data = std::ptr::null_mut();
+            let mut len = 0;
+            MFEnumDeviceSources(&attributes, &mut data, &mut len)?;
+            Array::<IMFActivate>::from_raw_parts(data as _, len)
+        };
+        let mut devices = Vec::new();
+        for activate in activates.as_slice().iter().flatten() {
+            let display_name =
+                get_string_attribute(&activate.cast()?, &MF_DEVSOURCE_ATTRIBUTE_FRIENDLY_NAME)?
+                    .unwrap_or_else(|| "Unknown".to_owned());
+            devices.push(WebcamDevice {
+                activate: activate.clone(),
+                display_name,
+            });
+        }
+        Ok(devices)
+    }
+
+    /// Finds a webcam by name, the same way as AudioCaptureDevice::find.
+    pub fn find(name: &str) -> Result<Option<WebcamDevice>> {
+        let devices = Self::enumerate()?;
+        let index = find_device_index(devices.iter().map(|device| device.display_name()), name);
+        Ok(index.and_then(|index| devices.into_iter().nth(index)))
+    }
+
+    pub fn display_name(&self) -> &str {
+        &self.display_name
+    }
+}
+
+/// A decoded frame from the webcam, stored top-down as BGRX.
+pub struct WebcamFrame {
+    pub size: SizeInt32,
+    pub data: Vec<u8>,
+    // Increases with each frame, so that consumers can skip frames they have seen
+    pub index: u64,
+}
+
+impl WebcamFrame {
+    pub fn pitch(&self) -> u32 {
+        self.size.Width as u32 * BYTES_PER_PIXEL as u32
+    }
+}
+
+/// Reads frames from a webcam on a background thread, keeping only the latest.
+/// Capture stops when the last reference is dropped.
+pub struct WebcamCapture {
+    latest_frame: Arc<Mutex<Option<Arc<WebcamFrame>>>>,
+    should_stop: Arc<AtomicBool>,
+    thread_handle: Option<JoinHandle<Result<()>>>,
+}
+
+struct FrameReader {
+    source: IMFMediaSource,
+    reader: IMFSourceReader,
+    size: SizeInt32,
+    stride: i32,
+}
+
+unsafe impl Send for FrameReader {}
+impl WebcamCapture {
+    pub fn new(device: &WebcamDevice) -> Result<Self> {
+        let frame_reader = FrameReader::new(device)?;
+        let latest_frame = Arc::new(Mutex::new(None));
+        let should_stop = Arc::new(AtomicBool::new(false));
+        let thread_handle = {
+            let latest_frame = latest_frame.clone();
+            let should_stop = should_stop.clone();
+            std::thread::spawn(move || -> Result<()> {
+                let result = unsafe { MFStartup(MF_VERSION, MFSTARTUP_FULL) }
+                    .and_then(|_| frame_reader.run(&latest_frame, &should_stop));
+                // The source is shut down either way
+                let result = result.and(unsafe { frame_reader.source.Shutdown() });
+                if let Err(error) = &result {
+                    error!(
+                        "Webcam capture failed: {:?} - {}",
+                        error.code(),
+                        error.message()
+                    );
+                    println!("Webcam capture stopped unexpectedly!");
+                }
+                result
+            })
+        };
+        Ok(Self {
+            latest_frame,
+            should_stop,
+            thread_handle: Some(thread_handle),
+        })
+    }
+
+    pub fn latest_frame(&self) -> Option<Arc<WebcamFrame>> {
+        self.latest_frame.lock().unwrap().clone()
+    }
+}
+
+impl Drop for WebcamCapture {
+    fn drop(&mut self) {
+        if let Some(handle) = self.thread_handle.take() {
+            self.should_stop.store(true, Ordering::SeqCst);
+            // The capture thread already logged its error
+            let _ = handle.join();
+        }
+    }
+}
+
+impl FrameReader {
+    fn new(device: &WebcamDevice) -> Result<Self> {
+        unsafe {
+            let source: IMFMediaSource = device.activate.ActivateObject()?;
+            // Let the source reader convert whatever the camera produces to RGB32
+            let mut attributes = None;
+            MFCreateAttributes(&mut attributes, 1)?;
+            let attributes = attributes.unwrap();
+            attributes.SetUINT32(&MF_SOURCE_READER_ENABLE_VIDEO_PROCESSING, 1)?;
+            let reader = MFCreateSourceReaderFromMediaSource(&source, &attributes)?;
+
+            let stream_index = MF_SOURCE_READER_FIRST_VIDEO_STREAM.0 as u32;
+            let media_type = MFCreateMediaType()?;
+            media_type.SetGUID(&MF_MT_MAJOR_TYPE, &MFMediaType_Video)?;
+            media_type.SetGUID(&MF_MT_SUBTYPE, &MFVideoFormat_RGB32)?;
+            reader.SetCurrentMediaType(stream_index, None, &media_type)?;
+
+            let media_type = reader.GetCurrentMediaType(stream_index)?;
+            let frame_size = media_type.GetUINT64(&MF_MT_FRAME_SIZE)?;
+            let size = SizeInt32 {
+                Width: (frame_size >> 32) as i32,
+                Height: frame_size as u32 as i32,
+            };
+            // A negative stride means the rows are stored bottom-up
+            let stride = media_type
+                .GetUINT32(&MF_MT_DEFAULT_STRIDE)
+                .map(|stride| stride as i32)
+                .unwrap_or(size.Width * BYTES_PER_PIXEL as i32);
+
+            Ok(Self {
+                source,
+                reader,
+                size,
+                stride,
+            })
+        }
+    }
+
+    fn run(
+        &self,
+        latest_frame: &Mutex<Option<Arc<WebcamFrame>>>,
+        should_stop: &AtomicBool,
+    ) -> Result<()> {
+        let mut index = 0;
+        while !should_stop.load(Ordering::SeqCst) {
+            let mut flags = 0;
+            let mut sample: Option<IMFSample> = None;
+            unsafe {
+                self.reader.ReadSample(
+                    MF_SOURCE_READER_FIRST_VIDEO_STREAM.0 as u32,
+                    0,
+                    None,
+                    Some(&mut flags),
+                    None,
+                    Some(&mut sample),
+                )?;
+            }
+            if flags & (MF_SOURCE_READERF_ENDOFSTREAM.0 | MF_SOURCE_READERF_ERROR.0) as u32 != 0 {
+                break;
+            }
+            // Stream ticks and format changes don't come with a sample
+            if let Some(sample) = sample {
+                let data = self.copy_sample(&sample)?;
+                index += 1;
+                *latest_frame.lock().unwrap() = Some(Arc::new(WebcamFrame {
+                    size: self.size,
+                    data,
+                    index,
+                }));
+            }
+        }
+        Ok(())
+    }
+
+    fn copy_sample(&self, sample: &IMFSample) -> Result<Vec<u8>> {
+        unsafe {
+            let buffer = sample.ConvertToContiguousBuffer()?;
+            let mut bytes = std::ptr::null_mut();
+            let mut length = 0;
+            buffer.Lock(&mut bytes, None, Some(&mut length))?;
+            let data = copy_rows(
+                std::slice::from_raw_parts(bytes, length as usize),
+                self.stride,
+                self.size,
+            );
+            buffer.Unlock()?;
+            Ok(data)
+        }
+    }
+}
+
+/// Copies the rows of an image into a tightly packed, top-down buffer.
+fn copy_rows(source: &[u8], stride: i32, size: SizeInt32) -> Vec<u8> {
+    let row_length = size.Width as usize * BYTES_PER_PIXEL;
+    let height = size.Height as usize;
+    let pitch = stride.unsigned_abs() as usize;
+    let mut data = Vec::with_capacity(row_length * height);
+    for row in 0..height {
+        let source_row = if stride < 0 { height - 1 - row } else { row };
+        let start = source_row * pitch;
+        data.extend_from_slice(&source[start..start + row_length]);
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Graphics::SizeInt32;
+
+    use super::copy_rows;
+
+    #[test]
+    fn copy_rows_test() {
+        let size = SizeInt32 {
+            Width: 1,
+            Height: 2,
+        };
+        // Each row has 4 bytes of padding
+        let source = [1, 1, 1, 1, 0, 0, 0, 0, 2, 2, 2, 2, 0, 0, 0, 0];
+        assert_eq!(copy_rows(&source, 8, size), [1, 1, 1, 1, 2, 2, 2, 2]);
+        assert_eq!(copy_rows(&source, -8, size), [2, 2, 2, 2, 1, 1, 1, 1]);
+    }
+}
@@ -0,0 +1,242 @@
+use std::path::Path;
+
+use windows::{
+    core::{HSTRING, PCWSTR, PWSTR},
+    Graphics::RectInt32,
+    Win32::{
+        Foundation::{CloseHandle, BOOL, HWND, LPARAM, MAX_PATH, RECT},
+        Graphics::Dwm::{DwmGetWindowAttribute, DWMWA_CLOAKED, DWMWA_EXTENDED_FRAME_BOUNDS},
+        System::Threading::{
+            OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32,
+            PROCESS_QUERY_LIMITED_INFORMATION,
+        },
+        UI::WindowsAndMessaging::{
+            EnumWindows, FindWindowW, GetAncestor, GetWindowLongW, GetWindowTextLengthW,
+            GetWindowTextW, GetWindowThreadProcessId, IsIconic, IsWindow, IsWindowVisible, GA_ROOT,
+            GWL_EXSTYLE, WS_EX_TOOLWINDOW,
+        },
+    },
+};
+
+/// A window that can be recorded, see enumerate_windows.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WindowInfo {
+    pub handle: HWND,
+    pub title: String,
+}
+
+/// Lists the top level windows that show up in the taskbar or Alt+Tab,
+/// which are the ones worth recording, in z-order.
+pub fn enumerate_windows() -> Vec<WindowInfo> {
+    get_top_level_windows()
+        .into_iter()
+        .filter(|window| is_capturable(*window))
+        .map(|window| WindowInfo {
+            handle: window,
+            title: get_window_title(window),
+        })
+        .filter(|window| !window.title.is_empty())
+        .collect()
+}
+
+/// Finds the visible top level windows of the apps, which are named by
+/// their executable (e.g. slack or slack.exe). Unlike enumerate_windows,
+/// this includes tool windows, e.g. notifications.
+pub fn find_app_windows(apps: &[String]) -> Vec<HWND> {
+    get_top_level_windows()
+        .into_iter()
+        .filter(|window| unsafe { IsWindowVisible(*window).as_bool() })
+        .filter(|window| {
+            get_window_process_name(*window)
+                .map(|name| apps.iter().any(|app| is_app_match(&name, app)))
+                .unwrap_or(false)
+        })
+        .collect()
+}
+
+/// Finds the windows of a process, named by its id or executable (e.g.
+/// notepad or notepad.exe), that can be recorded. Minimized windows are
+/// left out since they aren't on screen.
+pub fn find_process_windows(query: &str) -> Vec<HWND> {
+    let process_id: Option<u32> = query.trim().parse().ok();
+    get_top_level_windows()
+        .into_iter()
+        .filter(|window| is_capturable(*window) && !unsafe { IsIconic(*window).as_bool() })
+        .filter(|window| {
+            if let Some(process_id) = process_id {
+                get_window_process_id(*window) == process_id
+            } else {
+                get_window_process_name(*window)
+                    .map(|name| is_app_match(&name, query))
+                    .unwrap_or(false)
+            }
+        })
+        .collect()
+}
+
+/// Where the window is on the desktop. The extended frame bounds match
+/// what is captured, unlike GetWindowRect.
+pub fn get_window_bounds(window: HWND) -> Option<RectInt32> {
+    let mut rect = RECT::default();
+    unsafe {
+        DwmGetWindowAttribute(
+            window,
+            DWMWA_EXTENDED_FRAME_BOUNDS,
+            &mut rect as *mut _ as *mut _,
+            std::mem::size_of::<RECT>() as u32,
+        )
+        .ok()?;
+    }
+    Some(RectInt32 {
+        X: rect.left,
+        Y: rect.top,
+        Width: rect.right - rect.left,
+        Height: rect.bottom - rect.top,
+    })
+}
+
+fn get_top_level_windows() -> Vec<HWND> {
+    let mut windows: Vec<HWND> = Vec::new();
+    unsafe {
+        // Nothing is left to enumerate if this fails
+        let _ = EnumWindows(
+            Some(enum_window),
+            LPARAM(&mut windows as *mut Vec<HWND> as isize),
+        );
+    }
+    windows
+}
+
+extern "system" fn enum_window(window: HWND, state: LPARAM) -> BOOL {
+    let windows = unsafe { &mut *(state.0 as *mut Vec<HWND>) };
+    windows.push(window);
+    true.into()
+}
+
+fn is_capturable(window: HWND) -> bool {
+    unsafe {
+        if !IsWindowVisible(window).as_bool() || GetAncestor(window, GA_ROOT) != window {
+            return false;
+        }
+        if GetWindowLongW(window, GWL_EXSTYLE) as u32 & WS_EX_TOOLWINDOW.0 != 0 {
+            return false;
+        }
+    }
+    !is_cloaked(window)
+}
+
+/// Cloaked windows (e.g. on other virtual desktops) aren't shown.
+pub fn is_cloaked(window: HWND) -> bool {
+    let mut cloaked = 0u32;
+    let result = unsafe {
+        DwmGetWindowAttribute(
+            window,
+            DWMWA_CLOAKED,
+            &mut cloaked as *mut u32 as *mut _,
+            std::mem::size_of::<u32>() as u32,
+        )
+    };
+    result.is_ok() && cloaked != 0
+}
+
+pub fn find_window(query: &str) -> Option<HWND> {
+    // First see if we were handed a window handle (either in decimal or hex)
+    if let Some(handle) = parse_window_handle(query) {
+        let window = HWND(handle);
+        if unsafe { IsWindow(window).as_bool() } {
+            return Some(window);
+        }
+    }
+
+    // Otherwise, treat the query as the title of the window
+    let window = unsafe { FindWindowW(PCWSTR::null(), &HSTRING::from(query)) };
+    if window.0 != 0 {
+        Some(window)
+    } else {
+        None
+    }
+}
+
+pub fn get_window_title(window: HWND) -> String {
+    let length = unsafe { GetWindowTextLengthW(window) };
+    let mut title = vec![0u16; length as usize + 1];
+    let length = unsafe { GetWindowTextW(window, &mut title) };
+    String::from_utf16_lossy(&title[..length.max(0) as usize])
+}
+
+fn get_window_process_id(window: HWND) -> u32 {
+    let mut process_id = 0;
+    unsafe { GetWindowThreadProcessId(window, Some(&mut process_id)) };
+    process_id
+}
+
+/// The file name of the window's executable (e.g. notepad.exe), if the
+/// process can be opened.
+pub fn get_window_process_name(window: HWND) -> Option<String> {
+    let process_id = get_window_process_id(window);
+    let process =
+        unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, process_id) }.ok()?;
+    let mut path = vec![0u16; MAX_PATH as usize];
+    let mut length = path.len() as u32;
+    let result = unsafe {
+        QueryFullProcessImageNameW(
+            process,
+            PROCESS_NAME_WIN32,
+            PWSTR(path.as_mut_ptr()),
+            &mut length,
+        )
+    };
+    unsafe {
+        let _ = CloseHandle(process);
+    }
+    result.ok()?;
+    let path = String::from_utf16_lossy(&path[..length as usize]);
+    Path::new(&path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+}
+
+// Apps can be named with or without the extension, in any case
+fn is_app_match(process_name: &str, app: &str) -> bool {
+    let process_name = process_name.to_lowercase();
+    let app = app.trim().to_lowercase();
+    process_name == app || process_name.strip_suffix(".exe") == Some(app.as_str())
+}
+
+fn parse_window_handle(value: &str) -> Option<isize> {
+    let value = value.trim();
+    if let Some(hex) = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        isize::from_str_radix(hex, 16).ok()
+    } else {
+        value.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_app_match, parse_window_handle};
+
+    #[test]
+    fn window_handle_parsing_test() {
+        assert_eq!(parse_window_handle("1234"), Some(1234));
+        assert_eq!(parse_window_handle("0x1A2B"), Some(0x1A2B));
+        assert_eq!(parse_window_handle("0X00ff"), Some(0xFF));
+        assert_eq!(parse_window_handle(" 42 "), Some(42));
+
+        assert_eq!(parse_window_handle("Untitled - Notepad"), None);
+        assert_eq!(parse_window_handle("0x"), None);
+        assert_eq!(parse_window_handle("0xZZ"), None);
+    }
+
+    #[test]
+    fn app_matching_test() {
+        assert!(is_app_match("Slack.exe", "slack"));
+        assert!(is_app_match("slack.exe", "Slack.exe"));
+        assert!(is_app_match("1Password.exe", " 1password "));
+        assert!(!is_app_match("slack.exe", "slac"));
+        assert!(!is_app_match("Teams.exe", "ms-teams"));
+    }
+}